@@ -0,0 +1,181 @@
+//! Python bindings for Nickel.
+//!
+//! The bindings are built with [maturin](https://github.com/PyO3/maturin), from this directory:
+//!
+//! ```text
+//! $ maturin develop --release
+//! ```
+//!
+//! Results are converted to Python dictionaries, lists and scalars:
+//!
+//! ```python
+//! >>> import nickel
+//! >>> nickel.evaluate('{port = 8000 + 80, hosts = ["a", "b"]}')
+//! {'hosts': ['a', 'b'], 'port': 8080}
+//! ```
+//!
+//! Errors raise a `NickelError`, whose `diagnostics` attribute holds the list of diagnostics, as
+//! described in the C interface of Nickel (`src/capi.rs`).
+//!
+//! Once the module is installed, the smoke tests run with:
+//!
+//! ```text
+//! $ python -m unittest discover tests
+//! ```
+use nickel_lang::capi;
+use nickel_lang::error::{diagnostic, Error, IOError};
+use nickel_lang::program::Program;
+use nickel_lang::serialize::{self, ExportFormat, ExportOptions, Secrets};
+use nickel_lang::term::RichTerm;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::Value;
+use std::ffi::OsStr;
+
+create_exception!(
+    nickel,
+    NickelError,
+    PyException,
+    "An error reported by Nickel, together with its diagnostics."
+);
+
+/// Convert a JSON value to the corresponding Python value.
+fn to_py(py: Python, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.to_object(py),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => i.to_object(py),
+            None => n.as_f64().to_object(py),
+        },
+        Value::String(s) => s.to_object(py),
+        Value::Array(values) => {
+            let list = PyList::empty(py);
+            for value in values {
+                list.append(to_py(py, value)?)?;
+            }
+            list.to_object(py)
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, value) in map {
+                dict.set_item(key, to_py(py, value)?)?;
+            }
+            dict.to_object(py)
+        }
+    })
+}
+
+/// Build a `NickelError` from a list of diagnostics.
+fn mk_error(py: Python, diagnostics: Value) -> PyErr {
+    let message = diagnostics
+        .as_array()
+        .map(|diagnostics| {
+            diagnostics
+                .iter()
+                .filter_map(|diagnostic| diagnostic["message"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .filter(|message| !message.is_empty())
+        .unwrap_or_else(|| String::from("unknown error"));
+
+    let err = NickelError::new_err(message);
+    match to_py(py, &diagnostics).and_then(|d| err.value(py).setattr("diagnostics", d)) {
+        Ok(()) => err,
+        Err(err) => err,
+    }
+}
+
+/// Run an operation on a program, and convert its error, if any, to a `NickelError`.
+fn run<T, F>(py: Python, program: Result<Program, Error>, f: F) -> PyResult<T>
+where
+    F: FnOnce(&mut Program) -> Result<T, Error>,
+{
+    let mut program = program.map_err(|err| mk_error(py, capi::build_error_to_json(&err)))?;
+
+    f(&mut program).map_err(|err| {
+        let diagnostics = program.diagnostics(err);
+        mk_error(py, diagnostic::to_json(program.files(), &diagnostics))
+    })
+}
+
+/// Create a program from a source string.
+fn mk_program(source: &str) -> Result<Program, Error> {
+    Program::builder()
+        .named_source_str("<python>", source)
+        .build()
+}
+
+/// Evaluate a program and return the result as Python values.
+fn eval(py: Python, program: Result<Program, Error>) -> PyResult<PyObject> {
+    let result = run(py, program, |program| {
+        let rt = program.eval_full().map(RichTerm::from)?;
+        let rt = serialize::redact(rt, Secrets::default())?;
+        serialize::validate(ExportFormat::Json, &rt)?;
+        Ok(serialize::to_string(ExportFormat::Json, &rt)?)
+    })?;
+
+    let value: Value = serde_json::from_str(&result)
+        .map_err(|err| NickelError::new_err(format!("invalid result: {}", err)))?;
+    to_py(py, &value)
+}
+
+/// Evaluate a Nickel program and return the result as Python values.
+#[pyfunction]
+fn evaluate(py: Python, source: &str) -> PyResult<PyObject> {
+    eval(py, mk_program(source))
+}
+
+/// Evaluate a Nickel file and return the result as Python values.
+#[pyfunction]
+fn evaluate_file(py: Python, path: &str) -> PyResult<PyObject> {
+    eval(py, Program::builder().source_file(OsStr::new(path)).build())
+}
+
+/// Typecheck a Nickel program. Raise a `NickelError` if it is ill-typed.
+#[pyfunction]
+fn typecheck(py: Python, source: &str) -> PyResult<()> {
+    run(py, mk_program(source), |program| program.typecheck())
+}
+
+/// Return the metadata of a dot-separated path of a Nickel program, as a dictionary with the
+/// `doc`, `types`, `contracts`, `default`, `value` and `fields` keys.
+#[pyfunction]
+#[pyo3(signature = (source, path = None))]
+fn query(py: Python, source: &str, path: Option<String>) -> PyResult<PyObject> {
+    let t = run(py, mk_program(source), |program| program.query(path))?;
+    to_py(py, &capi::query_to_json(&t))
+}
+
+/// Evaluate a Nickel program and export the result to a text format, such as `json`, `yaml` or
+/// `toml`.
+#[pyfunction]
+#[pyo3(signature = (source, format = "json"))]
+fn export(py: Python, source: &str, format: &str) -> PyResult<String> {
+    let format = format.parse::<ExportFormat>();
+    run(py, mk_program(source), |program| {
+        let format = format.map_err(|err| IOError(err.to_string()))?;
+        let rt = program.eval_full().map(RichTerm::from)?;
+        let rt = serialize::redact(rt, Secrets::default())?;
+        serialize::validate(format, &rt)?;
+        Ok(serialize::to_string_with_options(
+            format,
+            &rt,
+            &ExportOptions::default(),
+        )?)
+    })
+}
+
+#[pymodule]
+fn nickel(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("NickelError", py.get_type::<NickelError>())?;
+    m.add_function(wrap_pyfunction!(evaluate, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate_file, m)?)?;
+    m.add_function(wrap_pyfunction!(typecheck, m)?)?;
+    m.add_function(wrap_pyfunction!(query, m)?)?;
+    m.add_function(wrap_pyfunction!(export, m)?)?;
+    Ok(())
+}
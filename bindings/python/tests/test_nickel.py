@@ -0,0 +1,98 @@
+"""Smoke tests of the Python bindings.
+
+Run them from `bindings/python`, once the module is installed with `maturin develop`:
+
+    $ python -m unittest discover tests
+"""
+import os
+import tempfile
+import unittest
+
+import nickel
+
+
+class Evaluate(unittest.TestCase):
+    def test_values(self):
+        self.assertEqual(
+            nickel.evaluate('{port = 8000 + 80, hosts = ["a", "b"], tls = false, ratio = 0.5}'),
+            {"port": 8080, "hosts": ["a", "b"], "tls": False, "ratio": 0.5},
+        )
+        self.assertIsNone(nickel.evaluate("null"))
+        self.assertEqual(nickel.evaluate('"a" ++ "b"'), "ab")
+
+    def test_file(self):
+        with tempfile.TemporaryDirectory() as tmp:
+            with open(os.path.join(tmp, "lib.ncl"), "w") as f:
+                f.write("{base = 8000}")
+            main = os.path.join(tmp, "main.ncl")
+            with open(main, "w") as f:
+                f.write('(import "lib.ncl").base + 1')
+
+            self.assertEqual(nickel.evaluate_file(main), 8001)
+
+
+class Typecheck(unittest.TestCase):
+    def test_well_typed(self):
+        self.assertIsNone(nickel.typecheck("let x : Num = 1 in x"))
+
+    def test_ill_typed(self):
+        with self.assertRaises(nickel.NickelError) as cm:
+            nickel.typecheck('let x : Num = "a" in x')
+        self.assertEqual(cm.exception.diagnostics[0]["code"], "N0308")
+
+
+class Query(unittest.TestCase):
+    def test_field(self):
+        result = nickel.query('{port | doc "The port" | Num = 80}', "port")
+        self.assertEqual(result["doc"], "The port")
+        self.assertEqual(result["contracts"], ["Num"])
+        self.assertEqual(result["value"], "80")
+        self.assertFalse(result["default"])
+
+    def test_root(self):
+        self.assertEqual(nickel.query("{b = 1, a = 2}")["fields"], ["a", "b"])
+
+
+class Export(unittest.TestCase):
+    def test_formats(self):
+        self.assertEqual(nickel.export("{a = 1}", "yaml"), "---\na: 1\n")
+        self.assertEqual(nickel.export("{a = 1}", "toml"), "a = 1\n")
+        self.assertEqual(nickel.export("{a = 1}"), '{\n  "a": 1\n}')
+
+    def test_unknown_format(self):
+        with self.assertRaises(nickel.NickelError) as cm:
+            nickel.export("{a = 1}", "nope")
+        self.assertIn("nope", str(cm.exception))
+
+
+class Diagnostics(unittest.TestCase):
+    def test_payload(self):
+        with self.assertRaises(nickel.NickelError) as cm:
+            nickel.evaluate('1 + "a"')
+        err = cm.exception
+        self.assertEqual(str(err), "Type error")
+
+        [diagnostic] = err.diagnostics
+        self.assertEqual(diagnostic["severity"], "error")
+        self.assertEqual(diagnostic["code"], "N0402")
+        self.assertEqual(diagnostic["message"], "Type error")
+        label = diagnostic["labels"][0]
+        self.assertTrue(label["primary"])
+        self.assertEqual(label["file"], "<python>")
+        self.assertEqual(label["start"], {"line": 1, "column": 5})
+        self.assertEqual(label["end"], {"line": 1, "column": 8})
+        self.assertEqual(diagnostic["fixes"], [])
+
+    def test_missing_file(self):
+        with self.assertRaises(nickel.NickelError) as cm:
+            nickel.evaluate_file("/nonexistent/main.ncl")
+        [diagnostic] = cm.exception.diagnostics
+        self.assertEqual(diagnostic["code"], "N0602")
+        self.assertEqual(diagnostic["labels"], [])
+
+    def test_is_an_exception(self):
+        self.assertTrue(issubclass(nickel.NickelError, Exception))
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,51 @@
+/* C interface of Nickel, available when building the library with the `capi` feature. See
+ * src/capi.rs for the documentation. */
+#ifndef NICKEL_H
+#define NICKEL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Context nickel_context;
+
+/* Create a new context. It must be freed with nickel_context_free. */
+nickel_context *nickel_context_new(void);
+
+/* Free a context. Do nothing if ctx is NULL. */
+void nickel_context_free(nickel_context *ctx);
+
+/* Add a file to the virtual filesystem of a context. Once a file has been added, sources and
+ * imports are only read from the added files. Return 0 on success and -1 on invalid arguments. */
+int nickel_context_add_file(nickel_context *ctx, const char *path, const char *content);
+
+/* Evaluate a UTF-8 source string or a file to JSON. Return 0 on success, 1 if the evaluation
+ * failed, in which case the diagnostics are available, and -1 on invalid arguments or on an
+ * internal error. */
+int nickel_eval_string(nickel_context *ctx, const char *source);
+int nickel_eval_file(nickel_context *ctx, const char *path);
+
+/* Typecheck a source string. Return codes are the same as for nickel_eval_string. */
+int nickel_typecheck_string(nickel_context *ctx, const char *source);
+
+/* Query the metadata of a dot-separated path of a source string, or of the whole program if path
+ * is NULL. The result is a JSON object. */
+int nickel_query_string(nickel_context *ctx, const char *source, const char *path);
+
+/* Export a source string to a text format: "json", "yaml", "toml", etc. The result is the
+ * exported text. */
+int nickel_export_string(nickel_context *ctx, const char *source, const char *format);
+
+/* Return the result of the last operation, or NULL if it failed. The string is owned by the
+ * context, and is valid until the next operation or until the context is freed. */
+const char *nickel_result_json(const nickel_context *ctx);
+
+/* Return the diagnostics of the last operation as a JSON list, or NULL if it succeeded. The
+ * string is owned by the context, as for nickel_result_json. */
+const char *nickel_diagnostics_json(const nickel_context *ctx);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* NICKEL_H */
@@ -0,0 +1,212 @@
+//! Benchmarking of Nickel programs.
+//!
+//! A [`Bench`](struct.Bench.html) applies a function defined in a Nickel file to arguments,
+//! and measures the time taken to typecheck and to evaluate the application. Library authors can
+//! use it to compare the performance of their Nickel code across versions of the interpreter,
+//! either directly or from a benchmark harness such as criterion:
+//!
+//! ```ignore
+//! let bench = Bench::new("benches/fibonacci.ncl")
+//!     .field("run")
+//!     .arg("20")
+//!     .mode(EvalMode::DeepSeq);
+//! let metrics = bench.run(10)?;
+//! println!("{:?}", metrics.mean().eval);
+//! ```
+use crate::error::{Error, IOError};
+use crate::program::Program;
+use crate::serialize::nickel::{field_name, quote};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How far the result of a benchmarked program is evaluated.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum EvalMode {
+    /// Evaluate to a weak head normal form: the fields of a record or the elements of a list are
+    /// only evaluated if needed to compute the result.
+    #[default]
+    Normal,
+    /// Evaluate the result fully, as when it is exported.
+    DeepSeq,
+}
+
+/// The application of a function defined in a Nickel file to arguments, to be benchmarked.
+#[derive(Debug, Clone)]
+pub struct Bench {
+    file: PathBuf,
+    field: Option<String>,
+    args: Vec<String>,
+    mode: EvalMode,
+}
+
+impl Bench {
+    /// Benchmark the evaluation of the given file. Without a [field](#method.field) or
+    /// [arguments](#method.arg), the benchmarked program is the content of the file.
+    pub fn new(file: impl Into<PathBuf>) -> Self {
+        Bench {
+            file: file.into(),
+            field: None,
+            args: Vec::new(),
+            mode: EvalMode::default(),
+        }
+    }
+
+    /// Benchmark the field at the given dot-separated path of the record defined by the file,
+    /// such as `benches.fibonacci`, instead of the whole file.
+    pub fn field(mut self, path: impl Into<String>) -> Self {
+        self.field = Some(path.into());
+        self
+    }
+
+    /// Apply the benchmarked function to an additional argument, given as Nickel source.
+    pub fn arg(mut self, source: impl Into<String>) -> Self {
+        self.args.push(source.into());
+        self
+    }
+
+    /// Set how far the result is evaluated. Default: [`EvalMode::Normal`](enum.EvalMode.html).
+    pub fn mode(mut self, mode: EvalMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Return the source of the benchmarked program, such as `(import "/bench/fib.ncl").run 20`.
+    pub fn source(&self) -> Result<String, Error> {
+        let path = fs::canonicalize(&self.file).map_err(IOError::from)?;
+        let mut source = format!("(import {})", quote(&path.to_string_lossy()));
+
+        if let Some(field) = &self.field {
+            for id in field.split('.') {
+                source.push('.');
+                source.push_str(&field_name(id));
+            }
+        }
+        for arg in &self.args {
+            source.push_str(&format!(" ({})", arg));
+        }
+
+        Ok(source)
+    }
+
+    /// Build the benchmarked program.
+    pub fn program(&self) -> Result<Program, Error> {
+        Program::builder()
+            .named_source_str(
+                format!("<bench {}>", self.file.to_string_lossy()),
+                self.source()?,
+            )
+            .build()
+    }
+
+    /// Typecheck and evaluate the benchmarked program the given number of times, each time from
+    /// scratch, and return the time taken by each run.
+    pub fn run(&self, iterations: usize) -> Result<Metrics, Error> {
+        let mut samples = Vec::with_capacity(iterations);
+
+        for _ in 0..iterations {
+            let mut program = self.program()?;
+
+            let start = Instant::now();
+            program.typecheck()?;
+            let typecheck = start.elapsed();
+
+            let start = Instant::now();
+            match self.mode {
+                EvalMode::Normal => program.eval()?,
+                EvalMode::DeepSeq => program.eval_full()?,
+            };
+            let eval = start.elapsed();
+
+            samples.push(Sample { typecheck, eval });
+        }
+
+        Ok(Metrics { samples })
+    }
+}
+
+/// The time taken by a run of a benchmark. Parsing the program and loading the standard library
+/// is included in the typechecking time.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Sample {
+    pub typecheck: Duration,
+    pub eval: Duration,
+}
+
+/// The results of a benchmark.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Metrics {
+    /// The time taken by each run, in order.
+    pub samples: Vec<Sample>,
+}
+
+impl Metrics {
+    /// The mean time taken by the runs, or zero if there was no run.
+    pub fn mean(&self) -> Sample {
+        let n = self.samples.len().max(1) as u32;
+        Sample {
+            typecheck: self.samples.iter().map(|s| s.typecheck).sum::<Duration>() / n,
+            eval: self.samples.iter().map(|s| s.eval).sum::<Duration>() / n,
+        }
+    }
+
+    /// The shortest times taken by the runs, or zero if there was no run.
+    pub fn min(&self) -> Sample {
+        let min = |f: fn(&Sample) -> Duration| self.samples.iter().map(f).min().unwrap_or_default();
+        Sample {
+            typecheck: min(|s| s.typecheck),
+            eval: min(|s| s.eval),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::Term;
+
+    #[test]
+    fn bench() {
+        let dir = std::env::temp_dir().join(format!("nickel-bench-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("lib.ncl");
+        fs::write(
+            &file,
+            "{benches = {\"add one\" = fun x y => [x + y, x - y]}}",
+        )
+        .unwrap();
+
+        let bench = Bench::new(&file)
+            .field("benches.add one")
+            .arg("3")
+            .arg("1")
+            .mode(EvalMode::DeepSeq);
+        let source = bench.source().unwrap();
+        assert!(source.ends_with("lib.ncl\").benches.\"add one\" (3) (1)"));
+
+        match bench.program().unwrap().eval_full().unwrap() {
+            Term::List(elts) => assert_eq!(
+                elts.iter()
+                    .map(|rt| rt.as_ref().clone())
+                    .collect::<Vec<_>>(),
+                vec![Term::Num(4.0), Term::Num(2.0)]
+            ),
+            t => panic!("expected a list, got {:?}", t),
+        }
+
+        let metrics = bench.run(3).unwrap();
+        assert_eq!(metrics.samples.len(), 3);
+        assert!(metrics.min().eval <= metrics.mean().eval);
+        assert_eq!(
+            Metrics {
+                samples: Vec::new()
+            }
+            .mean()
+            .eval,
+            Duration::default()
+        );
+
+        assert!(Bench::new(dir.join("missing.ncl")).run(1).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
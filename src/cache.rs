@@ -2,19 +2,21 @@
 
 use crate::error::{Error, ImportError, ParseError, TypecheckError};
 use crate::identifier::Ident;
-use crate::parser::lexer::Lexer;
+use crate::parser::lexer::{IdentPolicy, Lexer};
 use crate::position::TermPos;
 use crate::stdlib as nickel_stdlib;
 use crate::term::{RichTerm, Term};
 use crate::typecheck::type_check;
-use crate::{eval, parser, transformations};
+use crate::vfs::{FileSystem, OsFileSystem};
+use crate::{eval, parser, trace_event, trace_span, transformations};
 use codespan::{FileId, Files};
 use io::Read;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::result::Result;
 use std::time::SystemTime;
 use void::Void;
@@ -29,7 +31,8 @@ pub enum InputFormat {
 }
 
 impl InputFormat {
-    fn from_path_buf(path_buf: &Path) -> Option<InputFormat> {
+    /// Determine the format of a file from the extension of its path.
+    pub fn from_path_buf(path_buf: &Path) -> Option<InputFormat> {
         match path_buf.extension().and_then(OsStr::to_str) {
             Some("ncl") => Some(InputFormat::Nickel),
             Some("json") => Some(InputFormat::Json),
@@ -64,6 +67,26 @@ pub struct Cache {
     terms: HashMap<FileId, (RichTerm, EntryState)>,
     /// The list of ids corresponding to the stdlib modules
     stdlib_ids: Option<Vec<FileId>>,
+    /// The characters allowed in the identifiers of parsed sources.
+    ident_policy: IdentPolicy,
+    /// The directories where imports are looked up when they are not found relatively to the
+    /// importing file.
+    import_paths: Vec<PathBuf>,
+    /// The packages imported by name, as in `import "utils/lists.ncl"`, indexed by name. See
+    /// [`add_package`](#method.add_package).
+    packages: HashMap<String, Package>,
+    /// The filesystem from which files and imports are read.
+    fs: Rc<dyn FileSystem>,
+    /// The entries imported by each entry, as recorded by [`add_import`](#method.add_import).
+    /// Used to find the entries depending on a source which has been modified.
+    imports: HashMap<FileId, HashSet<FileId>>,
+}
+
+/// A package imported by name: a directory, and the file imported by the name alone.
+#[derive(Debug, Clone, PartialEq)]
+struct Package {
+    root: PathBuf,
+    entry: Option<PathBuf>,
 }
 
 /// Cache keys for sources.
@@ -148,16 +171,91 @@ impl Cache {
             file_ids: HashMap::new(),
             terms: HashMap::new(),
             stdlib_ids: None,
+            ident_policy: IdentPolicy::default(),
+            import_paths: Vec::new(),
+            packages: HashMap::new(),
+            fs: Rc::new(OsFileSystem),
+            imports: HashMap::new(),
         }
     }
 
+    /// Set the filesystem from which the files loaded from now on are read. By default, files are
+    /// read from the filesystem of the operating system.
+    pub fn set_file_system(&mut self, fs: Rc<dyn FileSystem>) {
+        self.fs = fs;
+    }
+
+    /// Add a directory to the list of directories where imports are looked up, after the
+    /// directory of the importing file.
+    pub fn add_import_path(&mut self, path: impl Into<PathBuf>) {
+        self.import_paths.push(path.into());
+    }
+
+    /// Make the files of a directory importable by name: `import "<name>/<path>"` imports
+    /// `<root>/<path>`, and `import "<name>"` imports the entry point, if any. Used for the
+    /// dependencies declared in a [manifest](../manifest/index.html).
+    pub fn add_package(
+        &mut self,
+        name: impl Into<String>,
+        root: impl Into<PathBuf>,
+        entry: Option<PathBuf>,
+    ) {
+        let root = root.into();
+        self.packages.insert(name.into(), Package { root, entry });
+    }
+
+    /// Compute the path of an imported file. Relative paths are first resolved relatively to the
+    /// importing file, then relatively to the package whose name is their first component, if
+    /// any, and then relatively to the import paths, in order. If the file isn't found anywhere,
+    /// return the path relative to the importing file.
+    pub(crate) fn find_import(&self, path: &OsStr, parent: Option<PathBuf>) -> PathBuf {
+        let path_buf = with_parent(path, parent);
+
+        if self.fs.exists(&path_buf) || Path::new(path).is_absolute() {
+            return path_buf;
+        }
+
+        let mut components = Path::new(path).components();
+        let package = components
+            .next()
+            .and_then(|first| first.as_os_str().to_str())
+            .and_then(|name| self.packages.get(name));
+        if let Some(package) = package {
+            let rest = components.as_path();
+            match &package.entry {
+                Some(entry) if rest.as_os_str().is_empty() => return entry.clone(),
+                _ => return package.root.join(rest),
+            }
+        }
+
+        self.import_paths
+            .iter()
+            .map(|dir| dir.join(path))
+            .find(|candidate| self.fs.exists(candidate))
+            .unwrap_or(path_buf)
+    }
+
+    /// Return the filesystem from which files and imports are read.
+    pub fn file_system(&self) -> &dyn FileSystem {
+        self.fs.as_ref()
+    }
+
+    /// Return the paths of the files of a directory and of its subdirectories, as listed by the
+    /// filesystem of the cache.
+    pub fn list_files(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.fs.list_files(dir)
+    }
+
+    /// Set the characters allowed in the identifiers of the sources parsed from now on.
+    pub fn set_ident_policy(&mut self, policy: IdentPolicy) {
+        self.ident_policy = policy;
+    }
+
     /// Load a file in the file database. Do not insert an entry in the name-id table.
     fn load_file(&mut self, path: impl Into<OsString>) -> io::Result<FileId> {
         let path = path.into();
-        let mut buffer = String::new();
-        fs::File::open(&path)
-            .and_then(|mut file| file.read_to_string(&mut buffer))
-            .map(|_| self.files.add(path, buffer))
+        let buffer = self.fs.read_to_string(Path::new(&path))?;
+        Ok(self.files.add(path, buffer))
     }
 
     /// Same as [`add_file`](#method.add_file), but assume that the path is already normalized,
@@ -187,8 +285,8 @@ impl Cache {
     /// entry in the name-id table.
     pub fn add_file(&mut self, path: impl Into<OsString>) -> io::Result<FileId> {
         let path = path.into();
-        let timestamp = timestamp(&path)?;
-        let normalized = normalize_path(PathBuf::from(&path).as_path())?;
+        let timestamp = self.fs.modified(Path::new(&path))?;
+        let normalized = self.fs.normalize(Path::new(&path))?;
         self.add_file_(normalized, timestamp)
     }
 
@@ -211,8 +309,8 @@ impl Cache {
     /// timestamps. If it was not in cache, add it as a new entry.
     pub fn get_or_add_file(&mut self, path: impl Into<OsString>) -> io::Result<CacheOp<FileId>> {
         let path = path.into();
-        let timestamp = timestamp(&path)?;
-        let normalized = normalize_path(PathBuf::from(&path).as_path())?;
+        let timestamp = self.fs.modified(Path::new(&path))?;
+        let normalized = self.fs.normalize(Path::new(&path))?;
         self.get_or_add_file_(normalized, timestamp)
     }
 
@@ -272,10 +370,66 @@ impl Cache {
         }
     }
 
+    /// Replace the content of a source, and invalidate the entries depending on it. The term of
+    /// the source and the imports recorded for it are cleared, as they have to be computed again
+    /// from the new content.
+    ///
+    /// Return the source together with the entries importing it, directly or transitively, which
+    /// are the ones to check again. See [`invalidate`](#method.invalidate).
+    pub fn update_source(&mut self, file_id: FileId, s: String) -> HashSet<FileId> {
+        self.files.update(file_id, s);
+        self.terms.remove(&file_id);
+        self.imports.remove(&file_id);
+
+        let mut dirty = self.invalidate(file_id);
+        dirty.insert(file_id);
+        dirty
+    }
+
+    /// Invalidate the entries importing a source, directly or transitively, after this source
+    /// has been modified. Parsing doesn't depend on imports, so parsed terms are kept, but the
+    /// terms which have been typechecked or transformed are cleared.
+    ///
+    /// Return the invalidated entries.
+    pub fn invalidate(&mut self, file_id: FileId) -> HashSet<FileId> {
+        let dependents = self.dependents(file_id);
+        self.terms
+            .retain(|id, (_, state)| !dependents.contains(id) || *state == EntryState::Parsed);
+        dependents
+    }
+
+    /// Record that an entry imports another one.
+    pub fn add_import(&mut self, importer: FileId, imported: FileId) {
+        self.imports.entry(importer).or_default().insert(imported);
+    }
+
+    /// Return the entries importing a source, directly or transitively, according to the imports
+    /// recorded by [`add_import`](#method.add_import). The source itself is only included if it
+    /// is part of an import cycle.
+    pub fn dependents(&self, file_id: FileId) -> HashSet<FileId> {
+        let mut dependents = HashSet::new();
+        let mut todo = vec![file_id];
+
+        while let Some(id) = todo.pop() {
+            for (importer, imported) in &self.imports {
+                if imported.contains(&id) && dependents.insert(*importer) {
+                    todo.push(*importer);
+                }
+            }
+        }
+
+        dependents
+    }
+
     /// Parse a source and populate the corresponding entry in the cache, or do nothing if the
     /// entry has already been parsed.
     pub fn parse(&mut self, file_id: FileId) -> Result<CacheOp<()>, ParseError> {
         if self.terms.contains_key(&file_id) {
+            trace_event!(
+                "parse",
+                file = self.name(file_id).to_string_lossy(),
+                cache = "hit"
+            );
             Ok(CacheOp::Cached(()))
         } else {
             self.terms
@@ -292,6 +446,11 @@ impl Cache {
         format: InputFormat,
     ) -> Result<CacheOp<()>, ParseError> {
         if self.terms.contains_key(&file_id) {
+            trace_event!(
+                "parse",
+                file = self.name(file_id).to_string_lossy(),
+                cache = "hit"
+            );
             Ok(CacheOp::Cached(()))
         } else {
             self.terms.insert(
@@ -305,9 +464,12 @@ impl Cache {
         }
     }
 
-    /// Parse a source without querying nor populating the cache.
+    /// Parse a source without querying nor populating the cache. The format of the source is
+    /// determined by the extension of its name, and defaults to Nickel.
     pub fn parse_nocache(&self, file_id: FileId) -> Result<RichTerm, ParseError> {
-        self.parse_nocache_multi(file_id, InputFormat::Nickel)
+        let format = InputFormat::from_path_buf(Path::new(self.files.name(file_id)))
+            .unwrap_or(InputFormat::Nickel);
+        self.parse_nocache_multi(file_id, format)
     }
 
     /// Parse a source without querying nor populating the cache. Support multiple formats.
@@ -316,13 +478,13 @@ impl Cache {
         file_id: FileId,
         format: InputFormat,
     ) -> Result<RichTerm, ParseError> {
+        let _span = trace_span!("parse", file = self.name(file_id).to_string_lossy());
         let buf = self.files.source(file_id);
 
         match format {
             InputFormat::Nickel => {
-                let t = parser::grammar::TermParser::new()
-                    .parse(file_id, Lexer::new(&buf))
-                    .map_err(|err| ParseError::from_lalrpop(err, file_id))?;
+                let lexer = Lexer::with_ident_policy(&buf, self.ident_policy);
+                let t = parser::grammar::ExtendedTermParser::new().parse_file(file_id, lexer)?;
                 Ok(t)
             }
             InputFormat::Json => serde_json::from_str(self.files.source(file_id))
@@ -348,9 +510,15 @@ impl Cache {
         // After self.parse(), the cache must be populated
         let (t, state) = self.terms.get(&file_id).unwrap();
 
-        if *state > EntryState::Typechecked {
+        if *state >= EntryState::Typechecked {
+            trace_event!(
+                "typecheck",
+                file = self.name(file_id).to_string_lossy(),
+                cache = "hit"
+            );
             Ok(CacheOp::Cached(()))
         } else if *state == EntryState::Parsed {
+            let _span = trace_span!("typecheck", file = self.name(file_id).to_string_lossy());
             type_check(t, global_env, self)?;
             self.update_state(file_id, EntryState::Typechecked);
             Ok(CacheOp::Done(()))
@@ -364,8 +532,16 @@ impl Cache {
     /// source has been parsed.
     pub fn transform(&mut self, file_id: FileId) -> Result<CacheOp<()>, CacheError<ImportError>> {
         match self.entry_state(file_id) {
-            Some(EntryState::Transformed) => Ok(CacheOp::Cached(())),
+            Some(EntryState::Transformed) => {
+                trace_event!(
+                    "transform",
+                    file = self.name(file_id).to_string_lossy(),
+                    cache = "hit"
+                );
+                Ok(CacheOp::Cached(()))
+            }
             Some(_) => {
+                let _span = trace_span!("transform", file = self.name(file_id).to_string_lossy());
                 let (t, _) = self.terms.remove(&file_id).unwrap();
                 let t = transformations::transform(t, self)?;
                 self.terms.insert(file_id, (t, EntryState::Transformed));
@@ -395,8 +571,16 @@ impl Cache {
         file_id: FileId,
     ) -> Result<CacheOp<()>, CacheError<ImportError>> {
         match self.entry_state(file_id) {
-            Some(EntryState::Transformed) => Ok(CacheOp::Cached(())),
+            Some(EntryState::Transformed) => {
+                trace_event!(
+                    "transform",
+                    file = self.name(file_id).to_string_lossy(),
+                    cache = "hit"
+                );
+                Ok(CacheOp::Cached(()))
+            }
             Some(_) => {
+                let _span = trace_span!("transform", file = self.name(file_id).to_string_lossy());
                 let (mut t, _) = self.terms.remove(&file_id).unwrap();
                 match t.term.as_mut() {
                     Term::Record(ref mut map) | Term::RecRecord(ref mut map) => {
@@ -461,7 +645,11 @@ impl Cache {
         global_env: &eval::Environment,
     ) -> Result<RichTerm, Error> {
         let term = self.parse_nocache(file_id)?;
-        type_check(&term, global_env, self)?;
+        {
+            let _span = trace_span!("typecheck", file = self.name(file_id).to_string_lossy());
+            type_check(&term, global_env, self)?;
+        }
+        let _span = trace_span!("transform", file = self.name(file_id).to_string_lossy());
         let term = transformations::transform(term, self)?;
         Ok(term)
     }
@@ -487,8 +675,8 @@ impl Cache {
     /// metadata retrieval fails, or if the stored entry has no timestamps (it was added as a
     /// stand-alone source), `None` is returned.
     pub fn id_of_file(&self, path: impl AsRef<OsStr>) -> io::Result<Option<FileId>> {
-        let normalized = normalize_path(PathBuf::from(path.as_ref()).as_path())?;
-        let timestamp = timestamp(path)?;
+        let normalized = self.fs.normalize(Path::new(path.as_ref()))?;
+        let timestamp = self.fs.modified(Path::new(path.as_ref()))?;
         Ok(self.id_of_file_(normalized, timestamp))
     }
 
@@ -503,6 +691,11 @@ impl Cache {
             })
     }
 
+    /// Get a reference to the underlying files.
+    pub fn files(&self) -> &Files<String> {
+        &self.files
+    }
+
     /// Get a mutable reference to the underlying files. Required by
     /// [`to_diagnostic`](../error/trait.ToDiagnostic.html#tymethod.to_diagnostic).
     pub fn files_mut(&mut self) -> &mut Files<String> {
@@ -532,6 +725,14 @@ impl Cache {
         self.terms.get(&file_id).map(|(t, _)| t)
     }
 
+    /// Return `true` if the source is a module of the standard library.
+    pub fn is_stdlib(&self, file_id: FileId) -> bool {
+        self.stdlib_ids
+            .as_ref()
+            .map(|ids| ids.contains(&file_id))
+            .unwrap_or(false)
+    }
+
     /// Load and parse the standard library in the cache.
     pub fn load_stdlib(&mut self) -> Result<CacheOp<()>, Error> {
         if self.stdlib_ids.is_some() {
@@ -580,6 +781,7 @@ impl Cache {
 
     /// Load, parse, typecheck and apply program transformations to the standard library.
     pub fn prepare_stdlib(&mut self) -> Result<(), Error> {
+        let _span = trace_span!("stdlib");
         // We have a small bootstraping problem: to typecheck the global environment, we already
         // need a global evaluation environment, because stdlib parts may be mutually recursive.
         // But typechecking is performed before program transformations, so this environment is not
@@ -666,6 +868,14 @@ pub trait ImportResolver {
         pos: &TermPos,
     ) -> Result<(ResolvedTerm, FileId), ImportError>;
 
+    /// Read the content of a file imported as a raw string, without parsing it.
+    fn resolve_str(
+        &mut self,
+        path: &OsStr,
+        parent: Option<PathBuf>,
+        pos: &TermPos,
+    ) -> Result<String, ImportError>;
+
     /// Insert an entry in the term cache after transformation.
     fn insert(&mut self, file_id: FileId, term: RichTerm);
 
@@ -682,7 +892,9 @@ impl ImportResolver for Cache {
         parent: Option<PathBuf>,
         pos: &TermPos,
     ) -> Result<(ResolvedTerm, FileId), ImportError> {
-        let path_buf = with_parent(path, parent);
+        let _span = trace_span!("import", path = path.to_string_lossy());
+        let parent_id = parent.as_ref().and_then(|parent| self.id_of(parent));
+        let path_buf = self.find_import(path, parent);
         let format = InputFormat::from_path_buf(&path_buf).unwrap_or(InputFormat::Nickel);
         let id_op = self.get_or_add_file(&path_buf).map_err(|err| {
             ImportError::IOError(
@@ -691,8 +903,23 @@ impl ImportResolver for Cache {
                 *pos,
             )
         })?;
+
+        let imported = match id_op {
+            CacheOp::Cached(id) | CacheOp::Done(id) => id,
+        };
+        if let Some(parent_id) = parent_id {
+            self.add_import(parent_id, imported);
+        }
+
         let file_id = match id_op {
-            CacheOp::Cached(id) => return Ok((ResolvedTerm::FromCache(), id)),
+            CacheOp::Cached(id) => {
+                trace_event!(
+                    "parse",
+                    file = self.name(id).to_string_lossy(),
+                    cache = "hit"
+                );
+                return Ok((ResolvedTerm::FromCache(), id));
+            }
             CacheOp::Done(id) => id,
         };
 
@@ -708,6 +935,27 @@ impl ImportResolver for Cache {
         ))
     }
 
+    fn resolve_str(
+        &mut self,
+        path: &OsStr,
+        parent: Option<PathBuf>,
+        pos: &TermPos,
+    ) -> Result<String, ImportError> {
+        let path_buf = self.find_import(path, parent);
+        let file_id = match self.get_or_add_file(&path_buf) {
+            Ok(CacheOp::Cached(id)) | Ok(CacheOp::Done(id)) => id,
+            Err(err) => {
+                return Err(ImportError::IOError(
+                    path.to_string_lossy().into_owned(),
+                    format!("{}", err),
+                    *pos,
+                ))
+            }
+        };
+
+        Ok(self.files.source(file_id).clone())
+    }
+
     fn get(&self, file_id: FileId) -> Option<RichTerm> {
         self.terms.get(&file_id).map(|(term, state)| {
             debug_assert!(*state == EntryState::Transformed);
@@ -762,6 +1010,15 @@ pub mod resolvers {
             panic!("cache::resolvers: dummy resolver should not have been invoked");
         }
 
+        fn resolve_str(
+            &mut self,
+            _path: &OsStr,
+            _parent: Option<PathBuf>,
+            _pos: &TermPos,
+        ) -> Result<String, ImportError> {
+            panic!("cache::resolvers: dummy resolver should not have been invoked");
+        }
+
         fn insert(&mut self, _file_id: FileId, _term: RichTerm) {
             panic!("cache::resolvers: dummy resolver should not have been invoked");
         }
@@ -825,8 +1082,7 @@ pub mod resolvers {
                 self.term_cache.insert(file_id, None);
                 let buf = self.files.source(file_id);
                 let term = parser::grammar::TermParser::new()
-                    .parse(file_id, Lexer::new(&buf))
-                    .map_err(|e| ParseError::from_lalrpop(e, file_id))
+                    .parse_strict(file_id, Lexer::new(&buf))
                     .map_err(|e| ImportError::ParseError(e, *pos))?;
                 Ok((
                     ResolvedTerm::FromFile {
@@ -838,6 +1094,24 @@ pub mod resolvers {
             }
         }
 
+        fn resolve_str(
+            &mut self,
+            path: &OsStr,
+            _parent: Option<PathBuf>,
+            pos: &TermPos,
+        ) -> Result<String, ImportError> {
+            self.file_cache
+                .get(path.to_string_lossy().as_ref())
+                .map(|file_id| self.files.source(*file_id).clone())
+                .ok_or_else(|| {
+                    ImportError::IOError(
+                        path.to_string_lossy().into_owned(),
+                        String::from("Import not found by the mockup resolver."),
+                        *pos,
+                    )
+                })
+        }
+
         fn insert(&mut self, file_id: FileId, term: RichTerm) {
             self.term_cache.insert(file_id, Some(term));
         }
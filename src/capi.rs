@@ -0,0 +1,504 @@
+//! C interface, enabled by the `capi` feature.
+//!
+//! The crate is built as a Rust library only. The shared library exposing this interface is built
+//! with `cargo rustc --lib --release --features capi --crate-type cdylib`.
+//!
+//! The interface revolves around an opaque context, which holds the result of the last
+//! operation, or the corresponding diagnostics, as a JSON list. The result is a JSON document for
+//! evaluations and queries, the exported text for exports, and `null` for typechecking. See
+//! `include/nickel.h` for the C declarations. The Python bindings of `bindings/python` expose the
+//! same operations.
+//!
+//! ```c
+//! nickel_context *ctx = nickel_context_new();
+//! if (nickel_eval_string(ctx, "{port = 80 + 8000}") == 0) {
+//!     printf("%s\n", nickel_result_json(ctx));
+//! } else {
+//!     fprintf(stderr, "%s\n", nickel_diagnostics_json(ctx));
+//! }
+//! nickel_context_free(ctx);
+//! ```
+//!
+//! Strings returned by the context are owned by the context, and are valid until the next
+//! evaluation or until the context is freed. Diagnostics are written as a list of objects with a
+//! `severity`, a `code`, a `message`, a list of `labels`, a list of `notes` and a list of suggested
+//! `fixes`, as described in [`diagnostic::to_json`](../error/diagnostic/fn.to_json.html).
+//!
+//! Files added to a context with `nickel_context_add_file` form a virtual filesystem: once a file
+//! has been added, the sources evaluated in this context, and their imports, are read from the
+//! added files only, and never from the filesystem of the operating system. This is useful to run
+//! Nickel inside a sandbox, such as a WASI runtime.
+//!
+//! The result of a query is an object with the `doc`, the `types` and `contracts` annotations,
+//! the `default` flag and the shallow representation of the `value` of the queried field, and the
+//! list of its `fields` if it is a record.
+use crate::error::{diagnostic, Error, IOError};
+use crate::program::{Program, ProgramBuilder};
+use crate::serialize::{self, ExportFormat, ExportOptions, Secrets};
+use crate::term::{AnnotKind, MergePriority, MetaValue, RichTerm, Term, REDACTED};
+use crate::vfs::VirtualFileSystem;
+use codespan::Files;
+use serde_json::{json, Value};
+use std::ffi::{CStr, CString, OsStr};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::rc::Rc;
+
+/// The evaluation context.
+pub struct Context {
+    result: Option<CString>,
+    diagnostics: Option<CString>,
+    fs: Option<Rc<VirtualFileSystem>>,
+}
+
+/// Convert a string to a C string. Interior null bytes, which can't be represented, are escaped
+/// as `\u0000` by the JSON serializer, so this never fails on JSON documents.
+fn to_c_string(s: String) -> CString {
+    CString::new(s.replace('\0', "\\u0000")).unwrap()
+}
+
+/// Convert the result of a query to JSON. Also used by the Python bindings.
+pub fn query_to_json(t: &Term) -> Value {
+    let fields = |t: &Term| match t {
+        Term::Record(map) | Term::RecRecord(map) => {
+            let mut fields: Vec<_> = map.keys().map(|id| id.to_string()).collect();
+            fields.sort();
+            json!(fields)
+        }
+        _ => Value::Null,
+    };
+
+    match t {
+        Term::MetaValue(meta @ MetaValue { value, .. }) => {
+            let (types, contracts): (Vec<_>, Vec<_>) = meta
+                .annotations()
+                .into_iter()
+                .partition(|ctr| ctr.kind == AnnotKind::Type);
+            let to_strings = |ctrs: Vec<&crate::term::Contract>| -> Vec<String> {
+                ctrs.iter().map(|ctr| ctr.label.types.to_string()).collect()
+            };
+
+            json!({
+                "doc": meta.doc,
+                "types": to_strings(types),
+                "contracts": to_strings(contracts),
+                "default": meta.priority == MergePriority::Default,
+                "value": value.as_ref().map(|rt| if meta.sensitive {
+                    String::from(REDACTED)
+                } else {
+                    rt.as_ref().shallow_repr()
+                }),
+                "fields": value.as_ref().map_or(Value::Null, |rt| fields(rt.as_ref())),
+            })
+        }
+        t => json!({
+            "doc": null,
+            "types": [],
+            "contracts": [],
+            "default": false,
+            "value": t.shallow_repr(),
+            "fields": fields(t),
+        }),
+    }
+}
+
+impl Context {
+    fn new() -> Self {
+        Context {
+            result: None,
+            diagnostics: None,
+            fs: None,
+        }
+    }
+
+    /// Create a program builder, reading files from the virtual filesystem if any.
+    fn builder(&self) -> ProgramBuilder {
+        match self.fs {
+            Some(ref fs) => Program::builder().file_system(fs.clone()),
+            None => Program::builder(),
+        }
+    }
+
+    /// Create a program from a source string.
+    fn mk_program(&self, source: &str) -> Result<Program, Error> {
+        self.builder().named_source_str("<capi>", source).build()
+    }
+
+    /// Evaluate a program to JSON and store the result or the diagnostics.
+    fn eval(&mut self, program: Result<Program, Error>) -> c_int {
+        self.run(program, |program| {
+            let rt = program.eval_full().map(RichTerm::from)?;
+            let rt = serialize::redact(rt, Secrets::default())?;
+            serialize::validate(ExportFormat::Json, &rt)?;
+            Ok(serialize::to_string(ExportFormat::Json, &rt)?)
+        })
+    }
+
+    /// Run an operation on a program, and store its result or the diagnostics.
+    fn run<F>(&mut self, program: Result<Program, Error>, f: F) -> c_int
+    where
+        F: FnOnce(&mut Program) -> Result<String, Error>,
+    {
+        self.result = None;
+        self.diagnostics = None;
+
+        let mut program = match program {
+            Ok(program) => program,
+            Err(err) => {
+                self.diagnostics = Some(to_c_string(build_error_to_json(&err).to_string()));
+                return 1;
+            }
+        };
+
+        match f(&mut program) {
+            Ok(result) => {
+                self.result = Some(to_c_string(result));
+                0
+            }
+            Err(err) => {
+                let diagnostics = program.diagnostics(err);
+                self.diagnostics = Some(to_c_string(
+                    diagnostic::to_json(program.files(), &diagnostics).to_string(),
+                ));
+                1
+            }
+        }
+    }
+}
+
+/// Convert an error raised while building a program, such as a missing source file, to a list of
+/// diagnostics. Also used by the Python bindings.
+pub fn build_error_to_json(err: &Error) -> Value {
+    let mut files = Files::new();
+    let diagnostics = err.diagnostics(&mut files, None);
+    diagnostic::to_json(&files, &diagnostics)
+}
+
+/// Convert a C string to a string slice. Return `None` if the pointer is null or if the string is
+/// not valid UTF-8.
+///
+/// # Safety
+///
+/// `s` must be null or a valid null-terminated string.
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        CStr::from_ptr(s).to_str().ok()
+    }
+}
+
+/// Run a function, turning a panic into an error code.
+fn catch<F>(f: F) -> c_int
+where
+    F: FnOnce() -> c_int,
+{
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(-1)
+}
+
+/// Create a new context. It must be freed with `nickel_context_free`.
+#[no_mangle]
+pub extern "C" fn nickel_context_new() -> *mut Context {
+    Box::into_raw(Box::new(Context::new()))
+}
+
+/// Free a context. Do nothing if `ctx` is null.
+///
+/// # Safety
+///
+/// `ctx` must be null or have been returned by `nickel_context_new`, and must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_context_free(ctx: *mut Context) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
+
+/// Add a file to the virtual filesystem of a context, or replace its content. Relative paths are
+/// relative to the root `/`. Return `0` on success and `-1` on invalid arguments.
+///
+/// # Safety
+///
+/// `ctx` must be a valid context, and `path` and `content` valid null-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_context_add_file(
+    ctx: *mut Context,
+    path: *const c_char,
+    content: *const c_char,
+) -> c_int {
+    match (ctx.as_mut(), to_str(path), to_str(content)) {
+        (Some(ctx), Some(path), Some(content)) => {
+            Rc::make_mut(ctx.fs.get_or_insert_with(Default::default)).add_file(path, content);
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Evaluate a null-terminated UTF-8 string. Return `0` on success, `1` if the evaluation failed,
+/// in which case the diagnostics are available, and `-1` on invalid arguments or on an internal
+/// error.
+///
+/// # Safety
+///
+/// `ctx` must be a valid context, and `source` a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_string(ctx: *mut Context, source: *const c_char) -> c_int {
+    let (ctx, source) = match (ctx.as_mut(), to_str(source)) {
+        (Some(ctx), Some(source)) => (ctx, source),
+        _ => return -1,
+    };
+
+    catch(|| ctx.eval(ctx.mk_program(source)))
+}
+
+/// Evaluate a file, given as a null-terminated path. Return codes are the same as for
+/// `nickel_eval_string`.
+///
+/// # Safety
+///
+/// `ctx` must be a valid context, and `path` a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_file(ctx: *mut Context, path: *const c_char) -> c_int {
+    let (ctx, path) = match (ctx.as_mut(), to_str(path)) {
+        (Some(ctx), Some(path)) => (ctx, path),
+        _ => return -1,
+    };
+
+    catch(|| ctx.eval(ctx.builder().source_file(OsStr::new(path)).build()))
+}
+
+/// Typecheck a null-terminated UTF-8 string. Return codes are the same as for
+/// `nickel_eval_string`.
+///
+/// # Safety
+///
+/// `ctx` must be a valid context, and `source` a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_typecheck_string(
+    ctx: *mut Context,
+    source: *const c_char,
+) -> c_int {
+    let (ctx, source) = match (ctx.as_mut(), to_str(source)) {
+        (Some(ctx), Some(source)) => (ctx, source),
+        _ => return -1,
+    };
+
+    catch(|| {
+        ctx.run(ctx.mk_program(source), |program| {
+            program.typecheck()?;
+            Ok(String::from("null"))
+        })
+    })
+}
+
+/// Query the metadata of a path of a null-terminated UTF-8 string. If `path` is null, query the
+/// whole program. Return codes are the same as for `nickel_eval_string`.
+///
+/// # Safety
+///
+/// `ctx` must be a valid context, `source` a valid null-terminated string, and `path` null or a
+/// valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_query_string(
+    ctx: *mut Context,
+    source: *const c_char,
+    path: *const c_char,
+) -> c_int {
+    let (ctx, source) = match (ctx.as_mut(), to_str(source)) {
+        (Some(ctx), Some(source)) => (ctx, source),
+        _ => return -1,
+    };
+    let path = match to_str(path) {
+        Some(path) => Some(String::from(path)),
+        None if path.is_null() => None,
+        None => return -1,
+    };
+
+    catch(|| {
+        ctx.run(ctx.mk_program(source), |program| {
+            let t = program.query(path)?;
+            Ok(query_to_json(&t).to_string())
+        })
+    })
+}
+
+/// Export a null-terminated UTF-8 string to the given format (`json`, `yaml`, `toml`, etc.).
+/// Return codes are the same as for `nickel_eval_string`. Binary formats are not supported.
+///
+/// # Safety
+///
+/// `ctx` must be a valid context, and `source` and `format` valid null-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_export_string(
+    ctx: *mut Context,
+    source: *const c_char,
+    format: *const c_char,
+) -> c_int {
+    let (ctx, source, format) = match (ctx.as_mut(), to_str(source), to_str(format)) {
+        (Some(ctx), Some(source), Some(format)) => (ctx, source, format),
+        _ => return -1,
+    };
+
+    catch(|| {
+        let format = format.parse::<ExportFormat>();
+        ctx.run(ctx.mk_program(source), |program| {
+            let format = format.map_err(|err| IOError(err.to_string()))?;
+            let rt = program.eval_full().map(RichTerm::from)?;
+            let rt = serialize::redact(rt, Secrets::default())?;
+            serialize::validate(format, &rt)?;
+            Ok(serialize::to_string_with_options(
+                format,
+                &rt,
+                &ExportOptions::default(),
+            )?)
+        })
+    })
+}
+
+/// Return the result of the last successful operation, or null if the last operation failed.
+///
+/// # Safety
+///
+/// `ctx` must be a valid context.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_result_json(ctx: *const Context) -> *const c_char {
+    ctx.as_ref()
+        .and_then(|ctx| ctx.result.as_ref())
+        .map_or(ptr::null(), |s| s.as_ptr())
+}
+
+/// Return the diagnostics of the last failed operation as a JSON list, or null if the last
+/// operation succeeded.
+///
+/// # Safety
+///
+/// `ctx` must be a valid context.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_diagnostics_json(ctx: *const Context) -> *const c_char {
+    ctx.as_ref()
+        .and_then(|ctx| ctx.diagnostics.as_ref())
+        .map_or(ptr::null(), |s| s.as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval() {
+        let ctx = nickel_context_new();
+        let source = CString::new("{port = 80 + 8000, hosts = [\"a\"]}").unwrap();
+
+        unsafe {
+            assert_eq!(nickel_eval_string(ctx, source.as_ptr()), 0);
+            let result: Value =
+                serde_json::from_str(to_str(nickel_result_json(ctx)).unwrap()).unwrap();
+            assert_eq!(result, json!({"port": 8080, "hosts": ["a"]}));
+            assert_eq!(to_str(nickel_diagnostics_json(ctx)), None);
+
+            let source = CString::new("{a = 1 + \"b\"}").unwrap();
+            assert_eq!(nickel_eval_string(ctx, source.as_ptr()), 1);
+            assert_eq!(to_str(nickel_result_json(ctx)), None);
+            let diagnostics: Value =
+                serde_json::from_str(to_str(nickel_diagnostics_json(ctx)).unwrap()).unwrap();
+            assert_eq!(diagnostics[0]["severity"], json!("error"));
+            assert!(diagnostics[0]["labels"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|label| label["file"] == json!("<capi>")
+                    && label["start"] == json!({"line": 1, "column": 10})));
+
+            let path = CString::new("/nonexistent/file.ncl").unwrap();
+            assert_eq!(nickel_eval_file(ctx, path.as_ptr()), 1);
+            let diagnostics: Value =
+                serde_json::from_str(to_str(nickel_diagnostics_json(ctx)).unwrap()).unwrap();
+            assert_eq!(diagnostics[0]["code"], json!("N0602"));
+
+            assert_eq!(nickel_eval_string(ctx, ptr::null()), -1);
+            nickel_context_free(ctx);
+        }
+    }
+
+    #[test]
+    fn operations() {
+        let ctx = nickel_context_new();
+        let source =
+            CString::new("{server = {port | doc \"The port\" | Num | default = 80}}").unwrap();
+
+        unsafe {
+            let path = CString::new("server.port").unwrap();
+            assert_eq!(nickel_query_string(ctx, source.as_ptr(), path.as_ptr()), 0);
+            let result: Value =
+                serde_json::from_str(to_str(nickel_result_json(ctx)).unwrap()).unwrap();
+            assert_eq!(result["doc"], json!("The port"));
+            assert_eq!(result["contracts"], json!(["Num"]));
+            assert_eq!(result["default"], json!(true));
+
+            assert_eq!(nickel_query_string(ctx, source.as_ptr(), ptr::null()), 0);
+            let result: Value =
+                serde_json::from_str(to_str(nickel_result_json(ctx)).unwrap()).unwrap();
+            assert_eq!(result["fields"], json!(["server"]));
+
+            let format = CString::new("yaml").unwrap();
+            assert_eq!(
+                nickel_export_string(ctx, source.as_ptr(), format.as_ptr()),
+                0
+            );
+            assert_eq!(
+                to_str(nickel_result_json(ctx)),
+                Some("---\nserver:\n  port: 80\n")
+            );
+            let format = CString::new("unknown").unwrap();
+            assert_eq!(
+                nickel_export_string(ctx, source.as_ptr(), format.as_ptr()),
+                1
+            );
+
+            let source = CString::new("(1 + \"a\" : Num)").unwrap();
+            assert_eq!(nickel_typecheck_string(ctx, source.as_ptr()), 1);
+            let source = CString::new("(1 + 1 : Num)").unwrap();
+            assert_eq!(nickel_typecheck_string(ctx, source.as_ptr()), 0);
+
+            nickel_context_free(ctx);
+        }
+    }
+
+    #[test]
+    fn virtual_files() {
+        let ctx = nickel_context_new();
+        let path = CString::new("lib/ports.ncl").unwrap();
+        let content = CString::new("{http = 80}").unwrap();
+        let source = CString::new("(import \"lib/ports.ncl\").http").unwrap();
+        let missing = CString::new("import \"Cargo.toml\"").unwrap();
+
+        unsafe {
+            assert_eq!(
+                nickel_context_add_file(ctx, path.as_ptr(), content.as_ptr()),
+                0
+            );
+            assert_eq!(nickel_eval_string(ctx, source.as_ptr()), 0);
+            assert_eq!(to_str(nickel_result_json(ctx)), Some("80"));
+
+            let main = CString::new("/main.ncl").unwrap();
+            assert_eq!(
+                nickel_context_add_file(ctx, main.as_ptr(), source.as_ptr()),
+                0
+            );
+            assert_eq!(nickel_eval_file(ctx, main.as_ptr()), 0);
+            assert_eq!(to_str(nickel_result_json(ctx)), Some("80"));
+
+            // Files of the operating system are not visible anymore.
+            assert_eq!(nickel_eval_string(ctx, missing.as_ptr()), 1);
+            assert_eq!(
+                nickel_context_add_file(ctx, ptr::null(), content.as_ptr()),
+                -1
+            );
+
+            nickel_context_free(ctx);
+        }
+    }
+}
@@ -0,0 +1,535 @@
+//! Completion of the input at a cursor, shared by the language server and the REPL.
+//!
+//! What is completed is determined from the text which precedes the cursor:
+//!
+//! - After a dot, the fields of the record designated by the dotted path of identifiers before
+//!   it, such as `lists` in `lists.le`. The fields are the ones of the records the path may
+//!   evaluate to (see [`Analysis::records`](../lsp/analysis/struct.Analysis.html#method.records))
+//!   and the ones of the row type of its annotation, together with their type.
+//! - After a backtick, or at the place of a case of a switch, the enum tags expected there: the
+//!   tags of the enum type of the enclosing annotation or of the tested expression of the
+//!   enclosing switch. If none is known, the tags used anywhere in the input are suggested.
+//! - Inside the string of an import, the Nickel and data files of the corresponding directory.
+//! - Otherwise, the variables in scope, including the modules of the standard library.
+use crate::identifier::Ident;
+use crate::lsp::analysis::{scope_at, terms_at, Analysis, Imports};
+use crate::lsp::hover::annotated_type;
+use crate::lsp::parse_document;
+use crate::lsp::symbols::{detail, is_function};
+use crate::term::{RichTerm, Term};
+use crate::types::{AbsType, Types};
+use crate::vfs::FileSystem;
+use codespan::{ByteIndex, FileId};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+/// The identifier inserted at the cursor when the word being completed is empty, such as in
+/// `` `x == ` ``, so that the source is more likely to parse.
+const PLACEHOLDER: &str = "completion_placeholder";
+
+/// The extensions of the files suggested inside an import.
+const IMPORT_EXTENSIONS: [&str; 5] = ["ncl", "json", "yaml", "yml", "toml"];
+
+/// The kind of a completion.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CompletionKind {
+    Variable,
+    Function,
+    Field,
+    EnumTag,
+    File,
+    Directory,
+}
+
+/// A suggestion.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Completion {
+    /// The text to insert.
+    pub label: String,
+    pub kind: CompletionKind,
+    /// The type annotation or the contracts of the suggested symbol, if any.
+    pub detail: Option<String>,
+}
+
+impl Completion {
+    fn new(label: impl Into<String>, kind: CompletionKind) -> Self {
+        Completion {
+            label: label.into(),
+            kind,
+            detail: None,
+        }
+    }
+}
+
+/// What is completed at the cursor.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Target {
+    /// A variable.
+    Ident,
+    /// A field of the record designated by a dotted path of identifiers. The path is empty if
+    /// the accessed expression is not a path, such as in `(f x).`.
+    Field(Vec<String>),
+    /// An enum tag.
+    EnumTag,
+    /// A file inside the string of an import, in the given directory, relative to the importing
+    /// file.
+    ImportPath(String),
+}
+
+/// Determine what is completed at the end of a text. Return the target together with the byte
+/// offset where the partial word being completed starts.
+pub fn target(before: &str) -> (Target, usize) {
+    if let Some((dir, start)) = import_string(before) {
+        return (Target::ImportPath(String::from(dir)), start);
+    }
+
+    let start = word_start(before);
+    let preceding = &before[..start];
+
+    if preceding.ends_with('`') {
+        (Target::EnumTag, start)
+    } else if let Some(record) = preceding.strip_suffix('.') {
+        let mut path = Vec::new();
+        let mut rest = record;
+
+        loop {
+            let word_start = word_start(rest);
+            if word_start == rest.len() {
+                path.clear();
+                break;
+            }
+
+            path.push(String::from(&rest[word_start..]));
+            match rest[..word_start].strip_suffix('.') {
+                Some(r) => rest = r,
+                None => break,
+            }
+        }
+
+        path.reverse();
+        (Target::Field(path), start)
+    } else if is_switch_case(preceding) {
+        (Target::EnumTag, start)
+    } else {
+        (Target::Ident, start)
+    }
+}
+
+/// Return the byte offset of the start of the identifier which ends the text.
+fn word_start(text: &str) -> usize {
+    text.char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+        .last()
+        .map_or(text.len(), |(i, _)| i)
+}
+
+/// If the text ends inside the string of an import, return the directory part of the string and
+/// the offset of the file name being completed.
+fn import_string(before: &str) -> Option<(&str, usize)> {
+    let quote = before.rfind('"')?;
+    let typed = &before[quote + 1..];
+    let keyword = before[..quote].trim_end();
+
+    if !keyword.ends_with("import") || typed.contains('\n') {
+        return None;
+    }
+
+    match typed.rfind('/') {
+        Some(slash) => Some((&typed[..slash], quote + 1 + slash + 1)),
+        None => Some(("", quote + 1)),
+    }
+}
+
+/// Return `true` if the text ends at the place of a case of a switch: right after the opening
+/// brace of the switch, or after a comma separating cases.
+fn is_switch_case(text: &str) -> bool {
+    let text = text.trim_end();
+    if !text.ends_with(['{', ',']) {
+        return false;
+    }
+
+    // Look for the unmatched opening brace before the cursor.
+    let mut depth = 0;
+    for (i, c) in text.char_indices().rev() {
+        match c {
+            '}' | ')' | ']' => depth += 1,
+            '(' | '[' if depth > 0 => depth -= 1,
+            '(' | '[' => return false,
+            '{' if depth > 0 => depth -= 1,
+            '{' => return text[..i].trim_end().ends_with("switch"),
+            _ => (),
+        }
+    }
+
+    false
+}
+
+/// Compute the completions at the given index of a source, where `globals` are bound. `imports`
+/// are the files imported by the source (see [`Imports`](../lsp/analysis/type.Imports.html)), and
+/// `files` the filesystem and the directory from which its imports are resolved, if any.
+///
+/// Return the byte offset where the partial word being completed starts, and the suggestions
+/// which start with this word, in alphabetical order.
+pub fn complete(
+    source: &str,
+    index: ByteIndex,
+    file_id: FileId,
+    globals: &HashMap<Ident, RichTerm>,
+    imports: &Imports,
+    files: Option<(&dyn FileSystem, &Path)>,
+) -> (usize, Vec<Completion>) {
+    let offset = index.to_usize().min(source.len());
+    let (target, start) = target(&source[..offset]);
+    let prefix = &source[start..offset];
+
+    let term = if prefix.is_empty() {
+        let source = format!("{}{}{}", &source[..offset], PLACEHOLDER, &source[offset..]);
+        parse_document(file_id, &source)
+    } else {
+        parse_document(file_id, source)
+    };
+    let analysis = Analysis::new(&term, globals, imports);
+
+    let mut completions = match target {
+        Target::Ident => {
+            let scope = scope_at(&term, index);
+            let locals = scope
+                .iter()
+                .rev()
+                .map(|(id, value)| binding_completion(id, *value, CompletionKind::Variable));
+            let globals = globals
+                .iter()
+                .map(|(id, value)| binding_completion(id, Some(value), CompletionKind::Variable));
+            locals.chain(globals).collect()
+        }
+        Target::Field(path) => field_completions(&path, &term, index, &analysis, globals),
+        Target::EnumTag => {
+            let mut tags = expected_tags(&term, index);
+            if tags.is_empty() {
+                collect_tags(&term, &mut tags);
+            }
+
+            tags.into_iter()
+                .map(|tag| Completion::new(tag, CompletionKind::EnumTag))
+                .collect()
+        }
+        Target::ImportPath(dir) => files
+            .map(|(fs, base)| import_completions(fs, &base.join(dir)))
+            .unwrap_or_default(),
+    };
+
+    completions.retain(|completion| {
+        completion.label.starts_with(prefix) && completion.label != PLACEHOLDER
+    });
+    // Sorting is stable: for duplicated labels, the first completion is the innermost binding.
+    completions.sort_by(|c1, c2| c1.label.cmp(&c2.label));
+    completions.dedup_by(|c1, c2| c1.label == c2.label);
+
+    (start, completions)
+}
+
+/// Return the completion of a binding to the given term.
+fn binding_completion(id: &Ident, value: Option<&RichTerm>, kind: CompletionKind) -> Completion {
+    match value {
+        Some(value) => Completion {
+            label: id.label.clone(),
+            kind: if is_function(value) {
+                CompletionKind::Function
+            } else {
+                kind
+            },
+            detail: detail(value),
+        },
+        None => Completion::new(id.label.clone(), kind),
+    }
+}
+
+/// Return the fields of the record designated by a path of identifiers at the given index.
+fn field_completions<'a>(
+    path: &[String],
+    term: &'a RichTerm,
+    index: ByteIndex,
+    analysis: &Analysis<'a>,
+    globals: &'a HashMap<Ident, RichTerm>,
+) -> Vec<Completion> {
+    let (head, tail) = match path.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+
+    let scope = scope_at(term, index);
+    let value = match scope.iter().rev().find(|(id, _)| &id.label == head) {
+        Some((_, value)) => *value,
+        None => globals.get(&Ident::from(head.as_str())),
+    };
+
+    let mut values: Vec<&'a RichTerm> = value.into_iter().collect();
+    let mut types: Vec<Types> = values.iter().filter_map(|v| annotated_type(v)).collect();
+
+    for name in tail {
+        let id = Ident::from(name.as_str());
+        types = types
+            .iter()
+            .filter_map(|ty| record_rows(ty).remove(name))
+            .chain(values.iter().flat_map(|value| {
+                analysis
+                    .field_definitions(value, &id)
+                    .into_iter()
+                    .filter_map(|(_, field)| annotated_type(field))
+            }))
+            .collect();
+        values = values
+            .iter()
+            .flat_map(|value| analysis.field_definitions(value, &id))
+            .map(|(_, field)| field)
+            .collect();
+    }
+
+    let fields = values
+        .iter()
+        .flat_map(|value| analysis.records(value))
+        .flat_map(|record| record.iter())
+        .map(|(id, value)| binding_completion(id, Some(value), CompletionKind::Field));
+    let rows = types
+        .iter()
+        .flat_map(record_rows)
+        .map(|(name, ty)| Completion {
+            label: name,
+            kind: match ty.0 {
+                AbsType::Arrow(..) => CompletionKind::Function,
+                _ => CompletionKind::Field,
+            },
+            detail: Some(ty.to_string()),
+        });
+
+    fields.chain(rows).collect()
+}
+
+/// Return the fields of a record type, together with their type.
+fn record_rows(ty: &Types) -> HashMap<String, Types> {
+    let mut rows = HashMap::new();
+
+    if let AbsType::StaticRecord(row) = &ty.0 {
+        let mut row = row.as_ref();
+        while let AbsType::RowExtend(id, Some(ty), tail) = &row.0 {
+            rows.insert(id.label.clone(), ty.as_ref().clone());
+            row = tail;
+        }
+    }
+
+    rows
+}
+
+/// Return the tags of an enum type.
+fn enum_rows(ty: &Types) -> Vec<String> {
+    let mut tags = Vec::new();
+
+    if let AbsType::Enum(row) = &ty.0 {
+        let mut row = row.as_ref();
+        while let AbsType::RowExtend(id, None, tail) = &row.0 {
+            tags.push(id.label.clone());
+            row = tail;
+        }
+    }
+
+    tags
+}
+
+/// Return the types of the annotation and of the contracts of a term.
+fn annotations(rt: &RichTerm) -> Vec<&Types> {
+    match rt.as_ref() {
+        Term::MetaValue(meta) => meta
+            .types
+            .iter()
+            .chain(meta.contracts.iter())
+            .map(|ctr| &ctr.types)
+            .collect(),
+        Term::Promise(ty, _, _) => vec![ty],
+        _ => Vec::new(),
+    }
+}
+
+/// Return the enum tags expected at the given index, according to the innermost enclosing
+/// annotation or switch.
+fn expected_tags(term: &RichTerm, index: ByteIndex) -> Vec<String> {
+    let scope = scope_at(term, index);
+
+    for rt in terms_at(term, index).into_iter().rev() {
+        let tags: Vec<String> = match rt.as_ref() {
+            Term::Switch(exp, cases, _) => {
+                let value = match exp.as_ref() {
+                    Term::Var(id) => scope
+                        .iter()
+                        .rev()
+                        .find(|(bound, _)| *bound == id)
+                        .and_then(|(_, value)| *value),
+                    _ => Some(exp),
+                };
+                let typed = value.map(annotations).unwrap_or_default();
+
+                typed
+                    .into_iter()
+                    .flat_map(enum_rows)
+                    .chain(cases.keys().map(|id| id.label.clone()))
+                    .collect()
+            }
+            _ => annotations(rt).into_iter().flat_map(enum_rows).collect(),
+        };
+
+        if !tags.is_empty() {
+            return tags;
+        }
+    }
+
+    Vec::new()
+}
+
+/// Collect the enum tags appearing in a term: tag literals, cases of switches and enum types of
+/// annotations.
+fn collect_tags(rt: &RichTerm, tags: &mut Vec<String>) {
+    match rt.as_ref() {
+        Term::Enum(id) => tags.push(id.label.clone()),
+        Term::Switch(_, cases, _) => tags.extend(cases.keys().map(|id| id.label.clone())),
+        _ => tags.extend(annotations(rt).into_iter().flat_map(enum_rows)),
+    }
+
+    rt.term.for_each_subterm(|sub| collect_tags(sub, tags));
+}
+
+/// Return the files and the subdirectories of a directory which may be imported.
+fn import_completions(fs: &dyn FileSystem, dir: &Path) -> Vec<Completion> {
+    fs.list_files(dir)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|path| {
+            let mut components = path.strip_prefix(dir).ok()?.components();
+            let name = match components.next()? {
+                Component::Normal(name) => name.to_string_lossy().into_owned(),
+                _ => return None,
+            };
+
+            if components.next().is_some() {
+                Some(Completion::new(name, CompletionKind::Directory))
+            } else {
+                let extension = path.extension()?.to_str()?;
+                IMPORT_EXTENSIONS
+                    .contains(&extension)
+                    .then(|| Completion::new(name, CompletionKind::File))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use crate::vfs::VirtualFileSystem;
+    use codespan::Files;
+
+    /// Return the labels of the completions at the cursor, marked by `¦` in `source`, where
+    /// `globals` are bound.
+    fn complete_at(source: &str, globals: &HashMap<Ident, RichTerm>) -> Vec<String> {
+        let offset = source.find('¦').unwrap();
+        let source = source.replace('¦', "");
+        let file_id = Files::new().add("<test>", source.clone());
+
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/src/lib.ncl", "");
+        fs.add_file("/src/data.yaml", "");
+        fs.add_file("/src/README.md", "");
+        fs.add_file("/src/nested/server.ncl", "");
+        let files: Option<(&dyn FileSystem, &Path)> = Some((&fs, Path::new("/src")));
+
+        let index = ByteIndex(offset as u32);
+        complete(&source, index, file_id, globals, &HashMap::new(), files)
+            .1
+            .into_iter()
+            .map(|completion| completion.label)
+            .collect()
+    }
+
+    #[test]
+    fn targets() {
+        assert_eq!(target("1 + fo"), (Target::Ident, 4));
+        assert_eq!(
+            target("a.b.c"),
+            (Target::Field(vec![String::from("a"), String::from("b")]), 4)
+        );
+        assert_eq!(target("(f x)."), (Target::Field(Vec::new()), 6));
+        assert_eq!(target("x == `Fo"), (Target::EnumTag, 6));
+        assert_eq!(target("switch { a => 1, "), (Target::EnumTag, 17));
+        assert_eq!(target("switch { a => {b = 1}, c"), (Target::EnumTag, 23));
+        assert_eq!(target("{a = 1, "), (Target::Ident, 8));
+        assert_eq!(
+            target("import \"lib/se"),
+            (Target::ImportPath(String::from("lib")), 12)
+        );
+    }
+
+    #[test]
+    fn variables_and_fields() {
+        let mut globals = HashMap::new();
+        let lists = "{length : forall a. List a -> Num = fun l => 0, head = 1}";
+        globals.insert(
+            Ident::from("lists"),
+            grammar::TermParser::new()
+                .parse_tolerant(
+                    Files::new().add("", String::from(lists)),
+                    lexer::Lexer::new(lists),
+                )
+                .0,
+        );
+
+        assert_eq!(
+            complete_at("let low = 1 in let f = fun x => l¦", &globals),
+            vec!["lists", "low"]
+        );
+        assert_eq!(
+            complete_at("let low = 1 in lists.¦", &globals),
+            vec!["head", "length"]
+        );
+        assert_eq!(
+            complete_at(
+                "let r = {server = {port = 1}} & {server.host = 1} in r.server.¦",
+                &globals
+            ),
+            vec!["host", "port"]
+        );
+        assert_eq!(
+            complete_at("let r : {a : Num, ab : Str} = x in r.a¦", &globals),
+            vec!["a", "ab"]
+        );
+    }
+
+    #[test]
+    fn enum_tags() {
+        let globals = HashMap::new();
+        assert_eq!(
+            complete_at("let x | <foo, bar> = `¦", &globals),
+            vec!["bar", "foo"]
+        );
+        assert_eq!(
+            complete_at("let x = `baz in {y | <foo, bar> = `f¦}", &globals),
+            vec!["foo"]
+        );
+        assert_eq!(
+            complete_at("let x = `baz in x == `¦", &globals),
+            vec!["baz"]
+        );
+    }
+
+    #[test]
+    fn imports() {
+        let globals = HashMap::new();
+        assert_eq!(
+            complete_at("import \"¦", &globals),
+            vec!["data.yaml", "lib.ncl", "nested"]
+        );
+        assert_eq!(
+            complete_at("import \"nested/s¦", &globals),
+            vec!["server.ncl"]
+        );
+    }
+}
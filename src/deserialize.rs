@@ -0,0 +1,355 @@
+//! Extraction of Rust values from evaluated terms.
+//!
+//! A fully evaluated term implements [`serde::Deserializer`], so that any type implementing
+//! [`serde::Deserialize`] can be extracted from the result of a Nickel program:
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct Config {
+//!     host: String,
+//!     port: u16,
+//! }
+//!
+//! let config: Config = nickel::from_term(program.eval_full()?)?;
+//! ```
+//!
+//! Records are deserialized as maps or structs, lists as sequences, and enum tags as unit
+//! variants. An enum variant holding data is represented as a record with a single field named
+//! after the variant. Errors carry the position of the innermost term that couldn't be extracted.
+use crate::error::ExtractionError;
+use crate::identifier::Ident;
+use crate::position::TermPos;
+use crate::serialize::strip_meta;
+use crate::term::{RichTerm, Term};
+use serde::de::value::BorrowedStrDeserializer;
+use serde::de::{
+    self, Deserialize, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use std::fmt;
+
+impl fmt::Display for ExtractionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ExtractionError {}
+
+impl de::Error for ExtractionError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ExtractionError(msg.to_string(), TermPos::None)
+    }
+}
+
+impl ExtractionError {
+    /// Set the position of the error, if it doesn't have one already.
+    fn or_pos(self, pos: TermPos) -> Self {
+        match self {
+            ExtractionError(msg, TermPos::None) => ExtractionError(msg, pos),
+            err => err,
+        }
+    }
+}
+
+/// Extract a Rust value from a fully evaluated term.
+pub fn from_term<T, R>(rt: R) -> Result<T, ExtractionError>
+where
+    T: de::DeserializeOwned,
+    R: Into<RichTerm>,
+{
+    let rt = rt.into();
+    T::deserialize(&rt)
+}
+
+impl<'de> de::Deserializer<'de> for &'de RichTerm {
+    type Error = ExtractionError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let rt = strip_meta(self);
+
+        let result = match rt.as_ref() {
+            Term::Null => visitor.visit_unit(),
+            Term::Bool(b) => visitor.visit_bool(*b),
+            Term::Num(n) if n.fract() == 0.0 && *n >= 0.0 && *n < (u64::MAX as f64) => {
+                visitor.visit_u64(*n as u64)
+            }
+            Term::Num(n) if n.fract() == 0.0 && *n >= (i64::MIN as f64) && *n < 0.0 => {
+                visitor.visit_i64(*n as i64)
+            }
+            Term::Num(n) => visitor.visit_f64(*n),
+            Term::Str(s) => visitor.visit_borrowed_str(s),
+            Term::Enum(Ident { label: id, .. }) => visitor.visit_borrowed_str(id),
+            Term::List(ts) => visitor.visit_seq(ListAccess { iter: ts.iter() }),
+            Term::Record(map) => {
+                let mut fields: Vec<_> = map.iter().collect();
+                fields.sort_by_key(|(id, _)| *id);
+                visitor.visit_map(RecordAccess {
+                    iter: fields.into_iter(),
+                    value: None,
+                })
+            }
+            t => Err(ExtractionError(
+                format!(
+                    "expected a fully evaluated value, got {}",
+                    t.type_of()
+                        .unwrap_or_else(|| String::from("an unevaluated term"))
+                ),
+                rt.pos,
+            )),
+        };
+
+        result.map_err(|err| err.or_pos(rt.pos))
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let rt = strip_meta(self);
+
+        match rt.as_ref() {
+            Term::Null => visitor.visit_none(),
+            _ => visitor.visit_some(rt),
+        }
+        .map_err(|err| err.or_pos(rt.pos))
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let rt = strip_meta(self);
+
+        match rt.as_ref() {
+            Term::Enum(Ident { label: id, .. }) | Term::Str(id) => {
+                visitor.visit_enum(BorrowedStrDeserializer::new(id))
+            }
+            Term::Record(map) if map.len() == 1 => {
+                let (Ident { label: id, .. }, t) = map.iter().next().unwrap();
+                visitor.visit_enum(VariantRecord {
+                    variant: id,
+                    value: t,
+                })
+            }
+            _ => Err(ExtractionError(
+                String::from("expected an enum tag or a record with a single field"),
+                rt.pos,
+            )),
+        }
+        .map_err(|err| err.or_pos(rt.pos))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+struct ListAccess<'de> {
+    iter: std::slice::Iter<'de, RichTerm>,
+}
+
+impl<'de> SeqAccess<'de> for ListAccess<'de> {
+    type Error = ExtractionError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.iter.next().map(|t| seed.deserialize(t)).transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct RecordAccess<'de> {
+    iter: std::vec::IntoIter<(&'de Ident, &'de RichTerm)>,
+    value: Option<&'de RichTerm>,
+}
+
+impl<'de> MapAccess<'de> for RecordAccess<'de> {
+    type Error = ExtractionError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some((Ident { label: id, .. }, t)) => {
+                self.value = Some(t);
+                seed.deserialize(BorrowedStrDeserializer::new(id))
+                    .map(Some)
+                    .map_err(|err: ExtractionError| err.or_pos(t.pos))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let t = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before its key"))?;
+        seed.deserialize(t)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// An enum variant holding data, represented as a record with a single field.
+struct VariantRecord<'de> {
+    variant: &'de str,
+    value: &'de RichTerm,
+}
+
+impl<'de> EnumAccess<'de> for VariantRecord<'de> {
+    type Error = ExtractionError;
+    type Variant = &'de RichTerm;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for &'de RichTerm {
+    type Error = ExtractionError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        <()>::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::eval_full_str;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Protocol {
+        Http,
+        Https,
+        Custom(String),
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Server {
+        host: String,
+        port: u16,
+        weight: f64,
+        protocols: Vec<Protocol>,
+        backup: Option<bool>,
+        labels: HashMap<String, String>,
+    }
+
+    #[test]
+    fn structs() {
+        let server: Server = from_term(eval_full_str(
+            r#"{
+                host = "example.org",
+                port = 4000 + 443,
+                weight = 0.5,
+                protocols = [`http, "https", {custom = "gopher"}],
+                backup = null,
+                labels = {env = "prod"},
+            }"#,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            server,
+            Server {
+                host: String::from("example.org"),
+                port: 4443,
+                weight: 0.5,
+                protocols: vec![
+                    Protocol::Http,
+                    Protocol::Https,
+                    Protocol::Custom(String::from("gopher"))
+                ],
+                backup: None,
+                labels: vec![(String::from("env"), String::from("prod"))]
+                    .into_iter()
+                    .collect(),
+            }
+        );
+
+        assert_eq!(from_term::<i64, _>(eval_full_str("-3")).unwrap(), -3);
+        assert_eq!(
+            from_term::<(bool, String), _>(eval_full_str("[true, \"a\"]")).unwrap(),
+            (true, String::from("a"))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let source = "{host = \"example.org\", port = -1}";
+        let err = from_term::<Server, _>(eval_full_str(source)).unwrap_err();
+        let ExtractionError(_, pos) = err;
+        let span = pos.into_opt().unwrap();
+        assert_eq!(&source[span.start.to_usize()..span.end.to_usize()], "-1");
+
+        assert!(from_term::<u8, _>(eval_full_str("1.5")).is_err());
+        assert!(from_term::<Server, _>(eval_full_str("{host = \"a\"}")).is_err());
+        assert!(from_term::<Protocol, _>(eval_full_str("`ftp")).is_err());
+    }
+}
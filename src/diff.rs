@@ -0,0 +1,274 @@
+//! Structural diffs of values.
+//!
+//! Values are compared as JSON: records field by field and lists element by element, such that
+//! a change deep inside a large configuration is reported by a single line with its path, rather
+//! than by two full dumps of the values. Diffs are used to report the differences between a
+//! [snapshot](../snapshot/index.html) and the result of a program, between the results of two
+//! programs (`nickel diff`), and between the expected and the actual value of a failed
+//! `contracts.equal` contract.
+use crate::serialize::nickel::field_name;
+use crate::term::{MetaValue, RichTerm, Term, REDACTED};
+use codespan_reporting::term::termcolor::{Color, ColorSpec, WriteColor};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io;
+
+/// A difference between an expected value and the actual one, at a path of the actual value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A value has changed, possibly to a value of another type.
+    Changed {
+        path: String,
+        expected: Value,
+        actual: Value,
+    },
+    /// A field or an element is in the actual value, but not in the expected one.
+    Added { path: String, value: Value },
+    /// A field or an element is in the expected value, but not in the actual one.
+    Removed { path: String, value: Value },
+}
+
+impl Change {
+    /// The color of the change in a terminal: yellow for a changed value, green for an added
+    /// one and red for a removed one.
+    fn color(&self) -> Color {
+        match self {
+            Change::Changed { .. } => Color::Yellow,
+            Change::Added { .. } => Color::Green,
+            Change::Removed { .. } => Color::Red,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Changed {
+                path,
+                expected,
+                actual,
+            } => write!(f, "~ {}: {} -> {}", path, expected, actual),
+            Change::Added { path, value } => write!(f, "+ {}: {}", path, value),
+            Change::Removed { path, value } => write!(f, "- {}: {}", path, value),
+        }
+    }
+}
+
+/// Return the differences between an expected value and the actual one, in the order of the
+/// paths. The root is denoted by `.`.
+pub fn diff(expected: &Value, actual: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_at(&mut changes, "", expected, actual);
+    changes
+}
+
+fn diff_at(changes: &mut Vec<Change>, path: &str, expected: &Value, actual: &Value) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            let mut keys: Vec<&String> = expected.keys().chain(actual.keys()).collect();
+            keys.sort();
+            keys.dedup();
+
+            for key in keys {
+                let field_path = if path.is_empty() {
+                    field_name(key)
+                } else {
+                    format!("{}.{}", path, field_name(key))
+                };
+
+                match (expected.get(key), actual.get(key)) {
+                    (Some(e), Some(a)) => diff_at(changes, &field_path, e, a),
+                    (Some(e), None) => changes.push(Change::Removed {
+                        path: field_path,
+                        value: e.clone(),
+                    }),
+                    (None, Some(a)) => changes.push(Change::Added {
+                        path: field_path,
+                        value: a.clone(),
+                    }),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for i in 0..expected.len().max(actual.len()) {
+                let elt_path = format!("{}[{}]", path, i);
+
+                match (expected.get(i), actual.get(i)) {
+                    (Some(e), Some(a)) => diff_at(changes, &elt_path, e, a),
+                    (Some(e), None) => changes.push(Change::Removed {
+                        path: elt_path,
+                        value: e.clone(),
+                    }),
+                    (None, Some(a)) => changes.push(Change::Added {
+                        path: elt_path,
+                        value: a.clone(),
+                    }),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (expected, actual) if expected != actual => changes.push(Change::Changed {
+            path: if path.is_empty() {
+                String::from(".")
+            } else {
+                String::from(path)
+            },
+            expected: expected.clone(),
+            actual: actual.clone(),
+        }),
+        _ => (),
+    }
+}
+
+/// Convert a fully evaluated and substituted term to JSON, for comparison. Contrary to the
+/// serialization, the conversion never fails: the values which can't be serialized, such as
+/// functions, are represented by a string holding their
+/// [`shallow_repr`](../term/enum.Term.html#method.shallow_repr). Sensitive values are represented
+/// by [`REDACTED`](../term/constant.REDACTED.html), such that diffs never reveal them, nor their
+/// changes.
+pub fn to_value(t: &Term) -> Value {
+    match t {
+        Term::Record(map) | Term::RecRecord(map) => Value::Object(
+            map.iter()
+                .map(|(id, rt)| (id.label.clone(), to_value(rt.as_ref())))
+                .collect::<Map<String, Value>>(),
+        ),
+        Term::List(ts) => Value::Array(ts.iter().map(|rt| to_value(rt.as_ref())).collect()),
+        Term::MetaValue(MetaValue {
+            sensitive: true, ..
+        }) => Value::String(String::from(REDACTED)),
+        Term::MetaValue(MetaValue {
+            value: Some(rt), ..
+        }) => to_value(rt.as_ref()),
+        t => serde_json::to_value(t).unwrap_or_else(|_| Value::String(t.shallow_repr())),
+    }
+}
+
+/// Return the differences between two fully evaluated and substituted terms. See
+/// [`to_value`](fn.to_value.html).
+pub fn diff_terms(expected: &RichTerm, actual: &RichTerm) -> Vec<Change> {
+    diff(&to_value(expected.as_ref()), &to_value(actual.as_ref()))
+}
+
+/// Write changes one per line, indented by the given number of spaces, and colored if the writer
+/// supports it.
+pub fn write(writer: &mut dyn WriteColor, changes: &[Change], indent: usize) -> io::Result<()> {
+    for change in changes {
+        write!(writer, "{}", " ".repeat(indent))?;
+        writer.set_color(ColorSpec::new().set_fg(Some(change.color())))?;
+        write!(writer, "{}", change)?;
+        writer.reset()?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::make as mk_term;
+    use crate::{mk_app, mk_record};
+    use codespan_reporting::term::termcolor::{Ansi, NoColor};
+    use serde_json::json;
+
+    #[test]
+    fn structural_diff() {
+        let expected = json!({
+            "name": "web",
+            "ports": [80, 443],
+            "env": {"DEBUG": false, "old": 1},
+        });
+        let actual = json!({
+            "name": "web",
+            "ports": [8080],
+            "env": {"DEBUG": true, "my var": "x"},
+        });
+
+        let lines: Vec<String> = diff(&expected, &actual)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "~ env.DEBUG: false -> true",
+                "+ env.\"my var\": \"x\"",
+                "- env.old: 1",
+                "~ ports[0]: 80 -> 8080",
+                "- ports[1]: 443",
+            ]
+        );
+        assert_eq!(
+            diff(&json!(1), &json!("a")),
+            vec![Change::Changed {
+                path: String::from("."),
+                expected: json!(1),
+                actual: json!("a"),
+            }]
+        );
+        assert_eq!(diff(&expected, &expected), Vec::new());
+    }
+
+    #[test]
+    fn terms() {
+        let expected = mk_record!(
+            ("port", Term::Num(80.0)),
+            ("hosts", Term::List(vec![mk_term::string("a")]))
+        );
+        let actual = mk_record!(
+            ("port", Term::Num(80.5)),
+            ("hosts", Term::List(vec![mk_term::string("a")])),
+            ("handler", mk_term::id())
+        );
+        assert_eq!(
+            diff_terms(&expected, &actual),
+            vec![
+                Change::Added {
+                    path: String::from("handler"),
+                    value: json!("<func>"),
+                },
+                Change::Changed {
+                    path: String::from("port"),
+                    expected: json!(80),
+                    actual: json!(80.5),
+                },
+            ]
+        );
+        assert_eq!(
+            to_value(mk_app!(mk_term::id(), Term::Null).as_ref()),
+            json!("<unevaluated>")
+        );
+        let secret = |s: &str| {
+            mk_record!((
+                "password",
+                Term::MetaValue(MetaValue {
+                    sensitive: true,
+                    ..MetaValue::from(mk_term::string(s))
+                })
+            ))
+        };
+        assert_eq!(
+            to_value(secret("hunter2").as_ref()),
+            json!({"password": "<redacted>"})
+        );
+        assert_eq!(
+            diff_terms(&secret("hunter2"), &secret("hunter3")),
+            Vec::new()
+        );
+
+        let changes = diff_terms(&expected, &actual);
+        let mut plain = NoColor::new(Vec::new());
+        write(&mut plain, &changes, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(plain.into_inner()).unwrap(),
+            "  + handler: \"<func>\"\n  ~ port: 80 -> 80.5\n"
+        );
+        let mut colored = Ansi::new(Vec::new());
+        write(&mut colored, &changes[..1], 0).unwrap();
+        assert_eq!(
+            String::from_utf8(colored.into_inner()).unwrap(),
+            "\u{1b}[0m\u{1b}[32m+ handler: \"<func>\"\u{1b}[0m\n"
+        );
+    }
+}
@@ -0,0 +1,714 @@
+//! Documentation generator: the HTML pages written by `nickel doc`.
+//!
+//! The documentation is extracted from the parsed source of a program, without evaluating it, so
+//! that functions and fields without a value are documented as well. The program is expected to
+//! be a record, possibly behind let bindings, merges or imports. Each field is documented by its
+//! metadata: its documentation, its type annotation, its contracts and its default value, unless
+//! the value is sensitive. The fenced code blocks of a documentation are shown separately as
+//! examples. The definitions of a field which is defined several times, such as in merged
+//! records, are combined.
+//!
+//! Each record gets its own page, listing its fields. All the pages share a search index, which
+//! is a script defining the paths of all the fields, searched as the user types.
+use crate::cache::{Cache, ImportResolver};
+use crate::error::{Error, IOError};
+use crate::term::{BinaryOp, MergePriority, RichTerm, StrChunk, Term, REDACTED};
+use codespan::FileId;
+use serde_json::json;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The documentation of a record field, or of a whole program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    /// The documentation, without its examples.
+    pub doc: Option<String>,
+    /// The fenced code blocks of the documentation, without their delimiters.
+    pub examples: Vec<String>,
+    pub types: Option<String>,
+    pub contracts: Vec<String>,
+    /// The source of the default value, or [`REDACTED`](../term/constant.REDACTED.html) if the
+    /// value is sensitive.
+    pub default: Option<String>,
+    /// Whether the value is marked as sensitive.
+    pub sensitive: bool,
+    /// The fields of the value, sorted by name, if it is a record.
+    pub fields: Option<Vec<Entry>>,
+}
+
+/// Extract the documentation of a parsed program. The entry is named after the file.
+pub fn extract(cache: &mut Cache, file_id: FileId) -> Result<Entry, Error> {
+    let name = Path::new(cache.name(file_id))
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut entry = Entry {
+        name,
+        ..Default::default()
+    };
+
+    if let Some(rt) = cache.get_owned(file_id) {
+        let mut extractor = Extractor {
+            cache,
+            visiting: vec![file_id],
+        };
+        extractor.describe(&rt, file_id, &mut entry)?;
+    }
+
+    Ok(entry)
+}
+
+struct Extractor<'a> {
+    cache: &'a mut Cache,
+    /// The files being described, to stop at cyclic imports.
+    visiting: Vec<FileId>,
+}
+
+impl<'a> Extractor<'a> {
+    /// Add the documentation of a term to an entry. The metadata already in the entry take
+    /// precedence.
+    fn describe(&mut self, rt: &RichTerm, file_id: FileId, entry: &mut Entry) -> Result<(), Error> {
+        match rt.term.as_ref() {
+            Term::MetaValue(meta) => {
+                if let (None, Some(doc)) = (&entry.doc, &meta.doc) {
+                    let (text, examples) = split_examples(doc);
+                    entry.doc = Some(text).filter(|text| !text.is_empty());
+                    entry.examples = examples;
+                }
+                if entry.types.is_none() {
+                    entry.types = meta.types.as_ref().map(|ctr| ctr.types.to_string());
+                }
+                for ctr in &meta.contracts {
+                    let ctr = ctr.types.to_string();
+                    if !entry.contracts.contains(&ctr) {
+                        entry.contracts.push(ctr);
+                    }
+                }
+
+                if meta.sensitive {
+                    entry.sensitive = true;
+                    entry.default = entry.default.take().map(|_| String::from(REDACTED));
+                }
+
+                if let Some(value) = &meta.value {
+                    if meta.priority == MergePriority::Default && entry.default.is_none() {
+                        entry.default = Some(if entry.sensitive {
+                            String::from(REDACTED)
+                        } else {
+                            self.source(value)
+                        });
+                    }
+                    self.describe(value, file_id, entry)?;
+                }
+            }
+            Term::Let(_, _, body) => self.describe(body, file_id, entry)?,
+            Term::Record(map) | Term::RecRecord(map) => {
+                for (id, value) in map {
+                    self.describe(value, file_id, field(entry, &id.label))?;
+                }
+            }
+            // Fields with a quoted name are defined dynamically. Those with an interpolated name
+            // are not documented.
+            Term::App(head, value) => match head.as_ref() {
+                Term::Op2(BinaryOp::DynDefine(), name, record)
+                | Term::Op2(BinaryOp::DynExtend(), name, record) => {
+                    self.describe(record, file_id, entry)?;
+                    if let Some(name) = static_name(name) {
+                        self.describe(value, file_id, field(entry, &name))?;
+                    }
+                }
+                _ => (),
+            },
+            Term::Op2(BinaryOp::Merge(), t1, t2) => {
+                self.describe(t1, file_id, entry)?;
+                self.describe(t2, file_id, entry)?;
+            }
+            Term::Import(path) => self.describe_import(path, rt, file_id, entry)?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn describe_import(
+        &mut self,
+        path: &OsString,
+        rt: &RichTerm,
+        parent_id: FileId,
+        entry: &mut Entry,
+    ) -> Result<(), Error> {
+        let parent = PathBuf::from(self.cache.name(parent_id));
+        let (_, file_id) = self.cache.resolve(path, Some(parent), &rt.pos)?;
+
+        if !self.visiting.contains(&file_id) {
+            if let Some(imported) = self.cache.get_owned(file_id) {
+                self.visiting.push(file_id);
+                self.describe(&imported, file_id, entry)?;
+                self.visiting.pop();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the source of a term, or a representation of it if it has no position.
+    fn source(&self, rt: &RichTerm) -> String {
+        match rt.pos.into_opt() {
+            Some(span) => String::from(
+                &self.cache.files().source(span.src_id)[span.start.to_usize()..span.end.to_usize()],
+            ),
+            None => rt.as_ref().shallow_repr(),
+        }
+    }
+}
+
+/// Return the entry of a field of a record, inserted at its place in the sorted fields if needed.
+fn field<'e>(entry: &'e mut Entry, name: &str) -> &'e mut Entry {
+    let fields = entry.fields.get_or_insert_with(Vec::new);
+    let index = match fields.binary_search_by(|field| field.name.as_str().cmp(name)) {
+        Ok(index) => index,
+        Err(index) => {
+            let field = Entry {
+                name: String::from(name),
+                ..Default::default()
+            };
+            fields.insert(index, field);
+            index
+        }
+    };
+
+    &mut fields[index]
+}
+
+/// Return the name of a field defined by a string, if this string is not interpolated.
+fn static_name(rt: &RichTerm) -> Option<String> {
+    match rt.as_ref() {
+        Term::Str(s) => Some(s.clone()),
+        Term::StrChunks(chunks) => chunks
+            .iter()
+            .rev()
+            .map(|chunk| match chunk {
+                StrChunk::Literal(s) => Some(s.as_str()),
+                StrChunk::Expr(..) => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Split a documentation into its text and the content of its fenced code blocks.
+fn split_examples(doc: &str) -> (String, Vec<String>) {
+    let mut text = Vec::new();
+    let mut examples = Vec::new();
+    let mut example: Option<Vec<&str>> = None;
+
+    for line in doc.lines() {
+        let is_fence = line.trim_start().starts_with("```");
+        example = match (example, is_fence) {
+            (Some(lines), true) => {
+                examples.push(lines.join("\n"));
+                None
+            }
+            (Some(mut lines), false) => {
+                lines.push(line);
+                Some(lines)
+            }
+            (None, true) => Some(Vec::new()),
+            (None, false) => {
+                text.push(line);
+                None
+            }
+        };
+    }
+
+    // An unterminated block extends to the end of the documentation.
+    examples.extend(example.map(|lines| lines.join("\n")));
+    (String::from(text.join("\n").trim()), examples)
+}
+
+/// Write the HTML documentation of a program in a directory, created if needed.
+pub fn write_html(root: &Entry, dir: &Path) -> Result<(), IOError> {
+    fs::create_dir_all(dir)?;
+
+    for (name, content) in pages(root) {
+        fs::write(dir.join(name), content)?;
+    }
+    fs::write(dir.join("search-index.js"), search_index(root))?;
+    fs::write(dir.join("search.js"), SEARCH_SCRIPT)?;
+    fs::write(dir.join("style.css"), STYLE)?;
+
+    Ok(())
+}
+
+/// The script searching the index as the user types in the search box of a page.
+const SEARCH_SCRIPT: &str = r#"const input = document.getElementById("search");
+const results = document.getElementById("search-results");
+
+input.addEventListener("input", () => {
+  const query = input.value.trim().toLowerCase();
+  results.replaceChildren();
+  if (query === "") {
+    return;
+  }
+
+  searchIndex
+    .filter((item) => item.path.toLowerCase().includes(query))
+    .slice(0, 20)
+    .forEach((item) => {
+      const link = document.createElement("a");
+      link.href = item.url;
+      link.textContent = item.path;
+      const li = document.createElement("li");
+      li.append(link);
+      if (item.summary) {
+        li.append(" - " + item.summary);
+      }
+      results.append(li);
+    });
+});
+"#;
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 60em; margin: auto; padding: 1em; }
+nav { border-bottom: 1px solid #ccc; padding-bottom: 0.5em; }
+#search { float: right; }
+#search-results { list-style: none; }
+code, pre { background: #f4f4f4; }
+pre { padding: 0.5em; overflow-x: auto; }
+dt { margin-top: 1em; font-weight: bold; }
+dd { margin-left: 1.5em; }
+";
+
+/// Return the file name and the content of the pages of the records of a program.
+pub fn pages(root: &Entry) -> Vec<(String, String)> {
+    let mut pages = Vec::new();
+    collect_pages(root, &[], &mut pages);
+    pages
+}
+
+fn collect_pages<'a>(entry: &'a Entry, parents: &[&'a Entry], pages: &mut Vec<(String, String)>) {
+    let fields = match &entry.fields {
+        Some(fields) => fields,
+        None => return,
+    };
+
+    let mut path = parents.to_vec();
+    path.push(entry);
+    pages.push((page_name(&path), page(&path, fields)));
+
+    for field in fields {
+        collect_pages(field, &path, pages);
+    }
+}
+
+/// The file name of the page of the record at the end of a path, starting from the root.
+fn page_name(path: &[&Entry]) -> String {
+    if path.len() <= 1 {
+        String::from("index.html")
+    } else {
+        let names: Vec<String> = path.iter().map(|entry| anchor(&entry.name)).collect();
+        format!("{}.html", names.join("."))
+    }
+}
+
+/// Encode a field name for use in a file name or as the identifier of an HTML element. Quoted
+/// field names can contain any character, such as spaces, dots or slashes: characters other than
+/// alphanumeric ones, `_` and `-` are replaced by `~` followed by the hexadecimal value of their
+/// bytes.
+fn anchor(name: &str) -> String {
+    let mut result = String::new();
+
+    for c in name.chars() {
+        if c.is_alphanumeric() || c == '_' || c == '-' {
+            result.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                result.push_str(&format!("~{:02X}", byte));
+            }
+        }
+    }
+
+    result
+}
+
+fn dotted(path: &[&Entry]) -> String {
+    path.iter()
+        .map(|entry| entry.name.as_str())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn page(path: &[&Entry], fields: &[Entry]) -> String {
+    let entry = path[path.len() - 1];
+    let title = escape(&dotted(path));
+    let breadcrumbs: Vec<String> = (1..path.len())
+        .map(|len| {
+            format!(
+                "<a href=\"{}\">{}</a>",
+                escape(&page_name(&path[..len])),
+                escape(&path[len - 1].name)
+            )
+        })
+        .chain(std::iter::once(escape(&entry.name)))
+        .collect();
+
+    let mut html = format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+<link rel=\"stylesheet\" href=\"style.css\">
+<script src=\"search-index.js\"></script>
+<script src=\"search.js\" defer></script>
+</head>
+<body>
+<nav>{breadcrumbs}
+<input id=\"search\" type=\"search\" placeholder=\"Search\" autocomplete=\"off\">
+<ul id=\"search-results\"></ul>
+</nav>
+<main>
+<h1>{title}</h1>
+",
+        title = title,
+        breadcrumbs = breadcrumbs.join(" . ")
+    );
+    html.push_str(&metadata(entry));
+
+    if !fields.is_empty() {
+        html.push_str("<h2>Fields</h2>\n<dl>\n");
+    }
+    for field in fields {
+        html.push_str(&format!(
+            "<dt id=\"{0}\"><a href=\"#{0}\">{1}</a>",
+            anchor(&field.name),
+            escape(&field.name)
+        ));
+        if let Some(types) = &field.types {
+            html.push_str(&format!(" <code>: {}</code>", escape(types)));
+        }
+        for ctr in &field.contracts {
+            html.push_str(&format!(" <code>| {}</code>", escape(ctr)));
+        }
+        if field.sensitive {
+            html.push_str(" <code>| sensitive</code>");
+        }
+        if field.fields.is_some() {
+            let mut field_path = path.to_vec();
+            field_path.push(field);
+            html.push_str(&format!(
+                " <a href=\"{}\">(record)</a>",
+                escape(&page_name(&field_path))
+            ));
+        }
+        html.push_str("</dt>\n<dd>\n");
+
+        if let Some(default) = &field.default {
+            html.push_str(&format!(
+                "<p>Default: <code>{}</code></p>\n",
+                escape(default)
+            ));
+        }
+        html.push_str(&documentation(field));
+        html.push_str("</dd>\n");
+    }
+    if !fields.is_empty() {
+        html.push_str("</dl>\n");
+    }
+
+    html.push_str("</main>\n</body>\n</html>\n");
+    html
+}
+
+/// Render the type, the contracts, the default value and the documentation of the record of a
+/// page.
+fn metadata(entry: &Entry) -> String {
+    let mut html = String::new();
+
+    if let Some(types) = &entry.types {
+        html.push_str(&format!("<p>Type: <code>{}</code></p>\n", escape(types)));
+    }
+    for ctr in &entry.contracts {
+        html.push_str(&format!("<p>Contract: <code>{}</code></p>\n", escape(ctr)));
+    }
+    if entry.sensitive {
+        html.push_str("<p>Sensitive: the value is not shown.</p>\n");
+    }
+    if let Some(default) = &entry.default {
+        html.push_str(&format!(
+            "<p>Default: <code>{}</code></p>\n",
+            escape(default)
+        ));
+    }
+    html.push_str(&documentation(entry));
+    html
+}
+
+fn documentation(entry: &Entry) -> String {
+    let mut html = entry.doc.as_deref().map(markdown).unwrap_or_default();
+
+    if !entry.examples.is_empty() {
+        html.push_str("<p>Examples:</p>\n");
+    }
+    for example in &entry.examples {
+        html.push_str(&format!(
+            "<pre class=\"example\"><code>{}</code></pre>\n",
+            escape(example)
+        ));
+    }
+    html
+}
+
+/// Return the search index of a program: a script defining `searchIndex`, the list of the paths
+/// of all the fields, with the URL of their documentation and the first line of it.
+pub fn search_index(root: &Entry) -> String {
+    let mut items = Vec::new();
+    collect_items(root, &[], &mut items);
+
+    format!("const searchIndex = {};\n", serde_json::Value::from(items))
+}
+
+fn collect_items<'a>(entry: &'a Entry, parents: &[&'a Entry], items: &mut Vec<serde_json::Value>) {
+    let mut path = parents.to_vec();
+    path.push(entry);
+
+    for field in entry.fields.iter().flatten() {
+        let mut field_path = path.clone();
+        field_path.push(field);
+        let summary = field
+            .doc
+            .as_deref()
+            .and_then(|doc| doc.lines().next())
+            .unwrap_or_default();
+
+        items.push(json!({
+            "path": dotted(&field_path[1..]),
+            "url": format!("{}#{}", page_name(&path), anchor(&field.name)),
+            "summary": summary,
+        }));
+        collect_items(field, &path, items);
+    }
+}
+
+/// Render a documentation as HTML. Only a subset of Markdown is supported: paragraphs, headers,
+/// lists, inline code and strong emphasis.
+fn markdown(text: &str) -> String {
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut in_list = false;
+
+    let flush = |paragraph: &mut Vec<&str>, html: &mut String| {
+        if !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", inline(&paragraph.join("\n"))));
+            paragraph.clear();
+        }
+    };
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        let item = trimmed
+            .strip_prefix("- ")
+            .or_else(|| trimmed.strip_prefix("* "));
+
+        if item.is_none() && in_list && (trimmed.is_empty() || !line.starts_with(' ')) {
+            html.push_str("</ul>\n");
+            in_list = false;
+        }
+
+        if let Some(item) = item {
+            flush(&mut paragraph, &mut html);
+            if !in_list {
+                html.push_str("<ul>\n");
+                in_list = true;
+            }
+            html.push_str(&format!("<li>{}</li>\n", inline(item)));
+        } else if in_list {
+            // The continuation of a list item.
+            html.truncate(html.len() - "</li>\n".len());
+            html.push_str(&format!("\n{}</li>\n", inline(trimmed)));
+        } else if trimmed.starts_with('#') {
+            flush(&mut paragraph, &mut html);
+            let level = trimmed.chars().take_while(|c| *c == '#').count();
+            // The headers of a documentation are below the headers of the page.
+            let level = (level + 2).min(6);
+            html.push_str(&format!(
+                "<h{0}>{1}</h{0}>\n",
+                level,
+                inline(trimmed.trim_start_matches('#').trim())
+            ));
+        } else if trimmed.is_empty() {
+            flush(&mut paragraph, &mut html);
+        } else {
+            paragraph.push(trimmed);
+        }
+    }
+
+    flush(&mut paragraph, &mut html);
+    if in_list {
+        html.push_str("</ul>\n");
+    }
+    html
+}
+
+/// Render the inline code and the strong emphasis of a line of Markdown.
+fn inline(text: &str) -> String {
+    let mut html = String::new();
+
+    for (i, part) in text.split('`').enumerate() {
+        if i % 2 == 1 {
+            html.push_str(&format!("<code>{}</code>", escape(part)));
+        } else {
+            for (j, part) in part.split("**").enumerate() {
+                if j % 2 == 1 {
+                    html.push_str(&format!("<strong>{}</strong>", escape(part)));
+                } else {
+                    html.push_str(&escape(part));
+                }
+            }
+        }
+    }
+
+    html
+}
+
+/// Escape the characters of a text which are special in HTML.
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract_source(source: &str) -> Entry {
+        let mut cache = Cache::new();
+        let file_id = cache.add_string("lib.ncl", String::from(source));
+        cache.parse(file_id).unwrap();
+        extract(&mut cache, file_id).unwrap()
+    }
+
+    #[test]
+    fn extraction() {
+        let root = extract_source(
+            r##"let base = 8000 in {
+                server = {
+                    port | Num
+                         | doc m#"
+                             The port to listen on.
+
+                             ```
+                             {server.port = 80}
+                             ```
+                           "#m
+                         | default = base,
+                    host : Str = "localhost",
+                },
+            } & {server.port | #Port}"##,
+        );
+        assert_eq!(root.name, "lib");
+
+        let server = &root.fields.as_ref().unwrap()[0];
+        let fields = server.fields.as_ref().unwrap();
+        let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+        assert_eq!(names, vec!["host", "port"]);
+
+        assert_eq!(fields[0].types.as_deref(), Some("Str"));
+        assert_eq!(fields[0].fields, None);
+        assert_eq!(fields[1].doc.as_deref(), Some("The port to listen on."));
+        assert_eq!(fields[1].examples, vec!["{server.port = 80}"]);
+        assert_eq!(fields[1].contracts, vec!["Num", "#Port"]);
+        assert_eq!(fields[1].default.as_deref(), Some("base"));
+    }
+
+    #[test]
+    fn sensitive() {
+        let root = extract_source(
+            r#"{
+                token | Str | default | sensitive = "changeme",
+                password | default = "hunter2",
+            } & {password | sensitive}"#,
+        );
+        let fields = root.fields.as_ref().unwrap();
+        assert!(fields.iter().all(|field| field.sensitive));
+        assert!(fields
+            .iter()
+            .all(|field| field.default.as_deref() == Some(REDACTED)));
+
+        let (_, index) = &pages(&root)[0];
+        assert!(index.contains("<code>| Str</code> <code>| sensitive</code>"));
+        assert!(!index.contains("changeme") && !index.contains("hunter2"));
+    }
+
+    #[test]
+    fn quoted_names() {
+        let root = extract_source(
+            r##"{
+                "x-api-key" | doc "The key." | Str = "secret",
+                "q r".s = 1,
+                "../up" = {a = 1},
+                "#{"inter"}polated" = 2,
+                plain = 3,
+            }"##,
+        );
+        let fields = root.fields.as_ref().unwrap();
+        let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+        assert_eq!(names, vec!["../up", "plain", "q r", "x-api-key"]);
+        assert_eq!(fields[3].doc.as_deref(), Some("The key."));
+        assert_eq!(fields[3].contracts, vec!["Str"]);
+        assert_eq!(fields[2].fields.as_ref().unwrap()[0].name, "s");
+
+        let pages = pages(&root);
+        let names: Vec<&str> = pages.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["index.html", "lib.~2E~2E~2Fup.html", "lib.q~20r.html"]
+        );
+        assert!(pages[0].1.contains(
+            "<dt id=\"x-api-key\"><a href=\"#x-api-key\">x-api-key</a> <code>| Str</code>"
+        ));
+    }
+
+    #[test]
+    fn html() {
+        let root = extract_source(
+            r#"{
+                a | doc "The **first** field, `<a>`." = {b | doc "- one\n- two" = 1},
+                c = 2,
+            }"#,
+        );
+
+        let pages = pages(&root);
+        let names: Vec<&str> = pages.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["index.html", "lib.a.html"]);
+
+        let (_, index) = &pages[0];
+        assert!(index.contains(
+            "<dt id=\"a\"><a href=\"#a\">a</a> <a href=\"lib.a.html\">(record)</a></dt>"
+        ));
+        assert!(index.contains("<p>The <strong>first</strong> field, <code>&lt;a&gt;</code>.</p>"));
+        let (_, a) = &pages[1];
+        assert!(a.contains("<a href=\"index.html\">lib</a> . a"));
+        assert!(a.contains("<ul>\n<li>one</li>\n<li>two</li>\n</ul>"));
+
+        assert_eq!(
+            search_index(&root),
+            "const searchIndex = [\
+             {\"path\":\"a\",\"summary\":\"The **first** field, `<a>`.\",\"url\":\"index.html#a\"},\
+             {\"path\":\"a.b\",\"summary\":\"- one\",\"url\":\"lib.a.html#b\"},\
+             {\"path\":\"c\",\"summary\":\"\",\"url\":\"index.html#c\"}];\n"
+        );
+    }
+}
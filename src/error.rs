@@ -1,21 +1,29 @@
 //! Error types and error reporting.
 //!
-//! Define error types for different phases of the execution, together with functions to generate a
-//! [codespan](https://crates.io/crates/codespan-reporting) diagnostic from them.
+//! Define error types for different phases of the execution, together with their conversion to
+//! [diagnostics](diagnostic/index.html), which are rendered by the different outputs. Diagnostics
+//! are built using [codespan](https://crates.io/crates/codespan-reporting).
+use crate::diff::Change;
 use crate::eval::{CallStack, StackElem};
 use crate::identifier::Ident;
 use crate::label::ty_path;
 use crate::parser::lexer::LexicalError;
 use crate::parser::utils::mk_span;
 use crate::position::{RawSpan, TermPos};
+#[cfg(feature = "repl-backend")]
+use crate::repl;
 use crate::serialize::ExportFormat;
 use crate::term::RichTerm;
-use crate::types::Types;
-use crate::{label, repl};
+use crate::types::{AbsType, Types};
+use crate::warning::{self, Warning};
+use crate::{explain, label};
 use codespan::{FileId, Files};
 use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle};
+use diagnostic::{Edit, Fix};
 use std::fmt::Write;
 
+pub mod diagnostic;
+
 /// A general error occurring during either parsing or evaluation.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Error {
@@ -24,8 +32,12 @@ pub enum Error {
     ParseError(ParseError),
     ImportError(ImportError),
     SerializationError(SerializationError),
+    ExtractionError(ExtractionError),
     IOError(IOError),
+    #[cfg(feature = "repl-backend")]
     REPLError(REPLError),
+    /// Warnings whose level is set to deny, see [`warning`](../warning/index.html).
+    DeniedWarnings(Vec<Warning>),
 }
 
 /// An error occurring during evaluation.
@@ -81,6 +93,9 @@ pub enum EvalError {
     ),
     /// An unexpected internal error.
     InternalError(String, TermPos),
+    /// A term built outside of the parser can't be evaluated, see
+    /// [`RichTerm::check`](../term/struct.RichTerm.html#method.check).
+    IllFormedTerm(String, TermPos),
     /// Errors occurring rarely enough to not deserve a dedicated variant.
     Other(String, TermPos),
 }
@@ -206,6 +221,19 @@ pub enum ParseError {
     InvalidEscapeSequence(RawSpan),
     /// Invalid ASCII escape code in a string literal.
     InvalidAsciiEscapeCode(RawSpan),
+    /// A comma is probably missing between two record fields, as in `{a = 1 b = 2}`.
+    MissingComma(
+        RawSpan, /* end of the previous field */
+        RawSpan, /* unexpected token */
+    ),
+    /// The end of the input was reached inside a string literal.
+    UnterminatedString(RawSpan /* opening delimiter */),
+    /// A non-ASCII identifier was found while identifiers are restricted to ASCII.
+    NonAsciiIdentifier(RawSpan),
+    /// Invalid Unicode escape sequence in a string literal.
+    InvalidUnicodeEscape(RawSpan),
+    /// An integer literal in base 16, 8 or 2 which cannot be represented exactly as a number.
+    InexactNumber(RawSpan),
     /// Error when parsing an external format such as JSON, YAML, etc.
     ExternalFormatError(
         String, /* format */
@@ -237,25 +265,123 @@ pub enum SerializationError {
     UnsupportedNull(ExportFormat, RichTerm),
     /// Tried exporting something else than a `Str` to raw format.
     NotAString(RichTerm),
+    /// Tried exporting something else than a `List` to a format made of a sequence of values,
+    /// such as NDJSON. The first argument is the name of the format.
+    NotAList(String, RichTerm),
+    /// Tried exporting something else than a flat record of scalar values to a tabular format,
+    /// such as CSV. The first argument describes the error.
+    NotFlat(String, RichTerm),
     /// A term contains constructs that cannot be serialized.
-    NonSerializable(RichTerm),
+    NonSerializable(
+        RichTerm,
+        /* path of the value in the exported term */ Option<String>,
+    ),
     Other(String),
 }
 
+/// An error occurred when extracting a Rust value from an evaluated term, together with the
+/// position of the offending term.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExtractionError(pub String, pub TermPos);
+
 /// A general I/O error, occurring when reading a source file or writing an export.
 #[derive(Debug, PartialEq, Clone)]
 pub struct IOError(pub String);
 
 /// An error occurring during an REPL session.
+#[cfg(feature = "repl-backend")]
 #[derive(Debug, PartialEq, Clone)]
 pub enum REPLError {
-    UnknownCommand(String),
+    /// An unknown command, with its span in the input and the closest command, if any.
+    UnknownCommand {
+        name: String,
+        span: RawSpan,
+        suggestion: Option<repl::command::CommandType>,
+    },
+    /// A command without its required argument, with the span of the command in the input.
     MissingArg {
         cmd: repl::command::CommandType,
         msg_opt: Option<String>,
+        span: RawSpan,
+    },
+    /// An invalid argument of a command, with its span in the input.
+    InvalidArg {
+        cmd: repl::command::CommandType,
+        msg: String,
+        span: RawSpan,
     },
 }
 
+impl Error {
+    /// Convert the error to diagnostics, with its code and its suggested fixes. `contract_id` is
+    /// the file of the builtin contracts, see [`ToDiagnostic`](trait.ToDiagnostic.html).
+    pub fn diagnostics(
+        &self,
+        files: &mut Files<String>,
+        contract_id: Option<FileId>,
+    ) -> Vec<diagnostic::Diagnostic> {
+        if let Error::DeniedWarnings(warnings) = self {
+            return warnings
+                .iter()
+                .map(|warning| warning.diagnostic(warning::Level::Deny))
+                .collect();
+        }
+
+        let diagnostics = self.to_diagnostic(files, contract_id);
+        let mut diagnostics = diagnostics.iter().map(diagnostic::Diagnostic::from);
+        // The other diagnostics are the elements of the callstack.
+        let first = diagnostics
+            .next()
+            .map(|d| d.with_fixes(self.fixes()).with_diff(self.diff()));
+
+        first.into_iter().chain(diagnostics).collect()
+    }
+
+    /// Return the differences between the expected value and the actual one, if the error is a
+    /// failed comparison.
+    pub fn diff(&self) -> Vec<Change> {
+        match self {
+            Error::EvalError(EvalError::BlameError(l, _)) => l.diff.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Return the fixes suggested for the error.
+    pub fn fixes(&self) -> Vec<Fix> {
+        let edit = |span: RawSpan, replacement: &str| Edit {
+            span,
+            replacement: String::from(replacement),
+        };
+
+        match self {
+            Error::ParseError(ParseError::MissingComma(prev_span, _)) => vec![Fix {
+                message: String::from("insert a comma"),
+                edits: vec![edit(
+                    RawSpan {
+                        start: prev_span.end,
+                        ..*prev_span
+                    },
+                    ",",
+                )],
+            }],
+            Error::ParseError(ParseError::UnmatchedCloseBrace(span)) => vec![Fix {
+                message: String::from("remove the brace"),
+                edits: vec![edit(*span, "")],
+            }],
+            #[cfg(feature = "repl-backend")]
+            Error::REPLError(REPLError::UnknownCommand {
+                span,
+                suggestion: Some(cmd),
+                ..
+            }) => vec![Fix {
+                message: format!("use `:{}`", cmd),
+                edits: vec![edit(*span, &cmd.to_string())],
+            }],
+            _ => Vec::new(),
+        }
+    }
+}
+
 impl From<EvalError> for Error {
     fn from(error: EvalError) -> Error {
         Error::EvalError(error)
@@ -268,6 +394,19 @@ impl From<ParseError> for Error {
     }
 }
 
+impl TypecheckError {
+    /// Return `true` if the error is a mismatch with the dynamic type. The value can't be checked
+    /// by a type annotation, but it can by a contract annotation, which is checked at run time.
+    pub fn suggests_contract(&self) -> bool {
+        match self {
+            TypecheckError::TypeMismatch(expd, actual, _) => {
+                matches!(expd.0, AbsType::Dyn()) || matches!(actual.0, AbsType::Dyn())
+            }
+            _ => false,
+        }
+    }
+}
+
 impl From<TypecheckError> for Error {
     fn from(error: TypecheckError) -> Error {
         Error::TypecheckError(error)
@@ -286,6 +425,12 @@ impl From<SerializationError> for Error {
     }
 }
 
+impl From<ExtractionError> for Error {
+    fn from(error: ExtractionError) -> Error {
+        Error::ExtractionError(error)
+    }
+}
+
 impl From<IOError> for Error {
     fn from(error: IOError) -> Error {
         Error::IOError(error)
@@ -316,16 +461,63 @@ pub fn escape(s: &str) -> String {
     .expect("escape(): converting from a string should give back a valid UTF8 string")
 }
 
+#[cfg(feature = "repl-backend")]
 impl From<REPLError> for Error {
     fn from(error: REPLError) -> Error {
         Error::REPLError(error)
     }
 }
 
+/// Detect a comma missing between two record fields, as in `{a = 1 b = 2}`. In this case, the
+/// parser only fails on the `=` of the second field, since `1 b` is a valid application. If the
+/// unexpected token `=` spanning `start..end` follows a field path, itself preceded by the end of
+/// an expression, return the offset of the end of this expression.
+fn missing_comma(source: &str, start: usize, end: usize, expected: &[String]) -> Option<usize> {
+    if source.get(start..end) != Some("=") || !expected.iter().any(|tok| tok == "\",\"") {
+        return None;
+    }
+
+    let mut before = source[..start].trim_end();
+    loop {
+        let name_start = if let Some(quoted) = before.strip_suffix('"') {
+            quoted.rfind('"')?
+        } else {
+            before
+                .char_indices()
+                .rev()
+                .find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_'))
+                .map(|(i, c)| i + c.len_utf8())
+                .unwrap_or(0)
+        };
+
+        if name_start == before.len() {
+            return None;
+        }
+
+        match before[..name_start].strip_suffix('.') {
+            Some(path) => before = path,
+            None => {
+                before = &before[..name_start];
+                break;
+            }
+        }
+    }
+
+    let prev = before.trim_end();
+    if prev.is_empty() || prev.ends_with(['{', ',']) {
+        None
+    } else {
+        Some(prev.len())
+    }
+}
+
 impl ParseError {
+    /// Convert an error of the parser. `source` is the text being parsed, which is used to
+    /// diagnose common mistakes such as missing commas.
     pub fn from_lalrpop<T>(
         error: lalrpop_util::ParseError<usize, T, LexicalError>,
         file_id: FileId,
+        source: &str,
     ) -> ParseError {
         match error {
             lalrpop_util::ParseError::InvalidToken { location } => {
@@ -334,7 +526,13 @@ impl ParseError {
             lalrpop_util::ParseError::UnrecognizedToken {
                 token: Some((start, _, end)),
                 expected,
-            } => ParseError::UnexpectedToken(mk_span(file_id, start, end), expected),
+            } => match missing_comma(source, start, end, &expected) {
+                Some(prev_end) => ParseError::MissingComma(
+                    mk_span(file_id, prev_end - 1, prev_end),
+                    mk_span(file_id, start, end),
+                ),
+                None => ParseError::UnexpectedToken(mk_span(file_id, start, end), expected),
+            },
             lalrpop_util::ParseError::User {
                 error: LexicalError::Generic(start, end),
             } => ParseError::UnexpectedToken(mk_span(file_id, start, end), Vec::new()),
@@ -354,6 +552,18 @@ impl ParseError {
             lalrpop_util::ParseError::User {
                 error: LexicalError::InvalidAsciiEscapeCode(location),
             } => ParseError::InvalidAsciiEscapeCode(mk_span(file_id, location, location + 2)),
+            lalrpop_util::ParseError::User {
+                error: LexicalError::UnterminatedString(start, end),
+            } => ParseError::UnterminatedString(mk_span(file_id, start, end)),
+            lalrpop_util::ParseError::User {
+                error: LexicalError::NonAsciiIdentifier(start, end),
+            } => ParseError::NonAsciiIdentifier(mk_span(file_id, start, end)),
+            lalrpop_util::ParseError::User {
+                error: LexicalError::InvalidUnicodeEscape(start, end),
+            } => ParseError::InvalidUnicodeEscape(mk_span(file_id, start, end)),
+            lalrpop_util::ParseError::User {
+                error: LexicalError::InexactNumber(start, end),
+            } => ParseError::InexactNumber(mk_span(file_id, start, end)),
         }
     }
 
@@ -826,15 +1036,28 @@ impl ToDiagnostic<FileId> for Error {
         files: &mut Files<String>,
         contract_id: Option<FileId>,
     ) -> Vec<Diagnostic<FileId>> {
-        match self {
+        let mut diagnostics = match self {
             Error::ParseError(err) => err.to_diagnostic(files, contract_id),
             Error::TypecheckError(err) => err.to_diagnostic(files, contract_id),
             Error::EvalError(err) => err.to_diagnostic(files, contract_id),
             Error::ImportError(err) => err.to_diagnostic(files, contract_id),
             Error::SerializationError(err) => err.to_diagnostic(files, contract_id),
+            Error::ExtractionError(err) => err.to_diagnostic(files, contract_id),
             Error::IOError(err) => err.to_diagnostic(files, contract_id),
+            #[cfg(feature = "repl-backend")]
             Error::REPLError(err) => err.to_diagnostic(files, contract_id),
+            Error::DeniedWarnings(warnings) => warnings
+                .iter()
+                .map(|warning| warning.diagnostic(warning::Level::Deny).to_codespan())
+                .collect(),
+        };
+
+        // The other diagnostics are the elements of the callstack, see the documentation of
+        // `to_diagnostic`.
+        if let Some(diagnostic) = diagnostics.first_mut() {
+            diagnostic.code = Some(String::from(explain::error_code(self)));
         }
+        diagnostics
     }
 }
 
@@ -886,7 +1109,20 @@ impl ToDiagnostic<FileId> for EvalError {
                 // If we have a reference to the thunk that was being tested, we can try to show
                 // more information about the final, evaluated value that is responsible for the
                 // blame.
-                if let Some(ref thunk) = l.arg_thunk {
+                // The differences with the expected value, if any, are more informative than the
+                // shallow representation of the value, and are rendered separately. A sensitive
+                // value is not shown at all.
+                let mut notes = notes;
+                if l.sensitive {
+                    notes.push(String::from(
+                        "The value is sensitive: it has been redacted from this message.",
+                    ));
+                }
+                if let Some(thunk) = l
+                    .arg_thunk
+                    .as_ref()
+                    .filter(|_| l.diff.is_empty() && !l.sensitive)
+                {
                     let mut val = thunk.get_owned().body;
 
                     match (val.pos, l.arg_pos.as_opt_ref(), contract_id) {
@@ -951,7 +1187,7 @@ impl ToDiagnostic<FileId> for EvalError {
                         .enumerate()
                         .map(|(i, (id_opt, pos))| {
                             let name = id_opt
-                                .map(|Ident(id)| id)
+                                .map(|Ident { label: id, .. }| id)
                                 .unwrap_or_else(|| String::from("<func>"));
                             Diagnostic::note().with_labels(vec![secondary(&pos)
                                 .with_message(format!("({}) calling {}", i + 1, name))])
@@ -1065,10 +1301,12 @@ impl ToDiagnostic<FileId> for EvalError {
                     .with_message("Non mergeable terms")
                     .with_labels(labels)]
             }
-            EvalError::UnboundIdentifier(Ident(ident), span_opt) => vec![Diagnostic::error()
-                .with_message("Unbound identifier")
-                .with_labels(vec![primary_alt(span_opt.into_opt(), ident.clone(), files)
-                    .with_message("this identifier is unbound")])],
+            EvalError::UnboundIdentifier(Ident { label: ident, .. }, span_opt) => {
+                vec![Diagnostic::error()
+                    .with_message("Unbound identifier")
+                    .with_labels(vec![primary_alt(span_opt.into_opt(), ident.clone(), files)
+                        .with_message("this identifier is unbound")])]
+            }
             EvalError::InfiniteRecursion(_call_stack, span_opt) => {
                 let labels = span_opt
                     .as_opt_ref()
@@ -1098,6 +1336,20 @@ impl ToDiagnostic<FileId> for EvalError {
                     .with_labels(labels)
                     .with_notes(vec![String::from(INTERNAL_ERROR_MSG)])]
             }
+            EvalError::IllFormedTerm(msg, span_opt) => {
+                let labels = span_opt
+                    .as_opt_ref()
+                    .map(|span| vec![primary(span).with_message("here")])
+                    .unwrap_or_default();
+
+                vec![Diagnostic::error()
+                    .with_message(format!("ill-formed term: {}", msg))
+                    .with_labels(labels)
+                    .with_notes(vec![String::from(
+                        "The term was not produced by the parser. Build terms with the helpers \
+                        of the `term::make` module.",
+                    )])]
+            }
             EvalError::SerializationError(err) => err.to_diagnostic(files, contract_id),
             EvalError::DeserializationError(format, msg, span_opt) => {
                 let labels = span_opt
@@ -1142,6 +1394,37 @@ impl ToDiagnostic<FileId> for ParseError {
             ParseError::InvalidAsciiEscapeCode(span) => Diagnostic::error()
                 .with_message("Invalid ascii escape code")
                 .with_labels(vec![primary(span)]),
+            ParseError::MissingComma(prev_span, span) => Diagnostic::error()
+                .with_message("Unexpected token")
+                .with_labels(vec![
+                    primary(span),
+                    secondary(prev_span).with_message("did you forget a `,` after this field?"),
+                ]),
+            ParseError::UnterminatedString(span) => Diagnostic::error()
+                .with_message("Unterminated string")
+                .with_labels(vec![
+                    primary(span).with_message("this string is never closed")
+                ]),
+            ParseError::InvalidUnicodeEscape(span) => Diagnostic::error()
+                .with_message("Invalid Unicode escape sequence")
+                .with_labels(vec![primary(span)])
+                .with_notes(vec![String::from(
+                    "A Unicode escape sequence `\\u{..}` must contain the hexadecimal value of a \
+                    code point, between 0 and 10FFFF and outside of the surrogate range D800-DFFF.",
+                )]),
+            ParseError::InexactNumber(span) => Diagnostic::error()
+                .with_message("Number literal not exactly representable")
+                .with_labels(vec![primary(span)])
+                .with_notes(vec![String::from(
+                    "Numbers are double-precision floats: integer literals in base 16, 8 or 2 must \
+                    be exactly representable, which is the case of all integers up to 2^53.",
+                )]),
+            ParseError::NonAsciiIdentifier(span) => Diagnostic::error()
+                .with_message("Non-ASCII identifier")
+                .with_labels(vec![primary(span)])
+                .with_notes(vec![String::from(
+                    "Identifiers are restricted to ASCII characters. Use a quoted field name instead.",
+                )]),
             ParseError::ExternalFormatError(format, msg, span_opt) => {
                 let labels = span_opt
                     .as_ref()
@@ -1189,7 +1472,7 @@ impl ToDiagnostic<FileId> for TypecheckError {
                     .with_message("Ill-formed type")
                     .with_labels(vec![label])]
             }
-            TypecheckError::MissingRow(Ident(ident), expd, actual, span_opt) =>
+            TypecheckError::MissingRow(Ident { label: ident, .. }, expd, actual, span_opt) =>
                 vec![Diagnostic::error()
                     .with_message(format!("Type error: missing row `{}`", ident))
                     .with_labels(mk_expr_label(span_opt))
@@ -1208,7 +1491,7 @@ impl ToDiagnostic<FileId> for TypecheckError {
                     ])]
             ,
 
-            TypecheckError::ExtraRow(Ident(ident), expd, actual, span_opt) =>
+            TypecheckError::ExtraRow(Ident { label: ident, .. }, expd, actual, span_opt) =>
                 vec![Diagnostic::error()
                     .with_message(format!("Type error: extra row `{}`", ident))
                     .with_labels(mk_expr_label(span_opt))
@@ -1227,7 +1510,7 @@ impl ToDiagnostic<FileId> for TypecheckError {
                     ])]
             ,
 
-            TypecheckError::UnboundTypeVariable(Ident(ident), span_opt) =>
+            TypecheckError::UnboundTypeVariable(Ident { label: ident, .. }, span_opt) =>
                vec![Diagnostic::error()
                     .with_message(String::from("Unbound type variable"))
                     .with_labels(vec![primary_alt(span_opt.into_opt(), ident.clone(), files).with_message("this type variable is unbound")])
@@ -1235,18 +1518,23 @@ impl ToDiagnostic<FileId> for TypecheckError {
                         format!("Maybe you forgot to put a `forall {}.` somewhere in the enclosing type ?", ident),
                     ])]
             ,
-            TypecheckError::TypeMismatch(expd, actual, span_opt) =>
+            TypecheckError::TypeMismatch(expd, actual, span_opt) => {
+                let mut notes = vec![
+                    format!("The type of the expression was expected to be `{}`", expd),
+                    format!("The type of the expression was inferred to be `{}`", actual),
+                    String::from("These types are not compatible"),
+                ];
+                if self.suggests_contract() {
+                    notes.push(String::from("A dynamically typed value can't be checked by a type annotation `:`. Use a contract annotation `|` to check it at run time instead"));
+                }
+
                 vec![
                     Diagnostic::error()
                         .with_message("Incompatible types")
                         .with_labels(mk_expr_label(span_opt))
-                        .with_notes(vec![
-                        format!("The type of the expression was expected to be `{}`", expd),
-                        format!("The type of the expression was inferred to be `{}`", actual),
-                        String::from("These types are not compatible"),
-                    ])]
-            ,
-            TypecheckError::RowKindMismatch(Ident(ident), expd, actual, span_opt) => {
+                        .with_notes(notes)]
+            }
+            TypecheckError::RowKindMismatch(Ident { label: ident, .. }, expd, actual, span_opt) => {
                 let (expd_str, actual_str) = match (expd, actual) {
                     (Some(_), None) => ("an enum type", "a record type"),
                     (None, Some(_)) => ("a record type", "an enum type"),
@@ -1310,7 +1598,7 @@ impl ToDiagnostic<FileId> for TypecheckError {
                     }));
                 diags
             }
-            TypecheckError::RowConflict(Ident(ident), conflict, _expd, _actual, span_opt) => {
+            TypecheckError::RowConflict(Ident { label: ident, .. }, conflict, _expd, _actual, span_opt) => {
 vec![
                     Diagnostic::error()
                         .with_message("Multiple rows declaration")
@@ -1419,12 +1707,42 @@ impl ToDiagnostic<FileId> for SerializationError {
                         .unwrap_or_else(|| String::from("<unevaluated>"))
                 ))
                 .with_labels(vec![primary_term(&rt, files)])],
+            SerializationError::NotAList(format, rt) => vec![Diagnostic::error()
+                .with_message(format!(
+                    "{} export only supports `List`, got {}",
+                    format,
+                    rt.as_ref()
+                        .type_of()
+                        .unwrap_or_else(|| String::from("<unevaluated>"))
+                ))
+                .with_labels(vec![primary_term(rt, files)])],
+            SerializationError::NotFlat(msg, rt) => vec![Diagnostic::error()
+                .with_message(msg.clone())
+                .with_labels(vec![primary_term(rt, files)])],
             SerializationError::UnsupportedNull(format, rt) => vec![Diagnostic::error()
                 .with_message(format!("{} doesn't support null values", format))
                 .with_labels(vec![primary_term(&rt, files)])],
-            SerializationError::NonSerializable(rt) => vec![Diagnostic::error()
-                .with_message("non serializable term")
-                .with_labels(vec![primary_term(&rt, files)])],
+            SerializationError::NonSerializable(rt, path) => {
+                let message = match path {
+                    Some(path) if !path.is_empty() => {
+                        format!("non serializable value at `{}`", path)
+                    }
+                    _ => String::from("non serializable value"),
+                };
+                let ty = rt
+                    .as_ref()
+                    .type_of()
+                    .unwrap_or_else(|| String::from("<unevaluated>"));
+
+                vec![Diagnostic::error()
+                    .with_message(message)
+                    .with_labels(vec![primary_term(&rt, files)
+                        .with_message(format!("this value of type {} can't be exported", ty))])
+                    .with_notes(vec![String::from(
+                        "Only null, booleans, numbers, strings, enum tags, and lists and records of \
+                        such values can be exported.",
+                    )])]
+            }
             SerializationError::Other(msg) => vec![Diagnostic::error()
                 .with_message("error during serialization")
                 .with_notes(vec![msg.clone()])],
@@ -1432,6 +1750,24 @@ impl ToDiagnostic<FileId> for SerializationError {
     }
 }
 
+impl ToDiagnostic<FileId> for ExtractionError {
+    fn to_diagnostic(
+        &self,
+        _files: &mut Files<String>,
+        _contract_id: Option<FileId>,
+    ) -> Vec<Diagnostic<FileId>> {
+        let ExtractionError(msg, pos) = self;
+        let labels = match pos.into_opt() {
+            Some(span) => vec![primary(&span)],
+            None => Vec::new(),
+        };
+
+        vec![Diagnostic::error()
+            .with_message(format!("could not extract value: {}", msg))
+            .with_labels(labels)]
+    }
+}
+
 impl ToDiagnostic<FileId> for IOError {
     fn to_diagnostic(
         &self,
@@ -1444,6 +1780,7 @@ impl ToDiagnostic<FileId> for IOError {
     }
 }
 
+#[cfg(feature = "repl-backend")]
 impl ToDiagnostic<FileId> for REPLError {
     fn to_diagnostic(
         &self,
@@ -1451,12 +1788,14 @@ impl ToDiagnostic<FileId> for REPLError {
         _contract_id: Option<FileId>,
     ) -> Vec<Diagnostic<FileId>> {
         match self {
-            REPLError::UnknownCommand(s) => vec![Diagnostic::error()
-                .with_message(format!("unknown command `{}`", s))
+            // The closest command is suggested as a fix, see `Error::fixes`.
+            REPLError::UnknownCommand { name, span, .. } => vec![Diagnostic::error()
+                .with_message(format!("unknown command `{}`", name))
+                .with_labels(vec![primary(span).with_message("unknown command")])
                 .with_notes(vec![String::from(
                     "type `:?` or `:help` for a list of available commands.",
                 )])],
-            REPLError::MissingArg { cmd, msg_opt } => {
+            REPLError::MissingArg { cmd, msg_opt, span } => {
                 let mut notes = msg_opt
                     .as_ref()
                     .map(|msg| vec![msg.clone()])
@@ -1468,8 +1807,16 @@ impl ToDiagnostic<FileId> for REPLError {
 
                 vec![Diagnostic::error()
                     .with_message(format!("{}: missing argument", cmd))
+                    .with_labels(vec![primary(span).with_message("expects an argument")])
                     .with_notes(notes)]
             }
+            REPLError::InvalidArg { cmd, msg, span } => vec![Diagnostic::error()
+                .with_message(format!("{}: {}", cmd, msg))
+                .with_labels(vec![primary(span).with_message("invalid argument")])
+                .with_notes(vec![format!(
+                    "type `:? {}` or `:help {}` for more information.",
+                    cmd, cmd
+                )])],
         }
     }
 }
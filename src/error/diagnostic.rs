@@ -0,0 +1,385 @@
+//! Diagnostics: the representation of errors shared by all the outputs of Nickel.
+//!
+//! An [`Error`](../enum.Error.html) is converted to a list of [`Diagnostic`](struct.Diagnostic.html)
+//! values by [`Error::diagnostics`](../enum.Error.html#method.diagnostics). A diagnostic is made of
+//! a severity, the [code](../../explain/index.html) of the error, a message, labels pointing to
+//! the sources, notes, suggested fixes and a [structural diff](../../diff/index.html) of the
+//! expected and actual values. It doesn't depend on the way it is rendered: the
+//! terminal output ([`emit`](fn.emit.html)), the JSON output of the C interface and of WASM
+//! embeddings ([`to_json`](fn.to_json.html)), SARIF logs and the language server all consume this
+//! single representation.
+use crate::diff::{self, Change};
+use crate::position::RawSpan;
+use codespan::{ByteIndex, FileId, Files};
+use codespan_reporting::diagnostic as reporting;
+use codespan_reporting::term::{self, termcolor::WriteColor};
+use serde_json::{json, Value};
+
+/// The severity of a diagnostic.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    Help,
+    Note,
+    Warning,
+    Error,
+    /// An unexpected internal error.
+    Bug,
+}
+
+impl Severity {
+    /// The name of the severity, in lower case.
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Help => "help",
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Bug => "bug",
+        }
+    }
+}
+
+/// A label of a diagnostic, annotating a span of a source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    primary: bool,
+    span: RawSpan,
+    message: String,
+}
+
+impl Label {
+    pub fn new(primary: bool, span: RawSpan, message: impl Into<String>) -> Self {
+        Label {
+            primary,
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// Return `true` if the label points to the cause of the diagnostic, or `false` if it only
+    /// gives context.
+    pub fn is_primary(&self) -> bool {
+        self.primary
+    }
+
+    pub fn span(&self) -> RawSpan {
+        self.span
+    }
+
+    /// The message of the label, which may be empty.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// The replacement of a span of a source by a text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub span: RawSpan,
+    pub replacement: String,
+}
+
+/// A suggested fix of a diagnostic: edits of the sources, described by a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fix {
+    pub message: String,
+    pub edits: Vec<Edit>,
+}
+
+/// A diagnostic of an error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    severity: Severity,
+    code: Option<String>,
+    message: String,
+    labels: Vec<Label>,
+    notes: Vec<String>,
+    fixes: Vec<Fix>,
+    diff: Vec<Change>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            code: None,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            fixes: Vec::new(),
+            diff: Vec::new(),
+        }
+    }
+
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn with_labels(mut self, labels: Vec<Label>) -> Self {
+        self.labels.extend(labels);
+        self
+    }
+
+    pub fn with_notes(mut self, notes: Vec<String>) -> Self {
+        self.notes.extend(notes);
+        self
+    }
+
+    pub fn with_fixes(mut self, fixes: Vec<Fix>) -> Self {
+        self.fixes.extend(fixes);
+        self
+    }
+
+    pub fn with_diff(mut self, diff: Vec<Change>) -> Self {
+        self.diff.extend(diff);
+        self
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// The code of the error, see [`explain`](../../explain/index.html).
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    /// The labels pointing to the cause of the diagnostic.
+    pub fn primary_labels(&self) -> impl Iterator<Item = &Label> {
+        self.labels.iter().filter(|label| label.primary)
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    pub fn fixes(&self) -> &[Fix] {
+        &self.fixes
+    }
+
+    /// The differences between the expected value and the actual one, if the error is a failed
+    /// comparison.
+    pub fn diff(&self) -> &[Change] {
+        &self.diff
+    }
+
+    /// Convert the diagnostic to a diagnostic of codespan, to render it in a terminal. The fixes
+    /// are rendered as notes. The diff is not part of the result, see [`emit`](fn.emit.html).
+    pub fn to_codespan(&self) -> reporting::Diagnostic<FileId> {
+        let severity = match self.severity {
+            Severity::Help => reporting::Severity::Help,
+            Severity::Note => reporting::Severity::Note,
+            Severity::Warning => reporting::Severity::Warning,
+            Severity::Error => reporting::Severity::Error,
+            Severity::Bug => reporting::Severity::Bug,
+        };
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| {
+                let range = label.span.start.to_usize()..label.span.end.to_usize();
+                let style = if label.primary {
+                    reporting::LabelStyle::Primary
+                } else {
+                    reporting::LabelStyle::Secondary
+                };
+                reporting::Label::new(style, label.span.src_id, range).with_message(&label.message)
+            })
+            .collect();
+        let notes = self
+            .notes
+            .iter()
+            .cloned()
+            .chain(self.fixes.iter().map(|fix| format!("fix: {}", fix.message)))
+            .collect();
+
+        let diagnostic = reporting::Diagnostic::new(severity)
+            .with_message(&self.message)
+            .with_labels(labels)
+            .with_notes(notes);
+        match &self.code {
+            Some(code) => diagnostic.with_code(code),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&reporting::Diagnostic<FileId>> for Diagnostic {
+    fn from(d: &reporting::Diagnostic<FileId>) -> Self {
+        let severity = match d.severity {
+            reporting::Severity::Help => Severity::Help,
+            reporting::Severity::Note => Severity::Note,
+            reporting::Severity::Warning => Severity::Warning,
+            reporting::Severity::Error => Severity::Error,
+            reporting::Severity::Bug => Severity::Bug,
+        };
+        let labels = d
+            .labels
+            .iter()
+            .map(|label| Label {
+                primary: label.style == reporting::LabelStyle::Primary,
+                span: RawSpan {
+                    src_id: label.file_id,
+                    start: ByteIndex(label.range.start as u32),
+                    end: ByteIndex(label.range.end as u32),
+                },
+                message: label.message.clone(),
+            })
+            .collect();
+
+        Diagnostic {
+            severity,
+            code: d.code.clone(),
+            message: d.message.clone(),
+            labels,
+            notes: d.notes.clone(),
+            fixes: Vec::new(),
+            diff: Vec::new(),
+        }
+    }
+}
+
+/// Render diagnostics for a terminal. The diff of a diagnostic is written after it, one colored
+/// change per line.
+pub fn emit(
+    writer: &mut dyn WriteColor,
+    files: &Files<String>,
+    diagnostics: &[Diagnostic],
+) -> Result<(), std::io::Error> {
+    let config = term::Config::default();
+
+    for diagnostic in diagnostics {
+        term::emit(writer, &config, files, &diagnostic.to_codespan())?;
+
+        if !diagnostic.diff.is_empty() {
+            writeln!(writer, "  = expected value -> actual value:")?;
+            diff::write(writer, &diagnostic.diff, 4)?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Convert diagnostics to a JSON list. Each diagnostic is an object with a `severity`, a `code`
+/// (possibly `null`), a `message`, a list of `labels`, a list of `notes`, a list of `fixes` and a
+/// `diff`, the list of the changes between the expected value and the actual one, as lines such
+/// as `~ server.port: 80 -> 8080`.
+/// Each label has a `file`, a `message`, a `primary` flag and the `start` and `end` positions of
+/// the annotated snippet, as 1-based lines and columns. Each fix has a `message` and a list of
+/// `edits`, made of the `file`, the `start` and `end` positions of the replaced text and of its
+/// `replacement`.
+pub fn to_json(files: &Files<String>, diagnostics: &[Diagnostic]) -> Value {
+    let position = |file_id: FileId, index: ByteIndex| match files.location(file_id, index) {
+        Ok(location) => json!({
+            "line": location.line.to_usize() + 1,
+            "column": location.column.to_usize() + 1,
+        }),
+        Err(_) => Value::Null,
+    };
+    let file = |span: RawSpan| files.name(span.src_id).to_string_lossy().into_owned();
+
+    Value::Array(
+        diagnostics
+            .iter()
+            .map(|d| {
+                let labels: Vec<Value> = d
+                    .labels
+                    .iter()
+                    .map(|label| {
+                        json!({
+                            "file": file(label.span),
+                            "message": label.message,
+                            "primary": label.primary,
+                            "start": position(label.span.src_id, label.span.start),
+                            "end": position(label.span.src_id, label.span.end),
+                        })
+                    })
+                    .collect();
+                let fixes: Vec<Value> = d
+                    .fixes
+                    .iter()
+                    .map(|fix| {
+                        let edits: Vec<Value> = fix
+                            .edits
+                            .iter()
+                            .map(|edit| {
+                                json!({
+                                    "file": file(edit.span),
+                                    "start": position(edit.span.src_id, edit.span.start),
+                                    "end": position(edit.span.src_id, edit.span.end),
+                                    "replacement": edit.replacement,
+                                })
+                            })
+                            .collect();
+                        json!({"message": fix.message, "edits": edits})
+                    })
+                    .collect();
+
+                json!({
+                    "severity": d.severity.name(),
+                    "code": d.code,
+                    "message": d.message,
+                    "labels": labels,
+                    "notes": d.notes,
+                    "fixes": fixes,
+                    "diff": d.diff.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                })
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, ParseError};
+
+    #[test]
+    fn missing_comma() {
+        let mut files = Files::new();
+        let file_id = files.add("main.ncl", String::from("{a = 1 b = 2}"));
+        let span = |start, end| RawSpan {
+            src_id: file_id,
+            start: ByteIndex(start),
+            end: ByteIndex(end),
+        };
+        let err = Error::from(ParseError::MissingComma(span(5, 6), span(7, 8)));
+
+        let diagnostics = err.diagnostics(&mut files, None);
+        let d = &diagnostics[0];
+        assert_eq!(d.severity(), Severity::Error);
+        assert_eq!(d.code(), Some("N0107"));
+        assert_eq!(
+            d.fixes(),
+            &[Fix {
+                message: String::from("insert a comma"),
+                edits: vec![Edit {
+                    span: span(6, 6),
+                    replacement: String::from(","),
+                }],
+            }]
+        );
+        assert_eq!(Diagnostic::from(&d.to_codespan()).labels(), d.labels());
+
+        let json = to_json(&files, &diagnostics);
+        assert_eq!(json[0]["code"], json!("N0107"));
+        assert_eq!(
+            json[0]["fixes"][0]["edits"][0],
+            json!({
+                "file": "main.ncl",
+                "start": {"line": 1, "column": 7},
+                "end": {"line": 1, "column": 7},
+                "replacement": ",",
+            })
+        );
+    }
+}
@@ -94,9 +94,11 @@ use crate::operation::{continuate_operation, OperationCont};
 use crate::position::TermPos;
 use crate::stack::Stack;
 use crate::term::{make as mk_term, MetaValue, RichTerm, StrChunk, Term, UnaryOp};
-use std::cell::{Ref, RefCell, RefMut};
+use crate::trace_span;
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::collections::HashMap;
 use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
 
 /// The state of a thunk.
 ///
@@ -119,6 +121,7 @@ pub enum ThunkState {
 pub struct ThunkData {
     closure: Closure,
     state: ThunkState,
+    counter: ThunkCounter,
 }
 
 impl ThunkData {
@@ -126,10 +129,122 @@ impl ThunkData {
         ThunkData {
             closure,
             state: ThunkState::Suspended,
+            counter: ThunkCounter::new(),
         }
     }
 }
 
+/// Statistics of the evaluations performed on the current thread by a function, as returned by
+/// [`with_stats`](fn.with_stats.html).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// The wall-clock time taken by the function.
+    pub elapsed: Duration,
+    /// The number of reduction steps, which are the iterations of the abstract machine.
+    pub steps: usize,
+    /// The largest number of thunks allocated by the function and alive at the same time.
+    pub peak_thunks: usize,
+}
+
+/// The counters of the evaluations of a thread.
+struct Counters {
+    steps: Cell<usize>,
+    live_thunks: Cell<usize>,
+    peak_thunks: Cell<usize>,
+}
+
+thread_local! {
+    static COUNTERS: Counters = const {
+        Counters {
+            steps: Cell::new(0),
+            live_thunks: Cell::new(0),
+            peak_thunks: Cell::new(0),
+        }
+    };
+}
+
+/// Run a function, and return its result together with the statistics of the evaluations it
+/// performs on the current thread.
+pub fn with_stats<T>(f: impl FnOnce() -> T) -> (T, Stats) {
+    let (steps, live_thunks, peak_thunks) = COUNTERS.with(|counters| {
+        let saved = (
+            counters.steps.get(),
+            counters.live_thunks.get(),
+            counters.peak_thunks.get(),
+        );
+        counters.peak_thunks.set(counters.live_thunks.get());
+        saved
+    });
+
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+
+    let stats = COUNTERS.with(|counters| {
+        let stats = Stats {
+            elapsed,
+            steps: counters.steps.get() - steps,
+            peak_thunks: counters.peak_thunks.get() - live_thunks,
+        };
+        // Keep the peak of an enclosing call.
+        counters
+            .peak_thunks
+            .set(counters.peak_thunks.get().max(peak_thunks));
+        stats
+    });
+    (result, stats)
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "time: {:?}, steps: {}, peak thunks: {}",
+            self.elapsed, self.steps, self.peak_thunks
+        )
+    }
+}
+
+/// A guard counting the thunks alive on the current thread.
+#[derive(Debug)]
+struct ThunkCounter;
+
+impl ThunkCounter {
+    fn new() -> Self {
+        COUNTERS.with(|counters| {
+            let live = counters.live_thunks.get() + 1;
+            counters.live_thunks.set(live);
+            if live > counters.peak_thunks.get() {
+                counters.peak_thunks.set(live);
+            }
+        });
+        ThunkCounter
+    }
+}
+
+impl Clone for ThunkCounter {
+    fn clone(&self) -> Self {
+        ThunkCounter::new()
+    }
+}
+
+impl PartialEq for ThunkCounter {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Drop for ThunkCounter {
+    fn drop(&mut self) {
+        // Thunks may outlive the counters when a thread exits.
+        let _ = COUNTERS.try_with(|counters| {
+            counters
+                .live_thunks
+                .set(counters.live_thunks.get().saturating_sub(1))
+        });
+    }
+}
+
 /// A thunk.
 ///
 /// A thunk is a shared suspended computation. It is the primary device for the implementation of
@@ -182,6 +297,7 @@ impl Thunk {
             let ThunkData {
                 ref closure,
                 ref state,
+                ..
             } = data;
             (closure, state)
         });
@@ -195,6 +311,7 @@ impl Thunk {
             let ThunkData {
                 ref mut closure,
                 ref mut state,
+                ..
             } = data;
             (closure, state)
         });
@@ -242,10 +359,9 @@ impl ThunkUpdateFrame {
     /// - `false` if the corresponding closure has been dropped since
     pub fn update(self, closure: Closure) -> bool {
         if let Some(data) = Weak::upgrade(&self.data) {
-            *data.borrow_mut() = ThunkData {
-                closure,
-                state: ThunkState::Evaluated,
-            };
+            let mut data = data.borrow_mut();
+            data.closure = closure;
+            data.state = ThunkState::Evaluated;
             true
         } else {
             false
@@ -346,6 +462,7 @@ pub fn eval<R>(t0: RichTerm, global_env: &Environment, resolver: &mut R) -> Resu
 where
     R: ImportResolver,
 {
+    let _span = trace_span!("eval", mode = "normal");
     eval_closure(Closure::atomic_closure(t0), global_env, resolver, true).map(|(term, _)| term)
 }
 
@@ -360,20 +477,188 @@ where
 {
     use crate::transformations::fresh_var;
 
+    let _span = trace_span!("eval", mode = "full");
     let var = fresh_var();
+    let pos = t0.pos.into_inherited();
     // Desugar to let x = term in deepSeq x x
-    let wrapper = mk_term::let_in(
+    let mut wrapper = mk_term::let_in(
         var.clone(),
         t0,
         mk_app!(
             mk_term::op1(UnaryOp::DeepSeq(), Term::Var(var.clone())),
             Term::Var(var)
         ),
-    );
+    )
+    .with_pos(pos);
+    wrapper.inherit_pos();
     eval_closure(Closure::atomic_closure(wrapper), global_env, resolver, true)
         .map(|(term, env)| subst(term.into(), &global_env, &env).into())
 }
 
+/// Evaluate a Nickel term to a weak head normal form. If the result is a list, fully evaluate its
+/// elements one at a time, in order, and pass each of them to `f` as soon as it is evaluated.
+/// Otherwise, fully evaluate the result and return it.
+///
+/// Contrary to [`eval_full`](fn.eval_full.html), an element which has been passed to `f` is not
+/// retained anymore by the evaluator (unless it is shared with elements which are yet to be
+/// evaluated), such that long lists can be processed in constant memory.
+pub fn eval_full_elements<R, F, E>(
+    t0: RichTerm,
+    global_env: &Environment,
+    resolver: &mut R,
+    mut f: F,
+) -> Result<Option<Term>, E>
+where
+    R: ImportResolver,
+    F: FnMut(Term) -> Result<(), E>,
+    E: From<EvalError>,
+{
+    use crate::transformations::fresh_var;
+
+    let _span = trace_span!("eval", mode = "elements");
+    let (term, mut env) = eval_closure(Closure::atomic_closure(t0), global_env, resolver, true)?;
+
+    let ts = match term {
+        Term::List(ts) => ts,
+        term => {
+            let var = fresh_var();
+            let mut local_env = Environment::new();
+            env_add(&mut local_env, var.clone(), term.into(), env);
+            let (term, env) = deep_seq_var(var, local_env, global_env, resolver)?;
+            return Ok(Some(subst(term.into(), global_env, &env).into()));
+        }
+    };
+
+    // Elements are usually variables bound in `env`. A binding is removed from `env` once the last
+    // element referring to it has been evaluated, so that the evaluated element can be freed.
+    let mut occurrences: HashMap<Ident, usize> = HashMap::new();
+    for t in ts.iter() {
+        if let Term::Var(id) = t.as_ref() {
+            *occurrences.entry(id.clone()).or_insert(0) += 1;
+        }
+    }
+
+    for t in ts.into_iter() {
+        let thunk = match t.as_ref() {
+            Term::Var(id) if env.contains_key(id) => {
+                let count = occurrences.get_mut(id).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    env.remove(id).unwrap()
+                } else {
+                    env.get(id).unwrap().clone()
+                }
+            }
+            // Constants don't need an environment, which avoids copying `env` for each of them.
+            Term::Null | Term::Bool(_) | Term::Num(_) | Term::Str(_) | Term::Enum(_) => {
+                Thunk::new(Closure::atomic_closure(t), IdentKind::Let())
+            }
+            _ => Thunk::new(
+                Closure {
+                    body: t,
+                    env: env.clone(),
+                },
+                IdentKind::Let(),
+            ),
+        };
+
+        let var = fresh_var();
+        let mut local_env = Environment::new();
+        local_env.insert(var.clone(), thunk);
+        let (term, elt_env) = deep_seq_var(var, local_env, global_env, resolver)?;
+        f(subst(term.into(), global_env, &elt_env).into())?;
+    }
+
+    Ok(None)
+}
+
+/// Evaluate `deepSeq var var` in the environment `env`, which must bind `var`.
+fn deep_seq_var<R>(
+    var: Ident,
+    env: Environment,
+    global_env: &Environment,
+    resolver: &mut R,
+) -> Result<(Term, Environment), EvalError>
+where
+    R: ImportResolver,
+{
+    let body = mk_app!(
+        mk_term::op1(UnaryOp::DeepSeq(), Term::Var(var.clone())),
+        Term::Var(var)
+    );
+    eval_closure(Closure { body, env }, global_env, resolver, true)
+}
+
+/// Evaluate a Nickel term to a WHNF, as [`eval`](fn.eval.html), but don't unwrap a
+/// [sensitive](../term/struct.MetaValue.html#structfield.sensitive) metavalue at the top-level, so
+/// that it can be printed redacted. The value of the metavalue is still evaluated.
+pub fn eval_sensitive<R>(
+    t: RichTerm,
+    global_env: &Environment,
+    resolver: &mut R,
+) -> Result<Term, EvalError>
+where
+    R: ImportResolver,
+{
+    let (term, env) = eval_closure(Closure::atomic_closure(t), global_env, resolver, false)?;
+
+    match term {
+        Term::MetaValue(mut meta) if meta.sensitive => {
+            if let Some(t) = meta.value.take() {
+                let pos = t.pos;
+                let (evaluated, _) =
+                    eval_closure(Closure { body: t, env }, global_env, resolver, true)?;
+                meta.value.replace(RichTerm::new(evaluated, pos));
+            }
+
+            Ok(Term::MetaValue(meta))
+        }
+        term => eval_closure(
+            Closure {
+                body: term.into(),
+                env,
+            },
+            global_env,
+            resolver,
+            true,
+        )
+        .map(|(term, _)| term),
+    }
+}
+
+/// Fully evaluate a Nickel term, as [`eval_full`](fn.eval_full.html), but don't unwrap a
+/// [sensitive](../term/struct.MetaValue.html#structfield.sensitive) metavalue at the top-level, as
+/// for the fields of the result.
+pub fn eval_full_sensitive<R>(
+    t: RichTerm,
+    global_env: &Environment,
+    resolver: &mut R,
+) -> Result<Term, EvalError>
+where
+    R: ImportResolver,
+{
+    use crate::transformations::fresh_var;
+
+    let _span = trace_span!("eval", mode = "full");
+    let (term, env) = eval_closure(Closure::atomic_closure(t), global_env, resolver, false)?;
+    let sensitive = matches!(&term, Term::MetaValue(meta) if meta.sensitive);
+
+    let var = fresh_var();
+    let mut local_env = Environment::new();
+    env_add(&mut local_env, var.clone(), term.into(), env);
+    let (term, env) = deep_seq_var(var, local_env, global_env, resolver)?;
+    let term = subst(term.into(), global_env, &env);
+
+    if sensitive {
+        Ok(Term::MetaValue(MetaValue {
+            sensitive: true,
+            ..MetaValue::from(term)
+        }))
+    } else {
+        Ok(term.into())
+    }
+}
+
 /// Evaluate a Nickel Term, stopping when a meta value is encountered at the top-level without
 /// unwrapping it. Then evaluate the underlying value, and substitute variables in order to obtain
 /// a WHNF that is printable.
@@ -440,6 +725,7 @@ where
     let mut stack = Stack::new();
 
     loop {
+        COUNTERS.with(|counters| counters.steps.set(counters.steps.get() + 1));
         let Closure {
             body: RichTerm {
                 term: boxed_term,
@@ -721,7 +1007,7 @@ where
                     ));
                 }
             }
-            Term::Import(path) => {
+            Term::Import(path) | Term::ImportStr(path) => {
                 return Err(EvalError::InternalError(
                     format!("Unresolved import ({})", path.to_string_lossy()),
                     pos,
@@ -814,7 +1100,9 @@ pub fn subst(rt: RichTerm, global_env: &Environment, env: &Environment) -> RichT
             | v @ Term::Var(_)
             | v @ Term::Enum(_)
             | v @ Term::Import(_)
-            | v @ Term::ResolvedImport(_) => RichTerm::new(v, pos),
+            | v @ Term::ImportStr(_)
+            | v @ Term::ResolvedImport(_)
+            | v @ Term::ParseError => RichTerm::new(v, pos),
             Term::Let(id, t1, t2) => {
                 let t1 = subst_(t1, global_env, env, Cow::Borrowed(bound.as_ref()));
                 let t2 = subst_(t2, global_env, env, bound);
@@ -995,7 +1283,7 @@ mod tests {
         let id = Files::new().add("<test>", String::from(s));
 
         grammar::TermParser::new()
-            .parse(id, lexer::Lexer::new(&s))
+            .parse_strict(id, lexer::Lexer::new(&s))
             .map(|mut t| {
                 t.clean_pos();
                 t
@@ -1045,6 +1333,20 @@ mod tests {
         assert_eq!(Ok(Term::Num(5.0)), eval_no_import(t));
     }
 
+    #[test]
+    fn stats() {
+        let t = parse("let x = 1 + 1 in let y = x + x in [x, y]").unwrap();
+        let (result, stats) = with_stats(|| eval_no_import(t.clone()));
+        assert!(result.is_ok());
+        assert!(stats.steps > 0);
+        assert!(stats.peak_thunks >= 2);
+
+        // Statistics are collected from scratch by each call.
+        let (_, again) = with_stats(|| eval_no_import(t));
+        assert_eq!(again.steps, stats.steps);
+        assert_eq!(again.peak_thunks, stats.peak_thunks);
+    }
+
     #[test]
     fn simple_let() {
         let t = mk_term::let_in("x", Term::Num(5.0), mk_term::var("x"));
@@ -1133,6 +1435,10 @@ mod tests {
             String::from("nested"),
             String::from("let x = import \"two\" in x + 1"),
         );
+        resolver.add_source(
+            String::from("nested_as"),
+            String::from("import \"two\" as x\nimport \"lib\" as lib\nif lib.f then x + 2 else 0"),
+        );
         resolver.add_source(
             String::from("cycle"),
             String::from("let x = import \"cycle_b\" in {a = 1, b = x.a}"),
@@ -1191,6 +1497,17 @@ mod tests {
             Term::Num(3.0)
         );
 
+        // let x = import "nested_as" in x
+        assert_eq!(
+            eval(
+                mk_import("x", "nested_as", mk_term::var("x"), &mut resolver).unwrap(),
+                &HashMap::new(),
+                &mut resolver
+            )
+            .unwrap(),
+            Term::Num(4.0)
+        );
+
         // let x = import "lib" in x.f
         assert_eq!(
             eval(
@@ -1224,6 +1541,34 @@ mod tests {
             .unwrap(),
             Term::Num(1.0)
         );
+
+        // let x = import_str "bad" in x
+        assert_eq!(
+            eval(
+                transform(
+                    mk_term::let_in("x", mk_term::import_str("bad"), mk_term::var("x")),
+                    &mut resolver
+                )
+                .unwrap(),
+                &HashMap::new(),
+                &mut resolver
+            )
+            .unwrap(),
+            Term::Str(String::from("^$*/.23ab 0°@"))
+        );
+
+        // let x = import_str "does_not_exist" in x
+        assert!(matches!(
+            transform(
+                mk_term::let_in(
+                    "x",
+                    mk_term::import_str("does_not_exist"),
+                    mk_term::var("x")
+                ),
+                &mut resolver,
+            ),
+            Err(ImportError::IOError(_, _, _))
+        ));
     }
 
     #[test]
@@ -0,0 +1,632 @@
+//! Error codes: stable identifiers of the kinds of errors, with an extended explanation of each,
+//! printed by `nickel explain`.
+//!
+//! The code of an error is attached to its first diagnostic (see
+//! [`ToDiagnostic`](../error/trait.ToDiagnostic.html)). Codes are made of a letter and four
+//! digits, the first two of which identify the phase which raised the error:
+//!
+//! - `N01xx`: parsing,
+//! - `N02xx`: imports,
+//! - `N03xx`: typechecking,
+//! - `N04xx`: evaluation,
+//! - `N05xx`: serialization,
+//! - `N06xx`: other errors,
+//! - `N07xx`: [warnings](../warning/index.html), which are only errors when they are denied.
+//!
+//! Codes are never reused: a code whose error disappears is retired.
+use crate::error::{Error, EvalError, ImportError, ParseError, SerializationError, TypecheckError};
+
+/// The description of an error code.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    /// A short description, in lower case.
+    pub title: &'static str,
+    /// The extended explanation, with examples.
+    pub explanation: &'static str,
+}
+
+/// Return the code of the kind of an error.
+pub fn error_code(error: &Error) -> &'static str {
+    match error {
+        Error::ParseError(err) | Error::ImportError(ImportError::ParseError(err, _)) => match err {
+            ParseError::UnexpectedEOF(..) => "N0101",
+            ParseError::UnexpectedToken(..) => "N0102",
+            ParseError::ExtraToken(..) => "N0103",
+            ParseError::UnmatchedCloseBrace(..) => "N0104",
+            ParseError::InvalidEscapeSequence(..) => "N0105",
+            ParseError::InvalidAsciiEscapeCode(..) => "N0106",
+            ParseError::MissingComma(..) => "N0107",
+            ParseError::UnterminatedString(..) => "N0108",
+            ParseError::NonAsciiIdentifier(..) => "N0109",
+            ParseError::InvalidUnicodeEscape(..) => "N0110",
+            ParseError::ExternalFormatError(..) => "N0111",
+            ParseError::InexactNumber(..) => "N0112",
+        },
+        Error::ImportError(ImportError::IOError(..)) => "N0201",
+        Error::TypecheckError(err) => match err {
+            TypecheckError::MissingRow(..) => "N0301",
+            TypecheckError::ExtraRow(..) => "N0302",
+            TypecheckError::MissingDynTail(..) => "N0303",
+            TypecheckError::ExtraDynTail(..) => "N0304",
+            TypecheckError::UnboundIdentifier(..) => "N0305",
+            TypecheckError::IllformedType(..) => "N0306",
+            TypecheckError::UnboundTypeVariable(..) => "N0307",
+            TypecheckError::TypeMismatch(..) => "N0308",
+            TypecheckError::RowKindMismatch(..) => "N0309",
+            TypecheckError::RowMismatch(..) => "N0310",
+            TypecheckError::RowConflict(..) => "N0311",
+            TypecheckError::ArrowTypeMismatch(..) => "N0312",
+        },
+        Error::EvalError(err) => match err {
+            EvalError::BlameError(..) => "N0401",
+            EvalError::TypeError(..) => "N0402",
+            EvalError::NotAFunc(..) => "N0403",
+            EvalError::FieldMissing(..) => "N0404",
+            EvalError::NotEnoughArgs(..) => "N0405",
+            EvalError::MergeIncompatibleArgs(..) => "N0406",
+            EvalError::UnboundIdentifier(..) => "N0407",
+            EvalError::InfiniteRecursion(..) => "N0408",
+            EvalError::SerializationError(..) => "N0409",
+            EvalError::DeserializationError(..) => "N0410",
+            EvalError::InternalError(..) => "N0411",
+            EvalError::Other(..) => "N0412",
+            EvalError::IllFormedTerm(..) => "N0413",
+        },
+        Error::SerializationError(err) => match err {
+            SerializationError::UnsupportedNull(..) => "N0501",
+            SerializationError::NotAString(..) => "N0502",
+            SerializationError::NotAList(..) => "N0503",
+            SerializationError::NotFlat(..) => "N0504",
+            SerializationError::NonSerializable(..) => "N0505",
+            SerializationError::Other(..) => "N0506",
+        },
+        Error::ExtractionError(..) => "N0601",
+        Error::IOError(..) => "N0602",
+        #[cfg(feature = "repl-backend")]
+        Error::REPLError(..) => "N0603",
+        Error::DeniedWarnings(warnings) => warnings
+            .first()
+            .map_or("N0700", |warning| warning.kind().code()),
+    }
+}
+
+/// Return the description of an error code, ignoring case.
+pub fn lookup(code: &str) -> Option<&'static ErrorCode> {
+    CODES
+        .iter()
+        .find(|error_code| error_code.code.eq_ignore_ascii_case(code.trim()))
+}
+
+/// All the error codes, in increasing order.
+pub const CODES: &[ErrorCode] = &[
+    ErrorCode {
+        code: "N0101",
+        title: "unexpected end of input",
+        explanation: "The input ended before the expression was complete. A delimiter may be \
+unclosed, or a construct may miss its last part, such as the body of a `let` or of a function:
+
+    let x = 1
+
+A toplevel `let` needs a body, introduced by `in`:
+
+    let x = 1 in x + 1",
+    },
+    ErrorCode {
+        code: "N0102",
+        title: "unexpected token",
+        explanation: "The parser found a token which can't appear at this place. The diagnostic \
+lists the tokens which were expected instead. For example, the fields of a record are defined \
+with `=`, not `:`, which introduces a type annotation:
+
+    {port: 80}
+
+should be written:
+
+    {port = 80}",
+    },
+    ErrorCode {
+        code: "N0103",
+        title: "superfluous token",
+        explanation:
+            "The parser found a token after a complete expression, where the input was expected to \
+end. A program is a single expression: definitions are chained with `let ... in`, and several \
+values are grouped in a record or a list.",
+    },
+    ErrorCode {
+        code: "N0104",
+        title: "unmatched closing brace",
+        explanation: "A closing brace `}` has no matching opening brace:
+
+    {a = 1}}
+
+Remove the extra brace, or check the braces of the enclosing records and interpolations.",
+    },
+    ErrorCode {
+        code: "N0105",
+        title: "invalid escape sequence",
+        explanation: "A string literal contains a backslash followed by a character which doesn't \
+form an escape sequence. The supported sequences are `\\n`, `\\r`, `\\t`, `\\0`, `\\\\`, \
+`\\\"`, `\\'`, `\\#`, `\\xNN` and `\\u{NNNN}`.
+
+    \"C:\\dir\"
+
+Escape the backslash itself: `\"C:\\\\dir\"`, or use a raw string, where backslashes are not \
+special: `r#\"C:\\dir\"#`.",
+    },
+    ErrorCode {
+        code: "N0106",
+        title: "invalid ASCII escape code",
+        explanation: "An escape sequence `\\xNN` in a string literal doesn't denote an ASCII \
+character: its code must be written with two hexadecimal digits and be at most `7F`.
+
+    \"\\x80\"
+
+Use a Unicode escape sequence for other characters, such as `\"\\u{80}\"`.",
+    },
+    ErrorCode {
+        code: "N0107",
+        title: "missing comma",
+        explanation:
+            "Two fields of a record, or two elements of a list, follow each other without \
+a comma between them.
+
+    {a = 1 b = 2}
+
+should be written:
+
+    {a = 1, b = 2}",
+    },
+    ErrorCode {
+        code: "N0108",
+        title: "unterminated string",
+        explanation: "The input ended inside a string literal: its closing delimiter, `\"` or \
+`\"#m` for a multiline string, is missing.
+
+    {name = \"nickel}
+
+Close the string where it is meant to end: `{name = \"nickel\"}`.",
+    },
+    ErrorCode {
+        code: "N0109",
+        title: "non-ASCII identifier",
+        explanation: "An identifier contains non-ASCII characters, while the program was run \
+with `--ascii-identifiers`.
+
+    let café = 1 in café
+
+Rename the identifier, or remove the option to allow Unicode identifiers.",
+    },
+    ErrorCode {
+        code: "N0110",
+        title: "invalid Unicode escape sequence",
+        explanation: "An escape sequence `\\u{...}` in a string literal doesn't denote a Unicode \
+scalar value: it must contain one to six hexadecimal digits, and must not be a surrogate.
+
+    \"\\u{D800}\"",
+    },
+    ErrorCode {
+        code: "N0111",
+        title: "invalid data file",
+        explanation: "An imported JSON, YAML or TOML file, or a string given to `deserialize`, \
+could not be parsed in its format. The diagnostic shows the message of the parser of the format.
+
+    import \"config.json\"
+
+fails if `config.json` contains `{\"port\": 80,}`, since JSON doesn't allow trailing commas.",
+    },
+    ErrorCode {
+        code: "N0112",
+        title: "number literal not exactly representable",
+        explanation: "An integer literal in base 16, 8 or 2 doesn't have an exact representation \
+as a number. Numbers are double-precision floats, which represent all the integers up to 2^53 \
+exactly, but only some of the integers above.
+
+    0x20_0000_0000_0001
+
+Use a decimal literal if the rounded value is acceptable.",
+    },
+    ErrorCode {
+        code: "N0201",
+        title: "import failure",
+        explanation: "An imported file could not be read: it doesn't exist or is not readable. \
+Relative paths are resolved from the directory of the importing file, then from the import \
+paths of the program.
+
+    import \"lib/server.ncl\"
+
+Check the path, relatively to the file containing the import.",
+    },
+    ErrorCode {
+        code: "N0301",
+        title: "missing row field",
+        explanation: "A record is missing a field required by its type. For example:
+
+    ({a = 1} : {a : Num, b : Str})
+
+The annotation requires the field `b`, which the record doesn't define. Either add the field, as \
+in `{a = 1, b = \"\"}`, or remove it from the type. To only check the fields which are present, \
+use the open record type `{a : Num | Dyn}` or a contract.",
+    },
+    ErrorCode {
+        code: "N0302",
+        title: "extra row field",
+        explanation: "A record defines a field which its type doesn't allow. For example:
+
+    ({a = 1, b = 2} : {a : Num})
+
+Remove the field `b` from the record, add it to the type, or use the open record type \
+`{a : Num | Dyn}` to allow other fields.",
+    },
+    ErrorCode {
+        code: "N0303",
+        title: "missing dynamic tail",
+        explanation: "A record type was expected to be open, that is to end with `| Dyn`, but the \
+actual type is closed:
+
+    let r : {a : Num} = {a = 1} in (r : {a : Num | Dyn})
+
+The typechecker doesn't convert a record type to a less precise type with a dynamic tail. Use \
+the same kind of record type on both sides.",
+    },
+    ErrorCode {
+        code: "N0304",
+        title: "extra dynamic tail",
+        explanation:
+            "A record type was expected to be closed, but the actual type is open, that is \
+ends with `| Dyn`. This is the converse of N0303: use the same kind of record type on both \
+sides.",
+    },
+    ErrorCode {
+        code: "N0305",
+        title: "unbound identifier",
+        explanation: "A variable is used but not defined. It may be misspelled, defined after its \
+use, or be a function of the standard library used without its module:
+
+    let x = 1 in y + 1
+    length [1, 2]
+
+The second example should be written `lists.length [1, 2]`.",
+    },
+    ErrorCode {
+        code: "N0306",
+        title: "ill-formed type",
+        explanation:
+            "A type is not well-formed, such as a type which is not a row appearing where \
+a row is expected, as the tail of a record type:
+
+    {a : Num | Str}
+
+The tail of a record type is either `Dyn` or a type variable.",
+    },
+    ErrorCode {
+        code: "N0307",
+        title: "unbound type variable",
+        explanation:
+            "A type is not well-formed, such as a type which is not a row appearing where a \
+row is expected. The diagnostic shows the offending type.",
+    },
+    ErrorCode {
+        code: "N0308",
+        title: "type mismatch",
+        explanation: "The type of an expression is incompatible with the type expected at this \
+place, given by an annotation or by the use of the expression.
+
+    (1 + \"1\" : Num)
+
+In annotated code, values coming from untyped code have the type `Dyn`, which is only \
+compatible with itself. To check such a value at run time instead, use a contract annotation \
+`| T` rather than a type annotation `: T`.",
+    },
+    ErrorCode {
+        code: "N0309",
+        title: "row kind mismatch",
+        explanation:
+            "The same name is used as a record field and as an enum tag in row types which \
+must be equal. Check that records and enums are not mixed up where the name is used.",
+    },
+    ErrorCode {
+        code: "N0310",
+        title: "row mismatch",
+        explanation: "A field of a record, or a tag of an enum, has a type incompatible with the \
+type expected for it. The diagnostic shows the error on this field.
+
+    ({a = \"1\"} : {a : Num})",
+    },
+    ErrorCode {
+        code: "N0311",
+        title: "row conflict",
+        explanation: "A field is used with two incompatible types. This happens when the type of \
+a record is not fully known yet, and is later constrained to contain the same field with another \
+type:
+
+    let extend | forall c. { | c} -> {a : Str | c} = null in
+    (let bad = extend {a = 1} in 0 : Num)
+
+Here, `extend` adds a field `a` to a record which must not have one already.",
+    },
+    ErrorCode {
+        code: "N0312",
+        title: "function type mismatch",
+        explanation: "Two function types are incompatible, because of their domains or of their \
+codomains. The diagnostic shows which part of the types differs. This is commonly caused by \
+applying a function to an argument of the wrong type:
+
+    let f : Num -> Num = fun x => x + 1 in
+    (f true : Num)",
+    },
+    ErrorCode {
+        code: "N0401",
+        title: "contract violation",
+        explanation:
+            "A value doesn't respect a contract or a type annotation checked at run time. \
+The diagnostic shows the contract and the value which broke it.
+
+    {port | Num = \"80\"}
+
+When a function contract is broken, the blame is either on the argument given by the caller, or \
+on the value returned by the function.",
+    },
+    ErrorCode {
+        code: "N0402",
+        title: "dynamic type error",
+        explanation: "An operation was applied to a value of the wrong type during evaluation. \
+Only annotated code is typechecked: such errors can happen in untyped code.
+
+    1 + \"1\"",
+    },
+    ErrorCode {
+        code: "N0403",
+        title: "not a function",
+        explanation: "A value which is not a function was applied to an argument. This is often a \
+missing operator or comma:
+
+    let x = 1 in x (2 + 3)",
+    },
+    ErrorCode {
+        code: "N0404",
+        title: "missing field",
+        explanation: "A field was accessed, or required by an operation, on a record which \
+doesn't define it. The field may be misspelled, or defined in a record which isn't merged in.
+
+    {port = 80}.host
+
+Use `r.host ? default` to provide a value for a missing field.",
+    },
+    ErrorCode {
+        code: "N0405",
+        title: "not enough arguments",
+        explanation: "A primitive operation, written between `%`, was given too few arguments. \
+Primitive operations must be fully applied: use the functions of the standard library, such as \
+`lists.length`, which can be partially applied.",
+    },
+    ErrorCode {
+        code: "N0406",
+        title: "non-mergeable values",
+        explanation:
+            "Two values given to a merge `&` can't be combined: they are different values \
+which are neither records nor defaults.
+
+    {port = 80} & {port = 8080}
+
+Mark one of the definitions as a default, as in `{port | default = 80} & {port = 8080}`.",
+    },
+    ErrorCode {
+        code: "N0407",
+        title: "unbound identifier",
+        explanation: "A variable which is not defined was evaluated. Unbound identifiers are \
+usually reported as N0305, before evaluation.
+
+    let x = 1 in y",
+    },
+    ErrorCode {
+        code: "N0408",
+        title: "infinite recursion",
+        explanation: "A value depends on itself, so that its evaluation never ends. This is \
+usually a recursive record field referring to itself:
+
+    {a = a + 1}.a
+
+Fields of a recursive record may refer to each other, as long as there is no cycle.",
+    },
+    ErrorCode {
+        code: "N0409",
+        title: "serialization failure",
+        explanation: "A value given to the `serialize` builtin can't be represented in the chosen \
+format. See the N05xx codes for the causes.
+
+    builtins.serialize `Json {f = fun x => x}",
+    },
+    ErrorCode {
+        code: "N0410",
+        title: "deserialization failure",
+        explanation: "The string given to the `deserialize` builtin is not valid in the chosen \
+format.
+
+    builtins.deserialize `Json \"{port: 80}\"",
+    },
+    ErrorCode {
+        code: "N0411",
+        title: "internal error",
+        explanation: "The interpreter reached a state which should never happen. This is a bug of \
+Nickel: please report it with the program which caused it.",
+    },
+    ErrorCode {
+        code: "N0412",
+        title: "evaluation error",
+        explanation: "A rare evaluation error, which doesn't have a dedicated code, such as an \
+invalid argument given to a primitive operation. The diagnostic describes the problem.
+
+    lists.elemAt [1, 2] 5",
+    },
+    ErrorCode {
+        code: "N0413",
+        title: "ill-formed term",
+        explanation: "A term given by an application embedding Nickel, such as a binding added to \
+the global environment, can't be evaluated. Terms built by hand can break invariants which the \
+terms produced by the parser always satisfy, for example by applying `if` to a single argument, or \
+by giving a primitive operator the wrong number of arguments. Such terms should be built with the \
+helpers of the `term::make` module.",
+    },
+    ErrorCode {
+        code: "N0501",
+        title: "unsupported null",
+        explanation: "The exported value contains `null`, which the format can't represent, such \
+as TOML:
+
+    nickel export --format toml <<< '{port = null}'
+
+Remove the field, or give it a value.",
+    },
+    ErrorCode {
+        code: "N0502",
+        title: "not a string",
+        explanation: "The raw format exports a string as is, but the exported value is not a \
+string.
+
+    nickel export --format raw <<< '{text = \"a\"}'
+
+Export the field with `--field text`, or use another format.",
+    },
+    ErrorCode {
+        code: "N0503",
+        title: "not a list",
+        explanation: "The format exports a sequence of values, such as NDJSON or multi-document \
+YAML, but the exported value is not a list.
+
+    nickel export --format ndjson <<< '{a = 1}'",
+    },
+    ErrorCode {
+        code: "N0504",
+        title: "not a flat record",
+        explanation: "A tabular or flat format, such as CSV, dotenv or INI, requires records of \
+scalar values, but the exported value contains nested records or lists.
+
+    nickel export --format dotenv <<< '{server = {port = 80}}'",
+    },
+    ErrorCode {
+        code: "N0505",
+        title: "non-serializable value",
+        explanation: "The exported value contains a value which has no representation in data \
+formats, such as a function. The diagnostic gives the path of this value.
+
+    nickel export <<< '{inc = fun x => x + 1}'",
+    },
+    ErrorCode {
+        code: "N0506",
+        title: "serialization error",
+        explanation: "The exported value could not be written in the chosen format, for a reason \
+described by the diagnostic, such as a number which the format can't represent.",
+    },
+    ErrorCode {
+        code: "N0601",
+        title: "extraction failure",
+        explanation:
+            "A program embedding Nickel tried to convert the result of an evaluation to a \
+Rust value, but the result doesn't have the expected shape, such as a string where a number is \
+expected.",
+    },
+    ErrorCode {
+        code: "N0602",
+        title: "I/O error",
+        explanation: "A file could not be read or written, such as the program given with `-f` or \
+the output of `export`.",
+    },
+    ErrorCode {
+        code: "N0603",
+        title: "REPL error",
+        explanation: "A REPL command is unknown or misses its argument. Use `:help` for the list \
+of commands.
+
+    :load",
+    },
+    ErrorCode {
+        code: "N0701",
+        title: "deprecated syntax",
+        explanation:
+            "The program uses a syntax which is deprecated, and which may be removed in a \
+future version. The diagnostic gives the syntax to use instead: for example, `switch` is replaced \
+by `match`, which supports more patterns. The cases of `switch` are written as enum tags in \
+`match`: `switch { a => 1, _ => 2 }` becomes `match { `a => 1, _ => 2 }`.
+
+    nickel typecheck <<< 'switch { a => 1, _ => 2 } `a'
+
+This warning is named `deprecated`.",
+    },
+    ErrorCode {
+        code: "N0702",
+        title: "shadowed binding",
+        explanation:
+            "A variable is bound with the same name as a variable already in scope, which \
+then can't be used in the scope of the new binding. This is often a mistake, such as a function \
+parameter named like a variable that the body of the function uses. Rename one of the bindings.
+
+    nickel typecheck <<< 'let x = 1 in let f = fun x => x + 1 in f 2'
+
+This warning is named `shadowing`.",
+    },
+    ErrorCode {
+        code: "N0703",
+        title: "implicitly dynamic variable",
+        explanation: "Statically typed code uses a variable bound without type annotation by \
+dynamically typed code. The typechecker doesn't infer the type of such bindings, and gives them \
+the type `Dyn`: the uses of the variable are not checked, or are rejected, such as `x + 1` where \
+`x` is `Dyn`. Annotate the binding with its type, as in `let x : Num = ..`.
+
+    nickel typecheck <<< 'let x = 1 + 1 in (x == 2 : Bool)'
+
+This warning is named `implicit-dyn`.",
+    },
+    ErrorCode {
+        code: "N0704",
+        title: "unused import",
+        explanation: "An import is bound to a variable which is never used. Remove the import.
+
+    nickel typecheck <<< 'let lib = import \"lib.ncl\" in 1'
+
+This warning is named `unused-import`.",
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{IOError, ToDiagnostic};
+    use crate::identifier::Ident;
+    use crate::position::TermPos;
+    use crate::types::{AbsType, Types};
+    use codespan::Files;
+
+    #[test]
+    fn codes() {
+        let codes: Vec<&str> = CODES.iter().map(|error_code| error_code.code).collect();
+        let mut sorted = codes.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(codes, sorted);
+
+        assert!(codes
+            .iter()
+            .all(|code| code.len() == 5 && code.starts_with('N')));
+        assert_eq!(
+            lookup("n0301").map(|error_code| error_code.title),
+            Some("missing row field")
+        );
+        assert_eq!(lookup("N9999"), None);
+    }
+
+    #[test]
+    fn diagnostics() {
+        let err = Error::from(TypecheckError::MissingRow(
+            Ident::from("b"),
+            Types(AbsType::Num()),
+            Types(AbsType::Num()),
+            TermPos::None,
+        ));
+        let diagnostics = err.to_diagnostic(&mut Files::new(), None);
+        assert_eq!(diagnostics[0].code.as_deref(), Some("N0301"));
+
+        let err = Error::from(IOError(String::from("no such file")));
+        assert!(lookup(error_code(&err)).is_some());
+    }
+}
@@ -0,0 +1,533 @@
+//! Fetching of the dependencies of packages.
+//!
+//! Besides local directories, the [manifest](../manifest/index.html) of a package can declare
+//! dependencies on libraries shared through git repositories or through a registry:
+//!
+//! ```toml
+//! [dependencies]
+//! k8s = { git = "https://github.com/example/nickel-k8s", rev = "v1.2.0" }
+//! schemas = { version = "0.3.1", registry = "/srv/nickel-registry" }
+//! ```
+//!
+//! A registry is a directory holding each version of each library in `<name>/<version>`, such
+//! as `/srv/nickel-registry/schemas/0.3.1`. Without a `registry` key, the registry is the
+//! directory given by the `NICKEL_REGISTRY` environment variable.
+//!
+//! Dependencies are fetched once into a local [`Store`](struct.Store.html), shared by all the
+//! packages, at a path determined by their source and their version. A git dependency without a
+//! `rev` is fetched at the head of the default branch of the repository, and is only fetched
+//! again once removed from the store or when [updating](struct.Store.html#method.update_all)
+//! the dependencies. If the store is given the [lockfile](../lock/index.html) of the package, a
+//! git dependency whose revision hasn't changed since it has been locked is fetched at the locked
+//! commit instead. Git repositories are cloned with the `git` command. The
+//! names, revisions and versions of dependencies name directories of the store, and can't start
+//! with `-` nor contain `/`, `\` or `..`.
+use crate::error::IOError;
+use crate::lock::{self, Lockfile};
+use crate::manifest::{Dependency, Manifest, Source, FILE_NAME};
+use crate::vfs::OsFileSystem;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The environment variable giving the default registry.
+pub const REGISTRY_VAR: &str = "NICKEL_REGISTRY";
+
+/// The environment variable overriding the directory of the default store.
+pub const STORE_VAR: &str = "NICKEL_STORE";
+
+/// A directory holding fetched dependencies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Store {
+    root: PathBuf,
+    /// The lockfile giving the commits of git dependencies, if any.
+    lockfile: Option<Lockfile>,
+}
+
+impl Store {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Store {
+            root: root.into(),
+            lockfile: None,
+        }
+    }
+
+    /// Fetch git dependencies at the commits of a lockfile.
+    pub fn with_lockfile(mut self, lockfile: Lockfile) -> Self {
+        self.lockfile = Some(lockfile);
+        self
+    }
+
+    /// Return the default store, with the lockfile of a package, if any.
+    pub fn for_package(manifest: &Manifest) -> Result<Self, IOError> {
+        let store = Store::default();
+        Ok(match Lockfile::load(&lock::lockfile_path(manifest))? {
+            Some(lockfile) => store.with_lockfile(lockfile),
+            None => store,
+        })
+    }
+
+    /// The root directory of the store.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Return the directory of a dependency: the directory of a local dependency, or the
+    /// directory of a fetched dependency in the store, which may not exist yet.
+    pub fn locate(&self, dep: &Dependency) -> Result<PathBuf, IOError> {
+        if !matches!(dep.source, Source::Path(_)) {
+            check_dependency(dep)
+                .map_err(|err| IOError(format!("invalid dependency `{}`: {}", dep.name, err)))?;
+        }
+
+        match &dep.source {
+            Source::Path(path) => Ok(path.clone()),
+            Source::Git { url, rev } => {
+                let dir = self
+                    .root
+                    .join("git")
+                    .join(format!("{}-{}", dep.name, short_hash(url)));
+                match self.locked_commit(dep)? {
+                    Some(commit) => Ok(dir.join(commit)),
+                    None => Ok(dir.join(rev.as_deref().unwrap_or("HEAD"))),
+                }
+            }
+            Source::Registry { version, registry } => {
+                let registry = registry_dir(dep, registry.as_deref())?;
+                Ok(self
+                    .root
+                    .join("registry")
+                    .join(short_hash(&registry.to_string_lossy()))
+                    .join(&dep.name)
+                    .join(version))
+            }
+        }
+    }
+
+    /// Fetch a dependency into the store, unless it is a local dependency or it has already been
+    /// fetched. Return `true` if it has been fetched.
+    pub fn fetch(&self, dep: &Dependency) -> Result<bool, IOError> {
+        let dest = self.locate(dep)?;
+        if matches!(dep.source, Source::Path(_)) || dest.exists() {
+            return Ok(false);
+        }
+
+        // Fetch into a temporary directory first, such that an interrupted fetch doesn't leave an
+        // incomplete dependency in the store.
+        let mut tmp_name = dest.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp = dest.with_file_name(tmp_name);
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp).map_err(IOError::from)?;
+        }
+        fs::create_dir_all(&tmp).map_err(IOError::from)?;
+
+        let result = match &dep.source {
+            Source::Path(_) => unreachable!(),
+            Source::Git { url, rev } => {
+                let rev = self.locked_commit(dep)?.or(rev.as_deref());
+                clone(url, rev, &tmp)
+            }
+            Source::Registry { version, registry } => {
+                let dir = registry_dir(dep, registry.as_deref())?;
+                let src = dir.join(&dep.name).join(version);
+                if src.is_dir() {
+                    copy_dir(&src, &tmp)
+                } else {
+                    Err(IOError(format!(
+                        "version {} of `{}` not found in the registry {}{}",
+                        version,
+                        dep.name,
+                        dir.to_string_lossy(),
+                        available_versions(&dir.join(&dep.name))
+                    )))
+                }
+            }
+        };
+
+        match result.and_then(|()| fs::rename(&tmp, &dest).map_err(IOError::from)) {
+            Ok(()) => Ok(true),
+            Err(err) => {
+                let _ = fs::remove_dir_all(&tmp);
+                Err(IOError(format!(
+                    "could not fetch dependency `{}`: {}",
+                    dep.name, err.0
+                )))
+            }
+        }
+    }
+
+    /// Fetch the dependencies of a package, and the dependencies of its dependencies which have
+    /// a manifest, recursively. Return all the git and registry dependencies, in the order they
+    /// are found.
+    pub fn fetch_all(&self, manifest: &Manifest) -> Result<Vec<Fetched>, IOError> {
+        self.fetch_all_(manifest, false)
+    }
+
+    /// Fetch the dependencies of a package as [`fetch_all`](#method.fetch_all), but fetch the
+    /// git dependencies again, at the current commit of their revision. The lockfile of the
+    /// store, if any, is ignored.
+    pub fn update_all(&self, manifest: &Manifest) -> Result<Vec<Fetched>, IOError> {
+        let store = Store::new(self.root.clone());
+        store.fetch_all_(manifest, true)
+    }
+
+    fn fetch_all_(&self, manifest: &Manifest, update: bool) -> Result<Vec<Fetched>, IOError> {
+        let mut fetched = Vec::new();
+        let mut visited = vec![manifest.root.clone()];
+        self.fetch_deps(manifest, update, &mut fetched, &mut visited)?;
+        Ok(fetched)
+    }
+
+    fn fetch_deps(
+        &self,
+        manifest: &Manifest,
+        update: bool,
+        fetched: &mut Vec<Fetched>,
+        visited: &mut Vec<PathBuf>,
+    ) -> Result<(), IOError> {
+        for dep in &manifest.dependencies {
+            let path = self.locate(dep)?;
+            if visited.contains(&path) {
+                continue;
+            }
+            visited.push(path.clone());
+
+            if update && matches!(dep.source, Source::Git { .. }) && path.exists() {
+                fs::remove_dir_all(&path).map_err(IOError::from)?;
+            }
+            let new = self.fetch(dep)?;
+
+            if !matches!(dep.source, Source::Path(_)) {
+                fetched.push(Fetched {
+                    dependency: dep.clone(),
+                    path: path.clone(),
+                    new,
+                });
+            }
+
+            let manifest_path = path.join(FILE_NAME);
+            if manifest_path.exists() {
+                let manifest = Manifest::load(&OsFileSystem, &manifest_path)?;
+                self.fetch_deps(&manifest, update, fetched, visited)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Store {
+    /// Return the locked commit of a git dependency, if its revision hasn't changed since it has
+    /// been locked.
+    fn locked_commit(&self, dep: &Dependency) -> Result<Option<&str>, IOError> {
+        let commit = match &self.lockfile {
+            Some(lockfile) => lockfile.commit(dep),
+            None => None,
+        };
+
+        match commit {
+            Some(commit) if commit.is_empty() || !commit.chars().all(|c| c.is_ascii_hexdigit()) => {
+                Err(IOError(format!(
+                    "invalid locked commit `{}` for dependency `{}`",
+                    commit, dep.name
+                )))
+            }
+            commit => Ok(commit),
+        }
+    }
+}
+
+/// A git or registry dependency in the store.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fetched {
+    pub dependency: Dependency,
+    /// The directory of the dependency in the store.
+    pub path: PathBuf,
+    /// `true` if the dependency has just been fetched, and `false` if it was already in the store.
+    pub new: bool,
+}
+
+/// The default store is the directory given by the `NICKEL_STORE` environment variable, or
+/// `nickel/store` in the cache directory of the user.
+impl Default for Store {
+    fn default() -> Self {
+        if let Some(root) = env::var_os(STORE_VAR) {
+            return Store::new(root);
+        }
+
+        let cache = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .unwrap_or_else(env::temp_dir);
+        Store::new(cache.join("nickel").join("store"))
+    }
+}
+
+/// Check that the name, the revision and the version of a dependency can be used as directory
+/// names in the store, and as arguments of git commands. A manifest, including the manifest of a
+/// dependency, could otherwise write outside of the store or pass options to git.
+pub(crate) fn check_dependency(dep: &Dependency) -> Result<(), String> {
+    check_component("name", &dep.name)?;
+    match &dep.source {
+        Source::Path(_) => Ok(()),
+        Source::Git { rev, .. } => rev
+            .as_deref()
+            .map_or(Ok(()), |rev| check_component("revision", rev)),
+        Source::Registry { version, .. } => check_component("version", version),
+    }
+}
+
+fn check_component(what: &str, value: &str) -> Result<(), String> {
+    if value.is_empty()
+        || value.starts_with('-')
+        || value.contains(['/', '\\'])
+        || value.contains("..")
+    {
+        Err(format!("invalid {} `{}`", what, value))
+    } else {
+        Ok(())
+    }
+}
+
+/// The first characters of the SHA-256 digest of a string, to name directories after URLs.
+fn short_hash(s: &str) -> String {
+    format!("{:x}", Sha256::digest(s.as_bytes()))[..16].to_owned()
+}
+
+/// Return the registry of a dependency, or the default registry.
+fn registry_dir(dep: &Dependency, registry: Option<&Path>) -> Result<PathBuf, IOError> {
+    registry
+        .map(Path::to_path_buf)
+        .or_else(|| env::var_os(REGISTRY_VAR).map(PathBuf::from))
+        .ok_or_else(|| {
+            IOError(format!(
+                "no registry for dependency `{}`: set `registry` in the manifest, or the {} \
+                 environment variable",
+                dep.name, REGISTRY_VAR
+            ))
+        })
+}
+
+/// List the versions of a library of a registry, for error messages.
+fn available_versions(dir: &Path) -> String {
+    let mut versions: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    versions.sort();
+
+    if versions.is_empty() {
+        String::new()
+    } else {
+        format!(" (available: {})", versions.join(", "))
+    }
+}
+
+/// Clone a git repository into an empty directory, and check out a revision.
+fn clone(url: &str, rev: Option<&str>, dest: &Path) -> Result<(), IOError> {
+    git(&["clone", "--quiet", "--", url, "."], dest)?;
+    if let Some(rev) = rev {
+        git(&["checkout", "--quiet", rev, "--"], dest)?;
+    }
+    Ok(())
+}
+
+/// Return the commit checked out in a clone of a git repository.
+pub(crate) fn commit(dir: &Path) -> Result<String, IOError> {
+    git(&["rev-parse", "HEAD"], dir)
+}
+
+/// Run a git command in a directory, and return its standard output.
+fn git(args: &[&str], dir: &Path) -> Result<String, IOError> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|err| IOError(format!("could not run git: {}", err)))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    } else {
+        Err(IOError(format!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Copy the content of a directory into another one, recursively.
+fn copy_dir(src: &Path, dest: &Path) -> Result<(), IOError> {
+    for entry in fs::read_dir(src).map_err(IOError::from)? {
+        let entry = entry.map_err(IOError::from)?;
+        let target = dest.join(entry.file_name());
+
+        if entry.file_type().map_err(IOError::from)?.is_dir() {
+            fs::create_dir_all(&target).map_err(IOError::from)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target).map_err(IOError::from)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+    use crate::term::Term;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn registry() {
+        let dir = env::temp_dir().join(format!("nickel-fetch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        write(&dir.join("registry/lists/1.0.0/lists.ncl"), "{one = 1}");
+        write(
+            &dir.join("registry/lists/1.0.0/nickel.toml"),
+            "[package]\nname = \"lists\"\nentries = [\"lists.ncl\"]",
+        );
+        write(
+            &dir.join("infra/nickel.toml"),
+            "[package]\nname = \"infra\"\n[dependencies]\n\
+             lists = {version = \"1.0.0\", registry = \"../registry\"}",
+        );
+        write(&dir.join("infra/main.ncl"), "(import \"lists\").one + 1");
+
+        let store = Store::new(dir.join("store"));
+        let manifest = Manifest::load(&OsFileSystem, &dir.join("infra/nickel.toml")).unwrap();
+        let build = || {
+            Program::builder()
+                .source_file(dir.join("infra/main.ncl"))
+                .manifest(manifest.clone())
+                .store(store.clone())
+                .build()
+        };
+        assert!(build().is_err());
+
+        let fetched = store.fetch_all(&manifest).unwrap();
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].dependency, manifest.dependencies[0]);
+        assert!(fetched[0].new);
+        assert!(!store.fetch_all(&manifest).unwrap()[0].new);
+        assert_eq!(build().unwrap().eval().unwrap(), Term::Num(2.0));
+
+        let missing = Manifest::parse(
+            "[package]\nname = \"a\"\n[dependencies]\n\
+             lists = {version = \"2.0.0\", registry = \"registry\"}",
+            &dir,
+        )
+        .unwrap();
+        let IOError(msg) = store.fetch_all(&missing).unwrap_err();
+        assert!(msg.ends_with("(available: 1.0.0)"), "{}", msg);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn git_repository() {
+        let dir = env::temp_dir().join(format!("nickel-fetch-git-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = dir.join("repo");
+        write(&repo.join("lib.ncl"), "1");
+        let run = |args: &[&str]| {
+            let mut args = args.to_vec();
+            args.splice(0..0, ["-c", "user.name=test", "-c", "user.email=test@test"]);
+            git(&args, &repo)
+        };
+        if run(&["init", "--quiet"]).is_err() {
+            // No git available.
+            return;
+        }
+        run(&["add", "lib.ncl"]).unwrap();
+        run(&["commit", "--quiet", "-m", "v1"]).unwrap();
+        run(&["tag", "v1"]).unwrap();
+        write(&repo.join("lib.ncl"), "2");
+        run(&["commit", "--quiet", "-am", "v2"]).unwrap();
+
+        let store = Store::new(dir.join("store"));
+        let dep = |rev: Option<&str>| Dependency {
+            name: String::from("lib"),
+            source: Source::Git {
+                url: repo.to_string_lossy().into_owned(),
+                rev: rev.map(String::from),
+            },
+        };
+        for (rev, content) in [(Some("v1"), "1"), (None, "2")] {
+            assert!(store.fetch(&dep(rev)).unwrap());
+            let path = store.locate(&dep(rev)).unwrap().join("lib.ncl");
+            assert_eq!(fs::read_to_string(path).unwrap(), content);
+        }
+        assert!(!store.fetch(&dep(None)).unwrap());
+        let head = commit(&store.locate(&dep(None)).unwrap()).unwrap();
+        assert_eq!(head, git(&["rev-parse", "HEAD"], &repo).unwrap());
+        assert!(store.fetch(&dep(Some("v3"))).is_err());
+
+        // A locked dependency is fetched at its locked commit, unless its revision has changed.
+        let v1 = git(&["rev-parse", "v1"], &repo).unwrap();
+        let lockfile = Lockfile::parse(&format!(
+            "[[package]]\nname = \"lib\"\nsource = \"git+{}\"\ncommit = \"{}\"\nchecksum = \"\"",
+            repo.to_string_lossy(),
+            v1
+        ))
+        .unwrap();
+        let locked = Store::new(dir.join("store")).with_lockfile(lockfile);
+        assert!(locked.locate(&dep(None)).unwrap().ends_with(&v1));
+        assert!(locked.fetch(&dep(None)).unwrap());
+        let path = locked.locate(&dep(None)).unwrap().join("lib.ncl");
+        assert_eq!(fs::read_to_string(path).unwrap(), "1");
+        assert!(locked.locate(&dep(Some("v1"))).unwrap().ends_with("v1"));
+
+        // Updating fetches the head of the repository again, ignoring the lockfile.
+        write(&repo.join("lib.ncl"), "3");
+        run(&["commit", "--quiet", "-am", "v3"]).unwrap();
+        let manifest = Manifest::parse(
+            &format!(
+                "[package]\nname = \"a\"\n[dependencies]\nlib = {{git = \"{}\"}}",
+                repo.to_string_lossy()
+            ),
+            &dir,
+        )
+        .unwrap();
+        let path = store.locate(&dep(None)).unwrap().join("lib.ncl");
+        assert!(!store.fetch_all(&manifest).unwrap()[0].new);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "2");
+        assert!(locked.update_all(&manifest).unwrap()[0].new);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "3");
+
+        // Options and paths escaping the store are rejected before running git.
+        for rev in ["--upload-pack=touch pwned", "../../escape", "a/b", "a\\b"] {
+            let IOError(msg) = store.fetch(&dep(Some(rev))).unwrap_err();
+            assert!(msg.contains("invalid revision"), "{}", msg);
+        }
+        let option_url = Dependency {
+            name: String::from("lib"),
+            source: Source::Git {
+                url: format!(
+                    "--upload-pack=touch {}",
+                    dir.join("pwned").to_string_lossy()
+                ),
+                rev: None,
+            },
+        };
+        assert!(store.fetch(&option_url).is_err());
+        assert!(!dir.join("pwned").exists());
+        let escaping = Dependency {
+            name: String::from(".."),
+            ..dep(None)
+        };
+        assert!(store.locate(&escaping).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! Format specifiers of string interpolation.
+//!
+//! An interpolated expression can be followed by a format specifier, as in `"#{value:08.2f}"`.
+//! The syntax is a subset of the one of Python and Rust:
+//!
+//! ```text
+//! [[fill]align][+][#][0][width][.precision][type]
+//! ```
+//!
+//! - `align` is one of `<` (left), `>` (right) or `^` (center), and `fill` is the character used
+//!   to pad the value up to `width`, which defaults to a space.
+//! - `+` prints the sign of non-negative numbers.
+//! - `#` adds the `0x`, `0o` or `0b` prefix to hexadecimal, octal and binary numbers.
+//! - `0` pads numbers with zeros, after the sign and the prefix.
+//! - `precision` is the number of decimal digits of a number, or the maximum length of a string.
+//! - `type` is one of `d` (integer), `f` (fixed point), `e` (scientific notation), `x`/`X`
+//!   (hexadecimal), `o` (octal), `b` (binary) or `s` (string).
+use crate::serialize::{num_to_fixed, num_to_string};
+use unicode_width::UnicodeWidthStr;
+
+/// Alignment of a formatted value inside its field.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// A parsed format specifier.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FormatSpec {
+    pub fill: char,
+    pub align: Option<Align>,
+    pub sign: bool,
+    pub alternate: bool,
+    pub zero: bool,
+    pub width: usize,
+    pub precision: Option<usize>,
+    pub kind: Option<char>,
+}
+
+/// The presentation types accepted at the end of a format specifier.
+const KINDS: &str = "dfexXobs";
+
+fn align_of(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+impl FormatSpec {
+    /// Parse a format specifier, without the leading colon. Return `None` if `spec` is empty or
+    /// is not a valid specifier.
+    pub fn parse(spec: &str) -> Option<FormatSpec> {
+        let chars: Vec<char> = spec.chars().collect();
+        let mut i = 0;
+        let mut result = FormatSpec {
+            fill: ' ',
+            align: None,
+            sign: false,
+            alternate: false,
+            zero: false,
+            width: 0,
+            precision: None,
+            kind: None,
+        };
+
+        if chars.is_empty() {
+            return None;
+        }
+
+        if let Some(align) = chars.get(1).copied().and_then(align_of) {
+            result.fill = chars[0];
+            result.align = Some(align);
+            i = 2;
+        } else if let Some(align) = align_of(chars[0]) {
+            result.align = Some(align);
+            i = 1;
+        }
+
+        if chars.get(i) == Some(&'+') {
+            result.sign = true;
+            i += 1;
+        }
+
+        if chars.get(i) == Some(&'#') {
+            result.alternate = true;
+            i += 1;
+        }
+
+        if chars.get(i) == Some(&'0') {
+            result.zero = true;
+            i += 1;
+        }
+
+        let (width, next) = digits(&chars, i);
+        result.width = width.unwrap_or(0);
+        i = next;
+
+        if chars.get(i) == Some(&'.') {
+            let (precision, next) = digits(&chars, i + 1);
+            result.precision = Some(precision?);
+            i = next;
+        }
+
+        if let Some(c) = chars.get(i).filter(|c| KINDS.contains(**c)) {
+            result.kind = Some(*c);
+            i += 1;
+        }
+
+        if i == chars.len() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    /// Format a number according to the specifier.
+    pub fn format_num(&self, n: f64) -> Result<String, String> {
+        let (negative, body) = match self.kind {
+            Some(kind @ 'd') | Some(kind @ 'x') | Some(kind @ 'X') | Some(kind @ 'o')
+            | Some(kind @ 'b') => {
+                if !n.is_finite() || n.fract() != 0.0 || n.abs() > 2f64.powi(53) {
+                    return Err(format!(
+                        "format specifier `{}` expects an integer, got {}",
+                        kind,
+                        num_to_string(n)
+                    ));
+                }
+
+                let abs = n.abs() as u64;
+                let body = match kind {
+                    'x' => format!("{:x}", abs),
+                    'X' => format!("{:X}", abs),
+                    'o' => format!("{:o}", abs),
+                    'b' => format!("{:b}", abs),
+                    _ => abs.to_string(),
+                };
+                (n < 0.0, body)
+            }
+            kind => {
+                let repr = match (kind, self.precision) {
+                    (Some('f'), precision) => num_to_fixed(n, precision.unwrap_or(6)),
+                    (Some('e'), Some(precision)) => format!("{:.*e}", precision, n),
+                    (Some('e'), None) => format!("{:e}", n),
+                    (_, Some(precision)) => num_to_fixed(n, precision),
+                    (_, None) => num_to_string(n),
+                };
+
+                match repr.strip_prefix('-') {
+                    Some(body) => (true, String::from(body)),
+                    None => (false, repr),
+                }
+            }
+        };
+
+        let mut prefix = String::new();
+        if negative {
+            prefix.push('-');
+        } else if self.sign {
+            prefix.push('+');
+        }
+
+        if self.alternate {
+            prefix.push_str(match self.kind {
+                Some('x') | Some('X') => "0x",
+                Some('o') => "0o",
+                Some('b') => "0b",
+                _ => "",
+            });
+        }
+
+        if self.zero && self.align.is_none() {
+            let len = prefix.len() + body.width();
+            let zeros = "0".repeat(self.width.saturating_sub(len));
+            Ok(format!("{}{}{}", prefix, zeros, body))
+        } else {
+            Ok(self.pad(format!("{}{}", prefix, body), Align::Right))
+        }
+    }
+
+    /// Format a string according to the specifier.
+    pub fn format_str(&self, s: &str) -> Result<String, String> {
+        match self.kind {
+            None | Some('s') => (),
+            Some(kind) => {
+                return Err(format!(
+                    "format specifier `{}` expects a number, got a string",
+                    kind
+                ))
+            }
+        }
+
+        let s = match self.precision {
+            Some(precision) => s.chars().take(precision).collect(),
+            None => String::from(s),
+        };
+
+        Ok(self.pad(s, Align::Left))
+    }
+
+    /// Pad a formatted value up to the width of the specifier, using `default` if no alignment
+    /// was specified.
+    fn pad(&self, s: String, default: Align) -> String {
+        let missing = self.width.saturating_sub(s.width());
+        let fill = self.fill.to_string();
+
+        let left = match self.align.unwrap_or(default) {
+            Align::Left => 0,
+            Align::Right => missing,
+            Align::Center => missing / 2,
+        };
+
+        format!("{}{}{}", fill.repeat(left), s, fill.repeat(missing - left))
+    }
+}
+
+/// Parse a sequence of decimal digits starting at index `i`, and return the parsed value (if
+/// there was at least one digit) together with the index following the last digit.
+fn digits(chars: &[char], i: usize) -> (Option<usize>, usize) {
+    let mut end = i;
+    while chars.get(end).is_some_and(char::is_ascii_digit) {
+        end += 1;
+    }
+
+    let value = if end > i {
+        chars[i..end].iter().collect::<String>().parse().ok()
+    } else {
+        None
+    };
+    (value, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_num(spec: &str, n: f64) -> String {
+        FormatSpec::parse(spec).unwrap().format_num(n).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            FormatSpec::parse("*^+#08.2f"),
+            Some(FormatSpec {
+                fill: '*',
+                align: Some(Align::Center),
+                sign: true,
+                alternate: true,
+                zero: true,
+                width: 8,
+                precision: Some(2),
+                kind: Some('f'),
+            })
+        );
+        assert_eq!(FormatSpec::parse(""), None);
+        assert_eq!(FormatSpec::parse("Num"), None);
+        assert_eq!(FormatSpec::parse(" Num"), None);
+        assert_eq!(FormatSpec::parse("8."), None);
+        assert_eq!(FormatSpec::parse("8.2fx"), None);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(format_num("08.2f", 1.23456), "00001.23");
+        assert_eq!(format_num("08.2f", -1.23456), "-0001.23");
+        assert_eq!(format_num("x", 255.0), "ff");
+        assert_eq!(format_num("#06X", 255.0), "0x00FF");
+        assert_eq!(format_num("#b", 5.0), "0b101");
+        assert_eq!(format_num("+d", 42.0), "+42");
+        assert_eq!(format_num(">5", 1.5), "  1.5");
+        assert_eq!(format_num("<5", 1.5), "1.5  ");
+        assert_eq!(format_num("-^7d", 42.0), "--42---");
+        assert_eq!(format_num(".1e", 1500.0), "1.5e3");
+        assert!(FormatSpec::parse("d").unwrap().format_num(1.5).is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let spec = FormatSpec::parse("6").unwrap();
+        assert_eq!(spec.format_str("ab").unwrap(), "ab    ");
+        let spec = FormatSpec::parse(">6.1").unwrap();
+        assert_eq!(spec.format_str("ab").unwrap(), "     a");
+        assert!(FormatSpec::parse("x").unwrap().format_str("ab").is_err());
+    }
+}
@@ -0,0 +1,330 @@
+//! Random generation of well-typed terms, for property-based testing.
+//!
+//! Available with the `property-tests` feature, which also enables the property test suite of
+//! `tests/properties.rs`. A [`Generator`](struct.Generator.html) produces closed terms of a given
+//! type, which:
+//!
+//! - only use the constructors of the [`make`](../term/make/index.html) module, and thus pass
+//!   [`RichTerm::check`](../term/struct.RichTerm.html#method.check),
+//! - are in the form produced by the parser, such that pretty-printing and parsing them gives
+//!   the same term up to positions,
+//! - are accepted by the typechecker when annotated with their type, and can't fail with a
+//!   dynamic type error: they don't use equality on functions, division by zero, missing fields
+//!   or the standard library.
+//!
+//! The generator is deterministic: a seed always produces the same terms, such that a failure
+//! can be reproduced from the seed reported by the test suite.
+use crate::identifier::Ident;
+use crate::mk_app;
+use crate::term::make as mk_term;
+use crate::term::{BinaryOp, RichTerm, StrChunk, Term, UnaryOp};
+use crate::types::{AbsType, Types};
+
+/// The field names of the generated records.
+const FIELDS: &[&str] = &["a", "b", "c", "foo", "bar"];
+
+/// The characters of the generated strings, including the ones which must be escaped.
+const CHARS: &[char] = &[
+    'a', 'z', ' ', '"', '\\', '#', '{', '}', '\n', '\t', 'é', '%',
+];
+
+/// A generator of random types and terms.
+pub struct Generator {
+    state: u64,
+    /// The variables in scope, with their type.
+    vars: Vec<(Ident, Types)>,
+    /// The number of variables introduced so far, used to give them distinct names.
+    fresh: usize,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Generator {
+            state: seed,
+            vars: Vec::new(),
+            fresh: 0,
+        }
+    }
+
+    /// The next random number, from the SplitMix64 generator.
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A random number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    /// Generate a random type of at most the given depth, made of numbers, booleans, strings,
+    /// lists, functions and records.
+    pub fn types(&mut self, depth: usize) -> Types {
+        let choice = if depth == 0 {
+            self.below(3)
+        } else {
+            self.below(6)
+        };
+
+        match choice {
+            0 => Types(AbsType::Num()),
+            1 => Types(AbsType::Bool()),
+            2 => Types(AbsType::Str()),
+            3 => Types(AbsType::List(Box::new(self.types(depth - 1)))),
+            4 => Types(AbsType::Arrow(
+                Box::new(self.types(depth - 1)),
+                Box::new(self.types(depth - 1)),
+            )),
+            _ => {
+                let mut fields = Vec::new();
+                for _ in 0..=self.below(3) {
+                    let id = *self.pick(FIELDS);
+                    if !fields.iter().any(|(f, _)| *f == id) {
+                        fields.push((id, self.types(depth - 1)));
+                    }
+                }
+                record_type(fields)
+            }
+        }
+    }
+
+    /// Generate a closed term of the given type, whose syntax tree has at most the given depth
+    /// in addition to the depth of the literals of the type.
+    pub fn term(&mut self, ty: &Types, depth: usize) -> RichTerm {
+        if depth == 0 {
+            return self.leaf(ty);
+        }
+
+        match self.below(10) {
+            0 => self.leaf(ty),
+            1 => {
+                let bound_ty = self.types(1);
+                let bound = self.term(&bound_ty, depth - 1);
+                let id = self.fresh_var();
+                self.vars.push((id.clone(), bound_ty));
+                let body = self.term(ty, depth - 1);
+                self.vars.pop();
+                mk_term::let_in(id, bound, body)
+            }
+            2 => mk_term::if_then_else(
+                self.term(&Types(AbsType::Bool()), depth - 1),
+                self.term(ty, depth - 1),
+                self.term(ty, depth - 1),
+            ),
+            3 => {
+                let arg_ty = self.types(1);
+                let f = self.term(
+                    &Types(AbsType::Arrow(
+                        Box::new(arg_ty.clone()),
+                        Box::new(ty.clone()),
+                    )),
+                    depth - 1,
+                );
+                mk_term::app(f, self.term(&arg_ty, depth - 1))
+            }
+            4 => {
+                let field = *self.pick(FIELDS);
+                let mut fields = vec![(field, ty.clone())];
+                if self.below(2) == 0 {
+                    let other = *self.pick(FIELDS);
+                    if other != field {
+                        fields.push((other, self.types(1)));
+                    }
+                }
+                let record = self.term(&record_type(fields), depth - 1);
+                mk_term::static_access(record, field)
+            }
+            _ => self.specific(ty, depth),
+        }
+    }
+
+    /// Generate a term using an operation specific to the given type.
+    fn specific(&mut self, ty: &Types, depth: usize) -> RichTerm {
+        let num = Types(AbsType::Num());
+        let boolean = Types(AbsType::Bool());
+        let string = Types(AbsType::Str());
+
+        match &ty.0 {
+            AbsType::Num() => match self.below(4) {
+                0 => mk_term::op2(BinaryOp::Sub(), Term::Num(0.0), self.term(&num, depth - 1)),
+                n => {
+                    let op = [BinaryOp::Plus(), BinaryOp::Sub(), BinaryOp::Mult()][n - 1].clone();
+                    mk_term::op2(op, self.term(&num, depth - 1), self.term(&num, depth - 1))
+                }
+            },
+            AbsType::Bool() => match self.below(5) {
+                0 => mk_term::op1(UnaryOp::BoolNot(), self.term(&boolean, depth - 1)),
+                1 => mk_app!(
+                    mk_term::op1(UnaryOp::BoolAnd(), self.term(&boolean, depth - 1)),
+                    self.term(&boolean, depth - 1)
+                ),
+                2 => mk_app!(
+                    mk_term::op1(UnaryOp::BoolOr(), self.term(&boolean, depth - 1)),
+                    self.term(&boolean, depth - 1)
+                ),
+                3 => {
+                    let op = self
+                        .pick(&[
+                            BinaryOp::LessThan(),
+                            BinaryOp::LessOrEq(),
+                            BinaryOp::GreaterThan(),
+                            BinaryOp::GreaterOrEq(),
+                        ])
+                        .clone();
+                    mk_term::op2(op, self.term(&num, depth - 1), self.term(&num, depth - 1))
+                }
+                // Functions can't be compared, so equality is only used on base types.
+                _ => {
+                    let operand_ty = self.types(0);
+                    mk_term::op2(
+                        BinaryOp::Eq(),
+                        self.term(&operand_ty, depth - 1),
+                        self.term(&operand_ty, depth - 1),
+                    )
+                }
+            },
+            AbsType::Str() => match self.below(2) {
+                0 => mk_term::op2(
+                    BinaryOp::PlusStr(),
+                    self.term(&string, depth - 1),
+                    self.term(&string, depth - 1),
+                ),
+                _ => {
+                    // The parser doesn't produce empty literal chunks.
+                    let mut chunks = Vec::new();
+                    let prefix = self.string();
+                    if !prefix.is_empty() {
+                        chunks.push(StrChunk::Literal(prefix));
+                    }
+                    chunks.push(StrChunk::Expr(self.term(&string, depth - 1), 0));
+                    let suffix = self.string();
+                    if !suffix.is_empty() {
+                        chunks.push(StrChunk::Literal(suffix));
+                    }
+                    // The chunks are stored in reverse order.
+                    chunks.reverse();
+                    Term::StrChunks(chunks).into()
+                }
+            },
+            AbsType::List(_) => mk_term::op2(
+                BinaryOp::ListConcat(),
+                self.term(ty, depth - 1),
+                self.term(ty, depth - 1),
+            ),
+            AbsType::Arrow(dom, codom) => {
+                let id = self.fresh_var();
+                self.vars.push((id.clone(), dom.as_ref().clone()));
+                let body = self.term(codom, depth - 1);
+                self.vars.pop();
+                mk_term::fun(id, body)
+            }
+            _ => self.leaf(ty),
+        }
+    }
+
+    /// Generate a variable or a literal of the given type.
+    fn leaf(&mut self, ty: &Types) -> RichTerm {
+        let candidates: Vec<Ident> = self
+            .vars
+            .iter()
+            .filter(|(_, var_ty)| var_ty == ty)
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !candidates.is_empty() && self.below(2) == 0 {
+            return mk_term::var(self.pick(&candidates).clone());
+        }
+
+        match &ty.0 {
+            AbsType::Num() => {
+                let n = if self.below(4) == 0 {
+                    self.below(1000) as f64 / 8.0
+                } else {
+                    self.below(100) as f64
+                };
+                mk_term::num(n)
+            }
+            AbsType::Bool() => mk_term::bool(self.below(2) == 0),
+            AbsType::Str() => {
+                let chunks = match self.string() {
+                    s if s.is_empty() => Vec::new(),
+                    s => vec![StrChunk::Literal(s)],
+                };
+                Term::StrChunks(chunks).into()
+            }
+            AbsType::List(elt) => {
+                let elts: Vec<RichTerm> = (0..self.below(3)).map(|_| self.leaf(elt)).collect();
+                mk_term::list(elts)
+            }
+            AbsType::Arrow(dom, codom) => {
+                let id = self.fresh_var();
+                self.vars.push((id.clone(), dom.as_ref().clone()));
+                let body = self.leaf(codom);
+                self.vars.pop();
+                mk_term::fun(id, body)
+            }
+            AbsType::StaticRecord(row) => {
+                let mut fields = Vec::new();
+                let mut row = row.as_ref();
+                while let AbsType::RowExtend(id, Some(field_ty), tail) = &row.0 {
+                    fields.push((id.clone(), self.leaf(field_ty)));
+                    row = tail;
+                }
+                Term::RecRecord(fields.into_iter().collect()).into()
+            }
+            _ => mk_term::null(),
+        }
+    }
+
+    /// Generate a string literal.
+    fn string(&mut self) -> String {
+        (0..self.below(6)).map(|_| *self.pick(CHARS)).collect()
+    }
+
+    fn fresh_var(&mut self) -> Ident {
+        self.fresh += 1;
+        Ident::from(format!("x{}", self.fresh))
+    }
+}
+
+/// The type of a record with the given fields.
+fn record_type(fields: Vec<(&str, Types)>) -> Types {
+    let row = fields
+        .into_iter()
+        .rev()
+        .fold(Types(AbsType::RowEmpty()), |tail, (id, ty)| {
+            Types(AbsType::RowExtend(
+                Ident::from(id),
+                Some(Box::new(ty)),
+                Box::new(tail),
+            ))
+        });
+    Types(AbsType::StaticRecord(Box::new(row)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        let generate = |seed| {
+            let mut gen = Generator::new(seed);
+            let ty = gen.types(2);
+            let t = gen.term(&ty, 4);
+            (ty, t)
+        };
+
+        for seed in 0..20 {
+            let (ty, t) = generate(seed);
+            assert_eq!(generate(seed), (ty, t.clone()));
+            assert_eq!(t.check(), Ok(()));
+        }
+    }
+}
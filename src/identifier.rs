@@ -1,14 +1,77 @@
 //! Define the type of an identifier.
-use serde::{Deserialize, Serialize};
+use crate::position::TermPos;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Eq, Hash, PartialEq, Ord, PartialOrd, Clone, Serialize, Deserialize)]
-pub struct Ident(pub String);
+/// An identifier, together with the position where it was written, if it comes from the source.
+///
+/// The position is only informative: it is used by the language server to locate definitions, but
+/// two identifiers with the same label are equal wherever they appear.
+#[derive(Clone)]
+pub struct Ident {
+    pub label: String,
+    pub pos: TermPos,
+}
+
+impl Ident {
+    /// Create an identifier with a position.
+    pub fn new(label: impl Into<String>, pos: TermPos) -> Self {
+        Ident {
+            label: label.into(),
+            pos,
+        }
+    }
+}
 
 impl fmt::Display for Ident {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let Ident(ident) = self;
-        write!(f, "{}", ident)
+        write!(f, "{}", self.label)
+    }
+}
+
+impl fmt::Debug for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Ident").field(&self.label).finish()
+    }
+}
+
+impl PartialEq for Ident {
+    fn eq(&self, other: &Self) -> bool {
+        self.label == other.label
+    }
+}
+
+impl Eq for Ident {}
+
+impl Hash for Ident {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.label.hash(state)
+    }
+}
+
+impl PartialOrd for Ident {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ident {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.label.cmp(&other.label)
+    }
+}
+
+impl Serialize for Ident {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct("Ident", &self.label)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ident {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Ident::from)
     }
 }
 
@@ -17,6 +80,6 @@ where
     String: From<F>,
 {
     fn from(val: F) -> Self {
-        Ident(String::from(val))
+        Ident::new(String::from(val), TermPos::None)
     }
 }
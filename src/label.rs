@@ -2,6 +2,7 @@
 //!
 //! A label is a value holding metadata relative to contract checking. It gives the user useful
 //! information about the context of a contract failure.
+use crate::diff::Change;
 use crate::eval::Thunk;
 use crate::position::{RawSpan, TermPos};
 use crate::types::{AbsType, Types};
@@ -92,7 +93,7 @@ pub mod ty_path {
             (AbsType::Forall(_, _), Some(_)) => {
                 // The length of "forall" plus the final separating dot and whitespace ". "
                 let mut result = 8;
-                while let AbsType::Forall(Ident(id), body) = &ty.0 {
+                while let AbsType::Forall(Ident { label: id, .. }, body) = &ty.0 {
                     // The length of the identifier plus the preceding whitespace
                     result += id.len() + 1;
                     ty = body.as_ref();
@@ -246,6 +247,14 @@ pub struct Label {
     pub polarity: bool,
     /// The path of the type being currently checked in the original type.
     pub path: ty_path::Path,
+    /// The differences between the expected value and the value being checked, for the
+    /// contracts which check the equality to an expected value. Set at run-time by the
+    /// interpreter.
+    pub diff: Vec<Change>,
+    /// Whether the value being checked is
+    /// [sensitive](../term/struct.MetaValue.html#structfield.sensitive), in which case it is not
+    /// shown in error messages.
+    pub sensitive: bool,
 }
 
 impl Label {
@@ -263,6 +272,8 @@ impl Label {
             arg_pos: TermPos::None,
             polarity: false,
             path: Vec::new(),
+            diff: Vec::new(),
+            sensitive: false,
         }
     }
 }
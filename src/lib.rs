@@ -1,18 +1,56 @@
+#[cfg(feature = "tools")]
+pub mod bench;
 pub mod cache;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "tools")]
+pub mod completion;
+pub mod deserialize;
+pub mod diff;
+#[cfg(feature = "tools")]
+pub mod doc;
 pub mod error;
 pub mod eval;
+pub mod explain;
+#[cfg(feature = "packages")]
+pub mod fetch;
+pub mod format;
+#[cfg(feature = "property-tests")]
+pub mod generate;
 pub mod identifier;
 pub mod label;
+#[cfg(feature = "packages")]
+pub mod lock;
+#[cfg(feature = "tools")]
+pub mod lsp;
+#[cfg(feature = "packages")]
+pub mod manifest;
 pub mod merge;
+pub mod native;
 pub mod operation;
 pub mod parser;
 pub mod position;
+pub mod pretty;
+#[cfg(feature = "profiles")]
+pub mod profile;
 pub mod program;
+#[cfg(feature = "repl-backend")]
 pub mod repl;
+#[cfg(feature = "tools")]
+pub mod sarif;
 pub mod serialize;
+#[cfg(feature = "tools")]
+pub mod snapshot;
 pub mod stack;
 pub mod stdlib;
 pub mod term;
+pub mod trace;
 pub mod transformations;
 pub mod typecheck;
 pub mod types;
+pub mod vfs;
+pub mod warning;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use deserialize::from_term;
@@ -0,0 +1,352 @@
+//! Lockfiles of packages.
+//!
+//! The lockfile `nickel.lock`, next to the [manifest](../manifest/index.html) of a package,
+//! records the exact version of each git and registry dependency of the package, including the
+//! dependencies of its dependencies: the commit checked out for a git dependency, and a checksum
+//! of the fetched files. It is written by `nickel fetch`, and is meant to be checked in with the
+//! package:
+//!
+//! ```toml
+//! [[package]]
+//! name = "k8s"
+//! source = "git+https://github.com/example/nickel-k8s"
+//! version = "v1.2.0"
+//! commit = "8d5f1a2c..."
+//! checksum = "sha256:02b7e4c1..."
+//! ```
+//!
+//! Git dependencies are then [fetched](../fetch/struct.Store.html#method.with_lockfile) at their
+//! locked commit, even if a tag or a branch has moved since, until the lockfile is updated by
+//! `nickel fetch --update` or the revision is changed in the manifest. Evaluating a program
+//! doesn't write the lockfile. In locked mode (`--locked` on the command line), the lockfile is
+//! verified instead: evaluation fails if the fetched dependencies have drifted from the lockfile,
+//! for example if a dependency has been added to the manifest or the files of a dependency have
+//! been modified in the store.
+use crate::error::IOError;
+use crate::fetch::{self, Fetched};
+use crate::manifest::{Dependency, Manifest, Source};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name of lockfiles.
+pub const FILE_NAME: &str = "nickel.lock";
+
+/// The header of generated lockfiles.
+const HEADER: &str = "# This file is generated by nickel. It is not meant to be edited.\n";
+
+/// The locked dependencies of a package, sorted by name and source.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Lockfile {
+    #[serde(default, rename = "package")]
+    pub packages: Vec<LockedPackage>,
+}
+
+/// The exact version of a dependency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockedPackage {
+    pub name: String,
+    /// The origin of the dependency: `git+<url>` or `registry`.
+    pub source: String,
+    /// The revision of a git dependency, if given, or the version of a registry dependency.
+    pub version: Option<String>,
+    /// The commit of a git dependency.
+    pub commit: Option<String>,
+    /// The SHA-256 checksum of the files of the dependency, as computed by
+    /// [`checksum`](fn.checksum.html).
+    pub checksum: String,
+}
+
+impl LockedPackage {
+    /// Lock a dependency fetched in the store.
+    pub fn new(fetched: &Fetched) -> Result<Self, IOError> {
+        let dep = &fetched.dependency;
+        let (source, version, commit) = match &dep.source {
+            Source::Path(_) => unreachable!("local dependencies are not locked"),
+            Source::Git { url, rev } => (
+                format!("git+{}", url),
+                rev.clone(),
+                Some(fetch::commit(&fetched.path)?),
+            ),
+            // The location of the registry depends on the machine, and isn't locked: the version
+            // and the checksum identify the files.
+            Source::Registry { version, .. } => {
+                (String::from("registry"), Some(version.clone()), None)
+            }
+        };
+
+        Ok(LockedPackage {
+            name: dep.name.clone(),
+            source,
+            version,
+            commit,
+            checksum: checksum(&fetched.path)?,
+        })
+    }
+
+    fn key(&self) -> (&str, &str) {
+        (&self.name, &self.source)
+    }
+}
+
+impl Lockfile {
+    /// Lock the dependencies fetched in the store, as returned by
+    /// [`Store::fetch_all`](../fetch/struct.Store.html#method.fetch_all).
+    pub fn new(fetched: &[Fetched]) -> Result<Self, IOError> {
+        let mut packages = fetched
+            .iter()
+            .map(LockedPackage::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        packages.sort_by(|p1, p2| p1.key().cmp(&p2.key()));
+        Ok(Lockfile { packages })
+    }
+
+    /// Parse the content of a lockfile.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        toml::from_str(source).map_err(|err| err.to_string())
+    }
+
+    /// Read the lockfile at the given path, if it exists.
+    pub fn load(path: &Path) -> Result<Option<Self>, IOError> {
+        match fs::read_to_string(path) {
+            Ok(source) => Lockfile::parse(&source).map(Some).map_err(|err| {
+                IOError(format!(
+                    "invalid lockfile {}: {}",
+                    path.to_string_lossy(),
+                    err
+                ))
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(IOError::from(err)),
+        }
+    }
+
+    /// Return the content of the lockfile.
+    pub fn render(&self) -> String {
+        // Serializing plain strings and lists of records can't fail.
+        let body = toml::to_string(self).unwrap();
+        format!("{}{}", HEADER, body)
+    }
+
+    /// Return the locked commit of a git dependency, if its revision in the manifest is the locked
+    /// one.
+    pub fn commit(&self, dep: &Dependency) -> Option<&str> {
+        match &dep.source {
+            Source::Git { url, rev } => {
+                let source = format!("git+{}", url);
+                self.packages
+                    .iter()
+                    .find(|p| p.key() == (&dep.name, &source) && &p.version == rev)?
+                    .commit
+                    .as_deref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Describe the differences between this lockfile and the locked version of the fetched
+    /// dependencies, one per line. The lockfiles are the same if there is none.
+    pub fn drift(&self, actual: &Lockfile) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        for locked in &self.packages {
+            match actual.packages.iter().find(|p| p.key() == locked.key()) {
+                None => changes.push(format!(
+                    "`{}` ({}) is locked, but is not a dependency anymore",
+                    locked.name, locked.source
+                )),
+                Some(package) => {
+                    let fields = [
+                        ("version", &locked.version, &package.version),
+                        ("commit", &locked.commit, &package.commit),
+                    ];
+                    for (field, expected, found) in fields {
+                        if expected != found {
+                            changes.push(format!(
+                                "`{}` ({}): {} locked as {}, found {}",
+                                locked.name,
+                                locked.source,
+                                field,
+                                expected.as_deref().unwrap_or("none"),
+                                found.as_deref().unwrap_or("none")
+                            ));
+                        }
+                    }
+                    if locked.checksum != package.checksum {
+                        changes.push(format!(
+                            "`{}` ({}): the files differ from the locked checksum",
+                            locked.name, locked.source
+                        ));
+                    }
+                }
+            }
+        }
+
+        for package in &actual.packages {
+            if !self.packages.iter().any(|p| p.key() == package.key()) {
+                changes.push(format!(
+                    "`{}` ({}) is not in the lockfile",
+                    package.name, package.source
+                ));
+            }
+        }
+
+        changes
+    }
+}
+
+/// Describe the differences between the lockfile of a package and its fetched dependencies, one
+/// per line, including a missing lockfile. The lockfile is up to date if there is none.
+pub fn check(manifest: &Manifest, fetched: &[Fetched]) -> Result<Vec<String>, IOError> {
+    let path = lockfile_path(manifest);
+    let actual = Lockfile::new(fetched)?;
+
+    Ok(match Lockfile::load(&path)? {
+        Some(current) => current.drift(&actual),
+        None if actual.packages.is_empty() => Vec::new(),
+        None => vec![format!("no lockfile {}", path.to_string_lossy())],
+    })
+}
+
+/// Lock the fetched dependencies of a package. If `locked` is `false`, write the lockfile of the
+/// package if it has changed. It is not created if the package has no git or registry
+/// dependency. If `locked` is `true`, fail if the lockfile is missing or has drifted.
+pub fn sync(manifest: &Manifest, fetched: &[Fetched], locked: bool) -> Result<(), IOError> {
+    if locked {
+        let changes = check(manifest, fetched)?;
+        return if changes.is_empty() {
+            Ok(())
+        } else {
+            Err(IOError(format!(
+                "the dependencies have drifted from the lockfile:\n  {}",
+                changes.join("\n  ")
+            )))
+        };
+    }
+
+    let path = lockfile_path(manifest);
+    let actual = Lockfile::new(fetched)?;
+    match Lockfile::load(&path)? {
+        Some(current) if current == actual => Ok(()),
+        None if actual.packages.is_empty() => Ok(()),
+        _ => fs::write(&path, actual.render()).map_err(IOError::from),
+    }
+}
+
+/// Return the path of the lockfile of a package.
+pub fn lockfile_path(manifest: &Manifest) -> PathBuf {
+    manifest.root.join(FILE_NAME)
+}
+
+/// Compute the SHA-256 checksum of the files of a directory, their relative paths and their
+/// contents, in lexicographic order. The `.git` directory is skipped, such that the checksum of
+/// a git dependency only depends on the checked out files.
+pub fn checksum(dir: &Path) -> Result<String, IOError> {
+    let mut files = Vec::new();
+    list_files(dir, Path::new(""), &mut files)?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for file in files {
+        let content = fs::read(dir.join(&file)).map_err(IOError::from)?;
+        // Paths are separated by `/` on all platforms.
+        let name: Vec<_> = file.iter().map(|c| c.to_string_lossy()).collect();
+        hasher.update(name.join("/").as_bytes());
+        hasher.update([0]);
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+    }
+
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+fn list_files(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<(), IOError> {
+    for entry in fs::read_dir(dir.join(relative)).map_err(IOError::from)? {
+        let entry = entry.map_err(IOError::from)?;
+        let path = relative.join(entry.file_name());
+
+        if entry.file_type().map_err(IOError::from)?.is_dir() {
+            if entry.file_name() != ".git" {
+                list_files(dir, &path, files)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::Store;
+    use crate::vfs::OsFileSystem;
+    use std::env;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn lock() {
+        let dir = env::temp_dir().join(format!("nickel-lock-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        write(&dir.join("registry/lists/1.0.0/lists.ncl"), "{one = 1}");
+        write(&dir.join("registry/lists/1.1.0/lists.ncl"), "{one = 1}");
+        let manifest_source = |version: &str| {
+            format!(
+                "[package]\nname = \"infra\"\n[dependencies]\n\
+                 lists = {{version = \"{}\", registry = \"registry\"}}",
+                version
+            )
+        };
+        write(&dir.join("nickel.toml"), &manifest_source("1.0.0"));
+
+        let store = Store::new(dir.join("store"));
+        let load = || Manifest::load(&OsFileSystem, &dir.join("nickel.toml")).unwrap();
+        let fetched = store.fetch_all(&load()).unwrap();
+
+        let lock_path = dir.join(FILE_NAME);
+        assert!(sync(&load(), &fetched, true).is_err());
+        assert_eq!(check(&load(), &fetched).unwrap().len(), 1);
+        assert!(!lock_path.exists());
+        sync(&load(), &fetched, false).unwrap();
+        assert!(check(&load(), &fetched).unwrap().is_empty());
+        let lockfile = Lockfile::load(&lock_path).unwrap().unwrap();
+        assert_eq!(lockfile.packages.len(), 1);
+        assert_eq!(lockfile.packages[0].version.as_deref(), Some("1.0.0"));
+        assert!(lockfile.packages[0].checksum.starts_with("sha256:"));
+        assert!(fs::read_to_string(&lock_path).unwrap().starts_with(HEADER));
+        sync(&load(), &fetched, true).unwrap();
+
+        // The files of the dependency have been modified in the store.
+        write(&fetched[0].path.join("lists.ncl"), "{one = 2}");
+        let IOError(msg) = sync(&load(), &fetched, true).unwrap_err();
+        assert!(
+            msg.contains("the files differ from the locked checksum"),
+            "{}",
+            msg
+        );
+
+        write(&dir.join("nickel.toml"), &manifest_source("1.1.0"));
+        let fetched = store.fetch_all(&load()).unwrap();
+        let IOError(msg) = sync(&load(), &fetched, true).unwrap_err();
+        assert!(
+            msg.contains("version locked as 1.0.0, found 1.1.0"),
+            "{}",
+            msg
+        );
+        sync(&load(), &fetched, false).unwrap();
+        sync(&load(), &fetched, true).unwrap();
+
+        write(&dir.join("nickel.toml"), "[package]\nname = \"infra\"");
+        let IOError(msg) = sync(&load(), &[], true).unwrap_err();
+        assert!(msg.contains("is not a dependency anymore"), "{}", msg);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
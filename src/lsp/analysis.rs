@@ -0,0 +1,484 @@
+//! Static analysis of a document.
+//!
+//! The analysis resolves each occurrence of a variable to the binding which introduces it, be it a
+//! let binding, a function parameter, a field of a recursive record or a field of the standard
+//! library. It also determines, as far as possible without evaluating anything, the records that
+//! a term evaluates to, so that accesses to fields can be related to the definition of these
+//! fields, including across imports.
+use crate::identifier::Ident;
+use crate::position::{RawSpan, TermPos};
+use crate::term::{BinaryOp, RichTerm, Term, UnaryOp};
+use codespan::{ByteIndex, FileId};
+use std::collections::HashMap;
+
+/// The maximum number of variables and field accesses followed when looking for the records a
+/// term evaluates to. Protects against cycles, such as in `{a = a}`.
+const MAX_DEPTH: usize = 32;
+
+/// The index of a binding in an [`Analysis`](struct.Analysis.html).
+pub type BindingId = usize;
+
+/// The construct which introduces a binding.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BindingKind {
+    /// A field of the standard library, bound in the global environment.
+    Global,
+    /// A let binding.
+    Let,
+    /// A function parameter.
+    Param,
+    /// A field of a recursive record, in scope inside the record.
+    Field,
+}
+
+/// A name introduced by a binding construct.
+#[derive(Debug, Clone)]
+pub struct Binding<'a> {
+    pub ident: &'a Ident,
+    /// The bound term. Function parameters don't have one.
+    pub value: Option<&'a RichTerm>,
+    pub kind: BindingKind,
+}
+
+/// The files imported by a document, directly or transitively, indexed by the span of the import
+/// expression. An import which could not be loaded or parsed has no entry.
+pub type Imports = HashMap<RawSpan, (FileId, RichTerm)>;
+
+/// The bindings in scope, by name.
+type Scope<'a> = HashMap<&'a Ident, BindingId>;
+
+/// The bindings of a term, and the resolution of the occurrences of its variables.
+#[derive(Debug, Clone)]
+pub struct Analysis<'a> {
+    bindings: Vec<Binding<'a>>,
+    /// The occurrences of variables, indexed by their position, mapped to their binding.
+    usages: HashMap<RawSpan, BindingId>,
+    /// The field accesses, as the accessed field and the accessed term.
+    accesses: Vec<(&'a Ident, &'a RichTerm)>,
+    imports: &'a Imports,
+}
+
+impl<'a> Analysis<'a> {
+    /// Analyse a term and the files it imports, where the given global bindings are in scope.
+    pub fn new(
+        term: &'a RichTerm,
+        globals: &'a HashMap<Ident, RichTerm>,
+        imports: &'a Imports,
+    ) -> Self {
+        let mut analysis = Analysis {
+            bindings: Vec::new(),
+            usages: HashMap::new(),
+            accesses: Vec::new(),
+            imports,
+        };
+
+        let scope = globals
+            .iter()
+            .map(|(id, value)| (id, analysis.bind(id, Some(value), BindingKind::Global)))
+            .collect();
+        analysis.walk(term, &scope);
+        for (_, imported) in imports.values() {
+            analysis.walk(imported, &scope);
+        }
+
+        analysis
+    }
+
+    fn bind(
+        &mut self,
+        ident: &'a Ident,
+        value: Option<&'a RichTerm>,
+        kind: BindingKind,
+    ) -> BindingId {
+        self.bindings.push(Binding { ident, value, kind });
+        self.bindings.len() - 1
+    }
+
+    fn walk(&mut self, rt: &'a RichTerm, scope: &Scope<'a>) {
+        match rt.term.as_ref() {
+            Term::Var(id) => {
+                if let (Some(binding), TermPos::Original(span)) = (scope.get(id), rt.pos) {
+                    self.usages.insert(span, *binding);
+                }
+            }
+            Term::Let(id, bound, body) => {
+                self.walk(bound, scope);
+
+                let mut scope = scope.clone();
+                scope.insert(id, self.bind(id, Some(bound), BindingKind::Let));
+                self.walk(body, &scope);
+            }
+            Term::Fun(id, body) => {
+                let mut scope = scope.clone();
+                scope.insert(id, self.bind(id, None, BindingKind::Param));
+                self.walk(body, &scope);
+            }
+            Term::RecRecord(map) => {
+                let mut scope = scope.clone();
+                for (id, value) in map {
+                    scope.insert(id, self.bind(id, Some(value), BindingKind::Field));
+                }
+
+                map.values().for_each(|value| self.walk(value, &scope));
+            }
+            Term::Op1(UnaryOp::StaticAccess(id), record) => {
+                self.accesses.push((id, record));
+                self.walk(record, scope);
+            }
+            t => t.for_each_subterm(|sub| self.walk(sub, scope)),
+        }
+    }
+
+    /// Return a binding.
+    pub fn binding(&self, id: BindingId) -> &Binding<'a> {
+        &self.bindings[id]
+    }
+
+    /// Return the binding of the occurrence of a variable at the given position, if it is bound.
+    pub fn definition(&self, usage: &RawSpan) -> Option<&Binding<'a>> {
+        self.usages.get(usage).map(|id| &self.bindings[*id])
+    }
+
+    /// Return the binding whose name is written at the given index of a file, if any.
+    pub fn binding_at(&self, file_id: FileId, index: ByteIndex) -> Option<&Binding<'a>> {
+        self.bindings
+            .iter()
+            .find(|binding| match binding.ident.pos {
+                TermPos::Original(span) => {
+                    span.src_id == file_id && span.start <= index && index <= span.end
+                }
+                _ => false,
+            })
+    }
+
+    /// Extend the definitions of a field with the definitions of the same field in the records
+    /// it is merged with, as determined by the field accesses which resolve to one of these
+    /// definitions.
+    pub fn merged_definitions(&self, mut definitions: Vec<RawSpan>) -> Vec<RawSpan> {
+        loop {
+            let mut extended = false;
+
+            for (id, record) in &self.accesses {
+                let fields: Vec<RawSpan> = self
+                    .field_definitions(record, id)
+                    .into_iter()
+                    .filter_map(|(field, _)| field.pos.into_opt())
+                    .collect();
+
+                if fields.iter().any(|span| definitions.contains(span)) {
+                    for span in fields {
+                        if !definitions.contains(&span) {
+                            definitions.push(span);
+                            extended = true;
+                        }
+                    }
+                }
+            }
+
+            if !extended {
+                return definitions;
+            }
+        }
+    }
+
+    /// Return the spans of the references to a symbol, given by the spans of its definitions:
+    /// the occurrences of variables bound by one of these definitions, and the accesses to a field
+    /// defined by one of them. The result is sorted and doesn't include the definitions
+    /// themselves.
+    pub fn references(&self, definitions: &[RawSpan]) -> Vec<RawSpan> {
+        let is_definition =
+            |id: &Ident| matches!(id.pos, TermPos::Original(span) if definitions.contains(&span));
+
+        let mut references: Vec<RawSpan> = self
+            .usages
+            .iter()
+            .filter(|(_, binding)| is_definition(self.bindings[**binding].ident))
+            .map(|(span, _)| *span)
+            .collect();
+
+        for (id, record) in &self.accesses {
+            if let TermPos::Original(span) = id.pos {
+                let fields = self.field_definitions(record, id);
+                if fields.iter().any(|(field, _)| is_definition(field)) {
+                    references.push(span);
+                }
+            }
+        }
+
+        references.sort_by_key(|span| (span.src_id, span.start));
+        references.dedup();
+        references
+    }
+
+    /// Return the records that a term may evaluate to, as far as it can be determined without
+    /// evaluation: record literals, merges of records, imported records, and variables and fields
+    /// bound to records.
+    /// The result is empty if the term is not known to evaluate to a record.
+    pub fn records(&self, rt: &'a RichTerm) -> Vec<&'a HashMap<Ident, RichTerm>> {
+        let mut records = Vec::new();
+        self.collect_records(rt, MAX_DEPTH, &mut records);
+        records
+    }
+
+    fn collect_records(
+        &self,
+        rt: &'a RichTerm,
+        depth: usize,
+        records: &mut Vec<&'a HashMap<Ident, RichTerm>>,
+    ) {
+        if depth == 0 {
+            return;
+        }
+
+        match rt.term.as_ref() {
+            Term::Record(map) | Term::RecRecord(map) => records.push(map),
+            Term::MetaValue(meta) => {
+                if let Some(value) = &meta.value {
+                    self.collect_records(value, depth, records);
+                }
+            }
+            Term::Let(_, _, body) => self.collect_records(body, depth, records),
+            Term::Op2(BinaryOp::Merge(), t1, t2) => {
+                self.collect_records(t1, depth, records);
+                self.collect_records(t2, depth, records);
+            }
+            Term::Var(_) => {
+                let value = rt
+                    .pos
+                    .as_opt_ref()
+                    .and_then(|span| self.definition(span))
+                    .and_then(|binding| binding.value);
+
+                if let Some(value) = value {
+                    self.collect_records(value, depth - 1, records);
+                }
+            }
+            Term::Op1(UnaryOp::StaticAccess(id), record) => {
+                for (_, field) in self.field_definitions_(record, id, depth - 1) {
+                    self.collect_records(field, depth - 1, records);
+                }
+            }
+            Term::Import(_) => {
+                let imported = rt.pos.as_opt_ref().and_then(|span| self.imports.get(span));
+
+                if let Some((_, imported)) = imported {
+                    self.collect_records(imported, depth - 1, records);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Return the definitions of the field `id` of the records that a term may evaluate to (see
+    /// [`records`](#method.records)). There may be several of them if the field is defined in
+    /// several merged records. Each definition is given as the name of the field, whose position
+    /// is the one of the definition, together with its value.
+    pub fn field_definitions(
+        &self,
+        rt: &'a RichTerm,
+        id: &Ident,
+    ) -> Vec<(&'a Ident, &'a RichTerm)> {
+        self.field_definitions_(rt, id, MAX_DEPTH)
+    }
+
+    fn field_definitions_(
+        &self,
+        rt: &'a RichTerm,
+        id: &Ident,
+        depth: usize,
+    ) -> Vec<(&'a Ident, &'a RichTerm)> {
+        let mut records = Vec::new();
+        self.collect_records(rt, depth, &mut records);
+        records
+            .into_iter()
+            .filter_map(|record| record.get_key_value(id))
+            .collect()
+    }
+}
+
+/// Return the chain of the nested terms whose position contains the given index, from the root to
+/// the innermost one. Subterms without a position are looked into but are not part of the chain.
+/// If several siblings contain the index, only the first one is followed.
+pub fn terms_at(rt: &RichTerm, index: ByteIndex) -> Vec<&RichTerm> {
+    let mut chain = Vec::new();
+    collect_terms_at(rt, index, &mut chain);
+    chain
+}
+
+fn collect_terms_at<'a>(rt: &'a RichTerm, index: ByteIndex, chain: &mut Vec<&'a RichTerm>) -> bool {
+    match rt.pos.as_opt_ref() {
+        Some(span) if span.start <= index && index <= span.end => chain.push(rt),
+        Some(_) => return false,
+        None => (),
+    }
+
+    let mut found = false;
+    rt.term.for_each_subterm(|sub| {
+        found = found || collect_terms_at(sub, index, chain);
+    });
+
+    found || rt.pos.is_def()
+}
+
+/// Return the local bindings in scope at the given index, from the outermost to the innermost
+/// one, as their name and their bound term, if any. The scope of a let binding extends to the end
+/// of its body, even if the body couldn't be parsed.
+pub fn scope_at(rt: &RichTerm, index: ByteIndex) -> Vec<(&Ident, Option<&RichTerm>)> {
+    let mut scope = Vec::new();
+    collect_scope(rt, index, &mut scope);
+    scope
+}
+
+fn collect_scope<'a>(
+    rt: &'a RichTerm,
+    index: ByteIndex,
+    scope: &mut Vec<(&'a Ident, Option<&'a RichTerm>)>,
+) {
+    match rt.term.as_ref() {
+        Term::Let(id, bound, body) => {
+            if contains(bound, index) {
+                collect_scope(bound, index, scope);
+            } else {
+                scope.push((id, Some(bound)));
+                collect_scope(body, index, scope);
+            }
+        }
+        Term::Fun(id, body) => {
+            scope.push((id, None));
+            collect_scope(body, index, scope);
+        }
+        Term::RecRecord(map) => {
+            scope.extend(map.iter().map(|(id, value)| (id, Some(value))));
+            if let Some(value) = map.values().find(|value| contains(value, index)) {
+                collect_scope(value, index, scope);
+            }
+        }
+        t => {
+            let mut inner = None;
+            t.for_each_subterm(|sub| {
+                if inner.is_none() && contains(sub, index) {
+                    inner = Some(sub);
+                }
+            });
+
+            if let Some(inner) = inner {
+                collect_scope(inner, index, scope);
+            }
+        }
+    }
+}
+
+/// Return `true` if a term contains the given index. A term without a position contains the
+/// index if one of its subterms does.
+fn contains(rt: &RichTerm, index: ByteIndex) -> bool {
+    match rt.pos {
+        TermPos::Original(span) => span.start <= index && index <= span.end,
+        _ => {
+            let mut found = false;
+            rt.term
+                .for_each_subterm(|sub| found = found || contains(sub, index));
+            found
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use codespan::Files;
+
+    fn parse(s: &str) -> RichTerm {
+        let id = Files::new().add("<test>", String::from(s));
+        grammar::TermParser::new()
+            .parse_strict(id, lexer::Lexer::new(s))
+            .unwrap()
+    }
+
+    /// Return the span of the `n`-th occurrence of `pattern` in `source`.
+    fn nth_span(rt: &RichTerm, source: &str, pattern: &str, n: usize) -> RawSpan {
+        let start = source.match_indices(pattern).nth(n).unwrap().0;
+        RawSpan {
+            src_id: rt.pos.unwrap().src_id,
+            start: ByteIndex(start as u32),
+            end: ByteIndex((start + pattern.len()) as u32),
+        }
+    }
+
+    #[test]
+    fn scopes() {
+        let source = "let x = 1 in let f = fun x => x + y in {y = x, z = y} & f x";
+        let rt = parse(source);
+        let (globals, imports) = (HashMap::new(), HashMap::new());
+        let analysis = Analysis::new(&rt, &globals, &imports);
+
+        let kind = |pattern, n| {
+            analysis
+                .definition(&nth_span(&rt, source, pattern, n))
+                .map(|binding| binding.kind)
+        };
+
+        assert_eq!(kind("x", 2), Some(BindingKind::Param));
+        // `y` is not in scope in `f`, as `{y = x, z = y}` is a recursive record.
+        assert_eq!(kind("y", 0), None);
+        assert_eq!(kind("x", 3), Some(BindingKind::Let));
+        assert_eq!(kind("y", 2), Some(BindingKind::Field));
+        assert_eq!(kind("f", 2), Some(BindingKind::Let));
+    }
+
+    #[test]
+    fn scope() {
+        let source = "let x = 1 in {a = fun y => y, b = let z = 2 in z} & {c = x}";
+        let rt = parse(source);
+        let names = |index: usize| -> Vec<String> {
+            scope_at(&rt, ByteIndex(index as u32))
+                .into_iter()
+                .map(|(id, _)| id.label.clone())
+                .collect()
+        };
+
+        let mut in_fun = names(source.find("=> y").unwrap() + 3);
+        in_fun[1..3].sort();
+        assert_eq!(in_fun, vec!["x", "a", "b", "y"]);
+        let mut in_let = names(source.find("in z").unwrap() + 3);
+        in_let[1..3].sort();
+        assert_eq!(in_let, vec!["x", "a", "b", "z"]);
+        assert_eq!(names(source.find("c = x").unwrap() + 4), vec!["x", "c"]);
+        assert_eq!(names(source.find('1').unwrap()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn fields() {
+        let source = "let base = {a = 1} in let r = base & {b = {c = 2}} in r.b.c + r.a";
+        let rt = parse(source);
+        let (globals, imports) = (HashMap::new(), HashMap::new());
+        let analysis = Analysis::new(&rt, &globals, &imports);
+
+        let chain = terms_at(&rt, ByteIndex(source.find("r.a").unwrap() as u32 + 2));
+        let access = chain.last().unwrap();
+        match access.term.as_ref() {
+            Term::Op1(UnaryOp::StaticAccess(id), record) => {
+                assert_eq!(id, &Ident::from("a"));
+                let fields = analysis.field_definitions(record, id);
+                assert_eq!(fields.len(), 1);
+                assert_eq!(
+                    fields[0].0.pos,
+                    TermPos::Original(nth_span(&rt, source, "a", 1))
+                );
+                assert_eq!(fields[0].1.as_ref(), &Term::Num(1.0));
+            }
+            t => panic!("expected a field access, got {:?}", t),
+        }
+
+        let chain = terms_at(&rt, ByteIndex(source.find("r.b.c").unwrap() as u32 + 4));
+        match chain.last().unwrap().term.as_ref() {
+            Term::Op1(UnaryOp::StaticAccess(id), record) => {
+                assert_eq!(
+                    analysis.field_definitions(record, id)[0].1.as_ref(),
+                    &Term::Num(2.0)
+                );
+            }
+            t => panic!("expected a field access, got {:?}", t),
+        }
+    }
+}
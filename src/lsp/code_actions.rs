@@ -0,0 +1,567 @@
+//! Code actions: quick fixes for the errors of a document, and the conversion of a contract
+//! annotation to a type annotation.
+//!
+//! - A record missing a field required by its type gets the field, with a value of the expected
+//!   type.
+//! - An unbound identifier is qualified with a module of the standard library defining a field of
+//!   this name, or bound to the import of a file of the same name, found in the directory of the
+//!   document or in its subdirectories.
+//! - The type annotation `:` of a dynamically typed value is converted to a contract annotation
+//!   `|`, as suggested by the typechecker. Conversely, a contract annotation is converted to a type
+//!   annotation if the annotated value typechecks against it.
+use super::analysis::{scope_at, terms_at};
+use crate::cache::ImportResolver;
+use crate::error::{Error, TypecheckError};
+use crate::identifier::Ident;
+use crate::position::{RawSpan, TermPos};
+use crate::term::{AnnotKind, Contract, MetaValue, RichTerm, Term};
+use crate::typecheck::{self, type_check_in_env, TypeWrapper};
+use crate::types::{AbsType, Types};
+use crate::vfs::FileSystem;
+use codespan::{ByteIndex, ByteOffset, FileId};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+/// The extensions of the files which can be imported.
+const IMPORTABLE: [&str; 5] = ["ncl", "json", "yaml", "yml", "toml"];
+
+/// The kind of a code action.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ActionKind {
+    /// A fix of an error.
+    QuickFix,
+    /// A rewrite which doesn't change the meaning of a correct program.
+    Rewrite,
+}
+
+impl ActionKind {
+    /// The kind in the language server protocol.
+    pub fn name(self) -> &'static str {
+        match self {
+            ActionKind::QuickFix => "quickfix",
+            ActionKind::Rewrite => "refactor.rewrite",
+        }
+    }
+}
+
+/// A replacement of a part of the document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub span: RawSpan,
+    pub text: String,
+}
+
+/// A code action.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeAction {
+    pub title: String,
+    pub kind: ActionKind,
+    pub edits: Vec<Edit>,
+    /// The index of the fixed error, for quick fixes.
+    pub error: Option<usize>,
+}
+
+/// The document for which code actions are computed.
+pub struct Context<'a> {
+    pub term: &'a RichTerm,
+    pub source: &'a str,
+    pub file_id: FileId,
+    /// The fields of the standard library.
+    pub globals: &'a HashMap<Ident, RichTerm>,
+    /// The global typing environment.
+    pub type_env: &'a typecheck::Environment,
+    pub resolver: &'a dyn ImportResolver,
+    /// The filesystem and the path of the document, to look for files to import.
+    pub files: Option<(&'a dyn FileSystem, &'a Path)>,
+}
+
+/// Return the code actions available for the part of the document between `start` and `end`:
+/// the fixes of the errors located there, and the conversion of an annotation.
+pub fn code_actions(
+    ctx: &Context,
+    errors: &[Error],
+    start: ByteIndex,
+    end: ByteIndex,
+) -> Vec<CodeAction> {
+    let mut actions = Vec::new();
+
+    for (index, error) in errors.iter().enumerate() {
+        let err = match error {
+            Error::TypecheckError(err) => err,
+            _ => continue,
+        };
+        let span = match error_pos(err) {
+            TermPos::Original(span) | TermPos::Inherited(span) => span,
+            TermPos::None => continue,
+        };
+        if span.src_id != ctx.file_id || span.end < start || end < span.start {
+            continue;
+        }
+
+        let fixes = match innermost(err) {
+            TypecheckError::MissingRow(id, expected, _, TermPos::Original(span)) => {
+                missing_field(ctx, id, expected, *span)
+            }
+            TypecheckError::UnboundIdentifier(id, _) => {
+                let mut fixes = qualify(ctx, id, span);
+                fixes.extend(import(ctx, id));
+                fixes
+            }
+            err if err.suggests_contract() => to_contract(ctx, span).into_iter().collect(),
+            _ => Vec::new(),
+        };
+
+        actions.extend(fixes.into_iter().map(|(title, edit)| CodeAction {
+            title,
+            kind: ActionKind::QuickFix,
+            edits: vec![edit],
+            error: Some(index),
+        }));
+    }
+
+    if let Some((title, edit)) = to_type(ctx, start) {
+        actions.push(CodeAction {
+            title,
+            kind: ActionKind::Rewrite,
+            edits: vec![edit],
+            error: None,
+        });
+    }
+
+    actions
+}
+
+/// Return the position of a typechecking error.
+fn error_pos(err: &TypecheckError) -> TermPos {
+    match err {
+        TypecheckError::UnboundIdentifier(_, pos)
+        | TypecheckError::MissingRow(.., pos)
+        | TypecheckError::MissingDynTail(.., pos)
+        | TypecheckError::ExtraRow(.., pos)
+        | TypecheckError::ExtraDynTail(.., pos)
+        | TypecheckError::UnboundTypeVariable(_, pos)
+        | TypecheckError::TypeMismatch(.., pos)
+        | TypecheckError::RowKindMismatch(.., pos)
+        | TypecheckError::RowMismatch(.., pos)
+        | TypecheckError::RowConflict(.., pos)
+        | TypecheckError::ArrowTypeMismatch(.., pos) => *pos,
+        TypecheckError::IllformedType(_) => TermPos::None,
+    }
+}
+
+/// Return the error underlying a mismatch of the field of a record type.
+fn innermost(err: &TypecheckError) -> &TypecheckError {
+    match err {
+        TypecheckError::RowMismatch(_, _, _, err, _) => innermost(err),
+        err => err,
+    }
+}
+
+/// Add a missing field to a record literal, with a value of the type expected for the field.
+fn missing_field(
+    ctx: &Context,
+    id: &Ident,
+    expected: &Types,
+    span: RawSpan,
+) -> Vec<(String, Edit)> {
+    let is_record = terms_at(ctx.term, span.start).into_iter().any(|rt| {
+        rt.pos == TermPos::Original(span)
+            && matches!(rt.term.as_ref(), Term::Record(_) | Term::RecRecord(_))
+    });
+    let field_type = match &expected.0 {
+        AbsType::StaticRecord(row) => row_type(row, id),
+        _ => None,
+    };
+    let close = span.end.to_usize().saturating_sub(1);
+    if !is_record || ctx.source.as_bytes().get(close) != Some(&b'}') {
+        return Vec::new();
+    }
+
+    let before = ctx.source[span.start.to_usize()..close].trim_end();
+    let at = span.start + ByteOffset(before.len() as i64);
+    let field = format!(
+        "{} = {}",
+        id,
+        field_type
+            .map(skeleton)
+            .unwrap_or_else(|| String::from("null"))
+    );
+    let text = if before.ends_with('{') {
+        field
+    } else if before.ends_with(',') {
+        format!(" {}", field)
+    } else {
+        format!(", {}", field)
+    };
+
+    vec![(
+        format!("Add the missing field `{}`", id),
+        Edit {
+            span: RawSpan {
+                src_id: span.src_id,
+                start: at,
+                end: at,
+            },
+            text,
+        },
+    )]
+}
+
+/// Return the type of a field in a row.
+fn row_type<'a>(row: &'a Types, id: &Ident) -> Option<&'a Types> {
+    match &row.0 {
+        AbsType::RowExtend(field, ty, _) if field == id => ty.as_deref(),
+        AbsType::RowExtend(_, _, tail) => row_type(tail, id),
+        _ => None,
+    }
+}
+
+/// Return the source of a simple value of a type.
+fn skeleton(ty: &Types) -> String {
+    match &ty.0 {
+        AbsType::Num() => String::from("0"),
+        AbsType::Bool() => String::from("false"),
+        AbsType::Str() => String::from("\"\""),
+        AbsType::List(_) => String::from("[]"),
+        AbsType::DynRecord(_) => String::from("{}"),
+        AbsType::StaticRecord(row) => {
+            let mut fields = Vec::new();
+            let mut row = row.as_ref();
+            while let AbsType::RowExtend(id, field, tail) = &row.0 {
+                let value = field
+                    .as_deref()
+                    .map(skeleton)
+                    .unwrap_or_else(|| String::from("null"));
+                fields.push(format!("{} = {}", id, value));
+                row = tail;
+            }
+            format!("{{{}}}", fields.join(", "))
+        }
+        AbsType::Enum(row) => match &row.0 {
+            AbsType::RowExtend(id, ..) => format!("`{}", id),
+            _ => String::from("null"),
+        },
+        AbsType::Arrow(_, codomain) => format!("fun _ => {}", skeleton(codomain)),
+        AbsType::Forall(_, ty) => skeleton(ty),
+        _ => String::from("null"),
+    }
+}
+
+/// Qualify an unbound identifier with the modules of the standard library which define it.
+fn qualify(ctx: &Context, id: &Ident, span: RawSpan) -> Vec<(String, Edit)> {
+    let mut modules: Vec<&Ident> = ctx
+        .globals
+        .iter()
+        .filter(|(_, value)| record_fields(value).is_some_and(|fields| fields.contains_key(id)))
+        .map(|(module, _)| module)
+        .collect();
+    modules.sort();
+
+    modules
+        .into_iter()
+        .map(|module| {
+            let text = format!("{}.{}", module, id);
+            (format!("Replace with `{}`", text), Edit { span, text })
+        })
+        .collect()
+}
+
+/// Return the fields of a record, possibly with metadata.
+fn record_fields(rt: &RichTerm) -> Option<&HashMap<Ident, RichTerm>> {
+    match rt.as_ref() {
+        Term::Record(fields) | Term::RecRecord(fields) => Some(fields),
+        Term::MetaValue(meta) => meta.value.as_ref().and_then(record_fields),
+        _ => None,
+    }
+}
+
+/// Bind an unbound identifier to the import of the files of the same name.
+fn import(ctx: &Context, id: &Ident) -> Vec<(String, Edit)> {
+    let (fs, path) = match ctx.files {
+        Some(files) => files,
+        None => return Vec::new(),
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    fs.list_files(dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|file| {
+            file.as_path() != path
+                && file
+                    .file_stem()
+                    .is_some_and(|stem| stem == id.label.as_str())
+                && file
+                    .extension()
+                    .is_some_and(|ext| IMPORTABLE.iter().any(|importable| ext == *importable))
+        })
+        .filter_map(|file| {
+            let relative = file.strip_prefix(dir).ok()?;
+            let components: Vec<String> = relative
+                .components()
+                .map(|c| match c {
+                    Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?;
+            let relative = components.join("/");
+
+            Some((
+                format!("Import `{}` as `{}`", relative, id),
+                Edit {
+                    span: RawSpan {
+                        src_id: ctx.file_id,
+                        start: ByteIndex(0),
+                        end: ByteIndex(0),
+                    },
+                    text: format!("let {} = import \"{}\" in\n", id, relative),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Convert the type annotation enclosing a dynamically typed value to a contract annotation.
+fn to_contract(ctx: &Context, span: RawSpan) -> Option<(String, Edit)> {
+    let annotation = terms_at(ctx.term, span.start)
+        .into_iter()
+        .rev()
+        .find_map(|rt| match rt.as_ref() {
+            Term::MetaValue(MetaValue {
+                types: Some(ctr), ..
+            }) => Some(ctr),
+            _ => None,
+        })?;
+    let separator = separator(ctx, annotation, ':')?;
+
+    Some((
+        String::from("Check with a contract annotation `|` instead"),
+        Edit {
+            span: separator,
+            text: String::from("|"),
+        },
+    ))
+}
+
+/// Convert the contract annotation at the given index to a type annotation, if it is the first
+/// annotation of the value, and if the value typechecks against it.
+fn to_type(ctx: &Context, index: ByteIndex) -> Option<(String, Edit)> {
+    let meta = annotation_at(ctx.term, index)?;
+    let (first, rest) = meta.contracts.split_first()?;
+    let value = meta.value.as_ref()?;
+    let separator = separator(ctx, first, '|')?;
+
+    let converted = MetaValue {
+        types: Some(Contract {
+            kind: AnnotKind::Type,
+            ..first.clone()
+        }),
+        contracts: rest.to_vec(),
+        ..meta.clone()
+    };
+    // The variables of the document in scope are assumed to be dynamically typed.
+    let mut env = ctx.type_env.clone();
+    let value_start = value.pos.as_opt_ref().map_or(index, |span| span.start);
+    for (id, _) in scope_at(ctx.term, value_start) {
+        env.insert(id.clone(), TypeWrapper::Concrete(AbsType::Dyn()));
+    }
+    type_check_in_env(
+        &RichTerm::from(Term::MetaValue(converted)),
+        &env,
+        ctx.resolver,
+    )
+    .ok()?;
+
+    Some((
+        String::from("Check with a type annotation `:` instead"),
+        Edit {
+            span: separator,
+            text: String::from(":"),
+        },
+    ))
+}
+
+/// Return the metavalue without type annotation whose first contract annotation contains the
+/// given index.
+fn annotation_at(rt: &RichTerm, index: ByteIndex) -> Option<&MetaValue> {
+    if let Term::MetaValue(meta) = rt.as_ref() {
+        let span = &meta.contracts.first()?.label.span;
+        if meta.types.is_none()
+            && meta.contracts[0].kind == AnnotKind::Contract
+            && span.start <= index
+            && index <= span.end
+        {
+            return Some(meta);
+        }
+    }
+
+    let mut found = None;
+    rt.term.for_each_subterm(|sub| {
+        if found.is_none() {
+            found = annotation_at(sub, index);
+        }
+    });
+    found
+}
+
+/// Return the span of the separator of an annotation, which is the first character before its
+/// type which is not a whitespace.
+fn separator(ctx: &Context, annotation: &Contract, expected: char) -> Option<RawSpan> {
+    let span = annotation.label.span;
+    if span.src_id != ctx.file_id {
+        return None;
+    }
+
+    let before = ctx.source[..span.start.to_usize()].trim_end();
+    if !before.ends_with(expected) {
+        return None;
+    }
+
+    let start = ByteIndex((before.len() - expected.len_utf8()) as u32);
+    Some(RawSpan {
+        src_id: span.src_id,
+        start,
+        end: ByteIndex(before.len() as u32),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use crate::typecheck::type_check_vars;
+    use crate::vfs::VirtualFileSystem;
+    use codespan::Files;
+
+    /// Apply the edits of an action to a source.
+    fn apply(source: &str, action: &CodeAction) -> String {
+        let mut result = String::from(source);
+        let mut edits = action.edits.clone();
+        edits.sort_by_key(|edit| std::cmp::Reverse(edit.span.start));
+        for edit in edits {
+            result.replace_range(
+                edit.span.start.to_usize()..edit.span.end.to_usize(),
+                &edit.text,
+            );
+        }
+        result
+    }
+
+    /// Return the titles of the code actions at the given offset of `source`, and the source
+    /// after the application of each of them. The document is `/config/main.ncl`, and the
+    /// directory contains a `lib.ncl` file. `strings` is bound to a record with a `length` field.
+    fn actions_at(source: &str, index: usize) -> Vec<(String, String)> {
+        let mut files = Files::new();
+        let file_id = files.add("/config/main.ncl", String::from(source));
+        let stdlib = "{length = fun s => 0}";
+        let stdlib_id = files.add("<stdlib>", String::from(stdlib));
+        let parse = |file_id, source| {
+            grammar::TermParser::new()
+                .parse_strict(file_id, lexer::Lexer::new(source))
+                .unwrap()
+        };
+
+        let rt = parse(file_id, source);
+        let mut globals = HashMap::new();
+        globals.insert(Ident::from("strings"), parse(stdlib_id, stdlib));
+        let mut type_env = typecheck::Environment::new();
+        type_env.insert(
+            Ident::from("strings"),
+            TypeWrapper::Concrete(AbsType::Dyn()),
+        );
+
+        let cache = crate::cache::Cache::new();
+        let (result, _) = type_check_vars(&rt, &type_env, &cache);
+        let errors: Vec<Error> = result.err().into_iter().map(Error::from).collect();
+
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/config/lib.ncl", "{}");
+        fs.add_file("/config/main.ncl", source);
+        let ctx = Context {
+            term: &rt,
+            source,
+            file_id,
+            globals: &globals,
+            type_env: &type_env,
+            resolver: &cache,
+            files: Some((&fs, Path::new("/config/main.ncl"))),
+        };
+
+        let index = ByteIndex(index as u32);
+        code_actions(&ctx, &errors, index, index)
+            .into_iter()
+            .map(|action| {
+                let result = apply(source, &action);
+                (action.title, result)
+            })
+            .collect()
+    }
+
+    fn action(title: &str, result: &str) -> (String, String) {
+        (String::from(title), String::from(result))
+    }
+
+    #[test]
+    fn missing_fields() {
+        assert_eq!(
+            actions_at("({a = 1} : {a : Num, b : {c : Str, d : List Num}})", 2),
+            vec![action(
+                "Add the missing field `b`",
+                "({a = 1, b = {c = \"\", d = []}} : {a : Num, b : {c : Str, d : List Num}})"
+            )]
+        );
+        assert_eq!(
+            actions_at("({ } : {e : <foo, bar>})", 1),
+            vec![action(
+                "Add the missing field `e`",
+                "({e = `foo } : {e : <foo, bar>})"
+            )]
+        );
+    }
+
+    #[test]
+    fn unbound_identifiers() {
+        assert_eq!(
+            actions_at("(length \"a\" : Num)", 1),
+            vec![action(
+                "Replace with `strings.length`",
+                "(strings.length \"a\" : Num)"
+            )]
+        );
+        assert_eq!(
+            actions_at("(lib : Dyn)", 1),
+            vec![action(
+                "Import `lib.ncl` as `lib`",
+                "let lib = import \"lib.ncl\" in\n(lib : Dyn)"
+            )]
+        );
+        assert!(actions_at("(main : Dyn)", 1).is_empty());
+    }
+
+    #[test]
+    fn annotations() {
+        let source = "let f = fun x => x in (f 1 : Num)";
+        assert_eq!(
+            actions_at(source, source.rfind('f').unwrap()),
+            vec![action(
+                "Check with a contract annotation `|` instead",
+                "let f = fun x => x in (f 1 | Num)"
+            )]
+        );
+
+        let source = "let x = 1 in {a | Num = 1, b | Num = x, c | Str | Dyn = \"c\"}";
+        assert_eq!(
+            actions_at(source, source.find("Num").unwrap()),
+            vec![action(
+                "Check with a type annotation `:` instead",
+                "let x = 1 in {a : Num = 1, b | Num = x, c | Str | Dyn = \"c\"}"
+            )]
+        );
+        // The bound variable is assumed to be dynamically typed.
+        assert!(actions_at(source, source.rfind("Num").unwrap()).is_empty());
+        assert_eq!(
+            actions_at(source, source.find("Str").unwrap())[0].1,
+            "let x = 1 in {a | Num = 1, b | Num = x, c : Str | Dyn = \"c\"}"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Definition lookup: the places where the symbol under the cursor is defined.
+//!
+//! - A variable is defined by the binding which introduces it: a let binding, a function
+//!   parameter or a field of a recursive record. Fields of the standard library don't have a
+//!   definition in a file.
+//! - A field of a field access is defined by the fields of the records that the accessed term may
+//!   evaluate to, possibly in an imported file. There are several definitions if the field is
+//!   defined in several merged records.
+//! - An import is defined by the imported file.
+use super::analysis::{terms_at, Analysis, BindingKind, Imports};
+use crate::position::RawSpan;
+use crate::term::{RichTerm, Term, UnaryOp};
+use codespan::ByteIndex;
+
+/// Return the span of the symbol at the given index, together with the spans of its definitions,
+/// or `None` if there is no symbol at this index. The definition of an import is the empty span at
+/// the start of the imported file.
+pub fn definition<'a>(
+    analysis: &Analysis<'a>,
+    term: &'a RichTerm,
+    imports: &Imports,
+    index: ByteIndex,
+) -> Option<(RawSpan, Vec<RawSpan>)> {
+    let innermost = *terms_at(term, index).last()?;
+    let span = innermost.pos.into_opt()?;
+
+    let definitions = match innermost.term.as_ref() {
+        Term::Var(_) => analysis
+            .definition(&span)
+            .filter(|binding| binding.kind != BindingKind::Global)
+            .and_then(|binding| binding.ident.pos.into_opt())
+            .into_iter()
+            .collect(),
+        Term::Op1(UnaryOp::StaticAccess(id), record) => {
+            let definitions = analysis
+                .field_definitions(record, id)
+                .into_iter()
+                .filter_map(|(field, _)| field.pos.into_opt())
+                .collect();
+            return Some((id.pos.into_opt().unwrap_or(span), definitions));
+        }
+        Term::Import(_) => imports
+            .get(&span)
+            .map(|(file_id, _)| RawSpan {
+                src_id: *file_id,
+                start: ByteIndex(0),
+                end: ByteIndex(0),
+            })
+            .into_iter()
+            .collect(),
+        _ => return None,
+    };
+
+    Some((span, definitions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use codespan::Files;
+    use std::collections::HashMap;
+
+    /// Return the byte offsets of the definitions of the symbol at the given offset of `source`.
+    fn definitions_at(source: &str, index: usize) -> Vec<usize> {
+        let file_id = Files::new().add("<test>", String::from(source));
+        let rt = grammar::TermParser::new()
+            .parse_strict(file_id, lexer::Lexer::new(source))
+            .unwrap();
+        let (globals, imports) = (HashMap::new(), HashMap::new());
+        let analysis = Analysis::new(&rt, &globals, &imports);
+
+        definition(&analysis, &rt, &imports, ByteIndex(index as u32))
+            .map(|(_, spans)| spans.iter().map(|span| span.start.to_usize()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn variables() {
+        let source = "let x = 1 in let f = fun x => x + 1 in f x";
+        let positions: Vec<usize> = source.match_indices('x').map(|(i, _)| i).collect();
+
+        assert_eq!(definitions_at(source, positions[3]), vec![positions[0]]);
+        // The parameter of `f` shadows the outer `x`.
+        assert_eq!(definitions_at(source, positions[2]), vec![positions[1]]);
+        assert_eq!(
+            definitions_at(source, source.rfind('f').unwrap()),
+            vec![source.find('f').unwrap()]
+        );
+        assert!(definitions_at("lists.length []", 0).is_empty());
+    }
+
+    #[test]
+    fn merged_fields() {
+        let source = "let r = {a = 1, b = 2} & {a | Num, c = 3} in r.a";
+        let mut definitions = definitions_at(source, source.len() - 1);
+        definitions.sort_unstable();
+
+        let expected: Vec<usize> = source.match_indices('a').map(|(i, _)| i).take(2).collect();
+        assert_eq!(definitions, expected);
+    }
+}
@@ -0,0 +1,138 @@
+//! Hover information: the type, the contracts and the documentation of the symbol under the
+//! cursor, which is either a variable or the field of a field access.
+use super::analysis::{terms_at, Analysis};
+use crate::identifier::Ident;
+use crate::position::RawSpan;
+use crate::repl::query_print::{metadata_markdown, Attributes};
+use crate::term::{RichTerm, Term, UnaryOp};
+use crate::types::{AbsType, Types};
+use codespan::ByteIndex;
+use std::collections::HashMap;
+
+/// Return the span of the symbol at the given index, together with the Markdown description of
+/// the symbol, or `None` if there is no symbol at this index.
+///
+/// `types` are the types of the occurrences of variables inferred by the typechecker (see
+/// [`type_check_vars`](../../typecheck/fn.type_check_vars.html)).
+pub fn hover<'a>(
+    analysis: &Analysis<'a>,
+    term: &'a RichTerm,
+    types: &HashMap<RawSpan, Types>,
+    index: ByteIndex,
+) -> Option<(RawSpan, String)> {
+    let innermost = *terms_at(term, index).last()?;
+    let span = innermost.pos.into_opt()?;
+
+    match innermost.term.as_ref() {
+        Term::Var(id) => {
+            let value = analysis.definition(&span).and_then(|binding| binding.value);
+            Some((span, describe(id, types.get(&span), value)))
+        }
+        Term::Op1(UnaryOp::StaticAccess(id), record) => {
+            let value = analysis
+                .field_definitions(record, id)
+                .first()
+                .map(|(_, value)| *value);
+            let span = id.pos.into_opt().unwrap_or(span);
+            Some((span, describe(id, None, value)))
+        }
+        _ => None,
+    }
+}
+
+/// Describe a symbol in Markdown, given its inferred type and the term it is bound to, if known.
+///
+/// The type shown is the type annotation of the bound term if there is one, or the inferred type
+/// otherwise. The contracts, the default value and the documentation of the bound term follow.
+fn describe(id: &Ident, inferred: Option<&Types>, value: Option<&RichTerm>) -> String {
+    let annotated = value.and_then(annotated_type);
+    // A free type variable means that nothing is known about the type.
+    let informative = inferred.filter(|ty| !matches!(ty.0, AbsType::Dyn() | AbsType::Var(_)));
+
+    let mut result = match annotated.as_ref().or(informative) {
+        Some(ty) => format!("```nickel\n{} : {}\n```\n", id, ty),
+        None => format!("```nickel\n{}\n```\n", id),
+    };
+
+    if let Some(Term::MetaValue(meta)) = value.map(RichTerm::as_ref) {
+        let attrs = Attributes {
+            doc: true,
+            types: false,
+            contract: true,
+            default: true,
+            value: false,
+        };
+        let metadata = metadata_markdown(meta, attrs);
+
+        if !metadata.is_empty() {
+            result.push('\n');
+            result.push_str(&metadata);
+        }
+    }
+
+    result
+}
+
+/// Return the type annotation of a term, if it has one.
+pub fn annotated_type(rt: &RichTerm) -> Option<Types> {
+    match rt.as_ref() {
+        Term::MetaValue(meta) => meta.types.as_ref().map(|ctr| ctr.label.types.clone()),
+        Term::Promise(ty, _, _) => Some(ty.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use crate::typecheck::{type_check_vars, Environment};
+    use codespan::Files;
+
+    /// Return the hover contents at the given index of `source`.
+    fn hover_at(source: &str, index: usize) -> Option<String> {
+        let id = Files::new().add("<test>", String::from(source));
+        let rt = grammar::TermParser::new()
+            .parse_strict(id, lexer::Lexer::new(source))
+            .unwrap();
+        let (_, types) = type_check_vars(&rt, &Environment::new(), &crate::cache::Cache::new());
+        let types = types.into_iter().collect();
+        let (globals, imports) = (HashMap::new(), HashMap::new());
+        let analysis = Analysis::new(&rt, &globals, &imports);
+
+        hover(&analysis, &rt, &types, ByteIndex(index as u32)).map(|(_, contents)| contents)
+    }
+
+    #[test]
+    fn variables() {
+        let source = "let f : Num -> Num = fun x => x + 1 in f 2";
+        assert_eq!(
+            hover_at(source, source.rfind('f').unwrap()).unwrap(),
+            "```nickel\nf : Num -> Num\n```\n"
+        );
+
+        let source = "(let s = \"a\" in s ++ \"b\" : Str)";
+        assert_eq!(
+            hover_at(source, source.rfind('s').unwrap()).unwrap(),
+            "```nickel\ns : Str\n```\n"
+        );
+
+        let source = "let x = 1 in x";
+        assert_eq!(hover_at(source, source.find('x').unwrap()), None);
+        assert_eq!(
+            hover_at(source, source.rfind('x').unwrap()).unwrap(),
+            "```nickel\nx\n```\n"
+        );
+    }
+
+    #[test]
+    fn fields() {
+        let source = "let r = {port | Num | doc \"The port\" | default = 80} in r.port";
+        let contents = hover_at(source, source.rfind("port").unwrap()).unwrap();
+
+        assert!(contents.starts_with("```nickel\nport\n```\n"));
+        assert!(contents.contains("* **contract**: `Num`"));
+        assert!(contents.contains("* **default**: `80`"));
+        assert!(contents.contains("\nThe port\n"));
+    }
+}
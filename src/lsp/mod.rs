@@ -0,0 +1,1601 @@
+//! A language server for Nickel, implementing a subset of the [language server
+//! protocol](https://microsoft.github.io/language-server-protocol/) over the standard input and
+//! output. It is started by `nickel lsp`.
+//!
+//! The server keeps the content of the documents opened by the editor, which is sent in full on
+//! each change. Each version of a document is parsed, recovering from syntax errors, and
+//! typechecked, recording the types of variables. The files it imports are loaded transitively,
+//! from the opened documents or from the disk. The requests are then answered from these parsed
+//! terms and from a static [analysis](analysis/index.html) of the bindings of the document.
+//!
+//! The syntax errors, the imports which can't be loaded, the type errors and the
+//! [warnings](../warning/index.html) of a document are published as diagnostics. The imports are recorded in the [cache](../cache/struct.Cache.html),
+//! so that a change only checks again the modified document and the opened documents importing
+//! it, while the files imported from the disk are parsed once.
+//!
+//! Supported requests:
+//!
+//! - `textDocument/hover`: see [`hover`](hover/index.html).
+//! - `textDocument/definition`: see [`definition`](definition/index.html).
+//! - `textDocument/references`: see [`references`](references/index.html).
+//! - `textDocument/prepareRename`, `textDocument/rename`: see [`rename`](rename/index.html).
+//! - `textDocument/completion`: see [`completion`](../completion/index.html).
+//! - `textDocument/documentSymbol`: the outline of the document, see
+//!   [`symbols`](symbols/index.html).
+//! - `textDocument/codeAction`: quick fixes and conversions of annotations, see
+//!   [`code_actions`](code_actions/index.html).
+//! - `textDocument/semanticTokens/full`: the classification of the identifiers of the document,
+//!   see [`semantic_tokens`](semantic_tokens/index.html).
+//! - `workspace/symbol`: the symbols of the opened documents and of the Nickel files of the
+//!   workspace directory whose name fuzzily matches a query.
+//!
+//! If the workspace belongs to a package declared by a [manifest](../manifest/index.html), the
+//! imports are resolved as in the package, and the workspace symbols are the ones of the files of
+//! the package.
+//!
+//! References are also available on the command line with `nickel refs`, see
+//! [`file_references`](fn.file_references.html).
+use crate::cache::{Cache, CacheOp, InputFormat};
+use crate::completion::{self, CompletionKind};
+use crate::error::diagnostic::{Diagnostic, Severity};
+use crate::error::{Error, IOError, ImportError, ParseError};
+use crate::fetch::Store;
+use crate::identifier::Ident;
+use crate::manifest::Manifest;
+use crate::parser::{grammar, lexer::Lexer, ExtendedTerm};
+use crate::position::RawSpan;
+use crate::term::{make as mk_term, RichTerm, Term};
+use crate::typecheck::{self, type_check_vars, Envs};
+use crate::types::Types;
+use crate::warning::{self, Level};
+use codespan::{ByteIndex, ByteOffset, FileId, Files};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+pub mod analysis;
+pub mod code_actions;
+pub mod definition;
+pub mod hover;
+pub mod references;
+pub mod rename;
+pub mod semantic_tokens;
+pub mod symbols;
+pub mod transport;
+
+use analysis::{Analysis, BindingKind, Imports};
+use references::Referenced;
+use rename::RenameError;
+use symbols::Symbol;
+
+/// The error code of the JSON-RPC protocol for unknown methods.
+const METHOD_NOT_FOUND: i64 = -32601;
+/// The error code of the JSON-RPC protocol for invalid parameters.
+const INVALID_PARAMS: i64 = -32602;
+/// The error code of the language server protocol for requests received after a shutdown.
+const INVALID_REQUEST: i64 = -32600;
+/// The error code of the language server protocol for valid requests which could not be answered.
+const REQUEST_FAILED: i64 = -32803;
+
+/// An opened document.
+struct Document {
+    file_id: FileId,
+    /// The parsed content of the document. Parts which could not be parsed are replaced with
+    /// [`Term::ParseError`](../term/enum.Term.html) nodes.
+    term: RichTerm,
+    /// The syntax errors of the document.
+    parse_errors: Vec<ParseError>,
+    /// The types of the occurrences of variables, inferred by the typechecker.
+    types: HashMap<RawSpan, Types>,
+    /// The files imported by the document, directly or transitively.
+    imports: Imports,
+    /// The errors of the document, together with their diagnostics in the protocol.
+    errors: Vec<(Error, Vec<Value>)>,
+}
+
+/// The state of the language server.
+pub struct Server {
+    cache: Cache,
+    /// The fields of the standard library, which are bound in the global environment.
+    globals: HashMap<Ident, RichTerm>,
+    /// The global typing environment.
+    type_env: typecheck::Environment,
+    /// The opened documents, indexed by URI.
+    documents: HashMap<String, Document>,
+    /// The root directory of the workspace, if the client opened one.
+    root: Option<PathBuf>,
+    /// The manifest of the package of the workspace, if any.
+    manifest: Option<Manifest>,
+    /// The symbols of the files of the workspace which are not opened. A modified file gets a new
+    /// id in the cache, so that its entry is computed again.
+    workspace_symbols: HashMap<FileId, Vec<Symbol>>,
+    /// Set once the client has requested a shutdown.
+    shutdown: bool,
+    /// Set once the client has sent the `exit` notification.
+    exited: bool,
+}
+
+impl Server {
+    /// Create a server, loading the standard library.
+    pub fn new() -> Result<Self, Error> {
+        let mut cache = Cache::new();
+        cache.load_stdlib()?;
+        let eval_env = cache
+            .mk_global_env()
+            .expect("lsp::Server::new(): expected the standard library to be loaded");
+
+        let globals = eval_env
+            .iter()
+            .map(|(id, thunk)| (id.clone(), thunk.borrow().body.clone()))
+            .collect();
+
+        Ok(Server {
+            cache,
+            globals,
+            type_env: Envs::mk_global(&eval_env),
+            documents: HashMap::new(),
+            root: None,
+            manifest: None,
+            workspace_symbols: HashMap::new(),
+            shutdown: false,
+            exited: false,
+        })
+    }
+
+    /// Return `true` once the client has sent the `exit` notification.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Handle a message of the client, and return the messages to send back.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        let result = match message.get("id") {
+            // A request, which expects a response.
+            Some(_) if self.shutdown => Err((INVALID_REQUEST, String::from("shutdown requested"))),
+            Some(_) => self.request(method, params),
+            // A notification.
+            None => return self.notification(method, params),
+        };
+
+        let response = match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": message["id"], "result": result}),
+            Err((code, msg)) => json!({
+                "jsonrpc": "2.0",
+                "id": message["id"],
+                "error": {"code": code, "message": msg},
+            }),
+        };
+
+        vec![response]
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => {
+                let root = params["rootUri"]
+                    .as_str()
+                    .and_then(uri_to_path)
+                    .or_else(|| params["rootPath"].as_str().map(PathBuf::from));
+                if let Some(root) = root {
+                    // An invalid manifest can't be reported before a document is opened, and is
+                    // ignored.
+                    let _ = self.set_root(root);
+                }
+
+                Ok(json!({
+                    "capabilities": {
+                        // Documents are synchronized by sending their full content.
+                        "textDocumentSync": 1,
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "renameProvider": {"prepareProvider": true},
+                        "completionProvider": {"triggerCharacters": [".", "`", "\"", "/"]},
+                        "documentSymbolProvider": true,
+                        "workspaceSymbolProvider": true,
+                        "codeActionProvider": {
+                            "codeActionKinds": [
+                                code_actions::ActionKind::QuickFix.name(),
+                                code_actions::ActionKind::Rewrite.name(),
+                            ],
+                        },
+                        "semanticTokensProvider": {
+                            "legend": {
+                                "tokenTypes": semantic_tokens::TokenType::LEGEND
+                                    .iter()
+                                    .map(|ty| ty.name())
+                                    .collect::<Vec<_>>(),
+                                "tokenModifiers": semantic_tokens::MODIFIERS,
+                            },
+                            "full": true,
+                        },
+                    },
+                    "serverInfo": {"name": "nickel", "version": env!("CARGO_PKG_VERSION")},
+                }))
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => {
+                let (document, index) = self.document_position(params)?;
+                let analysis = Analysis::new(&document.term, &self.globals, &document.imports);
+                let result = hover::hover(&analysis, &document.term, &document.types, index).map(
+                    |(span, contents)| {
+                        json!({
+                            "contents": {"kind": "markdown", "value": contents},
+                            "range": to_range(self.cache.files(), span),
+                        })
+                    },
+                );
+
+                Ok(result.unwrap_or(Value::Null))
+            }
+            "textDocument/definition" => {
+                let (document, index) = self.document_position(params)?;
+                let analysis = Analysis::new(&document.term, &self.globals, &document.imports);
+                let result =
+                    definition::definition(&analysis, &document.term, &document.imports, index)
+                        .map(|(_, spans)| {
+                            let locations: Vec<Value> =
+                                spans.into_iter().map(|span| self.location(span)).collect();
+                            Value::from(locations)
+                        });
+
+                Ok(result.unwrap_or(Value::Null))
+            }
+            "textDocument/references" => {
+                let (document, index) = self.document_position(params)?;
+                let include_declaration = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or_default();
+                let result = self
+                    .references(document, index, include_declaration)
+                    .map(|spans| {
+                        let locations: Vec<Value> =
+                            spans.into_iter().map(|span| self.location(span)).collect();
+                        Value::from(locations)
+                    });
+
+                Ok(result.unwrap_or(Value::Null))
+            }
+            "textDocument/prepareRename" => {
+                let (document, index) = self.document_position(params)?;
+                let analysis = Analysis::new(&document.term, &self.globals, &document.imports);
+                let symbol =
+                    references::symbol_at(&analysis, &document.term, document.file_id, index);
+
+                match symbol {
+                    Some(symbol)
+                        if symbol.referenced == Referenced::Binding(BindingKind::Global) =>
+                    {
+                        Err((REQUEST_FAILED, RenameError::Global(symbol.name).to_string()))
+                    }
+                    Some(symbol) => Ok(json!({
+                        "range": to_range(self.cache.files(), symbol.span),
+                        "placeholder": symbol.name,
+                    })),
+                    None => Ok(Value::Null),
+                }
+            }
+            "textDocument/rename" => {
+                let (document, index) = self.document_position(params)?;
+                let new_name = params["newName"].as_str().unwrap_or_default();
+                let analysis = Analysis::new(&document.term, &self.globals, &document.imports);
+                let files = self.cache.files();
+                let spans = rename::rename(
+                    &analysis,
+                    &document.term,
+                    &document.imports,
+                    document.file_id,
+                    index,
+                    new_name,
+                )
+                .map_err(|err| {
+                    let msg = match err {
+                        RenameError::DynamicAccess(_, span) => {
+                            let (line, character) = line_character(files, span.src_id, span.start);
+                            format!(
+                                "{} ({}:{}:{})",
+                                err,
+                                files.name(span.src_id).to_string_lossy(),
+                                line + 1,
+                                character + 1
+                            )
+                        }
+                        err => err.to_string(),
+                    };
+                    (REQUEST_FAILED, msg)
+                })?;
+
+                let mut changes = serde_json::Map::new();
+                for span in spans {
+                    let location = self.location(span);
+                    let uri = location["uri"].as_str().unwrap_or_default().to_owned();
+                    let edits = changes
+                        .entry(uri)
+                        .or_insert_with(|| Value::from(Vec::<Value>::new()));
+                    if let Value::Array(edits) = edits {
+                        edits.push(json!({"range": location["range"], "newText": new_name}));
+                    }
+                }
+
+                Ok(json!({ "changes": changes }))
+            }
+            "textDocument/completion" => {
+                let (document, index) = self.document_position(params)?;
+                let files = self.cache.files();
+                let dir = Path::new(files.name(document.file_id))
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""));
+                let (_, completions) = completion::complete(
+                    files.source(document.file_id),
+                    index,
+                    document.file_id,
+                    &self.globals,
+                    &document.imports,
+                    Some((self.cache.file_system(), dir)),
+                );
+
+                let items: Vec<Value> = completions
+                    .into_iter()
+                    .map(|completion| {
+                        let mut item = json!({
+                            "label": completion.label,
+                            "kind": completion_kind(completion.kind),
+                        });
+                        if let Some(detail) = completion.detail {
+                            item["detail"] = Value::from(detail);
+                        }
+                        item
+                    })
+                    .collect();
+
+                Ok(Value::from(items))
+            }
+            "textDocument/documentSymbol" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let document = self
+                    .documents
+                    .get(uri)
+                    .ok_or_else(|| (INVALID_PARAMS, format!("unknown document {}", uri)))?;
+                let symbols: Vec<Value> =
+                    symbols::document_symbols(&document.term, document.file_id)
+                        .iter()
+                        .map(|symbol| self.document_symbol(symbol))
+                        .collect();
+
+                Ok(Value::from(symbols))
+            }
+            "textDocument/codeAction" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let document = self
+                    .documents
+                    .get(uri)
+                    .ok_or_else(|| (INVALID_PARAMS, format!("unknown document {}", uri)))?;
+                let files = self.cache.files();
+                let range = &params["range"];
+                let (start, end) = to_index(files, document.file_id, &range["start"])
+                    .zip(to_index(files, document.file_id, &range["end"]))
+                    .ok_or_else(|| (INVALID_PARAMS, String::from("invalid range")))?;
+
+                let path = PathBuf::from(files.name(document.file_id));
+                let ctx = code_actions::Context {
+                    term: &document.term,
+                    source: files.source(document.file_id),
+                    file_id: document.file_id,
+                    globals: &self.globals,
+                    type_env: &self.type_env,
+                    resolver: &self.cache,
+                    files: Some((self.cache.file_system(), &path)),
+                };
+                let errors: Vec<Error> =
+                    document.errors.iter().map(|(err, _)| err.clone()).collect();
+
+                let actions: Vec<Value> = code_actions::code_actions(&ctx, &errors, start, end)
+                    .into_iter()
+                    .map(|action| {
+                        let edits: Vec<Value> = action
+                            .edits
+                            .iter()
+                            .map(|edit| {
+                                json!({"range": to_range(files, edit.span), "newText": edit.text})
+                            })
+                            .collect();
+                        let mut result = json!({
+                            "title": action.title,
+                            "kind": action.kind.name(),
+                            "edit": {"changes": {uri: edits}},
+                        });
+                        if let Some(index) = action.error {
+                            result["diagnostics"] = Value::from(document.errors[index].1.clone());
+                        }
+                        result
+                    })
+                    .collect();
+
+                Ok(Value::from(actions))
+            }
+            "textDocument/semanticTokens/full" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let document = self
+                    .documents
+                    .get(uri)
+                    .ok_or_else(|| (INVALID_PARAMS, format!("unknown document {}", uri)))?;
+                let files = self.cache.files();
+                let analysis = Analysis::new(&document.term, &self.globals, &document.imports);
+                let tokens = semantic_tokens::semantic_tokens(
+                    &analysis,
+                    &document.term,
+                    &document.types,
+                    document.file_id,
+                    files.source(document.file_id),
+                );
+
+                Ok(json!({"data": encode_tokens(files, document.file_id, &tokens)}))
+            }
+            "workspace/symbol" => {
+                let query = params["query"].as_str().unwrap_or_default();
+                Ok(Value::from(self.search_symbols(query)))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method {}", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        match method {
+            "exit" => {
+                self.exited = true;
+                Vec::new()
+            }
+            "textDocument/didOpen" => match params["textDocument"]["text"].as_str() {
+                Some(text) => self.open(uri, String::from(text)),
+                None => Vec::new(),
+            },
+            "textDocument/didChange" => {
+                // With full synchronization, the last change holds the whole content.
+                let changes = params["contentChanges"].as_array();
+                match changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    Some(text) => self.update(uri, String::from(text)),
+                    None => Vec::new(),
+                }
+            }
+            "textDocument/didClose" => self.close(uri),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Open a document, and check it together with the opened documents importing its file,
+    /// which now import the content of the document instead of the file on the disk.
+    fn open(&mut self, uri: &str, text: String) -> Vec<Value> {
+        if self.documents.contains_key(uri) {
+            return self.update(uri, text);
+        }
+
+        // The document is named after its path, so that its imports are resolved relatively to it.
+        let name = uri_to_path(uri).unwrap_or_else(|| PathBuf::from(uri));
+        let mut dirty = match self.cache.id_of(&name) {
+            Some(file_id) => self.cache.invalidate(file_id),
+            None => HashSet::new(),
+        };
+
+        let file_id = self.cache.add_string(name, text);
+        let (term, parse_errors) = parse_tolerant(file_id, self.cache.files().source(file_id));
+        self.documents.insert(
+            String::from(uri),
+            Document {
+                file_id,
+                term,
+                parse_errors,
+                types: HashMap::new(),
+                imports: HashMap::new(),
+                errors: Vec::new(),
+            },
+        );
+
+        dirty.insert(file_id);
+        self.check(&dirty)
+    }
+
+    /// Update the content of a document. Only the document and the opened documents importing
+    /// it, directly or transitively, are checked again.
+    fn update(&mut self, uri: &str, text: String) -> Vec<Value> {
+        let document = match self.documents.get_mut(uri) {
+            Some(document) => document,
+            None => return self.open(uri, text),
+        };
+
+        let dirty = self.cache.update_source(document.file_id, text);
+        let (term, parse_errors) = parse_tolerant(
+            document.file_id,
+            self.cache.files().source(document.file_id),
+        );
+        document.term = term;
+        document.parse_errors = parse_errors;
+
+        self.check(&dirty)
+    }
+
+    /// Close a document. The opened documents importing it are checked again, as they now
+    /// import the file on the disk, and the diagnostics of the document are cleared.
+    fn close(&mut self, uri: &str) -> Vec<Value> {
+        let document = match self.documents.remove(uri) {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+
+        let dirty = self.cache.invalidate(document.file_id);
+        let mut notifications = self.check(&dirty);
+        notifications.push(publish_diagnostics(uri, Vec::new()));
+        notifications
+    }
+
+    /// Typecheck the opened documents whose file is in `dirty` and load their imports again, and
+    /// return the notifications publishing their diagnostics. Their content must already be
+    /// parsed.
+    fn check(&mut self, dirty: &HashSet<FileId>) -> Vec<Value> {
+        let mut uris: Vec<String> = self
+            .documents
+            .iter()
+            .filter(|(_, document)| dirty.contains(&document.file_id))
+            .map(|(uri, _)| uri.clone())
+            .collect();
+        uris.sort();
+
+        uris.into_iter()
+            .map(|uri| {
+                let diagnostics = self.check_document(&uri);
+                publish_diagnostics(&uri, diagnostics)
+            })
+            .collect()
+    }
+
+    /// Typecheck a document and load its imports again, and return its diagnostics: its syntax
+    /// errors, the imports which can't be loaded or parsed, the first type error and its
+    /// warnings.
+    fn check_document(&mut self, uri: &str) -> Vec<Value> {
+        let document = &self.documents[uri];
+        let (file_id, term) = (document.file_id, document.term.clone());
+        let mut errors: Vec<Error> = document
+            .parse_errors
+            .iter()
+            .cloned()
+            .map(Error::from)
+            .collect();
+
+        let mut imports = HashMap::new();
+        self.load_imports(&term, &mut imports, &mut HashMap::new(), &mut errors);
+        let (result, types) = type_check_vars(&term, &self.type_env, &self.cache);
+        if let Err(err) = result {
+            errors.push(Error::from(err));
+        }
+
+        let diagnostics: Vec<Vec<Value>> = errors
+            .iter()
+            .map(|err| {
+                err.diagnostics(self.cache.files_mut(), None)
+                    .iter()
+                    .filter_map(|diagnostic| to_diagnostic(self.cache.files(), file_id, diagnostic))
+                    .collect()
+            })
+            .collect();
+
+        let source = self.cache.files().source(file_id);
+        let warnings: Vec<Value> = warning::deprecations(file_id, source)
+            .into_iter()
+            .chain(warning::check(&term))
+            .filter_map(|w| to_diagnostic(self.cache.files(), file_id, &w.diagnostic(Level::Warn)))
+            .collect();
+
+        let document = self.documents.get_mut(uri).unwrap();
+        document.types = types.into_iter().collect();
+        document.imports = imports;
+        document.errors = errors.into_iter().zip(diagnostics).collect();
+
+        document
+            .errors
+            .iter()
+            .flat_map(|(_, diagnostics)| diagnostics.iter().cloned())
+            .chain(warnings)
+            .collect()
+    }
+
+    /// Load and parse the files imported by a term, and the files they import in turn. An opened
+    /// document is imported with its current content. Other files are parsed once, and their
+    /// terms are then taken from the cache. `loaded` holds the files already loaded by this call,
+    /// and `errors` receives the imports which can't be loaded or parsed.
+    fn load_imports(
+        &mut self,
+        rt: &RichTerm,
+        imports: &mut Imports,
+        loaded: &mut HashMap<FileId, Option<RichTerm>>,
+        errors: &mut Vec<Error>,
+    ) {
+        let (path, span) = match (rt.term.as_ref(), rt.pos.into_opt()) {
+            (Term::Import(path), Some(span)) => (path, span),
+            (t, _) => {
+                t.for_each_subterm(|sub| self.load_imports(sub, imports, loaded, errors));
+                return;
+            }
+        };
+
+        let parent = PathBuf::from(self.cache.name(span.src_id));
+        let path_buf = self.cache.find_import(path, Some(parent));
+        let opened = self
+            .documents
+            .values()
+            .find(|document| Path::new(self.cache.name(document.file_id)) == path_buf);
+
+        let file_id = match opened {
+            Some(document) => {
+                loaded
+                    .entry(document.file_id)
+                    .or_insert_with(|| Some(document.term.clone()));
+                document.file_id
+            }
+            None => match self.cache.get_or_add_file(&path_buf) {
+                Ok(CacheOp::Done(file_id)) | Ok(CacheOp::Cached(file_id)) => file_id,
+                Err(err) => {
+                    errors.push(Error::from(ImportError::IOError(
+                        path.to_string_lossy().into_owned(),
+                        format!("{}", err),
+                        rt.pos,
+                    )));
+                    return;
+                }
+            },
+        };
+        self.cache.add_import(span.src_id, file_id);
+
+        let imported = match loaded.get(&file_id) {
+            Some(imported) => imported.clone(),
+            None => {
+                let format = InputFormat::from_path_buf(&path_buf).unwrap_or(InputFormat::Nickel);
+                let imported = match self.cache.parse_multi(file_id, format) {
+                    Ok(_) => self.cache.get_owned(file_id),
+                    Err(err) => {
+                        errors.push(Error::from(ImportError::ParseError(err, rt.pos)));
+                        None
+                    }
+                };
+                loaded.insert(file_id, imported.clone());
+
+                if let Some(imported) = &imported {
+                    self.load_imports(imported, imports, loaded, errors);
+                }
+                imported
+            }
+        };
+
+        if let Some(imported) = imported {
+            imports.insert(span, (file_id, imported));
+        }
+    }
+
+    /// Return the references to the symbol at the given index of a document.
+    fn references(
+        &self,
+        document: &Document,
+        index: ByteIndex,
+        include_declaration: bool,
+    ) -> Option<Vec<RawSpan>> {
+        let analysis = Analysis::new(&document.term, &self.globals, &document.imports);
+        references::references(
+            &analysis,
+            &document.term,
+            document.file_id,
+            index,
+            include_declaration,
+        )
+    }
+
+    /// Set the root directory of the workspace, and resolve the imports as in the package of the
+    /// root, if it has a manifest.
+    fn set_root(&mut self, root: PathBuf) -> Result<(), IOError> {
+        let manifest = Manifest::discover(self.cache.file_system(), &root);
+        self.root = Some(root);
+
+        if let Some(manifest) = manifest? {
+            manifest.configure(&mut self.cache, &Store::default())?;
+            self.manifest = Some(manifest);
+        }
+        Ok(())
+    }
+
+    /// Return the symbols of the opened documents and of the Nickel files of the workspace, or
+    /// of its package, whose name matches the query, as symbol informations of the protocol, best
+    /// matches first.
+    fn search_symbols(&mut self, query: &str) -> Vec<Value> {
+        let mut file_ids: Vec<FileId> = self.documents.values().map(|doc| doc.file_id).collect();
+        let paths = match (&self.manifest, &self.root) {
+            (Some(manifest), _) => manifest.files(self.cache.file_system()).ok(),
+            (None, Some(root)) => self.cache.list_files(root).ok(),
+            (None, None) => None,
+        }
+        .unwrap_or_default();
+
+        for path in paths {
+            let is_nickel = path.extension().is_some_and(|ext| ext == "ncl");
+            if !is_nickel
+                || self
+                    .cache
+                    .id_of(&path)
+                    .is_some_and(|id| file_ids.contains(&id))
+            {
+                continue;
+            }
+
+            if let Ok(CacheOp::Done(file_id)) | Ok(CacheOp::Cached(file_id)) =
+                self.cache.get_or_add_file(&path)
+            {
+                if !self.workspace_symbols.contains_key(&file_id) {
+                    let term = parse_document(file_id, self.cache.files().source(file_id));
+                    let symbols = symbols::document_symbols(&term, file_id);
+                    self.workspace_symbols.insert(file_id, symbols);
+                }
+                file_ids.push(file_id);
+            }
+        }
+
+        let documents: HashMap<FileId, Vec<Symbol>> = self
+            .documents
+            .values()
+            .map(|doc| {
+                (
+                    doc.file_id,
+                    symbols::document_symbols(&doc.term, doc.file_id),
+                )
+            })
+            .collect();
+
+        let mut matches: Vec<(usize, Option<String>, &Symbol)> = file_ids
+            .iter()
+            .filter_map(|id| documents.get(id).or_else(|| self.workspace_symbols.get(id)))
+            .flat_map(|symbols| symbols::flatten(symbols))
+            .filter_map(|(container, symbol)| {
+                symbols::fuzzy_match(query, &symbol.name).map(|score| (score, container, symbol))
+            })
+            .collect();
+        matches.sort_by(|(score1, _, s1), (score2, _, s2)| {
+            (score1, &s1.name).cmp(&(score2, &s2.name))
+        });
+
+        matches
+            .into_iter()
+            .map(|(_, container, symbol)| {
+                let mut info = json!({
+                    "name": symbol.name,
+                    "kind": symbol.kind.code(),
+                    "location": self.location(symbol.name_span),
+                });
+                if let Some(container) = container {
+                    info["containerName"] = Value::from(container);
+                }
+                info
+            })
+            .collect()
+    }
+
+    /// Convert a symbol of a document to a document symbol of the protocol.
+    fn document_symbol(&self, symbol: &Symbol) -> Value {
+        let files = self.cache.files();
+        let children: Vec<Value> = symbol
+            .children
+            .iter()
+            .map(|child| self.document_symbol(child))
+            .collect();
+        let mut result = json!({
+            "name": symbol.name,
+            "kind": symbol.kind.code(),
+            "range": to_range(files, symbol.span),
+            "selectionRange": to_range(files, symbol.name_span),
+            "children": children,
+        });
+
+        if let Some(detail) = &symbol.detail {
+            result["detail"] = Value::from(detail.as_str());
+        }
+        result
+    }
+
+    /// Convert a span to a location of the protocol. The URI of an opened document is the one
+    /// given by the client.
+    fn location(&self, span: RawSpan) -> Value {
+        let uri = self
+            .documents
+            .iter()
+            .find(|(_, document)| document.file_id == span.src_id)
+            .map(|(uri, _)| uri.clone())
+            .unwrap_or_else(|| path_to_uri(Path::new(self.cache.name(span.src_id))));
+
+        json!({"uri": uri, "range": to_range(self.cache.files(), span)})
+    }
+
+    /// Retrieve the document and the byte index designated by the parameters of a request.
+    fn document_position(&self, params: &Value) -> Result<(&Document, ByteIndex), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("unknown document {}", uri)))?;
+        let index = to_index(self.cache.files(), document.file_id, &params["position"])
+            .ok_or_else(|| (INVALID_PARAMS, String::from("invalid position")))?;
+
+        Ok((document, index))
+    }
+}
+
+/// Run the language server on the given input and output, until the client sends the `exit`
+/// notification or closes the input.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> Result<(), Error> {
+    let mut server = Server::new()?;
+
+    while let Some(message) = transport::read_message(&mut input).map_err(IOError::from)? {
+        for response in server.handle(&message) {
+            transport::write_message(&mut output, &response).map_err(IOError::from)?;
+        }
+
+        if server.exited() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the language server on the standard input and output.
+pub fn run() -> Result<(), Error> {
+    let stdin = io::stdin();
+    serve(stdin.lock(), io::stdout())
+}
+
+/// Return the references to the symbol at the given line and column of a file, including its
+/// definitions, as `path:line:column` strings. Lines and columns start at 1, and columns are
+/// counted in characters. The path of a reference in the file itself is the given path.
+pub fn file_references(path: &Path, line: usize, column: usize) -> Result<Vec<String>, Error> {
+    let text = std::fs::read_to_string(path).map_err(IOError::from)?;
+    let absolute = std::env::current_dir().map_err(IOError::from)?.join(path);
+    let uri = path_to_uri(&absolute);
+
+    let mut server = Server::new()?;
+    if let Some(dir) = absolute.parent() {
+        server.set_root(dir.to_path_buf())?;
+    }
+    server.open(&uri, text);
+    let document = &server.documents[&uri];
+    let files = server.cache.files();
+
+    let index = line
+        .checked_sub(1)
+        .and_then(|line| files.line_span(document.file_id, line as u32).ok())
+        .map(|span| {
+            let line_text =
+                &files.source(document.file_id)[span.start().to_usize()..span.end().to_usize()];
+            let offset = match line_text.char_indices().nth(column.saturating_sub(1)) {
+                Some((offset, _)) => offset,
+                None => line_text.len(),
+            };
+            span.start() + ByteOffset(offset as i64)
+        });
+    let spans = index
+        .and_then(|index| server.references(document, index, true))
+        .unwrap_or_default();
+
+    Ok(spans
+        .into_iter()
+        .map(|span| {
+            let name = if span.src_id == document.file_id {
+                path.to_string_lossy()
+            } else {
+                files.name(span.src_id).to_string_lossy()
+            };
+            let location = files.location(span.src_id, span.start).unwrap();
+            format!(
+                "{}:{}:{}",
+                name,
+                location.line.to_usize() + 1,
+                location.column.to_usize() + 1
+            )
+        })
+        .collect())
+}
+
+/// Convert a `file://` URI to a path, decoding percent-encoded characters.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut i = 0;
+
+    while i < path.len() {
+        let decoded = match path[i] {
+            b'%' => path
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+
+        match decoded {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(path[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// Parse a Nickel source, recovering from syntax errors. A source may end with a declaration, as
+/// it is being edited: its binding is kept, for the analysis of the declaration itself.
+pub(crate) fn parse_document(file_id: FileId, source: &str) -> RichTerm {
+    parse_tolerant(file_id, source).0
+}
+
+/// Same as [`parse_document`](fn.parse_document.html), but also return the syntax errors.
+fn parse_tolerant(file_id: FileId, source: &str) -> (RichTerm, Vec<ParseError>) {
+    let lexer = Lexer::new(source);
+    let (term, mut errors) = grammar::ExtendedTermParser::new().parse_tolerant(file_id, lexer);
+
+    let term = match term {
+        ExtendedTerm::RichTerm(t) => return (t, errors),
+        ExtendedTerm::ToplevelLet(id, t) => {
+            mk_term::let_in(id, t, RichTerm::from(Term::ParseError))
+        }
+        ExtendedTerm::ToplevelLetRec(_) => RichTerm::from(Term::ParseError),
+    };
+
+    // As for a file, a document can't end with a declaration.
+    errors.push(ParseError::UnexpectedEOF(
+        file_id,
+        vec![String::from("\";\"")],
+    ));
+    (term, errors)
+}
+
+/// Return the notification publishing the diagnostics of a document.
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    })
+}
+
+/// Convert a diagnostic to a diagnostic of the protocol for a document. Its range is the one of
+/// its first primary label in the document, or of its first label in the document if no primary
+/// label is. Return `None` if the diagnostic doesn't point to the document.
+fn to_diagnostic(files: &Files<String>, file_id: FileId, diagnostic: &Diagnostic) -> Option<Value> {
+    let labels = || {
+        diagnostic
+            .labels()
+            .iter()
+            .filter(|label| label.span().src_id == file_id)
+    };
+    let label = labels()
+        .find(|label| label.is_primary())
+        .or_else(|| labels().next())?;
+
+    let severity = match diagnostic.severity() {
+        Severity::Bug | Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Note => 3,
+        Severity::Help => 4,
+    };
+    let mut message = std::iter::once(diagnostic.message().to_owned())
+        .chain(diagnostic.notes().iter().cloned())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !label.message().is_empty() {
+        message = format!("{}: {}", message, label.message());
+    }
+
+    Some(json!({
+        "range": to_range(files, label.span()),
+        "severity": severity,
+        "code": diagnostic.code(),
+        "source": "nickel",
+        "message": message,
+    }))
+}
+
+/// Return the code of the kind of a completion in the language server protocol.
+fn completion_kind(kind: CompletionKind) -> u8 {
+    match kind {
+        CompletionKind::Variable => 6,
+        CompletionKind::Function => 3,
+        CompletionKind::Field => 5,
+        CompletionKind::EnumTag => 20,
+        CompletionKind::File => 17,
+        CompletionKind::Directory => 19,
+    }
+}
+
+/// Convert a path to a `file://` URI.
+fn path_to_uri(path: &Path) -> String {
+    format!("file://{}", crate::sarif::to_uri(path))
+}
+
+/// Convert a position of the protocol, made of a line and of a column counted in UTF-16 code
+/// units, to a byte index.
+fn to_index(files: &Files<String>, file_id: FileId, position: &Value) -> Option<ByteIndex> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let line_span = files.line_span(file_id, line as u32).ok()?;
+    let line_start = line_span.start().to_usize();
+    let line_text = &files.source(file_id)[line_start..line_span.end().to_usize()];
+
+    let mut units = 0;
+    for (offset, c) in line_text.char_indices() {
+        if units >= character {
+            return Some(ByteIndex((line_start + offset) as u32));
+        }
+        units += c.len_utf16();
+    }
+
+    Some(line_span.end())
+}
+
+/// Convert a byte index to a position of the protocol.
+fn to_position(files: &Files<String>, file_id: FileId, index: ByteIndex) -> Value {
+    let (line, character) = line_character(files, file_id, index);
+    json!({"line": line, "character": character})
+}
+
+/// Return the line of a byte index, and its column counted in UTF-16 code units.
+fn line_character(files: &Files<String>, file_id: FileId, index: ByteIndex) -> (usize, usize) {
+    let line = files.line_index(file_id, index);
+    let line_start = files
+        .line_span(file_id, line)
+        .map(|span| span.start().to_usize())
+        .unwrap_or_default();
+    let character = files.source(file_id)[line_start..index.to_usize()]
+        .chars()
+        .map(char::len_utf16)
+        .sum();
+
+    (line.to_usize(), character)
+}
+
+/// Encode semantic tokens as the protocol does: each token is made of five integers, which are
+/// its line relatively to the previous token, its column (relatively to the previous token if
+/// they are on the same line), its length, its type and its modifiers.
+fn encode_tokens(
+    files: &Files<String>,
+    file_id: FileId,
+    tokens: &[semantic_tokens::SemanticToken],
+) -> Vec<usize> {
+    let source = files.source(file_id);
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut previous_line, mut previous_character) = (0, 0);
+
+    for token in tokens {
+        let (line, character) = line_character(files, file_id, token.span.start);
+        let length = source[token.span.start.to_usize()..token.span.end.to_usize()]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+
+        let delta_character = if line == previous_line {
+            character - previous_character
+        } else {
+            character
+        };
+        data.extend_from_slice(&[
+            line - previous_line,
+            delta_character,
+            length,
+            token.token_type.index(),
+            token.modifiers as usize,
+        ]);
+        previous_line = line;
+        previous_character = character;
+    }
+
+    data
+}
+
+/// Convert a span to a range of the protocol.
+fn to_range(files: &Files<String>, span: RawSpan) -> Value {
+    json!({
+        "start": to_position(files, span.src_id, span.start),
+        "end": to_position(files, span.src_id, span.end),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::VirtualFileSystem;
+    use std::rc::Rc;
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "method": method, "params": params})
+    }
+
+    #[test]
+    fn session() {
+        let mut server = Server::new().unwrap();
+        let uri = "file:///config/my%20file.ncl";
+
+        let responses = server.handle(&request(1, "initialize", json!({})));
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["hoverProvider"],
+            json!(true)
+        );
+
+        let open = json!({"textDocument": {"uri": uri, "text": "let x = 1 in\nx + 1"}});
+        let notifications = server.handle(&notification("textDocument/didOpen", open));
+        assert_eq!(
+            notifications[0]["params"],
+            json!({"uri": uri, "diagnostics": []})
+        );
+
+        let source = "let ünïcode = 1 in\n(ünïcode + lists.length [] : Num)";
+        let change = json!({"textDocument": {"uri": uri}, "contentChanges": [{"text": source}]});
+        server.handle(&notification("textDocument/didChange", change));
+
+        let hover = |server: &mut Server, line, character| {
+            let position = json!({"textDocument": {"uri": uri}, "position": {"line": line, "character": character}});
+            server.handle(&request(2, "textDocument/hover", position))[0].clone()
+        };
+
+        let response = hover(&mut server, 1, 3);
+        assert_eq!(
+            response["result"]["range"],
+            json!({"start": {"line": 1, "character": 1}, "end": {"line": 1, "character": 8}})
+        );
+        assert!(response["result"]["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .starts_with("```nickel\nünïcode : Num\n```"));
+
+        let response = hover(&mut server, 1, 18);
+        let contents = response["result"]["contents"]["value"].as_str().unwrap();
+        assert!(contents.starts_with("```nickel\nlength : forall a. List a -> Num\n```"));
+        assert!(contents.contains("Return the number of elements of a list."));
+
+        assert_eq!(hover(&mut server, 0, 0)["result"], Value::Null);
+        assert_eq!(
+            hover(&mut server, 5, 0)["error"]["code"],
+            json!(INVALID_PARAMS)
+        );
+
+        let responses = server.handle(&request(3, "textDocument/unknown", json!({})));
+        assert_eq!(responses[0]["error"]["code"], json!(METHOD_NOT_FOUND));
+
+        server.handle(&request(4, "shutdown", Value::Null));
+        server.handle(&notification("exit", Value::Null));
+        assert!(server.exited());
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/config/lib.ncl", "{port = 80}");
+        let mut server = Server::new().unwrap();
+        server.cache.set_file_system(Rc::new(fs));
+
+        let (lib, main, other) = (
+            "file:///config/lib.ncl",
+            "file:///config/main.ncl",
+            "file:///config/other.ncl",
+        );
+        let open = |server: &mut Server, uri: &str, text: &str| {
+            let open = json!({"textDocument": {"uri": uri, "text": text}});
+            server.handle(&notification("textDocument/didOpen", open))
+        };
+        let change = |server: &mut Server, uri: &str, text: &str| {
+            let change = json!({"textDocument": {"uri": uri}, "contentChanges": [{"text": text}]});
+            server.handle(&notification("textDocument/didChange", change))
+        };
+        // The URIs and the diagnostics codes of publishDiagnostics notifications.
+        let published = |notifications: Vec<Value>| -> Vec<(String, Vec<String>)> {
+            notifications
+                .iter()
+                .map(|n| {
+                    let codes = n["params"]["diagnostics"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|d| String::from(d["code"].as_str().unwrap()))
+                        .collect();
+                    (String::from(n["params"]["uri"].as_str().unwrap()), codes)
+                })
+                .collect()
+        };
+
+        let notifications = open(
+            &mut server,
+            main,
+            "let lib = import \"lib.ncl\" in\n(1 + \"a\" : Num)",
+        );
+        let diagnostic = &notifications[0]["params"]["diagnostics"][0];
+        assert_eq!(diagnostic["severity"], json!(1));
+        assert_eq!(diagnostic["range"]["start"]["line"], json!(1));
+        assert_eq!(
+            published(notifications),
+            vec![(
+                String::from(main),
+                vec![String::from("N0308"), String::from("N0704")]
+            )]
+        );
+        assert_eq!(
+            published(change(
+                &mut server,
+                main,
+                "let lib = import \"lib.ncl\" in\nlib.port"
+            )),
+            vec![(String::from(main), vec![])]
+        );
+
+        // Opening the imported file checks the documents importing it.
+        assert_eq!(
+            published(open(&mut server, lib, "{port = }")),
+            vec![
+                (String::from(lib), vec![String::from("N0102")]),
+                (String::from(main), vec![])
+            ]
+        );
+        assert_eq!(
+            published(open(&mut server, other, "1")),
+            vec![(String::from(other), vec![])]
+        );
+        assert_eq!(published(change(&mut server, lib, "{port = 81}")).len(), 2);
+        let missing = "let lib = import \"missing.ncl\" in\nlib";
+        assert_eq!(
+            published(change(&mut server, other, missing)),
+            vec![(String::from(other), vec![String::from("N0201")])]
+        );
+
+        let close = json!({"textDocument": {"uri": lib}});
+        assert_eq!(
+            published(server.handle(&notification("textDocument/didClose", close))),
+            vec![(String::from(main), vec![]), (String::from(lib), vec![])]
+        );
+        assert_eq!(
+            published(change(&mut server, main, "let x = 1 in x")).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn manifest() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file(
+            "/ws/nickel.toml",
+            "[package]\nname = \"infra\"\nimport-paths = [\"lib\"]",
+        );
+        fs.add_file("/ws/lib/server.ncl", "{port = 80}");
+        fs.add_file("/other/port.ncl", "{port = 1}");
+        let mut server = Server::new().unwrap();
+        server.cache.set_file_system(Rc::new(fs));
+        server.handle(&request(
+            1,
+            "initialize",
+            json!({"rootUri": "file:///ws/src"}),
+        ));
+        assert_eq!(server.manifest.as_ref().unwrap().name, "infra");
+
+        let uri = "file:///ws/src/main.ncl";
+        let source = "let server = import \"server.ncl\" in server.port";
+        let open = json!({"textDocument": {"uri": uri, "text": source}});
+        let notifications = server.handle(&notification("textDocument/didOpen", open));
+        assert_eq!(notifications[0]["params"]["diagnostics"], json!([]));
+
+        let response = server.handle(&request(2, "workspace/symbol", json!({"query": "port"})));
+        let found: Vec<&str> = response[0]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|info| info["location"]["uri"].as_str().unwrap())
+            .collect();
+        assert_eq!(found, vec!["file:///ws/lib/server.ncl"]);
+    }
+
+    #[test]
+    fn code_actions() {
+        let mut server = Server::new().unwrap();
+        let uri = "file:///config/main.ncl";
+        let source = "({a = 1} : {a : Num, b : Str})";
+        let open = json!({"textDocument": {"uri": uri, "text": source}});
+        let notifications = server.handle(&notification("textDocument/didOpen", open));
+        let diagnostics = &notifications[0]["params"]["diagnostics"];
+
+        let position = json!({"line": 0, "character": 2});
+        let params = json!({
+            "textDocument": {"uri": uri},
+            "range": {"start": position, "end": position},
+            "context": {"diagnostics": diagnostics},
+        });
+        let response = server.handle(&request(1, "textDocument/codeAction", params));
+        let action = &response[0]["result"][0];
+
+        assert_eq!(action["title"], json!("Add the missing field `b`"));
+        assert_eq!(action["kind"], json!("quickfix"));
+        assert_eq!(&action["diagnostics"], diagnostics);
+        let position = json!({"line": 0, "character": 7});
+        assert_eq!(
+            action["edit"]["changes"][uri],
+            json!([{"range": {"start": position, "end": position}, "newText": ", b = \"\""}])
+        );
+    }
+
+    #[test]
+    fn rename() {
+        let mut server = Server::new().unwrap();
+        let uri = "file:///config/main.ncl";
+        let source = "let r = {a = 1} in r.a + r.\"a\" + lists.length []";
+        let open = json!({"textDocument": {"uri": uri, "text": source}});
+        server.handle(&notification("textDocument/didOpen", open));
+        let position = |character| json!({"line": 0, "character": character});
+
+        let params = json!({"textDocument": {"uri": uri}, "position": position(21)});
+        let response = server.handle(&request(1, "textDocument/prepareRename", params));
+        assert_eq!(
+            response[0]["result"],
+            json!({"range": {"start": position(21), "end": position(22)}, "placeholder": "a"})
+        );
+
+        // The field is also accessed dynamically.
+        let params =
+            json!({"textDocument": {"uri": uri}, "position": position(21), "newName": "b"});
+        let response = server.handle(&request(2, "textDocument/rename", params));
+        assert_eq!(response[0]["error"]["code"], json!(REQUEST_FAILED));
+        assert!(response[0]["error"]["message"]
+            .as_str()
+            .unwrap()
+            .ends_with(":1:26)"));
+
+        let source = "let r = {a = 1} in r.a + lists.length []";
+        let change = json!({
+            "textDocument": {"uri": uri},
+            "contentChanges": [{"text": source}],
+        });
+        server.handle(&notification("textDocument/didChange", change));
+        let params =
+            json!({"textDocument": {"uri": uri}, "position": position(21), "newName": "b"});
+        let response = server.handle(&request(3, "textDocument/rename", params));
+        assert_eq!(
+            response[0]["result"]["changes"][uri],
+            json!([
+                {"range": {"start": position(9), "end": position(10)}, "newText": "b"},
+                {"range": {"start": position(21), "end": position(22)}, "newText": "b"},
+            ])
+        );
+
+        let params =
+            json!({"textDocument": {"uri": uri}, "position": position(25), "newName": "b"});
+        let response = server.handle(&request(4, "textDocument/prepareRename", params));
+        assert_eq!(response[0]["error"]["code"], json!(REQUEST_FAILED));
+    }
+
+    #[test]
+    fn semantic_tokens() {
+        let mut server = Server::new().unwrap();
+        let uri = "file:///config/main.ncl";
+        let open =
+            json!({"textDocument": {"uri": uri, "text": "let é = 1 in\n  é + lists.length []"}});
+        server.handle(&notification("textDocument/didOpen", open));
+
+        let params = json!({"textDocument": {"uri": uri}});
+        let response = server.handle(&request(1, "textDocument/semanticTokens/full", params));
+        let (variable, function) = (
+            semantic_tokens::TokenType::Variable.index(),
+            semantic_tokens::TokenType::Function.index(),
+        );
+        let (declaration, default_library) = (
+            semantic_tokens::DECLARATION as usize,
+            semantic_tokens::DEFAULT_LIBRARY as usize,
+        );
+        // Lines and columns are relative to the previous token.
+        let expected: Vec<usize> = [
+            [0, 4, 1, variable, declaration],
+            [1, 2, 1, variable, 0],
+            [0, 4, 5, variable, default_library],
+            [0, 6, 6, function, default_library],
+        ]
+        .concat();
+        assert_eq!(response[0]["result"]["data"], json!(expected));
+    }
+
+    #[test]
+    fn definitions() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/config/base.ncl", "{port = 80, host = \"a\"}");
+        fs.add_file(
+            "/config/lib/extra.ncl",
+            "let base = import \"../base.ncl\" in\nbase & {port | Num}",
+        );
+        let mut server = Server::new().unwrap();
+        server.cache.set_file_system(Rc::new(fs));
+
+        let uri = "file:///config/main.ncl";
+        let source = "let cfg = import \"lib/extra.ncl\" in\ncfg.port + cfg.host";
+        let open = json!({"textDocument": {"uri": uri, "text": source}});
+        server.handle(&notification("textDocument/didOpen", open));
+
+        let mut definition = |line, character| {
+            let position = json!({"textDocument": {"uri": uri}, "position": {"line": line, "character": character}});
+            server.handle(&request(2, "textDocument/definition", position))[0]["result"].clone()
+        };
+        let location = |uri, line, start, end| {
+            json!({
+                "uri": uri,
+                "range": {
+                    "start": {"line": line, "character": start},
+                    "end": {"line": line, "character": end},
+                },
+            })
+        };
+
+        assert_eq!(definition(1, 1), json!([location(uri, 0, 4, 7)]));
+        // The field is defined in both merged records, across two imports.
+        let mut locations = definition(1, 5).as_array().unwrap().clone();
+        locations.sort_by_key(|location| location["uri"].as_str().unwrap().to_owned());
+        assert_eq!(
+            locations,
+            vec![
+                location("file:///config/base.ncl", 0, 1, 5),
+                location("file:///config/lib/extra.ncl", 1, 8, 12)
+            ]
+        );
+        assert_eq!(
+            definition(1, 16),
+            json!([location("file:///config/base.ncl", 0, 12, 16)])
+        );
+        assert_eq!(
+            definition(0, 12),
+            json!([location("file:///config/lib/extra.ncl", 0, 0, 0)])
+        );
+        assert_eq!(definition(0, 0), Value::Null);
+    }
+
+    #[test]
+    fn references() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/base.ncl", "{port = 80}");
+        let mut server = Server::new().unwrap();
+        server.cache.set_file_system(Rc::new(fs));
+
+        let uri = "file:///main.ncl";
+        let source = "let cfg = (import \"base.ncl\") & {port | Num} in\ncfg.port + cfg.port";
+        let open = json!({"textDocument": {"uri": uri, "text": source}});
+        server.handle(&notification("textDocument/didOpen", open));
+
+        let mut references = |line, character, include_declaration| {
+            let params = json!({
+                "textDocument": {"uri": uri},
+                "position": {"line": line, "character": character},
+                "context": {"includeDeclaration": include_declaration},
+            });
+            let response = server.handle(&request(2, "textDocument/references", params));
+            let locations = response[0]["result"].as_array().unwrap().clone();
+            locations
+                .iter()
+                .map(|location| {
+                    format!(
+                        "{}:{}:{}",
+                        location["uri"].as_str().unwrap(),
+                        location["range"]["start"]["line"],
+                        location["range"]["start"]["character"]
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let usages = vec!["file:///main.ncl:1:4", "file:///main.ncl:1:15"];
+        assert_eq!(references(1, 5, false), usages);
+
+        let mut all = vec!["file:///main.ncl:0:33"];
+        all.extend(usages.iter());
+        all.push("file:///base.ncl:0:1");
+        let mut found = references(0, 34, true);
+        found.sort_by_key(|location| !location.starts_with(uri));
+        assert_eq!(found, all);
+    }
+
+    #[test]
+    fn symbols() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/ws/lib/server.ncl", "{server = {port = 80, host = \"a\"}}");
+        fs.add_file("/ws/.cache/port.ncl", "{port = 1}");
+        fs.add_file("/ws/data.json", "{\"port\": 1}");
+        fs.add_file("/other.ncl", "{port = 1}");
+        let mut server = Server::new().unwrap();
+        server.cache.set_file_system(Rc::new(fs));
+        server.handle(&request(1, "initialize", json!({"rootUri": "file:///ws"})));
+
+        let uri = "file:///ws/main.ncl";
+        let source = "let portal | Num = 1 in\n{a = {b = portal}}";
+        let open = json!({"textDocument": {"uri": uri, "text": source}});
+        server.handle(&notification("textDocument/didOpen", open));
+
+        let params = json!({"textDocument": {"uri": uri}});
+        let response = server.handle(&request(2, "textDocument/documentSymbol", params));
+        let symbols = &response[0]["result"];
+        assert_eq!(symbols[0]["name"], json!("portal"));
+        assert_eq!(symbols[0]["detail"], json!("Num"));
+        assert_eq!(
+            symbols[0]["selectionRange"],
+            json!({"start": {"line": 0, "character": 4}, "end": {"line": 0, "character": 10}})
+        );
+        assert_eq!(symbols[1]["name"], json!("a"));
+        assert_eq!(symbols[1]["children"][0]["name"], json!("b"));
+
+        let response = server.handle(&request(3, "workspace/symbol", json!({"query": "prt"})));
+        let found: Vec<(&str, &str, &str)> = response[0]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|info| {
+                (
+                    info["name"].as_str().unwrap(),
+                    info["containerName"].as_str().unwrap_or_default(),
+                    info["location"]["uri"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("port", "server", "file:///ws/lib/server.ncl"),
+                ("portal", "", uri)
+            ]
+        );
+    }
+
+    #[test]
+    fn completions() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/config/lib.ncl", "{port | Num = 80}");
+        let mut server = Server::new().unwrap();
+        server.cache.set_file_system(Rc::new(fs));
+
+        let uri = "file:///config/main.ncl";
+        let source = "let lib = import \"lib.ncl\" in\nlib.p + lists.len";
+        let open = json!({"textDocument": {"uri": uri, "text": source}});
+        server.handle(&notification("textDocument/didOpen", open));
+
+        let mut complete = |line, character| {
+            let position = json!({"textDocument": {"uri": uri}, "position": {"line": line, "character": character}});
+            server.handle(&request(2, "textDocument/completion", position))[0]["result"].clone()
+        };
+
+        assert_eq!(complete(0, 18), json!([{"label": "lib.ncl", "kind": 17}]));
+        assert_eq!(
+            complete(1, 5),
+            json!([{"label": "port", "kind": 5, "detail": "Num"}])
+        );
+        let response = complete(1, 17);
+        assert_eq!(response[0]["label"], json!("length"));
+        assert_eq!(response[0]["kind"], json!(3));
+        assert_eq!(response[0]["detail"], json!("forall a. List a -> Num"));
+    }
+
+    #[test]
+    fn uris() {
+        assert_eq!(
+            uri_to_path("file:///a%20b/%C3%BC.ncl"),
+            Some(PathBuf::from("/a b/ü.ncl"))
+        );
+        assert_eq!(uri_to_path("untitled:1"), None);
+    }
+}
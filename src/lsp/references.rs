@@ -0,0 +1,166 @@
+//! References lookup: the places where the symbol under the cursor is used.
+//!
+//! The symbol is either a variable, the field of a field access, or the name of a binding at its
+//! definition. It is identified by the spans of its definitions, and its references are the
+//! variables bound by one of these definitions and the accesses to a field defined by one of
+//! them, in the document and in the files it imports. The definitions of a field include the
+//! definitions of the same field in the records it is merged with.
+use super::analysis::{terms_at, Analysis, BindingKind};
+use crate::position::RawSpan;
+use crate::term::{RichTerm, Term, UnaryOp};
+use codespan::{ByteIndex, FileId};
+
+/// What a symbol refers to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Referenced {
+    /// A name introduced by a binding construct.
+    Binding(BindingKind),
+    /// The field of a field access.
+    Field,
+}
+
+/// The symbol at an index of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub referenced: Referenced,
+    /// The span of the name at the index.
+    pub span: RawSpan,
+    /// The spans of the definitions of the symbol, not including the definitions of the same
+    /// field in merged records. A field of an unknown record has none.
+    pub definitions: Vec<RawSpan>,
+}
+
+impl Symbol {
+    /// Return `true` if the symbol is a record field.
+    pub fn is_field(&self) -> bool {
+        matches!(
+            self.referenced,
+            Referenced::Field | Referenced::Binding(BindingKind::Field)
+        )
+    }
+}
+
+/// Return the symbol at the given index of a file, or `None` if there is no symbol at this index.
+pub fn symbol_at<'a>(
+    analysis: &Analysis<'a>,
+    term: &'a RichTerm,
+    file_id: FileId,
+    index: ByteIndex,
+) -> Option<Symbol> {
+    if let Some(binding) = analysis.binding_at(file_id, index) {
+        let span = binding.ident.pos.into_opt()?;
+        return Some(Symbol {
+            name: binding.ident.label.clone(),
+            referenced: Referenced::Binding(binding.kind),
+            span,
+            definitions: vec![span],
+        });
+    }
+
+    let innermost = *terms_at(term, index).last()?;
+    let span = innermost.pos.into_opt()?;
+
+    match innermost.term.as_ref() {
+        Term::Var(id) => {
+            let binding = analysis.definition(&span)?;
+            Some(Symbol {
+                name: id.label.clone(),
+                referenced: Referenced::Binding(binding.kind),
+                span,
+                definitions: binding.ident.pos.into_opt().into_iter().collect(),
+            })
+        }
+        Term::Op1(UnaryOp::StaticAccess(id), record) => Some(Symbol {
+            name: id.label.clone(),
+            referenced: Referenced::Field,
+            span: id.pos.into_opt()?,
+            definitions: analysis
+                .field_definitions(record, id)
+                .into_iter()
+                .filter_map(|(field, _)| field.pos.into_opt())
+                .collect(),
+        }),
+        _ => None,
+    }
+}
+
+/// Return the spans of the references to the symbol at the given index of a file, or `None` if
+/// there is no symbol at this index. If `include_declaration` is set, the definitions of the
+/// symbol are included, unless it is a field of the standard library.
+pub fn references<'a>(
+    analysis: &Analysis<'a>,
+    term: &'a RichTerm,
+    file_id: FileId,
+    index: ByteIndex,
+    include_declaration: bool,
+) -> Option<Vec<RawSpan>> {
+    let symbol = symbol_at(analysis, term, file_id, index)?;
+
+    // The accessed record is unknown: the access is the only known reference.
+    if symbol.referenced == Referenced::Field && symbol.definitions.is_empty() {
+        return Some(vec![symbol.span]);
+    }
+
+    let definitions = analysis.merged_definitions(symbol.definitions);
+    let mut references = analysis.references(&definitions);
+    if include_declaration && symbol.referenced != Referenced::Binding(BindingKind::Global) {
+        references.extend(definitions);
+        references.sort_by_key(|span| (span.src_id, span.start));
+        references.dedup();
+    }
+
+    Some(references)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use codespan::Files;
+    use std::collections::HashMap;
+
+    /// Return the byte offsets of the references to the symbol at the given offset of `source`,
+    /// including its definitions.
+    fn references_at(source: &str, index: usize) -> Vec<usize> {
+        let file_id = Files::new().add("<test>", String::from(source));
+        let rt = grammar::TermParser::new()
+            .parse_strict(file_id, lexer::Lexer::new(source))
+            .unwrap();
+        let (globals, imports) = (HashMap::new(), HashMap::new());
+        let analysis = Analysis::new(&rt, &globals, &imports);
+
+        references(&analysis, &rt, file_id, ByteIndex(index as u32), true)
+            .map(|spans| spans.iter().map(|span| span.start.to_usize()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn variables() {
+        let source = "let x = 1 in let f = fun x => x + 1 in f x + x";
+        let positions: Vec<usize> = source.match_indices('x').map(|(i, _)| i).collect();
+        let outer = vec![positions[0], positions[3], positions[4]];
+
+        assert_eq!(references_at(source, positions[0]), outer);
+        assert_eq!(references_at(source, positions[4]), outer);
+        assert_eq!(
+            references_at(source, positions[2]),
+            vec![positions[1], positions[2]]
+        );
+        assert!(references_at(source, source.find('1').unwrap()).is_empty());
+    }
+
+    #[test]
+    fn fields() {
+        let source = "let r = {a = 1, b = a} & {a | Num} in r.a + r.b + {a = 2}.a";
+        let positions: Vec<usize> = source.match_indices('a').map(|(i, _)| i).collect();
+        let expected = positions[..4].to_vec();
+
+        assert_eq!(references_at(source, positions[0]), expected);
+        assert_eq!(references_at(source, positions[3]), expected);
+        assert_eq!(
+            references_at(source, positions[5]),
+            vec![positions[4], positions[5]]
+        );
+    }
+}
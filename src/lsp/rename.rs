@@ -0,0 +1,263 @@
+//! Rename: the edits which rename the symbol under the cursor everywhere it is used.
+//!
+//! The edit sites are the references to the symbol, including its definitions, as given by the
+//! references lookup. Renaming a field is refused when the field may also be accessed or defined
+//! through a dynamic operation (`r."#{name}"`, `{"#{name}" = value}`, `-$`, `%hasField%`), whose
+//! key is not renamed along with it, since this would silently change the meaning of the program.
+use super::analysis::{Analysis, BindingKind, Imports};
+use super::references::{self, Referenced};
+use crate::parser::lexer::{Lexer, NormalToken, Token};
+use crate::position::RawSpan;
+use crate::term::{BinaryOp, RichTerm, StrChunk, Term};
+use codespan::{ByteIndex, FileId};
+use std::fmt;
+
+/// The reason why a symbol can't be renamed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenameError {
+    /// There is no symbol at the position of the request.
+    NoSymbol,
+    /// The new name is not a valid identifier.
+    InvalidName(String),
+    /// The symbol is defined by the standard library.
+    Global(String),
+    /// The symbol is the field of a record whose definition is unknown.
+    UnknownDefinition(String),
+    /// The field is accessed through a dynamic operation, whose span is given.
+    DynamicAccess(String, RawSpan),
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenameError::NoSymbol => write!(f, "there is no symbol to rename at this position"),
+            RenameError::InvalidName(name) => write!(f, "`{}` is not a valid identifier", name),
+            RenameError::Global(name) => write!(
+                f,
+                "`{}` is defined by the standard library and can't be renamed",
+                name
+            ),
+            RenameError::UnknownDefinition(name) => write!(
+                f,
+                "the definition of the field `{}` is unknown, so its uses can't be found",
+                name
+            ),
+            RenameError::DynamicAccess(name, _) => write!(
+                f,
+                "the field `{}` may be accessed dynamically, and such accesses can't be renamed",
+                name
+            ),
+        }
+    }
+}
+
+/// Return the spans of the names to replace to rename the symbol at the given index of a file to
+/// `new_name`, sorted by file and by position.
+pub fn rename<'a>(
+    analysis: &Analysis<'a>,
+    term: &'a RichTerm,
+    imports: &'a Imports,
+    file_id: FileId,
+    index: ByteIndex,
+    new_name: &str,
+) -> Result<Vec<RawSpan>, RenameError> {
+    if !is_identifier(new_name) {
+        return Err(RenameError::InvalidName(String::from(new_name)));
+    }
+
+    let symbol =
+        references::symbol_at(analysis, term, file_id, index).ok_or(RenameError::NoSymbol)?;
+    match symbol.referenced {
+        Referenced::Binding(BindingKind::Global) => return Err(RenameError::Global(symbol.name)),
+        Referenced::Field if symbol.definitions.is_empty() => {
+            return Err(RenameError::UnknownDefinition(symbol.name))
+        }
+        _ => (),
+    }
+
+    let is_field = symbol.is_field();
+    let definitions = analysis.merged_definitions(symbol.definitions.clone());
+
+    if is_field {
+        let mut uses = Vec::new();
+        dynamic_uses(term, &mut uses);
+        for (_, imported) in imports.values() {
+            dynamic_uses(imported, &mut uses);
+        }
+
+        let unsafe_use = uses.into_iter().find(|(key, record, _)| {
+            let accessed: Vec<RawSpan> = analysis
+                .records(record)
+                .into_iter()
+                .flat_map(|fields| fields.keys())
+                .filter(|id| id.label == symbol.name)
+                .filter_map(|id| id.pos.into_opt())
+                .collect();
+            let targets_symbol = accessed.iter().any(|span| definitions.contains(span));
+
+            match literal(key) {
+                // A literal key only refers to the symbol if it has the same name, and if the
+                // record is either unknown or one which defines the symbol.
+                Some(s) => s == symbol.name && (accessed.is_empty() || targets_symbol),
+                None => targets_symbol,
+            }
+        });
+
+        if let Some((_, _, span)) = unsafe_use {
+            return Err(RenameError::DynamicAccess(symbol.name, span));
+        }
+    }
+
+    let mut spans = analysis.references(&definitions);
+    spans.extend(definitions);
+    spans.sort_by_key(|span| (span.src_id, span.start));
+    spans.dedup();
+
+    Ok(spans)
+}
+
+/// Return `true` if a name is a valid identifier, which is not a keyword.
+pub fn is_identifier(name: &str) -> bool {
+    let mut lexer = Lexer::new(name);
+    match (lexer.next(), lexer.next()) {
+        (Some(Ok((_, Token::Normal(NormalToken::Identifier(id)), _))), None) => id == name,
+        _ => false,
+    }
+}
+
+/// Return the content of a string without interpolated expressions.
+fn literal(rt: &RichTerm) -> Option<&str> {
+    match rt.term.as_ref() {
+        Term::Str(s) => Some(s),
+        Term::StrChunks(chunks) => match chunks.as_slice() {
+            [] => Some(""),
+            [StrChunk::Literal(s)] => Some(s),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Collect the key, the record and the span of the dynamic field operations of a term.
+fn dynamic_uses<'a>(rt: &'a RichTerm, uses: &mut Vec<(&'a RichTerm, &'a RichTerm, RawSpan)>) {
+    if let Term::Op2(BinaryOp::DynAccess(), key, record)
+    | Term::Op2(BinaryOp::DynRemove(), key, record)
+    | Term::Op2(BinaryOp::HasField(), key, record)
+    | Term::Op2(BinaryOp::DynDefine(), key, record)
+    | Term::Op2(BinaryOp::DynExtend(), key, record) = rt.term.as_ref()
+    {
+        if let Some(span) = rt.pos.into_opt() {
+            uses.push((key, record, span));
+        }
+    }
+
+    rt.term.for_each_subterm(|sub| dynamic_uses(sub, uses));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use codespan::Files;
+    use std::collections::HashMap;
+
+    /// Rename the symbol at the given offset of `source` to `new`, and return the renamed source.
+    fn rename_at(source: &str, index: usize, new: &str) -> Result<String, RenameError> {
+        let file_id = Files::new().add("<test>", String::from(source));
+        let rt = grammar::TermParser::new()
+            .parse_strict(file_id, lexer::Lexer::new(source))
+            .unwrap();
+        let (globals, imports) = (HashMap::new(), HashMap::new());
+        let analysis = Analysis::new(&rt, &globals, &imports);
+
+        let spans = rename(
+            &analysis,
+            &rt,
+            &imports,
+            file_id,
+            ByteIndex(index as u32),
+            new,
+        )?;
+        let mut result = String::from(source);
+        for span in spans.iter().rev() {
+            result.replace_range(span.start.to_usize()..span.end.to_usize(), new);
+        }
+        Ok(result)
+    }
+
+    #[test]
+    fn bindings() {
+        let source = "let x = 1 in let f = fun x => x + 1 in f x + x";
+        assert_eq!(
+            rename_at(source, 4, "y"),
+            Ok(String::from(
+                "let y = 1 in let f = fun x => x + 1 in f y + y"
+            ))
+        );
+        assert_eq!(
+            rename_at(source, source.rfind('x').unwrap(), "y"),
+            Ok(String::from(
+                "let y = 1 in let f = fun x => x + 1 in f y + y"
+            ))
+        );
+        assert_eq!(
+            rename_at(source, 4, "let"),
+            Err(RenameError::InvalidName(String::from("let")))
+        );
+        assert_eq!(
+            rename_at(source, 4, "a b"),
+            Err(RenameError::InvalidName(String::from("a b")))
+        );
+        assert_eq!(
+            rename_at(source, source.find('1').unwrap(), "y"),
+            Err(RenameError::NoSymbol)
+        );
+    }
+
+    #[test]
+    fn fields() {
+        let source = "let r = {a = 1, b = a} & {a | Num} in r.a + r.b";
+        assert_eq!(
+            rename_at(source, source.find('a').unwrap(), "c"),
+            Ok(String::from(
+                "let r = {c = 1, b = c} & {c | Num} in r.c + r.b"
+            ))
+        );
+
+        let unknown = "fun r => r.a";
+        assert_eq!(
+            rename_at(unknown, unknown.rfind('a').unwrap(), "c"),
+            Err(RenameError::UnknownDefinition(String::from("a")))
+        );
+    }
+
+    #[test]
+    fn dynamic_accesses() {
+        let literal = "let r = {a = 1} in r.a + r.\"a\"";
+        assert!(matches!(
+            rename_at(literal, literal.find('a').unwrap(), "c"),
+            Err(RenameError::DynamicAccess(name, _)) if name == "a"
+        ));
+
+        let computed = "let r = {a = 1} in let k = \"a\" in r.\"#{k}\"";
+        assert!(matches!(
+            rename_at(computed, computed.find('a').unwrap(), "c"),
+            Err(RenameError::DynamicAccess(..))
+        ));
+
+        let defined = "let r = {a = 1, \"a\" = 2} in r.a";
+        assert!(matches!(
+            rename_at(defined, defined.find('a').unwrap(), "c"),
+            Err(RenameError::DynamicAccess(..))
+        ));
+
+        // The dynamic accesses of other names or of other records are left alone.
+        let other = "let r = {a = 1, b = 2} in let s = {a = 3} in r.a + r.\"b\" + s.\"a\"";
+        assert_eq!(
+            rename_at(other, other.find('a').unwrap(), "c"),
+            Ok(String::from(
+                "let r = {c = 1, b = 2} in let s = {a = 3} in r.c + r.\"b\" + s.\"a\""
+            ))
+        );
+    }
+}
@@ -0,0 +1,471 @@
+//! Semantic tokens: the classification of the identifiers of a document, so that an editor can
+//! highlight them according to what they denote rather than to their syntax only.
+//!
+//! Variables are classified according to their binding, as determined by the
+//! [analysis](../analysis/index.html): a variable bound to a function, or whose type inferred by
+//! the typechecker is a function type, is a function, and the fields of the standard library are
+//! marked as such. Fields are classified at their definition and at their accesses. In type
+//! annotations, the names of types, the type variables, the contracts and the fields of record and
+//! enum types are classified as well.
+use super::analysis::{Analysis, BindingKind};
+use super::symbols::is_function;
+use crate::identifier::Ident;
+use crate::parser::lexer::{Lexer, NormalToken, Token};
+use crate::position::{RawSpan, TermPos};
+use crate::term::{Contract, RichTerm, Term, UnaryOp};
+use crate::types::{AbsType, Types};
+use codespan::{ByteIndex, FileId};
+use std::collections::HashMap;
+
+/// The modifier of the token of the name of a binding, at its definition.
+pub const DECLARATION: u32 = 1;
+/// The modifier of the tokens which refer to the standard library.
+pub const DEFAULT_LIBRARY: u32 = 1 << 1;
+/// The names of the modifiers in the protocol, in the order of their bits.
+pub const MODIFIERS: [&str; 2] = ["declaration", "defaultLibrary"];
+
+/// The type of a token.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TokenType {
+    Function,
+    Variable,
+    Parameter,
+    /// A record field, or a field of a record type.
+    Property,
+    /// An enum tag, or a tag of an enum type.
+    EnumMember,
+    Type,
+    TypeParameter,
+    Contract,
+}
+
+impl TokenType {
+    /// The token types, in the order of the legend sent to the client.
+    pub const LEGEND: [TokenType; 8] = [
+        TokenType::Function,
+        TokenType::Variable,
+        TokenType::Parameter,
+        TokenType::Property,
+        TokenType::EnumMember,
+        TokenType::Type,
+        TokenType::TypeParameter,
+        TokenType::Contract,
+    ];
+
+    /// The name of the type in the protocol. The protocol has no type for contracts, which are
+    /// reported as interfaces.
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenType::Function => "function",
+            TokenType::Variable => "variable",
+            TokenType::Parameter => "parameter",
+            TokenType::Property => "property",
+            TokenType::EnumMember => "enumMember",
+            TokenType::Type => "type",
+            TokenType::TypeParameter => "typeParameter",
+            TokenType::Contract => "interface",
+        }
+    }
+
+    /// The index of the type in the legend.
+    pub fn index(self) -> usize {
+        TokenType::LEGEND.iter().position(|ty| *ty == self).unwrap()
+    }
+}
+
+/// A classified identifier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    pub span: RawSpan,
+    pub token_type: TokenType,
+    /// The modifiers, as a combination of [`DECLARATION`](constant.DECLARATION.html) and
+    /// [`DEFAULT_LIBRARY`](constant.DEFAULT_LIBRARY.html).
+    pub modifiers: u32,
+}
+
+/// Return the tokens of a document, sorted by position and without overlap.
+///
+/// `types` are the types of the occurrences of variables inferred by the typechecker (see
+/// [`type_check_vars`](../../typecheck/fn.type_check_vars.html)), and `source` is the content of
+/// the document.
+pub fn semantic_tokens<'a>(
+    analysis: &Analysis<'a>,
+    term: &'a RichTerm,
+    types: &HashMap<RawSpan, Types>,
+    file_id: FileId,
+    source: &str,
+) -> Vec<SemanticToken> {
+    let mut collector = Collector {
+        analysis,
+        types,
+        file_id,
+        tokens: Vec::new(),
+    };
+    collector.term(term);
+    collector.keywords(source);
+
+    // The names introduced by desugaring may be written at the same place as the original ones,
+    // which come first.
+    let mut tokens = collector.tokens;
+    tokens.sort_by_key(|token| token.span.start);
+    let mut end = ByteIndex(0);
+    tokens.retain(|token| {
+        let keep = token.span.start >= end;
+        if keep {
+            end = token.span.end;
+        }
+        keep
+    });
+
+    tokens
+}
+
+struct Collector<'b, 'a> {
+    analysis: &'b Analysis<'a>,
+    types: &'b HashMap<RawSpan, Types>,
+    file_id: FileId,
+    tokens: Vec<SemanticToken>,
+}
+
+impl<'b, 'a> Collector<'b, 'a> {
+    /// Add a token, if it is written in the document.
+    fn push(&mut self, pos: TermPos, token_type: TokenType, modifiers: u32) {
+        if let TermPos::Original(span) = pos {
+            if span.src_id == self.file_id && span.start < span.end {
+                self.tokens.push(SemanticToken {
+                    span,
+                    token_type,
+                    modifiers,
+                });
+            }
+        }
+    }
+
+    fn term(&mut self, rt: &'a RichTerm) {
+        match rt.term.as_ref() {
+            Term::Var(_) => self.variable(rt),
+            Term::Let(id, bound, body) => {
+                self.push(id.pos, value_type(bound, TokenType::Variable), DECLARATION);
+                self.term(bound);
+                self.term(body);
+            }
+            Term::Fun(id, body) => {
+                self.push(id.pos, TokenType::Parameter, DECLARATION);
+                self.term(body);
+            }
+            Term::Record(map) | Term::RecRecord(map) => {
+                for (id, value) in map {
+                    self.push(id.pos, value_type(value, TokenType::Property), DECLARATION);
+                    self.term(value);
+                }
+            }
+            Term::Op1(UnaryOp::StaticAccess(id), record) => {
+                self.field(id, record, TokenType::Property);
+                self.term(record);
+            }
+            Term::Enum(id) => self.push(id.pos, TokenType::EnumMember, 0),
+            Term::Switch(exp, cases, default) => {
+                self.term(exp);
+                for (id, case) in cases {
+                    self.push(id.pos, TokenType::EnumMember, 0);
+                    self.term(case);
+                }
+                default.iter().for_each(|default| self.term(default));
+            }
+            Term::MetaValue(meta) => {
+                for Contract { types, .. } in meta.types.iter().chain(meta.contracts.iter()) {
+                    self.types(types, &[]);
+                }
+                meta.value.iter().for_each(|value| self.term(value));
+            }
+            Term::Promise(ty, _, t) => {
+                self.types(ty, &[]);
+                self.term(t);
+            }
+            t => t.for_each_subterm(|sub| self.term(sub)),
+        }
+    }
+
+    /// Add the token of a variable, classified according to its binding.
+    fn variable(&mut self, rt: &'a RichTerm) {
+        let span = match rt.pos {
+            TermPos::Original(span) => span,
+            _ => return,
+        };
+        let is_arrow = self.types.get(&span).is_some_and(is_arrow);
+
+        let (token_type, modifiers) = match self.analysis.definition(&span) {
+            Some(binding) => {
+                let default = match binding.kind {
+                    BindingKind::Param => TokenType::Parameter,
+                    BindingKind::Field => TokenType::Property,
+                    BindingKind::Global | BindingKind::Let => TokenType::Variable,
+                };
+                let token_type = match binding.value {
+                    _ if is_arrow => TokenType::Function,
+                    Some(value) => value_type(value, default),
+                    None => default,
+                };
+                let modifiers = if binding.kind == BindingKind::Global {
+                    DEFAULT_LIBRARY
+                } else {
+                    0
+                };
+                (token_type, modifiers)
+            }
+            None if is_arrow => (TokenType::Function, 0),
+            None => (TokenType::Variable, 0),
+        };
+
+        self.push(rt.pos, token_type, modifiers);
+    }
+
+    /// Add the token of the field of a field access. The field is a function if one of its
+    /// definitions is.
+    fn field(&mut self, id: &Ident, record: &'a RichTerm, default: TokenType) {
+        let definitions = self.analysis.field_definitions(record, id);
+        let token_type = if definitions.iter().any(|(_, value)| is_function(value)) {
+            TokenType::Function
+        } else {
+            default
+        };
+        let modifiers = if self.is_global(record) {
+            DEFAULT_LIBRARY
+        } else {
+            0
+        };
+
+        self.push(id.pos, token_type, modifiers);
+    }
+
+    /// Return `true` if a term is a field of the standard library, or a field of such a field.
+    fn is_global(&self, rt: &RichTerm) -> bool {
+        match (rt.term.as_ref(), rt.pos) {
+            (Term::Var(_), TermPos::Original(span)) => self
+                .analysis
+                .definition(&span)
+                .is_some_and(|binding| binding.kind == BindingKind::Global),
+            (Term::Op1(UnaryOp::StaticAccess(_), record), _) => self.is_global(record),
+            _ => false,
+        }
+    }
+
+    /// Add the tokens of a type. `vars` are the type variables in scope.
+    fn types(&mut self, ty: &'a Types, vars: &[&'a Ident]) {
+        match &ty.0 {
+            AbsType::Var(id) if vars.contains(&id) => {
+                self.push(id.pos, TokenType::TypeParameter, 0)
+            }
+            AbsType::Var(id) => self.push(id.pos, TokenType::Type, 0),
+            AbsType::Forall(id, body) => {
+                self.push(id.pos, TokenType::TypeParameter, DECLARATION);
+                let mut vars = vars.to_vec();
+                vars.push(id);
+                self.types(body, &vars);
+            }
+            AbsType::Flat(rt) => self.contract(rt),
+            AbsType::Arrow(domain, codomain) => {
+                self.types(domain, vars);
+                self.types(codomain, vars);
+            }
+            AbsType::List(ty) | AbsType::DynRecord(ty) => self.types(ty, vars),
+            AbsType::Enum(row) => self.row(row, TokenType::EnumMember, vars),
+            AbsType::StaticRecord(row) => self.row(row, TokenType::Property, vars),
+            AbsType::RowExtend(..) => self.row(ty, TokenType::Property, vars),
+            _ => (),
+        }
+    }
+
+    /// Add the tokens of the row of an enum type or of a record type.
+    fn row(&mut self, ty: &'a Types, token_type: TokenType, vars: &[&'a Ident]) {
+        match &ty.0 {
+            AbsType::RowExtend(id, field, tail) => {
+                self.push(id.pos, token_type, 0);
+                field.iter().for_each(|field| self.types(field, vars));
+                self.row(tail, token_type, vars);
+            }
+            _ => self.types(ty, vars),
+        }
+    }
+
+    /// Add the tokens of a contract: its name is the variable or the accessed field it is made
+    /// of, if any.
+    fn contract(&mut self, rt: &'a RichTerm) {
+        let modifiers = if self.is_global(rt) {
+            DEFAULT_LIBRARY
+        } else {
+            0
+        };
+
+        match rt.term.as_ref() {
+            Term::Var(_) => self.push(rt.pos, TokenType::Contract, modifiers),
+            Term::Op1(UnaryOp::StaticAccess(id), record) => {
+                self.push(id.pos, TokenType::Contract, modifiers);
+                self.term(record);
+            }
+            _ => self.term(rt),
+        }
+    }
+
+    /// Add the tokens of the builtin types, which are keywords and don't have a position in the
+    /// parsed term.
+    fn keywords(&mut self, source: &str) {
+        for token in Lexer::new(source) {
+            match token {
+                Ok((
+                    start,
+                    Token::Normal(
+                        NormalToken::Dyn
+                        | NormalToken::Num
+                        | NormalToken::Bool
+                        | NormalToken::Str
+                        | NormalToken::List,
+                    ),
+                    end,
+                )) => {
+                    let span = RawSpan {
+                        src_id: self.file_id,
+                        start: ByteIndex(start as u32),
+                        end: ByteIndex(end as u32),
+                    };
+                    self.push(TermPos::Original(span), TokenType::Type, DEFAULT_LIBRARY);
+                }
+                Ok(_) => (),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// Return the type of the token of a name bound to a value: a function if the value is one, and
+/// the given default otherwise.
+fn value_type(value: &RichTerm, default: TokenType) -> TokenType {
+    if is_function(value) {
+        TokenType::Function
+    } else {
+        default
+    }
+}
+
+/// Return `true` if a type is a function type, possibly polymorphic.
+fn is_arrow(ty: &Types) -> bool {
+    match &ty.0 {
+        AbsType::Arrow(..) => true,
+        AbsType::Forall(_, body) => is_arrow(body),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::grammar;
+    use crate::typecheck::{type_check_vars, Environment};
+    use codespan::Files;
+
+    /// Return the text of the tokens of `source`, with their type and modifiers. `lists` is bound
+    /// to a record of the standard library with a `length` function.
+    fn tokens(source: &str) -> Vec<(String, TokenType, u32)> {
+        let mut files = Files::new();
+        let file_id = files.add("<test>", String::from(source));
+        let stdlib = "{length = fun l => 0, Nat = fun l x => x}";
+        let stdlib_id = files.add("<stdlib>", String::from(stdlib));
+
+        let parse = |file_id, source| {
+            grammar::TermParser::new()
+                .parse_strict(file_id, Lexer::new(source))
+                .unwrap()
+        };
+        let rt = parse(file_id, source);
+        let mut globals = HashMap::new();
+        globals.insert(Ident::from("lists"), parse(stdlib_id, stdlib));
+
+        let (_, types) = type_check_vars(&rt, &Environment::new(), &crate::cache::Cache::new());
+        let types = types.into_iter().collect();
+        let imports = HashMap::new();
+        let analysis = Analysis::new(&rt, &globals, &imports);
+
+        semantic_tokens(&analysis, &rt, &types, file_id, source)
+            .into_iter()
+            .map(|token| {
+                let text = &source[token.span.start.to_usize()..token.span.end.to_usize()];
+                (String::from(text), token.token_type, token.modifiers)
+            })
+            .collect()
+    }
+
+    fn token(text: &str, token_type: TokenType, modifiers: u32) -> (String, TokenType, u32) {
+        (String::from(text), token_type, modifiers)
+    }
+
+    #[test]
+    fn terms() {
+        use TokenType::*;
+
+        assert_eq!(
+            tokens("let f = fun x => x in {a = f 1, b.c = `Foo}.b.c"),
+            vec![
+                token("f", Function, DECLARATION),
+                token("x", Parameter, DECLARATION),
+                token("x", Parameter, 0),
+                token("a", Property, DECLARATION),
+                token("f", Function, 0),
+                token("b", Property, DECLARATION),
+                token("c", Property, DECLARATION),
+                token("Foo", EnumMember, 0),
+                token("b", Property, 0),
+                token("c", Property, 0),
+            ]
+        );
+        assert_eq!(
+            tokens("lists.length (switch {foo => 1, _ => 2} `foo)"),
+            vec![
+                token("lists", Variable, DEFAULT_LIBRARY),
+                token("length", Function, DEFAULT_LIBRARY),
+                token("foo", EnumMember, 0),
+                token("foo", EnumMember, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn types() {
+        use TokenType::*;
+
+        assert_eq!(
+            tokens("let f : (Num -> Num) -> Num = fun g => g 1 in f"),
+            vec![
+                token("f", Function, DECLARATION),
+                token("Num", Type, DEFAULT_LIBRARY),
+                token("Num", Type, DEFAULT_LIBRARY),
+                token("Num", Type, DEFAULT_LIBRARY),
+                token("g", Parameter, DECLARATION),
+                token("g", Function, 0),
+                token("f", Function, 0),
+            ]
+        );
+        assert_eq!(
+            tokens("let id : forall a. a -> <foo, bar> = fun x => x in id"),
+            vec![
+                token("id", Function, DECLARATION),
+                token("a", TypeParameter, DECLARATION),
+                token("a", TypeParameter, 0),
+                token("foo", EnumMember, 0),
+                token("bar", EnumMember, 0),
+                token("x", Parameter, DECLARATION),
+                token("x", Parameter, 0),
+                token("id", Function, 0),
+            ]
+        );
+        assert_eq!(
+            tokens("{port | #(lists.Nat) | {x : Str} = 80}"),
+            vec![
+                token("port", Property, DECLARATION),
+                token("lists", Variable, DEFAULT_LIBRARY),
+                token("Nat", Contract, DEFAULT_LIBRARY),
+                token("x", Property, 0),
+                token("Str", Type, DEFAULT_LIBRARY),
+            ]
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! Symbols: the outline of a document, made of its let bindings and of its nested record fields,
+//! and the fuzzy matching of symbol names used to search the symbols of a workspace.
+use super::hover::annotated_type;
+use crate::identifier::Ident;
+use crate::position::{RawSpan, TermPos};
+use crate::term::{RichTerm, Term};
+use codespan::FileId;
+
+/// The kind of a symbol.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SymbolKind {
+    /// A let binding.
+    Variable,
+    /// A record field.
+    Field,
+    /// A let binding or a record field bound to a function.
+    Function,
+}
+
+impl SymbolKind {
+    /// The code of the kind in the language server protocol.
+    pub fn code(self) -> u8 {
+        match self {
+            SymbolKind::Variable => 13,
+            SymbolKind::Field => 8,
+            SymbolKind::Function => 12,
+        }
+    }
+}
+
+/// A symbol of a document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The type annotation or the contracts of the symbol, if any.
+    pub detail: Option<String>,
+    /// The span of the whole definition, from the name to the end of the bound term.
+    pub span: RawSpan,
+    /// The span of the name.
+    pub name_span: RawSpan,
+    /// The symbols defined inside the bound term, such as the fields of a record.
+    pub children: Vec<Symbol>,
+}
+
+/// Return the symbols of a document, in the order of their definitions. Only the symbols whose
+/// name is written in the given file are returned: the names generated by the parser, or coming
+/// from another file, don't have a position in the document.
+pub fn document_symbols(rt: &RichTerm, file_id: FileId) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    collect_symbols(rt, file_id, &mut symbols);
+    symbols
+}
+
+fn collect_symbols(rt: &RichTerm, file_id: FileId, symbols: &mut Vec<Symbol>) {
+    match rt.term.as_ref() {
+        Term::Let(id, bound, body) => {
+            push_symbol(id, bound, SymbolKind::Variable, file_id, symbols);
+            collect_symbols(body, file_id, symbols);
+        }
+        Term::Record(map) | Term::RecRecord(map) => {
+            let mut fields = Vec::new();
+            for (id, value) in map {
+                push_symbol(id, value, SymbolKind::Field, file_id, &mut fields);
+            }
+
+            fields.sort_by_key(|symbol| symbol.span.start);
+            symbols.extend(fields);
+        }
+        t => t.for_each_subterm(|sub| collect_symbols(sub, file_id, symbols)),
+    }
+}
+
+/// Push the symbol of a binding. If its name has no position in the document, push the symbols
+/// of the bound term instead.
+fn push_symbol(
+    id: &Ident,
+    value: &RichTerm,
+    kind: SymbolKind,
+    file_id: FileId,
+    symbols: &mut Vec<Symbol>,
+) {
+    let mut children = Vec::new();
+    collect_symbols(value, file_id, &mut children);
+
+    let name_span = match id.pos {
+        TermPos::Original(span) if span.src_id == file_id => span,
+        _ => {
+            symbols.extend(children);
+            return;
+        }
+    };
+
+    let end = match value.pos {
+        TermPos::Original(span) if span.src_id == file_id && span.end > name_span.end => span.end,
+        _ => name_span.end,
+    };
+
+    symbols.push(Symbol {
+        name: id.label.clone(),
+        kind: if is_function(value) {
+            SymbolKind::Function
+        } else {
+            kind
+        },
+        detail: detail(value),
+        span: RawSpan { end, ..name_span },
+        name_span,
+        children,
+    });
+}
+
+/// Return `true` if a term is a function, possibly with metadata.
+pub fn is_function(rt: &RichTerm) -> bool {
+    match rt.as_ref() {
+        Term::Fun(..) => true,
+        Term::MetaValue(meta) => meta.value.as_ref().is_some_and(is_function),
+        _ => false,
+    }
+}
+
+/// Return the type annotation of a term, or its contracts separated by `|` if it has none.
+pub fn detail(rt: &RichTerm) -> Option<String> {
+    if let Some(ty) = annotated_type(rt) {
+        return Some(ty.to_string());
+    }
+
+    match rt.as_ref() {
+        Term::MetaValue(meta) if !meta.contracts.is_empty() => Some(
+            meta.contracts
+                .iter()
+                .map(|ctr| ctr.types.to_string())
+                .collect::<Vec<_>>()
+                .join(" | "),
+        ),
+        _ => None,
+    }
+}
+
+/// Return the symbols of a document and of their children, each with the dot-separated path of
+/// its parent, if any.
+pub fn flatten(symbols: &[Symbol]) -> Vec<(Option<String>, &Symbol)> {
+    let mut result = Vec::new();
+    flatten_into(symbols, None, &mut result);
+    result
+}
+
+fn flatten_into<'a>(
+    symbols: &'a [Symbol],
+    container: Option<&str>,
+    result: &mut Vec<(Option<String>, &'a Symbol)>,
+) {
+    for symbol in symbols {
+        result.push((container.map(String::from), symbol));
+
+        let path = match container {
+            Some(container) => format!("{}.{}", container, symbol.name),
+            None => symbol.name.clone(),
+        };
+        flatten_into(&symbol.children, Some(&path), result);
+    }
+}
+
+/// Match a name against a query, ignoring case: the name matches if it contains the characters
+/// of the query in the same order. Return the number of characters of the name skipped before
+/// the last matched one, which is lower for better matches, or `None` if the name doesn't match.
+pub fn fuzzy_match(query: &str, name: &str) -> Option<usize> {
+    let mut chars = name.chars().flat_map(char::to_lowercase);
+    let mut skipped = 0;
+
+    for expected in query.chars().flat_map(char::to_lowercase) {
+        loop {
+            match chars.next() {
+                Some(c) if c == expected => break,
+                Some(_) => skipped += 1,
+                None => return None,
+            }
+        }
+    }
+
+    Some(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use codespan::Files;
+
+    /// Return the names of the symbols of `source`, with the names of their children between
+    /// parentheses.
+    fn outline(source: &str) -> String {
+        fn print(symbols: &[Symbol]) -> String {
+            symbols
+                .iter()
+                .map(|symbol| {
+                    if symbol.children.is_empty() {
+                        symbol.name.clone()
+                    } else {
+                        format!("{}({})", symbol.name, print(&symbol.children))
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+
+        let file_id = Files::new().add("<test>", String::from(source));
+        let rt = grammar::TermParser::new()
+            .parse_strict(file_id, lexer::Lexer::new(source))
+            .unwrap();
+        print(&document_symbols(&rt, file_id))
+    }
+
+    #[test]
+    fn outlines() {
+        assert_eq!(
+            outline("let port = 80 in {server = {port = port, host.name = \"a\"}, zone = 1}"),
+            "port server(port host(name)) zone"
+        );
+        assert_eq!(
+            outline("let f = fun x => let y = x in y in {a = 1} & {b = f 2}"),
+            "f(y) a b"
+        );
+    }
+
+    #[test]
+    fn symbols() {
+        let source = "{port | Num = 80, url | Url | Str, handler = fun x => x}";
+        let file_id = Files::new().add("<test>", String::from(source));
+        let rt = grammar::TermParser::new()
+            .parse_strict(file_id, lexer::Lexer::new(source))
+            .unwrap();
+        let symbols = document_symbols(&rt, file_id);
+
+        let port = &symbols[0];
+        assert_eq!(port.kind, SymbolKind::Field);
+        assert_eq!(port.detail, Some(String::from("Num")));
+        assert_eq!(
+            &source[port.span.start.to_usize()..port.span.end.to_usize()],
+            "port | Num = 80"
+        );
+        assert_eq!(port.name_span.end.to_usize(), 5);
+        assert_eq!(symbols[1].detail, Some(String::from("Url | Str")));
+        assert_eq!(symbols[2].kind, SymbolKind::Function);
+    }
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(fuzzy_match("prt", "port"), Some(1));
+        assert_eq!(fuzzy_match("PoRt", "port"), Some(0));
+        assert_eq!(fuzzy_match("", "port"), Some(0));
+        assert_eq!(fuzzy_match("tp", "http"), Some(2));
+        assert_eq!(fuzzy_match("tp", "port"), None);
+    }
+}
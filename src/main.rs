@@ -1,10 +1,37 @@
 //! Entry point of the program.
-use nickel::error::{Error, IOError};
+use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use nickel::diff::{self, Change};
+use nickel::error::{Error, IOError, SerializationError};
+use nickel::explain;
+use nickel::fetch::{Fetched, Store};
+use nickel::lock;
+use nickel::lsp;
+use nickel::manifest::Manifest;
+use nickel::parser::lexer::IdentPolicy;
 use nickel::program::Program;
+use nickel::repl;
+#[cfg(feature = "repl")]
+use nickel::repl::rustyline_frontend;
+use nickel::repl::simple_frontend;
+use nickel::sarif;
+use nickel::serialize::{
+    self,
+    csv::CsvOptions,
+    xml::{RecordMapping, XmlOptions},
+    yaml::{MultilineStyle, YamlOptions, YamlStyle},
+    ExportFormat, ExportOptions, HashAlgorithm, JsonOptions, Secrets, Selection,
+};
+use nickel::snapshot::{self, Outcome};
 use nickel::term::RichTerm;
-use nickel::{repl, repl::rustyline_frontend};
-use nickel::{serialize, serialize::ExportFormat};
-use std::path::PathBuf;
+#[cfg(feature = "trace")]
+use nickel::trace::{self, Timeline};
+use nickel::vfs::OsFileSystem;
+use nickel::warning::{self, Level};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "trace")]
+use std::rc::Rc;
+use std::str::FromStr;
 use std::{fs, process};
 // use std::ffi::OsStr;
 use structopt::StructOpt;
@@ -13,10 +40,35 @@ use structopt::StructOpt;
 #[derive(StructOpt, Debug)]
 /// The interpreter of the Nickel language.
 struct Opt {
-    /// The input file. Standard input by default
+    /// The input file. Default: the first entry point of the `nickel.toml` manifest of the current
+    /// directory if the standard input is a terminal, and the standard input otherwise
     #[structopt(short = "f", long)]
     #[structopt(parse(from_os_str))]
     file: Option<PathBuf>,
+    /// Restrict identifiers to ASCII characters
+    #[structopt(long)]
+    ascii_identifiers: bool,
+    /// Fail if the git and registry dependencies of the package have drifted from its
+    /// `nickel.lock` lockfile, instead of warning about it or, for `nickel fetch`, updating the
+    /// lockfile
+    #[structopt(long)]
+    locked: bool,
+    /// Format of the reported errors: `human` or `sarif`. SARIF logs are written on the standard
+    /// error, and contain no result if the program succeeds. Default: `human`.
+    #[structopt(long, default_value = "human")]
+    error_format: ErrorFormat,
+    /// Silence a kind of warning, given by its name or its code, or all warnings with
+    /// `warnings`. May be given several times
+    #[structopt(long, number_of_values = 1)]
+    allow: Vec<String>,
+    /// Report a kind of warning as an error, given by its name or its code, or all warnings with
+    /// `warnings`. Takes precedence over `--allow` for the same kind. May be given several times
+    #[structopt(long, number_of_values = 1)]
+    deny: Vec<String>,
+    /// Print the time taken by each phase of the interpreter on the standard error
+    #[cfg(feature = "trace")]
+    #[structopt(long)]
+    trace: bool,
     #[structopt(subcommand)]
     command: Option<Command>,
 }
@@ -24,15 +76,83 @@ struct Opt {
 /// Available subcommands.
 #[derive(StructOpt, Debug)]
 enum Command {
+    /// Evaluate a program. This is the default when no subcommand is given
+    Eval {
+        /// Only print the digest of the canonical JSON serialization of the fully evaluated
+        /// result, computed with the given algorithm: `md5`, `sha1`, `sha256` or `sha512`
+        #[structopt(long)]
+        hash: Option<HashAlgorithm>,
+    },
     /// Export the result to a different format
     Export {
-        /// Available formats: `raw, json`. Default format: `json`.
+        /// Available formats: `raw, json, ndjson, yaml, toml, xml, nix, dotenv, ini,
+        /// plist, bplist, csv, tsv`. Default format: `json`.
         #[structopt(long)]
         format: Option<ExportFormat>,
         /// Output file. Standard output by default
         #[structopt(short = "o", long)]
         #[structopt(parse(from_os_str))]
         output: Option<PathBuf>,
+        /// Indent JSON output, using the given number of spaces. Default: 2
+        #[structopt(long, conflicts_with = "compact")]
+        pretty: Option<Option<usize>>,
+        /// Write JSON output on a single line, without any whitespace
+        #[structopt(long)]
+        compact: bool,
+        /// Sort the fields of JSON records. Fields are always written in alphabetical order: this
+        /// flag only makes this choice explicit
+        #[structopt(long)]
+        #[allow(dead_code)]
+        sort_keys: bool,
+        /// Layout of YAML lists and records: `block` or `flow`. Default: `block`.
+        #[structopt(long)]
+        yaml_style: Option<YamlStyle>,
+        /// Representation of YAML multi-line strings: `literal`, `folded` or `quoted`. Default:
+        /// `literal`.
+        #[structopt(long)]
+        yaml_multiline: Option<MultilineStyle>,
+        /// Write identical YAML lists and records once, and refer to them using aliases
+        #[structopt(long)]
+        yaml_aliases: bool,
+        /// Write each element of a list as a separate YAML document
+        #[structopt(long)]
+        yaml_multi_document: bool,
+        /// Mapping of the fields of records to XML: `elements` or `attributes`. Default:
+        /// `elements`.
+        #[structopt(long)]
+        xml_records: Option<RecordMapping>,
+        /// Comma-separated list of the columns of CSV and TSV output. Default: all the fields of
+        /// the records, in alphabetical order.
+        #[structopt(long, use_delimiter = true)]
+        csv_columns: Option<Vec<String>>,
+        /// Only export the field at the given dot-separated path, such as `services.web`
+        #[structopt(long)]
+        field: Option<String>,
+        /// Remove the field at the given dot-separated path, relatively to the exported field.
+        /// May be given several times
+        #[structopt(long, number_of_values = 1)]
+        exclude: Vec<String>,
+        /// Layer a profile declared in the `profiles` field of the result over the rest of the
+        /// result. May be given several times, each profile overriding the previous ones
+        #[structopt(long, number_of_values = 1)]
+        profile: Vec<String>,
+        /// Print the profile each exported value comes from on the standard error
+        #[structopt(long, requires = "profile")]
+        show_origins: bool,
+        /// Replace the values marked as sensitive by a placeholder. By default, exporting a
+        /// sensitive value fails
+        #[structopt(long, conflicts_with = "allow-secrets")]
+        redact: bool,
+        /// Export the values marked as sensitive as they are
+        #[structopt(long)]
+        allow_secrets: bool,
+    },
+    /// Translate a JSON, YAML or TOML file, given by its extension, to Nickel source
+    Convert {
+        /// Annotate the result with a contract, given as a Nickel expression such as `import
+        /// "schema.ncl"`
+        #[structopt(long)]
+        contract: Option<String>,
     },
     /// Print the metadata attached to an attribute, given as a path
     Query {
@@ -40,6 +160,8 @@ enum Command {
         #[structopt(long)]
         doc: bool,
         #[structopt(long)]
+        types: bool,
+        #[structopt(long)]
         contract: bool,
         #[structopt(long)]
         default: bool,
@@ -48,84 +170,671 @@ enum Command {
     },
     /// Typecheck a program, but do not run it
     Typecheck,
+    /// Write the documentation of a program as browsable HTML pages, one per record, with a
+    /// search box
+    Doc {
+        /// Output directory. Default: `doc`
+        #[structopt(short = "o", long)]
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Evaluate programs and check that they succeed
+    Test {
+        /// The programs to test. Default: the entry points of the `nickel.toml` manifest of the
+        /// current directory
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+        /// Also compare the fully evaluated result of each program with its snapshot, the
+        /// canonical JSON file `<program>.snap.json`
+        #[structopt(long)]
+        snapshot: bool,
+        /// Write the snapshots which are missing or differ from the results, instead of failing
+        #[structopt(long, requires = "snapshot")]
+        update: bool,
+        /// Directory of the snapshots. Default: the directory of each program
+        #[structopt(long, requires = "snapshot")]
+        #[structopt(parse(from_os_str))]
+        snapshot_dir: Option<PathBuf>,
+    },
+    /// Print the structural differences between the fully evaluated results of two programs, as
+    /// the paths of the fields and elements which have changed, been added or been removed. Exit
+    /// with status 1 if the results differ
+    Diff {
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+    },
+    /// Fetch the git and registry dependencies of the package of the current directory, as
+    /// declared by its `nickel.toml` manifest, into the local store, and write its `nickel.lock`
+    /// lockfile. Git dependencies are fetched at their locked commit. Missing dependencies are
+    /// also fetched before evaluating a program of the package, without writing the lockfile
+    Fetch {
+        /// Fetch the git dependencies again at the current commit of their revision, ignoring
+        /// the lockfile
+        #[structopt(long)]
+        update: bool,
+    },
     /// Start an REPL session
-    REPL,
+    REPL {
+        /// Run the inputs and commands of a file instead, write them to the standard output
+        /// together with their results, and exit with status 1 if one of them fails
+        #[structopt(long)]
+        #[structopt(parse(from_os_str))]
+        script: Option<PathBuf>,
+        /// Read the inputs from the script, or from the standard input if no script is given, and
+        /// write one JSON object per input with its type, its value and its diagnostics
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Print the extended explanation of an error code, such as `N0301`
+    Explain { code: String },
+    /// Start a language server communicating on the standard input and output
+    Lsp,
+    /// Print the references to the symbol at the given position of a file, including its
+    /// definitions, one `path:line:column` per line
+    Refs {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// The position of the symbol, as `line:column`, both starting at 1
+        position: Position,
+    },
+}
+
+/// A position in a file, as given on the command line.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid position: {} (expected `line:column`)", s);
+        let (line, column) = s.split_once(':').ok_or_else(invalid)?;
+
+        Ok(Position {
+            line: line.parse().map_err(|_| invalid())?,
+            column: column.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Format of the reported errors.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum ErrorFormat {
+    Human,
+    Sarif,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "human" => Ok(ErrorFormat::Human),
+            "sarif" => Ok(ErrorFormat::Sarif),
+            _ => Err(format!("unknown error format: {}", s)),
+        }
+    }
 }
 
 fn main() {
     let opts = Opt::from_args();
 
-    if let Some(Command::REPL) = opts.command {
+    if let Some(Command::REPL { script, json }) = &opts.command {
+        if script.is_some() || *json {
+            let format = if *json {
+                simple_frontend::Format::Json
+            } else {
+                simple_frontend::Format::Text
+            };
+            let stdout = std::io::stdout();
+            let result = match script {
+                Some(script) => simple_frontend::run_script(script, format, &mut stdout.lock()),
+                None => simple_frontend::run(
+                    std::io::stdin().lock().lines(),
+                    format,
+                    &mut stdout.lock(),
+                ),
+            };
+
+            match result {
+                Ok(steps) if steps.iter().all(|step| step.is_success()) => (),
+                Ok(_) => process::exit(1),
+                Err(Error::IOError(IOError(msg))) => {
+                    eprintln!("error: {}", msg);
+                    process::exit(1);
+                }
+                Err(err) => {
+                    eprintln!("error: {:?}", err);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+
         #[cfg(feature = "repl")]
-        if rustyline_frontend::repl().is_err() {
+        if rustyline_frontend::repl(&rustyline_frontend::Options::default()).is_err() {
             process::exit(1);
         }
 
         #[cfg(not(feature = "repl"))]
         eprintln!("error: this executable was not compiled with REPL support");
+    } else if let Some(Command::Lsp) = opts.command {
+        if let Err(err) = lsp::run() {
+            eprintln!("error: {:?}", err);
+            process::exit(1);
+        }
+    } else if let Some(Command::Explain { code }) = &opts.command {
+        match explain::lookup(code) {
+            Some(error_code) => println!(
+                "{}: {}\n\n{}",
+                error_code.code, error_code.title, error_code.explanation
+            ),
+            None => {
+                eprintln!("error: unknown error code {}", code);
+                process::exit(1);
+            }
+        }
+    } else if let Some(Command::Refs { file, position }) = &opts.command {
+        match lsp::file_references(file, position.line, position.column) {
+            Ok(references) => references.iter().for_each(|r| println!("{}", r)),
+            Err(err) => {
+                eprintln!("error: {:?}", err);
+                process::exit(1);
+            }
+        }
+    } else if let Some(Command::Fetch { update }) = opts.command {
+        if let Err(msg) = fetch(opts.locked, update) {
+            eprintln!("error: {}", msg);
+            process::exit(1);
+        }
+    } else if let Some(Command::Diff { old, new }) = &opts.command {
+        match diff(old, new, opts.ascii_identifiers, opts.locked) {
+            Some(true) => (),
+            Some(false) => process::exit(1),
+            None => process::exit(2),
+        }
+    } else if let Some(Command::Test {
+        files,
+        snapshot,
+        update,
+        snapshot_dir,
+    }) = &opts.command
+    {
+        let files = if files.is_empty() {
+            match manifest(None) {
+                Ok(Some(manifest)) if !manifest.entries.is_empty() => manifest.entries,
+                Ok(_) => {
+                    eprintln!("error: no program to test, and no entry point in a manifest");
+                    process::exit(1);
+                }
+                Err(IOError(msg)) => {
+                    eprintln!("error: {}", msg);
+                    process::exit(1);
+                }
+            }
+        } else {
+            files.clone()
+        };
+        let options = TestOptions {
+            ascii_identifiers: opts.ascii_identifiers,
+            locked: opts.locked,
+            snapshot: *snapshot,
+            update: *update,
+            snapshot_dir: snapshot_dir.as_deref(),
+        };
+        if !test(&files, &options) {
+            process::exit(1);
+        }
     } else {
-        let mut program = opts
-            .file
-            .map(Program::new_from_file)
-            .unwrap_or_else(Program::new_from_stdin)
-            .unwrap_or_else(|err| {
-                eprintln!("Error when reading input: {}", err);
+        let mut program = load_program(opts.file.as_deref(), opts.locked).unwrap_or_else(|msg| {
+            eprintln!("Error when reading input: {}", msg);
+            process::exit(1)
+        });
+
+        if opts.ascii_identifiers {
+            program.set_ident_policy(IdentPolicy::Ascii);
+        }
+
+        let mut config = warning::Config::new();
+        let levels = opts
+            .allow
+            .iter()
+            .map(|name| (name, Level::Allow))
+            .chain(opts.deny.iter().map(|name| (name, Level::Deny)));
+        for (name, level) in levels {
+            if let Err(msg) = config.set(name, level) {
+                eprintln!("error: {}", msg);
+                process::exit(1);
+            }
+        }
+        program.set_warning_config(config);
+
+        #[cfg(feature = "trace")]
+        let timeline = if opts.trace {
+            let timeline = Rc::new(Timeline::new());
+            trace::set_subscriber(Some(timeline.clone()));
+            Some(timeline)
+        } else {
+            None
+        };
+
+        let (warnings, result) = match program.warnings() {
+            Ok(warnings) => {
+                if opts.error_format == ErrorFormat::Human {
+                    let _ = program.report_warnings(&mut stderr(), &warnings);
+                }
+                let result = run(&mut program, opts.command);
+                (warnings, result)
+            }
+            Err(err) => (Vec::new(), Err(err)),
+        };
+
+        #[cfg(feature = "trace")]
+        if let Some(timeline) = timeline {
+            eprint!("{}", timeline);
+        }
+
+        match (opts.error_format, result) {
+            (ErrorFormat::Human, Ok(())) => (),
+            (ErrorFormat::Human, Err(err)) => {
+                let _ = program.report(&mut stderr(), err);
                 process::exit(1)
-            });
-
-        let result = match opts.command {
-            Some(Command::Export { format, output }) => export(&mut program, format, output),
-            Some(Command::Query {
-                path,
-                doc,
-                contract,
-                default,
-                value,
-            }) => {
-                program.query(path).map(|term| {
-                    // Print a default selection of attributes if no option is specified
-                    let attrs = if !doc && !contract && !default && !value {
-                        repl::query_print::Attributes::default()
-                    } else {
-                        repl::query_print::Attributes {
-                            doc,
-                            contract,
-                            default,
-                            value,
-                        }
-                    };
-
-                    repl::query_print::print_query_result(&term, attrs)
-                })
             }
-            Some(Command::Typecheck) => program.typecheck().map(|_| ()),
-            Some(Command::REPL) => unreachable!(),
-            None => program.eval().map(|t| println!("Done: {:?}", t)),
+            (ErrorFormat::Sarif, result) => {
+                let failed = result.is_err();
+                let errors: Vec<_> = warnings
+                    .iter()
+                    .map(|w| {
+                        let rule = sarif::warning_rule_id(w.kind());
+                        (rule, vec![w.diagnostic(Level::Warn)])
+                    })
+                    .chain(result.err().map(|err| {
+                        let rule = sarif::rule_id(&err);
+                        (rule, program.diagnostics(err))
+                    }))
+                    .collect();
+                eprintln!("{:#}", sarif::to_sarif(program.files(), &errors));
+
+                if failed {
+                    process::exit(1)
+                }
+            }
+        }
+    }
+}
+
+/// Run a command which operates on a program.
+fn run(program: &mut Program, command: Option<Command>) -> Result<(), Error> {
+    match command {
+        Some(Command::Export {
+            format,
+            output,
+            pretty,
+            compact,
+            sort_keys: _,
+            yaml_style,
+            yaml_multiline,
+            yaml_aliases,
+            yaml_multi_document,
+            xml_records,
+            csv_columns,
+            field,
+            exclude,
+            profile,
+            show_origins,
+            redact,
+            allow_secrets,
+        }) => {
+            let indent = if compact {
+                None
+            } else {
+                Some(pretty.flatten().unwrap_or(2))
+            };
+            let options = ExportOptions {
+                json: JsonOptions { indent },
+                yaml: YamlOptions {
+                    style: yaml_style.unwrap_or_default(),
+                    multiline: yaml_multiline.unwrap_or_default(),
+                    aliases: yaml_aliases,
+                    multi_document: yaml_multi_document,
+                },
+                xml: XmlOptions {
+                    records: xml_records.unwrap_or_default(),
+                },
+                csv: CsvOptions {
+                    columns: csv_columns,
+                },
+                selection: Selection { field, exclude },
+                profiles: profile,
+                secrets: if redact {
+                    Secrets::Redact
+                } else if allow_secrets {
+                    Secrets::Allow
+                } else {
+                    Secrets::Reject
+                },
+            };
+            export(program, format, output, &options, show_origins)
+        }
+        Some(Command::Convert { contract }) => program
+            .eval_full()
+            .map(RichTerm::from)
+            .and_then(|rt| Ok(serialize::nickel::to_string(&rt, contract.as_deref())?))
+            .map(|source| print!("{}", source)),
+        Some(Command::Query {
+            path,
+            doc,
+            types,
+            contract,
+            default,
+            value,
+        }) => {
+            program.query(path).map(|term| {
+                // Print a default selection of attributes if no option is specified
+                let attrs = if !doc && !types && !contract && !default && !value {
+                    repl::query_print::Attributes::default()
+                } else {
+                    repl::query_print::Attributes {
+                        doc,
+                        types,
+                        contract,
+                        default,
+                        value,
+                    }
+                };
+
+                repl::query_print::print_query_result(&term, attrs)
+            })
+        }
+        Some(Command::Typecheck) => program.typecheck().map(|_| ()),
+        Some(Command::Doc { output }) => {
+            program.doc(&output.unwrap_or_else(|| PathBuf::from("doc")))
+        }
+        Some(Command::REPL { .. })
+        | Some(Command::Lsp)
+        | Some(Command::Explain { .. })
+        | Some(Command::Refs { .. })
+        | Some(Command::Diff { .. })
+        | Some(Command::Fetch { .. })
+        | Some(Command::Test { .. }) => unreachable!(),
+        Some(Command::Eval {
+            hash: Some(algorithm),
+        }) => program
+            .eval_full()
+            .map(RichTerm::from)
+            .and_then(|rt| Ok(serialize::canonical_hash(&rt, algorithm)?))
+            .map(|digest| println!("{}", digest)),
+        Some(Command::Eval { hash: None }) | None => {
+            program.eval().map(|t| println!("Done: {:?}", t))
+        }
+    }
+}
+
+/// Find the manifest of the package of a file, or of the current directory if there is no file.
+fn manifest(file: Option<&Path>) -> Result<Option<Manifest>, IOError> {
+    let dir = match file.and_then(Path::parent) {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let dir = dir.canonicalize().map_err(IOError::from)?;
+    Manifest::discover(&OsFileSystem, &dir)
+}
+
+/// Fetch the dependencies of the package of the current directory, or fetch the git
+/// dependencies again if `update` is `true`, print the ones which have been fetched, and update
+/// or verify the lockfile.
+fn fetch(locked: bool, update: bool) -> Result<(), String> {
+    let manifest = manifest(None)
+        .map_err(|IOError(msg)| msg)?
+        .ok_or_else(|| format!("no {} manifest found", nickel::manifest::FILE_NAME))?;
+
+    let store = Store::for_package(&manifest).map_err(|IOError(msg)| msg)?;
+    let fetched = if update {
+        store.update_all(&manifest)
+    } else {
+        store.fetch_all(&manifest)
+    }
+    .map_err(|IOError(msg)| msg)?;
+
+    for fetched in fetched.iter().filter(|fetched| fetched.new) {
+        println!("fetched {}", fetched.dependency.name);
+    }
+    lock::sync(&manifest, &fetched, locked).map_err(|IOError(msg)| msg)
+}
+
+/// Fetch the missing dependencies of a package into a store, and verify the lockfile if `locked`
+/// is `true`. Otherwise, only warn if the lockfile is out of date: it is written by
+/// `nickel fetch`, not as a side effect of an evaluation.
+fn fetch_dependencies(
+    manifest: &Manifest,
+    store: &Store,
+    locked: bool,
+) -> Result<Vec<Fetched>, String> {
+    let fetched = store.fetch_all(manifest).map_err(|IOError(msg)| msg)?;
+
+    if locked {
+        lock::sync(manifest, &fetched, true).map_err(|IOError(msg)| msg)?;
+    } else if !lock::check(manifest, &fetched)
+        .map_err(|IOError(msg)| msg)?
+        .is_empty()
+    {
+        eprintln!(
+            "warning: the lockfile of the package is out of date, run `nickel fetch` to update it"
+        );
+    }
+    Ok(fetched)
+}
+
+/// Load a program, resolving its imports as in the package of the file, if any, whose missing
+/// dependencies are fetched. Without a file, load the main program of the package of the
+/// current directory if the standard input is a terminal, and the standard input otherwise.
+fn load_program(file: Option<&Path>, locked: bool) -> Result<Program, String> {
+    let manifest = manifest(file).map_err(|IOError(msg)| msg)?;
+    let store = match &manifest {
+        Some(manifest) => {
+            let store = Store::for_package(manifest).map_err(|IOError(msg)| msg)?;
+            for fetched in fetch_dependencies(manifest, &store, locked)? {
+                if fetched.new {
+                    eprintln!("fetched {}", fetched.dependency.name);
+                }
+            }
+            Some(store)
+        }
+        None => None,
+    };
+    let main = manifest.as_ref().and_then(Manifest::main);
+
+    let builder = match (file, main) {
+        (Some(file), _) => Program::builder().source_file(file),
+        (None, Some(main)) if std::io::stdin().is_terminal() => {
+            Program::builder().source_file(main)
+        }
+        (None, _) => Program::builder().source_stdin(),
+    };
+    let builder = match (manifest, store) {
+        (Some(manifest), Some(store)) => builder.manifest(manifest).store(store),
+        _ => builder,
+    };
+
+    builder.build().map_err(|err| match err {
+        Error::IOError(IOError(msg)) => msg,
+        err => format!("{:?}", err),
+    })
+}
+
+/// Options of the `test` subcommand.
+struct TestOptions<'a> {
+    ascii_identifiers: bool,
+    locked: bool,
+    snapshot: bool,
+    update: bool,
+    snapshot_dir: Option<&'a Path>,
+}
+
+/// Evaluate each program, comparing its result with its snapshot if requested, and print a
+/// report. Return `true` if all the tests passed.
+fn test(files: &[PathBuf], options: &TestOptions) -> bool {
+    let mut failed = 0;
+
+    for file in files {
+        let name = file.to_string_lossy();
+        let mut program = match load_program(Some(file), options.locked) {
+            Ok(program) => program,
+            Err(msg) => {
+                println!("test {} ... error: {}", name, msg);
+                failed += 1;
+                continue;
+            }
         };
+        if options.ascii_identifiers {
+            program.set_ident_policy(IdentPolicy::Ascii);
+        }
 
-        if let Err(err) = result {
-            program.report(err);
-            process::exit(1)
+        let result = if options.snapshot {
+            let path = snapshot::snapshot_path(file, options.snapshot_dir);
+            snapshot::check(&mut program, &path, options.update)
+        } else {
+            program.eval_full().map(|_| Outcome::Matched)
+        };
+
+        match result {
+            Ok(outcome) => {
+                let (status, changes) = match &outcome {
+                    Outcome::Matched => ("ok", None),
+                    Outcome::Mismatched(changes) => (
+                        "FAILED: the result differs from the snapshot",
+                        Some(changes),
+                    ),
+                    Outcome::Missing => (
+                        "FAILED: no snapshot, run with `--update` to create it",
+                        None,
+                    ),
+                    Outcome::Created => ("snapshot created", None),
+                    Outcome::Updated(changes) => ("snapshot updated", Some(changes)),
+                };
+                println!("test {} ... {}", name, status);
+                if let Some(changes) = changes {
+                    print_changes(changes, 4);
+                }
+
+                if !outcome.is_success() {
+                    failed += 1;
+                }
+            }
+            Err(err) => {
+                println!("test {} ... FAILED", name);
+                let _ = program.report(&mut stderr(), err);
+                failed += 1;
+            }
         }
     }
+
+    println!("\n{} passed; {} failed", files.len() - failed, failed);
+    failed == 0
+}
+
+/// Print the differences between the results of two programs. Return `Some(true)` if they are
+/// the same, or `None` if one of them could not be evaluated, after reporting the error.
+fn diff(old: &Path, new: &Path, ascii_identifiers: bool, locked: bool) -> Option<bool> {
+    let eval = |file: &Path| {
+        let mut program = match load_program(Some(file), locked) {
+            Ok(program) => program,
+            Err(msg) => {
+                eprintln!("error: could not read {}: {}", file.to_string_lossy(), msg);
+                return None;
+            }
+        };
+        if ascii_identifiers {
+            program.set_ident_policy(IdentPolicy::Ascii);
+        }
+
+        program
+            .eval_full()
+            .map_err(|err| {
+                let _ = program.report(&mut stderr(), err);
+            })
+            .ok()
+            .map(RichTerm::from)
+    };
+
+    let changes = diff::diff_terms(&eval(old)?, &eval(new)?);
+    print_changes(&changes, 0);
+    Some(changes.is_empty())
+}
+
+/// The standard error, where errors and warnings are reported. Failing to write them is ignored,
+/// as there is nowhere else to report it.
+fn stderr() -> StandardStream {
+    StandardStream::stderr(ColorChoice::Always)
+}
+
+/// Print changes on the standard output, colored if it is a terminal.
+fn print_changes(changes: &[Change], indent: usize) {
+    let choice = if std::io::stdout().is_terminal() {
+        ColorChoice::Auto
+    } else {
+        ColorChoice::Never
+    };
+    let stdout = StandardStream::stdout(choice);
+
+    let result = diff::write(&mut stdout.lock(), changes, indent);
+    if let Err(err) = result {
+        eprintln!("error: could not write to the standard output: {}", err);
+        process::exit(2);
+    }
 }
 
 fn export(
     program: &mut Program,
     format: Option<ExportFormat>,
     output: Option<PathBuf>,
+    options: &ExportOptions,
+    show_origins: bool,
 ) -> Result<(), Error> {
-    let rt = program.eval_full().map(RichTerm::from)?;
     let format = format.unwrap_or_default();
 
-    serialize::validate(format, &rt)?;
-
-    if let Some(file) = output {
-        let file = fs::File::create(&file).map_err(IOError::from)?;
-        serialize::to_writer(file, format, &rt)?;
+    let writer: Box<dyn Write> = if let Some(file) = output {
+        Box::new(fs::File::create(&file).map_err(IOError::from)?)
     } else {
-        serialize::to_writer(std::io::stdout(), format, &rt)?;
+        Box::new(std::io::stdout())
+    };
+
+    // Lists can only be streamed if they are exported as a whole.
+    if format == ExportFormat::Ndjson && options.selection.is_all() && options.profiles.is_empty() {
+        return export_ndjson(program, writer, options.secrets);
     }
 
+    let origins = program.export_with_origins(writer, format, options)?;
+    if show_origins {
+        eprintln!("origins of the exported values:");
+        for origin in origins {
+            eprintln!("  {}: {}", origin.path, origin.layer);
+        }
+    }
     Ok(())
 }
+
+/// Export a list to NDJSON, writing each element as soon as it is evaluated.
+fn export_ndjson(
+    program: &mut Program,
+    mut writer: Box<dyn Write>,
+    secrets: Secrets,
+) -> Result<(), Error> {
+    let result = program.eval_full_elements(|t| {
+        let rt = serialize::redact(RichTerm::from(t), secrets)?;
+        serialize::validate(ExportFormat::Json, &rt)?;
+        serialize::ndjson_line_to_writer(&mut writer, &rt)?;
+        writer.flush().map_err(IOError::from)?;
+        Ok(())
+    })?;
+
+    match result {
+        Some(t) => Err(SerializationError::NotAList(String::from("NDJSON"), t.into()).into()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,392 @@
+//! Project manifests.
+//!
+//! A Nickel codebase spanning several files can be declared as a package by a `nickel.toml`
+//! manifest at its root:
+//!
+//! ```toml
+//! [package]
+//! name = "infra"
+//! version = "0.1.0"
+//! # The programs evaluated, documented and tested by default.
+//! entries = ["main.ncl", "staging.ncl"]
+//! # Where imports are looked up when they are not found relatively to the importing file.
+//! import-paths = ["lib"]
+//!
+//! [dependencies]
+//! # `import "utils"` imports the first entry of the package at `../utils`, and
+//! # `import "utils/lists.ncl"` imports `../utils/lists.ncl`.
+//! utils = { path = "../utils" }
+//! # Dependencies fetched from a git repository or from a registry, see the fetch module.
+//! k8s = { git = "https://github.com/example/nickel-k8s", rev = "v1.2.0" }
+//! schemas = { version = "0.3.1" }
+//! ```
+//!
+//! Paths are relative to the directory of the manifest, which is the root of the package: the
+//! files below it belong to the project. The manifest of a file is found by looking for a
+//! `nickel.toml` in its directory and in the parent directories, as done by the command-line
+//! interface, the test runner and the language server.
+use crate::cache::Cache;
+use crate::error::IOError;
+use crate::fetch::{self, Store};
+use crate::vfs::FileSystem;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The name of manifest files.
+pub const FILE_NAME: &str = "nickel.toml";
+
+/// A package, as declared by a manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// The directory of the manifest, to which the paths of the package are relative.
+    pub root: PathBuf,
+    pub name: String,
+    pub version: Option<String>,
+    /// The entry points of the package, the first one being the main program.
+    pub entries: Vec<PathBuf>,
+    /// The additional directories where imports are looked up.
+    pub import_paths: Vec<PathBuf>,
+    /// The packages imported by name, sorted by name.
+    pub dependencies: Vec<Dependency>,
+}
+
+/// A package imported by name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    pub name: String,
+    pub source: Source,
+}
+
+/// Where a dependency comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A local directory, which is the root of the package.
+    Path(PathBuf),
+    /// A git repository, at the given revision or at the head of its default branch.
+    Git { url: String, rev: Option<String> },
+    /// A version of a library of a registry, or of the default registry. See the [fetch
+    /// module](../fetch/index.html).
+    Registry {
+        version: String,
+        registry: Option<PathBuf>,
+    },
+}
+
+/// The content of a manifest file, before resolving its paths.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawManifest {
+    package: RawPackage,
+    #[serde(default)]
+    dependencies: BTreeMap<String, RawDependency>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawPackage {
+    name: String,
+    version: Option<String>,
+    #[serde(default)]
+    entries: Vec<PathBuf>,
+    #[serde(default)]
+    import_paths: Vec<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawDependency {
+    path: Option<PathBuf>,
+    git: Option<String>,
+    rev: Option<String>,
+    version: Option<String>,
+    registry: Option<PathBuf>,
+}
+
+impl RawDependency {
+    fn source(self, root: &Path) -> Result<Source, String> {
+        match self {
+            RawDependency {
+                path: Some(path),
+                git: None,
+                rev: None,
+                version: None,
+                registry: None,
+            } => Ok(Source::Path(root.join(path))),
+            RawDependency {
+                path: None,
+                git: Some(url),
+                rev,
+                version: None,
+                registry: None,
+            } => Ok(Source::Git { url, rev }),
+            RawDependency {
+                path: None,
+                git: None,
+                rev: None,
+                version: Some(version),
+                registry,
+            } => Ok(Source::Registry {
+                version,
+                registry: registry.map(|registry| root.join(registry)),
+            }),
+            _ => Err(String::from(
+                "expected either `path`, `git` and an optional `rev`, or `version` and an \
+                 optional `registry`",
+            )),
+        }
+    }
+}
+
+impl Manifest {
+    /// Parse the content of a manifest, whose directory is `root`.
+    pub fn parse(source: &str, root: impl Into<PathBuf>) -> Result<Self, String> {
+        let raw: RawManifest = toml::from_str(source).map_err(|err| err.to_string())?;
+        let root = root.into();
+
+        Ok(Manifest {
+            name: raw.package.name,
+            version: raw.package.version,
+            entries: raw.package.entries.iter().map(|p| root.join(p)).collect(),
+            import_paths: raw
+                .package
+                .import_paths
+                .iter()
+                .map(|p| root.join(p))
+                .collect(),
+            dependencies: raw
+                .dependencies
+                .into_iter()
+                .map(|(name, dep)| {
+                    let dep = dep.source(&root).and_then(|source| {
+                        let dep = Dependency {
+                            name: name.clone(),
+                            source,
+                        };
+                        fetch::check_dependency(&dep).map(|()| dep)
+                    });
+                    dep.map_err(|err| format!("invalid dependency `{}`: {}", name, err))
+                })
+                .collect::<Result<_, String>>()?,
+            root,
+        })
+    }
+
+    /// Read and parse a manifest file.
+    pub fn load(fs: &dyn FileSystem, path: &Path) -> Result<Self, IOError> {
+        let source = fs.read_to_string(path).map_err(IOError::from)?;
+        let root = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+
+        Manifest::parse(&source, root).map_err(|err| {
+            IOError(format!(
+                "invalid manifest {}: {}",
+                path.to_string_lossy(),
+                err
+            ))
+        })
+    }
+
+    /// Find the manifest of the files of a directory, in the directory or in its closest
+    /// ancestor having one, and load it. Return `None` if there is no manifest.
+    pub fn discover(fs: &dyn FileSystem, dir: &Path) -> Result<Option<Self>, IOError> {
+        dir.ancestors()
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|path| fs.exists(path))
+            .map(|path| Manifest::load(fs, &path))
+            .transpose()
+    }
+
+    /// Return the main program of the package, if any.
+    pub fn main(&self) -> Option<&Path> {
+        self.entries.first().map(PathBuf::as_path)
+    }
+
+    /// Return `true` if a file belongs to the package, that is if it is below its root.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.root)
+    }
+
+    /// Return the Nickel files of the package.
+    pub fn files(&self, fs: &dyn FileSystem) -> io::Result<Vec<PathBuf>> {
+        Ok(fs
+            .list_files(&self.root)?
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ncl"))
+            .collect())
+    }
+
+    /// Add the import paths and the dependencies of the package to a cache, as well as the ones
+    /// of the dependencies which have a manifest, recursively. The entry point of a dependency
+    /// is the main program of its manifest. Fail if a dependency has not been fetched in the
+    /// store.
+    pub fn configure(&self, cache: &mut Cache, store: &Store) -> Result<(), IOError> {
+        let mut visited = vec![self.root.clone()];
+        self.configure_(cache, store, &mut visited)
+    }
+
+    fn configure_(
+        &self,
+        cache: &mut Cache,
+        store: &Store,
+        visited: &mut Vec<PathBuf>,
+    ) -> Result<(), IOError> {
+        for path in &self.import_paths {
+            cache.add_import_path(path);
+        }
+
+        for dep in &self.dependencies {
+            let path = store.locate(dep)?;
+            if !matches!(dep.source, Source::Path(_)) && !cache.file_system().exists(&path) {
+                return Err(IOError(format!(
+                    "dependency `{}` has not been fetched, run `nickel fetch`",
+                    dep.name
+                )));
+            }
+
+            let manifest_path = path.join(FILE_NAME);
+            let manifest = if cache.file_system().exists(&manifest_path) {
+                Some(Manifest::load(cache.file_system(), &manifest_path)?)
+            } else {
+                None
+            };
+
+            let entry = manifest
+                .as_ref()
+                .and_then(|m| m.main())
+                .map(Path::to_path_buf);
+            cache.add_package(dep.name.clone(), path, entry);
+
+            if let Some(manifest) = manifest {
+                if !visited.contains(&manifest.root) {
+                    visited.push(manifest.root.clone());
+                    manifest.configure_(cache, store, visited)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+    use crate::term::Term;
+    use crate::vfs::VirtualFileSystem;
+    use std::rc::Rc;
+
+    #[test]
+    fn parse() {
+        let source = r#"
+            [package]
+            name = "infra"
+            entries = ["main.ncl"]
+            import-paths = ["lib"]
+
+            [dependencies]
+            utils = { path = "../utils" }
+            k8s = { git = "https://example.org/k8s.git", rev = "v1" }
+            schemas = { version = "0.3.1", registry = "registry" }
+        "#;
+        let manifest = Manifest::parse(source, "/ws/infra").unwrap();
+        assert_eq!(manifest.name, "infra");
+        assert_eq!(manifest.version, None);
+        assert_eq!(manifest.main(), Some(Path::new("/ws/infra/main.ncl")));
+        assert_eq!(manifest.import_paths, vec![PathBuf::from("/ws/infra/lib")]);
+        assert_eq!(
+            manifest.dependencies,
+            vec![
+                Dependency {
+                    name: String::from("k8s"),
+                    source: Source::Git {
+                        url: String::from("https://example.org/k8s.git"),
+                        rev: Some(String::from("v1")),
+                    },
+                },
+                Dependency {
+                    name: String::from("schemas"),
+                    source: Source::Registry {
+                        version: String::from("0.3.1"),
+                        registry: Some(PathBuf::from("/ws/infra/registry")),
+                    },
+                },
+                Dependency {
+                    name: String::from("utils"),
+                    source: Source::Path(PathBuf::from("/ws/infra/../utils")),
+                },
+            ]
+        );
+        assert!(manifest.contains(Path::new("/ws/infra/lib/server.ncl")));
+        assert!(!manifest.contains(Path::new("/ws/utils/lists.ncl")));
+
+        assert!(Manifest::parse("[package]\nversion = \"1\"", "/").is_err());
+        assert!(Manifest::parse("[package]\nname = \"a\"\nentry = \"b\"", "/").is_err());
+        assert!(Manifest::parse(
+            "[package]\nname = \"a\"\n[dependencies]\n\"b/c\" = {path = \"d\"}",
+            "/"
+        )
+        .is_err());
+        assert!(Manifest::parse(
+            "[package]\nname = \"a\"\n[dependencies]\nb = {path = \"b\", rev = \"c\"}",
+            "/"
+        )
+        .is_err());
+        assert!(Manifest::parse(
+            "[package]\nname = \"a\"\n[dependencies]\nb = {git = \"c\", rev = \"--d\"}",
+            "/"
+        )
+        .is_err());
+        assert!(Manifest::parse(
+            "[package]\nname = \"a\"\n[dependencies]\nb = {version = \"../1\"}",
+            "/"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn package() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file(
+            "/ws/infra/nickel.toml",
+            "[package]\nname = \"infra\"\nentries = [\"main.ncl\"]\nimport-paths = [\"lib\"]\n\
+             [dependencies]\nutils = {path = \"../utils\"}",
+        );
+        fs.add_file(
+            "/ws/infra/main.ncl",
+            r#"(import "server.ncl").port + (import "utils").one + (import "utils/two.ncl")"#,
+        );
+        fs.add_file("/ws/infra/lib/server.ncl", "{port = 80}");
+        fs.add_file(
+            "/ws/utils/nickel.toml",
+            "[package]\nname = \"utils\"\nentries = [\"src/utils.ncl\"]",
+        );
+        fs.add_file("/ws/utils/src/utils.ncl", "{one = 1}");
+        fs.add_file("/ws/utils/two.ncl", "2");
+        let fs = Rc::new(fs);
+
+        let manifest = Manifest::discover(fs.as_ref(), Path::new("/ws/infra/lib"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(manifest.root, PathBuf::from("/ws/infra"));
+        assert_eq!(
+            manifest.files(fs.as_ref()).unwrap(),
+            vec![
+                PathBuf::from("/ws/infra/lib/server.ncl"),
+                PathBuf::from("/ws/infra/main.ncl")
+            ]
+        );
+        assert!(Manifest::discover(fs.as_ref(), Path::new("/ws"))
+            .unwrap()
+            .is_none());
+
+        let mut program = Program::builder()
+            .source_file(manifest.main().unwrap())
+            .file_system(fs)
+            .manifest(manifest)
+            .build()
+            .unwrap();
+        assert_eq!(program.eval().unwrap(), Term::Num(83.0));
+    }
+}
@@ -53,6 +53,7 @@
 //! evaluates to a contract check, that is an `Assume(..., t)`
 use crate::error::EvalError;
 use crate::eval::{Closure, Environment};
+use crate::label::Label;
 use crate::position::TermPos;
 use crate::term::{make as mk_term, BinaryOp, Contract, MetaValue, RichTerm, Term};
 use crate::transformations::Closurizable;
@@ -183,6 +184,7 @@ pub fn merge(
                 types: types1,
                 contracts: contracts1,
                 priority: priority1,
+                sensitive: sensitive1,
                 value: value1,
             } = meta1;
             let MetaValue {
@@ -190,6 +192,7 @@ pub fn merge(
                 types: types2,
                 contracts: contracts2,
                 priority: priority2,
+                sensitive: sensitive2,
                 value: value2,
             } = meta2;
 
@@ -212,6 +215,7 @@ pub fn merge(
                         &env1,
                         types2.iter().chain(contracts2.iter()),
                         &env2,
+                        sensitive1 || sensitive2,
                     );
                     (Some(v), e)
                 }
@@ -229,6 +233,7 @@ pub fn merge(
                         &env2,
                         types1.iter().chain(contracts1.iter()),
                         &env1,
+                        sensitive1 || sensitive2,
                     );
                     (Some(v), e)
                 }
@@ -287,6 +292,7 @@ pub fn merge(
                 types,
                 contracts,
                 priority,
+                sensitive: sensitive1 || sensitive2,
                 value,
             };
 
@@ -349,11 +355,14 @@ pub fn merge(
 ///
 /// - the term is given by `t1` in its environment `env1`
 /// - the contracts are given as an iterator `it2` together with their environment `env2`
+/// - `sensitive` tells if the result of the merge is sensitive, in which case the value is not
+///   shown if a contract fails
 fn cross_apply_contracts<'a>(
     t1: RichTerm,
     env1: &Environment,
     it2: impl Iterator<Item = &'a Contract>,
     env2: &Environment,
+    sensitive: bool,
 ) -> (RichTerm, Environment) {
     let mut env = Environment::new();
     let mut env1_local = env1.clone();
@@ -362,7 +371,11 @@ fn cross_apply_contracts<'a>(
     let result = it2
         .fold(t1, |acc, ctr| {
             let ty_closure = ctr.types.clone().closurize(&mut env1_local, env2.clone());
-            mk_term::assume(ty_closure, ctr.label.clone(), acc).with_pos(pos)
+            let label = Label {
+                sensitive,
+                ..ctr.label.clone()
+            };
+            mk_term::assume(ty_closure, label, acc).with_pos(pos)
         })
         .closurize(&mut env, env1_local);
 
@@ -0,0 +1,214 @@
+//! Native functions: Rust callbacks exposed as Nickel functions.
+//!
+//! An embedder implements [`NativeFunction`](trait.NativeFunction.html), or wraps a Rust closure
+//! using [`from_fn1`](fn.from_fn1.html) or [`from_fn2`](fn.from_fn2.html), and registers it in the
+//! global environment using
+//! [`ProgramBuilder::with_native_function`](../program/struct.ProgramBuilder.html#method.with_native_function):
+//!
+//! ```ignore
+//! let mut program = Program::builder()
+//!     .source_str(r#"{password = secret "db"}"#)
+//!     .with_native_function("secret", native::from_fn1(|name: String| vault.get(&name)))
+//!     .build()?;
+//! ```
+//!
+//! A native function of arity `n` is represented as the Nickel function `fun x1 ... xn =>
+//! %native% [x1, ..., xn]`, where `%native%` is a primitive operator which fully evaluates its
+//! arguments before calling the Rust code. Native functions are untyped: they have type `Dyn` in
+//! statically typed code.
+use crate::deserialize::from_term;
+use crate::identifier::Ident;
+use crate::serialize::serialize_to_term;
+use crate::term::{make as mk_term, RichTerm, Term, UnaryOp};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::rc::Rc;
+
+/// A Rust function callable from Nickel.
+pub trait NativeFunction {
+    /// The number of arguments of the function.
+    fn arity(&self) -> usize;
+
+    /// Call the function. The arguments are fully evaluated, and there are exactly
+    /// [`arity`](#tymethod.arity) of them. The error message is reported at the position of the
+    /// call.
+    fn call(&self, args: Vec<RichTerm>) -> Result<RichTerm, String>;
+}
+
+/// A named native function, as stored in the `Native` primitive operator.
+#[derive(Clone)]
+pub struct NativeFn {
+    pub name: String,
+    pub function: Rc<dyn NativeFunction>,
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFn({})", self.name)
+    }
+}
+
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+/// Build the Nickel function calling a native function.
+pub fn mk_native_term(name: impl Into<String>, function: Rc<dyn NativeFunction>) -> RichTerm {
+    let params: Vec<Ident> = (0..function.arity())
+        .map(|i| Ident::from(format!("%native_arg{}", i)))
+        .collect();
+    let args = RichTerm::from(Term::List(
+        params.iter().cloned().map(mk_term::var).collect(),
+    ));
+    let native = NativeFn {
+        name: name.into(),
+        function,
+    };
+
+    params.into_iter().rev().fold(
+        mk_term::op1(UnaryOp::Native(native, false), args),
+        |body, param| Term::Fun(param, body).into(),
+    )
+}
+
+/// A native function built from a Rust closure.
+struct ClosureFn<F> {
+    arity: usize,
+    f: F,
+}
+
+impl<F> NativeFunction for ClosureFn<F>
+where
+    F: Fn(Vec<RichTerm>) -> Result<RichTerm, String>,
+{
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, args: Vec<RichTerm>) -> Result<RichTerm, String> {
+        (self.f)(args)
+    }
+}
+
+/// Convert an argument to a Rust value.
+fn extract<T: DeserializeOwned>(arg: RichTerm, index: usize) -> Result<T, String> {
+    from_term(arg).map_err(|err| format!("argument {}: {}", index + 1, err))
+}
+
+/// Convert a result to a term.
+fn inject<T: Serialize>(result: T) -> Result<RichTerm, String> {
+    serialize_to_term(&result).map_err(|err| format!("result: {:?}", err))
+}
+
+/// Wrap a Rust function of one argument. The argument and the result are converted from and to
+/// Nickel values through serde.
+pub fn from_fn1<A, R, F>(f: F) -> Rc<dyn NativeFunction>
+where
+    A: DeserializeOwned,
+    R: Serialize,
+    F: Fn(A) -> Result<R, String> + 'static,
+{
+    Rc::new(ClosureFn {
+        arity: 1,
+        f: move |args: Vec<RichTerm>| {
+            let mut args = args.into_iter();
+            let a = extract(args.next().unwrap(), 0)?;
+            inject(f(a)?)
+        },
+    })
+}
+
+/// Wrap a Rust function of two arguments. See [`from_fn1`](fn.from_fn1.html).
+pub fn from_fn2<A, B, R, F>(f: F) -> Rc<dyn NativeFunction>
+where
+    A: DeserializeOwned,
+    B: DeserializeOwned,
+    R: Serialize,
+    F: Fn(A, B) -> Result<R, String> + 'static,
+{
+    Rc::new(ClosureFn {
+        arity: 2,
+        f: move |args: Vec<RichTerm>| {
+            let mut args = args.into_iter();
+            let a = extract(args.next().unwrap(), 0)?;
+            let b = extract(args.next().unwrap(), 1)?;
+            inject(f(a, b)?)
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, EvalError};
+    use crate::program::Program;
+    use std::collections::HashMap;
+
+    fn eval_with_natives(s: &str) -> Result<Term, Error> {
+        let secrets: HashMap<String, String> = vec![(String::from("db"), String::from("hunter2"))]
+            .into_iter()
+            .collect();
+
+        Program::builder()
+            .source_str(s)
+            .with_native_function(
+                "secret",
+                from_fn1(move |name: String| {
+                    secrets
+                        .get(&name)
+                        .cloned()
+                        .ok_or_else(|| format!("unknown secret {}", name))
+                }),
+            )
+            .with_native_function(
+                "scale",
+                from_fn2(|factor: f64, xs: Vec<f64>| {
+                    Ok(xs.into_iter().map(|x| x * factor).collect::<Vec<_>>())
+                }),
+            )
+            .build()
+            .and_then(|mut p| p.eval_full())
+    }
+
+    #[test]
+    fn calls() {
+        assert_eq!(
+            eval_with_natives(r#"secret ("d" ++ "b")"#),
+            Ok(Term::Str(String::from("hunter2")))
+        );
+        assert_eq!(
+            eval_with_natives("lists.map (fun x => x + 1) (scale 2 [1, 1 + 1])")
+                .map(|t| serde_json::to_value(&t).unwrap()),
+            Ok(serde_json::json!([3, 5]))
+        );
+        let partial = eval_with_natives("let double = scale 2 in double [3]");
+        assert_eq!(
+            partial.map(|t| serde_json::to_value(&t).unwrap()),
+            Ok(serde_json::json!([6]))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let source = r#"let x = secret "api" in x"#;
+        match eval_with_natives(source) {
+            Err(Error::EvalError(EvalError::Other(msg, pos))) => {
+                assert!(msg.contains("unknown secret api"));
+                let span = pos.into_opt().unwrap();
+                assert_eq!(
+                    &source[span.start.to_usize()..span.end.to_usize()],
+                    r#"secret "api""#
+                );
+            }
+            result => panic!("expected an evaluation error, got {:?}", result),
+        }
+
+        assert!(matches!(
+            eval_with_natives("secret 1"),
+            Err(Error::EvalError(EvalError::Other(..)))
+        ));
+    }
+}
@@ -6,23 +6,28 @@
 //! the functions [`process_unary_operation`](fn.process_unary_operation.html) and
 //! [`process_binary_operation`](fn.process_binary_operation.html) receive evaluated operands and
 //! implement the actual semantics of operators.
+use crate::diff;
 use crate::error::EvalError;
-use crate::eval::{subst, CallStack, Closure, Environment};
+use crate::eval::{subst, CallStack, Closure, Environment, StackElem, Thunk, ThunkState};
+use crate::format::FormatSpec;
 use crate::identifier::Ident;
-use crate::label::ty_path;
+use crate::label::{ty_path, Label};
 use crate::merge;
 use crate::merge::merge;
 use crate::mk_record;
 use crate::position::TermPos;
 use crate::stack::Stack;
 use crate::term::make as mk_term;
-use crate::term::{BinaryOp, NAryOp, RichTerm, StrChunk, Term, UnaryOp};
-use crate::transformations::Closurizable;
+use crate::term::{BinaryOp, MetaValue, NAryOp, RichTerm, StrChunk, Term, UnaryOp};
+use crate::transformations::{fresh_var, Closurizable};
 use crate::{mk_app, mk_fun};
 use crate::{serialize, serialize::ExportFormat};
 use md5::digest::Digest;
 use simple_counter::*;
+use std::collections::HashMap;
 use std::iter::Extend;
+use std::time::{SystemTime, UNIX_EPOCH};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 generate_counter!(FreshVariableCounter, usize);
 
@@ -132,7 +137,7 @@ pub fn continuate_operation(
 
                 Ok(next)
             } else {
-                let result = process_nary_operation(op, evaluated, stack, pos);
+                let result = process_nary_operation(op, evaluated, stack, call_stack, pos);
                 *enriched_strict = prev_enriched_strict;
                 result
             }
@@ -447,7 +452,7 @@ fn process_unary_operation(
                     Some(e) => Ok(Closure { body: e, env }),
 
                     None => Err(EvalError::FieldMissing(
-                        id.0,
+                        id.label,
                         String::from("(.)"),
                         RichTerm {
                             term: Box::new(Term::Record(static_map)),
@@ -467,7 +472,10 @@ fn process_unary_operation(
         }
         UnaryOp::FieldsOf() => {
             if let Term::Record(map) = *t {
-                let mut fields: Vec<String> = map.keys().map(|Ident(id)| id.clone()).collect();
+                let mut fields: Vec<String> = map
+                    .keys()
+                    .map(|Ident { label: id, .. }| id.clone())
+                    .collect();
                 fields.sort();
                 let terms = fields.into_iter().map(mk_term::string).collect();
                 Ok(Closure::atomic_closure(RichTerm::new(
@@ -593,10 +601,10 @@ fn process_unary_operation(
                 let rec = rec
                     .into_iter()
                     .map(|e| {
-                        let (Ident(s), t) = e;
+                        let (Ident { label: s, .. }, t) = e;
                         let pos = t.pos.into_inherited();
                         (
-                            Ident(s.clone()),
+                            Ident::from(s.clone()),
                             mk_app!(f_as_var.clone(), mk_term::string(s), t)
                                 .closurize(&mut shared_env, env.clone())
                                 .with_pos(pos),
@@ -713,6 +721,83 @@ fn process_unary_operation(
                 ))
             }
         }
+        UnaryOp::ListSum()
+        | UnaryOp::ListProduct()
+        | UnaryOp::ListMin()
+        | UnaryOp::ListMax()
+        | UnaryOp::ListMean() => {
+            let op_name = match u_op {
+                UnaryOp::ListSum() => "sum",
+                UnaryOp::ListProduct() => "product",
+                UnaryOp::ListMin() => "min",
+                UnaryOp::ListMax() => "max",
+                _ => "mean",
+            };
+
+            if let Term::List(ts) = *t {
+                // Force the elements which haven't been evaluated yet, and apply the operation
+                // again to the list of their values.
+                if !ts.iter().all(|t| is_forced(t, &env)) {
+                    let with_env = env.clone();
+                    let ts: Vec<RichTerm> = ts
+                        .into_iter()
+                        .map(|t| match t.as_ref() {
+                            Term::Num(_) | Term::Var(_) => t,
+                            _ => t.closurize(&mut env, with_env.clone()),
+                        })
+                        .collect();
+                    let op = mk_term::op1(u_op, RichTerm::new(Term::List(ts.clone()), pos))
+                        .with_pos(pos_op);
+                    let body = ts
+                        .into_iter()
+                        .filter(|t| matches!(t.as_ref(), Term::Var(_)))
+                        .rev()
+                        .fold(op, |acc, t| {
+                            mk_app!(mk_term::op1(UnaryOp::Seq(), t), acc).with_pos(pos_op_inh)
+                        });
+
+                    return Ok(Closure { body, env });
+                }
+
+                let nums = forced_nums(ts, &env).map_err(|elt| {
+                    EvalError::TypeError(
+                        String::from("Num"),
+                        format!("{}, element of the list", op_name),
+                        arg_pos,
+                        elt,
+                    )
+                })?;
+
+                let result = match u_op {
+                    UnaryOp::ListSum() => Some(nums.iter().sum()),
+                    UnaryOp::ListProduct() => Some(nums.iter().product()),
+                    UnaryOp::ListMin() => nums.iter().cloned().fold(None, |acc: Option<f64>, n| {
+                        Some(acc.map_or(n, |m| m.min(n)))
+                    }),
+                    UnaryOp::ListMax() => nums.iter().cloned().fold(None, |acc: Option<f64>, n| {
+                        Some(acc.map_or(n, |m| m.max(n)))
+                    }),
+                    _ if nums.is_empty() => None,
+                    _ => Some(nums.iter().sum::<f64>() / (nums.len() as f64)),
+                };
+
+                let result = result.ok_or_else(|| {
+                    EvalError::Other(format!("{}: expected a non-empty list", op_name), pos_op)
+                })?;
+
+                Ok(Closure::atomic_closure(RichTerm::new(
+                    Term::Num(result),
+                    pos_op_inh,
+                )))
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("List"),
+                    String::from(op_name),
+                    arg_pos,
+                    RichTerm { term: t, pos },
+                ))
+            }
+        }
         UnaryOp::ChunksConcat() => {
             let (mut acc, indent, env_chunks) = stack.pop_str_acc().unwrap();
 
@@ -888,9 +973,24 @@ fn process_unary_operation(
                 ))
             }
         }
+        UnaryOp::StrDedent() => {
+            if let Term::Str(s) = *t {
+                Ok(Closure::atomic_closure(RichTerm::new(
+                    Term::Str(str_dedent(&s)),
+                    pos_op_inh,
+                )))
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Str"),
+                    String::from("strDedent"),
+                    arg_pos,
+                    RichTerm { term: t, pos },
+                ))
+            }
+        }
         UnaryOp::ToStr() => {
             let result = match *t {
-                Term::Num(n) => Ok(Term::Str(n.to_string())),
+                Term::Num(n) => Ok(Term::Str(serialize::num_to_string(n))),
                 Term::Str(s) => Ok(Term::Str(s)),
                 Term::Bool(b) => Ok(Term::Str(b.to_string())),
                 Term::Enum(id) => Ok(Term::Str(id.to_string())),
@@ -927,7 +1027,7 @@ fn process_unary_operation(
                 let re = regex::Regex::new("_?[a-zA-Z][_a-zA-Z0-9]*").unwrap();
                 if re.is_match(&s) {
                     Ok(Closure::atomic_closure(RichTerm::new(
-                        Term::Enum(Ident(s)),
+                        Term::Enum(Ident::from(s)),
                         pos_op_inh,
                     )))
                 } else {
@@ -945,6 +1045,92 @@ fn process_unary_operation(
                 ))
             }
         }
+        UnaryOp::Trace() => {
+            if let Term::Str(msg) = *t {
+                let (next, ..) = stack
+                    .pop_arg()
+                    .ok_or_else(|| EvalError::NotEnoughArgs(2, String::from("trace"), pos_op))?;
+                eprintln!("trace: {}", msg);
+                Ok(next)
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Str"),
+                    String::from("trace, 1st argument"),
+                    arg_pos,
+                    RichTerm { term: t, pos },
+                ))
+            }
+        }
+        UnaryOp::Clock() => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|err| EvalError::Other(format!("clock: {}", err), pos_op))?;
+            Ok(Closure::atomic_closure(RichTerm::new(
+                Term::Num(now.as_secs_f64()),
+                pos_op_inh,
+            )))
+        }
+        UnaryOp::Native(native, false) => {
+            if let Term::List(ts) = *t {
+                // The arguments haven't been evaluated yet, so the last application of the call
+                // stack is the call of the native function
+                let pos_call = call_stack
+                    .iter()
+                    .rev()
+                    .find_map(|elem| match elem {
+                        StackElem::App(pos) if pos.is_def() => Some(*pos),
+                        _ => None,
+                    })
+                    .unwrap_or(pos_op);
+                let args = RichTerm {
+                    term: Box::new(Term::List(ts)),
+                    pos,
+                };
+
+                Ok(Closure {
+                    body: mk_app!(
+                        mk_term::op1(UnaryOp::DeepSeq(), args.clone()),
+                        mk_term::op1(UnaryOp::Native(native, true), args).with_pos(pos_call)
+                    )
+                    .with_pos(pos_call),
+                    env,
+                })
+            } else {
+                Err(EvalError::InternalError(
+                    format!(
+                        "native function {}: expected a list of arguments",
+                        native.name
+                    ),
+                    pos_op,
+                ))
+            }
+        }
+        UnaryOp::Native(native, true) => {
+            if let Term::List(ts) = *t {
+                // The arguments must be fully substituted to be passed to the native function
+                let global_env = Environment::new();
+                let args = ts
+                    .into_iter()
+                    .map(|t| subst(t, &global_env, &env))
+                    .collect();
+                let mut result = native.function.call(args).map_err(|msg| {
+                    EvalError::Other(format!("native function {}: {}", native.name, msg), pos_op)
+                })?;
+
+                if !result.pos.is_def() {
+                    result.pos = pos_op_inh;
+                }
+                Ok(Closure::atomic_closure(result))
+            } else {
+                Err(EvalError::InternalError(
+                    format!(
+                        "native function {}: expected a list of arguments",
+                        native.name
+                    ),
+                    pos_op,
+                ))
+            }
+        }
     }
 }
 
@@ -1199,8 +1385,18 @@ fn process_binary_operation(
                 let thunk = stack
                     .track_arg()
                     .ok_or_else(|| EvalError::NotEnoughArgs(3, String::from("assume"), pos_op))?;
-                l.arg_pos = thunk.borrow().body.pos;
+                // A label propagated from a tagged record contract may already hold the position
+                // of the enclosing value, which is better than none.
+                let arg_pos = thunk.borrow().body.pos;
+                if arg_pos.is_def() {
+                    l.arg_pos = arg_pos;
+                }
                 l.arg_thunk = Some(thunk);
+                let tag_label = if l.tag.is_empty() {
+                    None
+                } else {
+                    Some(l.clone())
+                };
 
                 stack.push_arg(
                     Closure::atomic_closure(RichTerm::new(Term::Lbl(l), pos2.into_inherited())),
@@ -1217,12 +1413,20 @@ fn process_binary_operation(
                     }),
                     Term::Record(..) => {
                         let mut new_env = Environment::new();
-                        let closurized = RichTerm {
+                        let mut closurized = RichTerm {
                             term: t1,
                             pos: pos1,
                         }
                         .closurize(&mut new_env, env1);
 
+                        // The contracts of the fields inherit the tag of the label, which would
+                        // be lost otherwise.
+                        if let Some(l) = tag_label {
+                            closurized =
+                                mk_term::op2(BinaryOp::TagContracts(), Term::Lbl(l), closurized)
+                                    .with_pos(pos1);
+                        }
+
                         // Convert the record to the function `fun l x => contract & x`.
                         let body = mk_fun!(
                             "_l",
@@ -1284,6 +1488,51 @@ fn process_binary_operation(
                 ))
             }
         }
+        BinaryOp::TagContracts() => {
+            if let Term::Lbl(l) = *t1 {
+                let tag_label = |label: &mut Label| {
+                    if label.tag.is_empty() {
+                        label.tag = l.tag.clone();
+                    }
+                    if !label.arg_pos.is_def() {
+                        label.arg_pos = l.arg_pos;
+                    }
+                };
+                let tag = |t: RichTerm| {
+                    let pos = t.pos;
+                    mk_term::op2(BinaryOp::TagContracts(), Term::Lbl(l.clone()), t).with_pos(pos)
+                };
+
+                let term = match *t2 {
+                    Term::MetaValue(mut meta) => {
+                        for ctr in meta.types.iter_mut().chain(meta.contracts.iter_mut()) {
+                            tag_label(&mut ctr.label);
+                        }
+                        meta.value = meta.value.map(tag);
+                        Term::MetaValue(meta)
+                    }
+                    Term::Record(map) => {
+                        Term::Record(map.into_iter().map(|(id, t)| (id, tag(t))).collect())
+                    }
+                    t => t,
+                };
+
+                Ok(Closure {
+                    body: RichTerm::new(term, pos2),
+                    env: env2,
+                })
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Label"),
+                    String::from("tagContracts, 1st argument"),
+                    fst_pos,
+                    RichTerm {
+                        term: t1,
+                        pos: pos1,
+                    },
+                ))
+            }
+        }
         BinaryOp::Tag() => {
             if let Term::Str(s) = *t1 {
                 if let Term::Lbl(mut l) = *t2 {
@@ -1489,7 +1738,7 @@ fn process_binary_operation(
         BinaryOp::GoField() => {
             if let Term::Str(field) = *t1 {
                 if let Term::Lbl(mut l) = *t2 {
-                    l.path.push(ty_path::Elem::Field(Ident(field)));
+                    l.path.push(ty_path::Elem::Field(Ident::from(field)));
                     Ok(Closure::atomic_closure(RichTerm::new(
                         Term::Lbl(l),
                         pos_op_inh,
@@ -1521,7 +1770,7 @@ fn process_binary_operation(
         BinaryOp::DynAccess() => {
             if let Term::Str(id) = *t1 {
                 if let Term::Record(mut static_map) = *t2 {
-                    match static_map.remove(&Ident(id.clone())) {
+                    match static_map.remove(&Ident::from(id.clone())) {
                         Some(e) => Ok(Closure { body: e, env: env2 }),
                         None => Err(EvalError::FieldMissing(
                             id,
@@ -1556,7 +1805,7 @@ fn process_binary_operation(
                 ))
             }
         }
-        BinaryOp::DynExtend() => {
+        BinaryOp::DynExtend() | BinaryOp::DynDefine() => {
             let (clos, _) = stack
                 .pop_arg()
                 .ok_or_else(|| EvalError::NotEnoughArgs(3, String::from("$[ .. ]"), pos_op))?;
@@ -1564,12 +1813,20 @@ fn process_binary_operation(
             if let Term::Str(id) = *t1 {
                 if let Term::Record(mut static_map) = *t2 {
                     let as_var = clos.body.closurize(&mut env2, clos.env);
-                    match static_map.insert(Ident(id.clone()), as_var) {
-                        Some(_) => Err(EvalError::Other(format!("$[ .. ]: tried to extend record with the field {}, but it already exists", id), pos_op)),
-                        None => Ok(Closure {
-                            body: Term::Record(static_map).into(),
-                            env: env2,
-                        }),
+                    match static_map.remove(&Ident::from(id.clone())) {
+                        Some(_) if b_op == BinaryOp::DynExtend() => Err(EvalError::Other(format!("$[ .. ]: tried to extend record with the field {}, but it already exists", id), pos_op)),
+                        prev => {
+                            let t = match prev {
+                                Some(prev) => mk_term::op2(BinaryOp::Merge(), prev, as_var),
+                                None => as_var,
+                            };
+                            static_map.insert(Ident::from(id), t);
+
+                            Ok(Closure {
+                                body: Term::Record(static_map).into(),
+                                env: env2,
+                            })
+                        }
                     }
                 } else {
                     Err(EvalError::TypeError(
@@ -1597,7 +1854,7 @@ fn process_binary_operation(
         BinaryOp::DynRemove() => {
             if let Term::Str(id) = *t1 {
                 if let Term::Record(mut static_map) = *t2 {
-                    match static_map.remove(&Ident(id.clone())) {
+                    match static_map.remove(&Ident::from(id.clone())) {
                         None => Err(EvalError::FieldMissing(
                             id,
                             String::from("(-$)"),
@@ -1639,7 +1896,7 @@ fn process_binary_operation(
             if let Term::Str(id) = *t1 {
                 if let Term::Record(static_map) = *t2 {
                     Ok(Closure::atomic_closure(RichTerm::new(
-                        Term::Bool(static_map.contains_key(&Ident(id))),
+                        Term::Bool(static_map.contains_key(&Ident::from(id))),
                         pos_op_inh,
                     )))
                 } else {
@@ -1665,6 +1922,262 @@ fn process_binary_operation(
                 ))
             }
         }
+        BinaryOp::GetPath() => {
+            if let Term::List(path) = *t1 {
+                let mut shared_env = Environment::new();
+                let record = RichTerm {
+                    term: t2,
+                    pos: pos2,
+                }
+                .closurize(&mut shared_env, env2);
+
+                // Unfold the path as a sequence of nested dynamic accesses
+                let body = path.into_iter().fold(record, |acc, field| {
+                    let field = field.closurize(&mut shared_env, env1.clone());
+                    mk_term::op2(BinaryOp::DynAccess(), field, acc)
+                });
+
+                Ok(Closure {
+                    body: body.with_pos(pos_op_inh),
+                    env: shared_env,
+                })
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("List"),
+                    String::from("getPath, 1st argument"),
+                    fst_pos,
+                    RichTerm {
+                        term: t1,
+                        pos: pos1,
+                    },
+                ))
+            }
+        }
+        BinaryOp::HasPath() => {
+            if let Term::List(path) = *t1 {
+                let mut shared_env = Environment::new();
+                let mut current = RichTerm {
+                    term: t2,
+                    pos: pos2,
+                }
+                .closurize(&mut shared_env, env2);
+                let mut conds = Vec::new();
+
+                // Every intermediate value must be a record having the next field of the path
+                for (i, field) in path.into_iter().enumerate() {
+                    let field = field.closurize(&mut shared_env, env1.clone());
+
+                    if i > 0 {
+                        conds.push(mk_term::op1(UnaryOp::IsRecord(), current.clone()));
+                    }
+                    conds.push(mk_term::op2(
+                        BinaryOp::HasField(),
+                        field.clone(),
+                        current.clone(),
+                    ));
+                    current = mk_term::op2(BinaryOp::DynAccess(), field, current);
+                }
+
+                let body = conds
+                    .into_iter()
+                    .rev()
+                    .fold(RichTerm::from(Term::Bool(true)), |acc, cond| {
+                        mk_app!(mk_term::op1(UnaryOp::BoolAnd(), cond), acc)
+                    });
+
+                Ok(Closure {
+                    body: body.with_pos(pos_op_inh),
+                    env: shared_env,
+                })
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("List"),
+                    String::from("hasPath, 1st argument"),
+                    fst_pos,
+                    RichTerm {
+                        term: t1,
+                        pos: pos1,
+                    },
+                ))
+            }
+        }
+        BinaryOp::DeepRepr() => {
+            if let Term::Num(depth) = *t1 {
+                if depth < 0.0 || depth.fract() != 0.0 {
+                    return Err(EvalError::Other(
+                        format!(
+                            "deepRepr: expected the 1st argument to be a non-negative integer, got {}",
+                            depth
+                        ),
+                        pos_op,
+                    ));
+                }
+
+                let depth = depth as usize;
+                let rt2 = RichTerm {
+                    term: t2,
+                    pos: pos2,
+                };
+
+                // Only the values rendered are forced: the ones which haven't been evaluated yet
+                // are forced first, and the operation is applied again to the same value, until
+                // all the values up to `depth` are evaluated.
+                let mut thunks = Vec::new();
+                unevaluated_upto(&rt2, &env2, depth, &mut thunks);
+                if !thunks.is_empty() {
+                    let vars: Vec<Ident> = thunks
+                        .into_iter()
+                        .map(|thunk| {
+                            let var = fresh_var();
+                            env2.insert(var.clone(), thunk);
+                            var
+                        })
+                        .collect();
+                    let op = mk_term::op2(BinaryOp::DeepRepr(), Term::Num(depth as f64), rt2)
+                        .with_pos(pos_op);
+                    let body = vars.into_iter().rev().fold(op, |acc, var| {
+                        mk_app!(mk_term::op1(UnaryOp::Seq(), Term::Var(var)), acc)
+                            .with_pos(pos_op_inh)
+                    });
+
+                    return Ok(Closure { body, env: env2 });
+                }
+
+                Ok(Closure::atomic_closure(RichTerm::new(
+                    Term::Str(subst_upto(&rt2, &env2, depth).as_ref().deep_repr(depth)),
+                    pos_op_inh,
+                )))
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Num"),
+                    String::from("deepRepr, 1st argument"),
+                    fst_pos,
+                    RichTerm {
+                        term: t1,
+                        pos: pos1,
+                    },
+                ))
+            }
+        }
+        BinaryOp::ToStrFixed() => match (*t1, *t2) {
+            (Term::Num(digits), Term::Num(n)) => {
+                if digits < 0.0 || digits.fract() != 0.0 {
+                    Err(EvalError::Other(
+                        format!(
+                            "strFromFixed: expected the 1st argument to be a non-negative integer, got {}",
+                            digits
+                        ),
+                        pos_op,
+                    ))
+                } else {
+                    Ok(Closure::atomic_closure(RichTerm::new(
+                        Term::Str(serialize::num_to_fixed(n, digits as usize)),
+                        pos_op_inh,
+                    )))
+                }
+            }
+            (Term::Num(_), t2) => Err(EvalError::TypeError(
+                String::from("Num"),
+                String::from("strFromFixed, 2nd argument"),
+                snd_pos,
+                RichTerm {
+                    term: Box::new(t2),
+                    pos: pos2,
+                },
+            )),
+            (t1, _) => Err(EvalError::TypeError(
+                String::from("Num"),
+                String::from("strFromFixed, 1st argument"),
+                fst_pos,
+                RichTerm {
+                    term: Box::new(t1),
+                    pos: pos1,
+                },
+            )),
+        },
+        BinaryOp::StrFormat() => {
+            let spec = match *t1 {
+                Term::Str(s) => FormatSpec::parse(&s).ok_or_else(|| {
+                    EvalError::Other(
+                        format!("strFormat: invalid format specifier `{}`", s),
+                        pos_op,
+                    )
+                })?,
+                t1 => {
+                    return Err(EvalError::TypeError(
+                        String::from("Str"),
+                        String::from("strFormat, 1st argument"),
+                        fst_pos,
+                        RichTerm {
+                            term: Box::new(t1),
+                            pos: pos1,
+                        },
+                    ))
+                }
+            };
+
+            let result = match *t2 {
+                Term::Num(n) => spec.format_num(n),
+                Term::Str(s) => spec.format_str(&s),
+                Term::Bool(b) => spec.format_str(&b.to_string()),
+                t2 => {
+                    return Err(EvalError::TypeError(
+                        String::from("Num, Str or Bool"),
+                        String::from("strFormat, 2nd argument"),
+                        snd_pos,
+                        RichTerm {
+                            term: Box::new(t2),
+                            pos: pos2,
+                        },
+                    ))
+                }
+            };
+
+            match result {
+                Ok(s) => Ok(Closure::atomic_closure(RichTerm::new(
+                    Term::Str(s),
+                    pos_op_inh,
+                ))),
+                Err(msg) => Err(EvalError::Other(format!("strFormat: {}", msg), pos_op)),
+            }
+        }
+        BinaryOp::RecordOverride() => match (*t1, *t2) {
+            (Term::Record(base), Term::Record(fields)) => {
+                let mut env = Environment::new();
+                let mut result: HashMap<Ident, RichTerm> = base
+                    .into_iter()
+                    .map(|(id, t)| (id, t.closurize(&mut env, env1.clone())))
+                    .collect();
+                result.extend(
+                    fields
+                        .into_iter()
+                        .map(|(id, t)| (id, t.closurize(&mut env, env2.clone()))),
+                );
+
+                Ok(Closure {
+                    body: RichTerm::new(Term::Record(result), pos_op_inh),
+                    env,
+                })
+            }
+            (Term::Record(_), t2) => Err(EvalError::TypeError(
+                String::from("Record"),
+                String::from("record spread, 2nd operand"),
+                snd_pos,
+                RichTerm {
+                    term: Box::new(t2),
+                    pos: pos2,
+                },
+            )),
+            (t1, _) => Err(EvalError::TypeError(
+                String::from("Record"),
+                String::from("record spread, 1st operand"),
+                fst_pos,
+                RichTerm {
+                    term: Box::new(t1),
+                    pos: pos1,
+                },
+            )),
+        },
         BinaryOp::ListConcat() => match (*t1, *t2) {
             (Term::List(ts1), Term::List(ts2)) => {
                 let mut env = Environment::new();
@@ -2044,6 +2557,42 @@ fn process_binary_operation(
                 )),
             }
         }
+        BinaryOp::StrIndent() => match (*t1, *t2) {
+            (Term::Str(s), Term::Num(n)) => {
+                if n.fract() != 0.0 || n < 0.0 {
+                    Err(EvalError::Other(
+                        format!(
+                            "strIndent: expected the 2nd argument to be a non-negative integer, got {}",
+                            n
+                        ),
+                        pos_op,
+                    ))
+                } else {
+                    Ok(Closure::atomic_closure(RichTerm::new(
+                        Term::Str(str_indent(&s, n as usize)),
+                        pos_op_inh,
+                    )))
+                }
+            }
+            (Term::Str(_), t2) => Err(EvalError::TypeError(
+                String::from("Num"),
+                String::from("strIndent, 2nd argument"),
+                snd_pos,
+                RichTerm {
+                    term: Box::new(t2),
+                    pos: pos2,
+                },
+            )),
+            (t1, _) => Err(EvalError::TypeError(
+                String::from("Str"),
+                String::from("strIndent, 1st argument"),
+                fst_pos,
+                RichTerm {
+                    term: Box::new(t1),
+                    pos: pos1,
+                },
+            )),
+        },
     }
 }
 
@@ -2055,6 +2604,7 @@ fn process_nary_operation(
     n_op: NAryOp,
     args: Vec<(Closure, TermPos)>,
     _stack: &mut Stack,
+    call_stack: &mut CallStack,
     pos_op: TermPos,
 ) -> Result<Closure, EvalError> {
     let pos_op_inh = pos_op.into_inherited();
@@ -2062,7 +2612,7 @@ fn process_nary_operation(
     // Currently, for fixed arity primitive operators, the parser must ensure that they get exactly
     // the right number of argument: if it is not the case, this is a bug, and we panic.
     match n_op {
-        NAryOp::StrReplace() | NAryOp::StrReplaceRegex() => {
+        NAryOp::StrReplace() | NAryOp::StrReplaceRegex() | NAryOp::StrSplice() => {
             let mut args_wo_env = args
                 .into_iter()
                 .map(|(clos, pos)| (clos.body.term, clos.body.pos, pos));
@@ -2073,13 +2623,21 @@ fn process_nary_operation(
 
             match (*fst, *snd, *thd) {
                 (Term::Str(s), Term::Str(from), Term::Str(to)) => {
-                    let result = if let NAryOp::StrReplace() = n_op {
-                        str::replace(&s, &from, &to)
-                    } else {
-                        let re = regex::Regex::new(&from)
-                            .map_err(|err| EvalError::Other(err.to_string(), pos_op))?;
+                    let result = match n_op {
+                        NAryOp::StrReplace() => str::replace(&s, &from, &to),
+                        NAryOp::StrSplice() if from.is_empty() => {
+                            return Err(EvalError::Other(
+                                String::from("strSplice: the placeholder must not be empty"),
+                                pos_op,
+                            ))
+                        }
+                        NAryOp::StrSplice() => str_splice(&s, &from, &to),
+                        _ => {
+                            let re = regex::Regex::new(&from)
+                                .map_err(|err| EvalError::Other(err.to_string(), pos_op))?;
 
-                        re.replace_all(&s, to.as_str()).into_owned()
+                            re.replace_all(&s, to.as_str()).into_owned()
+                        }
                     };
 
                     Ok(Closure::atomic_closure(RichTerm::new(
@@ -2156,7 +2714,353 @@ fn process_nary_operation(
                 )),
             }
         }
+        NAryOp::StrPadLeft()
+        | NAryOp::StrPadRight()
+        | NAryOp::StrCenter()
+        | NAryOp::StrTruncate() => {
+            let mut args_wo_env = args
+                .into_iter()
+                .map(|(clos, pos)| (clos.body.term, clos.body.pos, pos));
+            let (fst, pos1, fst_pos) = args_wo_env.next().unwrap();
+            let (snd, pos2, snd_pos) = args_wo_env.next().unwrap();
+            let (thd, pos3, thd_pos) = args_wo_env.next().unwrap();
+            debug_assert!(args_wo_env.next().is_none());
+
+            match (*fst, *snd, *thd) {
+                (Term::Str(s), Term::Num(width), Term::Str(fill)) => {
+                    if width < 0.0 || width.fract() != 0.0 {
+                        return Err(EvalError::Other(format!("{}: expected the 2nd argument (width) to be a non-negative integer, got {}", n_op, width), pos_op));
+                    }
+
+                    let width = width as usize;
+                    let result = match n_op {
+                        NAryOp::StrTruncate() => str_truncate(&s, width, &fill),
+                        NAryOp::StrPadLeft() => str_pad(&s, width, &fill, 1.0),
+                        NAryOp::StrPadRight() => str_pad(&s, width, &fill, 0.0),
+                        _ => str_pad(&s, width, &fill, 0.5),
+                    }
+                    .map_err(|msg| EvalError::Other(format!("{}: {}", n_op, msg), pos_op))?;
+
+                    Ok(Closure::atomic_closure(RichTerm::new(
+                        Term::Str(result),
+                        pos_op_inh,
+                    )))
+                }
+                (Term::Str(_), Term::Num(_), t3) => Err(EvalError::TypeError(
+                    String::from("Str"),
+                    format!("{}, 3rd argument", n_op),
+                    thd_pos,
+                    RichTerm::new(t3, pos3),
+                )),
+                (Term::Str(_), t2, _) => Err(EvalError::TypeError(
+                    String::from("Num"),
+                    format!("{}, 2nd argument", n_op),
+                    snd_pos,
+                    RichTerm::new(t2, pos2),
+                )),
+                (t1, _, _) => Err(EvalError::TypeError(
+                    String::from("Str"),
+                    format!("{}, 1st argument", n_op),
+                    fst_pos,
+                    RichTerm::new(t1, pos1),
+                )),
+            }
+        }
+        NAryOp::BlameDiff() => {
+            let mut args = args.into_iter();
+            let (lbl_clos, lbl_pos) = args.next().unwrap();
+            let (expected_clos, _) = args.next().unwrap();
+            let (actual_clos, _) = args.next().unwrap();
+            debug_assert!(args.next().is_none());
+
+            match *lbl_clos.body.term {
+                Term::Lbl(mut label) => {
+                    // The differences would reveal a sensitive value.
+                    if !label.sensitive {
+                        // Comparing needs all variables to be fully substituted
+                        let global_env = Environment::new();
+                        let expected = subst(expected_clos.body, &global_env, &expected_clos.env);
+                        let actual = subst(actual_clos.body, &global_env, &actual_clos.env);
+                        label.diff = diff::diff_terms(&expected, &actual);
+                    }
+
+                    Err(EvalError::BlameError(label, std::mem::take(call_stack)))
+                }
+                t => Err(EvalError::TypeError(
+                    String::from("Label"),
+                    String::from("blameDiff, 1st argument"),
+                    lbl_pos,
+                    RichTerm::new(t, lbl_clos.body.pos),
+                )),
+            }
+        }
+        NAryOp::UpdatePath() => {
+            let mut args = args.into_iter();
+            let (path_clos, path_pos) = args.next().unwrap();
+            let (f_clos, _) = args.next().unwrap();
+            let (rec_clos, _) = args.next().unwrap();
+            debug_assert!(args.next().is_none());
+
+            let Closure {
+                body: RichTerm { term, pos },
+                env: path_env,
+            } = path_clos;
+
+            if let Term::List(path) = *term {
+                let mut shared_env = Environment::new();
+                let f = f_clos.body.closurize(&mut shared_env, f_clos.env);
+                let mut current = rec_clos.body.closurize(&mut shared_env, rec_clos.env);
+
+                let mut levels = Vec::with_capacity(path.len());
+                for field in path.into_iter() {
+                    let field = field.closurize(&mut shared_env, path_env.clone());
+                    let next = mk_term::op2(BinaryOp::DynAccess(), field.clone(), current.clone());
+                    levels.push((field, current));
+                    current = next;
+                }
+
+                // Rebuild each level from the innermost one, replacing the field of the path by
+                // its updated value
+                let body =
+                    levels
+                        .into_iter()
+                        .rev()
+                        .fold(mk_app!(f, current), |acc, (field, record)| {
+                            let removed =
+                                mk_term::op2(BinaryOp::DynRemove(), field.clone(), record);
+                            mk_app!(mk_term::op2(BinaryOp::DynExtend(), field, removed), acc)
+                        });
+
+                Ok(Closure {
+                    body: body.with_pos(pos_op_inh),
+                    env: shared_env,
+                })
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("List"),
+                    format!("{}, 1st argument", n_op),
+                    path_pos,
+                    RichTerm::new(*term, pos),
+                ))
+            }
+        }
+    }
+}
+
+/// Collect the thunks which haven't been evaluated yet among the values of a term, up to `depth`
+/// levels of nested lists and records. These are the values rendered by
+/// [`Term::deep_repr`](../term/enum.Term.html#method.deep_repr).
+fn unevaluated_upto(rt: &RichTerm, env: &Environment, depth: usize, thunks: &mut Vec<Thunk>) {
+    match rt.as_ref() {
+        Term::Var(id) => {
+            if let Some(thunk) = env.get(id) {
+                if thunk.state() == ThunkState::Evaluated {
+                    let closure = thunk.borrow();
+                    unevaluated_upto(&closure.body, &closure.env, depth, thunks);
+                } else {
+                    thunks.push(thunk.clone());
+                }
+            }
+        }
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => {
+            unevaluated_upto(t, env, depth, thunks)
+        }
+        Term::Record(map) | Term::RecRecord(map) if depth > 0 => map
+            .values()
+            .for_each(|t| unevaluated_upto(t, env, depth - 1, thunks)),
+        Term::List(ts) if depth > 0 => ts
+            .iter()
+            .for_each(|t| unevaluated_upto(t, env, depth - 1, thunks)),
+        _ => (),
+    }
+}
+
+/// Substitute the variables of a term up to `depth` levels of nested lists and records, as
+/// needed by [`Term::deep_repr`](../term/enum.Term.html#method.deep_repr). The deeper values
+/// are left as is, such that they are neither copied nor evaluated.
+fn subst_upto(rt: &RichTerm, env: &Environment, depth: usize) -> RichTerm {
+    let subst_map = |map: &HashMap<Ident, RichTerm>| {
+        map.iter()
+            .map(|(id, t)| (id.clone(), subst_upto(t, env, depth - 1)))
+            .collect()
+    };
+
+    let term = match rt.as_ref() {
+        Term::Var(id) => {
+            return match env.get(id) {
+                Some(thunk) => {
+                    let closure = thunk.borrow();
+                    subst_upto(&closure.body, &closure.env, depth)
+                }
+                None => rt.clone(),
+            }
+        }
+        Term::MetaValue(meta @ MetaValue { value: Some(t), .. }) => Term::MetaValue(MetaValue {
+            value: Some(subst_upto(t, env, depth)),
+            ..meta.clone()
+        }),
+        Term::Record(map) if depth > 0 => Term::Record(subst_map(map)),
+        Term::RecRecord(map) if depth > 0 => Term::RecRecord(subst_map(map)),
+        Term::List(ts) if depth > 0 => {
+            Term::List(ts.iter().map(|t| subst_upto(t, env, depth - 1)).collect())
+        }
+        _ => return rt.clone(),
+    };
+
+    RichTerm::new(term, rt.pos)
+}
+
+/// Return `true` if an element of a list is a number, or a variable bound to an evaluated thunk in
+/// `env`. Unbound variables are considered forced, and rejected by
+/// [`forced_nums`](fn.forced_nums.html).
+fn is_forced(t: &RichTerm, env: &Environment) -> bool {
+    match t.as_ref() {
+        Term::Num(_) => true,
+        Term::Var(id) => match env.get(id) {
+            Some(thunk) => thunk.state() == ThunkState::Evaluated,
+            None => true,
+        },
+        _ => false,
+    }
+}
+
+/// Extract the numbers of a list whose elements have already been [forced](fn.is_forced.html).
+/// If an element is not a number, it is returned as an error.
+fn forced_nums(ts: Vec<RichTerm>, env: &Environment) -> Result<Vec<f64>, RichTerm> {
+    fn as_num(t: &RichTerm, env: &Environment) -> Option<f64> {
+        match t.as_ref() {
+            Term::Num(n) => Some(*n),
+            Term::Var(id) => {
+                let thunk = env.get(id)?;
+                if thunk.state() != ThunkState::Evaluated {
+                    return None;
+                }
+
+                let closure = thunk.borrow();
+                as_num(&closure.body, &closure.env)
+            }
+            _ => None,
+        }
     }
+
+    ts.into_iter().map(|t| as_num(&t, env).ok_or(t)).collect()
+}
+
+/// Pad a string with the character `fill` until it reaches the display width `width`. `left`
+/// is the proportion of the padding to put on the left: `1.0` aligns the string to the right,
+/// `0.0` to the left, and `0.5` centers it, with the extra character going on the right.
+///
+/// Widths are computed as the number of columns occupied by the string when displayed.
+fn str_pad(s: &str, width: usize, fill: &str, left: f64) -> Result<String, String> {
+    let mut fill_chars = fill.chars();
+    let fill_char = match (fill_chars.next(), fill_chars.next()) {
+        (Some(c), None) if UnicodeWidthChar::width(c) == Some(1) => c,
+        _ => {
+            return Err(format!(
+                "expected the padding to be a single character of width 1, got \"{}\"",
+                fill
+            ))
+        }
+    };
+
+    let padding = width.saturating_sub(UnicodeWidthStr::width(s));
+    let left_padding = ((padding as f64) * left).floor() as usize;
+    let right_padding = padding - left_padding;
+
+    let fill = fill_char.to_string();
+    Ok(format!(
+        "{}{}{}",
+        fill.repeat(left_padding),
+        s,
+        fill.repeat(right_padding)
+    ))
+}
+
+/// Truncate a string to the display width `width`. If the string is too wide, it is shortened
+/// and `ellipsis` is appended at the end, such that the result still fits in `width` columns.
+fn str_truncate(s: &str, width: usize, ellipsis: &str) -> Result<String, String> {
+    if UnicodeWidthStr::width(s) <= width {
+        return Ok(String::from(s));
+    }
+
+    let available = width
+        .checked_sub(UnicodeWidthStr::width(ellipsis))
+        .ok_or_else(|| format!("the ellipsis \"{}\" is wider than {}", ellipsis, width))?;
+
+    let mut result = String::new();
+    let mut current = 0;
+    for c in s.chars() {
+        current += UnicodeWidthChar::width(c).unwrap_or(0);
+        if current > available {
+            break;
+        }
+        result.push(c);
+    }
+
+    result.push_str(ellipsis);
+    Ok(result)
+}
+
+/// Indent each non-blank line of a string by `n` spaces.
+fn str_indent(s: &str, n: usize) -> String {
+    let indent = " ".repeat(n);
+    s.split('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                String::from(line)
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Remove the longest whitespace prefix common to all the non-blank lines of a string.
+fn str_dedent(s: &str) -> String {
+    let is_indent = |c: char| c == ' ' || c == '\t';
+    let common = s
+        .split('\n')
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.chars().take_while(|c| is_indent(*c)).count())
+        .min()
+        .unwrap_or(0);
+
+    s.split('\n')
+        .map(|line| {
+            let prefix = line
+                .char_indices()
+                .take(common)
+                .take_while(|(_, c)| is_indent(*c))
+                .map(|(_, c)| c.len_utf8())
+                .sum::<usize>();
+            &line[prefix..]
+        })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// Replace each occurrence of `placeholder` in `template` by `value`, indenting the lines of
+/// `value` following the first one by the whitespace prefix of the line where the placeholder
+/// occurs. This mirrors the treatment of interpolated expressions inside multiline strings.
+fn str_splice(template: &str, placeholder: &str, value: &str) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+
+    for (index, _) in template.match_indices(placeholder) {
+        let line_start = template[..index].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let indent: String = template[line_start..index]
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect();
+
+        result.push_str(&template[last..index]);
+        result.push_str(&value.replace("\n", &format!("\n{}", indent)));
+        last = index + placeholder.len();
+    }
+
+    result.push_str(&template[last..]);
+    result
 }
 
 /// Compute the equality of two terms, represented as closures.
@@ -29,6 +29,20 @@
 //! `0`, this is the end of the current interpolated expressions, and we leave the normal mode and
 //! go back to string mode. In our example, this is the second `}`: at this point, the lexer knows
 //! that the coming characters must be lexed as string tokens, and not as normal tokens.
+//!
+//! Raw strings `r#"..."#`, which can be delimited by any number of `#` characters, do not
+//! support escape sequences nor interpolation, and are thus lexed in one go in normal mode.
+//!
+//! Finally, a colon at the top-level of an interpolated expression which is followed by a valid
+//! [format specifier](../../format/index.html) up to the closing brace, as in `#{x:08.2f}`, is
+//! lexed as a single `FormatSpec` token instead of the start of a type annotation.
+//!
+//! `rec`, `and`, `match` and `as` are contextual keywords: `rec` is only lexed as such right after
+//! `let` and when it is followed by a binding, `and` when it starts a new binding of an open `let
+//! rec` group, `match` when it is followed by the cases of a match expression, and `as` after the
+//! path of an import or a wildcard. They thus remain usable as ordinary identifiers and field
+//! names.
+use crate::format::FormatSpec;
 use logos::Logos;
 
 /// The tokens in normal mode.
@@ -38,9 +52,12 @@ pub enum NormalToken<'input> {
     #[error]
     Error,
 
-    #[regex("_?[a-zA-Z][_a-zA-Z0-9]*")]
+    #[regex("_?\\p{XID_Start}\\p{XID_Continue}*")]
     Identifier(&'input str),
-    #[regex("[0-9]*\\.?[0-9]+", |lex| lex.slice().parse())]
+    #[regex("([0-9]+(_[0-9]+)*)?\\.?[0-9]+(_[0-9]+)*", |lex| parse_decimal(lex.slice()))]
+    #[regex("0x[0-9a-fA-F]+(_[0-9a-fA-F]+)*", |lex| parse_radix(lex.slice(), 16))]
+    #[regex("0o[0-7]+(_[0-7]+)*", |lex| parse_radix(lex.slice(), 8))]
+    #[regex("0b[01]+(_[01]+)*", |lex| parse_radix(lex.slice(), 2))]
     NumLiteral(f64),
 
     #[token("Dyn")]
@@ -66,8 +83,16 @@ pub enum NormalToken<'input> {
     In,
     #[token("let")]
     Let,
+    /// `rec` is only a keyword right after `let`, see [`Lexer::next`].
+    Rec,
+    /// `and` is only a keyword between the bindings of a `let rec` group, see [`Lexer::next`].
+    And,
     #[token("switch")]
     Switch,
+    /// `match` is only a keyword when it starts a match expression, see [`Lexer::next`].
+    Match,
+    /// `as` is only a keyword after an imported path or a wildcard, see [`Lexer::next`].
+    As,
 
     #[token("null")]
     Null,
@@ -76,6 +101,8 @@ pub enum NormalToken<'input> {
     #[token("false")]
     False,
 
+    #[token(";")]
+    Semicolon,
     #[token(",")]
     Comma,
     #[token(":")]
@@ -90,6 +117,10 @@ pub enum NormalToken<'input> {
     Ampersand,
     #[token(".")]
     Dot,
+    #[token("..")]
+    DoubleDot,
+    #[token("?")]
+    QuestionMark,
     #[token("$[")]
     DollarBracket,
     #[token("\"")]
@@ -124,6 +155,8 @@ pub enum NormalToken<'input> {
     Fun,
     #[token("import")]
     Import,
+    #[token("import_str")]
+    ImportStr,
     #[token("|")]
     Pipe,
     #[token("->")]
@@ -138,6 +171,8 @@ pub enum NormalToken<'input> {
     Underscore,
     #[regex("m(#+)\"", |lex| lex.slice().len())]
     MultiStringStart(usize),
+    #[regex("r(#+)\"", |lex| lex.slice().len() - 2)]
+    RawStringStart(usize),
 
     #[token("%tag%")]
     Tag,
@@ -202,12 +237,24 @@ pub enum NormalToken<'input> {
     ElemAt,
     #[token("%generate%")]
     ListGen,
+    #[token("%listSum%")]
+    ListSum,
+    #[token("%listProduct%")]
+    ListProduct,
+    #[token("%listMin%")]
+    ListMin,
+    #[token("%listMax%")]
+    ListMax,
+    #[token("%listMean%")]
+    ListMean,
     #[token("merge")]
     Merge,
     #[token("default")]
     Default,
     #[token("doc")]
     Doc,
+    #[token("sensitive")]
+    Sensitive,
 
     #[token("%hash%")]
     OpHash,
@@ -243,8 +290,40 @@ pub enum NormalToken<'input> {
     StrLength,
     #[token("%strSubstr%")]
     StrSubstr,
+    #[token("%strIndent%")]
+    StrIndent,
+    #[token("%strDedent%")]
+    StrDedent,
+    #[token("%strSplice%")]
+    StrSplice,
+    #[token("%getPath%")]
+    GetPath,
+    #[token("%hasPath%")]
+    HasPath,
+    #[token("%updatePath%")]
+    UpdatePath,
+    #[token("%strPadLeft%")]
+    StrPadLeft,
+    #[token("%strPadRight%")]
+    StrPadRight,
+    #[token("%strCenter%")]
+    StrCenter,
+    #[token("%strTruncate%")]
+    StrTruncate,
+    #[token("%blameDiff%")]
+    BlameDiff,
+    #[token("%trace%")]
+    Trace,
+    #[token("%clock%")]
+    Clock,
+    #[token("%deepRepr%")]
+    DeepRepr,
     #[token("%toStr%")]
     ToStr,
+    #[token("%toStrFixed%")]
+    ToStrFixed,
+    #[token("%strFormat%")]
+    StrFormat,
     #[token("%numFromStr%")]
     NumFromStr,
     #[token("%enumFromStr%")]
@@ -272,6 +351,16 @@ pub enum NormalToken<'input> {
     GreaterOrEq,
     #[regex("//[^\n]*")]
     LineComment,
+    /// A documentation comment `## ...`, attached to the field or let binding that follows it.
+    #[regex("##[^\n]*")]
+    DocComment(&'input str),
+    /// The content of a raw string. This token is not produced by the underlying lexer, but by
+    /// the modal lexer once it has found the end delimiter matching a `RawStringStart`.
+    RawString(&'input str),
+    /// A format specifier ending an interpolated expression, without the leading colon. This
+    /// token is not produced by the underlying lexer, but by the modal lexer when it encounters a
+    /// colon at the top-level of an interpolated expression.
+    FormatSpec(&'input str),
 }
 
 /// The tokens in string mode.
@@ -295,6 +384,8 @@ pub enum StringToken<'input> {
     // Repetition range `{2}` was not supported at the time of writing this regex.
     #[regex("\\\\x[A-Fa-f0-9][A-Fa-f0-9]", |lex| &lex.slice()[2..4])]
     EscapedAscii(&'input str),
+    #[regex("\\\\u\\{[^}\"]*\\}", |lex| { let s = lex.slice(); &s[3..s.len() - 1] })]
+    EscapedUnicode(&'input str),
 }
 
 /// The tokens in multiline string mode.
@@ -303,9 +394,20 @@ pub enum MultiStringToken<'input> {
     #[error]
     Error,
 
-    #[regex("[^\"#]+")]
+    #[regex("[^\"#\\\\]+")]
     Literal(&'input str),
 
+    // Multiline strings only support a few escape sequences, such that a backslash followed by any
+    // other character, as in a regular expression `\d+`, is kept as it is.
+    #[regex("\\\\[ntr0\\\\]", |lex| lex.slice().chars().nth(1))]
+    EscapedChar(char),
+    #[regex("\\\\x[A-Fa-f0-9][A-Fa-f0-9]", |lex| &lex.slice()[2..4])]
+    EscapedAscii(&'input str),
+    #[regex("\\\\u\\{[^}\"]*\\}", |lex| { let s = lex.slice(); &s[3..s.len() - 1] })]
+    EscapedUnicode(&'input str),
+    #[token("\\", |lex| lex.slice())]
+    Backslash(&'input str),
+
     // A token that starts as a multiline end delimiter, but is not one. To avoid hacking
     // look-aheads in the lexer (which Logos doesn't support for performance reason), we just use a
     // separate token. This one has lowest matching priority according to Logos' rules, so it is
@@ -379,10 +481,30 @@ pub enum LexicalError {
     InvalidEscapeSequence(usize),
     /// Invalid escape ASCII code in a string literal.
     InvalidAsciiEscapeCode(usize),
+    /// A non-ASCII identifier was found while identifiers are restricted to ASCII.
+    NonAsciiIdentifier(usize, usize),
+    /// Invalid Unicode escape sequence `\u{..}` in a string literal.
+    InvalidUnicodeEscape(usize, usize),
+    /// The end of the input was reached inside a string literal. Contain the span of the opening
+    /// delimiter.
+    UnterminatedString(usize, usize),
+    /// An integer literal in base 16, 8 or 2 which cannot be represented exactly as a number.
+    InexactNumber(usize, usize),
     /// Generic lexer error
     Generic(usize, usize),
 }
 
+/// The characters allowed in identifiers.
+#[derive(Clone, PartialEq, Eq, Debug, Copy, Default)]
+pub enum IdentPolicy {
+    /// An identifier is an optional underscore followed by a Unicode `XID_Start` character and by
+    /// `XID_Continue` characters.
+    #[default]
+    Unicode,
+    /// Identifiers are restricted to ASCII letters, digits and underscores.
+    Ascii,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Copy)]
 pub enum ModeElt {
     Str,
@@ -412,14 +534,87 @@ pub struct Lexer<'input> {
     /// already inside an interpolated expression. In this case, once this string ends, we must
     /// restore the original brace counter, which is what this stack is used for.
     pub stack: Vec<ModeElt>,
+    /// The spans of the opening delimiters of the strings being lexed, used to report
+    /// unterminated strings.
+    pub string_starts: Vec<(usize, usize)>,
+    /// The characters allowed in identifiers.
+    pub ident_policy: IdentPolicy,
+    /// The last token produced, used to lex contextual keywords.
+    pub last: Option<Token<'input>>,
+    /// The nesting depth of parentheses, brackets, braces and interpolated expressions.
+    pub depth: usize,
+    /// The open `let rec` groups, as the depth at which they appear and the number of `let`
+    /// bindings nested at the same depth which are not closed by an `in` yet. The group is closed
+    /// by the next `in` at its depth.
+    pub rec_groups: Vec<(usize, usize)>,
 }
 
 impl<'input> Lexer<'input> {
     pub fn new(s: &'input str) -> Self {
+        Self::with_ident_policy(s, IdentPolicy::default())
+    }
+
+    pub fn with_ident_policy(s: &'input str, ident_policy: IdentPolicy) -> Self {
         Lexer {
             lexer: Some(ModalLexer::Normal(NormalToken::lexer(s))),
             stack: Vec::new(),
             count: 0,
+            string_starts: Vec::new(),
+            ident_policy,
+            last: None,
+            depth: 0,
+            rec_groups: Vec::new(),
+        }
+    }
+
+    /// Update the nesting depth and the open `let rec` groups after a token.
+    fn track_rec_groups(&mut self, token: Option<&Token<'input>>) {
+        let in_group = |groups: &[(usize, usize)], depth| {
+            groups.last().map(|(group_depth, _)| *group_depth) == Some(depth)
+        };
+
+        match token {
+            Some(Token::Normal(NormalToken::LParen))
+            | Some(Token::Normal(NormalToken::LBracket))
+            | Some(Token::Normal(NormalToken::LBrace))
+            | Some(Token::Str(StringToken::HashBrace))
+            | Some(Token::MultiStr(MultiStringToken::Interpolation)) => self.depth += 1,
+            Some(Token::Normal(NormalToken::RParen))
+            | Some(Token::Normal(NormalToken::RBracket))
+            | Some(Token::Normal(NormalToken::RBrace)) => {
+                self.depth = self.depth.saturating_sub(1);
+                let depth = self.depth;
+                self.rec_groups
+                    .retain(|(group_depth, _)| *group_depth <= depth);
+            }
+            Some(Token::Normal(NormalToken::Let)) if in_group(&self.rec_groups, self.depth) => {
+                self.rec_groups.last_mut().unwrap().1 += 1
+            }
+            Some(Token::Normal(NormalToken::Rec)) => {
+                // The `let` introducing this group was counted as a binding of the enclosing one.
+                if in_group(&self.rec_groups, self.depth) {
+                    self.rec_groups.last_mut().unwrap().1 -= 1;
+                }
+                self.rec_groups.push((self.depth, 0));
+            }
+            Some(Token::Normal(NormalToken::In)) if in_group(&self.rec_groups, self.depth) => {
+                match self.rec_groups.last_mut().unwrap() {
+                    (_, 0) => {
+                        self.rec_groups.pop();
+                    }
+                    (_, lets) => *lets -= 1,
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Return the whole input being lexed.
+    pub fn source(&self) -> &'input str {
+        match self.lexer.as_ref().unwrap() {
+            ModalLexer::Normal(lexer) => lexer.source(),
+            ModalLexer::Str(lexer) => lexer.source(),
+            ModalLexer::MultiStr(lexer) => lexer.source(),
         }
     }
 
@@ -495,6 +690,14 @@ impl<'input> Lexer<'input> {
         }
     }
 
+    /// Return `true` if the lexer is currently lexing an interpolated expression.
+    fn in_interpolation(&self) -> bool {
+        matches!(
+            self.stack.last(),
+            Some(ModeElt::Str) | Some(ModeElt::MultiStr(_))
+        )
+    }
+
     fn leave_normal(&mut self) {
         match self.lexer.take() {
             Some(ModalLexer::Normal(lexer)) => {
@@ -521,13 +724,105 @@ impl<'input> Iterator for Lexer<'input> {
 
         let lexer = self.lexer.as_mut().unwrap();
         let mut token = lexer.next();
-        let span = lexer.span();
+        let mut span = lexer.span();
 
         match token.as_ref() {
-            Some(Normal(NormalToken::DoubleQuote)) => self.enter_str(),
+            Some(Normal(NormalToken::DoubleQuote)) => {
+                self.string_starts.push((span.start, span.end));
+                self.enter_str()
+            }
             Some(Normal(NormalToken::MultiStringStart(hash_count))) => {
+                self.string_starts.push((span.start, span.end));
                 self.enter_indstr(*hash_count)
             }
+            Some(Normal(NormalToken::Colon)) if self.count == 0 && self.in_interpolation() => {
+                if let Some(ModalLexer::Normal(lexer)) = self.lexer.as_mut() {
+                    let remainder = lexer.remainder();
+                    let spec = remainder.find('}').map(|end| &remainder[..end]);
+
+                    if let Some(spec) = spec.filter(|spec| FormatSpec::parse(spec).is_some()) {
+                        lexer.bump(spec.len());
+                        token = Some(Normal(NormalToken::FormatSpec(spec)));
+                        span = lexer.span();
+                    }
+                }
+            }
+            // A raw string is lexed in one go until the first end delimiter with the same number
+            // of `#` characters, as its content is not interpreted in any way.
+            Some(Normal(NormalToken::RawStringStart(hash_count))) => {
+                let end_delimiter = format!("\"{}", "#".repeat(*hash_count));
+
+                if let Some(ModalLexer::Normal(lexer)) = self.lexer.as_mut() {
+                    let remainder = lexer.remainder();
+
+                    match remainder.find(&end_delimiter) {
+                        Some(end) => {
+                            lexer.bump(end + end_delimiter.len());
+                            token = Some(Normal(NormalToken::RawString(&remainder[..end])));
+                            span = lexer.span();
+                        }
+                        None => {
+                            return Some(Err(LexicalError::Generic(
+                                span.start,
+                                span.end + remainder.len(),
+                            )))
+                        }
+                    }
+                }
+            }
+            Some(Normal(NormalToken::Identifier(id)))
+                if self.ident_policy == IdentPolicy::Ascii && !id.is_ascii() =>
+            {
+                return Some(Err(LexicalError::NonAsciiIdentifier(span.start, span.end)))
+            }
+            // `rec` is a keyword only when it starts a `let rec` group, as in `let rec f = ...`.
+            // Otherwise, as in `let rec = ...` or `fun rec => ...`, it is a plain identifier.
+            Some(Normal(NormalToken::Identifier("rec")))
+                if self.last == Some(Normal(NormalToken::Let)) =>
+            {
+                if let Some(ModalLexer::Normal(lexer)) = self.lexer.as_ref() {
+                    let next = lexer.remainder().trim_start().chars().next();
+
+                    if !matches!(next, Some('=') | Some(':') | Some('|')) {
+                        token = Some(Normal(NormalToken::Rec));
+                    }
+                }
+            }
+            // `and` is a keyword only when it starts a new binding of the innermost `let rec`
+            // group, as in `let rec f = ... and g = ...`. Elsewhere, it is a plain identifier.
+            Some(Normal(NormalToken::Identifier("and")))
+                if self.rec_groups.last().map(|(depth, _)| *depth) == Some(self.depth) =>
+            {
+                if let Some(ModalLexer::Normal(lexer)) = self.lexer.as_ref() {
+                    if starts_binding(lexer.remainder()) {
+                        token = Some(Normal(NormalToken::And));
+                    }
+                }
+            }
+            // Similarly, `match` is a keyword only when it is followed by the cases of a match
+            // expression, such that it remains usable as a field name, as in `strings.match`.
+            Some(Normal(NormalToken::Identifier("match")))
+                if self.last != Some(Normal(NormalToken::Dot)) =>
+            {
+                if let Some(ModalLexer::Normal(lexer)) = self.lexer.as_ref() {
+                    if lexer.remainder().trim_start().starts_with('{') {
+                        token = Some(Normal(NormalToken::Match));
+                    }
+                }
+            }
+            // `as` is a keyword only after the path of an import, as in `import "lib.ncl" as lib`,
+            // or after a wildcard, as in `_ as x => ...`.
+            Some(Normal(NormalToken::Identifier("as")))
+                if matches!(
+                    self.last,
+                    Some(Normal(NormalToken::DoubleQuote))
+                        | Some(Normal(NormalToken::RawString(_)))
+                        | Some(MultiStr(MultiStringToken::End))
+                        | Some(Normal(NormalToken::Underscore))
+                ) =>
+            {
+                token = Some(Normal(NormalToken::As))
+            }
             Some(Normal(NormalToken::LBrace)) => self.count += 1,
             Some(Normal(NormalToken::RBrace)) => {
                 if self.count == 0 {
@@ -541,6 +836,7 @@ impl<'input> Iterator for Lexer<'input> {
                 }
             }
             Some(Str(StringToken::DoubleQuote)) => {
+                self.string_starts.pop();
                 self.leave_str();
                 // To make things simpler on the parser side, we only return one variant for
                 // `DoubleQuote`, namely the the normal one.
@@ -568,17 +864,39 @@ impl<'input> Iterator for Lexer<'input> {
                     return Some(Err(LexicalError::InvalidEscapeSequence(span.start + 1)));
                 }
             }
-            Some(Str(StringToken::EscapedAscii(code))) => {
+            Some(Str(StringToken::EscapedAscii(code)))
+            | Some(MultiStr(MultiStringToken::EscapedAscii(code))) => {
                 if let Some(esc) = escape_ascii(code) {
                     token = Some(Str(StringToken::EscapedChar(esc)));
                 } else {
                     return Some(Err(LexicalError::InvalidAsciiEscapeCode(span.start + 2)));
                 }
             }
+            // Escaped characters of both kinds of strings are returned as the `EscapedChar` variant
+            // of string mode, such that the parser only has to handle one of them.
+            Some(MultiStr(MultiStringToken::EscapedChar(c))) => {
+                let esc = escape_char(*c).expect("lexer: invalid multiline string escape");
+                token = Some(Str(StringToken::EscapedChar(esc)));
+            }
+            Some(Str(StringToken::EscapedUnicode(code)))
+            | Some(MultiStr(MultiStringToken::EscapedUnicode(code))) => {
+                if let Some(esc) = escape_unicode(code) {
+                    token = Some(Str(StringToken::EscapedChar(esc)));
+                } else {
+                    return Some(Err(LexicalError::InvalidUnicodeEscape(
+                        span.start, span.end,
+                    )));
+                }
+            }
+            // A backslash which doesn't start an escape sequence is part of a multiline string.
+            Some(MultiStr(MultiStringToken::Backslash(s))) => {
+                token = Some(MultiStr(MultiStringToken::Literal(s)))
+            }
             // If we encounter a `CandidateEnd` token with the right number of characters, this is
             // the end of a multiline string
             Some(MultiStr(MultiStringToken::CandidateEnd(s))) if s.len() == self.count => {
                 token = Some(MultiStr(MultiStringToken::End));
+                self.string_starts.pop();
                 self.leave_indstr()
             }
             // Otherwise, it is just part of the string, so we transform the token into a
@@ -586,6 +904,11 @@ impl<'input> Iterator for Lexer<'input> {
             Some(MultiStr(MultiStringToken::CandidateEnd(s))) => {
                 token = Some(MultiStr(MultiStringToken::FalseEnd(s)))
             }
+            // A well-formed integer literal is only rejected by `parse_radix` if it isn't exactly
+            // representable.
+            Some(Normal(NormalToken::Error)) if is_radix_literal(&self.source()[span.clone()]) => {
+                return Some(Err(LexicalError::InexactNumber(span.start, span.end)))
+            }
             // Early report errors for now. This could change in the future
             Some(Normal(NormalToken::Error))
             | Some(Str(StringToken::Error))
@@ -594,13 +917,38 @@ impl<'input> Iterator for Lexer<'input> {
             }
             // Ignore comment
             Some(Normal(NormalToken::LineComment)) => return self.next(),
+            // Reaching the end of the input inside a string, or inside an interpolated expression,
+            // is reported at the opening delimiter of the string rather than at the end of the
+            // input.
+            None => {
+                if let Some((start, end)) = self.string_starts.pop() {
+                    return Some(Err(LexicalError::UnterminatedString(start, end)));
+                }
+            }
             _ => (),
         }
 
+        self.track_rec_groups(token.as_ref());
+        self.last = token.clone();
         token.map(|t| Ok((span.start, t, span.end)))
     }
 }
 
+/// Determine if the remainder of the input starts with a binding, that is an identifier followed
+/// by `=`, by a type annotation or by a contract annotation.
+fn starts_binding(remainder: &str) -> bool {
+    let rest = remainder.trim_start();
+    let ident_len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    let after = rest[ident_len..].trim_start();
+
+    ident_len > 0
+        && ((after.starts_with('=') && !after.starts_with("=="))
+            || after.starts_with(':')
+            || (after.starts_with('|') && !after.starts_with("||") && !after.starts_with("|>")))
+}
+
 /// Generate the character corresponding to an escape char.
 fn escape_char(chr: char) -> Option<char> {
     match chr {
@@ -611,10 +959,62 @@ fn escape_char(chr: char) -> Option<char> {
         'n' => Some('\n'),
         'r' => Some('\r'),
         't' => Some('\t'),
+        '0' => Some('\0'),
         _ => None,
     }
 }
 
+/// Parse a decimal number literal, which can contain `_` separators between digits.
+fn parse_decimal(literal: &str) -> Option<f64> {
+    literal.replace('_', "").parse().ok()
+}
+
+/// Parse an integer literal in base `radix`, prefixed by `0x`, `0o` or `0b` and possibly
+/// containing `_` separators between digits. Fail if the value cannot be represented exactly as a
+/// number.
+fn parse_radix(literal: &str, radix: u32) -> Option<f64> {
+    let value = u64::from_str_radix(&literal[2..].replace('_', ""), radix).ok()?;
+    let result = value as f64;
+
+    // The conversion back to `u64` saturates, so values rounded up to 2^64 must be excluded
+    // before comparing.
+    if result < 2f64.powi(64) && result as u64 == value {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+/// Determine if a string has the form of an integer literal in base 16, 8 or 2, as accepted by
+/// `parse_radix`.
+fn is_radix_literal(literal: &str) -> bool {
+    let radix = match literal.get(..2) {
+        Some("0x") => 16,
+        Some("0o") => 8,
+        Some("0b") => 2,
+        _ => return false,
+    };
+    let digits = &literal[2..];
+
+    !digits.is_empty()
+        && !digits.starts_with('_')
+        && !digits.ends_with('_')
+        && !digits.contains("__")
+        && digits.chars().all(|c| c == '_' || c.is_digit(radix))
+}
+
+/// Generate the character corresponding to a Unicode escape sequence `\u{code}`, where `code` is
+/// the hexadecimal value of a code point, which is neither a surrogate nor above `10FFFF`.
+fn escape_unicode(code: &str) -> Option<char> {
+    if code.is_empty() || code.len() > 6 || !code.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u32::from_str_radix(code, 16)
+        .ok()
+        .and_then(std::char::from_u32)
+}
+
 /// Generate the character corresponding to an ASCII escape sequence.
 ///
 /// # Arguments
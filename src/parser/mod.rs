@@ -1,6 +1,9 @@
+use crate::error::ParseError;
 use crate::identifier::Ident;
-use crate::term::RichTerm;
-use lalrpop_util::lalrpop_mod;
+use crate::term::{RichTerm, Term};
+use codespan::{FileId, Files};
+use lalrpop_util::{lalrpop_mod, ErrorRecovery};
+use lexer::{Lexer, LexicalError, Token};
 
 lalrpop_mod!(
     #[allow(clippy::all)]
@@ -8,12 +11,183 @@ lalrpop_mod!(
     pub grammar);
 
 pub mod lexer;
+pub mod normalize;
 #[cfg(test)]
 mod tests;
 pub mod utils;
 
 /// Either a term or a toplevel let declaration.
+#[derive(Clone, Debug, PartialEq)]
 pub enum ExtendedTerm {
     RichTerm(RichTerm),
     ToplevelLet(Ident, RichTerm),
+    /// A toplevel `let rec` declaration of a group of mutually recursive bindings.
+    ToplevelLetRec(Vec<(Ident, RichTerm)>),
+}
+
+impl ExtendedTerm {
+    /// Give the subterms generated without position the position of their closest ancestor. See
+    /// [`RichTerm::inherit_pos`](../term/struct.RichTerm.html#method.inherit_pos).
+    fn inherit_pos(&mut self) {
+        let terms = match self {
+            ExtendedTerm::RichTerm(t) | ExtendedTerm::ToplevelLet(_, t) => vec![t],
+            ExtendedTerm::ToplevelLetRec(bindings) => bindings.iter_mut().map(|(_, t)| t).collect(),
+        };
+
+        for t in terms {
+            t.inherit_pos();
+            debug_assert!(t.all_pos_defined(), "parsed term without position: {:?}", t);
+        }
+    }
+}
+
+/// Parse a Nickel expression, without requiring a [`Cache`](../cache/struct.Cache.html). Return
+/// the parsed term, or all the syntax errors found in `source` in the order they were encountered.
+///
+/// The positions of the term and of the errors refer to a file database which only holds
+/// `source`, and which is dropped afterwards. To report errors with the corresponding source
+/// snippets, add `source` to your own database and use [`parse_expr_in`](fn.parse_expr_in.html).
+pub fn parse_expr(source: &str) -> Result<RichTerm, Vec<ParseError>> {
+    parse_expr_in(mk_file_id(source), source)
+}
+
+/// Parse a Nickel expression whose source has the identifier `file_id` in a file database. See
+/// [`parse_expr`](fn.parse_expr.html).
+pub fn parse_expr_in(file_id: FileId, source: &str) -> Result<RichTerm, Vec<ParseError>> {
+    let (t, errors) = grammar::TermParser::new().parse_tolerant(file_id, Lexer::new(source));
+    if errors.is_empty() {
+        Ok(t)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parse a Nickel expression or a toplevel let declaration, as accepted by the REPL, without
+/// requiring a [`Cache`](../cache/struct.Cache.html). See [`parse_expr`](fn.parse_expr.html).
+pub fn parse_extended(source: &str) -> Result<ExtendedTerm, Vec<ParseError>> {
+    parse_extended_in(mk_file_id(source), source)
+}
+
+/// Parse a Nickel expression or a toplevel let declaration whose source has the identifier
+/// `file_id` in a file database. See [`parse_extended`](fn.parse_extended.html).
+pub fn parse_extended_in(file_id: FileId, source: &str) -> Result<ExtendedTerm, Vec<ParseError>> {
+    let (t, errors) =
+        grammar::ExtendedTermParser::new().parse_tolerant(file_id, Lexer::new(source));
+    if errors.is_empty() {
+        Ok(t)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Create a file database holding only `source`, and return the identifier of `source`.
+fn mk_file_id(source: &str) -> FileId {
+    Files::new().add("<input>", String::from(source))
+}
+
+/// The result of a parser, together with the syntax errors it recovered from.
+type Recovered<'input, T> = (
+    Result<T, lalrpop_util::ParseError<usize, Token<'input>, LexicalError>>,
+    Vec<ErrorRecovery<usize, Token<'input>, LexicalError>>,
+);
+
+/// Turn the result of a parser into an error if it is an error itself, or if the parser had to
+/// recover from a syntax error. In the latter case, the first error is returned.
+fn into_strict<T>(
+    (result, errors): Recovered<T>,
+    file_id: FileId,
+    source: &str,
+) -> Result<T, ParseError> {
+    match errors.into_iter().next() {
+        Some(recovery) => Err(ParseError::from_lalrpop(recovery.error, file_id, source)),
+        None => result.map_err(|err| ParseError::from_lalrpop(err, file_id, source)),
+    }
+}
+
+impl grammar::TermParser {
+    /// Parse a term, failing on the first syntax error.
+    pub fn parse_strict(&self, file_id: FileId, lexer: Lexer) -> Result<RichTerm, ParseError> {
+        let source = lexer.source();
+        let mut errors = Vec::new();
+        let result = self.parse(file_id, &mut errors, lexer);
+        let mut t = into_strict((result, errors), file_id, source)?;
+        t.inherit_pos();
+        debug_assert!(t.all_pos_defined(), "parsed term without position: {:?}", t);
+        Ok(t)
+    }
+
+    /// Parse a term, recovering from syntax errors. Return a best-effort term, where the parts
+    /// that could not be parsed are replaced by [`Term::ParseError`](../term/enum.Term.html)
+    /// nodes, together with the list of syntax errors in the order they were encountered.
+    ///
+    /// If the parser could not recover at all, the returned term is a single error node.
+    pub fn parse_tolerant(&self, file_id: FileId, lexer: Lexer) -> (RichTerm, Vec<ParseError>) {
+        let source = lexer.source();
+        let mut recovered = Vec::new();
+        let result = self.parse(file_id, &mut recovered, lexer);
+        let mut errors: Vec<ParseError> = recovered
+            .into_iter()
+            .map(|recovery| ParseError::from_lalrpop(recovery.error, file_id, source))
+            .collect();
+
+        match result {
+            Ok(mut t) => {
+                t.inherit_pos();
+                (t, errors)
+            }
+            Err(err) => {
+                errors.push(ParseError::from_lalrpop(err, file_id, source));
+                (RichTerm::from(Term::ParseError), errors)
+            }
+        }
+    }
+}
+
+impl grammar::ExtendedTermParser {
+    /// Parse a term or a toplevel let declaration, failing on the first syntax error.
+    pub fn parse_strict(&self, file_id: FileId, lexer: Lexer) -> Result<ExtendedTerm, ParseError> {
+        let source = lexer.source();
+        let mut errors = Vec::new();
+        let result = self.parse(file_id, &mut errors, lexer);
+        let mut t = into_strict((result, errors), file_id, source)?;
+        t.inherit_pos();
+        Ok(t)
+    }
+
+    /// Parse a term or a toplevel let declaration, recovering from syntax errors. See
+    /// [`TermParser::parse_tolerant`](struct.TermParser.html#method.parse_tolerant).
+    pub fn parse_tolerant(&self, file_id: FileId, lexer: Lexer) -> (ExtendedTerm, Vec<ParseError>) {
+        let source = lexer.source();
+        let mut recovered = Vec::new();
+        let result = self.parse(file_id, &mut recovered, lexer);
+        let mut errors: Vec<ParseError> = recovered
+            .into_iter()
+            .map(|recovery| ParseError::from_lalrpop(recovery.error, file_id, source))
+            .collect();
+
+        match result {
+            Ok(mut t) => {
+                t.inherit_pos();
+                (t, errors)
+            }
+            Err(err) => {
+                errors.push(ParseError::from_lalrpop(err, file_id, source));
+                (
+                    ExtendedTerm::RichTerm(RichTerm::from(Term::ParseError)),
+                    errors,
+                )
+            }
+        }
+    }
+
+    /// Parse the content of a file, which is a term possibly preceded by toplevel declarations
+    /// terminated by `;`. Fail on the first syntax error, or if the file ends with a declaration.
+    pub fn parse_file(&self, file_id: FileId, lexer: Lexer) -> Result<RichTerm, ParseError> {
+        match self.parse_strict(file_id, lexer)? {
+            ExtendedTerm::RichTerm(t) => Ok(t),
+            ExtendedTerm::ToplevelLet(..) | ExtendedTerm::ToplevelLetRec(..) => Err(
+                ParseError::UnexpectedEOF(file_id, vec![String::from("\";\"")]),
+            ),
+        }
+    }
 }
@@ -0,0 +1,58 @@
+//! Normalization of identifiers.
+//!
+//! The same identifier can be written using different sequences of Unicode code points, which
+//! look identical: `é` can be either the single code point U+00E9, or the letter `e` followed by
+//! the combining acute accent U+0301. Identifiers, and the field names given as static strings,
+//! are normalized at parsing time to the Unicode normalization form NFC, so that visually
+//! identical names compare equal.
+use crate::term::{RichTerm, StrChunk, Term};
+use unicode_normalization::UnicodeNormalization;
+
+/// Normalize an identifier to NFC: combining marks are put in canonical order, and composed with
+/// their base character when possible.
+pub fn normalize(ident: &str) -> String {
+    ident.nfc().collect()
+}
+
+/// Normalize a field name given as a string. Only static strings are normalized: interpolated
+/// strings are left unchanged.
+pub fn normalize_field_name(rt: RichTerm) -> RichTerm {
+    let pos = rt.pos;
+
+    match *rt.term {
+        Term::Str(s) => RichTerm::new(Term::Str(normalize(&s)), pos),
+        Term::StrChunks(chunks) if chunks.iter().all(|c| matches!(c, StrChunk::Literal(_))) => {
+            let chunks = chunks
+                .into_iter()
+                .map(|chunk| match chunk {
+                    StrChunk::Literal(s) => StrChunk::Literal(normalize(&s)),
+                    chunk => chunk,
+                })
+                .collect();
+            RichTerm::new(Term::StrChunks(chunks), pos)
+        }
+        term => RichTerm::new(term, pos),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+
+    #[test]
+    fn compose() {
+        assert_eq!(normalize("caf\u{65}\u{301}"), "caf\u{E9}");
+        assert_eq!(normalize("\u{65}\u{323}\u{302}"), "\u{1EC7}");
+        assert_eq!(normalize("na\u{EF}ve"), "na\u{EF}ve");
+        assert_eq!(normalize("x\u{301}"), "x\u{301}");
+        assert_eq!(normalize(""), "");
+    }
+
+    #[test]
+    fn reorder_marks() {
+        // The dot below has a lower canonical combining class than the circumflex.
+        assert_eq!(normalize("\u{65}\u{302}\u{323}"), "\u{1EC7}");
+        assert_eq!(normalize("x\u{302}\u{323}"), "x\u{323}\u{302}");
+        assert_eq!(normalize("\u{1E0B}\u{323}"), "\u{1E0D}\u{307}");
+    }
+}
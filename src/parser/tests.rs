@@ -1,9 +1,10 @@
 use super::lexer::{Lexer, LexicalError, NormalToken, StringToken, Token};
 use crate::error::ParseError;
 use crate::identifier::Ident;
+use crate::position::TermPos;
 use crate::term::make as mk_term;
 use crate::term::Term::*;
-use crate::term::{BinaryOp, RichTerm, StrChunk, UnaryOp};
+use crate::term::{AnnotKind, BinaryOp, RichTerm, StrChunk, UnaryOp};
 use crate::{mk_app, mk_switch};
 use assert_matches::assert_matches;
 use codespan::Files;
@@ -11,9 +12,7 @@ use codespan::Files;
 fn parse(s: &str) -> Result<RichTerm, ParseError> {
     let id = Files::new().add("<test>", String::from(s));
 
-    super::grammar::TermParser::new()
-        .parse(id, Lexer::new(&s))
-        .map_err(|err| ParseError::from_lalrpop(err, id))
+    super::grammar::TermParser::new().parse_strict(id, Lexer::new(&s))
 }
 
 fn parse_without_pos(s: &str) -> RichTerm {
@@ -42,6 +41,37 @@ fn numbers() {
     assert_eq!(parse_without_pos("22.22"), Num(22.22).into());
     assert_eq!(parse_without_pos("(22)"), Num(22.0).into());
     assert_eq!(parse_without_pos("((22))"), Num(22.0).into());
+    assert_eq!(parse_without_pos(".5"), Num(0.5).into());
+    assert_eq!(parse_without_pos("1_000_000"), Num(1_000_000.0).into());
+    assert_eq!(parse_without_pos("1_000.000_1"), Num(1000.0001).into());
+    assert_eq!(parse_without_pos("0xff"), Num(255.0).into());
+    assert_eq!(parse_without_pos("0xFF_FF"), Num(65535.0).into());
+    assert_eq!(parse_without_pos("0o755"), Num(493.0).into());
+    assert_eq!(parse_without_pos("0b1010"), Num(10.0).into());
+    assert_eq!(
+        parse_without_pos("0x20_0000_0000_0000"),
+        Num(9007199254740992.0).into()
+    );
+
+    assert_eq!(
+        parse_without_pos("0x1000_0000_0000_0000"),
+        Num(1152921504606846976.0).into()
+    );
+
+    assert_matches!(
+        parse("0x20_0000_0000_0001"),
+        Err(ParseError::InexactNumber(..))
+    );
+    // Rounded to 2^64, which saturates when converted back to an integer.
+    assert_matches!(
+        parse("0xffff_ffff_ffff_ffff"),
+        Err(ParseError::InexactNumber(..))
+    );
+    assert_matches!(
+        parse("0xffff_ffff_ffff_ffff_f"),
+        Err(ParseError::InexactNumber(..))
+    );
+    assert_matches!(parse("1__0"), Err(..));
 }
 
 #[test]
@@ -157,7 +187,20 @@ fn enum_terms() {
     assert_eq!(
         parse_without_pos("switch { foo => true, bar => false, _ => 456, } 123"),
         mk_switch!(Num(123.), ("foo", Bool(true)), ("bar", Bool(false)) ; Num(456.))
-    )
+    );
+
+    match parse_without_pos("switch { foo => 1, _ as other => other } 123")
+        .term
+        .as_ref()
+    {
+        Let(var, exp, body) => {
+            assert_eq!(exp, &Num(123.).into());
+            assert_matches!(body.term.as_ref(), Switch(arg, _, Some(default))
+                if arg == &mk_term::var(var.clone())
+                    && default == &mk_term::let_in("other", mk_term::var(var.clone()), mk_term::var("other")));
+        }
+        t => panic!("expected a let binding, got {:?}", t),
+    }
 }
 
 #[test]
@@ -180,7 +223,7 @@ fn record_terms() {
         parse_without_pos("{ a = 1, \"#{123}\" = (if 4 then 5 else 6), d = 42}"),
         mk_app!(
             mk_term::op2(
-                BinaryOp::DynExtend(),
+                BinaryOp::DynDefine(),
                 StrChunks(vec![StrChunk::expr(RichTerm::from(Num(123.)))]),
                 RecRecord(
                     vec![
@@ -254,6 +297,63 @@ fn string_lexing() {
     );
 }
 
+#[test]
+fn raw_strings() {
+    assert_eq!(
+        parse_without_pos("r#\"a \\n #{b}\"#"),
+        Str(String::from("a \\n #{b}")).into()
+    );
+    assert_eq!(
+        parse_without_pos("r###\"\"## \"#\"###"),
+        Str(String::from("\"## \"#")).into()
+    );
+    assert_matches!(
+        parse("r#\"unterminated\""),
+        Err(ParseError::UnexpectedToken(..))
+    );
+}
+
+#[test]
+fn format_spec_lexing() {
+    assert_eq!(
+        lex_without_pos("\"#{x:08.2f}\""),
+        Ok(vec![
+            Token::Normal(NormalToken::DoubleQuote),
+            Token::Str(StringToken::HashBrace),
+            Token::Normal(NormalToken::Identifier("x")),
+            Token::Normal(NormalToken::FormatSpec("08.2f")),
+            Token::Normal(NormalToken::RBrace),
+            Token::Normal(NormalToken::DoubleQuote),
+        ])
+    );
+
+    // Not a valid format specifier: this is a type annotation.
+    assert_eq!(
+        lex_without_pos("\"#{x:Num}\""),
+        Ok(vec![
+            Token::Normal(NormalToken::DoubleQuote),
+            Token::Str(StringToken::HashBrace),
+            Token::Normal(NormalToken::Identifier("x")),
+            Token::Normal(NormalToken::Colon),
+            Token::Normal(NormalToken::Num),
+            Token::Normal(NormalToken::RBrace),
+            Token::Normal(NormalToken::DoubleQuote),
+        ])
+    );
+
+    // Colons outside of the top-level of an interpolated expression are left untouched.
+    assert_eq!(
+        lex_without_pos("{a:8}"),
+        Ok(vec![
+            Token::Normal(NormalToken::LBrace),
+            Token::Normal(NormalToken::Identifier("a")),
+            Token::Normal(NormalToken::Colon),
+            Token::Normal(NormalToken::NumLiteral(8.0)),
+            Token::Normal(NormalToken::RBrace),
+        ])
+    );
+}
+
 #[test]
 fn str_escape() {
     assert_matches!(
@@ -309,9 +409,43 @@ fn ascii_escape() {
     assert_eq!(parse_without_pos("m#\"\\x[f\"#m"), mk_single_chunk("\\x[f"));
     assert_eq!(parse_without_pos("m#\"\\x0\"#m"), mk_single_chunk("\\x0"));
     assert_eq!(parse_without_pos("m#\"\\x0z\"#m"), mk_single_chunk("\\x0z"));
-    assert_eq!(parse_without_pos("m#\"\\x00\"#m"), mk_single_chunk("\\x00"));
-    assert_eq!(parse_without_pos("m#\"\\x08\"#m"), mk_single_chunk("\\x08"));
-    assert_eq!(parse_without_pos("m#\"\\x7F\"#m"), mk_single_chunk("\\x7F"));
+    assert_eq!(parse_without_pos("m#\"\\x00\"#m"), mk_single_chunk("\x00"));
+    assert_eq!(parse_without_pos("m#\"\\x08\"#m"), mk_single_chunk("\x08"));
+    assert_eq!(parse_without_pos("m#\"\\x7F\"#m"), mk_single_chunk("\x7F"));
+    assert_matches!(
+        parse("m#\"\\xFF\"#m"),
+        Err(ParseError::InvalidAsciiEscapeCode(..))
+    );
+}
+
+#[test]
+fn unicode_escape() {
+    assert_eq!(
+        parse_without_pos(r#""rocket \u{1F680}\u{e9}\0""#),
+        mk_single_chunk("rocket \u{1F680}\u{e9}\0"),
+    );
+    assert_eq!(
+        parse_without_pos(r##"m#"\u{1F680}\t\r\0\\n\d+\u"#m"##),
+        mk_single_chunk("\u{1F680}\t\r\0\\n\\d+\\u"),
+    );
+
+    for bad in &[
+        "\\u{}",
+        "\\u{D800}",
+        "\\u{110000}",
+        "\\u{1234567}",
+        "\\u{+41}",
+        "\\u{xyz}",
+    ] {
+        assert_matches!(
+            parse(&format!("\"{}\"", bad)),
+            Err(ParseError::InvalidUnicodeEscape(..))
+        );
+        assert_matches!(
+            parse(&format!("m#\"{}\"#m", bad)),
+            Err(ParseError::InvalidUnicodeEscape(..))
+        );
+    }
 }
 
 /// Regression test for [#230](https://github.com/tweag/nickel/issues/230).
@@ -342,3 +476,320 @@ fn line_comments() {
         parse_without_pos("{field = foo}")
     );
 }
+
+#[test]
+fn doc_comments() {
+    assert_eq!(
+        parse_without_pos(
+            "{
+              ## The port to listen on.
+              ##   Defaults to 80.
+              port = 80,
+            }"
+        ),
+        parse_without_pos("{port | doc \"The port to listen on.\n  Defaults to 80.\" = 80}")
+    );
+    assert_eq!(
+        parse_without_pos("{## Ignored.\nfoo | doc \"Kept.\" = 1}"),
+        parse_without_pos("{foo | doc \"Kept.\" = 1}")
+    );
+    assert_eq!(
+        parse_without_pos("{## Nested.\na.b = 1}"),
+        parse_without_pos("{a.b | doc \"Nested.\" = 1}")
+    );
+    assert_eq!(
+        parse_without_pos("## Identity.\nlet id = fun x => x in id"),
+        parse_without_pos("let id | doc \"Identity.\" = fun x => x in id")
+    );
+    assert_matches!(parse("1 + ## Not attached.\n1"), Err(..));
+}
+
+/// Parse a string in error-recovering mode, and return the partial term without positions
+/// together with the number of syntax errors.
+fn parse_tolerant(s: &str) -> (RichTerm, usize) {
+    let id = Files::new().add("<test>", String::from(s));
+    let (mut t, errors) = super::grammar::TermParser::new().parse_tolerant(id, Lexer::new(s));
+    t.clean_pos();
+    (t, errors.len())
+}
+
+#[test]
+fn error_recovery() {
+    use crate::term::Term::ParseError as Error;
+
+    assert_eq!(
+        parse_tolerant("{a = 1, b = , c = 3}"),
+        (
+            RecRecord(
+                vec![
+                    (Ident::from("a"), Num(1.).into()),
+                    (Ident::from("b"), Error.into()),
+                    (Ident::from("c"), Num(3.).into())
+                ]
+                .into_iter()
+                .collect()
+            )
+            .into(),
+            1
+        )
+    );
+    assert_eq!(
+        parse_tolerant("{, b = 2}"),
+        (parse_without_pos("{b = 2}"), 1)
+    );
+    assert_eq!(
+        parse_tolerant("[1, ), 3]"),
+        (
+            List(vec![Num(1.).into(), Error.into(), Num(3.).into()]).into(),
+            1
+        )
+    );
+    assert_eq!(parse_tolerant("1 + 1"), (parse_without_pos("1 + 1"), 0));
+    assert_matches!(
+        parse("{a = 1, b = , c = 3}"),
+        Err(ParseError::UnexpectedToken(..))
+    );
+}
+
+#[test]
+fn missing_comma() {
+    use crate::position::RawSpan;
+
+    let span_of = |err| match err {
+        Err(ParseError::MissingComma(RawSpan { start, end, .. }, _)) => {
+            Some((start.to_usize(), end.to_usize()))
+        }
+        _ => None,
+    };
+
+    assert_eq!(span_of(parse("{a = 1\n b = 2}")), Some((5, 6)));
+    assert_eq!(span_of(parse("{a = \"x\" \"b c\" = 2}")), Some((7, 8)));
+    assert_eq!(span_of(parse("{a = {b = 1}\n c.d = 2}")), Some((11, 12)));
+    assert_matches!(parse("{a = = 1}"), Err(ParseError::UnexpectedToken(..)));
+}
+
+#[test]
+fn unterminated_strings() {
+    use crate::position::RawSpan;
+
+    let span_of = |err| match err {
+        Err(ParseError::UnterminatedString(RawSpan { start, end, .. })) => {
+            Some((start.to_usize(), end.to_usize()))
+        }
+        _ => None,
+    };
+
+    assert_eq!(span_of(parse("{a = \"abc, b = 2}")), Some((5, 6)));
+    assert_eq!(span_of(parse("\"a\" ++ m#\"abc\"")), Some((7, 10)));
+    assert_eq!(span_of(parse("\"a #{\"b\" ++ 1")), Some((0, 1)));
+    assert_matches!(parse("{a = \"abc\""), Err(ParseError::UnexpectedEOF(..)));
+}
+
+#[test]
+fn unicode_identifiers() {
+    use super::lexer::IdentPolicy;
+
+    assert_eq!(
+        parse_without_pos("{längd = 1, 名前 = 2}"),
+        RecRecord(
+            vec![
+                (Ident::from("längd"), Num(1.).into()),
+                (Ident::from("名前"), Num(2.).into()),
+            ]
+            .into_iter()
+            .collect()
+        )
+        .into()
+    );
+    assert_eq!(
+        parse_without_pos("let caf\u{65}\u{301} = 1 in caf\u{E9}"),
+        parse_without_pos("let caf\u{E9} = 1 in caf\u{E9}")
+    );
+    // Combining marks are reordered before being composed.
+    assert_eq!(
+        parse_without_pos("let e\u{302}\u{323} = 1 in \u{1EC7}"),
+        parse_without_pos("let \u{1EC7} = 1 in \u{1EC7}")
+    );
+    // Static quoted field names are normalized as well, but not interpolated ones.
+    assert_eq!(
+        parse_without_pos("{\"e\u{301}\" = 1}.\"e\u{301}\""),
+        parse_without_pos("{\"\u{E9}\" = 1}.\"\u{E9}\"")
+    );
+    assert_eq!(
+        parse_without_pos("{r#\"e\u{301}\"# = 1}"),
+        parse_without_pos("{r#\"\u{E9}\"# = 1}")
+    );
+    assert_ne!(
+        parse_without_pos("{\"#{x}e\u{301}\" = 1}"),
+        parse_without_pos("{\"#{x}\u{E9}\" = 1}")
+    );
+
+    let s = "let längd = 1 in längd";
+    let id = Files::new().add("<test>", String::from(s));
+    assert_matches!(
+        super::grammar::TermParser::new()
+            .parse_strict(id, Lexer::with_ident_policy(s, IdentPolicy::Ascii)),
+        Err(ParseError::NonAsciiIdentifier(..))
+    );
+}
+
+#[test]
+fn import_as() {
+    assert_eq!(
+        parse_without_pos("import \"lib.ncl\" as lib in lib.f"),
+        parse_without_pos("let lib = import \"lib.ncl\" in lib.f")
+    );
+    assert_eq!(
+        parse_without_pos("import \"a.ncl\" as a\nimport \"b.ncl\" as b\n{x = a, y = b}"),
+        parse_without_pos("let a = import \"a.ncl\" in let b = import \"b.ncl\" in {x = a, y = b}")
+    );
+    assert_matches!(parse("import \"lib.ncl\" as \"lib\" in lib"), Err(..));
+
+    // Outside of imports and wildcards, `as` is an ordinary identifier.
+    assert_eq!(
+        parse_without_pos("let as = 1 in as"),
+        mk_term::let_in("as", Num(1.), mk_term::var("as"))
+    );
+    assert_eq!(
+        parse_without_pos("{as = 1}.as"),
+        mk_term::op1(
+            UnaryOp::StaticAccess(Ident::from("as")),
+            RecRecord(
+                vec![(Ident::from("as"), Num(1.).into())]
+                    .into_iter()
+                    .collect()
+            ),
+        )
+    );
+    assert_eq!(
+        parse_without_pos("import r#\"lib.ncl\"# as as in as"),
+        parse_without_pos("let as = import \"lib.ncl\" in as")
+    );
+}
+
+#[test]
+fn contextual_keywords() {
+    assert_eq!(
+        lex_without_pos("let rec f = rec").unwrap(),
+        vec![
+            Token::Normal(NormalToken::Let),
+            Token::Normal(NormalToken::Rec),
+            Token::Normal(NormalToken::Identifier("f")),
+            Token::Normal(NormalToken::Equals),
+            Token::Normal(NormalToken::Identifier("rec")),
+        ]
+    );
+    assert_eq!(
+        parse_without_pos("let rec = 1 in rec"),
+        mk_term::let_in("rec", Num(1.), mk_term::var("rec"))
+    );
+    assert_eq!(
+        parse_without_pos("fun rec => rec"),
+        mk_term::fun("rec", mk_term::var("rec"))
+    );
+    assert_eq!(
+        parse_without_pos("let and = 1 in and"),
+        mk_term::let_in("and", Num(1.), mk_term::var("and"))
+    );
+
+    // Inside a `let rec` group, `and` is a keyword only when it starts a new binding, and only at
+    // the level of the group.
+    let ands: Vec<_> = lex_without_pos("let rec f = g and and g = f and in and")
+        .unwrap()
+        .into_iter()
+        .filter_map(|tok| match tok {
+            Token::Normal(NormalToken::And) => Some(true),
+            Token::Normal(NormalToken::Identifier("and")) => Some(false),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(ands, vec![false, true, false, false]);
+    assert_matches!(
+        parse("let rec f = let x = 1 in x and g = [y | y in [f]] in g"),
+        Ok(..)
+    );
+    assert_matches!(parse("let rec f = 1 in f and g = 2"), Err(..));
+
+    assert_eq!(
+        lex_without_pos("match {x => x.match}").unwrap()[..2],
+        [
+            Token::Normal(NormalToken::Match),
+            Token::Normal(NormalToken::LBrace),
+        ]
+    );
+    assert_eq!(
+        parse_without_pos("{match = 1}.match"),
+        mk_term::op1(
+            UnaryOp::StaticAccess(Ident::from("match")),
+            RecRecord(
+                vec![(Ident::from("match"), Num(1.).into())]
+                    .into_iter()
+                    .collect()
+            ),
+        )
+    );
+}
+
+#[test]
+fn desugared_positions() {
+    let t = parse("{a = 1}.b ? [ x | x in [1, 2], x > 1 ]").unwrap();
+    assert!(t.all_pos_defined());
+
+    // The `if` generated by the desugaring of `?` inherits the position of the whole expression.
+    let span = t.pos.unwrap();
+    match t.term.as_ref() {
+        Let(_, _, body) => assert_eq!(body.pos, TermPos::Inherited(span)),
+        t => panic!("expected a let binding, got {:?}", t),
+    }
+}
+
+#[test]
+fn annotation_kinds() {
+    let t = parse("1 : Num | Str | doc \"x\"").unwrap();
+    match t.term.as_ref() {
+        MetaValue(meta) => {
+            let kinds: Vec<(AnnotKind, String)> = meta
+                .annotations()
+                .into_iter()
+                .map(|ctr| (ctr.kind, ctr.label.types.to_string()))
+                .collect();
+            assert_eq!(
+                kinds,
+                vec![
+                    (AnnotKind::Type, String::from("Num")),
+                    (AnnotKind::Contract, String::from("Str"))
+                ]
+            );
+        }
+        t => panic!("expected a metavalue, got {:?}", t),
+    }
+
+    // An inner type annotation demoted to a contract by flattening keeps its kind.
+    let flattened = match parse("(1 : Num) : Str").unwrap().term.as_ref() {
+        MetaValue(outer) => match outer.value.as_ref().map(|t| t.term.as_ref()) {
+            Some(MetaValue(inner)) => crate::term::MetaValue::flatten(outer.clone(), inner.clone()),
+            t => panic!("expected a metavalue, got {:?}", t),
+        },
+        t => panic!("expected a metavalue, got {:?}", t),
+    };
+    assert_eq!(flattened.types.map(|ctr| ctr.kind), Some(AnnotKind::Type));
+    assert_eq!(flattened.contracts[0].kind, AnnotKind::Type);
+}
+
+#[test]
+fn parsing_api() {
+    use super::{parse_expr, parse_extended, ExtendedTerm};
+
+    let mut t = parse_expr("1 + 1").unwrap();
+    t.clean_pos();
+    assert_eq!(t, mk_term::op2(BinaryOp::Plus(), Num(1.0), Num(1.0)));
+
+    // All the syntax errors are reported.
+    assert_matches!(parse_expr("{a = , b = }"), Err(errors) if errors.len() == 2);
+
+    assert_matches!(
+        parse_extended("let x = 1"),
+        Ok(ExtendedTerm::ToplevelLet(id, _)) if id == Ident::from("x")
+    );
+    assert_matches!(parse_extended("let x = 1 in"), Err(_));
+}
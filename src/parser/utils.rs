@@ -1,10 +1,11 @@
 use crate::identifier::Ident;
 /// A few helpers to generate position spans and labels easily during parsing
 use crate::label::Label;
-use crate::mk_app;
 use crate::position::{RawSpan, TermPos};
-use crate::term::{make as mk_term, BinaryOp, RichTerm, StrChunk, Term};
+use crate::term::{make as mk_term, BinaryOp, MetaValue, RichTerm, StrChunk, Term, UnaryOp};
+use crate::transformations::fresh_var;
 use crate::types::Types;
+use crate::{mk_app, mk_fun};
 use codespan::FileId;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
@@ -17,11 +18,31 @@ pub enum StringKind {
     Multiline,
 }
 
-/// Distinguish between a normal case `id => exp` and a default case `_ => exp`.
+/// Distinguish between a normal case `id => exp` and a default case `_ => exp`. The default case
+/// may bind the unmatched value, as in `_ as other => exp`.
 #[derive(Clone, Debug)]
 pub enum SwitchCase {
     Normal(Ident, RichTerm),
-    Default(RichTerm),
+    Default(Option<Ident>, RichTerm),
+}
+
+/// An entry of a record literal: either a field definition or a spread `..base`.
+#[derive(Clone, Debug)]
+pub enum RecordEntry {
+    Field(FieldPathElem, RichTerm),
+    Spread(RichTerm),
+    /// An entry which could not be parsed, produced by the error-recovering parser. It is ignored
+    /// when building the record.
+    Error,
+}
+
+/// A clause of a list comprehension `[ elt | x in list, guard ]`.
+#[derive(Clone, Debug)]
+pub enum CompClause {
+    /// A generator `x in list`, binding `x` to each element of `list` in turn.
+    Gen(Ident, RichTerm),
+    /// A boolean guard, filtering out the elements for which it evaluates to `false`.
+    Guard(RichTerm),
 }
 
 /// Left hand side of a record field declaration.
@@ -73,6 +94,55 @@ pub fn elaborate_field_path(
     (fst, content)
 }
 
+/// Attach the content of `##` documentation comments to the value of a field or of a let binding.
+/// An explicit `doc` annotation takes precedence over documentation comments.
+pub fn attach_doc(doc: Option<String>, t: RichTerm) -> RichTerm {
+    let doc = match doc {
+        Some(doc) => doc,
+        None => return t,
+    };
+
+    match *t.term {
+        Term::MetaValue(mut meta) => {
+            meta.doc.get_or_insert(doc);
+            RichTerm::new(Term::MetaValue(meta), t.pos)
+        }
+        term => {
+            let pos = t.pos;
+            let mut meta = MetaValue::from(RichTerm::new(term, pos));
+            meta.doc = Some(doc);
+            RichTerm::new(Term::MetaValue(meta), pos)
+        }
+    }
+}
+
+/// Combine two definitions of the same field.
+///
+/// Definitions coming from field paths sharing a common prefix, as in `{a.b = 1, a.c = 2}`, are
+/// plain records which are combined directly at parsing time, giving `{a = {b = 1, c = 2}}`. This
+/// keeps the result typeable, as the merge operator is not. In all the other cases, the definitions
+/// are combined using a merge.
+fn merge_definitions(t1: RichTerm, t2: RichTerm) -> RichTerm {
+    match (*t1.term, *t2.term) {
+        (Term::Record(mut map1), Term::Record(map2)) => {
+            for (id, t) in map2 {
+                let t = match map1.remove(&id) {
+                    Some(prev) => merge_definitions(prev, t),
+                    None => t,
+                };
+                map1.insert(id, t);
+            }
+
+            RichTerm::new(Term::Record(map1), t1.pos)
+        }
+        (term1, term2) => mk_term::op2(
+            BinaryOp::Merge(),
+            RichTerm::new(term1, t1.pos),
+            RichTerm::new(term2, t2.pos),
+        ),
+    }
+}
+
 /// Build a record from a list of field definitions. If a field is defined several times, the
 /// different definitions are merged.
 pub fn build_record<I>(fields: I) -> Term
@@ -88,7 +158,7 @@ where
                 Entry::Occupied(mut occpd) => {
                     // temporary putting null in the entry to take the previous value.
                     let prev = occpd.insert(Term::Null.into());
-                    occpd.insert(mk_term::op2(BinaryOp::Merge(), prev, t));
+                    occpd.insert(merge_definitions(prev, t));
                 }
                 Entry::Vacant(vac) => {
                     vac.insert(t);
@@ -102,10 +172,248 @@ where
         .into_iter()
         .fold(Term::RecRecord(static_map), |rec, field| {
             let (id_t, t) = field;
-            Term::App(mk_term::op2(BinaryOp::DynExtend(), id_t, rec), t)
+            Term::App(mk_term::op2(BinaryOp::DynDefine(), id_t, rec), t)
         })
 }
 
+/// Build a record literal which may contain spreads `..base`.
+///
+/// The fields of the spread records are added to the record, but explicit field definitions
+/// always take precedence, wherever the spreads appear. If several spread records define the same
+/// field, the last one wins. Explicit fields can refer to each other recursively, but not to the
+/// fields coming from spreads.
+pub fn build_record_with_spreads<I>(entries: I) -> Term
+where
+    I: IntoIterator<Item = RecordEntry>,
+{
+    let mut fields = Vec::new();
+    let mut spreads = Vec::new();
+
+    for entry in entries {
+        match entry {
+            RecordEntry::Field(path, t) => fields.push((path, t)),
+            RecordEntry::Spread(t) => spreads.push(t),
+            RecordEntry::Error => (),
+        }
+    }
+
+    let record = build_record(fields);
+    let mut spreads = spreads.into_iter();
+
+    match spreads.next() {
+        Some(first) => {
+            let base = spreads.fold(first, |acc, spread| {
+                mk_term::op2(BinaryOp::RecordOverride(), acc, spread)
+            });
+            Term::Op2(BinaryOp::RecordOverride(), base, record.into())
+        }
+        None => record,
+    }
+}
+
+/// Desugar the access with a fallback value `record.field ? default`, which evaluates to `default`
+/// if `record` does not have the field `field`, to
+///
+/// ```text
+/// let %r = record in if %hasField% "field" %r then %r.field else default
+/// ```
+pub fn mk_access_or(record: RichTerm, field: FieldPathElem, default: RichTerm) -> RichTerm {
+    let pos = record.pos;
+    let record_var = fresh_var();
+    let record_ref = mk_term::var(record_var.clone());
+    let mut bindings = vec![(record_var, record)];
+
+    let (name, access) = match field {
+        FieldPathElem::Ident(id) => (
+            mk_term::string(id.to_string()),
+            mk_term::op1(UnaryOp::StaticAccess(id), record_ref.clone()),
+        ),
+        FieldPathElem::Expr(exp) => {
+            let name_var = fresh_var();
+            let name_ref = mk_term::var(name_var.clone());
+            bindings.push((name_var, exp));
+
+            let access = mk_term::op2(BinaryOp::DynAccess(), name_ref.clone(), record_ref.clone());
+            (name_ref, access)
+        }
+    };
+
+    let body = mk_term::if_then_else(
+        mk_term::op2(BinaryOp::HasField(), name, record_ref),
+        access,
+        default,
+    );
+
+    bindings
+        .into_iter()
+        .rev()
+        .fold(body, |body, (id, t)| mk_term::let_in(id, t, body))
+        .with_pos(pos)
+}
+
+/// Build an import annotated with contracts, as in `import "values.yaml" | #Schema`. The
+/// imported value is checked as a whole when the import is evaluated, rather than lazily when each
+/// of its parts is used, and the blame messages mention the imported file:
+///
+/// ```text
+/// let %x = (import "values.yaml" | #Schema) in %deepSeq% %x %x
+/// ```
+pub fn mk_checked_import(import: RichTerm, mut meta: MetaValue) -> RichTerm {
+    let pos = import.pos;
+
+    if let Term::Import(ref path) = *import.term {
+        let tag = format!("imported from {}", path.to_string_lossy());
+        for contract in meta.contracts.iter_mut() {
+            contract.label.tag = tag.clone();
+        }
+    }
+
+    meta.value = Some(import);
+    let var = fresh_var();
+    let checked = RichTerm::new(Term::MetaValue(meta), pos);
+
+    mk_term::let_in(
+        var.clone(),
+        checked,
+        mk_app!(
+            mk_term::op1(UnaryOp::DeepSeq(), mk_term::var(var.clone())),
+            mk_term::var(var)
+        ),
+    )
+    .with_pos(pos)
+}
+
+/// Build a switch from its cases. If the default case binds the unmatched value, as in
+///
+/// ```text
+/// switch { foo => 1, _ as other => f other } exp
+/// ```
+///
+/// the argument is first bound to a fresh variable, and the switch is desugared to
+///
+/// ```text
+/// let %x = exp in switch { foo => 1, _ => let other = %x in f other } %x
+/// ```
+pub fn mk_switch(cases: Vec<SwitchCase>, exp: RichTerm) -> RichTerm {
+    let mut acc = HashMap::with_capacity(cases.len());
+    let mut default = None;
+
+    for case in cases {
+        match case {
+            SwitchCase::Normal(id, t) => {
+                acc.insert(id, t);
+            }
+            // If there are multiple default cases, the last one silently
+            // erases the others. We should have a dedicated error for that
+            SwitchCase::Default(binding, t) => {
+                default.replace((binding, t));
+            }
+        };
+    }
+
+    match default {
+        Some((Some(id), t)) => {
+            let var = fresh_var();
+            let default = mk_term::let_in(id, mk_term::var(var.clone()), t);
+            let switch = Term::Switch(mk_term::var(var.clone()), acc, Some(default));
+            mk_term::let_in(var, exp, switch)
+        }
+        default => RichTerm::from(Term::Switch(exp, acc, default.map(|(_, t)| t))),
+    }
+}
+
+/// Access a function of the `lists` module of the standard library.
+fn lists_fn(name: &str) -> RichTerm {
+    mk_term::op1(
+        UnaryOp::StaticAccess(Ident::from(name)),
+        mk_term::var("lists"),
+    )
+}
+
+/// Desugar a list comprehension to calls to `map`, `filter` and `flatten`. The guards following a
+/// generator filter the generated list, and all but the last generator are flattened:
+///
+/// ```text
+/// [ f x y | x in l1, p x, y in l2 ]
+/// ```
+///
+/// becomes
+///
+/// ```text
+/// lists.flatten (%map% (lists.filter (fun x => p x) l1) (fun x => %map% l2 (fun y => f x y)))
+/// ```
+///
+/// Guards appearing before the first generator are desugared to an `if` returning the empty
+/// list.
+pub fn mk_comprehension(elt: RichTerm, clauses: Vec<CompClause>) -> RichTerm {
+    desugar_clauses(elt, &mut clauses.into_iter().peekable())
+}
+
+fn desugar_clauses<I>(elt: RichTerm, clauses: &mut std::iter::Peekable<I>) -> RichTerm
+where
+    I: Iterator<Item = CompClause>,
+{
+    match clauses.next() {
+        None => RichTerm::from(Term::List(vec![elt])),
+        Some(CompClause::Guard(cond)) => {
+            mk_term::if_then_else(cond, desugar_clauses(elt, clauses), Term::List(Vec::new()))
+        }
+        Some(CompClause::Gen(id, list)) => {
+            let mut guards = Vec::new();
+            while let Some(CompClause::Guard(_)) = clauses.peek() {
+                if let Some(CompClause::Guard(cond)) = clauses.next() {
+                    guards.push(cond);
+                }
+            }
+
+            let list = match guards
+                .into_iter()
+                .reduce(|acc, cond| mk_app!(Term::Op1(UnaryOp::BoolAnd(), acc), cond))
+            {
+                Some(cond) => mk_app!(lists_fn("filter"), mk_fun!(id.clone(), cond), list),
+                None => list,
+            };
+
+            if clauses.peek().is_none() {
+                mk_app!(mk_term::op1(UnaryOp::ListMap(), list), mk_fun!(id, elt))
+            } else {
+                let inner = desugar_clauses(elt, clauses);
+                mk_app!(
+                    lists_fn("flatten"),
+                    mk_app!(mk_term::op1(UnaryOp::ListMap(), list), mk_fun!(id, inner))
+                )
+            }
+        }
+    }
+}
+
+/// Build the recursive record holding the bindings of a `let rec` group. Recursive records
+/// already implement mutual recursion, both for evaluation and typechecking, and the bindings of
+/// the group are then projected out of this record.
+pub fn build_rec_group<I>(bindings: I) -> Term
+where
+    I: IntoIterator<Item = (Ident, RichTerm)>,
+{
+    Term::RecRecord(bindings.into_iter().collect())
+}
+
+/// Desugar `let rec id1 = t1 and id2 = t2 in body` to `let %r = {id1 = t1, id2 = t2} in let id1
+/// = %r.id1 in let id2 = %r.id2 in body`, where `{id1 = t1, id2 = t2}` is a recursive record.
+pub fn mk_let_rec(bindings: Vec<(Ident, RichTerm)>, body: RichTerm) -> RichTerm {
+    let group = fresh_var();
+    let ids: Vec<Ident> = bindings.iter().map(|(id, _)| id.clone()).collect();
+
+    let body = ids.into_iter().rev().fold(body, |body, id| {
+        let access = mk_term::op1(
+            UnaryOp::StaticAccess(id.clone()),
+            mk_term::var(group.clone()),
+        );
+        mk_term::let_in(id, access, body)
+    });
+
+    mk_term::let_in(group, build_rec_group(bindings), body)
+}
+
 /// Make a span from parser byte offsets.
 pub fn mk_span(src_id: FileId, l: usize, r: usize) -> RawSpan {
     RawSpan {
@@ -129,6 +437,8 @@ pub fn mk_label(types: Types, src_id: FileId, l: usize, r: usize) -> Label {
         arg_pos: TermPos::None,
         polarity: true,
         path: Vec::new(),
+        diff: Vec::new(),
+        sensitive: false,
     }
 }
 
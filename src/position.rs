@@ -16,7 +16,7 @@ pub struct RawPos {
 /// A position span identified by a starting byte offset and an ending byte offset in a file.
 ///
 /// `end` is the offset of the last character plus one.
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct RawSpan {
     pub src_id: FileId,
     pub start: ByteIndex,
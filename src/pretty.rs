@@ -0,0 +1,993 @@
+//! Pretty-printing of terms as Nickel source.
+//!
+//! [`pretty`](fn.pretty.html) writes a term as Nickel source which parses back to an equivalent
+//! term. The layout is computed from a document in the style of Wadler's "A prettier printer": a
+//! group is written on a single line when it fits in the requested width, and is otherwise broken
+//! at its break points. Broken records and lists are written one element per line, with a
+//! trailing comma, and the bodies of `let`, `fun`, `if` and of definitions are indented.
+//!
+//! Parentheses are only inserted where the precedence of the operators requires them. The
+//! syntactic sugar expanded by the parser, such as `if`, `&&`, `!=`, dynamic fields, spreads or
+//! string interpolation, is written back in its sugared form, and the fields of records are
+//! sorted. Fresh variables, introduced by the parser or by program transformations, don't have a
+//! valid name and are renamed from `%N` to `_vN`.
+//!
+//! Terms which only exist at run-time, such as labels, symbols, resolved imports or operators
+//! which don't have a syntax, are written as by
+//! [`shallow_repr`](../term/enum.Term.html#method.shallow_repr), e.g. `<label>`, which doesn't
+//! parse.
+use crate::identifier::Ident;
+use crate::serialize::nickel::{field_name, quote};
+use crate::term::{BinaryOp, MergePriority, MetaValue, NAryOp, RichTerm, StrChunk, Term, UnaryOp};
+use crate::types::{AbsType, Types};
+use std::collections::HashMap;
+use unicode_width::UnicodeWidthStr;
+
+/// The default maximum width of the lines.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// The number of spaces by which nested blocks are indented.
+const INDENT: usize = 2;
+
+/// Write a term as Nickel source, whose lines are at most `width` columns wide when possible.
+pub fn pretty(rt: &RichTerm, width: usize) -> String {
+    render(&term(rt, TERM), width)
+}
+
+/// Write a type as Nickel source, whose lines are at most `width` columns wide when possible.
+pub fn pretty_types(ty: &Types, width: usize) -> String {
+    render(&types(ty, TY_FORALL), width)
+}
+
+/// A document: a text with potential line breaks.
+#[derive(Clone, Debug)]
+enum Doc {
+    Text(String),
+    /// A line break, or the given text when the enclosing group is written on a single line.
+    Line(&'static str),
+    /// The first text when the enclosing group is broken, and the second one otherwise.
+    Alt(&'static str, &'static str),
+    /// Indent the line breaks of a document by an additional number of spaces.
+    Nest(usize, Box<Doc>),
+    /// A document written on a single line if it fits, or broken otherwise.
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+/// A break point, written as a space when not broken.
+fn line() -> Doc {
+    Doc::Line(" ")
+}
+
+fn space() -> Doc {
+    text(" ")
+}
+
+/// A break point, written as nothing when not broken.
+fn softline() -> Doc {
+    Doc::Line("")
+}
+
+fn nest(doc: Doc) -> Doc {
+    Doc::Nest(INDENT, Box::new(doc))
+}
+
+fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+fn cat(docs: Vec<Doc>) -> Doc {
+    Doc::Concat(docs)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+fn render(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    let mut stack = vec![(0, Mode::Break, doc)];
+
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(s) => {
+                out.push_str(s);
+                column += s.width();
+            }
+            Doc::Line(flat) if mode == Mode::Flat => {
+                out.push_str(flat);
+                column += flat.width();
+            }
+            Doc::Line(_) => {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                column = indent;
+            }
+            Doc::Alt(broken, flat) => {
+                let s = if mode == Mode::Flat { flat } else { broken };
+                out.push_str(s);
+                column += s.width();
+            }
+            Doc::Nest(i, doc) => stack.push((indent + i, mode, doc)),
+            Doc::Group(doc) => {
+                let mode = if mode == Mode::Flat || fits(width.saturating_sub(column), doc, &stack)
+                {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                stack.push((indent, mode, doc));
+            }
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+        }
+    }
+
+    out
+}
+
+/// Render a document on a single line.
+fn flat(doc: Doc) -> String {
+    render(&group(doc), usize::MAX)
+}
+
+/// Return `true` if `doc` written on a single line, followed by the rest of the document up to
+/// its next line break, fits in `width` columns.
+fn fits(mut width: usize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+
+    loop {
+        let (mode, doc) = match stack.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some((_, mode, doc)) => (*mode, *doc),
+                None => return true,
+            },
+        };
+
+        let s = match doc {
+            Doc::Text(s) => s.as_str(),
+            Doc::Line(flat) if mode == Mode::Flat => flat,
+            Doc::Line(_) => return true,
+            Doc::Alt(broken, flat) => {
+                if mode == Mode::Flat {
+                    flat
+                } else {
+                    broken
+                }
+            }
+            Doc::Nest(_, doc) | Doc::Group(doc) => {
+                stack.push((mode, doc));
+                continue;
+            }
+            Doc::Concat(docs) => {
+                stack.extend(docs.iter().rev().map(|doc| (mode, doc)));
+                continue;
+            }
+        };
+
+        match width.checked_sub(s.width()) {
+            Some(remaining) => width = remaining,
+            None => return false,
+        }
+    }
+}
+
+/// Separate documents by a comma and a break point, with a trailing comma when broken, and
+/// enclose them in delimiters.
+fn block(open: &'static str, items: Vec<Doc>, close: &'static str) -> Doc {
+    if items.is_empty() {
+        return text(format!("{}{}", open, close));
+    }
+
+    let mut body = Vec::new();
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            body.push(text(","));
+            body.push(line());
+        }
+        body.push(item);
+    }
+    body.push(Doc::Alt(",", ""));
+
+    group(cat(vec![
+        text(open),
+        nest(cat(vec![softline(), cat(body)])),
+        softline(),
+        text(close),
+    ]))
+}
+
+// The precedence levels of the grammar, from the loosest to the tightest. A term of a given level
+// can appear where a term of a lower level is expected without parentheses.
+/// `let`, `fun`, `if`, `switch` and annotated terms.
+const TERM: u8 = 0;
+/// Imports.
+const INFIX: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const EQ: u8 = 4;
+const CMP: u8 = 5;
+const MERGE: u8 = 6;
+const NOT: u8 = 7;
+const ADD: u8 = 8;
+const MUL: u8 = 9;
+const CONCAT: u8 = 10;
+const NEG: u8 = 11;
+/// Applications and primitive operators.
+const APP: u8 = 12;
+/// Record accesses.
+const OPERAND: u8 = 13;
+const ATOM: u8 = 14;
+
+/// Write a term where a term of the given level is expected.
+fn term(rt: &RichTerm, level: u8) -> Doc {
+    let (prec, doc) = term_prec(rt);
+
+    if prec < level {
+        parens(doc)
+    } else {
+        doc
+    }
+}
+
+fn parens(doc: Doc) -> Doc {
+    group(cat(vec![text("("), Doc::Nest(1, Box::new(doc)), text(")")]))
+}
+
+/// Write a term, and return its precedence level.
+fn term_prec(rt: &RichTerm) -> (u8, Doc) {
+    if let Some(entries) = record_entries(rt) {
+        return (ATOM, record(entries));
+    }
+
+    match rt.as_ref() {
+        Term::Null => (ATOM, text("null")),
+        Term::Bool(b) => (ATOM, text(b.to_string())),
+        Term::Num(n) if n.is_sign_negative() && *n != 0.0 => (NEG, text(format!("-{}", -n))),
+        Term::Num(n) => (ATOM, text(n.to_string())),
+        Term::Str(s) => (ATOM, text(quote(s))),
+        Term::StrChunks(chunks) => (ATOM, string(chunks)),
+        Term::Var(id) => (ATOM, text(ident(id))),
+        Term::Enum(id) => (ATOM, text(format!("`{}", ident(id)))),
+        Term::List(elts) => (
+            ATOM,
+            block("[", elts.iter().map(|elt| term(elt, TERM)).collect(), "]"),
+        ),
+        Term::Fun(..) => {
+            let mut params = Vec::new();
+            let mut body = rt;
+            while let Term::Fun(id, t) = body.as_ref() {
+                params.push(ident(id));
+                body = t;
+            }
+
+            (
+                TERM,
+                group(cat(vec![
+                    text(format!("fun {} =>", params.join(" "))),
+                    nest(cat(vec![line(), term(body, TERM)])),
+                ])),
+            )
+        }
+        Term::Let(id, t1, t2) => (
+            TERM,
+            group(cat(vec![
+                definition(text(format!("let {}", ident(id))), t1, " in"),
+                line(),
+                term(t2, TERM),
+            ])),
+        ),
+        Term::App(..) => app(rt),
+        Term::Switch(exp, cases, default) => {
+            let mut cases: Vec<_> = cases.iter().collect();
+            cases.sort_by(|(id1, _), (id2, _)| id1.label.cmp(&id2.label));
+            let cases = cases
+                .into_iter()
+                .map(|(id, t)| (ident(id), t))
+                .chain(default.iter().map(|t| (String::from("_"), t)))
+                .map(|(pattern, t)| {
+                    group(cat(vec![
+                        text(format!("{} =>", pattern)),
+                        nest(cat(vec![line(), term(t, TERM)])),
+                    ]))
+                })
+                .collect();
+
+            (
+                TERM,
+                cat(vec![
+                    text("switch "),
+                    block("{", cases, "}"),
+                    text(" "),
+                    term(exp, TERM),
+                ]),
+            )
+        }
+        Term::Op1(op, t) => op1(op, t),
+        Term::Op2(op, t1, t2) => op2(op, t1, t2),
+        Term::OpN(op, args) => (
+            APP,
+            prefix(nary_name(op), args.iter().map(|t| term(t, OPERAND))),
+        ),
+        Term::Promise(ty, _, t) => (
+            TERM,
+            group(cat(vec![
+                term(t, INFIX),
+                nest(cat(vec![line(), text(": "), types(ty, TY_FORALL)])),
+            ])),
+        ),
+        Term::MetaValue(meta) => match &meta.value {
+            Some(t) => (
+                TERM,
+                group(cat(vec![term(t, INFIX), nest(annotations(meta, line))])),
+            ),
+            None => fallback(rt),
+        },
+        Term::Import(path) => (
+            INFIX,
+            text(format!("import {}", quote(&path.to_string_lossy()))),
+        ),
+        Term::ImportStr(path) => (
+            INFIX,
+            text(format!("import_str {}", quote(&path.to_string_lossy()))),
+        ),
+        _ => fallback(rt),
+    }
+}
+
+/// Write a term which doesn't have a syntax.
+fn fallback(rt: &RichTerm) -> (u8, Doc) {
+    (ATOM, text(rt.as_ref().shallow_repr()))
+}
+
+/// The name of an identifier in the source, renaming fresh variables to valid identifiers.
+fn ident(id: &Ident) -> String {
+    match id.label.strip_prefix('%') {
+        Some(n) => format!("_v{}", n),
+        None => id.label.clone(),
+    }
+}
+
+/// Write the definition `head = value` of a `let` binding or of a field, followed by `suffix`.
+/// The annotations of the value, if any, are written after the head.
+fn definition(head: Doc, value: &RichTerm, suffix: &'static str) -> Doc {
+    let (head, value) = match value.as_ref() {
+        Term::MetaValue(meta) if meta.value.is_some() => (
+            cat(vec![head, annotations(meta, space)]),
+            meta.value.as_ref().unwrap(),
+        ),
+        Term::MetaValue(meta) => return cat(vec![head, annotations(meta, space), text(suffix)]),
+        _ => (head, value),
+    };
+
+    // Records and lists are kept on the line of the head, and only broken inside their delimiters.
+    let delimited = matches!(value.as_ref(), Term::List(_)) || record_entries(value).is_some();
+    if delimited {
+        group(cat(vec![
+            head,
+            text(" = "),
+            term(value, TERM),
+            text(suffix),
+        ]))
+    } else {
+        let suffix = match suffix.strip_prefix(' ') {
+            Some(suffix) => cat(vec![line(), text(suffix)]),
+            None => text(suffix),
+        };
+        group(cat(vec![
+            head,
+            text(" ="),
+            nest(cat(vec![line(), term(value, TERM)])),
+            suffix,
+        ]))
+    }
+}
+
+/// Write the type, contract, priority and documentation annotations of a metavalue, each one
+/// preceded by `sep`.
+fn annotations(meta: &MetaValue, sep: fn() -> Doc) -> Doc {
+    let mut docs = Vec::new();
+
+    if let Some(ctr) = &meta.types {
+        docs.push(cat(vec![sep(), text(": "), types(&ctr.types, TY_FORALL)]));
+    }
+    for ctr in meta.contracts.iter() {
+        docs.push(cat(vec![sep(), text("| "), types(&ctr.types, TY_FORALL)]));
+    }
+    if meta.priority == MergePriority::Default {
+        docs.push(cat(vec![sep(), text("| default")]));
+    }
+    if meta.sensitive {
+        docs.push(cat(vec![sep(), text("| sensitive")]));
+    }
+    if let Some(doc) = &meta.doc {
+        docs.push(cat(vec![sep(), text(format!("| doc {}", quote(doc)))]));
+    }
+
+    cat(docs)
+}
+
+/// Write a string with interpolated expressions.
+fn string(chunks: &[StrChunk<RichTerm>]) -> Doc {
+    let mut docs = vec![text("\"")];
+    // The chunks are stored in reverse order.
+    let mut chunks = chunks.iter().rev().peekable();
+
+    while let Some(chunk) = chunks.next() {
+        match chunk {
+            StrChunk::Literal(s) => {
+                let quoted = quote(s);
+                let mut escaped = String::from(&quoted[1..quoted.len() - 1]);
+                // A hash at the end of a literal would start an interpolation with the next one.
+                if escaped.ends_with('#') && matches!(chunks.peek(), Some(StrChunk::Expr(..))) {
+                    escaped.pop();
+                    escaped.push_str("\\#");
+                }
+                docs.push(text(escaped));
+            }
+            StrChunk::Expr(t, _) => {
+                let formatted = match t.as_ref() {
+                    Term::Op2(BinaryOp::StrFormat(), spec, t) => match spec.as_ref() {
+                        Term::Str(spec) => Some((spec, t)),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+
+                // Interpolated expressions are written on a single line. The format specifier
+                // follows a colon, which must not be confused with a type annotation.
+                let interpolated = match formatted {
+                    Some((spec, t)) => format!("#{{{}:{}}}", flat(term(t, INFIX)), spec),
+                    None => format!("#{{{}}}", flat(term(t, TERM))),
+                };
+                docs.push(text(interpolated));
+            }
+        }
+    }
+
+    docs.push(text("\""));
+    cat(docs)
+}
+
+/// The entries of a record literal.
+struct RecordEntries<'a> {
+    spreads: Vec<&'a RichTerm>,
+    fields: &'a HashMap<Ident, RichTerm>,
+    /// The fields whose name is an expression, in their order of definition.
+    dynamic: Vec<(&'a RichTerm, &'a RichTerm)>,
+}
+
+/// Return the entries of a term if it is a record literal, with the dynamic fields and spreads
+/// which are desugared to operators by the parser.
+fn record_entries(rt: &RichTerm) -> Option<RecordEntries<'_>> {
+    match rt.as_ref() {
+        Term::Record(fields) | Term::RecRecord(fields) => Some(RecordEntries {
+            spreads: Vec::new(),
+            fields,
+            dynamic: Vec::new(),
+        }),
+        Term::App(f, value) => match f.as_ref() {
+            Term::Op2(BinaryOp::DynDefine(), key, record) => {
+                let mut entries = record_entries(record)?;
+                entries.dynamic.push((key, value));
+                Some(entries)
+            }
+            _ => None,
+        },
+        Term::Op2(BinaryOp::RecordOverride(), base, record) => {
+            let mut entries = record_entries(record).filter(|e| e.spreads.is_empty())?;
+            let mut base = base;
+            while let Term::Op2(BinaryOp::RecordOverride(), bases, spread) = base.as_ref() {
+                entries.spreads.push(spread);
+                base = bases;
+            }
+            entries.spreads.push(base);
+            entries.spreads.reverse();
+            Some(entries)
+        }
+        _ => None,
+    }
+}
+
+fn record(entries: RecordEntries) -> Doc {
+    let mut fields: Vec<_> = entries.fields.iter().collect();
+    fields.sort_by(|(id1, _), (id2, _)| id1.label.cmp(&id2.label));
+
+    let items = entries
+        .spreads
+        .into_iter()
+        .map(|spread| cat(vec![text(".."), term(spread, TERM)]))
+        .chain(
+            fields
+                .into_iter()
+                .map(|(id, value)| definition(text(field_name(&id.label)), value, "")),
+        )
+        .chain(
+            entries
+                .dynamic
+                .into_iter()
+                .map(|(key, value)| definition(key_string(key), value, "")),
+        )
+        .collect();
+
+    block("{", items, "}")
+}
+
+/// Write the key of a dynamic field or of a dynamic access, which must be a string.
+fn key_string(key: &RichTerm) -> Doc {
+    match key.as_ref() {
+        Term::Str(_) | Term::StrChunks(_) => term(key, ATOM),
+        _ => cat(vec![text("\"#{"), term(key, TERM), text("}\"")]),
+    }
+}
+
+/// Write an application, which may be the desugaring of an `if`, of a lazy boolean operator or of
+/// a dynamic extension.
+fn app(rt: &RichTerm) -> (u8, Doc) {
+    let mut args = Vec::new();
+    let mut head = rt;
+    let (prec, head) = loop {
+        match head.as_ref() {
+            Term::App(f, arg) => match sugared_app(head) {
+                Some(sugared) => break sugared,
+                None => {
+                    args.push(arg);
+                    head = f;
+                }
+            },
+            _ => break term_prec(head),
+        }
+    };
+
+    if args.is_empty() {
+        return (prec, head);
+    }
+
+    let head = if prec < APP { parens(head) } else { head };
+    let args = args
+        .into_iter()
+        .rev()
+        .map(|arg| cat(vec![line(), term(arg, OPERAND)]))
+        .collect();
+    (APP, group(cat(vec![head, nest(cat(args))])))
+}
+
+fn sugared_app(rt: &RichTerm) -> Option<(u8, Doc)> {
+    let (f, arg) = match rt.as_ref() {
+        Term::App(f, arg) => (f, arg),
+        _ => return None,
+    };
+
+    match f.as_ref() {
+        Term::App(ite, t1) => match ite.as_ref() {
+            Term::Op1(UnaryOp::Ite(), cond) => Some((
+                TERM,
+                group(cat(vec![
+                    text("if "),
+                    term(cond, TERM),
+                    text(" then"),
+                    nest(cat(vec![line(), term(t1, TERM)])),
+                    line(),
+                    text("else"),
+                    nest(cat(vec![line(), term(arg, TERM)])),
+                ])),
+            )),
+            _ => None,
+        },
+        Term::Op1(UnaryOp::BoolAnd(), t) => Some((AND, infix(t, AND, "&&", arg, EQ))),
+        Term::Op1(UnaryOp::BoolOr(), t) => Some((OR, infix(t, OR, "||", arg, AND))),
+        Term::Op2(BinaryOp::DynExtend(), key, record) => Some((
+            OPERAND,
+            cat(vec![
+                term(record, OPERAND),
+                text("$["),
+                term(key, TERM),
+                text(" = "),
+                term(arg, TERM),
+                text("]"),
+            ]),
+        )),
+        _ => None,
+    }
+}
+
+/// Write a binary infix operator, whose operands are written at the given levels.
+fn infix(t1: &RichTerm, level1: u8, op: &str, t2: &RichTerm, level2: u8) -> Doc {
+    group(cat(vec![
+        term(t1, level1),
+        nest(cat(vec![
+            line(),
+            text(format!("{} ", op)),
+            term(t2, level2),
+        ])),
+    ]))
+}
+
+/// Write a primitive operator `%name%` applied to arguments.
+fn prefix(name: &str, args: impl Iterator<Item = Doc>) -> Doc {
+    let args = args.map(|arg| cat(vec![line(), arg])).collect();
+    group(cat(vec![text(format!("%{}%", name)), nest(cat(args))]))
+}
+
+fn op1(op: &UnaryOp, t: &RichTerm) -> (u8, Doc) {
+    match op {
+        UnaryOp::BoolNot() => match t.as_ref() {
+            Term::Op2(BinaryOp::Eq(), t1, t2) => (EQ, infix(t1, EQ, "!=", t2, CMP)),
+            _ => (NOT, cat(vec![text("!"), term(t, NOT)])),
+        },
+        UnaryOp::StaticAccess(id) => (
+            OPERAND,
+            cat(vec![
+                term(t, OPERAND),
+                text("."),
+                text(field_name(&id.label)),
+            ]),
+        ),
+        UnaryOp::Embed(id) => (
+            APP,
+            prefix("embed", vec![text(ident(id)), term(t, OPERAND)].into_iter()),
+        ),
+        _ => match unary_name(op) {
+            Some(name) => (APP, prefix(name, std::iter::once(term(t, OPERAND)))),
+            None => (ATOM, text(Term::Op1(op.clone(), t.clone()).shallow_repr())),
+        },
+    }
+}
+
+fn op2(op: &BinaryOp, t1: &RichTerm, t2: &RichTerm) -> (u8, Doc) {
+    let infix_op = match op {
+        BinaryOp::Sub() if *t1.as_ref() == Term::Num(0.0) => {
+            return (NEG, cat(vec![text("-"), term(t2, NEG)]))
+        }
+        BinaryOp::Plus() => Some(("+", ADD)),
+        BinaryOp::Sub() => Some(("-", ADD)),
+        BinaryOp::Mult() => Some(("*", MUL)),
+        BinaryOp::Div() => Some(("/", MUL)),
+        BinaryOp::Modulo() => Some(("%", MUL)),
+        BinaryOp::PlusStr() => Some(("++", CONCAT)),
+        BinaryOp::ListConcat() => Some(("@", CONCAT)),
+        BinaryOp::Eq() => Some(("==", EQ)),
+        BinaryOp::LessThan() => Some(("<", CMP)),
+        BinaryOp::LessOrEq() => Some(("<=", CMP)),
+        BinaryOp::GreaterThan() => Some((">", CMP)),
+        BinaryOp::GreaterOrEq() => Some((">=", CMP)),
+        BinaryOp::Merge() => Some(("&", MERGE)),
+        _ => None,
+    };
+    if let Some((name, level)) = infix_op {
+        return (level, infix(t1, level, name, t2, level + 1));
+    }
+
+    match op {
+        BinaryOp::DynAccess() => (
+            OPERAND,
+            cat(vec![term(t2, OPERAND), text("."), key_string(t1)]),
+        ),
+        BinaryOp::DynRemove() => (
+            OPERAND,
+            cat(vec![term(t2, OPERAND), text(" -$ "), term(t1, ATOM)]),
+        ),
+        // A record override whose right operand is not a record literal, as others are written as
+        // spreads.
+        BinaryOp::RecordOverride() => (
+            ATOM,
+            block(
+                "{",
+                vec![
+                    cat(vec![text(".."), term(t1, TERM)]),
+                    cat(vec![text(".."), term(t2, TERM)]),
+                ],
+                "}",
+            ),
+        ),
+        _ => match binary_name(op) {
+            Some(name) => (
+                APP,
+                prefix(name, vec![term(t1, OPERAND), term(t2, ATOM)].into_iter()),
+            ),
+            None => (
+                ATOM,
+                text(Term::Op2(op.clone(), t1.clone(), t2.clone()).shallow_repr()),
+            ),
+        },
+    }
+}
+
+/// The name of a unary primitive operator, or `None` if it doesn't have a syntax.
+fn unary_name(op: &UnaryOp) -> Option<&'static str> {
+    let name = match op {
+        UnaryOp::IsNum() => "isNum",
+        UnaryOp::IsBool() => "isBool",
+        UnaryOp::IsStr() => "isStr",
+        UnaryOp::IsFun() => "isFun",
+        UnaryOp::IsList() => "isList",
+        UnaryOp::IsRecord() => "isRecord",
+        UnaryOp::Blame() => "blame",
+        UnaryOp::ChangePolarity() => "chngPol",
+        UnaryOp::Pol() => "polarity",
+        UnaryOp::GoDom() => "goDom",
+        UnaryOp::GoCodom() => "goCodom",
+        UnaryOp::GoList() => "goList",
+        UnaryOp::Wrap() => "wrap",
+        UnaryOp::ListMap() => "map",
+        UnaryOp::ListGen() => "generate",
+        UnaryOp::ListSum() => "listSum",
+        UnaryOp::ListProduct() => "listProduct",
+        UnaryOp::ListMin() => "listMin",
+        UnaryOp::ListMax() => "listMax",
+        UnaryOp::ListMean() => "listMean",
+        UnaryOp::RecordMap() => "recordMap",
+        UnaryOp::Seq() => "seq",
+        UnaryOp::DeepSeq() => "deepSeq",
+        UnaryOp::ListHead() => "head",
+        UnaryOp::ListTail() => "tail",
+        UnaryOp::ListLength() => "length",
+        UnaryOp::FieldsOf() => "fieldsOf",
+        UnaryOp::ValuesOf() => "valuesOf",
+        UnaryOp::StrTrim() => "strTrim",
+        UnaryOp::StrChars() => "strChars",
+        UnaryOp::CharCode() => "charCode",
+        UnaryOp::CharFromCode() => "charFromCode",
+        UnaryOp::StrUppercase() => "strUppercase",
+        UnaryOp::StrLowercase() => "strLowercase",
+        UnaryOp::StrLength() => "strLength",
+        UnaryOp::StrDedent() => "strDedent",
+        UnaryOp::ToStr() => "toStr",
+        UnaryOp::NumFromStr() => "numFromStr",
+        UnaryOp::EnumFromStr() => "enumFromStr",
+        UnaryOp::Trace() => "trace",
+        UnaryOp::Clock() => "clock",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// The name of a binary primitive operator written in prefix position, or `None` if it doesn't
+/// have such a syntax.
+fn binary_name(op: &BinaryOp) -> Option<&'static str> {
+    let name = match op {
+        BinaryOp::Unwrap() => "unwrap",
+        BinaryOp::GoField() => "goField",
+        BinaryOp::HasField() => "hasField",
+        BinaryOp::ListElemAt() => "elemAt",
+        BinaryOp::Tag() => "tag",
+        BinaryOp::Hash() => "hash",
+        BinaryOp::Serialize() => "serialize",
+        BinaryOp::Deserialize() => "deserialize",
+        BinaryOp::Pow() => "pow",
+        BinaryOp::StrSplit() => "strSplit",
+        BinaryOp::StrContains() => "strContains",
+        BinaryOp::StrMatch() => "strMatch",
+        BinaryOp::StrIsMatch() => "strIsMatch",
+        BinaryOp::StrIndent() => "strIndent",
+        BinaryOp::GetPath() => "getPath",
+        BinaryOp::HasPath() => "hasPath",
+        BinaryOp::DeepRepr() => "deepRepr",
+        BinaryOp::ToStrFixed() => "toStrFixed",
+        BinaryOp::StrFormat() => "strFormat",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+fn nary_name(op: &NAryOp) -> &'static str {
+    match op {
+        NAryOp::StrReplace() => "strReplace",
+        NAryOp::StrReplaceRegex() => "strReplaceRegex",
+        NAryOp::StrSubstr() => "strSubstr",
+        NAryOp::StrSplice() => "strSplice",
+        NAryOp::UpdatePath() => "updatePath",
+        NAryOp::StrPadLeft() => "strPadLeft",
+        NAryOp::StrPadRight() => "strPadRight",
+        NAryOp::StrCenter() => "strCenter",
+        NAryOp::StrTruncate() => "strTruncate",
+        NAryOp::BlameDiff() => "blameDiff",
+    }
+}
+
+// The precedence levels of types.
+const TY_FORALL: u8 = 0;
+const TY_ARROW: u8 = 1;
+const TY_LIST: u8 = 2;
+const TY_ATOM: u8 = 3;
+
+/// Write a type where a type of the given level is expected.
+fn types(ty: &Types, level: u8) -> Doc {
+    let (prec, doc) = match &ty.0 {
+        AbsType::Dyn() => (TY_ATOM, text("Dyn")),
+        AbsType::Num() => (TY_ATOM, text("Num")),
+        AbsType::Bool() => (TY_ATOM, text("Bool")),
+        AbsType::Str() => (TY_ATOM, text("Str")),
+        AbsType::Sym() => (TY_ATOM, text("Sym")),
+        AbsType::Var(id) => (TY_ATOM, text(ident(id))),
+        AbsType::Flat(t) => (TY_ATOM, cat(vec![text("#"), term(t, ATOM)])),
+        AbsType::List(elt) => (TY_LIST, cat(vec![text("List "), types(elt, TY_ATOM)])),
+        AbsType::Arrow(dom, codom) => (
+            TY_ARROW,
+            cat(vec![
+                types(dom, TY_LIST),
+                text(" -> "),
+                types(codom, TY_ARROW),
+            ]),
+        ),
+        AbsType::Forall(..) => {
+            let mut vars = Vec::new();
+            let mut body = ty;
+            while let Types(AbsType::Forall(id, t)) = body {
+                vars.push(ident(id));
+                body = t;
+            }
+
+            (
+                TY_FORALL,
+                cat(vec![
+                    text(format!("forall {}. ", vars.join(" "))),
+                    types(body, TY_ARROW),
+                ]),
+            )
+        }
+        AbsType::Enum(row) => {
+            let (ids, tail) = rows(row);
+            let mut s = ids
+                .into_iter()
+                .map(|(id, _)| ident(id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if let Some(tail) = tail {
+                s.push_str(&format!(" | {}", ident(tail)));
+            }
+            (TY_ATOM, text(format!("<{}>", s)))
+        }
+        AbsType::StaticRecord(row) => {
+            let (fields, tail) = rows(row);
+            let mut docs = Vec::new();
+            for (i, (id, ty)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    docs.push(text(", "));
+                }
+                docs.push(text(format!("{}: ", ident(id))));
+                docs.push(
+                    ty.map(|ty| types(ty, TY_FORALL))
+                        .unwrap_or_else(|| text("Dyn")),
+                );
+            }
+            match (tail, row_is_open(row)) {
+                (Some(tail), _) => docs.push(text(format!(" | {}", ident(tail)))),
+                (None, true) => docs.push(text(" | Dyn")),
+                (None, false) => (),
+            }
+            (TY_ATOM, cat(vec![text("{"), cat(docs), text("}")]))
+        }
+        AbsType::DynRecord(ty) => (
+            TY_ATOM,
+            cat(vec![text("{_: "), types(ty, TY_FORALL), text("}")]),
+        ),
+        AbsType::RowEmpty() | AbsType::RowExtend(..) => (TY_ATOM, text(ty.to_string())),
+    };
+
+    if prec < level {
+        cat(vec![text("("), doc, text(")")])
+    } else {
+        doc
+    }
+}
+
+/// Return the rows of a row type, and the variable of its tail if it is open.
+#[allow(clippy::type_complexity)]
+fn rows(mut row: &Types) -> (Vec<(&Ident, Option<&Types>)>, Option<&Ident>) {
+    let mut result = Vec::new();
+
+    loop {
+        match &row.0 {
+            AbsType::RowExtend(id, ty, tail) => {
+                result.push((id, ty.as_deref()));
+                row = tail;
+            }
+            AbsType::Var(id) => return (result, Some(id)),
+            _ => return (result, None),
+        }
+    }
+}
+
+/// Return `true` if a row type ends with `Dyn`.
+fn row_is_open(mut row: &Types) -> bool {
+    while let AbsType::RowExtend(_, _, tail) = &row.0 {
+        row = tail;
+    }
+    row.0 == AbsType::Dyn()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use crate::program::eval_full_str;
+    use codespan::Files;
+    use serde_json::Value;
+
+    fn parse(s: &str) -> RichTerm {
+        let id = Files::new().add("<test>", String::from(s));
+        grammar::TermParser::new()
+            .parse_strict(id, lexer::Lexer::new(s))
+            .unwrap()
+    }
+
+    fn eval(s: &str) -> Value {
+        serde_json::to_value(eval_full_str(s)).unwrap()
+    }
+
+    /// Check that the pretty-printed source of a program evaluates to the same value, and is
+    /// printed identically, and return it.
+    fn round_trip(s: &str, width: usize) -> String {
+        let printed = pretty(&parse(s), width);
+        assert_eq!(pretty(&parse(&printed), width), printed);
+        assert_eq!(eval(&printed), eval(s));
+        printed
+    }
+
+    #[test]
+    fn round_trips() {
+        let sources = [
+            "let x = 1 in let f = fun a b => a + b * 2 in f x (x - -3) / 2 - (x - 1)",
+            "if 1 < 2 && !(3 == 4) || false then 1 != 2 else [2 + 2 == 4, -(1 + 1) >= 0]",
+            "let base = {a = 1} in {..base, b = base.a, \"c d\" = 3, \"#{\"e\"}\" = 4} & {f | default | sensitive = 5}",
+            "let n = 3.5 in \"n = #{%toStr% n}, #{n:08.2f} # \\#{literal} quote \\\" #\"",
+            "switch { foo => 1, bar => 2, _ => 3 } `foo",
+            "let f : forall a. a -> List a = fun x => [x] in {a | Num = 1, b = (f 1 : List Num)}",
+            "match { `a => 1, _ => 2 } `a",
+            "{a = 1}.b ? 2",
+            "(({a = 1}$[\"b\" = 2]) -$ \"a\").\"b\"",
+            "[x * 2 | x in [1, 2, 3], x > 1]",
+            "let rec even = fun n => if n == 0 then true else odd (n - 1) and odd = fun n => if n == 0 then false else even (n - 1) in even 10",
+            "lists.map (fun x => x ++ \"!\") [\"a\", \"b\"] @ [%strReplace% \"abc\" \"b\" \"d\"]",
+        ];
+
+        for s in sources.iter() {
+            round_trip(s, DEFAULT_WIDTH);
+            round_trip(s, 20);
+        }
+    }
+
+    #[test]
+    fn width() {
+        let rt = parse("{b = [1, 2, 3], a = {c = \"x\"}}");
+        assert_eq!(pretty(&rt, 80), "{a = {c = \"x\"}, b = [1, 2, 3]}");
+        assert_eq!(
+            pretty(&rt, 20),
+            "{\n  a = {c = \"x\"},\n  b = [1, 2, 3],\n}"
+        );
+        assert_eq!(
+            pretty(&rt, 12),
+            "{\n  a = {\n    c = \"x\",\n  },\n  b = [\n    1,\n    2,\n    3,\n  ],\n}"
+        );
+
+        let rt = parse("let f = fun x => x + 1 in f 2");
+        assert_eq!(pretty(&rt, 80), "let f = fun x => x + 1 in f 2");
+        assert_eq!(pretty(&rt, 20), "let f =\n  fun x => x + 1\nin\nf 2");
+    }
+
+    #[test]
+    fn types() {
+        let source =
+            "null : forall a. (a -> a) -> List (List a) -> {x: <a, b | r>, y: {_: #f} | Dyn}";
+        let ty = match parse(source).as_ref() {
+            Term::MetaValue(meta) => meta.types.as_ref().unwrap().types.clone(),
+            _ => panic!("expected an annotated term"),
+        };
+        assert_eq!(
+            pretty_types(&ty, DEFAULT_WIDTH),
+            "forall a. (a -> a) -> List (List a) -> {x: <a, b | r>, y: {_: #f} | Dyn}"
+        );
+        assert_eq!(pretty(&parse(source), DEFAULT_WIDTH), source);
+    }
+}
@@ -0,0 +1,235 @@
+//! Configuration profiles.
+//!
+//! A program can declare named overlays, such as `dev`, `staging` and `prod`, in the `profiles`
+//! field of the record it evaluates to:
+//!
+//! ```ignore
+//! {
+//!   server = {host = "localhost", port = 8080},
+//!   replicas = 1,
+//!
+//!   profiles = {
+//!     staging = {server.host = "staging.example.org"},
+//!     prod = {server = {host = "example.org", port = 443}, replicas = 3},
+//!   },
+//! }
+//! ```
+//!
+//! Selecting profiles, as in `nickel export --profile staging --profile prod`, layers them over
+//! the base configuration (the record without the `profiles` field) in the order of selection,
+//! each layer having priority over the previous ones. Contrary to merging, which fails on
+//! conflicting values, a layer overrides the values of the lower layers: records are combined
+//! field by field, and any other value of a layer, including lists, replaces the value below.
+//!
+//! The [origins](struct.Origin.html) of the values of the result tell which layer each value
+//! comes from, as printed by `nickel export --show-origins`.
+use crate::error::SerializationError;
+use crate::identifier::Ident;
+use crate::serialize::{nickel::field_name, strip_meta};
+use crate::term::{RichTerm, Term};
+use std::collections::BTreeMap;
+
+/// The field declaring the profiles of a program.
+pub const FIELD: &str = "profiles";
+
+/// The name of the layer of the base configuration in the origins.
+pub const BASE: &str = "base";
+
+/// The layer a value of the result comes from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Origin {
+    /// The dot-separated path of the value.
+    pub path: String,
+    /// The name of the profile, or [`BASE`](constant.BASE.html).
+    pub layer: String,
+}
+
+/// Layer the given profiles over the base configuration of a fully evaluated term, in increasing
+/// priority. Return the result, and the origins of its values, sorted by path. If no profile is
+/// given, the term is returned unchanged, with no origin.
+pub fn apply(
+    rt: RichTerm,
+    profiles: &[String],
+) -> Result<(RichTerm, Vec<Origin>), SerializationError> {
+    if profiles.is_empty() {
+        return Ok((rt, Vec::new()));
+    }
+
+    let (mut base, declared) = match strip_meta(&rt).as_ref() {
+        Term::Record(map) => {
+            let mut base = map.clone();
+            let declared = base
+                .remove(&Ident::from(FIELD))
+                .map(|rt| strip_meta(&rt).clone());
+            (RichTerm::new(Term::Record(base), rt.pos), declared)
+        }
+        _ => (rt.clone(), None),
+    };
+    let declared = match declared.as_ref().map(AsRef::as_ref) {
+        Some(Term::Record(map)) => map,
+        _ => {
+            return Err(SerializationError::Other(format!(
+                "the program declares no profiles in a `{}` record",
+                FIELD
+            )))
+        }
+    };
+
+    let mut origins = BTreeMap::new();
+    set_origins(&base, BASE, &mut Vec::new(), &mut origins);
+
+    for name in profiles {
+        let layer = declared.get(&Ident::from(name.as_str())).ok_or_else(|| {
+            let mut available: Vec<&str> = declared.keys().map(|id| id.label.as_str()).collect();
+            available.sort_unstable();
+            SerializationError::Other(format!(
+                "unknown profile `{}` (available: {})",
+                name,
+                available.join(", ")
+            ))
+        })?;
+        base = overlay(&base, layer, name, &mut Vec::new(), &mut origins);
+    }
+
+    let origins = origins
+        .into_iter()
+        .map(|(path, layer)| Origin {
+            path: path
+                .iter()
+                .map(|id| field_name(id))
+                .collect::<Vec<_>>()
+                .join("."),
+            layer,
+        })
+        .collect();
+    Ok((base, origins))
+}
+
+/// Layer a value over another one, recording the origins of the values taken from the layer.
+fn overlay(
+    base: &RichTerm,
+    layer: &RichTerm,
+    name: &str,
+    path: &mut Vec<String>,
+    origins: &mut BTreeMap<Vec<String>, String>,
+) -> RichTerm {
+    match (strip_meta(base).as_ref(), strip_meta(layer).as_ref()) {
+        (Term::Record(base_map), Term::Record(layer_map)) => {
+            let mut map = base_map.clone();
+
+            for (id, t) in layer_map {
+                path.push(id.label.clone());
+                let value = match base_map.get(id) {
+                    Some(base_t) => overlay(base_t, t, name, path, origins),
+                    None => {
+                        set_origins(t, name, path, origins);
+                        t.clone()
+                    }
+                };
+                map.insert(id.clone(), value);
+                path.pop();
+            }
+
+            RichTerm::new(Term::Record(map), base.pos)
+        }
+        _ => {
+            origins.retain(|p, _| !p.starts_with(path));
+            set_origins(layer, name, path, origins);
+            layer.clone()
+        }
+    }
+}
+
+/// Record the origin of the values of a term, which are the values which aren't records.
+fn set_origins(
+    rt: &RichTerm,
+    name: &str,
+    path: &mut Vec<String>,
+    origins: &mut BTreeMap<Vec<String>, String>,
+) {
+    match strip_meta(rt).as_ref() {
+        Term::Record(map) => {
+            for (id, t) in map {
+                path.push(id.label.clone());
+                set_origins(t, name, path, origins);
+                path.pop();
+            }
+        }
+        _ => {
+            origins.insert(path.clone(), String::from(name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+    use crate::serialize::{self, ExportFormat};
+    use std::io::Cursor;
+
+    fn eval(source: &str) -> RichTerm {
+        Program::new_from_source(Cursor::new(source), "<test>")
+            .unwrap()
+            .eval_full()
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn layers() {
+        let rt = eval(
+            r#"{
+                server = {host = "localhost", port = 8080, tls = {enabled = false}},
+                replicas = 1,
+                profiles = {
+                    staging = {server.host = "staging", tags = ["a"]},
+                    prod = {server = {host = "prod", tls = true}, replicas = 3},
+                },
+            }"#,
+        );
+
+        let apply_json = |profiles: &[&str]| {
+            let profiles: Vec<String> = profiles.iter().map(|p| String::from(*p)).collect();
+            let (rt, origins) = apply(rt.clone(), &profiles).unwrap();
+            let json = serialize::to_string(ExportFormat::Json, &rt).unwrap();
+            let origins: Vec<String> = origins
+                .iter()
+                .map(|o| format!("{}: {}", o.path, o.layer))
+                .collect();
+            (json.split_whitespace().collect::<String>(), origins)
+        };
+
+        let (json, origins) = apply_json(&[]);
+        assert!(json.contains("\"profiles\""));
+        assert!(origins.is_empty());
+
+        let (json, origins) = apply_json(&["staging", "prod"]);
+        assert_eq!(
+            json,
+            r#"{"replicas":3,"server":{"host":"prod","port":8080,"tls":true},"tags":["a"]}"#
+        );
+        assert_eq!(
+            origins,
+            vec![
+                "replicas: prod",
+                "server.host: prod",
+                "server.port: base",
+                "server.tls: prod",
+                "tags: staging",
+            ]
+        );
+
+        let (json, _) = apply_json(&["prod", "staging"]);
+        assert!(json.contains(r#""host":"staging""#));
+
+        let unknown = apply(rt.clone(), &[String::from("dev")]).unwrap_err();
+        assert_eq!(
+            unknown,
+            SerializationError::Other(String::from(
+                "unknown profile `dev` (available: prod, staging)"
+            ))
+        );
+        assert!(apply(eval("{a = 1}"), &[String::from("dev")]).is_err());
+    }
+}
@@ -21,16 +21,49 @@
 //! embedded strings are then parsed by the functions in this module (see
 //! [`mk_global_env`](./struct.Program.html#method.mk_global_env)).  Each such value is added to
 //! the global environment before the evaluation of the program.
+//!
+//! # Embedding
+//!
+//! Applications embedding Nickel can configure a program using a
+//! [`ProgramBuilder`](./struct.ProgramBuilder.html):
+//!
+//! ```ignore
+//! let mut program = Program::builder()
+//!     .source_str("{port = params.base_port + 1}")
+//!     .with_binding("params", serialize::serialize_to_term(&params)?)
+//!     .import_path("/etc/nickel")
+//!     .build()?;
+//! let config: Config = nickel::from_term(program.eval_full()?)?;
+//! ```
+//!
+//! A program is evaluated on the thread which built it: its terms, the thunks of the evaluator
+//! and the cache share their data through `Rc`, and can't be sent to other threads. Independent
+//! programs can be built and evaluated on different threads.
 use crate::cache::*;
-use crate::error::{Error, ParseError, ToDiagnostic};
+#[cfg(feature = "tools")]
+use crate::doc;
+use crate::error::diagnostic::{self, Diagnostic};
+use crate::error::{Error, IOError};
+#[cfg(feature = "packages")]
+use crate::fetch::Store;
 use crate::identifier::Ident;
-use crate::parser::lexer::Lexer;
+#[cfg(feature = "packages")]
+use crate::manifest::Manifest;
+use crate::native::{mk_native_term, NativeFunction};
+use crate::parser::lexer::{IdentPolicy, Lexer};
+#[cfg(feature = "profiles")]
+use crate::profile::{self, Origin};
+use crate::serialize::{self, ExportFormat, ExportOptions};
 use crate::term::{RichTerm, Term};
-use crate::{eval, parser};
-use codespan::FileId;
-use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use crate::vfs::FileSystem;
+use crate::warning::{self, Warning};
+use crate::{eval, parser, transformations};
+use codespan::{FileId, Files};
+use codespan_reporting::term::termcolor::WriteColor;
 use std::ffi::OsString;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::result::Result;
 
 /// A Nickel program.
@@ -42,6 +75,12 @@ pub struct Program {
     main_id: FileId,
     /// The cache holding the sources and parsed terms of the main source as well as imports.
     cache: Cache,
+    /// Whether the standard library is loaded in the global environment.
+    stdlib: bool,
+    /// Additional bindings of the global environment.
+    bindings: Vec<(Ident, RichTerm)>,
+    /// The levels of the warnings.
+    warning_config: warning::Config,
 }
 
 impl Program {
@@ -54,7 +93,7 @@ impl Program {
         let mut cache = Cache::new();
         let main_id = cache.add_file(path)?;
 
-        Ok(Program { main_id, cache })
+        Ok(Program::new(main_id, cache))
     }
 
     /// Create a program by reading it from a generic source.
@@ -66,23 +105,95 @@ impl Program {
         let mut cache = Cache::new();
         let main_id = cache.add_source(source_name, source)?;
 
-        Ok(Program { main_id, cache })
+        Ok(Program::new(main_id, cache))
+    }
+
+    fn new(main_id: FileId, cache: Cache) -> Self {
+        Program {
+            main_id,
+            cache,
+            stdlib: true,
+            bindings: Vec::new(),
+            warning_config: warning::Config::new(),
+        }
+    }
+
+    /// Create a builder, to configure a program before loading its source.
+    pub fn builder() -> ProgramBuilder {
+        ProgramBuilder::new()
+    }
+
+    /// Generate a fresh global environment, made of the standard library, if enabled, and of the
+    /// additional bindings. If `prepare` is `true`, the standard library and the bindings are
+    /// transformed and can be used for evaluation. Otherwise, they are only typechecked.
+    fn mk_global_env(&mut self, prepare: bool) -> Result<eval::Environment, Error> {
+        let mut global_env = if !self.stdlib {
+            eval::Environment::new()
+        } else if prepare {
+            self.cache.prepare_stdlib()?;
+            self.cache
+                .mk_global_env()
+                .expect("program::mk_global_env(): expected the stdlib to be ready")
+        } else {
+            self.cache.load_stdlib()?;
+            self.cache.typecheck_stdlib().map_err(|err| err.unwrap_error("program::mk_global_env(): stdlib has been loaded but was not found in cache on typechecking"))?;
+            self.cache.mk_global_env().expect("program::mk_global_env(): stdlib has been loaded but was not found in cache on mk_global_env()")
+        };
+
+        for (id, rt) in self.bindings.iter() {
+            rt.check()?;
+            let rt = if prepare {
+                transformations::transform(rt.clone(), &mut self.cache)?
+            } else {
+                rt.clone()
+            };
+            eval::env_add(&mut global_env, id.clone(), rt, eval::Environment::new());
+        }
+
+        Ok(global_env)
     }
 
     /// Retrieve the parsed term and typecheck it, and generate a fresh global environment. Return
     /// both.
     fn prepare_eval(&mut self) -> Result<(RichTerm, eval::Environment), Error> {
-        self.cache.prepare_stdlib()?;
-        let global_env = self
-            .cache
-            .mk_global_env()
-            .expect("program::prepare_eval(): expected event to be ready");
+        let global_env = self.mk_global_env(true)?;
         Ok((
             self.cache.prepare_nocache(self.main_id, &global_env)?,
             global_env,
         ))
     }
 
+    /// Set the characters allowed in the identifiers of the program and of its imports.
+    pub fn set_ident_policy(&mut self, policy: IdentPolicy) {
+        self.cache.set_ident_policy(policy);
+    }
+
+    /// Set the levels of the warnings returned by [`warnings`](#method.warnings).
+    pub fn set_warning_config(&mut self, config: warning::Config) {
+        self.warning_config = config;
+    }
+
+    /// Parse the program and return its warnings which are not allowed. Fail with
+    /// [`Error::DeniedWarnings`](../error/enum.Error.html) if some warnings are denied.
+    pub fn warnings(&mut self) -> Result<Vec<Warning>, Error> {
+        self.cache.parse(self.main_id)?;
+        let source = self.cache.files().source(self.main_id);
+        let mut warnings = warning::deprecations(self.main_id, source);
+        warnings.extend(warning::check(self.cache.get_ref(self.main_id).unwrap()));
+
+        let config = &self.warning_config;
+        let (denied, warnings): (Vec<_>, Vec<_>) = warnings
+            .into_iter()
+            .filter(|w| config.level(w.kind()) != warning::Level::Allow)
+            .partition(|w| config.level(w.kind()) == warning::Level::Deny);
+
+        if denied.is_empty() {
+            Ok(warnings)
+        } else {
+            Err(Error::DeniedWarnings(denied))
+        }
+    }
+
     /// Parse if necessary, typecheck and then evaluate the program.
     pub fn eval(&mut self) -> Result<Term, Error> {
         let (t, global_env) = self.prepare_eval()?;
@@ -95,22 +206,36 @@ impl Program {
         eval::eval_full(t, &global_env, &mut self.cache).map_err(|e| e.into())
     }
 
+    /// Wrapper for [`eval_full_elements`](../eval/fn.eval_full_elements.html): if the program
+    /// evaluates to a list, fully evaluate its elements one by one and pass them to `f`.
+    /// Otherwise, return the fully evaluated result.
+    pub fn eval_full_elements<F>(&mut self, f: F) -> Result<Option<Term>, Error>
+    where
+        F: FnMut(Term) -> Result<(), Error>,
+    {
+        let (t, global_env) = self.prepare_eval()?;
+        eval::eval_full_elements(t, &global_env, &mut self.cache, f)
+    }
+
     /// Wrapper for [`query`](./fn.query.html).
     pub fn query(&mut self, path: Option<String>) -> Result<Term, Error> {
-        self.cache.prepare_stdlib()?;
-        let global_env = self
-            .cache
-            .mk_global_env()
-            .expect("program::prepare_eval(): expected event to be ready");
+        let global_env = self.mk_global_env(true)?;
         query(&mut self.cache, self.main_id, &global_env, path)
     }
 
+    /// Parse the program and write its documentation as HTML pages in the given directory. See
+    /// [`doc`](../doc/index.html).
+    #[cfg(feature = "tools")]
+    pub fn doc(&mut self, dir: &std::path::Path) -> Result<(), Error> {
+        self.cache.parse(self.main_id)?;
+        let root = doc::extract(&mut self.cache, self.main_id)?;
+        Ok(doc::write_html(&root, dir)?)
+    }
+
     /// Load, parse, and typecheck the program and the standard library, if not already done.
     pub fn typecheck(&mut self) -> Result<(), Error> {
         self.cache.parse(self.main_id)?;
-        self.cache.load_stdlib()?;
-        self.cache.typecheck_stdlib().map_err(|err| err.unwrap_error("program::typecheck(): stdlib has been loaded but was not found in cache on typechecking"))?;
-        let global_env = self.cache.mk_global_env().expect("program::typecheck(): stdlib has been loaded but was not found in cache on mk_global_env()");
+        let global_env = self.mk_global_env(false)?;
         self.cache
             .typecheck(self.main_id, &global_env)
             .map_err(|cache_err| {
@@ -119,12 +244,259 @@ impl Program {
         Ok(())
     }
 
+    /// Fully evaluate the program, layer the selected profiles over the result, if the
+    /// `profiles` feature is enabled, and write the selected part of the result to `writer` in the
+    /// given format.
+    pub fn export<W>(
+        &mut self,
+        writer: W,
+        format: ExportFormat,
+        options: &ExportOptions,
+    ) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let rt = self.eval_full().map(RichTerm::from)?;
+        #[cfg(feature = "profiles")]
+        let (rt, _) = profile::apply(rt, &options.profiles)?;
+        write_export(writer, format, rt, options)
+    }
+
+    /// Same as [`export`](#method.export), but also return the origins of the values of the
+    /// whole result if profiles are selected. See the [profile module](../profile/index.html).
+    #[cfg(feature = "profiles")]
+    pub fn export_with_origins<W>(
+        &mut self,
+        writer: W,
+        format: ExportFormat,
+        options: &ExportOptions,
+    ) -> Result<Vec<Origin>, Error>
+    where
+        W: Write,
+    {
+        let rt = self.eval_full().map(RichTerm::from)?;
+        let (rt, origins) = profile::apply(rt, &options.profiles)?;
+        write_export(writer, format, rt, options)?;
+        Ok(origins)
+    }
+
     /// Wrapper for [`report`](./fn.report.html).
-    pub fn report<E>(&mut self, error: E)
+    pub fn report<E>(&mut self, writer: &mut dyn WriteColor, error: E) -> io::Result<()>
+    where
+        E: Into<Error>,
+    {
+        report(writer, &mut self.cache, error)
+    }
+
+    /// Render warnings, as returned by [`warnings`](#method.warnings), to a writer.
+    pub fn report_warnings(
+        &self,
+        writer: &mut dyn WriteColor,
+        warnings: &[Warning],
+    ) -> io::Result<()> {
+        let diagnostics: Vec<Diagnostic> = warnings
+            .iter()
+            .map(|w| w.diagnostic(warning::Level::Warn))
+            .collect();
+        diagnostic::emit(writer, self.cache.files(), &diagnostics)
+    }
+
+    /// Wrapper for [`diagnostics`](./fn.diagnostics.html).
+    pub fn diagnostics<E>(&mut self, error: E) -> Vec<Diagnostic>
     where
-        E: ToDiagnostic<FileId>,
+        E: Into<Error>,
     {
-        report(&mut self.cache, error)
+        diagnostics(&mut self.cache, error)
+    }
+
+    /// The source files of the program and of its imports, to resolve the locations of
+    /// diagnostics.
+    pub fn files(&self) -> &Files<String> {
+        self.cache.files()
+    }
+}
+
+/// The source of a program being built.
+enum Source {
+    Str(OsString, String),
+    File(OsString),
+    Stdin,
+}
+
+/// A builder to configure a program: its source, the standard library, additional bindings of
+/// the global environment, import paths and package. See [`Program::builder`](./struct.Program.html#method.builder).
+pub struct ProgramBuilder {
+    source: Option<Source>,
+    stdlib: bool,
+    bindings: Vec<(Ident, RichTerm)>,
+    import_paths: Vec<PathBuf>,
+    #[cfg(feature = "packages")]
+    manifest: Option<Manifest>,
+    #[cfg(feature = "packages")]
+    store: Option<Store>,
+    ident_policy: IdentPolicy,
+    fs: Option<Rc<dyn FileSystem>>,
+    warning_config: warning::Config,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        ProgramBuilder {
+            source: None,
+            stdlib: true,
+            bindings: Vec::new(),
+            import_paths: Vec::new(),
+            #[cfg(feature = "packages")]
+            manifest: None,
+            #[cfg(feature = "packages")]
+            store: None,
+            ident_policy: IdentPolicy::default(),
+            fs: None,
+            warning_config: warning::Config::new(),
+        }
+    }
+
+    /// Use a string as the source of the program.
+    pub fn source_str(self, source: impl Into<String>) -> Self {
+        self.named_source_str("<string>", source)
+    }
+
+    /// Use a string as the source of the program, with the given name in error messages.
+    pub fn named_source_str(
+        mut self,
+        name: impl Into<OsString>,
+        source: impl Into<String>,
+    ) -> Self {
+        self.source = Some(Source::Str(name.into(), source.into()));
+        self
+    }
+
+    /// Read the source of the program from a file.
+    pub fn source_file(mut self, path: impl Into<OsString>) -> Self {
+        self.source = Some(Source::File(path.into()));
+        self
+    }
+
+    /// Read the source of the program from the standard input.
+    pub fn source_stdin(mut self) -> Self {
+        self.source = Some(Source::Stdin);
+        self
+    }
+
+    /// Load the standard library in the global environment. Enabled by default. Without the
+    /// standard library, programs can't use builtin contracts or stdlib functions.
+    pub fn with_stdlib(mut self, stdlib: bool) -> Self {
+        self.stdlib = stdlib;
+        self
+    }
+
+    /// Bind a term in the global environment of the program. The term must be closed, and is
+    /// typed as `Dyn` unless it has a type annotation. It is [checked](../term/struct.RichTerm.html#method.check)
+    /// before the evaluation, which fails if the term is ill-formed.
+    pub fn with_binding(mut self, name: impl Into<Ident>, term: impl Into<RichTerm>) -> Self {
+        self.bindings.push((name.into(), term.into()));
+        self
+    }
+
+    /// Bind a native function in the global environment of the program. See the [native
+    /// module](../native/index.html).
+    pub fn with_native_function(
+        self,
+        name: impl Into<String>,
+        function: Rc<dyn NativeFunction>,
+    ) -> Self {
+        let name = name.into();
+        let term = mk_native_term(name.clone(), function);
+        self.with_binding(name, term)
+    }
+
+    /// Look up imports in this directory when they are not found relatively to the importing
+    /// file. Import paths are tried in the order they are added.
+    pub fn import_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.import_paths.push(path.into());
+        self
+    }
+
+    /// Resolve imports as in the given package: look them up in its import paths, and import its
+    /// dependencies by name. See the [manifest module](../manifest/index.html).
+    #[cfg(feature = "packages")]
+    pub fn manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// Set the store of the fetched dependencies of the package. Default: the [default
+    /// store](../fetch/struct.Store.html), with the lockfile of the package, if any.
+    #[cfg(feature = "packages")]
+    pub fn store(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Set the characters allowed in the identifiers of the program and of its imports.
+    pub fn ident_policy(mut self, policy: IdentPolicy) -> Self {
+        self.ident_policy = policy;
+        self
+    }
+
+    /// Read the source file and the imports from this filesystem instead of the filesystem of
+    /// the operating system. See the [vfs module](../vfs/index.html).
+    pub fn file_system(mut self, fs: Rc<dyn FileSystem>) -> Self {
+        self.fs = Some(fs);
+        self
+    }
+
+    /// Set the levels of the warnings of the program.
+    pub fn warning_config(mut self, config: warning::Config) -> Self {
+        self.warning_config = config;
+        self
+    }
+
+    /// Load the source and create the program.
+    pub fn build(self) -> Result<Program, Error> {
+        let mut cache = Cache::new();
+        cache.set_ident_policy(self.ident_policy);
+        if let Some(fs) = self.fs {
+            cache.set_file_system(fs);
+        }
+        self.import_paths
+            .into_iter()
+            .for_each(|path| cache.add_import_path(path));
+        #[cfg(feature = "packages")]
+        if let Some(manifest) = self.manifest {
+            let store = match self.store {
+                Some(store) => store,
+                None => Store::for_package(&manifest)?,
+            };
+            manifest.configure(&mut cache, &store)?;
+        }
+
+        let main_id = match self.source {
+            Some(Source::Str(name, source)) => cache.add_string(name, source),
+            Some(Source::File(path)) => cache.add_file(path).map_err(IOError::from)?,
+            Some(Source::Stdin) => cache
+                .add_source("<stdin>", io::stdin())
+                .map_err(IOError::from)?,
+            None => {
+                return Err(Error::IOError(IOError(String::from(
+                    "no source was given to the program builder",
+                ))))
+            }
+        };
+
+        Ok(Program {
+            main_id,
+            cache,
+            stdlib: self.stdlib,
+            bindings: self.bindings,
+            warning_config: self.warning_config,
+        })
+    }
+}
+
+impl Default for ProgramBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -157,9 +529,8 @@ pub fn query(
         // errors.
         let source = format!("x.{}", p);
         let query_file_id = cache.add_tmp("<query>", source.clone());
-        let new_term = parser::grammar::TermParser::new()
-            .parse(query_file_id, Lexer::new(&source))
-            .map_err(|err| ParseError::from_lalrpop(err, query_file_id))?;
+        let new_term =
+            parser::grammar::TermParser::new().parse_strict(query_file_id, Lexer::new(&source))?;
 
         // Substituting `y` for `t`
         let mut env = eval::Environment::new();
@@ -177,47 +548,70 @@ pub fn query(
     Ok(eval::eval_meta(t, &global_env, cache)?)
 }
 
-/// Pretty-print an error.
+/// Convert an error to diagnostics, without printing them.
+pub fn diagnostics<E>(cache: &mut Cache, error: E) -> Vec<Diagnostic>
+where
+    E: Into<Error>,
+{
+    let contracts_id = cache.id_of("<stdlib/contracts.ncl>");
+    error.into().diagnostics(cache.files_mut(), contracts_id)
+}
+
+/// Pretty-print an error to a writer, such as the standard error of a terminal.
 ///
 /// This function is located here in `Program` because errors need a reference to `files` in
 /// order to produce a diagnostic (see [`label_alt`](../error/fn.label_alt.html)).
 //TODO: not sure where this should go. It seems to embed too much logic to be in `Cache`, but is
 //common to both `Program` and `REPL`. Leaving it here as a stand-alone function for now
-pub fn report<E>(cache: &mut Cache, error: E)
+pub fn report<E>(writer: &mut dyn WriteColor, cache: &mut Cache, error: E) -> io::Result<()>
 where
-    E: ToDiagnostic<FileId>,
+    E: Into<Error>,
 {
-    let writer = StandardStream::stderr(ColorChoice::Always);
-    let config = codespan_reporting::term::Config::default();
-    let contracts_id = cache.id_of("<stdlib/contracts.ncl>");
-    let diagnostics = error.to_diagnostic(cache.files_mut(), contracts_id);
-
-    let result = diagnostics.iter().try_for_each(|d| {
-        codespan_reporting::term::emit(&mut writer.lock(), &config, cache.files_mut(), &d)
-    });
-    match result {
-        Ok(()) => (),
-        Err(err) => panic!(
-            "Program::report: could not print an error on stderr: {}",
-            err
-        ),
-    };
+    let diagnostics = diagnostics(cache, error);
+    diagnostic::emit(writer, cache.files(), &diagnostics)
+}
+
+/// Fully evaluate a source string, as done by the tests of the serializers and of other modules.
+/// Panic if the evaluation fails.
+#[cfg(test)]
+pub(crate) fn eval_full_str(source: &str) -> RichTerm {
+    let mut program = Program::new_from_source(io::Cursor::new(source), "<test>").unwrap();
+    program.eval_full().map(RichTerm::from).unwrap()
+}
+
+/// Write the selected part of a fully evaluated term to `writer` in the given format.
+fn write_export<W>(
+    writer: W,
+    format: ExportFormat,
+    rt: RichTerm,
+    options: &ExportOptions,
+) -> Result<(), Error>
+where
+    W: Write,
+{
+    let rt = serialize::select(rt, &options.selection)?;
+    let rt = serialize::redact(rt, options.secrets)?;
+    serialize::validate(format, &rt)?;
+    serialize::to_writer_with_options(writer, format, &rt, options)?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::error::EvalError;
+    use crate::error::{EvalError, TypecheckError};
     use crate::parser::{grammar, lexer};
     use crate::position::TermPos;
+    use crate::vfs::VirtualFileSystem;
     use codespan::Files;
+    use serde_json::json;
     use std::io::Cursor;
 
     fn parse(s: &str) -> Option<RichTerm> {
         let id = Files::new().add("<test>", String::from(s));
 
         grammar::TermParser::new()
-            .parse(id, lexer::Lexer::new(&s))
+            .parse_strict(id, lexer::Lexer::new(&s))
             .map(|mut t| {
                 t.clean_pos();
                 t
@@ -278,4 +672,236 @@ mod tests {
         // that this test fails.
         eval_full("{y = fun x => x, x = fun y => y}").unwrap();
     }
+
+    #[test]
+    fn evaluation_full_elements() {
+        fn elements(s: &str) -> (Vec<serde_json::Value>, Option<serde_json::Value>) {
+            let mut p = Program::new_from_source(Cursor::new(s), "<test>").unwrap();
+            let mut elts = Vec::new();
+            let rest = p
+                .eval_full_elements(|t| {
+                    elts.push(serde_json::to_value(&t).unwrap());
+                    Ok(())
+                })
+                .unwrap();
+            (elts, rest.map(|t| serde_json::to_value(&t).unwrap()))
+        }
+
+        let source = "let x = {a = [1 + 1]} in [x, 1, x, \"b\", {c = x.a}, null]";
+        let (elts, rest) = elements(source);
+        assert_eq!(rest, None);
+        assert_eq!(
+            serde_json::Value::Array(elts),
+            serde_json::to_value(eval_full(source).unwrap()).unwrap()
+        );
+
+        let (elts, rest) = elements("lists.generate (fun i => {n = i}) 3");
+        assert_eq!(
+            elts,
+            vec![json!({"n": 0}), json!({"n": 1}), json!({"n": 2})]
+        );
+        assert_eq!(rest, None);
+
+        let (elts, rest) = elements("{a = [1 + 1]}");
+        assert!(elts.is_empty());
+        assert_eq!(rest, Some(json!({"a": [2]})));
+    }
+
+    #[test]
+    fn builder() {
+        let mut p = Program::builder()
+            .source_str("{port = params.base + 1, name = lists.head params.names}")
+            .with_binding(
+                "params",
+                serialize::serialize_to_term(&json!({"base": 8000, "names": ["web"]})).unwrap(),
+            )
+            .build()
+            .unwrap();
+        assert!(p.typecheck().is_ok());
+        let mut out = Vec::new();
+        p.export(
+            &mut out,
+            ExportFormat::Json,
+            &ExportOptions {
+                json: serialize::JsonOptions { indent: None },
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"{"name":"web","port":8001}"#
+        );
+
+        let mut p = Program::builder()
+            .source_str("lists.head [1]")
+            .with_stdlib(false)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            p.eval(),
+            Err(Error::TypecheckError(TypecheckError::UnboundIdentifier(..)))
+        ));
+        let mut p = Program::builder()
+            .source_str("1 + x")
+            .with_stdlib(false)
+            .with_binding("x", Term::Num(1.0))
+            .build()
+            .unwrap();
+        assert_eq!(p.eval(), Ok(Term::Num(2.0)));
+
+        let mut p = Program::builder()
+            .source_str("x")
+            .with_stdlib(false)
+            .with_binding(
+                "x",
+                crate::term::make::op1(crate::term::UnaryOp::Ite(), Term::Bool(true)),
+            )
+            .build()
+            .unwrap();
+        assert!(matches!(
+            p.eval(),
+            Err(Error::EvalError(EvalError::IllFormedTerm(..)))
+        ));
+
+        let mut p = Program::builder()
+            .source_str("import \"fibonacci.ncl\"")
+            .import_path(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/merge"))
+            .import_path(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/fibonacci"))
+            .build()
+            .unwrap();
+        assert_eq!(p.eval(), Ok(Term::Num(55.0)));
+
+        assert!(Program::builder().build().is_err());
+    }
+
+    #[test]
+    fn data_files() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/config.json", r#"{"port": 80, "hosts": ["a"]}"#);
+        fs.add_file("/config.yaml", "port: 80\nhosts: [a]\n");
+
+        for path in &["/config.json", "/config.yaml"] {
+            let mut p = Program::builder()
+                .source_file(*path)
+                .file_system(Rc::new(fs.clone()))
+                .build()
+                .unwrap();
+            assert_eq!(
+                p.eval_full().map(|t| serde_json::to_value(&t).unwrap()),
+                Ok(json!({"port": 80, "hosts": ["a"]}))
+            );
+        }
+    }
+
+    #[test]
+    fn checked_imports() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/valid.yaml", "port: 80\nhost: a\n");
+        fs.add_file("/invalid.yaml", "port: \"80\"\nhost: a\n");
+        fs.add_file("/schema.ncl", "{port | Num, host | Str}");
+        fs.add_file("/nested.yaml", "server:\n  port: \"80\"\n");
+        fs.add_file("/nested.ncl", "{server = {port | Num}}");
+        fs.add_file("/annotated.ncl", "{server | #{port | Num}}");
+        fs.add_file(
+            "/http.ncl",
+            "fun l x => if x.port == 80 then x else %blame% l",
+        );
+        let fs = Rc::new(fs);
+
+        let eval = |source: &str| {
+            Program::builder()
+                .source_str(source)
+                .import_path("/")
+                .file_system(fs.clone())
+                .build()
+                .unwrap()
+                .eval()
+        };
+
+        assert_eq!(
+            eval("(import \"valid.yaml\" | #(import \"schema.ncl\")).host"),
+            Ok(Term::Str(String::from("a")))
+        );
+        // The whole import is checked, even if only a valid field is used.
+        assert!(matches!(
+            eval("(import \"invalid.yaml\" | #(import \"schema.ncl\")).host"),
+            Err(Error::EvalError(EvalError::BlameError(..)))
+        ));
+        match eval("(import \"invalid.yaml\" | #(import \"http.ncl\")).host") {
+            Err(Error::EvalError(EvalError::BlameError(l, _))) => {
+                assert_eq!(l.tag, "imported from invalid.yaml")
+            }
+            result => panic!("expected a blame error, got {:?}", result),
+        }
+        // The blame of a field of a record schema also mentions the import.
+        for source in [
+            "(import \"invalid.yaml\" | #(import \"schema.ncl\")).host",
+            "(import \"nested.yaml\" | #(import \"nested.ncl\")).server",
+            "(import \"nested.yaml\" | #(import \"annotated.ncl\")).server",
+        ] {
+            match eval(source) {
+                Err(Error::EvalError(EvalError::BlameError(l, _))) => {
+                    assert!(l.tag.starts_with("imported from "), "{}", source);
+                    assert!(l.arg_pos.is_def(), "{}", source);
+                }
+                result => panic!("expected a blame error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn virtual_file_system() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("/config/main.ncl", "(import \"lib/ports.ncl\").http + 1");
+        fs.add_file("/config/lib/ports.ncl", "{http = import \"../base.ncl\"}");
+        fs.add_file("/config/base.ncl", "79");
+        fs.add_file("/shared/util.ncl", "{double = fun x => 2 * x}");
+        let fs = Rc::new(fs);
+
+        let mut p = Program::builder()
+            .source_file("/config/main.ncl")
+            .file_system(fs.clone())
+            .build()
+            .unwrap();
+        assert_eq!(p.eval(), Ok(Term::Num(80.0)));
+
+        let mut p = Program::builder()
+            .source_str("(import \"util.ncl\").double 2")
+            .import_path("/shared")
+            .file_system(fs.clone())
+            .build()
+            .unwrap();
+        assert_eq!(p.eval(), Ok(Term::Num(4.0)));
+
+        let mut p = Program::builder()
+            .source_str("import \"Cargo.toml\"")
+            .file_system(fs.clone())
+            .build()
+            .unwrap();
+        assert!(matches!(p.eval(), Err(Error::ImportError(..))));
+        assert!(Program::builder()
+            .source_file("/config/missing.ncl")
+            .file_system(fs)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn positions_after_transformations() {
+        let source = "let {a, b = {c}} = {a = 1, b = {c = [a, 2]}} in \
+                      let f | Num -> Num = fun x => x + 1 in \
+                      {foo | List Num = c, bar = match {{x} if x > 0 => f x, _ => 0} {x = a}}";
+        let mut p = Program::new_from_source(Cursor::new(source), "<test>").unwrap();
+        let (t, _) = p.prepare_eval().unwrap();
+        assert!(t.all_pos_defined());
+
+        match eval_full("match {1 => \"one\"} (1 + 1)") {
+            Err(Error::EvalError(EvalError::BlameError(l, _))) => {
+                let pos = l.arg_pos.unwrap();
+                assert_eq!((pos.start.to_usize(), pos.end.to_usize()), (19, 26));
+            }
+            result => panic!("expected a blame error, got {:?}", result),
+        }
+    }
 }
@@ -7,19 +7,32 @@
 //! jupyter-kernel (which is not exactly user-facing, but still manages input/output and
 //! formatting), etc.
 use crate::cache::Cache;
+use crate::error::diagnostic::{Diagnostic, Label, Severity};
+use crate::error::REPLError;
 use crate::error::{Error, EvalError, IOError};
-use crate::error::{ParseError, REPLError};
+use crate::explain::{self, ErrorCode};
 use crate::identifier::Ident;
+use crate::parser::lexer::{NormalToken, Token};
+use crate::parser::utils::build_rec_group;
 use crate::parser::{grammar, lexer, ExtendedTerm};
-use crate::term::{RichTerm, Term};
-use crate::types::Types;
-use crate::{eval, transformations, typecheck};
+use crate::position::RawSpan;
+use crate::serialize::{self, ExportFormat, Secrets};
+use crate::term::{make as mk_term, MetaValue, RichTerm, Term, UnaryOp};
+use crate::types::{AbsType, Types};
+use crate::{eval, program, transformations, typecheck};
+use codespan::FileId;
+use serde::{Deserialize, Serialize};
 use simple_counter::*;
+use std::collections::HashMap;
+use std::convert::TryInto;
 use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::result::Result;
 use std::str::FromStr;
 
 generate_counter!(InputNameCounter, usize);
+generate_counter!(SnapshotCounter, usize);
 
 /// Result of the evaluation of an input.
 pub enum EvalResult {
@@ -27,6 +40,8 @@ pub enum EvalResult {
     Evaluated(Term),
     /// The input was a toplevel let, which has been bound in the environment.
     Bound(Ident),
+    /// The input was a toplevel let rec, whose bindings have been added to the environment.
+    BoundRec(Vec<Ident>),
 }
 
 impl From<Term> for EvalResult {
@@ -35,20 +50,126 @@ impl From<Term> for EvalResult {
     }
 }
 
+/// A verbose report of an error, as printed by the `:explain` command.
+pub struct Explanation {
+    /// The diagnostics of the error, followed by the definition of the contract which has been
+    /// broken and by the bindings of the REPL environment used by the failed input, if any.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The extended explanation of the code of the error.
+    pub code: Option<&'static ErrorCode>,
+}
+
+/// An input of a REPL session which has changed the environment.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionEntry {
+    /// A toplevel let-binding, as typed by the user.
+    Input(String),
+    /// A loaded file. The path is absolute if the file has been found.
+    Load(PathBuf),
+    /// A toplevel binding removed from the environment.
+    Unbind(String),
+}
+
+/// The user-defined bindings of a REPL session, as returned by
+/// [`REPL::snapshot`](trait.REPL.html#tymethod.snapshot), to be restored in another session. The
+/// bindings are kept as source and are evaluated again when restored, such that a session only
+/// depends on the files it loads.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Session {
+    /// The toplevel let-bindings and the loaded files, in order.
+    pub entries: Vec<SessionEntry>,
+}
+
+impl Session {
+    /// Parse a session saved by [`render`](#method.render).
+    pub fn parse(source: &str) -> Result<Self, String> {
+        serde_json::from_str(source).map_err(|err| err.to_string())
+    }
+
+    /// Return the session as JSON.
+    pub fn render(&self) -> String {
+        // Serializing strings and paths can't fail.
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
 /// Interface of the REPL backend.
 pub trait REPL {
     /// Evaluate an expression, which can be either a standard term or a toplevel let-binding.
     fn eval(&mut self, exp: &str) -> Result<EvalResult, Error>;
+    /// Evaluate an input as [`eval`](#tymethod.eval), and return the statistics of its
+    /// evaluation.
+    fn time(&mut self, exp: &str) -> Result<(EvalResult, eval::Stats), Error>;
     /// Load the content of a file in the environment. Return the loaded record.
     fn load(&mut self, path: impl AsRef<OsStr>) -> Result<RichTerm, Error>;
     /// Typecheck an expression and return its [apparent type](../typecheck/fn.apparent_type.html).
     fn typecheck(&mut self, exp: &str) -> Result<Types, Error>;
     /// Query the metadata of an expression.
     fn query(&mut self, exp: &str) -> Result<Term, Error>;
+    /// Fully evaluate an expression. The sensitive values of the result are redacted, and the
+    /// metadata of its fields is removed.
+    fn print(&mut self, exp: &str) -> Result<RichTerm, Error>;
+    /// Fully evaluate an expression, or the bindings of the environment if there is none, and
+    /// serialize the result in the given format. The bindings of the standard library and the
+    /// bindings to functions are not exported, and the sensitive values are redacted.
+    fn export(&mut self, format: ExportFormat, exp: Option<&str>) -> Result<String, Error>;
+    /// Return the toplevel bindings of the environment, defined in the REPL or by loaded files,
+    /// sorted by name.
+    fn env(&self) -> Vec<Binding>;
+    /// Remove a toplevel binding from the environment. The bindings defined before its removal
+    /// keep referring to it. Return `false` if the identifier isn't bound, or is bound by the
+    /// standard library.
+    fn unbind(&mut self, id: Ident) -> bool;
+    /// Remove all the toplevel bindings from the environment, keeping the standard library, and
+    /// start a new session.
+    fn reset(&mut self);
+    /// Explain the last error raised by one of the other methods, or return `None` if none has
+    /// failed yet.
+    fn explain(&mut self) -> Option<Explanation>;
+    /// Return the toplevel let-bindings and the loaded files of the session.
+    fn snapshot(&self) -> Session;
+    /// Evaluate the bindings and load the files of a saved session, in order, on top of the
+    /// current environment. Stop at the first error.
+    fn restore(&mut self, session: &Session) -> Result<(), Error>;
+    /// Save the current environment, to go back to it later with
+    /// [`rollback`](#tymethod.rollback).
+    fn checkpoint(&mut self) -> SnapshotId;
+    /// Restore the environment saved by a checkpoint, and discard this checkpoint together with
+    /// the ones taken after it. Return `false` if the checkpoint has already been discarded.
+    fn rollback(&mut self, id: SnapshotId) -> bool;
+    /// Undo the last input which has changed the environment: a toplevel let-binding, a loaded
+    /// file, a removed binding, a reset or a restored session. Return `false` if there is nothing
+    /// to undo.
+    fn undo(&mut self) -> bool;
     /// Required for error reporting on the frontend.
     fn cache_mut(&mut self) -> &mut Cache;
 }
 
+/// A toplevel binding of the REPL environment, as listed by [`REPL::env`](trait.REPL.html#tymethod.env).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub id: Ident,
+    /// The apparent type of the bound value.
+    pub types: Types,
+    /// The first line of the documentation of the bound value, if any.
+    pub doc: Option<String>,
+}
+
+/// A checkpoint of the environment, as returned by
+/// [`REPL::checkpoint`](trait.REPL.html#tymethod.checkpoint).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SnapshotId(usize);
+
+/// The environment saved by a checkpoint.
+struct Checkpoint {
+    id: SnapshotId,
+    eval_env: eval::Environment,
+    type_env: typecheck::Environment,
+    history: Vec<SessionEntry>,
+}
+
 /// Standard implementation of the REPL backend.
 pub struct REPLImpl {
     /// The underlying cache, storing input, loaded files and parsed terms.
@@ -62,6 +183,16 @@ pub struct REPLImpl {
     /// [`TypeWrapper`](../typecheck/enum.TypeWrapper.html) for the ease of interacting with the
     /// typechecker, but there are not any unification variable in it.
     type_env: typecheck::Environment,
+    /// The last error, together with the input which raised it.
+    last_error: Option<(Error, String)>,
+    /// The inputs which have changed the environment, to be saved by
+    /// [`snapshot`](trait.REPL.html#tymethod.snapshot).
+    history: Vec<SessionEntry>,
+    /// The saved environments, in the order they have been taken.
+    checkpoints: Vec<Checkpoint>,
+    /// The checkpoints taken before the inputs which have changed the environment, to be restored
+    /// by [`undo`](trait.REPL.html#tymethod.undo).
+    undo_stack: Vec<SnapshotId>,
 }
 
 impl REPLImpl {
@@ -72,6 +203,10 @@ impl REPLImpl {
             parser: grammar::ExtendedTermParser::new(),
             eval_env: eval::Environment::new(),
             type_env: typecheck::Environment::new(),
+            last_error: None,
+            history: Vec::new(),
+            checkpoints: Vec::new(),
+            undo_stack: Vec::new(),
         }
     }
 
@@ -84,27 +219,294 @@ impl REPLImpl {
         self.type_env = typecheck::Envs::mk_global(&self.eval_env);
         Ok(())
     }
+
+    /// Run an operation, and keep the first checkpoint it takes to undo it if it changes the
+    /// environment. Operations only take a checkpoint right before modifying the environment,
+    /// such that evaluating an expression doesn't copy it. The checkpoints taken by nested
+    /// operations, such as the inputs of a restored session, are discarded, such that the
+    /// operation is undone as a whole.
+    fn undoable<T>(&mut self, op: impl FnOnce(&mut Self) -> T) -> T {
+        let first = self.checkpoints.len();
+        let history_len = self.history.len();
+        let result = op(self);
+
+        if let Some(id) = self.checkpoints.get(first).map(|checkpoint| checkpoint.id) {
+            if self.history.len() == history_len {
+                self.discard(id);
+            } else {
+                self.discard(SnapshotId(id.0 + 1));
+                self.undo_stack.push(id);
+            }
+        }
+        result
+    }
+
+    /// Discard a checkpoint and the ones taken after it, without restoring them.
+    fn discard(&mut self, from: SnapshotId) {
+        self.checkpoints
+            .retain(|checkpoint| checkpoint.id.0 < from.0);
+        self.undo_stack.retain(|id| id.0 < from.0);
+    }
+
+    /// Keep the error of a failed input, to be explained later.
+    fn record<T>(&mut self, input: &str, result: Result<T, Error>) -> Result<T, Error> {
+        if let Err(err) = &result {
+            self.last_error = Some((err.clone(), String::from(input)));
+        }
+        result
+    }
+
+    /// Return the position of the definition of a binding of the eval environment, unless it is
+    /// defined by the standard library.
+    fn definition(&self, id: &Ident) -> Option<RawSpan> {
+        let span = self.eval_env.get(id)?.borrow().body.pos.into_opt()?;
+        Some(span).filter(|span| !self.cache.is_stdlib(span.src_id))
+    }
+
+    /// Return the position of the definition of the contract corresponding to a type, if it is a
+    /// user-defined contract such as `#Port`.
+    fn contract_definition(&self, types: &Types) -> Option<RawSpan> {
+        match &types.0 {
+            AbsType::Flat(rt) => match rt.as_ref() {
+                Term::Var(id) => self.definition(id),
+                _ => rt.pos.into_opt(),
+            },
+            _ => None,
+        }
+    }
+
+    /// Return the labels of the definitions of the bindings of the eval environment which occur in
+    /// an input, in order of occurrence, excluding the standard library.
+    fn bindings(&self, input: &str) -> Vec<Label> {
+        let mut ids: Vec<Ident> = Vec::new();
+
+        for token in lexer::Lexer::new(input) {
+            if let Ok((_, Token::Normal(NormalToken::Identifier(id)), _)) = token {
+                let id = Ident::from(id);
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+
+        ids.into_iter()
+            .filter_map(|id| {
+                let span = self.definition(&id)?;
+                let value = self
+                    .eval_env
+                    .get(&id)?
+                    .borrow()
+                    .body
+                    .as_ref()
+                    .shallow_repr();
+                Some(Label::new(false, span, format!("{} = {}", id, value)))
+            })
+            .collect()
+    }
+
+    /// Return the terms bound in the eval environment, used to complete the input.
+    pub fn globals(&self) -> HashMap<Ident, RichTerm> {
+        self.eval_env
+            .iter()
+            .map(|(id, thunk)| (id.clone(), thunk.borrow().body.clone()))
+            .collect()
+    }
 }
 
 impl REPL for REPLImpl {
     fn eval(&mut self, exp: &str) -> Result<EvalResult, Error> {
+        self.undoable(|repl| {
+            let result = repl.eval_input(exp);
+            if let Ok(EvalResult::Bound(_)) | Ok(EvalResult::BoundRec(_)) = result {
+                repl.history.push(SessionEntry::Input(String::from(exp)));
+            }
+            repl.record(exp, result)
+        })
+    }
+
+    fn time(&mut self, exp: &str) -> Result<(EvalResult, eval::Stats), Error> {
+        let (result, stats) = eval::with_stats(|| self.eval(exp));
+        result.map(|result| (result, stats))
+    }
+
+    fn load(&mut self, path: impl AsRef<OsStr>) -> Result<RichTerm, Error> {
+        self.undoable(|repl| {
+            let result = repl.load_file(path.as_ref());
+            if result.is_ok() {
+                let path = Path::new(path.as_ref());
+                let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+                repl.history.push(SessionEntry::Load(path));
+            }
+            repl.record(&path.as_ref().to_string_lossy(), result)
+        })
+    }
+
+    fn typecheck(&mut self, exp: &str) -> Result<Types, Error> {
+        let result = self.typecheck_input(exp);
+        self.record(exp, result)
+    }
+
+    fn query(&mut self, exp: &str) -> Result<Term, Error> {
+        let file_id = self.cache.add_tmp("<repl-query>", String::from(exp));
+        let result = program::query(&mut self.cache, file_id, &self.eval_env, None);
+        self.record(exp, result)
+    }
+
+    fn print(&mut self, exp: &str) -> Result<RichTerm, Error> {
+        let result = self
+            .eval_full_input("<repl-print>", exp)
+            .map(|rt| strip_metadata(&rt));
+        self.record(exp, result)
+    }
+
+    fn export(&mut self, format: ExportFormat, exp: Option<&str>) -> Result<String, Error> {
+        let result = self.export_input(format, exp);
+        self.record(exp.unwrap_or(""), result)
+    }
+
+    fn env(&self) -> Vec<Binding> {
+        self.user_bindings()
+            .into_iter()
+            .map(|id| {
+                let types = self
+                    .type_env
+                    .get(&id)
+                    .cloned()
+                    .and_then(|tyw| tyw.try_into().ok())
+                    .unwrap_or(Types(AbsType::Dyn()));
+                let doc = doc_summary(&self.eval_env.get(&id).unwrap().borrow().body);
+                Binding { id, types, doc }
+            })
+            .collect()
+    }
+
+    fn unbind(&mut self, id: Ident) -> bool {
+        if !self.user_bindings().contains(&id) {
+            return false;
+        }
+
+        self.undoable(|repl| {
+            repl.checkpoint();
+            repl.eval_env.remove(&id);
+            repl.type_env.remove(&id);
+            repl.history.push(SessionEntry::Unbind(id.label));
+        });
+        true
+    }
+
+    fn reset(&mut self) {
+        self.undoable(|repl| {
+            repl.checkpoint();
+            repl.eval_env = repl.cache.mk_global_env().unwrap_or_default();
+            repl.type_env = typecheck::Envs::mk_global(&repl.eval_env);
+            repl.last_error = None;
+            repl.history.clear();
+        })
+    }
+
+    fn snapshot(&self) -> Session {
+        Session {
+            entries: self.history.clone(),
+        }
+    }
+
+    fn restore(&mut self, session: &Session) -> Result<(), Error> {
+        self.undoable(|repl| {
+            for entry in session.entries.iter() {
+                match entry {
+                    SessionEntry::Input(input) => repl.eval(input).map(|_| ())?,
+                    SessionEntry::Load(path) => repl.load(path).map(|_| ())?,
+                    SessionEntry::Unbind(id) => {
+                        repl.unbind(Ident::from(id.as_str()));
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn checkpoint(&mut self) -> SnapshotId {
+        let id = SnapshotId(SnapshotCounter::next());
+        self.checkpoints.push(Checkpoint {
+            id,
+            eval_env: self.eval_env.clone(),
+            type_env: self.type_env.clone(),
+            history: self.history.clone(),
+        });
+        id
+    }
+
+    fn rollback(&mut self, id: SnapshotId) -> bool {
+        let index = match self
+            .checkpoints
+            .iter()
+            .position(|checkpoint| checkpoint.id == id)
+        {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let checkpoint = self.checkpoints.remove(index);
+        self.discard(id);
+        self.eval_env = checkpoint.eval_env;
+        self.type_env = checkpoint.type_env;
+        self.history = checkpoint.history;
+        true
+    }
+
+    fn undo(&mut self) -> bool {
+        match self.undo_stack.pop() {
+            Some(id) => self.rollback(id),
+            None => false,
+        }
+    }
+
+    fn explain(&mut self) -> Option<Explanation> {
+        let (error, input) = self.last_error.clone()?;
+        let code = explain::lookup(explain::error_code(&error));
+        let mut diagnostics = program::diagnostics(&mut self.cache, error.clone());
+
+        if let Error::EvalError(EvalError::BlameError(l, _)) = &error {
+            if let Some(span) = self.contract_definition(&l.types) {
+                diagnostics.push(
+                    Diagnostic::new(Severity::Note, "the contract is defined here")
+                        .with_labels(vec![Label::new(true, span, "")]),
+                );
+            }
+        }
+
+        let bindings = self.bindings(&input);
+        if !bindings.is_empty() {
+            diagnostics.push(
+                Diagnostic::new(Severity::Note, "bindings of the REPL used by the input")
+                    .with_labels(bindings),
+            );
+        }
+
+        Some(Explanation { diagnostics, code })
+    }
+
+    fn cache_mut(&mut self) -> &mut Cache {
+        &mut self.cache
+    }
+}
+
+impl REPLImpl {
+    fn eval_input(&mut self, exp: &str) -> Result<EvalResult, Error> {
         let file_id = self.cache.add_string(
             format!("repl-input-{}", InputNameCounter::next()),
             String::from(exp),
         );
 
-        match self
-            .parser
-            .parse(file_id, lexer::Lexer::new(exp))
-            .map_err(|err| ParseError::from_lalrpop(err, file_id))?
-        {
+        match self.parser.parse_strict(file_id, lexer::Lexer::new(exp))? {
             ExtendedTerm::RichTerm(t) => {
                 typecheck::type_check_in_env(&t, &self.type_env, &self.cache)?;
                 let t = transformations::transform(t, &mut self.cache)?;
-                Ok(eval::eval(t, &self.eval_env, &mut self.cache)?.into())
+                Ok(eval::eval_sensitive(t, &self.eval_env, &mut self.cache)?.into())
             }
             ExtendedTerm::ToplevelLet(id, t) => {
                 typecheck::type_check_in_env(&t, &self.type_env, &self.cache)?;
+                self.checkpoint();
                 typecheck::Envs::env_add(&mut self.type_env, id.clone(), &t);
 
                 let t = transformations::transform(t, &mut self.cache)?;
@@ -113,13 +515,39 @@ impl REPL for REPLImpl {
                 eval::env_add(&mut self.eval_env, id.clone(), t, local_env);
                 Ok(EvalResult::Bound(id))
             }
+            ExtendedTerm::ToplevelLetRec(bindings) => {
+                let ids: Vec<Ident> = bindings.iter().map(|(id, _)| id.clone()).collect();
+                let group = RichTerm::from(build_rec_group(bindings.clone()));
+                typecheck::type_check_in_env(&group, &self.type_env, &self.cache)?;
+                self.checkpoint();
+
+                for (id, t) in bindings.iter() {
+                    typecheck::Envs::env_add(&mut self.type_env, id.clone(), t);
+                }
+
+                let group = transformations::transform(group, &mut self.cache)?;
+                let group_id = transformations::fresh_var();
+                let local_env = self.eval_env.clone();
+                eval::env_add(&mut self.eval_env, group_id.clone(), group, local_env);
+
+                for id in ids.iter() {
+                    let access = mk_term::op1(
+                        UnaryOp::StaticAccess(id.clone()),
+                        mk_term::var(group_id.clone()),
+                    );
+                    let local_env = self.eval_env.clone();
+                    eval::env_add(&mut self.eval_env, id.clone(), access, local_env);
+                }
+
+                Ok(EvalResult::BoundRec(ids))
+            }
         }
     }
 
-    fn load(&mut self, path: impl AsRef<OsStr>) -> Result<RichTerm, Error> {
+    fn load_file(&mut self, path: &OsStr) -> Result<RichTerm, Error> {
         let file_id = self
             .cache
-            .add_file(OsString::from(path.as_ref()))
+            .add_file(OsString::from(path))
             .map_err(IOError::from)?;
         self.cache.parse(file_id)?;
         let RichTerm { term, pos } = self.cache.get_ref(file_id).unwrap();
@@ -139,13 +567,14 @@ impl REPL for REPLImpl {
         })?;
 
         let term = self.cache.get_owned(file_id).unwrap();
+        self.checkpoint();
         typecheck::Envs::env_add_term(&mut self.type_env, &term).unwrap();
         eval::env_add_term(&mut self.eval_env, term.clone()).unwrap();
 
         Ok(term)
     }
 
-    fn typecheck(&mut self, exp: &str) -> Result<Types, Error> {
+    fn typecheck_input(&mut self, exp: &str) -> Result<Types, Error> {
         let file_id = self.cache.add_tmp("<repl-typecheck>", String::from(exp));
         let term = self.cache.parse_nocache(file_id)?;
         typecheck::type_check_in_env(&term, &self.type_env, &self.cache)?;
@@ -157,29 +586,167 @@ impl REPL for REPLImpl {
         .into())
     }
 
-    fn query(&mut self, exp: &str) -> Result<Term, Error> {
-        use crate::program;
+    /// Fully evaluate an expression, added to the cache under the given name, and redact the
+    /// sensitive values of the result.
+    fn eval_full_input(&mut self, name: &str, exp: &str) -> Result<RichTerm, Error> {
+        let file_id = self.cache.add_tmp(name, String::from(exp));
+        let term = self.cache.parse_nocache(file_id)?;
+        typecheck::type_check_in_env(&term, &self.type_env, &self.cache)?;
+        let term = transformations::transform(term, &mut self.cache)?;
 
-        let file_id = self.cache.add_tmp("<repl-query>", String::from(exp));
-        program::query(&mut self.cache, file_id, &self.eval_env, None)
+        let rt = eval::eval_full_sensitive(term, &self.eval_env, &mut self.cache)?.into();
+        Ok(serialize::redact(rt, Secrets::Redact)?)
     }
 
-    fn cache_mut(&mut self) -> &mut Cache {
-        &mut self.cache
+    /// Return `true` if an input is a toplevel let, and `false` if it is an expression or is
+    /// invalid.
+    fn is_binding(&mut self, exp: &str) -> bool {
+        let file_id = self.cache.add_tmp("<repl-input>", String::from(exp));
+        matches!(
+            self.parser.parse_strict(file_id, lexer::Lexer::new(exp)),
+            Ok(ExtendedTerm::ToplevelLet(..)) | Ok(ExtendedTerm::ToplevelLetRec(..))
+        )
+    }
+
+    /// Return the bindings of the environment, excluding the standard library, sorted by name.
+    fn user_bindings(&self) -> Vec<Ident> {
+        let mut ids: Vec<Ident> = self
+            .eval_env
+            .iter()
+            .filter(|(id, thunk)| {
+                let from_stdlib = thunk
+                    .borrow()
+                    .body
+                    .pos
+                    .into_opt()
+                    .is_some_and(|span| self.cache.is_stdlib(span.src_id));
+                // Fresh variables bind the groups of toplevel let rec.
+                !id.label.starts_with('%') && !from_stdlib
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Fully evaluate the bindings of the environment to a record, excluding the standard library
+    /// and the bindings to functions, and redact the sensitive values.
+    fn eval_full_bindings(&mut self) -> Result<RichTerm, Error> {
+        let mut map = HashMap::new();
+        for id in self.user_bindings() {
+            let t = eval::eval_full_sensitive(
+                RichTerm::from(Term::Var(id.clone())),
+                &self.eval_env,
+                &mut self.cache,
+            )?;
+            if !matches!(t, Term::Fun(..)) {
+                map.insert(id, RichTerm::from(t));
+            }
+        }
+        Ok(serialize::redact(
+            Term::Record(map).into(),
+            Secrets::Redact,
+        )?)
+    }
+
+    fn export_input(&mut self, format: ExportFormat, exp: Option<&str>) -> Result<String, Error> {
+        let rt = match exp {
+            Some(exp) => self.eval_full_input("<repl-export>", exp)?,
+            None => self.eval_full_bindings()?,
+        };
+        serialize::validate(format, &rt)?;
+        Ok(serialize::to_string(format, &rt)?)
+    }
+}
+
+/// Add a line read by `:paste` to the input, unless it is the terminator. A line may contain
+/// several lines of a pasted text, in which case the lines following the terminator are dropped.
+/// Return `true` once the terminator has been read.
+fn push_pasted(input: &mut String, line: &str, terminator: &str) -> bool {
+    for line in line.lines() {
+        if line.trim() == terminator {
+            return true;
+        }
+        input.push_str(line);
+        input.push('\n');
+    }
+    false
+}
+
+/// Return `true` if an input is the beginning of an expression, such as a record whose closing
+/// brace is missing, and must be continued on the next line.
+fn is_incomplete(parser: &grammar::ExtendedTermParser, file_id: FileId, input: &str) -> bool {
+    use crate::error::ParseError;
+
+    matches!(
+        parser.parse_strict(file_id, lexer::Lexer::new(input)),
+        Err(ParseError::UnexpectedEOF(..))
+            | Err(ParseError::UnmatchedCloseBrace(..))
+            | Err(ParseError::UnterminatedString(..))
+    )
+}
+
+/// Return the first non-empty line of the documentation of a bound term, if any.
+fn doc_summary(rt: &RichTerm) -> Option<String> {
+    match rt.as_ref() {
+        Term::MetaValue(MetaValue { doc: Some(doc), .. }) => doc
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(String::from),
+        // The value of a metavalue may have been moved to a let binding to be shared.
+        Term::Let(_, _, body) => doc_summary(body),
+        _ => None,
+    }
+}
+
+/// Remove the metadata of a fully evaluated term and of its subterms.
+fn strip_metadata(rt: &RichTerm) -> RichTerm {
+    match rt.as_ref() {
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => strip_metadata(t),
+        Term::Record(map) => RichTerm::new(
+            Term::Record(
+                map.iter()
+                    .map(|(id, t)| (id.clone(), strip_metadata(t)))
+                    .collect(),
+            ),
+            rt.pos,
+        ),
+        Term::List(ts) => {
+            RichTerm::new(Term::List(ts.iter().map(strip_metadata).collect()), rt.pos)
+        }
+        _ => rt.clone(),
     }
 }
 
 /// REPL commands helpers common to all frontends.
 pub mod command {
     use super::*;
+    use crate::position::RawSpan;
+    use codespan::{ByteIndex, FileId};
     use std::fmt;
 
+    /// The line ending the input of `:paste` if no terminator is given.
+    pub const PASTE_TERMINATOR: &str = ":end";
+
     /// Available commands.
     #[derive(Copy, Clone, Eq, PartialEq, Debug)]
     pub enum CommandType {
         Load,
         Typecheck,
         Query,
+        Print,
+        Time,
+        Doc,
+        Export,
+        Env,
+        Unbind,
+        Reset,
+        Undo,
+        Paste,
+        Explain,
+        SaveSession,
+        RestoreSession,
         Help,
         Exit,
     }
@@ -191,24 +758,62 @@ pub mod command {
         Load(OsString),
         Typecheck(String),
         Query(String),
+        Print(String),
+        /// Evaluate an expression and show the statistics of its evaluation, or switch the
+        /// statistics of every evaluation on or off if there is none.
+        Time(Option<String>),
+        /// Show the documentation of a path, such as `lists.map`.
+        Doc(String),
+        Export(ExportFormat, Option<String>),
+        Env,
+        Unbind(Ident),
+        Reset,
+        /// Undo the last input which has changed the environment.
+        Undo,
+        /// Read raw lines until the given terminator.
+        Paste(String),
+        Explain,
+        SaveSession(OsString),
+        RestoreSession(OsString),
         Help(Option<String>),
         Exit,
     }
 
     pub struct UnknownCommandError {}
 
-    /// Check that an argument is non-empty, or return an error with the given optional message.
-    fn require_arg(cmd: CommandType, arg: &str, msg_opt: Option<&str>) -> Result<(), REPLError> {
+    /// Check that an argument is non-empty, or return an error with the given optional message,
+    /// pointing to the span of the command.
+    fn require_arg(
+        cmd: CommandType,
+        span: RawSpan,
+        arg: &str,
+        msg_opt: Option<&str>,
+    ) -> Result<(), REPLError> {
         if arg.trim().is_empty() {
             Err(REPLError::MissingArg {
                 cmd,
                 msg_opt: msg_opt.map(String::from),
+                span,
             })
         } else {
             Ok(())
         }
     }
 
+    /// Return `true` if the input is a dot-separated path of identifiers.
+    fn is_path(input: &str) -> bool {
+        let mut expect_id = true;
+        for token in lexer::Lexer::new(input) {
+            match token {
+                Ok((_, Token::Normal(NormalToken::Identifier(_)), _)) if expect_id => (),
+                Ok((_, Token::Normal(NormalToken::Dot), _)) if !expect_id => (),
+                _ => return false,
+            }
+            expect_id = !expect_id;
+        }
+        !expect_id
+    }
+
     impl FromStr for CommandType {
         type Err = UnknownCommandError;
 
@@ -219,6 +824,18 @@ pub mod command {
                 "load" | "l" => Ok(Load),
                 "typecheck" | "tc" => Ok(Typecheck),
                 "query" | "q" => Ok(Query),
+                "print" | "p" => Ok(Print),
+                "time" => Ok(Time),
+                "doc" => Ok(Doc),
+                "export" => Ok(Export),
+                "env" => Ok(Env),
+                "unbind" => Ok(Unbind),
+                "reset" => Ok(Reset),
+                "undo" => Ok(Undo),
+                "paste" => Ok(Paste),
+                "explain" => Ok(Explain),
+                "save-session" | "save" => Ok(SaveSession),
+                "restore-session" | "restore" => Ok(RestoreSession),
                 "help" | "?" | "h" => Ok(Help),
                 "exit" | "e" => Ok(Exit),
                 _ => Err(UnknownCommandError {}),
@@ -226,7 +843,102 @@ pub mod command {
         }
     }
 
+    /// The number of insertions, deletions and substitutions of characters needed to turn `a`
+    /// into `b`.
+    fn edit_distance(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut row: Vec<usize> = (0..=b.len()).collect();
+
+        for (i, ca) in a.chars().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+
+            for (j, cb) in b.iter().enumerate() {
+                let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+                diagonal = row[j + 1];
+                row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+            }
+        }
+
+        row[b.len()]
+    }
+
+    /// Return the names of the commands which start with the given prefix, in the order of
+    /// [`CommandType::ALL`](enum.CommandType.html#associatedconstant.ALL).
+    pub fn complete_name(prefix: &str) -> Vec<String> {
+        CommandType::ALL
+            .iter()
+            .map(CommandType::to_string)
+            .filter(|name| name.starts_with(prefix))
+            .collect()
+    }
+
+    /// Return the offset of the expression argument in a command line, such as `:print 1 + 1`,
+    /// if the command takes one and the expression has started.
+    pub fn expression_offset(line: &str) -> Option<usize> {
+        let rest = line.strip_prefix(':')?;
+        let name_end = rest.find(char::is_whitespace)?;
+        let cmd: CommandType = rest[..name_end].parse().ok()?;
+
+        // The start of the next word after `offset`.
+        let skip_spaces =
+            |offset: usize| offset + rest[offset..].len() - rest[offset..].trim_start().len();
+        let start = skip_spaces(name_end);
+        let start = match cmd {
+            CommandType::Typecheck
+            | CommandType::Query
+            | CommandType::Print
+            | CommandType::Time
+            | CommandType::Doc => start,
+            // The expression comes after the format.
+            CommandType::Export => {
+                let format_end = start + rest[start..].find(char::is_whitespace)?;
+                skip_spaces(format_end)
+            }
+            _ => return None,
+        };
+        Some(start + 1)
+    }
+
     impl CommandType {
+        pub const ALL: [CommandType; 17] = [
+            CommandType::Load,
+            CommandType::Typecheck,
+            CommandType::Query,
+            CommandType::Print,
+            CommandType::Time,
+            CommandType::Doc,
+            CommandType::Export,
+            CommandType::Env,
+            CommandType::Unbind,
+            CommandType::Reset,
+            CommandType::Undo,
+            CommandType::Paste,
+            CommandType::Explain,
+            CommandType::SaveSession,
+            CommandType::RestoreSession,
+            CommandType::Help,
+            CommandType::Exit,
+        ];
+
+        /// Return the command whose name or alias is the closest to an unknown command name, if it
+        /// is close enough for the unknown name to be a typo.
+        pub fn closest(name: &str) -> Option<CommandType> {
+            CommandType::ALL
+                .iter()
+                .map(|cmd| {
+                    let distance = std::iter::once(cmd.to_string())
+                        .chain(cmd.aliases())
+                        .map(|candidate| edit_distance(name, &candidate))
+                        .min()
+                        .unwrap();
+                    (distance, *cmd)
+                })
+                .filter(|(distance, _)| *distance <= 2 && *distance < name.chars().count())
+                .min_by_key(|(distance, _)| *distance)
+                .map(|(_, cmd)| cmd)
+        }
+
         /// Return the aliases of a command.
         pub fn aliases(&self) -> Vec<String> {
             use CommandType::*;
@@ -235,6 +947,10 @@ pub mod command {
                 Load => vec![String::from("l")],
                 Typecheck => vec![String::from("tc")],
                 Query => vec![String::from("q")],
+                Print => vec![String::from("p")],
+                SaveSession => vec![String::from("save")],
+                RestoreSession => vec![String::from("restore")],
+                Time | Doc | Export | Env | Unbind | Reset | Undo | Paste | Explain => Vec::new(),
                 Help => vec![String::from("h"), String::from("?")],
                 Exit => vec![String::from("e")],
             }
@@ -249,36 +965,161 @@ pub mod command {
                 Load => write!(f, "load"),
                 Typecheck => write!(f, "typecheck"),
                 Query => write!(f, "query"),
+                Print => write!(f, "print"),
+                Time => write!(f, "time"),
+                Doc => write!(f, "doc"),
+                Export => write!(f, "export"),
+                Env => write!(f, "env"),
+                Unbind => write!(f, "unbind"),
+                Reset => write!(f, "reset"),
+                Undo => write!(f, "undo"),
+                Paste => write!(f, "paste"),
+                Explain => write!(f, "explain"),
+                SaveSession => write!(f, "save-session"),
+                RestoreSession => write!(f, "restore-session"),
                 Help => write!(f, "help"),
                 Exit => write!(f, "exit"),
             }
         }
     }
 
-    impl FromStr for Command {
-        type Err = REPLError;
-
-        fn from_str(s: &str) -> Result<Self, Self::Err> {
-            let cmd_end = s.find(' ').unwrap_or_else(|| s.len());
-            let cmd_str: String = s.chars().take(cmd_end).collect();
-            let cmd: CommandType = cmd_str
-                .parse()
-                .map_err(|_| REPLError::UnknownCommand(cmd_str.clone()))?;
-            let arg: String = s.chars().skip(cmd_end + 1).collect();
+    impl Command {
+        /// Parse an input line starting with `:`, such as `:load lib.ncl`, which has been added
+        /// to the cache with the given id. Errors point to the command name in the line.
+        pub fn parse(file_id: FileId, input: &str) -> Result<Self, REPLError> {
+            let start = if input.starts_with(':') { 1 } else { 0 };
+            let rest = &input[start..];
+            let cmd_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let cmd_str = &rest[..cmd_end];
+            let span = RawSpan {
+                src_id: file_id,
+                start: ByteIndex(start as u32),
+                end: ByteIndex((start + cmd_end) as u32),
+            };
+
+            let cmd: CommandType = cmd_str.parse().map_err(|_| REPLError::UnknownCommand {
+                name: String::from(cmd_str),
+                span,
+                suggestion: CommandType::closest(cmd_str),
+            })?;
+            let arg = String::from(rest.get(cmd_end + 1..).unwrap_or("").trim_end());
 
             match cmd {
                 CommandType::Load => {
-                    require_arg(cmd, &arg, Some("Please provide a file to load"))?;
+                    require_arg(cmd, span, &arg, Some("Please provide a file to load"))?;
                     Ok(Command::Load(OsString::from(arg)))
                 }
                 CommandType::Typecheck => {
-                    require_arg(cmd, &arg, None)?;
+                    require_arg(cmd, span, &arg, None)?;
                     Ok(Command::Typecheck(arg))
                 }
                 CommandType::Query => {
-                    require_arg(cmd, &arg, None)?;
+                    require_arg(cmd, span, &arg, None)?;
                     Ok(Command::Query(arg))
                 }
+                CommandType::Print => {
+                    require_arg(cmd, span, &arg, None)?;
+                    Ok(Command::Print(arg))
+                }
+                CommandType::Time => {
+                    let exp = arg.trim();
+                    Ok(Command::Time(
+                        Some(String::from(exp)).filter(|exp| !exp.is_empty()),
+                    ))
+                }
+                CommandType::Doc => {
+                    require_arg(
+                        cmd,
+                        span,
+                        &arg,
+                        Some("Please provide a path, such as `lists.map`"),
+                    )?;
+                    let path = arg.trim();
+                    if is_path(path) {
+                        Ok(Command::Doc(String::from(path)))
+                    } else {
+                        let path_start = start + cmd_end + 1 + arg.len() - arg.trim_start().len();
+                        Err(REPLError::InvalidArg {
+                            cmd,
+                            msg: format!("`{}` is not a path, such as `lists.map`", path),
+                            span: RawSpan {
+                                src_id: file_id,
+                                start: ByteIndex(path_start as u32),
+                                end: ByteIndex((path_start + path.len()) as u32),
+                            },
+                        })
+                    }
+                }
+                CommandType::Export => {
+                    require_arg(cmd, span, &arg, Some("Please provide a format"))?;
+                    let exp = arg.trim_start();
+                    let format_end = exp.find(char::is_whitespace).unwrap_or(exp.len());
+                    let format_start = start + cmd_end + 1 + arg.len() - exp.len();
+                    let format =
+                        exp[..format_end]
+                            .parse()
+                            .map_err(|err| REPLError::InvalidArg {
+                                cmd,
+                                msg: format!("{}", err),
+                                span: RawSpan {
+                                    src_id: file_id,
+                                    start: ByteIndex(format_start as u32),
+                                    end: ByteIndex((format_start + format_end) as u32),
+                                },
+                            })?;
+                    let exp = exp[format_end..].trim();
+                    Ok(Command::Export(
+                        format,
+                        Some(String::from(exp)).filter(|exp| !exp.is_empty()),
+                    ))
+                }
+                CommandType::Env => Ok(Command::Env),
+                CommandType::Unbind => {
+                    require_arg(
+                        cmd,
+                        span,
+                        &arg,
+                        Some("Please provide an identifier to unbind"),
+                    )?;
+                    let id = arg.trim();
+                    let mut tokens = lexer::Lexer::new(id);
+                    match (tokens.next(), tokens.next()) {
+                        (Some(Ok((_, Token::Normal(NormalToken::Identifier(_)), _))), None) => {
+                            Ok(Command::Unbind(Ident::from(id)))
+                        }
+                        _ => {
+                            let id_start = start + cmd_end + 1 + arg.len() - arg.trim_start().len();
+                            Err(REPLError::InvalidArg {
+                                cmd,
+                                msg: format!("`{}` is not an identifier", id),
+                                span: RawSpan {
+                                    src_id: file_id,
+                                    start: ByteIndex(id_start as u32),
+                                    end: ByteIndex((id_start + id.len()) as u32),
+                                },
+                            })
+                        }
+                    }
+                }
+                CommandType::Reset => Ok(Command::Reset),
+                CommandType::Undo => Ok(Command::Undo),
+                CommandType::Paste => {
+                    let terminator = arg.trim();
+                    Ok(Command::Paste(String::from(if terminator.is_empty() {
+                        PASTE_TERMINATOR
+                    } else {
+                        terminator
+                    })))
+                }
+                CommandType::Explain => Ok(Command::Explain),
+                CommandType::SaveSession => {
+                    require_arg(cmd, span, &arg, Some("Please provide a file to save to"))?;
+                    Ok(Command::SaveSession(OsString::from(arg)))
+                }
+                CommandType::RestoreSession => {
+                    require_arg(cmd, span, &arg, Some("Please provide a file to restore"))?;
+                    Ok(Command::RestoreSession(OsString::from(arg)))
+                }
                 CommandType::Exit => Ok(Command::Exit),
                 CommandType::Help => {
                     let arg_opt = if arg.trim().is_empty() {
@@ -291,9 +1132,7 @@ pub mod command {
                 }
             }
         }
-    }
 
-    impl Command {
         pub fn typ(&self) -> CommandType {
             use Command::*;
 
@@ -301,6 +1140,18 @@ pub mod command {
                 Load(..) => CommandType::Load,
                 Typecheck(..) => CommandType::Typecheck,
                 Query(..) => CommandType::Query,
+                Print(..) => CommandType::Print,
+                Time(..) => CommandType::Time,
+                Doc(..) => CommandType::Doc,
+                Export(..) => CommandType::Export,
+                Env => CommandType::Env,
+                Unbind(..) => CommandType::Unbind,
+                Reset => CommandType::Reset,
+                Undo => CommandType::Undo,
+                Paste(..) => CommandType::Paste,
+                Explain => CommandType::Explain,
+                SaveSession(..) => CommandType::SaveSession,
+                RestoreSession(..) => CommandType::RestoreSession,
                 Help(..) => CommandType::Help,
                 Exit => CommandType::Exit,
             }
@@ -311,63 +1162,276 @@ pub mod command {
 /// Native terminal implementation of an REPL frontend using rustyline.
 #[cfg(feature = "repl")]
 pub mod rustyline_frontend {
-    use super::command::{Command, CommandType, UnknownCommandError};
+    use super::command::{self, Command, CommandType, UnknownCommandError};
     use super::*;
 
-    use crate::error::ParseError;
+    use crate::completion;
+    use crate::error::diagnostic;
+    use crate::parser::lexer::{MultiStringToken, StringToken};
+    use crate::pretty;
     use crate::program;
+    use crate::vfs::OsFileSystem;
     use ansi_term::{Colour, Style};
-    use codespan::FileId;
+    use codespan::ByteIndex;
+    use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+    use rustyline::completion::{Completer, Pair};
     use rustyline::config::OutputStreamType;
     use rustyline::error::ReadlineError;
+    use rustyline::highlight::Highlighter;
+    use rustyline::history::History;
     use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+    use rustyline::Context;
     use rustyline::{Config, EditMode, Editor};
-    use rustyline_derive::{Completer, Helper, Highlighter, Hinter};
+    use rustyline_derive::{Helper, Hinter};
+    use std::borrow::Cow;
+    use std::env;
+    use std::path::Path;
 
-    /// Validator enabling multiline input.
+    /// Validator enabling multiline input, which also completes the input.
     ///
     /// The behavior is the following:
-    /// - always end an input that starts with the command prefix `:`
+    /// - always end an input that starts with the command prefix `:`, or any line read by
+    ///   `:paste`
     /// - otherwise, try to parse the input. If an unexpected end of file error occurs, continue
     ///   the input in a new line. Otherwise, accept and end the input.
+    ///
+    /// In terminals supporting bracketed paste, a pasted text is inserted as a whole and is only
+    /// validated once Enter is pressed.
+    ///
+    /// Completion proposes the variables in scope, the fields of records, enum tags and imported
+    /// files, using the shared [completion engine](../../completion/index.html).
     //TODO: the validator throws away the result of parsing, or the parse error, when accepting an
     //input, meaning that the work is done a second time by the REPL. Validator's work could be
     //reused. This overhead shouldn't be dramatic for the typical REPL input size, though.
-    #[derive(Completer, Helper, Highlighter, Hinter)]
+    #[derive(Helper, Hinter)]
     pub struct MultilineValidator {
         parser: grammar::ExtendedTermParser,
         /// Currently the parser expect a `FileId` to fill in location information. For this
         /// validator, this may be a dummy one, since for now location information is not used.
         file_id: FileId,
+        /// The terms bound in the REPL environment, refreshed after each input.
+        globals: HashMap<Ident, RichTerm>,
+        /// Whether the lines are read by `:paste`.
+        pasting: bool,
     }
 
     impl MultilineValidator {
-        fn new(file_id: FileId) -> Self {
+        pub(super) fn new(file_id: FileId, globals: HashMap<Ident, RichTerm>) -> Self {
             MultilineValidator {
                 parser: grammar::ExtendedTermParser::new(),
                 file_id,
+                globals,
+                pasting: false,
+            }
+        }
+    }
+
+    impl Completer for MultilineValidator {
+        type Candidate = Pair;
+
+        fn complete(
+            &self,
+            line: &str,
+            pos: usize,
+            _ctx: &Context<'_>,
+        ) -> rustyline::Result<(usize, Vec<Pair>)> {
+            if !line.starts_with(':') {
+                return Ok(self.complete_expression(line, pos));
+            }
+
+            let name_end = line.find(char::is_whitespace).unwrap_or(line.len());
+            if pos <= name_end {
+                let pairs = command::complete_name(&line[1..pos])
+                    .into_iter()
+                    .map(|name| Pair {
+                        display: name.clone(),
+                        replacement: name,
+                    })
+                    .collect();
+                return Ok((1, pairs));
+            }
+
+            match command::expression_offset(line) {
+                Some(offset) if pos >= offset => {
+                    let (start, pairs) = self.complete_expression(&line[offset..], pos - offset);
+                    Ok((start + offset, pairs))
+                }
+                _ => Ok((pos, Vec::new())),
+            }
+        }
+    }
+
+    impl MultilineValidator {
+        /// Complete an expression at the given position, returning the start of the completed
+        /// word and the suggestions.
+        fn complete_expression(&self, input: &str, pos: usize) -> (usize, Vec<Pair>) {
+            let (start, completions) = completion::complete(
+                input,
+                ByteIndex(pos as u32),
+                self.file_id,
+                &self.globals,
+                &HashMap::new(),
+                Some((&OsFileSystem, Path::new("."))),
+            );
+
+            let pairs = completions
+                .into_iter()
+                .map(|completion| Pair {
+                    display: match completion.detail {
+                        Some(detail) => format!("{} : {}", completion.label, detail),
+                        None => completion.label.clone(),
+                    },
+                    replacement: completion.label,
+                })
+                .collect();
+
+            (start, pairs)
+        }
+    }
+
+    /// Colorize the input as it is typed. The name of a command is written in bold.
+    impl Highlighter for MultilineValidator {
+        fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+            if !line.starts_with(':') {
+                return Cow::Owned(highlight(line));
+            }
+
+            let name_end = line.find(char::is_whitespace).unwrap_or(line.len());
+            let name = Style::new().bold().paint(&line[..name_end]);
+            match command::expression_offset(line) {
+                Some(offset) => Cow::Owned(format!(
+                    "{}{}{}",
+                    name,
+                    &line[name_end..offset],
+                    highlight(&line[offset..])
+                )),
+                None => Cow::Owned(format!("{}{}", name, &line[name_end..])),
+            }
+        }
+
+        fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+            true
+        }
+    }
+
+    /// The style of a token, or `None` if it is written as is.
+    fn token_style(token: &Token, text: &str) -> Option<Style> {
+        use NormalToken::*;
+
+        match token {
+            Token::Str(_) | Token::MultiStr(_) => Some(Colour::Green.normal()),
+            Token::Normal(token) => match token {
+                DoubleQuote | MultiStringStart(_) | RawStringStart(_) | RawString(_) => {
+                    Some(Colour::Green.normal())
+                }
+                NumLiteral(_) | Null | True | False => Some(Colour::Yellow.normal()),
+                Dyn | Num | Bool | Str | List => Some(Colour::Cyan.normal()),
+                If | Then | Else | Forall | In | Let | Rec | And | Switch | Match | As | Fun
+                | Import | ImportStr | Merge | Default | Doc | Sensitive => {
+                    Some(Colour::Purple.normal())
+                }
+                DocComment(_) => Some(Style::new().dimmed()),
+                // Primitive operators, such as `%blame%`.
+                _ if text.len() > 1 && text.starts_with('%') && text.ends_with('%') => {
+                    Some(Colour::Purple.normal())
+                }
+                _ => None,
+            },
+        }
+    }
+
+    /// Colorize the keywords, the strings, the numbers and the comments of an input, using the
+    /// tokens of the lexer. The input is written as is after a lexical error, such as an
+    /// unterminated string.
+    pub(super) fn highlight(input: &str) -> String {
+        let comment = Style::new().dimmed();
+        let mut spans: Vec<(usize, usize, Style)> = Vec::new();
+        // The line comments are skipped by the lexer: they are found in the text between tokens.
+        let push_comments = |spans: &mut Vec<(usize, usize, Style)>, from: usize, to: usize| {
+            let mut offset = from;
+            while let Some(start) = input[offset..to].find("//") {
+                let start = offset + start;
+                let end = input[start..to].find('\n').map_or(to, |end| start + end);
+                spans.push((start, end, comment));
+                offset = end;
+            }
+        };
+
+        let mut last = 0;
+        let mut complete = true;
+        // The number of braces opened in each enclosing interpolated expression, so that the
+        // closing brace of an interpolation is written as a part of the string.
+        let mut interpolations: Vec<usize> = Vec::new();
+        for token in lexer::Lexer::new(input) {
+            let (start, token, end) = match token {
+                Ok(token) => token,
+                Err(_) => {
+                    complete = false;
+                    break;
+                }
+            };
+            push_comments(&mut spans, last, start);
+
+            let style = match (&token, interpolations.last_mut()) {
+                (Token::Str(StringToken::HashBrace), _)
+                | (Token::MultiStr(MultiStringToken::Interpolation), _) => {
+                    interpolations.push(0);
+                    token_style(&token, &input[start..end])
+                }
+                (Token::Normal(NormalToken::LBrace), Some(open)) => {
+                    *open += 1;
+                    None
+                }
+                (Token::Normal(NormalToken::RBrace), Some(0)) => {
+                    interpolations.pop();
+                    Some(Colour::Green.normal())
+                }
+                (Token::Normal(NormalToken::RBrace), Some(open)) => {
+                    *open -= 1;
+                    None
+                }
+                _ => token_style(&token, &input[start..end]),
+            };
+            if let Some(style) = style {
+                match spans.last_mut() {
+                    // Merge the consecutive tokens of a string.
+                    Some((_, prev_end, prev_style))
+                        if *prev_end == start && *prev_style == style =>
+                    {
+                        *prev_end = end
+                    }
+                    _ => spans.push((start, end, style)),
+                }
             }
+            last = end;
         }
+        if complete {
+            push_comments(&mut spans, last, input.len());
+        }
+
+        let mut result = String::with_capacity(input.len());
+        let mut written = 0;
+        for (start, end, style) in spans {
+            result.push_str(&input[written..start]);
+            result.push_str(&style.paint(&input[start..end]).to_string());
+            written = end;
+        }
+        result.push_str(&input[written..]);
+        result
     }
 
     impl Validator for MultilineValidator {
         fn validate(&self, ctx: &mut ValidationContext<'_>) -> rustyline::Result<ValidationResult> {
             let input = ctx.input();
 
-            if input.starts_with(':') || input.trim().is_empty() {
+            if self.pasting || input.starts_with(':') || input.trim().is_empty() {
                 return Ok(ValidationResult::Valid(None));
             }
 
-            let result = self
-                .parser
-                .parse(self.file_id, lexer::Lexer::new(ctx.input()))
-                .map_err(|err| ParseError::from_lalrpop(err, self.file_id));
-
-            match result {
-                Err(ParseError::UnexpectedEOF(..)) | Err(ParseError::UnmatchedCloseBrace(..)) => {
-                    Ok(ValidationResult::Invalid(None))
-                }
-                _ => Ok(ValidationResult::Valid(None)),
+            if is_incomplete(&self.parser, self.file_id, input) {
+                Ok(ValidationResult::Invalid(None))
+            } else {
+                Ok(ValidationResult::Valid(None))
             }
         }
     }
@@ -378,45 +1442,123 @@ pub mod rustyline_frontend {
         Stdlib,
     }
 
+    /// The environment variable overriding the file of the history. The history is not saved if
+    /// it is empty.
+    pub const HISTORY_VAR: &str = "NICKEL_HISTORY";
+
+    /// The default maximum number of inputs kept in the history.
+    pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+    /// The options of the frontend.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Options {
+        /// The file the history is loaded from at the start of a session and saved to after each
+        /// input, if any.
+        pub history_file: Option<PathBuf>,
+        /// The maximum number of inputs kept in the history. The oldest ones are dropped first.
+        pub history_size: usize,
+    }
+
+    /// By default, the history is saved to the file given by the `NICKEL_HISTORY` environment
+    /// variable, or to `nickel/history` in the data directory of the user.
+    impl Default for Options {
+        fn default() -> Self {
+            let history_file = match env::var_os(HISTORY_VAR) {
+                Some(path) if path.is_empty() => None,
+                Some(path) => Some(PathBuf::from(path)),
+                None => env::var_os("XDG_DATA_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| {
+                        env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+                    })
+                    .map(|data| data.join("nickel").join("history")),
+            };
+
+            Options {
+                history_file,
+                history_size: DEFAULT_HISTORY_SIZE,
+            }
+        }
+    }
+
     /// The config of rustyline's editor.
-    pub fn config() -> Config {
+    pub fn config(options: &Options) -> Config {
         Config::builder()
             .history_ignore_space(true)
+            .history_ignore_dups(true)
+            .max_history_size(options.history_size)
             .edit_mode(EditMode::Emacs)
             .output_stream(OutputStreamType::Stdout)
             .build()
     }
 
+    /// Add an input to the history, removing its previous occurrences.
+    pub(super) fn add_history_entry(history: &mut History, line: &str) {
+        if history.iter().any(|entry| entry == line) {
+            let entries: Vec<String> = history
+                .iter()
+                .filter(|entry| *entry != line)
+                .cloned()
+                .collect();
+            history.clear();
+            for entry in entries {
+                history.add(entry);
+            }
+        }
+        history.add(line);
+    }
+
+    /// Save the history to a file, creating its directory if needed. The history is a
+    /// convenience: failing to save it doesn't interrupt the session.
+    pub(super) fn save_history(history: &mut History, path: &Path) {
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = history.save(path);
+    }
+
     /// Main loop of the REPL.
-    pub fn repl() -> Result<(), InitError> {
+    pub fn repl(options: &Options) -> Result<(), InitError> {
         let mut repl = REPLImpl::new();
 
         match repl.load_stdlib() {
             Ok(()) => (),
             Err(err) => {
-                program::report(repl.cache_mut(), err);
+                report(&mut repl, err);
                 return Err(InitError::Stdlib);
             }
         }
 
-        let validator =
-            MultilineValidator::new(repl.cache_mut().add_tmp("<repl-input>", String::new()));
+        let validator = MultilineValidator::new(
+            repl.cache_mut().add_tmp("<repl-input>", String::new()),
+            repl.globals(),
+        );
 
-        let mut editor = Editor::with_config(config());
+        let mut editor = Editor::with_config(config(options));
         editor.set_helper(Some(validator));
+        if let Some(path) = &options.history_file {
+            // The history file doesn't exist before the first session.
+            let _ = editor.load_history(path);
+        }
         let prompt = Style::new().fg(Colour::Green).paint("nickel> ").to_string();
+        // Whether the statistics of every evaluation are printed.
+        let mut timing = false;
 
         loop {
             let line = editor.readline(&prompt);
 
             if let Ok(line) = line.as_ref() {
-                editor.add_history_entry(line.clone());
+                add_history_entry(editor.history_mut(), line);
+                if let Some(path) = &options.history_file {
+                    save_history(editor.history_mut(), path);
+                }
             }
 
             match line {
                 Ok(line) if line.trim().is_empty() => (),
                 Ok(line) if line.starts_with(':') => {
-                    let cmd = line.chars().skip(1).collect::<String>().parse::<Command>();
+                    let file_id = repl.cache_mut().add_tmp("<repl-command>", line.clone());
+                    let cmd = Command::parse(file_id, &line);
                     let result = match cmd {
                         Ok(Command::Load(path)) => {
                             repl.load(&path).map(|term| match term.as_ref() {
@@ -432,7 +1574,90 @@ pub mod rustyline_frontend {
                         Ok(Command::Query(exp)) => repl.query(&exp).map(|t| {
                             query_print::print_query_result(&t, query_print::Attributes::default());
                         }),
-                        Ok(Command::Help(arg)) => {
+                        Ok(Command::Print(exp)) => repl
+                            .print(&exp)
+                            .map(|rt| println!("{}", pretty::pretty(&rt, pretty::DEFAULT_WIDTH))),
+                        Ok(Command::Time(Some(exp))) => repl.time(&exp).map(|(result, stats)| {
+                            if let EvalResult::Evaluated(t) = result {
+                                println!("{}", t.shallow_repr());
+                            }
+                            print_stats(&stats);
+                        }),
+                        Ok(Command::Time(None)) => {
+                            timing = !timing;
+                            println!(
+                                "Statistics of evaluations: {}",
+                                if timing { "on" } else { "off" }
+                            );
+                            Ok(())
+                        }
+                        Ok(Command::Doc(path)) => repl.query(&path).map(|t| {
+                            query_print::print_query_result(
+                                &t,
+                                query_print::Attributes::documentation(),
+                            );
+                        }),
+                        Ok(Command::Export(format, exp)) => repl
+                            .export(format, exp.as_deref())
+                            .map(|output| println!("{}", output.trim_end())),
+                        Ok(Command::Env) => {
+                            print_env(&repl);
+                            Ok(())
+                        }
+                        Ok(Command::Unbind(id)) => {
+                            if repl.unbind(id.clone()) {
+                                println!("Unbound `{}`.", id);
+                            } else {
+                                println!("`{}` is not a toplevel binding.", id);
+                            }
+                            Ok(())
+                        }
+                        Ok(Command::Reset) => {
+                            repl.reset();
+                            println!("The environment has been reset.");
+                            Ok(())
+                        }
+                        Ok(Command::Undo) => {
+                            if repl.undo() {
+                                println!("The last change of the environment has been undone.");
+                            } else {
+                                println!("Nothing to undo.");
+                            }
+                            Ok(())
+                        }
+                        Ok(Command::Paste(terminator)) => {
+                            match read_paste(&mut editor, &terminator) {
+                                Some(input) if !input.trim().is_empty() => {
+                                    add_history_entry(editor.history_mut(), input.trim_end());
+                                    if let Some(path) = &options.history_file {
+                                        save_history(editor.history_mut(), path);
+                                    }
+                                    repl.eval(&input).map(|result| {
+                                        if let EvalResult::Evaluated(t) = result {
+                                            println!("{}", t.shallow_repr())
+                                        }
+                                    })
+                                }
+                                _ => Ok(()),
+                            }
+                        }
+                        Ok(Command::Explain) => {
+                            print_explanation(&mut repl);
+                            Ok(())
+                        }
+                        Ok(Command::SaveSession(path)) => {
+                            let session = repl.snapshot();
+                            fs::write(&path, session.render())
+                                .map(|()| {
+                                    println!(
+                                        "Saved {} input(s) of the session.",
+                                        session.entries.len()
+                                    )
+                                })
+                                .map_err(|err| Error::from(IOError::from(err)))
+                        }
+                        Ok(Command::RestoreSession(path)) => restore_session(&mut repl, &path),
+                        Ok(Command::Help(arg)) => {
                             print_help(arg.as_deref());
                             Ok(())
                         }
@@ -444,16 +1669,30 @@ pub mod rustyline_frontend {
                     };
 
                     if let Err(err) = result {
-                        program::report(repl.cache_mut(), err);
+                        report(&mut repl, err);
                     } else {
                         println!();
                     }
+
+                    refresh_globals(&mut editor, &repl);
                 }
                 Ok(line) => {
-                    match repl.eval(&line) {
-                        Ok(EvalResult::Evaluated(t)) => println!("{}\n", t.shallow_repr()),
-                        Ok(EvalResult::Bound(_)) => (),
-                        Err(err) => program::report(repl.cache_mut(), err),
+                    match repl.time(&line) {
+                        Ok((EvalResult::Evaluated(t), stats)) => {
+                            println!("{}", t.shallow_repr());
+                            if timing {
+                                print_stats(&stats);
+                            }
+                            println!();
+                        }
+                        Ok((EvalResult::Bound(_), stats))
+                        | Ok((EvalResult::BoundRec(_), stats)) => {
+                            if timing {
+                                print_stats(&stats);
+                            }
+                            refresh_globals(&mut editor, &repl)
+                        }
+                        Err(err) => report(&mut repl, err),
                     };
                 }
                 Err(ReadlineError::Eof) => {
@@ -462,12 +1701,115 @@ pub mod rustyline_frontend {
                 }
                 Err(ReadlineError::Interrupted) => (),
                 Err(err) => {
-                    program::report(
-                        repl.cache_mut(),
-                        Error::IOError(IOError(format!("{}", err))),
+                    report(&mut repl, Error::IOError(IOError(format!("{}", err))));
+                }
+            }
+        }
+    }
+
+    /// Print an error on the standard error. Failing to print it is ignored, as there is nowhere
+    /// else to report it.
+    fn report(repl: &mut REPLImpl, error: impl Into<Error>) {
+        let mut stderr = StandardStream::stderr(ColorChoice::Always);
+        let _ = program::report(&mut stderr, repl.cache_mut(), error);
+    }
+
+    /// Update the terms known to the completer after a change of the REPL environment.
+    fn refresh_globals(editor: &mut Editor<MultilineValidator>, repl: &REPLImpl) {
+        if let Some(helper) = editor.helper_mut() {
+            helper.globals = repl.globals();
+        }
+    }
+
+    /// Read lines as is, without validating them, until the terminator or the end of the input.
+    /// Return `None` if the input is interrupted.
+    fn read_paste(editor: &mut Editor<MultilineValidator>, terminator: &str) -> Option<String> {
+        println!(
+            "Paste the input, and end it with a `{}` line or Ctrl+D.",
+            terminator
+        );
+        if let Some(helper) = editor.helper_mut() {
+            helper.pasting = true;
+        }
+
+        let mut input = String::new();
+        let result = loop {
+            match editor.readline("... ") {
+                Ok(line) if push_pasted(&mut input, &line, terminator) => break Some(input),
+                Ok(_) => (),
+                Err(ReadlineError::Eof) => break Some(input),
+                Err(_) => break None,
+            }
+        };
+
+        if let Some(helper) = editor.helper_mut() {
+            helper.pasting = false;
+        }
+        result
+    }
+
+    /// Restore a session saved by `:save-session` in a file.
+    fn restore_session(repl: &mut REPLImpl, path: &OsStr) -> Result<(), Error> {
+        let source = fs::read_to_string(path).map_err(IOError::from)?;
+        let session = Session::parse(&source).map_err(|err| {
+            IOError(format!(
+                "invalid session {}: {}",
+                path.to_string_lossy(),
+                err
+            ))
+        })?;
+        repl.restore(&session)?;
+        println!(
+            "Restored {} input(s) of the session.",
+            session.entries.len()
+        );
+        Ok(())
+    }
+
+    /// Print the statistics of an evaluation.
+    fn print_stats(stats: &eval::Stats) {
+        println!("{}", Style::new().dimmed().paint(stats.to_string()));
+    }
+
+    /// Print the toplevel bindings of the environment, one per line.
+    fn print_env(repl: &REPLImpl) {
+        let bindings = repl.env();
+
+        if bindings.is_empty() {
+            println!("No binding in the environment.");
+        }
+
+        for Binding { id, types, doc } in bindings {
+            match doc {
+                Some(doc) => println!(
+                    "{} : {}  {}",
+                    id,
+                    types,
+                    Style::new().dimmed().paint(format!("// {}", doc))
+                ),
+                None => println!("{} : {}", id, types),
+            }
+        }
+    }
+
+    /// Print the explanation of the last error.
+    fn print_explanation(repl: &mut REPLImpl) {
+        match repl.explain() {
+            Some(Explanation { diagnostics, code }) => {
+                let mut stderr = StandardStream::stderr(ColorChoice::Always);
+                let _ = diagnostic::emit(&mut stderr, repl.cache_mut().files(), &diagnostics);
+
+                if let Some(code) = code {
+                    println!(
+                        "{}\n\n{}",
+                        Style::new()
+                            .bold()
+                            .paint(format!("{}: {}", code.code, code.title)),
+                        code.explanation.trim_end()
                     );
                 }
             }
+            None => println!("No error to explain."),
         }
     }
 
@@ -500,6 +1842,27 @@ pub mod rustyline_frontend {
                     print_aliases(c);
                     println!("Print the metadata attached to an attribute");
                 }
+                Ok(c @ CommandType::Print) => {
+                    println!(":{} <expression>", c);
+                    print_aliases(c);
+                    println!("Evaluate the given expression fully, including the content of records and lists, and print it");
+                }
+                Ok(c @ CommandType::Time) => {
+                    println!(":{} [expression]", c);
+                    print_aliases(c);
+                    println!("Evaluate an expression and print the time, the number of reduction steps and the peak number of thunks of its evaluation. Without an expression, switch these statistics on or off for every evaluation");
+                }
+                Ok(c @ CommandType::Doc) => {
+                    println!(":{} <path>", c);
+                    print_aliases(c);
+                    println!("Show the documentation, the type and the contracts of a value given by a path, such as `lists.map`, and the fields of a record");
+                }
+                Ok(c @ CommandType::Export) => {
+                    println!(":{} <format> [expression]", c);
+                    print_aliases(c);
+                    print!("Evaluate the given expression fully and print it in the given format, such as json, yaml or toml.");
+                    println!(" Without an expression, export the bindings of the environment which are not functions");
+                }
                 Ok(c @ CommandType::Load) => {
                     println!(":{} <file>", c);
                     print_aliases(c);
@@ -511,6 +1874,47 @@ pub mod rustyline_frontend {
                     print_aliases(c);
                     println!("Typecheck the given expression and print its top-level type");
                 }
+                Ok(c @ CommandType::Env) => {
+                    println!(":{}", c);
+                    print_aliases(c);
+                    println!("List the toplevel bindings of the environment, with their type and the first line of their documentation");
+                }
+                Ok(c @ CommandType::Unbind) => {
+                    println!(":{} <identifier>", c);
+                    print_aliases(c);
+                    println!("Remove a toplevel binding from the environment. The bindings defined before keep referring to it");
+                }
+                Ok(c @ CommandType::Reset) => {
+                    println!(":{}", c);
+                    print_aliases(c);
+                    println!("Remove all the toplevel bindings and loaded files from the environment, keeping the standard library, and start a new session");
+                }
+                Ok(c @ CommandType::Undo) => {
+                    println!(":{}", c);
+                    print_aliases(c);
+                    println!("Undo the last input which has changed the environment: a toplevel let-binding, a loaded file, an unbinding, a reset or a restored session");
+                }
+                Ok(c @ CommandType::Paste) => {
+                    println!(":{} [terminator]", c);
+                    print_aliases(c);
+                    println!("Read the following lines as is, until a line consisting of the terminator (`{}` by default) or Ctrl+D, and evaluate them as one input", command::PASTE_TERMINATOR);
+                }
+                Ok(c @ CommandType::Explain) => {
+                    println!(":{}", c);
+                    print_aliases(c);
+                    print!("Explain the last error in detail: its extended explanation, the definition of the contract which has been broken, if any,");
+                    println!(" and the bindings of the environment used by the failed input");
+                }
+                Ok(c @ CommandType::SaveSession) => {
+                    println!(":{} <file>", c);
+                    print_aliases(c);
+                    println!("Save the toplevel let-bindings and the loaded files of the session to <file>");
+                }
+                Ok(c @ CommandType::RestoreSession) => {
+                    println!(":{} <file>", c);
+                    print_aliases(c);
+                    println!("Evaluate again the bindings and load the files of a session saved to <file>");
+                }
                 Ok(c @ CommandType::Exit) => {
                     println!(":{}", c);
                     print_aliases(c);
@@ -518,19 +1922,509 @@ pub mod rustyline_frontend {
                 }
                 Err(UnknownCommandError {}) => {
                     println!("Unknown command `{}`.", arg);
-                    println!("Available commands: ? help query load typecheck");
+                    println!("Available commands: ? help query print time doc export env unbind reset undo paste load typecheck explain save-session restore-session");
                 }
             }
         } else {
-            println!("Available commands: help query load typecheck exit");
+            println!("Available commands: help query print time doc export env unbind reset undo paste load typecheck explain save-session restore-session exit");
         }
     }
 }
 
 /// Rendering of the results of a metadata query.
+/// Non-interactive frontend, running a script of REPL inputs and commands, such as:
+///
+/// ```text
+/// let server = {
+///   host = "localhost",
+///   port = 8080,
+/// }
+/// :print server.port
+/// :typecheck server
+/// ```
+///
+/// An input which is the beginning of an expression continues on the next lines, as in the
+/// interactive frontend. In the text format, the inputs are written to the output after the
+/// prompt, followed by their results and errors, without colors, such that the output of a script
+/// reads like a REPL session. In the JSON format, each input gives a JSON object on a single line,
+/// with the type, the value and the diagnostics of the input as data.
+pub mod simple_frontend {
+    use super::command::{self, Command};
+    use super::*;
+
+    use crate::error::diagnostic;
+    use crate::pretty;
+    use codespan::Files;
+    use codespan_reporting::term::termcolor::NoColor;
+    use serde_json::json;
+    use std::fmt::Write as _;
+    use std::io::Write;
+
+    /// The prompt written before the inputs.
+    const PROMPT: &str = "nickel> ";
+
+    /// The outcome of an input of a script.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Outcome {
+        /// The input has been evaluated, or the command has succeeded.
+        Success,
+        /// The input or the command has failed, with the message of the error.
+        Failure(String),
+    }
+
+    /// An input of a script, together with its outcome.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Step {
+        /// The line of the script where the input starts, starting from 1.
+        pub line: usize,
+        /// The input, which may span several lines.
+        pub input: String,
+        /// The text written to the output for this input, excluding the input itself in the
+        /// text format.
+        pub output: String,
+        pub outcome: Outcome,
+    }
+
+    impl Step {
+        pub fn is_success(&self) -> bool {
+            self.outcome == Outcome::Success
+        }
+    }
+
+    /// An input of a script.
+    enum Request {
+        Command(Command),
+        Expression(String),
+    }
+
+    /// The format of the output of a script.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum Format {
+        /// The inputs after the prompt, followed by their results and errors, as in a session.
+        Text,
+        /// One JSON object per input, as returned by
+        /// [`InputOutcome::to_json`](struct.InputOutcome.html#method.to_json).
+        Json,
+    }
+
+    /// The outcome of an input, as data to be consumed by other programs, such as editors.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct InputOutcome {
+        pub input: String,
+        /// The identifiers bound by a toplevel let, with their apparent types.
+        pub bindings: Vec<(Ident, Types)>,
+        /// The apparent type of an expression.
+        pub types: Option<Types>,
+        /// The fully evaluated value of an expression, as printed by `:print`.
+        pub value: Option<RichTerm>,
+        /// The text written by a command.
+        pub output: Option<String>,
+        /// The diagnostics of the error, if the input has failed.
+        pub diagnostics: Vec<Diagnostic>,
+    }
+
+    impl InputOutcome {
+        fn new(input: &str) -> Self {
+            InputOutcome {
+                input: String::from(input),
+                bindings: Vec::new(),
+                types: None,
+                value: None,
+                output: None,
+                diagnostics: Vec::new(),
+            }
+        }
+
+        pub fn is_success(&self) -> bool {
+            self.diagnostics.is_empty()
+        }
+
+        /// Return the outcome as a JSON object, with the fields:
+        /// - `input`: the input
+        /// - `success`: whether the input has succeeded
+        /// - `bindings`: the `name` and the `type` of each identifier bound by a toplevel let
+        /// - `type`: the apparent type of an expression, or `null`
+        /// - `value`: the value of an expression as JSON, or `null` if it can't be serialized,
+        ///   such as a function
+        /// - `text`: the value of an expression as Nickel source, or `null`
+        /// - `output`: the text written by a command, or `null`
+        /// - `diagnostics`: the diagnostics of the error, in the format of
+        ///   [`diagnostic::to_json`](../../error/diagnostic/fn.to_json.html)
+        pub fn to_json(&self, files: &Files<String>) -> serde_json::Value {
+            let bindings: Vec<serde_json::Value> = self
+                .bindings
+                .iter()
+                .map(|(id, types)| json!({"name": id.label, "type": types.to_string()}))
+                .collect();
+            let value = self.value.as_ref().and_then(|rt| {
+                serialize::validate(ExportFormat::Json, rt).ok()?;
+                serde_json::to_value(rt).ok()
+            });
+
+            json!({
+                "input": self.input,
+                "success": self.is_success(),
+                "bindings": bindings,
+                "type": self.types.as_ref().map(ToString::to_string),
+                "value": value,
+                "text": self
+                    .value
+                    .as_ref()
+                    .map(|rt| pretty::pretty(rt, pretty::DEFAULT_WIDTH)),
+                "output": self.output,
+                "diagnostics": diagnostic::to_json(files, &self.diagnostics),
+            })
+        }
+    }
+
+    /// Evaluate an expression or a toplevel let, and return its outcome as data. An expression is
+    /// evaluated fully, and its sensitive values are redacted.
+    pub fn input(repl: &mut REPLImpl, input: &str) -> InputOutcome {
+        let mut outcome = InputOutcome::new(input);
+
+        let result = if repl.is_binding(input) {
+            repl.eval(input).map(|result| {
+                let ids = match result {
+                    EvalResult::Bound(id) => vec![id],
+                    EvalResult::BoundRec(ids) => ids,
+                    EvalResult::Evaluated(_) => Vec::new(),
+                };
+                outcome.bindings = repl
+                    .env()
+                    .into_iter()
+                    .filter(|binding| ids.contains(&binding.id))
+                    .map(|binding| (binding.id, binding.types))
+                    .collect();
+            })
+        } else {
+            repl.typecheck(input).and_then(|types| {
+                outcome.types = Some(types);
+                outcome.value = Some(repl.print(input)?);
+                Ok(())
+            })
+        };
+
+        if let Err(err) = result {
+            outcome.diagnostics = program::diagnostics(repl.cache_mut(), err);
+        }
+        outcome
+    }
+
+    /// Run the script of the given file in a new REPL. See [`run`](fn.run.html).
+    pub fn run_script<W: Write>(
+        path: impl AsRef<Path>,
+        format: Format,
+        out: &mut W,
+    ) -> Result<Vec<Step>, Error> {
+        let source = fs::read_to_string(path.as_ref()).map_err(|err| {
+            IOError(format!(
+                "could not read script {}: {}",
+                path.as_ref().to_string_lossy(),
+                err
+            ))
+        })?;
+        run(
+            source.lines().map(|line| Ok(String::from(line))),
+            format,
+            out,
+        )
+    }
+
+    /// Run the lines of a script in a new REPL, write the inputs and their results to `out` in the
+    /// given format as they are evaluated, and return the outcome of each input. A failing input
+    /// doesn't stop the script, but `:exit` does. Fail if the standard library can't be loaded,
+    /// or if the script can't be read or the output written.
+    pub fn run<I, W>(lines: I, format: Format, out: &mut W) -> Result<Vec<Step>, Error>
+    where
+        I: IntoIterator<Item = std::io::Result<String>>,
+        W: Write,
+    {
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib()?;
+
+        let parser = grammar::ExtendedTermParser::new();
+        let file_id = repl.cache_mut().add_tmp("<repl-input>", String::new());
+        let mut lines = lines.into_iter().enumerate();
+        let mut next_line = || -> Result<Option<(usize, String)>, Error> {
+            match lines.next() {
+                Some((index, line)) => Ok(Some((index, line.map_err(IOError::from)?))),
+                None => Ok(None),
+            }
+        };
+        let mut steps = Vec::new();
+
+        while let Some((index, line)) = next_line()? {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with("//") {
+                continue;
+            }
+
+            let mut input = line.clone();
+            if !line.starts_with(':') {
+                while is_incomplete(&parser, file_id, &input) {
+                    match next_line()? {
+                        Some((_, line)) => {
+                            input.push('\n');
+                            input.push_str(&line);
+                        }
+                        None => break,
+                    }
+                }
+            }
+
+            // The expression to evaluate, or the command to run.
+            let request = if line.starts_with(':') {
+                let file_id = repl.cache_mut().add_tmp("<repl-command>", input.clone());
+                match Command::parse(file_id, &input) {
+                    Ok(Command::Paste(terminator)) => {
+                        let mut pasted = String::new();
+                        while let Some((_, line)) = next_line()? {
+                            input.push('\n');
+                            input.push_str(&line);
+                            if push_pasted(&mut pasted, &line, &terminator) {
+                                break;
+                            }
+                        }
+                        Ok(Request::Expression(pasted))
+                    }
+                    Ok(cmd) => Ok(Request::Command(cmd)),
+                    Err(err) => Err(Error::from(err)),
+                }
+            } else {
+                Ok(Request::Expression(input.clone()))
+            };
+            let exit = matches!(request, Ok(Request::Command(Command::Exit)));
+
+            let (output, outcome) = match format {
+                Format::Text => {
+                    let mut output = String::new();
+                    let result = match request {
+                        Ok(Request::Command(Command::Exit)) => Ok(()),
+                        Ok(Request::Command(cmd)) => run_command(&mut repl, cmd, &mut output),
+                        Ok(Request::Expression(exp)) => eval(&mut repl, &exp, &mut output),
+                        Err(err) => Err(err),
+                    };
+                    let diagnostics = match result {
+                        Ok(()) => Vec::new(),
+                        Err(err) => program::diagnostics(repl.cache_mut(), err),
+                    };
+                    output.push_str(&render_diagnostics(repl.cache_mut(), &diagnostics));
+
+                    let mut echo = input.lines();
+                    writeln!(out, "{}{}", PROMPT, echo.next().unwrap_or(""))
+                        .and_then(|()| echo.try_for_each(|line| writeln!(out, "{}", line)))
+                        .and_then(|()| write!(out, "{}", output))
+                        .map_err(IOError::from)?;
+                    (output, diagnostics)
+                }
+                Format::Json => {
+                    let outcome = match request {
+                        Ok(Request::Command(Command::Exit)) => InputOutcome::new(&input),
+                        Ok(Request::Command(cmd)) => {
+                            let mut outcome = InputOutcome::new(&input);
+                            let mut output = String::new();
+                            if let Err(err) = run_command(&mut repl, cmd, &mut output) {
+                                outcome.diagnostics = program::diagnostics(repl.cache_mut(), err);
+                            }
+                            outcome.output = Some(output);
+                            outcome
+                        }
+                        Ok(Request::Expression(exp)) => InputOutcome {
+                            input: input.clone(),
+                            ..self::input(&mut repl, &exp)
+                        },
+                        Err(err) => InputOutcome {
+                            diagnostics: program::diagnostics(repl.cache_mut(), err),
+                            ..InputOutcome::new(&input)
+                        },
+                    };
+
+                    let output = format!("{}\n", outcome.to_json(repl.cache_mut().files()));
+                    write!(out, "{}", output)
+                        .and_then(|()| out.flush())
+                        .map_err(IOError::from)?;
+                    (output, outcome.diagnostics)
+                }
+            };
+
+            steps.push(Step {
+                line: index + 1,
+                input,
+                output,
+                outcome: match outcome.first() {
+                    None => Outcome::Success,
+                    Some(diagnostic) => Outcome::Failure(String::from(diagnostic.message())),
+                },
+            });
+
+            if exit {
+                break;
+            }
+        }
+
+        Ok(steps)
+    }
+
+    /// Evaluate an input, and write its result if it isn't a binding.
+    fn eval(repl: &mut REPLImpl, input: &str, output: &mut String) -> Result<(), Error> {
+        if let EvalResult::Evaluated(t) = repl.eval(input)? {
+            let _ = writeln!(output, "{}", t.shallow_repr());
+        }
+        Ok(())
+    }
+
+    /// Run a command, other than `:exit` and `:paste`, and write its result.
+    fn run_command(repl: &mut REPLImpl, cmd: Command, output: &mut String) -> Result<(), Error> {
+        match cmd {
+            Command::Load(path) => {
+                let term = repl.load(&path)?;
+                if let Term::Record(map) | Term::RecRecord(map) = term.as_ref() {
+                    let _ = writeln!(output, "Loaded {} symbol(s) in the environment.", map.len());
+                }
+            }
+            Command::Typecheck(exp) => {
+                let _ = writeln!(output, "Ok: {}", repl.typecheck(&exp)?);
+            }
+            Command::Query(exp) => write_query(&repl.query(&exp)?, Default::default(), output),
+            Command::Time(Some(exp)) => {
+                let (result, stats) = repl.time(&exp)?;
+                if let EvalResult::Evaluated(t) = result {
+                    let _ = writeln!(output, "{}", t.shallow_repr());
+                }
+                let _ = writeln!(output, "{}", stats);
+            }
+            Command::Time(None) => {
+                let _ = writeln!(
+                    output,
+                    "The statistics of every evaluation are only available in an interactive session."
+                );
+            }
+            Command::Doc(path) => write_query(
+                &repl.query(&path)?,
+                query_print::Attributes::documentation(),
+                output,
+            ),
+            Command::Print(exp) => {
+                let rt = repl.print(&exp)?;
+                let _ = writeln!(output, "{}", pretty::pretty(&rt, pretty::DEFAULT_WIDTH));
+            }
+            Command::Export(format, exp) => {
+                let exported = repl.export(format, exp.as_deref())?;
+                let _ = writeln!(output, "{}", exported.trim_end());
+            }
+            Command::Env => {
+                for Binding { id, types, doc } in repl.env() {
+                    let _ = match doc {
+                        Some(doc) => writeln!(output, "{} : {}  // {}", id, types, doc),
+                        None => writeln!(output, "{} : {}", id, types),
+                    };
+                }
+            }
+            Command::Unbind(id) => {
+                if repl.unbind(id.clone()) {
+                    let _ = writeln!(output, "Unbound `{}`.", id);
+                } else {
+                    let _ = writeln!(output, "`{}` is not a toplevel binding.", id);
+                }
+            }
+            Command::Reset => {
+                repl.reset();
+                let _ = writeln!(output, "The environment has been reset.");
+            }
+            Command::Undo => {
+                if repl.undo() {
+                    let _ = writeln!(
+                        output,
+                        "The last change of the environment has been undone."
+                    );
+                } else {
+                    let _ = writeln!(output, "Nothing to undo.");
+                }
+            }
+            Command::Explain => match repl.explain() {
+                Some(Explanation { diagnostics, code }) => {
+                    output.push_str(&render_diagnostics(repl.cache_mut(), &diagnostics));
+                    if let Some(code) = code {
+                        let _ = writeln!(
+                            output,
+                            "{}: {}\n\n{}",
+                            code.code,
+                            code.title,
+                            code.explanation.trim_end()
+                        );
+                    }
+                }
+                None => {
+                    let _ = writeln!(output, "No error to explain.");
+                }
+            },
+            Command::SaveSession(path) => {
+                let session = repl.snapshot();
+                fs::write(&path, session.render()).map_err(IOError::from)?;
+                let _ = writeln!(
+                    output,
+                    "Saved {} input(s) of the session.",
+                    session.entries.len()
+                );
+            }
+            Command::RestoreSession(path) => {
+                let source = fs::read_to_string(&path).map_err(IOError::from)?;
+                let session = Session::parse(&source).map_err(|err| {
+                    IOError(format!(
+                        "invalid session {}: {}",
+                        path.to_string_lossy(),
+                        err
+                    ))
+                })?;
+                repl.restore(&session)?;
+                let _ = writeln!(
+                    output,
+                    "Restored {} input(s) of the session.",
+                    session.entries.len()
+                );
+            }
+            Command::Help(_) => {
+                let names: Vec<String> = command::CommandType::ALL
+                    .iter()
+                    .map(|cmd| cmd.to_string())
+                    .collect();
+                let _ = writeln!(output, "Available commands: {}", names.join(" "));
+            }
+            Command::Exit | Command::Paste(_) => unreachable!("handled by the script runner"),
+        }
+        Ok(())
+    }
+
+    /// Write the metadata of the result of a query selected by `attrs`, as Markdown text.
+    fn write_query(term: &Term, attrs: query_print::Attributes, output: &mut String) {
+        match term {
+            Term::MetaValue(meta) => {
+                let metadata = query_print::metadata_markdown(meta, attrs);
+                if metadata.is_empty() {
+                    let _ = writeln!(output, "Requested metadata were not found for this value.");
+                } else {
+                    output.push_str(&metadata);
+                }
+            }
+            _ => {
+                let _ = writeln!(output, "No metadata found for this value.");
+            }
+        }
+    }
+
+    /// Render diagnostics as text, without colors.
+    fn render_diagnostics(cache: &mut Cache, diagnostics: &[Diagnostic]) -> String {
+        let mut buffer = Vec::new();
+        // Writing to a buffer can't fail.
+        diagnostic::emit(&mut NoColor::new(&mut buffer), cache.files(), diagnostics).unwrap();
+        String::from_utf8_lossy(&buffer).into_owned()
+    }
+}
+
 pub mod query_print {
     use crate::identifier::Ident;
-    use crate::term::{MergePriority, MetaValue, Term};
+    use crate::term::{AnnotKind, Contract, MergePriority, MetaValue, RichTerm, Term, REDACTED};
+    use std::cell::RefCell;
 
     /// A query printer. The implementation may differ depending on the activation of markdown
     /// support.
@@ -552,6 +2446,12 @@ pub mod query_print {
 
     pub struct SimpleRenderer {}
 
+    /// A query printer writing Markdown text to a buffer instead of the terminal.
+    #[derive(Default)]
+    pub struct MarkdownWriter {
+        buffer: RefCell<String>,
+    }
+
     /// Helper to render the result of the `query` sub-command without markdown support.
     impl QueryPrinter for SimpleRenderer {
         fn print_metadata(&self, attr: &str, value: &str) {
@@ -579,6 +2479,48 @@ pub mod query_print {
         }
     }
 
+    impl MarkdownWriter {
+        pub fn new() -> Self {
+            MarkdownWriter {
+                buffer: RefCell::new(String::new()),
+            }
+        }
+
+        /// Return the text written so far.
+        pub fn into_string(self) -> String {
+            self.buffer.into_inner()
+        }
+    }
+
+    impl QueryPrinter for MarkdownWriter {
+        fn print_metadata(&self, attr: &str, value: &str) {
+            self.buffer
+                .borrow_mut()
+                .push_str(&format!("* **{}**: `{}`\n", attr, value));
+        }
+
+        fn print_doc(&self, content: &str) {
+            let mut buffer = self.buffer.borrow_mut();
+            if !buffer.is_empty() {
+                buffer.push('\n');
+            }
+            buffer.push_str(content);
+            buffer.push('\n');
+        }
+
+        fn print_fields<'a, I>(&self, fields: I)
+        where
+            I: Iterator<Item = &'a Ident>,
+        {
+            let mut buffer = self.buffer.borrow_mut();
+            buffer.push_str("\n**Available fields**\n\n");
+
+            for field in fields {
+                buffer.push_str(&format!("* `{}`\n", field));
+            }
+        }
+    }
+
     #[cfg(feature = "markdown")]
     impl MarkdownRenderer {
         pub fn new() -> Self {
@@ -642,16 +2584,30 @@ pub mod query_print {
     #[derive(Clone, Copy, Eq, PartialEq)]
     pub struct Attributes {
         pub doc: bool,
+        pub types: bool,
         pub contract: bool,
         pub default: bool,
         pub value: bool,
     }
 
+    impl Attributes {
+        /// The attributes shown by `:doc`: the documentation, the type and the contracts, but not
+        /// the value.
+        pub fn documentation() -> Self {
+            Attributes {
+                default: false,
+                value: false,
+                ..Default::default()
+            }
+        }
+    }
+
     // By default, show all available metadata.
     impl Default for Attributes {
         fn default() -> Self {
             Attributes {
                 doc: true,
+                types: true,
                 contract: true,
                 default: true,
                 value: true,
@@ -674,6 +2630,86 @@ pub mod query_print {
         print_query_result_(term, selected_attrs, &renderer)
     }
 
+    /// Render the metadata of a metavalue selected by `selected_attrs` as Markdown text, as
+    /// printed by [`print_query_result`](./fn.print_query_result.html) but without the fields of
+    /// the value. Return an empty string if no attribute was found.
+    pub fn metadata_markdown(meta: &MetaValue, selected_attrs: Attributes) -> String {
+        let writer = MarkdownWriter::new();
+        print_metadata(meta, selected_attrs, &writer);
+        writer.into_string()
+    }
+
+    /// Print the metadata of a metavalue selected by `selected_attrs`. Return `true` if at least
+    /// one attribute was printed.
+    fn print_metadata<R: QueryPrinter>(
+        meta: &MetaValue,
+        selected_attrs: Attributes,
+        renderer: &R,
+    ) -> bool {
+        let mut found = false;
+        // We use the original user-written type stored in the label. Using `ctr.types`
+        // instead is unreadable most of the time, as it can have been altered by
+        // closurizations or other run-time rewriting
+        let (types, ctrs): (Vec<&Contract>, Vec<&Contract>) = meta
+            .annotations()
+            .into_iter()
+            .partition(|ctr| ctr.kind == AnnotKind::Type);
+
+        if !types.is_empty() && selected_attrs.types {
+            let types: Vec<String> = types
+                .iter()
+                .map(|ctr| ctr.label.types.to_string())
+                .collect();
+            renderer.print_metadata("type", &types.join(","));
+            found = true;
+        }
+
+        if !ctrs.is_empty() && selected_attrs.contract {
+            let ctrs: Vec<String> = ctrs.iter().map(|ctr| ctr.label.types.to_string()).collect();
+            renderer.print_metadata("contract", &ctrs.join(","));
+            found = true;
+        }
+
+        // Sensitive values are never printed.
+        let value_repr = |t: &RichTerm| {
+            if meta.sensitive {
+                String::from(REDACTED)
+            } else {
+                t.as_ref().shallow_repr()
+            }
+        };
+
+        match meta {
+            MetaValue {
+                priority: MergePriority::Default,
+                value: Some(t),
+                ..
+            } if selected_attrs.default => {
+                renderer.print_metadata("default", &value_repr(t));
+                found = true;
+            }
+            MetaValue {
+                priority: MergePriority::Normal,
+                value: Some(t),
+                ..
+            } if selected_attrs.value => {
+                renderer.print_metadata("value", &value_repr(t));
+                found = true;
+            }
+            _ => (),
+        }
+
+        match meta.doc {
+            Some(ref s) if selected_attrs.doc => {
+                renderer.print_doc(s);
+                found = true;
+            }
+            _ => (),
+        }
+
+        found
+    }
+
     /// Print the result of a metadata query, which is a "weakly" evaluated term (see
     /// [`eval_meta`](../../eval/fn.eval_meta.html) and [`query`](../../program/fn.query.html)).
     fn print_query_result_<R: QueryPrinter>(term: &Term, selected_attrs: Attributes, renderer: &R) {
@@ -693,47 +2729,7 @@ pub mod query_print {
 
         match term {
             Term::MetaValue(meta) => {
-                let mut found = false;
-                if !meta.contracts.is_empty() && selected_attrs.contract {
-                    let ctrs: Vec<String> = meta
-                        .contracts
-                        .iter()
-                        // We use the original user-written type stored in the label. Using
-                        // `ctr.types` instead is unreadable most of the time, as it can have been
-                        // altered by closurizations or other run-time rewriting
-                        .map(|ctr| ctr.label.types.to_string())
-                        .collect();
-                    renderer.print_metadata("contract", &ctrs.join(","));
-                    found = true;
-                }
-
-                match &meta {
-                    MetaValue {
-                        priority: MergePriority::Default,
-                        value: Some(t),
-                        ..
-                    } if selected_attrs.default => {
-                        renderer.print_metadata("default", &t.as_ref().shallow_repr());
-                        found = true;
-                    }
-                    MetaValue {
-                        priority: MergePriority::Normal,
-                        value: Some(t),
-                        ..
-                    } if selected_attrs.value => {
-                        renderer.print_metadata("value", &t.as_ref().shallow_repr());
-                        found = true;
-                    }
-                    _ => (),
-                }
-
-                match meta.doc {
-                    Some(ref s) if selected_attrs.doc => {
-                        renderer.print_doc(s);
-                        found = true;
-                    }
-                    _ => (),
-                }
+                let found = print_metadata(meta, selected_attrs, renderer);
 
                 if !found {
                     println!("Requested metadata were not found for this value.");
@@ -759,3 +2755,680 @@ pub mod query_print {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::command::{Command, CommandType};
+    use super::*;
+    use crate::error::diagnostic::{Edit, Fix};
+    use crate::position::RawSpan;
+    use codespan::{ByteIndex, Files};
+
+    #[test]
+    fn commands() {
+        let mut files = Files::new();
+        let mut parse = |input: &str| {
+            let file_id = files.add("<repl-command>", String::from(input));
+            let span = move |start, end| RawSpan {
+                src_id: file_id,
+                start: ByteIndex(start),
+                end: ByteIndex(end),
+            };
+            (Command::parse(file_id, input), span)
+        };
+
+        let (cmd, _) = parse(":l lib.ncl");
+        assert_eq!(cmd, Ok(Command::Load(OsString::from("lib.ncl"))));
+        let (cmd, _) = parse(":env");
+        assert_eq!(cmd, Ok(Command::Env));
+        let (cmd, _) = parse(":undo");
+        assert_eq!(cmd, Ok(Command::Undo));
+        let (cmd, _) = parse(":time");
+        assert_eq!(cmd, Ok(Command::Time(None)));
+        let (cmd, _) = parse(":time 1 + 1");
+        assert_eq!(cmd, Ok(Command::Time(Some(String::from("1 + 1")))));
+        let (cmd, _) = parse(":doc lists.map ");
+        assert_eq!(cmd, Ok(Command::Doc(String::from("lists.map"))));
+        assert!(parse(":doc lists.").0.is_err());
+        assert!(parse(":doc").0.is_err());
+        let (cmd, _) = parse(":paste");
+        assert_eq!(cmd, Ok(Command::Paste(String::from(":end"))));
+        let (cmd, _) = parse(":paste EOF ");
+        assert_eq!(cmd, Ok(Command::Paste(String::from("EOF"))));
+        let (cmd, _) = parse(":unbind  port ");
+        assert_eq!(cmd, Ok(Command::Unbind(Ident::from("port"))));
+        let (cmd, span) = parse(":unbind 1 + x");
+        assert_eq!(
+            cmd,
+            Err(REPLError::InvalidArg {
+                cmd: CommandType::Unbind,
+                msg: String::from("`1 + x` is not an identifier"),
+                span: span(8, 13),
+            })
+        );
+        let (cmd, _) = parse(":help query");
+        assert_eq!(cmd, Ok(Command::Help(Some(String::from("query")))));
+
+        let (cmd, span) = parse(":typecheck");
+        assert_eq!(
+            cmd,
+            Err(REPLError::MissingArg {
+                cmd: CommandType::Typecheck,
+                msg_opt: None,
+                span: span(1, 10),
+            })
+        );
+
+        let (cmd, span) = parse(":laod lib.ncl");
+        assert_eq!(
+            cmd,
+            Err(REPLError::UnknownCommand {
+                name: String::from("laod"),
+                span: span(1, 5),
+                suggestion: Some(CommandType::Load),
+            })
+        );
+        assert_eq!(
+            Error::from(cmd.unwrap_err()).fixes(),
+            vec![Fix {
+                message: String::from("use `:load`"),
+                edits: vec![Edit {
+                    span: span(1, 5),
+                    replacement: String::from("load"),
+                }],
+            }]
+        );
+
+        assert_eq!(CommandType::closest("tcc"), Some(CommandType::Typecheck));
+        assert_eq!(CommandType::closest("quit"), Some(CommandType::Exit));
+        assert_eq!(CommandType::closest("foobar"), None);
+        assert_eq!(CommandType::closest("x"), None);
+        assert_eq!(parse(":explain").0, Ok(Command::Explain));
+        assert_eq!(
+            parse(":save-session s.json").0,
+            Ok(Command::SaveSession(OsString::from("s.json")))
+        );
+        assert!(parse(":restore-session").0.is_err());
+        assert_eq!(
+            parse(":restore s.json").0,
+            Ok(Command::RestoreSession(OsString::from("s.json")))
+        );
+        assert_eq!(
+            parse(":p {a = 1}").0,
+            Ok(Command::Print(String::from("{a = 1}")))
+        );
+        assert!(parse(":print").0.is_err());
+
+        assert_eq!(
+            command::complete_name("res"),
+            vec!["reset", "restore-session"]
+        );
+        assert_eq!(command::complete_name("").len(), CommandType::ALL.len());
+        assert_eq!(command::expression_offset(":p  x.y"), Some(4));
+        assert_eq!(command::expression_offset(":doc lists.m"), Some(5));
+        assert_eq!(command::expression_offset(":export json x"), Some(13));
+        assert_eq!(command::expression_offset(":export json"), None);
+        assert_eq!(command::expression_offset(":load x"), None);
+        assert_eq!(command::expression_offset(":print"), None);
+
+        assert_eq!(
+            parse(":export json").0,
+            Ok(Command::Export(ExportFormat::Json, None))
+        );
+        assert_eq!(
+            parse(":export  yaml {a = 1} ").0,
+            Ok(Command::Export(
+                ExportFormat::Yaml,
+                Some(String::from("{a = 1}"))
+            ))
+        );
+        let (cmd, span) = parse(":export jsn x");
+        assert_eq!(
+            cmd,
+            Err(REPLError::InvalidArg {
+                cmd: CommandType::Export,
+                msg: String::from("unsupported export format jsn"),
+                span: span(8, 11),
+            })
+        );
+    }
+
+    #[test]
+    fn sessions() {
+        let dir = std::env::temp_dir().join(format!("nickel-session-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let lib = dir.join("lib.ncl");
+        fs::write(&lib, "{base = 8000}").unwrap();
+
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        repl.load(&lib).unwrap();
+        repl.eval("let port = base + 80").unwrap();
+        repl.eval("port + 1").unwrap();
+        assert!(repl.eval("let broken = 1 + \"a\"").is_ok());
+        assert!(repl.eval("let x = ").is_err());
+        repl.eval("let rec f = fun n => if n == 0 then 0 else f (n - 1)")
+            .unwrap();
+
+        let session = Session::parse(&repl.snapshot().render()).unwrap();
+        assert_eq!(
+            session.entries,
+            vec![
+                SessionEntry::Load(fs::canonicalize(&lib).unwrap()),
+                SessionEntry::Input(String::from("let port = base + 80")),
+                SessionEntry::Input(String::from("let broken = 1 + \"a\"")),
+                SessionEntry::Input(String::from(
+                    "let rec f = fun n => if n == 0 then 0 else f (n - 1)"
+                )),
+            ]
+        );
+
+        let mut restored = REPLImpl::new();
+        restored.load_stdlib().unwrap();
+        restored.restore(&session).unwrap();
+        match restored.eval("f 3 + port") {
+            Ok(EvalResult::Evaluated(Term::Num(n))) => assert_eq!(n, 8080.0),
+            _ => panic!("expected a number"),
+        }
+        assert_eq!(restored.snapshot(), session);
+
+        fs::remove_file(&lib).unwrap();
+        assert!(REPLImpl::new().restore(&session).is_err());
+        assert!(Session::parse("{\"entries\": [{\"run\": \"x\"}]}").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print() {
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        repl.eval(
+            "let server = {host = \"localhost\", ports = lists.map (fun p => p + 1) [79, 442]}",
+        )
+        .unwrap();
+
+        let printed = |repl: &mut REPLImpl, exp: &str| {
+            repl.print(exp)
+                .map(|rt| crate::pretty::pretty(&rt, crate::pretty::DEFAULT_WIDTH))
+        };
+        assert_eq!(
+            printed(&mut repl, "{web = server, replicas | default | Num = 2}").unwrap(),
+            "{replicas = 2, web = {host = \"localhost\", ports = [80, 443]}}"
+        );
+        assert_eq!(
+            printed(&mut repl, "{a | sensitive = \"hunter2\", b = [1 + 1]}").unwrap(),
+            "{a = \"<redacted>\", b = [2]}"
+        );
+        assert_eq!(
+            printed(&mut repl, "{a = \"hunter2\"} | sensitive").unwrap(),
+            "\"<redacted>\""
+        );
+        assert!(printed(&mut repl, "{a = 1 + \"a\"}").is_err());
+        assert!(repl.explain().is_some());
+    }
+
+    #[test]
+    fn export() {
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        assert_eq!(repl.export(ExportFormat::Json, None).unwrap(), "{}");
+
+        repl.eval("let port = 8000 + 80").unwrap();
+        repl.eval("let hosts = [\"a\", \"b\"]").unwrap();
+        repl.eval("let token | sensitive = \"hunter2\"").unwrap();
+        repl.eval("let f = fun x => x").unwrap();
+        repl.eval("let rec g = fun n => n").unwrap();
+        assert_eq!(
+            repl.export(ExportFormat::Json, None)
+                .unwrap()
+                .split_whitespace()
+                .collect::<String>(),
+            r#"{"hosts":["a","b"],"port":8080,"token":"<redacted>"}"#
+        );
+
+        assert_eq!(
+            repl.export(ExportFormat::Toml, Some("{server.port = port}"))
+                .unwrap(),
+            "[server]\nport = 8080\n"
+        );
+        assert!(repl.export(ExportFormat::Json, Some("{a = f}")).is_err());
+        assert!(repl.export(ExportFormat::Json, Some("1 + \"a\"")).is_err());
+        assert!(repl.explain().is_some());
+    }
+
+    #[test]
+    fn env() {
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        assert_eq!(repl.env(), Vec::new());
+
+        repl.eval("let port : Num = 8080").unwrap();
+        repl.eval("let host | doc \"\n  The host.\n  Defaults to localhost.\" = \"localhost\"")
+            .unwrap();
+        repl.eval("let rec f = fun x => x").unwrap();
+        let env: Vec<String> = repl
+            .env()
+            .into_iter()
+            .map(|Binding { id, types, doc }| match doc {
+                Some(doc) => format!("{} : {} // {}", id, types, doc),
+                None => format!("{} : {}", id, types),
+            })
+            .collect();
+        assert_eq!(
+            env,
+            vec!["f : Dyn", "host : Str // The host.", "port : Num"]
+        );
+    }
+
+    #[test]
+    fn unbind() {
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        repl.eval("let x = 1").unwrap();
+        repl.eval("let y = x + 1").unwrap();
+
+        assert!(repl.unbind(Ident::from("x")));
+        assert!(!repl.unbind(Ident::from("x")));
+        assert!(!repl.unbind(Ident::from("lists")));
+        assert!(repl.eval("x").is_err());
+        assert!(repl.typecheck("x").is_err());
+        // The bindings defined before keep referring to the removed one.
+        match repl.eval("y") {
+            Ok(EvalResult::Evaluated(Term::Num(n))) => assert_eq!(n, 2.0),
+            _ => panic!("expected a number"),
+        }
+
+        let mut restored = REPLImpl::new();
+        restored.load_stdlib().unwrap();
+        restored.restore(&repl.snapshot()).unwrap();
+        assert_eq!(restored.env(), repl.env());
+
+        repl.reset();
+        assert_eq!(repl.env(), Vec::new());
+        assert_eq!(repl.snapshot(), Session::default());
+        assert!(repl.eval("y").is_err());
+        assert!(repl.eval("lists").is_ok());
+    }
+
+    #[test]
+    fn undo() {
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        assert!(!repl.undo());
+
+        repl.eval("let x = 1").unwrap();
+        repl.eval("let x = \"shadowed\"").unwrap();
+        // Neither expressions nor failed inputs can be undone, and expressions don't copy the
+        // environment.
+        repl.eval("x").unwrap();
+        assert_eq!(repl.checkpoints.len(), 2);
+        assert!(repl.eval("let y = (").is_err());
+        assert!(repl.undo());
+        match repl.eval("x") {
+            Ok(EvalResult::Evaluated(Term::Num(n))) => assert_eq!(n, 1.0),
+            _ => panic!("expected a number"),
+        }
+        assert_eq!(repl.typecheck("x").unwrap(), Types(AbsType::Num()));
+
+        repl.unbind(Ident::from("x"));
+        repl.reset();
+        assert!(repl.undo());
+        assert!(repl.eval("x").is_err());
+        assert!(repl.undo());
+        assert!(repl.eval("x").is_ok());
+
+        // A restored session is undone as a whole.
+        let session = Session {
+            entries: vec![
+                SessionEntry::Input(String::from("let a = 1")),
+                SessionEntry::Input(String::from("let b = a")),
+            ],
+        };
+        repl.restore(&session).unwrap();
+        assert!(repl.undo());
+        assert!(repl.eval("a").is_err());
+        assert!(repl.eval("b").is_err());
+        assert_eq!(repl.snapshot().entries.len(), 1);
+
+        let id = repl.checkpoint();
+        repl.eval("let z = 1").unwrap();
+        assert!(repl.rollback(id));
+        assert!(repl.eval("z").is_err());
+        assert!(!repl.rollback(id));
+        assert!(repl.undo());
+        assert!(!repl.undo());
+        assert_eq!(repl.env(), Vec::new());
+    }
+
+    #[test]
+    fn paste() {
+        let mut input = String::new();
+        assert!(!push_pasted(&mut input, "let server = {", ":end"));
+        assert!(!push_pasted(&mut input, "  host = \"localhost\",", ":end"));
+        // A bracketed paste gives several lines at once.
+        assert!(push_pasted(
+            &mut input,
+            "  port = 80,\n}\n :end \nignored",
+            ":end"
+        ));
+        assert_eq!(
+            input,
+            "let server = {\n  host = \"localhost\",\n  port = 80,\n}\n"
+        );
+
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        repl.eval(&input).unwrap();
+        assert!(repl.print("server.port").is_ok());
+    }
+
+    #[test]
+    fn script() {
+        use simple_frontend::{run, Format, Outcome};
+
+        let script = "// Comments and empty lines are skipped.\n\
+                      let port = {\n  value = 8080,\n}.value\n\n\
+                      port + 1\n\
+                      :tc port\n\
+                      port ++ \"a\"\n\
+                      :paste\n[\n1]\n:end\n\
+                      :exit\n\
+                      port";
+        let mut out = Vec::new();
+        let lines = script.lines().map(|line| Ok(String::from(line)));
+        let steps = run(lines, Format::Text, &mut out).unwrap();
+
+        let summary: Vec<(usize, bool)> = steps
+            .iter()
+            .map(|step| (step.line, step.is_success()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (2, true),
+                (6, true),
+                (7, true),
+                (8, false),
+                (9, true),
+                (13, true)
+            ]
+        );
+        assert_eq!(steps[0].input, "let port = {\n  value = 8080,\n}.value");
+        assert_eq!(steps[1].output, "8081\n");
+        assert_eq!(steps[2].output, "Ok: Dyn\n");
+        assert!(matches!(steps[3].outcome, Outcome::Failure(_)));
+        assert_eq!(steps[4].output, "[ ... ]\n");
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(
+            "nickel> let port = {\n  value = 8080,\n}.value\nnickel> port + 1\n8081\n"
+        ));
+        assert!(out.contains("error"));
+        assert!(out.ends_with("nickel> :exit\n"));
+    }
+
+    #[test]
+    fn time() {
+        let mut repl = REPLImpl::new();
+        let (result, stats) = repl.time("let x = 2 in x * x").unwrap();
+        assert!(matches!(result, EvalResult::Evaluated(Term::Num(n)) if n == 4.0));
+        assert!(stats.steps > 0);
+        let (result, _) = repl.time("let y = 1").unwrap();
+        assert!(matches!(result, EvalResult::Bound(_)));
+        assert!(repl.time("y + \"a\"").is_err());
+    }
+
+    #[test]
+    fn doc() {
+        use simple_frontend::{run, Format};
+
+        let lines = vec![
+            "let port | doc \"The port.\" | Num = 80",
+            ":doc port",
+            ":doc lists.map",
+        ];
+        let mut out = Vec::new();
+        let steps = run(
+            lines.into_iter().map(|line| Ok(String::from(line))),
+            Format::Text,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(steps[1].output, "* **contract**: `Num`\n\nThe port.\n");
+        assert!(steps[2].output.contains("applies the function `f`"));
+        assert!(!steps[2].output.contains("**value**"));
+    }
+
+    #[test]
+    fn json_outcomes() {
+        use serde_json::json;
+        use simple_frontend::{input, run, Format};
+
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        let outcome = input(&mut repl, "let rec f = fun x => x and g = 1");
+        assert_eq!(
+            outcome.bindings,
+            vec![
+                (Ident::from("f"), Types(AbsType::Dyn())),
+                (Ident::from("g"), Types(AbsType::Num()))
+            ]
+        );
+        let outcome = input(&mut repl, "{a = g, b = [\"x\"]}");
+        assert!(outcome.is_success());
+        let json = outcome.to_json(repl.cache_mut().files());
+        assert_eq!(json["value"], json!({"a": 1, "b": ["x"]}));
+        assert_eq!(json["text"], json!("{a = 1, b = [\"x\"]}"));
+        assert_eq!(
+            input(&mut repl, "f").to_json(repl.cache_mut().files())["value"],
+            json!(null)
+        );
+        let outcome = input(&mut repl, "g + \"a\"");
+        assert!(!outcome.is_success());
+        assert_eq!(
+            outcome.to_json(repl.cache_mut().files())["diagnostics"][0]["code"],
+            json!("N0402")
+        );
+
+        let lines = vec!["let x = 1", ":env", "x +", "  1", ":unknown"];
+        let mut out = Vec::new();
+        let steps = run(
+            lines.into_iter().map(|line| Ok(String::from(line))),
+            Format::Json,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(steps.len(), 4);
+        assert!(!steps[3].is_success());
+        let out: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(out[1]["output"], json!("x : Num\n"));
+        assert_eq!(out[2]["input"], json!("x +\n  1"));
+        assert_eq!(out[2]["value"], json!(2));
+        assert_eq!(out[3]["success"], json!(false));
+    }
+
+    #[cfg(feature = "repl")]
+    #[test]
+    fn history() {
+        use rustyline::history::History;
+        use rustyline_frontend::{add_history_entry, config, save_history, Options};
+
+        let dir = std::env::temp_dir().join(format!("nickel-history-{}", std::process::id()));
+        let path = dir.join("nickel").join("history");
+        let options = Options {
+            history_file: Some(path.clone()),
+            history_size: 3,
+        };
+
+        let mut history = History::with_config(config(&options));
+        for line in &[
+            "let x = 1",
+            "x + 1",
+            "let x = 1",
+            ":p x",
+            "x + 1",
+            " secret",
+        ] {
+            add_history_entry(&mut history, line);
+        }
+        save_history(&mut history, &path);
+
+        let mut loaded = History::with_config(config(&options));
+        loaded.load(&path).unwrap();
+        let entries: Vec<&String> = loaded.iter().collect();
+        assert_eq!(entries, vec!["let x = 1", ":p x", "x + 1"]);
+
+        add_history_entry(&mut loaded, "let y = 2");
+        assert_eq!(loaded.len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "repl")]
+    #[test]
+    fn completion() {
+        use rustyline::completion::Completer;
+        use rustyline::history::History;
+        use rustyline::Context;
+        use rustyline_frontend::MultilineValidator;
+
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        repl.eval("let server = {host = \"a\", port = 80}").unwrap();
+        let file_id = repl.cache_mut().add_tmp("<repl-input>", String::new());
+        let validator = MultilineValidator::new(file_id, repl.globals());
+
+        let history = History::new();
+        let complete = |line: &str| {
+            let (start, pairs) = validator
+                .complete(line, line.len(), &Context::new(&history))
+                .unwrap();
+            let mut labels: Vec<String> = pairs.into_iter().map(|p| p.replacement).collect();
+            labels.sort();
+            (start, labels)
+        };
+
+        assert_eq!(complete("server.po"), (7, vec![String::from("port")]));
+        assert!(complete("ser").1.contains(&String::from("server")));
+        assert!(complete("lis").1.contains(&String::from("lists")));
+        assert_eq!(complete(":pr"), (1, vec![String::from("print")]));
+        assert_eq!(
+            complete(":print server.h"),
+            (14, vec![String::from("host")])
+        );
+        assert_eq!(
+            complete(":export json server.h"),
+            (20, vec![String::from("host")])
+        );
+        assert_eq!(complete(":load ser"), (9, Vec::new()));
+    }
+
+    #[cfg(feature = "repl")]
+    #[test]
+    fn highlight() {
+        use ansi_term::{Colour, Style};
+        use rustyline_frontend::highlight;
+
+        let input = "let x = \"a#{y}\" in // done\n  [1, true, x | Num] ## doc";
+        assert_eq!(
+            highlight(input),
+            format!(
+                "{} x = {}y{} {} {}\n  [{}, {}, x | {}] {}",
+                Colour::Purple.paint("let"),
+                Colour::Green.paint("\"a#{"),
+                Colour::Green.paint("}\""),
+                Colour::Purple.paint("in"),
+                Style::new().dimmed().paint("// done"),
+                Colour::Yellow.paint("1"),
+                Colour::Yellow.paint("true"),
+                Colour::Cyan.paint("Num"),
+                Style::new().dimmed().paint("## doc"),
+            )
+        );
+        assert_eq!(
+            highlight("1 + \"unterminated"),
+            format!(
+                "{} + {}",
+                Colour::Yellow.paint("1"),
+                Colour::Green.paint("\"unterminated")
+            )
+        );
+    }
+
+    #[test]
+    fn explain() {
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        assert!(repl.explain().is_none());
+
+        repl.eval("let x = 1").unwrap();
+        repl.eval("let Port = fun l v => if v > 1000 then v else %blame% l")
+            .unwrap();
+        assert!(repl.eval("x + 1 | #Port").is_err());
+        repl.eval("x").unwrap();
+
+        let Explanation { diagnostics, code } = repl.explain().unwrap();
+        assert_eq!(code.map(|code| code.code), Some("N0401"));
+        let notes: Vec<(&str, Vec<&str>)> = diagnostics
+            .iter()
+            .skip_while(|d| d.message() != "the contract is defined here")
+            .map(|d| {
+                (
+                    d.message(),
+                    d.labels().iter().map(|l| l.message()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                ("the contract is defined here", vec![""]),
+                (
+                    "bindings of the REPL used by the input",
+                    vec!["x = 1", "Port = <func>"]
+                ),
+            ]
+        );
+
+        assert!(repl.typecheck("lists.map 1 : Num").is_err());
+        let Explanation { diagnostics, .. } = repl.explain().unwrap();
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.message() != "bindings of the REPL used by the input"));
+    }
+
+    #[test]
+    fn sensitive() {
+        let mut repl = REPLImpl::new();
+        repl.load_stdlib().unwrap();
+        repl.eval(r#"let r = {password | sensitive = "hunter2", port = 80}"#)
+            .unwrap();
+
+        let shown = |result: Result<EvalResult, Error>| match result {
+            Ok(EvalResult::Evaluated(t)) => t.shallow_repr(),
+            _ => panic!("expected a term"),
+        };
+        assert_eq!(
+            shown(repl.eval("r.password")),
+            "<sensitive,value=<redacted>>"
+        );
+        assert_eq!(shown(repl.eval("r.port")), "80");
+
+        let error = match repl.eval(r#"({secret | Num | sensitive = "hunter2"}).secret"#) {
+            Err(error) => error,
+            _ => panic!("expected a blame error"),
+        };
+        let diagnostics = program::diagnostics(&mut repl.cache, error);
+        assert!(diagnostics[0]
+            .notes()
+            .iter()
+            .any(|note| note.contains("redacted")));
+        assert!(diagnostics[0]
+            .labels()
+            .iter()
+            .all(|l| !l.message().starts_with("evaluated to")));
+    }
+}
@@ -0,0 +1,329 @@
+//! Conversion of diagnostics to [SARIF](https://sarifweb.azurewebsites.net/), the Static Analysis
+//! Results Interchange Format.
+//!
+//! SARIF logs are understood by GitHub code scanning and other code analysis platforms, which can
+//! then annotate the sources with Nickel errors. Each diagnostic of an error gives a result, whose
+//! rule identifies the kind of the error (see [`rule_id`](fn.rule_id.html)). Primary labels are
+//! mapped to the locations of the result, and secondary labels to related locations. Labels
+//! pointing to sources which are not files, such as the standard library or the standard input,
+//! are dropped. Suggested fixes are mapped to SARIF fixes.
+use crate::error::diagnostic::{Diagnostic, Label, Severity};
+use crate::error::{Error, EvalError, ImportError, ParseError, SerializationError, TypecheckError};
+use crate::position::RawSpan;
+use crate::warning::Kind;
+use codespan::Files;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Return the identifier of the SARIF rule corresponding to the kind of an error.
+pub fn rule_id(error: &Error) -> &'static str {
+    match error {
+        Error::EvalError(err) => match err {
+            EvalError::BlameError(..) => "eval/contract-violation",
+            EvalError::TypeError(..) => "eval/type-error",
+            EvalError::NotAFunc(..) => "eval/not-a-function",
+            EvalError::FieldMissing(..) => "eval/missing-field",
+            EvalError::NotEnoughArgs(..) => "eval/not-enough-arguments",
+            EvalError::MergeIncompatibleArgs(..) => "eval/non-mergeable-terms",
+            EvalError::UnboundIdentifier(..) => "eval/unbound-identifier",
+            EvalError::InfiniteRecursion(..) => "eval/infinite-recursion",
+            EvalError::SerializationError(..) => "eval/serialization",
+            EvalError::DeserializationError(..) => "eval/deserialization",
+            EvalError::InternalError(..) => "eval/internal",
+            EvalError::IllFormedTerm(..) => "eval/ill-formed-term",
+            EvalError::Other(..) => "eval/other",
+        },
+        Error::TypecheckError(err) => match err {
+            TypecheckError::UnboundIdentifier(..) => "typecheck/unbound-identifier",
+            TypecheckError::IllformedType(..) => "typecheck/ill-formed-type",
+            TypecheckError::MissingRow(..) => "typecheck/missing-row",
+            TypecheckError::MissingDynTail(..) => "typecheck/missing-dyn-tail",
+            TypecheckError::ExtraRow(..) => "typecheck/extra-row",
+            TypecheckError::ExtraDynTail(..) => "typecheck/extra-dyn-tail",
+            TypecheckError::UnboundTypeVariable(..) => "typecheck/unbound-type-variable",
+            TypecheckError::TypeMismatch(..) => "typecheck/type-mismatch",
+            TypecheckError::RowKindMismatch(..) => "typecheck/row-kind-mismatch",
+            TypecheckError::RowMismatch(..) => "typecheck/row-mismatch",
+            TypecheckError::RowConflict(..) => "typecheck/row-conflict",
+            TypecheckError::ArrowTypeMismatch(..) => "typecheck/arrow-type-mismatch",
+        },
+        Error::ParseError(err) | Error::ImportError(ImportError::ParseError(err, _)) => match err {
+            ParseError::UnexpectedEOF(..) => "parse/unexpected-eof",
+            ParseError::UnexpectedToken(..) => "parse/unexpected-token",
+            ParseError::ExtraToken(..) => "parse/extra-token",
+            ParseError::UnmatchedCloseBrace(..) => "parse/unmatched-close-brace",
+            ParseError::InvalidEscapeSequence(..) => "parse/invalid-escape-sequence",
+            ParseError::InvalidAsciiEscapeCode(..) => "parse/invalid-ascii-escape-code",
+            ParseError::MissingComma(..) => "parse/missing-comma",
+            ParseError::UnterminatedString(..) => "parse/unterminated-string",
+            ParseError::NonAsciiIdentifier(..) => "parse/non-ascii-identifier",
+            ParseError::InvalidUnicodeEscape(..) => "parse/invalid-unicode-escape",
+            ParseError::ExternalFormatError(..) => "parse/external-format",
+            ParseError::InexactNumber(..) => "parse/inexact-number",
+        },
+        Error::ImportError(ImportError::IOError(..)) => "import/io",
+        Error::SerializationError(err) => match err {
+            SerializationError::UnsupportedNull(..) => "serialization/unsupported-null",
+            SerializationError::NotAString(..) => "serialization/not-a-string",
+            SerializationError::NotAList(..) => "serialization/not-a-list",
+            SerializationError::NotFlat(..) => "serialization/not-flat",
+            SerializationError::NonSerializable(..) => "serialization/non-serializable",
+            SerializationError::Other(..) => "serialization/other",
+        },
+        Error::ExtractionError(..) => "extraction",
+        Error::IOError(..) => "io",
+        Error::REPLError(..) => "repl",
+        Error::DeniedWarnings(warnings) => warnings
+            .first()
+            .map_or("warning", |warning| warning_rule_id(warning.kind())),
+    }
+}
+
+/// Return the identifier of the SARIF rule corresponding to a kind of warning.
+pub fn warning_rule_id(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Deprecated => "warning/deprecated",
+        Kind::Shadowing => "warning/shadowing",
+        Kind::ImplicitDyn => "warning/implicit-dyn",
+        Kind::UnusedImport => "warning/unused-import",
+    }
+}
+
+/// Convert the diagnostics of errors to a SARIF log. Each element of `errors` is made of the rule
+/// identifier of an error, and of its diagnostics.
+pub fn to_sarif(files: &Files<String>, errors: &[(&str, Vec<Diagnostic>)]) -> Value {
+    let base = std::env::current_dir()
+        .and_then(|dir| dir.canonicalize())
+        .ok();
+
+    let mut rules: Vec<&str> = errors.iter().map(|(rule, _)| *rule).collect();
+    rules.sort_unstable();
+    rules.dedup();
+
+    let results: Vec<Value> = errors
+        .iter()
+        .flat_map(|(rule, diagnostics)| {
+            let base = base.as_deref();
+            diagnostics
+                .iter()
+                .map(move |d| result_to_json(files, base, rule, d))
+        })
+        .collect();
+
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "nickel",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|id| json!({"id": id})).collect::<Vec<_>>(),
+                }
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }]
+    })
+}
+
+fn result_to_json(files: &Files<String>, base: Option<&Path>, rule: &str, d: &Diagnostic) -> Value {
+    let level = match d.severity() {
+        Severity::Bug | Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note | Severity::Help => "note",
+    };
+
+    let text = std::iter::once(d.message().to_owned())
+        .chain(d.notes().iter().cloned())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let (primary, secondary): (Vec<_>, Vec<_>) =
+        d.labels().iter().partition(|label| label.is_primary());
+    let locations = |labels: Vec<&Label>| -> Vec<Value> {
+        labels
+            .into_iter()
+            .filter_map(|label| location_to_json(files, base, label))
+            .collect()
+    };
+
+    let related: Vec<Value> = locations(secondary)
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut location)| {
+            location["id"] = json!(i);
+            location
+        })
+        .collect();
+
+    let fixes: Vec<Value> = d
+        .fixes()
+        .iter()
+        .filter_map(|fix| {
+            // A fix is only useful if all of its edits can be applied.
+            let changes = fix
+                .edits
+                .iter()
+                .map(|edit| {
+                    let (artifact, region) = region_to_json(files, base, edit.span)?;
+                    Some(json!({
+                        "artifactLocation": artifact,
+                        "replacements": [{
+                            "deletedRegion": region,
+                            "insertedContent": {"text": edit.replacement},
+                        }],
+                    }))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(json!({
+                "description": {"text": fix.message},
+                "artifactChanges": changes,
+            }))
+        })
+        .collect();
+
+    let mut result = json!({
+        "ruleId": rule,
+        "level": level,
+        "message": {"text": text},
+        "locations": locations(primary),
+        "relatedLocations": related,
+    });
+
+    if !fixes.is_empty() {
+        result["fixes"] = json!(fixes);
+    }
+
+    result
+}
+
+/// Convert a label to a SARIF location. Return `None` if the label doesn't point to a file.
+fn location_to_json(files: &Files<String>, base: Option<&Path>, label: &Label) -> Option<Value> {
+    let (artifact, region) = region_to_json(files, base, label.span())?;
+    let mut location = json!({
+        "physicalLocation": {
+            "artifactLocation": artifact,
+            "region": region,
+        }
+    });
+
+    if !label.message().is_empty() {
+        location["message"] = json!({ "text": label.message() });
+    }
+
+    Some(location)
+}
+
+/// Convert a span to a SARIF artifact location and region. Return `None` if the span doesn't
+/// point to a file.
+fn region_to_json(
+    files: &Files<String>,
+    base: Option<&Path>,
+    span: RawSpan,
+) -> Option<(Value, Value)> {
+    let name = files.name(span.src_id).to_string_lossy();
+    if name.starts_with('<') {
+        return None;
+    }
+
+    let path = PathBuf::from(name.as_ref());
+    let artifact = match base.and_then(|base| path.strip_prefix(base).ok()) {
+        Some(relative) => json!({"uri": to_uri(relative), "uriBaseId": "%SRCROOT%"}),
+        None if path.is_absolute() => json!({"uri": format!("file://{}", to_uri(&path))}),
+        None => json!({"uri": to_uri(&path)}),
+    };
+
+    let start = files.location(span.src_id, span.start).ok()?;
+    let end = files.location(span.src_id, span.end).ok()?;
+    let region = json!({
+        "startLine": start.line.to_usize() + 1,
+        "startColumn": start.column.to_usize() + 1,
+        "endLine": end.line.to_usize() + 1,
+        "endColumn": end.column.to_usize() + 1,
+    });
+
+    Some((artifact, region))
+}
+
+/// Convert a path to the path component of a URI, using forward slashes and percent-encoding
+/// the characters which are not allowed in URIs.
+pub(crate) fn to_uri(path: &Path) -> String {
+    let path = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut uri = String::with_capacity(path.len());
+
+    for c in path.replace("//", "/").chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' | '_' | '~' | '/' | ':' => uri.push(c),
+            c => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    uri.push_str(&format!("%{:02X}", b));
+                }
+            }
+        }
+    }
+
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+    use std::io::Cursor;
+
+    #[test]
+    fn typecheck_error() {
+        let mut p =
+            Program::new_from_source(Cursor::new("let x = 1 in\n(x + \"a\" : Num)"), "main.ncl")
+                .unwrap();
+        let err = p.typecheck().unwrap_err();
+        let rule = rule_id(&err);
+        assert_eq!(rule, "typecheck/type-mismatch");
+
+        let diagnostics = p.diagnostics(err);
+        let log = to_sarif(p.files(), &[(rule, diagnostics)]);
+        let run = &log["runs"][0];
+
+        assert_eq!(log["version"], json!("2.1.0"));
+        assert_eq!(run["tool"]["driver"]["rules"], json!([{"id": rule}]));
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], json!(rule));
+        assert_eq!(result["level"], json!("error"));
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], json!("main.ncl"));
+        assert_eq!(location["region"]["startLine"], json!(2));
+        assert_eq!(location["region"]["startColumn"], json!(6));
+    }
+
+    #[test]
+    fn fixes() {
+        let mut p = Program::new_from_source(Cursor::new("{a = 1 b = 2}"), "main.ncl").unwrap();
+        let err = p.eval().unwrap_err();
+        let rule = rule_id(&err);
+        let diagnostics = p.diagnostics(err);
+        let log = to_sarif(p.files(), &[(rule, diagnostics)]);
+
+        let fix = &log["runs"][0]["results"][0]["fixes"][0];
+        assert_eq!(fix["description"]["text"], json!("insert a comma"));
+        let change = &fix["artifactChanges"][0];
+        assert_eq!(change["artifactLocation"]["uri"], json!("main.ncl"));
+        let replacement = &change["replacements"][0];
+        assert_eq!(replacement["deletedRegion"]["startColumn"], json!(7));
+        assert_eq!(replacement["deletedRegion"]["endColumn"], json!(7));
+        assert_eq!(replacement["insertedContent"]["text"], json!(","));
+    }
+
+    #[test]
+    fn uris() {
+        assert_eq!(to_uri(Path::new("a b/ü.ncl")), "a%20b/%C3%BC.ncl");
+        assert_eq!(to_uri(Path::new("/etc/nickel")), "/etc/nickel");
+    }
+}
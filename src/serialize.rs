@@ -1,23 +1,45 @@
 //! Serialization of an evaluated program to various data format.
 use crate::error::SerializationError;
 use crate::identifier::Ident;
-use crate::term::{MetaValue, RichTerm, Term};
+use crate::term::{MetaValue, RichTerm, Term, REDACTED};
+use md5::digest::Digest;
 use serde::de::{Deserialize, Deserializer};
 use serde::ser::{Error, Serialize, SerializeMap, Serializer};
-use serde_xml_rs;
 use std::collections::HashMap;
 use std::fmt;
 use std::io;
 use std::str::FromStr;
 
+pub mod csv;
+pub mod dotenv;
+pub mod ini;
+pub mod nickel;
+pub mod nix;
+pub mod plist;
+pub mod xml;
+pub mod yaml;
+
 /// Available export formats.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum ExportFormat {
     Raw,
     Json,
+    /// Newline-delimited JSON: the elements of a list written as compact JSON, one per line.
+    Ndjson,
     Yaml,
     Toml,
     Xml,
+    Nix,
+    Dotenv,
+    Ini,
+    /// Apple property list, in the XML format.
+    Plist,
+    /// Apple property list, in the binary format.
+    BinaryPlist,
+    /// Comma-separated values, for lists of flat records.
+    Csv,
+    /// Tab-separated values, for lists of flat records.
+    Tsv,
 }
 
 impl std::default::Default for ExportFormat {
@@ -31,9 +53,17 @@ impl fmt::Display for ExportFormat {
         match self {
             Self::Raw => write!(f, "raw"),
             Self::Json => write!(f, "json"),
+            Self::Ndjson => write!(f, "ndjson"),
             Self::Yaml => write!(f, "yaml"),
             Self::Toml => write!(f, "toml"),
             Self::Xml => write!(f, "xml"),
+            Self::Nix => write!(f, "nix"),
+            Self::Dotenv => write!(f, "dotenv"),
+            Self::Ini => write!(f, "ini"),
+            Self::Plist => write!(f, "plist"),
+            Self::BinaryPlist => write!(f, "bplist"),
+            Self::Csv => write!(f, "csv"),
+            Self::Tsv => write!(f, "tsv"),
         }
     }
 }
@@ -54,18 +84,73 @@ impl FromStr for ExportFormat {
         match s.to_lowercase().as_ref() {
             "raw" => Ok(ExportFormat::Raw),
             "json" => Ok(ExportFormat::Json),
+            "ndjson" => Ok(ExportFormat::Ndjson),
             "yaml" => Ok(ExportFormat::Yaml),
             "toml" => Ok(ExportFormat::Toml),
             "xml" => Ok(ExportFormat::Xml),
+            "nix" => Ok(ExportFormat::Nix),
+            "dotenv" => Ok(ExportFormat::Dotenv),
+            "ini" => Ok(ExportFormat::Ini),
+            "plist" => Ok(ExportFormat::Plist),
+            "bplist" => Ok(ExportFormat::BinaryPlist),
+            "csv" => Ok(ExportFormat::Csv),
+            "tsv" => Ok(ExportFormat::Tsv),
             _ => Err(ParseFormatError(String::from(s))),
         }
     }
 }
 
+/// Absolute value above which numbers are written using the scientific notation by
+/// [`num_to_string`](fn.num_to_string.html).
+pub const SCIENTIFIC_UPPER_THRESHOLD: f64 = 1e21;
+
+/// Absolute value under which non-zero numbers are written using the scientific notation by
+/// [`num_to_string`](fn.num_to_string.html).
+pub const SCIENTIFIC_LOWER_THRESHOLD: f64 = 1e-7;
+
+/// Return the canonical textual representation of a number.
+///
+/// The output only depends on the value of the number, and not on the platform or the locale:
+/// - the shortest representation that round-trips to the same float is used
+/// - `-0` is written as `0`
+/// - the scientific notation is only used for numbers whose absolute value is greater than or
+///   equal to [`SCIENTIFIC_UPPER_THRESHOLD`](constant.SCIENTIFIC_UPPER_THRESHOLD.html), or
+///   strictly smaller than
+///   [`SCIENTIFIC_LOWER_THRESHOLD`](constant.SCIENTIFIC_LOWER_THRESHOLD.html)
+pub fn num_to_string(n: f64) -> String {
+    let abs = n.abs();
+
+    if n == 0.0 {
+        String::from("0")
+    } else if abs.is_finite()
+        && !(SCIENTIFIC_LOWER_THRESHOLD..SCIENTIFIC_UPPER_THRESHOLD).contains(&abs)
+    {
+        format!("{:e}", n)
+    } else {
+        format!("{}", n)
+    }
+}
+
+/// Return the textual representation of a number with exactly `digits` digits after the decimal
+/// point. As for [`num_to_string`](fn.num_to_string.html), the output doesn't depend on the
+/// platform or the locale, and a zero result is never written with a minus sign.
+pub fn num_to_fixed(n: f64, digits: usize) -> String {
+    let result = format!("{:.*}", digits, n);
+
+    if result.starts_with('-') && result[1..].chars().all(|c| c == '0' || c == '.') {
+        String::from(&result[1..])
+    } else {
+        result
+    }
+}
+
 /// Implicitly convert float to integers when possible to avoid trailing zeros. Note this this
 /// only work if the float is in range of either `i64` or `f64`. It seems there's no easy general
 /// solution (working for both YAML, TOML, and JSON) to choose the way floating point values are
 /// formatted.
+///
+/// Other floats are written using their shortest round-tripping representation by the underlying
+/// serializer, which doesn't depend on the platform. Note that `-0` is serialized as `0`.
 pub fn serialize_num<S>(n: &f64, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -94,14 +179,20 @@ where
     }
 }
 
+/// Return the fields of a record sorted alphabetically.
+fn sorted_fields(map: &HashMap<Ident, RichTerm>) -> Vec<(&Ident, &RichTerm)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    entries
+}
+
 /// Serializer for a record. Serialize fields in alphabetical order to get a deterministic output
 /// (by default, `HashMap`'s randomness implies a randomized order of fields in the output).
 pub fn serialize_record<S>(map: &HashMap<Ident, RichTerm>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    let mut entries: Vec<(_, _)> = map.iter().collect();
-    entries.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+    let entries = sorted_fields(map);
 
     let mut map_ser = serializer.serialize_map(Some(entries.len()))?;
     for (id, t) in entries.iter() {
@@ -131,9 +222,61 @@ impl<'de> Deserialize<'de> for RichTerm {
     }
 }
 
+impl From<serde_json::Value> for RichTerm {
+    /// Convert a JSON value to the corresponding Nickel term. Numbers which can't be represented
+    /// as a float, such as very large integers, are rounded to the closest float.
+    fn from(value: serde_json::Value) -> Self {
+        use serde_json::Value;
+
+        let t = match value {
+            Value::Null => Term::Null,
+            Value::Bool(b) => Term::Bool(b),
+            Value::Number(n) => Term::Num(n.as_f64().unwrap_or(f64::NAN)),
+            Value::String(s) => Term::Str(s),
+            Value::Array(vs) => Term::List(vs.into_iter().map(RichTerm::from).collect()),
+            Value::Object(map) => Term::Record(
+                map.into_iter()
+                    .map(|(id, v)| (Ident::from(id), RichTerm::from(v)))
+                    .collect(),
+            ),
+        };
+
+        RichTerm::from(t)
+    }
+}
+
+/// Convert a serializable Rust value to a Nickel term, through its JSON representation. Fail if
+/// the value can't be represented in JSON, for example if it is a map whose keys are not strings.
+pub fn serialize_to_term<T>(value: &T) -> Result<RichTerm, SerializationError>
+where
+    T: Serialize + ?Sized,
+{
+    serde_json::to_value(value)
+        .map(RichTerm::from)
+        .map_err(|err| SerializationError::Other(err.to_string()))
+}
+
 /// Check that a term is serializable. Serializable terms are booleans, numbers, strings, enum,
 /// lists of serializable terms or records of serializable terms.
+///
+/// The term is traversed in a deterministic order, fields being visited in alphabetical order. If
+/// a non serializable value is found, such as a function, the error holds the path of the first
+/// one in the exported term, and the value itself, whose position is where it was defined.
 pub fn validate(format: ExportFormat, t: &RichTerm) -> Result<(), SerializationError> {
+    validate_at(format, t, String::new())
+}
+
+/// Extend the path of a value with a field name.
+fn field_path(path: &str, id: &Ident) -> String {
+    if path.is_empty() {
+        id.to_string()
+    } else {
+        format!("{}.{}", path, id)
+    }
+}
+
+/// Same as [`validate`](fn.validate.html), for a term at the given path of the exported term.
+fn validate_at(format: ExportFormat, t: &RichTerm, path: String) -> Result<(), SerializationError> {
     use crate::term;
     use Term::*;
 
@@ -143,49 +286,369 @@ pub fn validate(format: ExportFormat, t: &RichTerm) -> Result<(), SerializationE
         } else {
             Err(SerializationError::NotAString(t.clone()))
         }
+    } else if format == ExportFormat::Ndjson {
+        match t.term.as_ref() {
+            List(vec) => vec.iter().enumerate().try_for_each(|(i, t)| {
+                validate_at(ExportFormat::Json, t, format!("{}[{}]", path, i))
+            }),
+            MetaValue(term::MetaValue {
+                value: Some(ref t), ..
+            }) => validate_at(format, t, path),
+            _ => Err(SerializationError::NotAList(
+                String::from("NDJSON"),
+                t.clone(),
+            )),
+        }
     } else {
         match t.term.as_ref() {
             // TOML doesn't support null values
-            Null if format == ExportFormat::Json || format == ExportFormat::Yaml => Ok(()),
-            Null => Err(SerializationError::UnsupportedNull(format, t.clone())),
-            Bool(_) | Num(_) | Str(_) | Enum(_) => Ok(()),
-            Record(map) | RecRecord(map) => {
-                map.iter().try_for_each(|(_, t)| validate(format, t))?;
-                Ok(())
-            }
-            List(vec) => {
-                vec.iter().try_for_each(|t| validate(format, t))?;
+            Null if format == ExportFormat::Json
+                || format == ExportFormat::Ndjson
+                || format == ExportFormat::Yaml
+                || format == ExportFormat::Nix
+                || format == ExportFormat::Csv
+                || format == ExportFormat::Tsv =>
+            {
                 Ok(())
             }
+            Null => Err(SerializationError::UnsupportedNull(format, t.clone())),
+            Bool(_) | Num(_) | Str(_) | Enum(_) => Ok(()),
+            Record(map) | RecRecord(map) => sorted_fields(map)
+                .into_iter()
+                .try_for_each(|(id, t)| validate_at(format, t, field_path(&path, id))),
+            List(vec) => vec
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, t)| validate_at(format, t, format!("{}[{}]", path, i))),
             //TODO: have a specific error for such missing value.
             MetaValue(term::MetaValue {
                 value: Some(ref t), ..
-            }) => validate(format, t),
-            _ => Err(SerializationError::NonSerializable(t.clone())),
+            }) => validate_at(format, t, path),
+            _ => Err(SerializationError::NonSerializable(t.clone(), Some(path))),
+        }
+    }
+}
+
+/// Options of the JSON serializer.
+///
+/// Whatever the options, the output is deterministic:
+/// - fields are written in alphabetical order, that is by increasing Unicode code points
+/// - only `"`, `\` and control characters are escaped in strings, which are otherwise written
+///   as UTF-8
+/// - numbers are written as described in [`serialize_num`](fn.serialize_num.html)
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct JsonOptions {
+    /// The number of spaces used to indent nested lists and records. If `None`, the output is
+    /// written on a single line without any whitespace.
+    pub indent: Option<usize>,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        JsonOptions { indent: Some(2) }
+    }
+}
+
+/// Format-specific options of the serializers. Options of formats other than the one used are
+/// ignored.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct ExportOptions {
+    pub json: JsonOptions,
+    pub yaml: yaml::YamlOptions,
+    pub xml: xml::XmlOptions,
+    pub csv: csv::CsvOptions,
+    /// The part of the result to export. The serializers ignore this option: the selection is
+    /// applied beforehand by [`select`](fn.select.html).
+    pub selection: Selection,
+    /// The profiles layered over the result, in increasing priority, before the selection. The
+    /// serializers ignore this option: the profiles are applied beforehand by
+    /// [`profile::apply`](../profile/fn.apply.html).
+    #[cfg(feature = "profiles")]
+    pub profiles: Vec<String>,
+    /// How the sensitive values are exported. The serializers ignore this option: the values are
+    /// checked or redacted beforehand by [`redact`](fn.redact.html).
+    pub secrets: Secrets,
+}
+
+/// How the values marked as sensitive, such as passwords, are exported.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Secrets {
+    /// Fail on the first sensitive value.
+    Reject,
+    /// Replace the sensitive values by [`REDACTED`](../term/constant.REDACTED.html).
+    Redact,
+    /// Export the sensitive values as they are.
+    Allow,
+}
+
+impl std::default::Default for Secrets {
+    fn default() -> Self {
+        Secrets::Reject
+    }
+}
+
+/// The part of a result to export: a sub-record, minus some of its fields.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Selection {
+    /// The dot-separated path of the field to export, instead of the whole result.
+    pub field: Option<String>,
+    /// The dot-separated paths of the fields to remove, relatively to the exported field. Paths
+    /// which don't exist are ignored.
+    pub exclude: Vec<String>,
+}
+
+impl Selection {
+    /// Return `true` if the selection is the whole result.
+    pub fn is_all(&self) -> bool {
+        self.field.is_none() && self.exclude.is_empty()
+    }
+}
+
+/// Return the underlying value of a term, skipping metavalues.
+pub(crate) fn strip_meta(rt: &RichTerm) -> &RichTerm {
+    match rt.as_ref() {
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => strip_meta(t),
+        _ => rt,
+    }
+}
+
+/// Apply a selection to a fully evaluated term.
+pub fn select(rt: RichTerm, selection: &Selection) -> Result<RichTerm, SerializationError> {
+    let mut result = match selection.field {
+        Some(ref path) => path.split('.').try_fold(rt, |rt, id| {
+            match strip_meta(&rt).as_ref() {
+                Term::Record(map) => map.get(&Ident::from(id)).cloned(),
+                _ => None,
+            }
+            .ok_or_else(|| SerializationError::Other(format!("the field `{}` doesn't exist", path)))
+        })?,
+        None => rt,
+    };
+
+    for path in selection.exclude.iter() {
+        result = exclude(&result, &path.split('.').collect::<Vec<_>>());
+    }
+
+    Ok(result)
+}
+
+/// Remove a field from a record, given its path.
+fn exclude(rt: &RichTerm, path: &[&str]) -> RichTerm {
+    let rt = strip_meta(rt);
+
+    match (rt.as_ref(), path) {
+        (Term::Record(map), [id, rest @ ..]) => {
+            let id = Ident::from(*id);
+            let mut map = map.clone();
+
+            if rest.is_empty() {
+                map.remove(&id);
+            } else if let Some(t) = map.get(&id) {
+                let t = exclude(t, rest);
+                map.insert(id, t);
+            }
+
+            RichTerm::new(Term::Record(map), rt.pos)
+        }
+        _ => rt.clone(),
+    }
+}
+
+/// Check or redact the sensitive values of a fully evaluated term, depending on `secrets`. The
+/// fields are visited in alphabetical order: if sensitive values are rejected, the error holds
+/// the path of the first one.
+pub fn redact(rt: RichTerm, secrets: Secrets) -> Result<RichTerm, SerializationError> {
+    if secrets == Secrets::Allow {
+        Ok(rt)
+    } else {
+        redact_at(&rt, secrets, "")
+    }
+}
+
+/// Same as [`redact`](fn.redact.html), for a term at the given path of the exported term.
+fn redact_at(rt: &RichTerm, secrets: Secrets, path: &str) -> Result<RichTerm, SerializationError> {
+    match rt.as_ref() {
+        Term::MetaValue(meta) if meta.sensitive => match secrets {
+            Secrets::Redact => Ok(RichTerm::new(Term::Str(String::from(REDACTED)), rt.pos)),
+            _ => Err(SerializationError::Other(format!(
+                "the value of `{}` is sensitive: pass --redact to replace it by a placeholder, or \
+                 --allow-secrets to export it",
+                if path.is_empty() { "." } else { path }
+            ))),
+        },
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => redact_at(t, secrets, path),
+        Term::Record(map) => {
+            let mut result = HashMap::with_capacity(map.len());
+            for (id, t) in sorted_fields(map) {
+                result.insert(id.clone(), redact_at(t, secrets, &field_path(path, id))?);
+            }
+            Ok(RichTerm::new(Term::Record(result), rt.pos))
+        }
+        Term::List(ts) => ts
+            .iter()
+            .enumerate()
+            .map(|(i, t)| redact_at(t, secrets, &format!("{}[{}]", path, i)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|ts| RichTerm::new(Term::List(ts), rt.pos)),
+        _ => Ok(rt.clone()),
+    }
+}
+
+fn json_to_writer<W>(
+    writer: W,
+    rt: &RichTerm,
+    options: &JsonOptions,
+) -> Result<(), SerializationError>
+where
+    W: io::Write,
+{
+    let result = match options.indent {
+        Some(indent) => {
+            let indent = " ".repeat(indent);
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            rt.serialize(&mut serde_json::Serializer::with_formatter(
+                writer, formatter,
+            ))
+        }
+        None => rt.serialize(&mut serde_json::Serializer::new(writer)),
+    };
+
+    result.map_err(|err| SerializationError::Other(err.to_string()))
+}
+
+/// Write one element of a list as a line of newline-delimited JSON.
+pub fn ndjson_line_to_writer<W>(mut writer: W, rt: &RichTerm) -> Result<(), SerializationError>
+where
+    W: io::Write,
+{
+    json_to_writer(&mut writer, rt, &JsonOptions { indent: None })?;
+    writer
+        .write_all(b"\n")
+        .map_err(|err| SerializationError::Other(err.to_string()))
+}
+
+fn ndjson_to_writer<W>(mut writer: W, rt: &RichTerm) -> Result<(), SerializationError>
+where
+    W: io::Write,
+{
+    match rt.as_ref() {
+        Term::List(ts) => ts
+            .iter()
+            .try_for_each(|t| ndjson_line_to_writer(&mut writer, t)),
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => ndjson_to_writer(writer, t),
+        _ => Err(SerializationError::NotAList(
+            String::from("NDJSON"),
+            rt.clone(),
+        )),
+    }
+}
+
+/// Hash algorithms of [`canonical_hash`](fn.canonical_hash.html).
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseHashAlgorithmError(String);
+
+impl fmt::Display for ParseHashAlgorithmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported hash algorithm {}", self.0)
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = ParseHashAlgorithmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "md5" => Ok(HashAlgorithm::Md5),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            _ => Err(ParseHashAlgorithmError(String::from(s))),
         }
     }
 }
 
+/// Return the hexadecimal digest of the canonical serialization of a fully evaluated term.
+///
+/// The canonical serialization is compact JSON, where the fields of records are sorted
+/// alphabetically and numbers are written as by [`serialize_num`](fn.serialize_num.html), so
+/// that two terms have the same digest if and only if they evaluate to the same JSON value,
+/// whatever the order of their definitions, the platform or the locale. Metadata is ignored.
+pub fn canonical_hash(
+    rt: &RichTerm,
+    algorithm: HashAlgorithm,
+) -> Result<String, SerializationError> {
+    validate(ExportFormat::Json, rt)?;
+
+    let mut buffer = Vec::new();
+    json_to_writer(&mut buffer, rt, &JsonOptions { indent: None })?;
+
+    let digest = match algorithm {
+        HashAlgorithm::Md5 => format!("{:x}", md5::Md5::digest(&buffer)),
+        HashAlgorithm::Sha1 => format!("{:x}", sha1::Sha1::digest(&buffer)),
+        HashAlgorithm::Sha256 => format!("{:x}", sha2::Sha256::digest(&buffer)),
+        HashAlgorithm::Sha512 => format!("{:x}", sha2::Sha512::digest(&buffer)),
+    };
+
+    Ok(digest)
+}
+
 pub fn to_writer<W>(
+    writer: W,
+    format: ExportFormat,
+    rt: &RichTerm,
+) -> Result<(), SerializationError>
+where
+    W: io::Write,
+{
+    to_writer_with_options(writer, format, rt, &ExportOptions::default())
+}
+
+pub fn to_writer_with_options<W>(
     mut writer: W,
     format: ExportFormat,
     rt: &RichTerm,
+    options: &ExportOptions,
 ) -> Result<(), SerializationError>
 where
     W: io::Write,
 {
     match format {
-        ExportFormat::Json => serde_json::to_writer_pretty(writer, &rt)
-            .map_err(|err| SerializationError::Other(err.to_string())),
-        ExportFormat::Yaml => serde_yaml::to_writer(writer, &rt)
-            .map_err(|err| SerializationError::Other(err.to_string())),
+        ExportFormat::Json => json_to_writer(writer, rt, &options.json),
+        ExportFormat::Ndjson => ndjson_to_writer(writer, rt),
+        ExportFormat::Yaml => yaml::to_string(rt, &options.yaml).and_then(|s| {
+            writer
+                .write_all(s.as_bytes())
+                .map_err(|err| SerializationError::Other(err.to_string()))
+        }),
         ExportFormat::Toml => toml::Value::try_from(&rt)
             .map_err(|err| SerializationError::Other(err.to_string()))
             .and_then(|v| {
                 write!(writer, "{}", v).map_err(|err| SerializationError::Other(err.to_string()))
             }),
-        ExportFormat::Xml => serde_xml_rs::to_writer(writer, &rt)
-            .map_err(|err| SerializationError::Other(err.to_string())),
+        ExportFormat::BinaryPlist => plist::to_bytes(rt).and_then(|bytes| {
+            writer
+                .write_all(&bytes)
+                .map_err(|err| SerializationError::Other(err.to_string()))
+        }),
+        ExportFormat::Xml
+        | ExportFormat::Nix
+        | ExportFormat::Dotenv
+        | ExportFormat::Ini
+        | ExportFormat::Plist
+        | ExportFormat::Csv
+        | ExportFormat::Tsv => to_string_with_options(format, rt, options).and_then(|s| {
+            writer
+                .write_all(s.as_bytes())
+                .map_err(|err| SerializationError::Other(err.to_string()))
+        }),
         ExportFormat::Raw => match rt.as_ref() {
             Term::Str(s) => writer
                 .write_all(s.as_bytes())
@@ -199,18 +662,40 @@ where
 }
 
 pub fn to_string(format: ExportFormat, rt: &RichTerm) -> Result<String, SerializationError> {
+    to_string_with_options(format, rt, &ExportOptions::default())
+}
+
+pub fn to_string_with_options(
+    format: ExportFormat,
+    rt: &RichTerm,
+    options: &ExportOptions,
+) -> Result<String, SerializationError> {
     match format {
-        ExportFormat::Json => serde_json::to_string_pretty(&rt)
-            .map_err(|err| SerializationError::Other(err.to_string())),
-        ExportFormat::Yaml => {
-            serde_yaml::to_string(&rt).map_err(|err| SerializationError::Other(err.to_string()))
+        ExportFormat::Json => {
+            let mut buffer = Vec::new();
+            json_to_writer(&mut buffer, rt, &options.json)?;
+            // The JSON serializer only writes valid UTF-8.
+            Ok(String::from_utf8(buffer).unwrap())
+        }
+        ExportFormat::Ndjson => {
+            let mut buffer = Vec::new();
+            ndjson_to_writer(&mut buffer, rt)?;
+            Ok(String::from_utf8(buffer).unwrap())
         }
+        ExportFormat::Yaml => yaml::to_string(rt, &options.yaml),
         ExportFormat::Toml => toml::Value::try_from(&rt)
             .map(|v| format!("{}", v))
             .map_err(|err| SerializationError::Other(err.to_string())),
-        ExportFormat::Xml => {
-            serde_xml_rs::to_string(&rt).map_err(|err| SerializationError::Other(err.to_string()))
-        }
+        ExportFormat::Xml => xml::to_string(rt, &options.xml),
+        ExportFormat::Plist => plist::to_string(rt),
+        ExportFormat::Csv => csv::to_string(rt, csv::Delimiter::Comma, &options.csv),
+        ExportFormat::Tsv => csv::to_string(rt, csv::Delimiter::Tab, &options.csv),
+        ExportFormat::BinaryPlist => Err(SerializationError::Other(String::from(
+            "binary property lists can't be written to a string",
+        ))),
+        ExportFormat::Nix => nix::to_string(rt),
+        ExportFormat::Dotenv => dotenv::to_string(rt),
+        ExportFormat::Ini => ini::to_string(rt),
         ExportFormat::Raw => match rt.as_ref() {
             Term::Str(s) => Ok(s.clone()),
             t => Err(SerializationError::Other(format!(
@@ -280,8 +765,10 @@ mod tests {
             let evaluated = mk_program($term).and_then(|mut p| p.eval_full()).unwrap();
             let from_json: RichTerm =
                 serde_json::from_str(&serde_json::to_string(&evaluated).unwrap()).unwrap();
-            let from_yaml: RichTerm =
-                serde_yaml::from_str(&serde_yaml::to_string(&evaluated).unwrap()).unwrap();
+            let from_yaml: RichTerm = serde_yaml::from_str(
+                &yaml::to_string(&evaluated.clone().into(), &Default::default()).unwrap(),
+            )
+            .unwrap();
             let from_toml: RichTerm =
                 toml::from_str(&format!("{}", &toml::Value::try_from(&evaluated).unwrap()))
                     .unwrap();
@@ -313,6 +800,25 @@ mod tests {
         };
     }
 
+    #[test]
+    fn numbers() {
+        assert_eq!(num_to_string(1.0), "1");
+        assert_eq!(num_to_string(-0.0), "0");
+        assert_eq!(num_to_string(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(
+            num_to_string(123456789012345680000.0),
+            "123456789012345680000"
+        );
+        assert_eq!(num_to_string(1e21), "1e21");
+        assert_eq!(num_to_string(-2.5e-8), "-2.5e-8");
+        assert_eq!(num_to_string(0.0000001), "0.0000001");
+
+        assert_eq!(num_to_fixed(1.005, 2), "1.00");
+        assert_eq!(num_to_fixed(2.5, 0), "2");
+        assert_eq!(num_to_fixed(-0.0001, 2), "0.00");
+        assert_eq!(num_to_fixed(-1.5, 1), "-1.5");
+    }
+
     #[test]
     fn basic() {
         assert_json_eq!("1 + 1", 2);
@@ -376,6 +882,205 @@ mod tests {
         );
     }
 
+    #[test]
+    fn json_options() {
+        let rt: RichTerm = mk_program(r#"{b = [1, 0.5], a = "\u{e9}\"\n"}"#)
+            .and_then(|mut p| p.eval_full())
+            .unwrap()
+            .into();
+        let to_json = |indent| {
+            let options = ExportOptions {
+                json: JsonOptions { indent },
+                ..Default::default()
+            };
+            to_string_with_options(ExportFormat::Json, &rt, &options).unwrap()
+        };
+
+        assert_eq!(to_json(None), "{\"a\":\"\u{e9}\\\"\\n\",\"b\":[1,0.5]}");
+        assert_eq!(
+            to_json(Some(1)),
+            "{\n \"a\": \"\u{e9}\\\"\\n\",\n \"b\": [\n  1,\n  0.5\n ]\n}"
+        );
+        assert_eq!(
+            to_string(ExportFormat::Json, &rt).unwrap(),
+            serde_json::to_string_pretty(&rt).unwrap()
+        );
+    }
+
+    #[test]
+    fn ndjson() {
+        let rt: RichTerm = mk_program("[1, {a = null, b = [\"c\"]}, []]")
+            .and_then(|mut p| p.eval_full())
+            .unwrap()
+            .into();
+        assert_eq!(
+            to_string(ExportFormat::Ndjson, &rt).unwrap(),
+            "1\n{\"a\":null,\"b\":[\"c\"]}\n[]\n"
+        );
+
+        assert_pass_validation!(ExportFormat::Ndjson, "[{a = null}, 1]", true);
+        assert_pass_validation!(ExportFormat::Ndjson, "{a = 1}", false);
+        assert_pass_validation!(ExportFormat::Ndjson, "[1, fun x => x]", false);
+    }
+
+    #[test]
+    fn from_serde() {
+        use crate::cache::resolvers::DummyResolver;
+        use crate::eval;
+        use crate::term::UnaryOp;
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        struct Server {
+            host: String,
+            port: u16,
+            tags: Vec<&'static str>,
+            backup: Option<bool>,
+        }
+
+        let server = Server {
+            host: String::from("example.org"),
+            port: 8080,
+            tags: vec!["web"],
+            backup: None,
+        };
+        let rt = serialize_to_term(&server).unwrap();
+        assert_eq!(
+            serde_json::to_value(&rt).unwrap(),
+            json!({"host": "example.org", "port": 8080, "tags": ["web"], "backup": null})
+        );
+
+        let port = mk_term::op1(UnaryOp::StaticAccess(Ident::from("port")), rt);
+        assert_eq!(
+            eval::eval(port, &HashMap::new(), &mut DummyResolver {}).unwrap(),
+            Term::Num(8080.0)
+        );
+
+        assert_eq!(
+            RichTerm::from(json!([1, "a", {"b": true}])),
+            RichTerm::from(Term::List(vec![
+                Term::Num(1.0).into(),
+                Term::Str(String::from("a")).into(),
+                Term::Record(
+                    vec![(Ident::from("b"), Term::Bool(true).into())]
+                        .into_iter()
+                        .collect()
+                )
+                .into(),
+            ]))
+        );
+
+        let mut map = HashMap::new();
+        map.insert(vec![1], 2);
+        assert!(serialize_to_term(&map).is_err());
+    }
+
+    #[test]
+    fn canonical_hashes() {
+        let hash = |s: &str, algorithm| {
+            let rt = mk_program(s)
+                .and_then(|mut p| p.eval_full())
+                .map(RichTerm::from)
+                .unwrap();
+            canonical_hash(&rt, algorithm)
+        };
+
+        assert_eq!(
+            hash("{b = [1 / 2, \"x\"], a = 1}", HashAlgorithm::Sha256).unwrap(),
+            "d9dda6d0c61c7433e4751740c766c964aa4c33cbf44060db3843e8fbbce9f537"
+        );
+        assert_eq!(
+            hash(
+                "{a | doc \"one\" = 0.5 + 0.5, b = [0.5] @ [\"x\"]}",
+                HashAlgorithm::Md5
+            )
+            .unwrap(),
+            "158438fa65b745bfba231be42f9430ff"
+        );
+        assert!(matches!(
+            hash("{f = fun x => x}", HashAlgorithm::Sha1),
+            Err(SerializationError::NonSerializable(..))
+        ));
+        assert_eq!("SHA256".parse(), Ok(HashAlgorithm::Sha256));
+        assert!("crc32".parse::<HashAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn selection() {
+        let source = r#"{
+            services = {
+                web = {port = 80, internal = {debug = fun x => x}},
+                db | doc "The database" = {port = 5432},
+            },
+            internal = "secret",
+        }"#;
+        let select_json = |field: Option<&str>, exclude: Vec<&str>| {
+            let rt = mk_program(source)
+                .and_then(|mut p| p.eval_full())
+                .map(RichTerm::from)
+                .unwrap();
+            let selection = Selection {
+                field: field.map(String::from),
+                exclude: exclude.into_iter().map(String::from).collect(),
+            };
+            select(rt, &selection).map(|rt| serde_json::to_value(&rt).unwrap())
+        };
+
+        assert_eq!(
+            select_json(Some("services.db"), vec![]).unwrap(),
+            json!({"port": 5432})
+        );
+        assert_eq!(
+            select_json(
+                Some("services"),
+                vec!["web.internal", "db", "missing.field"]
+            )
+            .unwrap(),
+            json!({"web": {"port": 80}})
+        );
+        assert_eq!(
+            select_json(None, vec!["internal", "services.web.internal"]).unwrap(),
+            json!({"services": {"web": {"port": 80}, "db": {"port": 5432}}})
+        );
+        assert!(select_json(Some("services.cache"), vec![]).is_err());
+        assert!(select_json(Some("internal.port"), vec![]).is_err());
+    }
+
+    #[test]
+    fn secrets() {
+        let rt: RichTerm = mk_program(
+            r#"{
+                db = {host = "localhost", password | Str | sensitive = "hunter2"},
+                tokens = [{value | sensitive = "abc"}],
+            }"#,
+        )
+        .and_then(|mut p| p.eval_full())
+        .unwrap()
+        .into();
+
+        assert_eq!(
+            redact(rt.clone(), Secrets::Reject).unwrap_err(),
+            SerializationError::Other(String::from(
+                "the value of `db.password` is sensitive: pass --redact to replace it by a \
+                 placeholder, or --allow-secrets to export it"
+            ))
+        );
+        assert_eq!(
+            serde_json::to_value(redact(rt.clone(), Secrets::Redact).unwrap()).unwrap(),
+            json!({
+                "db": {"host": "localhost", "password": "<redacted>"},
+                "tokens": [{"value": "<redacted>"}],
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(redact(rt, Secrets::Allow).unwrap()).unwrap(),
+            json!({
+                "db": {"host": "localhost", "password": "hunter2"},
+                "tokens": [{"value": "abc"}],
+            })
+        );
+    }
+
     #[test]
     fn prevalidation() {
         assert_pass_validation!(ExportFormat::Json, "{a = 1, b = {c = fun x => x}}", false);
@@ -388,6 +1093,43 @@ mod tests {
         assert_pass_validation!(ExportFormat::Toml, "{foo = null}", false);
     }
 
+    #[test]
+    fn non_serializable_paths() {
+        let validate_source = |format, source: &str| {
+            let rt = mk_program(source)
+                .and_then(|mut p| p.eval_full())
+                .map(RichTerm::from)
+                .unwrap();
+            validate(format, &rt)
+        };
+
+        let source = "{b = {c = [1, fun x => x]}, a = {f = fun y => y}}";
+        match validate_source(ExportFormat::Yaml, source) {
+            Err(SerializationError::NonSerializable(rt, Some(path))) => {
+                assert_eq!(path, "a.f");
+                let span = rt.pos.into_opt().unwrap();
+                assert_eq!(
+                    &source[span.start.to_usize()..span.end.to_usize()],
+                    "fun y => y"
+                );
+            }
+            result => panic!("expected a non serializable value, got {:?}", result),
+        }
+
+        assert!(matches!(
+            validate_source(ExportFormat::Json, "{b = {c = [1, fun x => x]}}"),
+            Err(SerializationError::NonSerializable(_, Some(path))) if path == "b.c[1]"
+        ));
+        assert!(matches!(
+            validate_source(ExportFormat::Ndjson, "[{a = 1}, {a = fun x => x}]"),
+            Err(SerializationError::NonSerializable(_, Some(path))) if path == "[1].a"
+        ));
+        assert!(matches!(
+            validate_source(ExportFormat::Json, "fun x => x"),
+            Err(SerializationError::NonSerializable(_, Some(path))) if path.is_empty()
+        ));
+    }
+
     #[test]
     fn involution() {
         assert_involutory!("{val = 1 + 1}");
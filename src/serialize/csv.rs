@@ -0,0 +1,215 @@
+//! Serialization of a list of flat records to CSV or TSV.
+//!
+//! Each record of the list is written as a row. The header is made of the union of the fields of
+//! all the records, sorted alphabetically, unless an explicit list of columns is given in
+//! [`CsvOptions`](struct.CsvOptions.html), in which case the other fields are ignored. A field
+//! missing from a record, or whose value is `null`, gives an empty cell.
+//!
+//! In CSV, cells containing the delimiter, a double quote, a line break, or leading or trailing
+//! spaces are quoted as described in RFC 4180. In TSV, backslashes, tabs and line breaks are
+//! escaped as `\\`, `\t`, `\n` and `\r`.
+use super::{num_to_string, sorted_fields, strip_meta};
+use crate::error::SerializationError;
+use crate::term::{RichTerm, Term};
+use std::collections::BTreeSet;
+
+/// The separator of the cells of a row.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Delimiter {
+    Comma,
+    Tab,
+}
+
+/// Options of the CSV and TSV serializers.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct CsvOptions {
+    /// The columns to write, in order. By default, all the fields of the records are written,
+    /// in alphabetical order.
+    pub columns: Option<Vec<String>>,
+}
+
+/// Serialize a fully evaluated list of flat records.
+pub fn to_string(
+    rt: &RichTerm,
+    delimiter: Delimiter,
+    options: &CsvOptions,
+) -> Result<String, SerializationError> {
+    let format = match delimiter {
+        Delimiter::Comma => "CSV",
+        Delimiter::Tab => "TSV",
+    };
+
+    let rows = match strip_meta(rt).as_ref() {
+        Term::List(ts) => ts
+            .iter()
+            .enumerate()
+            .map(|(i, t)| match strip_meta(t).as_ref() {
+                Term::Record(map) => Ok(map),
+                _ => Err(SerializationError::NotFlat(
+                    format!("{} export: row {} is not a record", format, i + 1),
+                    t.clone(),
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => {
+            return Err(SerializationError::NotAList(
+                String::from(format),
+                rt.clone(),
+            ))
+        }
+    };
+
+    let columns = match options.columns {
+        Some(ref columns) => columns.clone(),
+        None => rows
+            .iter()
+            .flat_map(|map| map.keys().map(|id| id.to_string()))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+    };
+
+    let mut out = String::new();
+    write_row(&mut out, columns.iter().map(String::as_str), delimiter);
+
+    for (i, map) in rows.into_iter().enumerate() {
+        let fields = sorted_fields(map);
+        let mut cells = vec![String::new(); columns.len()];
+
+        for (id, t) in fields {
+            let id = id.to_string();
+            let column = match columns.iter().position(|c| *c == id) {
+                Some(column) => column,
+                None => continue,
+            };
+
+            let t = strip_meta(t);
+            cells[column] = match t.as_ref() {
+                Term::Null => String::new(),
+                Term::Bool(b) => b.to_string(),
+                Term::Num(n) => num_to_string(*n),
+                Term::Str(s) => s.clone(),
+                Term::Enum(id) => id.to_string(),
+                _ => {
+                    return Err(SerializationError::NotFlat(
+                        format!(
+                            "{} export: field `{}` of row {} is not a scalar value",
+                            format,
+                            id,
+                            i + 1
+                        ),
+                        t.clone(),
+                    ))
+                }
+            };
+        }
+
+        write_row(&mut out, cells.iter().map(String::as_str), delimiter);
+    }
+
+    Ok(out)
+}
+
+fn write_row<'a, I>(out: &mut String, cells: I, delimiter: Delimiter)
+where
+    I: Iterator<Item = &'a str>,
+{
+    for (i, cell) in cells.enumerate() {
+        if i > 0 {
+            out.push(match delimiter {
+                Delimiter::Comma => ',',
+                Delimiter::Tab => '\t',
+            });
+        }
+
+        match delimiter {
+            Delimiter::Comma => out.push_str(&quote_csv(cell)),
+            Delimiter::Tab => out.push_str(&escape_tsv(cell)),
+        }
+    }
+
+    out.push('\n');
+}
+
+fn quote_csv(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) || s.trim() != s {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        String::from(s)
+    }
+}
+
+fn escape_tsv(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::eval_full_str;
+
+    fn to_csv(
+        s: &str,
+        delimiter: Delimiter,
+        columns: Option<Vec<&str>>,
+    ) -> Result<String, SerializationError> {
+        let rt = eval_full_str(s);
+        let options = CsvOptions {
+            columns: columns.map(|cs| cs.into_iter().map(String::from).collect()),
+        };
+        to_string(&rt, delimiter, &options)
+    }
+
+    #[test]
+    fn rows() {
+        let source = r#"[
+            {name = "web", port = 80, tls = false},
+            {name = "a, \"b\"", port = 443, region = `eu, tls = null},
+            {name = "tab\there"},
+        ]"#;
+
+        assert_eq!(
+            to_csv(source, Delimiter::Comma, None).unwrap(),
+            "name,port,region,tls\nweb,80,,false\n\"a, \"\"b\"\"\",443,eu,\ntab\there,,,\n"
+        );
+        assert_eq!(
+            to_csv(source, Delimiter::Tab, Some(vec!["port", "name"])).unwrap(),
+            "port\tname\n80\tweb\n443\ta, \"b\"\n\ttab\\there\n"
+        );
+        assert_eq!(to_csv("[]", Delimiter::Comma, None).unwrap(), "\n");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            to_csv("{a = 1}", Delimiter::Comma, None),
+            Err(SerializationError::NotAList(..))
+        ));
+        assert!(matches!(
+            to_csv("[{a = 1}, 2]", Delimiter::Comma, None),
+            Err(SerializationError::NotFlat(..))
+        ));
+        assert!(matches!(
+            to_csv("[{a = {b = 1}}]", Delimiter::Comma, None),
+            Err(SerializationError::NotFlat(..))
+        ));
+        assert!(matches!(
+            to_csv("[{a = [1]}]", Delimiter::Comma, None),
+            Err(SerializationError::NotFlat(..))
+        ));
+        // Ignored columns may hold any value.
+        assert!(to_csv("[{a = [1], b = 1}]", Delimiter::Comma, Some(vec!["b"])).is_ok());
+    }
+}
@@ -0,0 +1,159 @@
+//! Serialization to a dotenv file, made of `KEY=value` lines.
+//!
+//! The top-level value must be a record. Nested records are flattened by joining the names of
+//! the fields with underscores: `{db = {port = 5432}}` is written as `db_port=5432`. The names
+//! must be valid environment variable names, that is only made of ASCII letters, digits and
+//! underscores, and not start with a digit, and two flattened names must not collide.
+//!
+//! Strings, numbers, booleans and enum tags are written as values, quoted with double quotes if
+//! needed. Lists and `null` can't be represented and raise an error.
+use super::{num_to_string, sorted_fields};
+use crate::error::SerializationError;
+use crate::term::{MetaValue, RichTerm, Term};
+use std::collections::HashSet;
+
+/// Serialize a fully evaluated record to a dotenv file.
+pub fn to_string(rt: &RichTerm) -> Result<String, SerializationError> {
+    let mut lines = Vec::new();
+    flatten(rt, None, &mut lines)?;
+
+    let mut names = HashSet::new();
+    let mut out = String::new();
+    for (name, value) in lines {
+        if !names.insert(name.clone()) {
+            return Err(SerializationError::Other(format!(
+                "dotenv export: several fields are flattened to the same variable {}",
+                name
+            )));
+        }
+
+        out.push_str(&name);
+        out.push('=');
+        out.push_str(&value);
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+/// Flatten a record to a list of `(name, value)` pairs, where `prefix` is the flattened name of
+/// the record itself, if it is not the top-level value.
+fn flatten(
+    rt: &RichTerm,
+    prefix: Option<&str>,
+    lines: &mut Vec<(String, String)>,
+) -> Result<(), SerializationError> {
+    let name = || String::from(prefix.unwrap_or("<top-level>"));
+
+    match rt.as_ref() {
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => flatten(t, prefix, lines),
+        Term::Record(map) => sorted_fields(map).into_iter().try_for_each(|(id, t)| {
+            let id = id.to_string();
+            if !is_valid_name(&id) {
+                return Err(SerializationError::Other(format!(
+                    "dotenv export: {} is not a valid variable name",
+                    id
+                )));
+            }
+
+            match prefix {
+                Some(prefix) => flatten(t, Some(&format!("{}_{}", prefix, id)), lines),
+                None => flatten(t, Some(&id), lines),
+            }
+        }),
+        _ if prefix.is_none() => Err(SerializationError::Other(String::from(
+            "dotenv export: the top-level value must be a record",
+        ))),
+        Term::Str(s) => {
+            lines.push((name(), quote(s)));
+            Ok(())
+        }
+        Term::Enum(id) => {
+            lines.push((name(), quote(&id.to_string())));
+            Ok(())
+        }
+        Term::Num(n) => {
+            lines.push((name(), num_to_string(*n)));
+            Ok(())
+        }
+        Term::Bool(b) => {
+            lines.push((name(), b.to_string()));
+            Ok(())
+        }
+        Term::List(_) => Err(SerializationError::Other(format!(
+            "dotenv export: lists can't be represented (variable {})",
+            name()
+        ))),
+        Term::Null => Err(SerializationError::Other(format!(
+            "dotenv export: null can't be represented (variable {})",
+            name()
+        ))),
+        _ => Err(SerializationError::NonSerializable(rt.clone(), None)),
+    }
+}
+
+fn is_valid_name(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with(|c: char| c.is_ascii_digit())
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quote a value with double quotes if it contains characters other than ASCII alphanumeric
+/// characters and `_./:@+,-`. Inside double quotes, `"`, `\`, `$` and `` ` `` are escaped, as
+/// well as line breaks.
+fn quote(s: &str) -> String {
+    if s.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_./:@+,-".contains(c))
+    {
+        return String::from(s);
+    }
+
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' | '$' | '`' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::eval_full_str;
+
+    fn to_dotenv(s: &str) -> Result<String, SerializationError> {
+        to_string(&eval_full_str(s))
+    }
+
+    #[test]
+    fn flattening() {
+        assert_eq!(
+            to_dotenv(
+                r#"{PORT = 80, db = {host = "localhost", user = "a b"}, debug = false, mode = `prod}"#
+            )
+            .unwrap(),
+            "PORT=80\ndb_host=localhost\ndb_user=\"a b\"\ndebug=false\nmode=prod\n"
+        );
+        assert_eq!(
+            to_dotenv(r#"{msg = "say \"$HOME\"\n"}"#).unwrap(),
+            "msg=\"say \\\"\\$HOME\\\"\\n\"\n"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(to_dotenv("1").is_err());
+        assert!(to_dotenv("{a = [1]}").is_err());
+        assert!(to_dotenv("{a = null}").is_err());
+        assert!(to_dotenv(r#"{"a-b" = 1}"#).is_err());
+        assert!(to_dotenv("{a_b = 1, a = {b = 2}}").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Serialization to an INI file.
+//!
+//! The top-level value must be a record. Its fields which are not records are written first, as
+//! global properties. Each field which is a record is written as a section, and records nested
+//! in sections are written as sections whose name is the path of the field, joined with dots:
+//! `{a = {b = {c = 1}}}` gives a section `[a.b]` containing `c = 1`. Empty records are written
+//! as empty sections.
+//!
+//! Strings, numbers, booleans and enum tags are written as values, quoted with double quotes if
+//! needed. Lists and `null` can't be represented and raise an error, as well as names containing
+//! characters which are meaningful in INI files.
+use super::{num_to_string, sorted_fields, strip_meta};
+use crate::error::SerializationError;
+use crate::term::{RichTerm, Term};
+
+/// Serialize a fully evaluated record to an INI file.
+pub fn to_string(rt: &RichTerm) -> Result<String, SerializationError> {
+    let mut out = String::new();
+    section(&mut out, rt, None)?;
+    Ok(out)
+}
+
+/// Write the properties of a record, followed by its subsections. `name` is the name of the
+/// section, or `None` for the top-level record.
+fn section(out: &mut String, rt: &RichTerm, name: Option<&str>) -> Result<(), SerializationError> {
+    let map = match strip_meta(rt).as_ref() {
+        Term::Record(map) => map,
+        _ => {
+            return Err(SerializationError::Other(String::from(
+                "INI export: the top-level value must be a record",
+            )))
+        }
+    };
+
+    let fields = sorted_fields(map);
+    let mut subsections = Vec::new();
+
+    if let Some(name) = name {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push('[');
+        out.push_str(name);
+        out.push_str("]\n");
+    }
+
+    for (id, t) in fields {
+        let id = id.to_string();
+        if !is_valid_name(&id) {
+            return Err(SerializationError::Other(format!(
+                "INI export: {} is not a valid name",
+                id
+            )));
+        }
+
+        let path = match name {
+            Some(name) => format!("{}.{}", name, id),
+            None => id.clone(),
+        };
+
+        let t = strip_meta(t);
+        let value = match t.as_ref() {
+            Term::Record(_) => {
+                subsections.push((path, t));
+                continue;
+            }
+            Term::Str(s) => quote(s),
+            Term::Enum(id) => quote(&id.to_string()),
+            Term::Num(n) => num_to_string(*n),
+            Term::Bool(b) => b.to_string(),
+            Term::List(_) => {
+                return Err(SerializationError::Other(format!(
+                    "INI export: lists can't be represented (field {})",
+                    path
+                )))
+            }
+            Term::Null => {
+                return Err(SerializationError::Other(format!(
+                    "INI export: null can't be represented (field {})",
+                    path
+                )))
+            }
+            _ => return Err(SerializationError::NonSerializable(t.clone(), None)),
+        };
+
+        out.push_str(&id);
+        out.push_str(" = ");
+        out.push_str(&value);
+        out.push('\n');
+    }
+
+    subsections
+        .into_iter()
+        .try_for_each(|(path, t)| section(out, t, Some(&path)))
+}
+
+/// Determine if a string can be used as a property name or as a part of a section name.
+fn is_valid_name(s: &str) -> bool {
+    !s.is_empty()
+        && s.trim() == s
+        && !s.contains(|c: char| "=:;#[].\"".contains(c) || c.is_control())
+}
+
+/// Quote a value with double quotes if it is empty, has leading or trailing whitespace, or
+/// contains characters which are meaningful in INI files. Inside double quotes, `"` and `\` are
+/// escaped, as well as line breaks and tabs.
+fn quote(s: &str) -> String {
+    let needs_quotes = s.is_empty()
+        || s.trim() != s
+        || s.contains(|c: char| ";#\"\\".contains(c) || c.is_control());
+
+    if !needs_quotes {
+        return String::from(s);
+    }
+
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::eval_full_str;
+
+    fn to_ini(s: &str) -> Result<String, SerializationError> {
+        to_string(&eval_full_str(s))
+    }
+
+    #[test]
+    fn sections() {
+        assert_eq!(
+            to_ini(
+                r#"{
+                    name = "app",
+                    server = {port = 80, tls = {enabled = true}, host = "a; b"},
+                    db = {},
+                    mode = `prod,
+                }"#
+            )
+            .unwrap(),
+            "mode = prod\nname = app\n\n[db]\n\n[server]\nhost = \"a; b\"\nport = 80\n\n[server.tls]\nenabled = true\n"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(to_ini("[1]").is_err());
+        assert!(to_ini("{a = [1]}").is_err());
+        assert!(to_ini("{a = {b = null}}").is_err());
+        assert!(to_ini(r#"{"a=b" = 1}"#).is_err());
+        assert!(to_ini(r#"{"a.b" = {c = 1}}"#).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Serialization of a fully evaluated term to Nickel source, used by `nickel convert` to
+//! translate JSON, YAML and TOML documents to Nickel.
+//!
+//! Records are written one field per line, in alphabetical order, and lists of scalar values are
+//! written on a single line if they are short enough. Field names which are not identifiers, or
+//! which are keywords, are quoted. The documentation of a field, if any, is written as a `doc`
+//! annotation. The output can optionally be annotated with a contract.
+use super::sorted_fields;
+use crate::error::SerializationError;
+use crate::term::{MetaValue, RichTerm, Term};
+
+/// The number of spaces used to indent nested records and lists.
+const INDENT: usize = 2;
+
+/// The maximum width of a list written on a single line.
+const MAX_INLINE_WIDTH: usize = 80;
+
+/// The keywords of Nickel, which must be quoted when used as field names.
+const KEYWORDS: &[&str] = &[
+    "Bool",
+    "Dyn",
+    "List",
+    "Num",
+    "Str",
+    "else",
+    "false",
+    "forall",
+    "fun",
+    "if",
+    "import",
+    "import_str",
+    "in",
+    "let",
+    "null",
+    "switch",
+    "then",
+    "true",
+];
+
+/// Serialize a fully evaluated term to Nickel source. If `contract` is given, the result is
+/// annotated with it: it must be a Nickel expression evaluating to a contract, such as `import
+/// "schema.ncl"`.
+pub fn to_string(rt: &RichTerm, contract: Option<&str>) -> Result<String, SerializationError> {
+    let mut out = String::new();
+    write_value(&mut out, rt, 0)?;
+
+    if let Some(contract) = contract {
+        let contract = contract.trim();
+        out.push_str(" | #");
+        if is_identifier(contract) {
+            out.push_str(contract);
+        } else {
+            out.push('(');
+            out.push_str(contract);
+            out.push(')');
+        }
+    }
+
+    out.push('\n');
+    Ok(out)
+}
+
+fn newline(out: &mut String, indent: usize) {
+    out.push('\n');
+    out.push_str(&" ".repeat(indent));
+}
+
+fn write_value(out: &mut String, rt: &RichTerm, indent: usize) -> Result<(), SerializationError> {
+    match rt.as_ref() {
+        Term::Record(map) if map.is_empty() => out.push_str("{}"),
+        Term::Record(map) => {
+            out.push('{');
+            for (id, rt) in sorted_fields(map) {
+                newline(out, indent + INDENT);
+                out.push_str(&field_name(&id.to_string()));
+
+                if let Term::MetaValue(MetaValue { doc: Some(doc), .. }) = rt.as_ref() {
+                    out.push_str(" | doc ");
+                    out.push_str(&quote(doc));
+                }
+
+                out.push_str(" = ");
+                write_value(out, rt, indent + INDENT)?;
+                out.push(',');
+            }
+            newline(out, indent);
+            out.push('}');
+        }
+        Term::List(ts) if ts.is_empty() => out.push_str("[]"),
+        Term::List(ts) => {
+            let scalars = ts
+                .iter()
+                .map(scalar)
+                .collect::<Result<Option<Vec<_>>, _>>()?;
+
+            match scalars {
+                Some(scalars)
+                    if indent + scalars.iter().map(|s| s.len() + 2).sum::<usize>()
+                        <= MAX_INLINE_WIDTH =>
+                {
+                    out.push('[');
+                    out.push_str(&scalars.join(", "));
+                    out.push(']');
+                }
+                _ => {
+                    out.push('[');
+                    for rt in ts.iter() {
+                        newline(out, indent + INDENT);
+                        write_value(out, rt, indent + INDENT)?;
+                        out.push(',');
+                    }
+                    newline(out, indent);
+                    out.push(']');
+                }
+            }
+        }
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => write_value(out, t, indent)?,
+        _ => match scalar(rt)? {
+            Some(s) => out.push_str(&s),
+            None => return Err(SerializationError::NonSerializable(rt.clone(), None)),
+        },
+    }
+
+    Ok(())
+}
+
+/// Return the source of a scalar value, or `None` if the term is not a scalar.
+fn scalar(rt: &RichTerm) -> Result<Option<String>, SerializationError> {
+    let result = match rt.as_ref() {
+        Term::Null => String::from("null"),
+        Term::Bool(b) => b.to_string(),
+        Term::Num(n) if !n.is_finite() => {
+            return Err(SerializationError::Other(format!(
+                "the number {} can't be written as a Nickel literal",
+                n
+            )))
+        }
+        // The shortest representation of a float which round-trips, as given by `Display`, never
+        // uses the scientific notation, which Nickel number literals don't support.
+        Term::Num(n) if *n < 0.0 => format!("-{}", -n),
+        Term::Num(n) => format!("{}", n.abs()),
+        Term::Str(s) => quote(s),
+        Term::Enum(id) => format!("`{}", id),
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => return scalar(t),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(result))
+}
+
+/// Return `true` if a string is an ASCII identifier which is not a keyword.
+pub(crate) fn is_identifier(s: &str) -> bool {
+    let rest = s.strip_prefix('_').unwrap_or(s);
+    let mut chars = rest.chars();
+
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&s)
+}
+
+pub(crate) fn field_name(s: &str) -> String {
+    if is_identifier(s) {
+        String::from(s)
+    } else {
+        quote(s)
+    }
+}
+
+/// Write a string literal, escaping double quotes, backslashes, interpolation sequences and
+/// control characters.
+pub(crate) fn quote(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '#' if chars.peek() == Some(&'{') => result.push_str("\\#"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => result.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => result.push(c),
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::eval_full_str;
+    use serde_json::json;
+
+    #[test]
+    fn layout() {
+        let rt = RichTerm::from(json!({
+            "name": "web",
+            "ports": [80, 443],
+            "if": null,
+            "log-level": -0.5,
+            "backends": [{"host": "a"}, []],
+            "labels": {},
+        }));
+
+        assert_eq!(
+            to_string(&rt, Some("Config")).unwrap(),
+            r#"{
+  backends = [
+    {
+      host = "a",
+    },
+    [],
+  ],
+  "if" = null,
+  labels = {},
+  "log-level" = -0.5,
+  name = "web",
+  ports = [80, 443],
+} | #Config
+"#
+        );
+        assert_eq!(
+            to_string(&eval_full_str("\"a\""), Some("import \"schema.ncl\"")).unwrap(),
+            "\"a\" | #(import \"schema.ncl\")\n"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = json!({
+            "strings": ["quote \" backslash \\ #{interpolation} # hash", "line\nbreak\t\u{1}", "ünïcode"],
+            "numbers": [0, -3, 0.1, 1e300, -2.5e-10, 123456789012345u64],
+            "": {"with space": true, "_private": false, "let": "keyword", "9lives": 9},
+            "long": vec!["abcdefghijklmnopqrstuvwxyz"; 5],
+        });
+        let source = to_string(&RichTerm::from(value.clone()), None).unwrap();
+        assert_eq!(serde_json::to_value(eval_full_str(&source)).unwrap(), value);
+
+        let source = "{a | doc \"The \\\"a\\\" field\" = 1, b = `tag}";
+        let converted = to_string(&eval_full_str(source), None).unwrap();
+        assert_eq!(
+            converted,
+            "{\n  a | doc \"The \\\"a\\\" field\" = 1,\n  b = `tag,\n}\n"
+        );
+        assert_eq!(
+            serde_json::to_value(eval_full_str(&converted)).unwrap(),
+            json!({"a": 1, "b": "tag"})
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            to_string(&eval_full_str("{f = fun x => x}"), None),
+            Err(SerializationError::NonSerializable(..))
+        ));
+        assert!(to_string(&RichTerm::from(Term::Num(f64::INFINITY)), None).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! Serialization to a Nix expression.
+//!
+//! A fully evaluated term is written as the corresponding Nix value: records become attribute
+//! sets, lists become lists, and `null`, booleans, numbers and strings are written as the
+//! corresponding literals. Enum tags are written as strings, as for the other formats.
+//!
+//! Attributes are written in alphabetical order, and the documentation of a field, if any, is
+//! written as a comment right above the attribute.
+use super::{num_to_string, sorted_fields};
+use crate::error::SerializationError;
+use crate::term::{MetaValue, RichTerm, Term};
+
+/// The number of spaces used to indent nested attribute sets and lists.
+const INDENT: usize = 2;
+
+/// The keywords of Nix, which must be quoted when used as attribute names.
+const KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/// Serialize a fully evaluated term to a Nix expression.
+pub fn to_string(rt: &RichTerm) -> Result<String, SerializationError> {
+    let mut out = String::new();
+    write_value(&mut out, rt, 0)?;
+    out.push('\n');
+    Ok(out)
+}
+
+fn newline(out: &mut String, indent: usize) {
+    out.push('\n');
+    out.push_str(&" ".repeat(indent));
+}
+
+fn write_value(out: &mut String, rt: &RichTerm, indent: usize) -> Result<(), SerializationError> {
+    match rt.as_ref() {
+        Term::Null => out.push_str("null"),
+        Term::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Term::Num(n) => out.push_str(&nix_num(*n)?),
+        Term::Str(s) => out.push_str(&quote(s)),
+        Term::Enum(id) => out.push_str(&quote(&id.to_string())),
+        Term::Record(map) if map.is_empty() => out.push_str("{ }"),
+        Term::Record(map) => {
+            out.push('{');
+            for (id, rt) in sorted_fields(map) {
+                if let Term::MetaValue(MetaValue { doc: Some(doc), .. }) = rt.as_ref() {
+                    for line in doc.trim_end().lines() {
+                        newline(out, indent + INDENT);
+                        out.push('#');
+                        if !line.is_empty() {
+                            out.push(' ');
+                            out.push_str(line);
+                        }
+                    }
+                }
+
+                newline(out, indent + INDENT);
+                out.push_str(&attr_name(&id.to_string()));
+                out.push_str(" = ");
+                write_value(out, rt, indent + INDENT)?;
+                out.push(';');
+            }
+            newline(out, indent);
+            out.push('}');
+        }
+        Term::List(ts) if ts.is_empty() => out.push_str("[ ]"),
+        Term::List(ts) => {
+            out.push('[');
+            for rt in ts.iter() {
+                newline(out, indent + INDENT);
+                write_value(out, rt, indent + INDENT)?;
+            }
+            newline(out, indent);
+            out.push(']');
+        }
+        Term::MetaValue(MetaValue { value: Some(t), .. }) => write_value(out, t, indent)?,
+        Term::MetaValue(MetaValue { value: None, .. }) => {
+            return Err(SerializationError::Other(String::from("empty metavalue")))
+        }
+        _ => return Err(SerializationError::NonSerializable(rt.clone(), None)),
+    }
+
+    Ok(())
+}
+
+/// Return the representation of a number. Integers which fit in a 64 bits signed integer are
+/// written as Nix integers, and other numbers as Nix floats, whose literals always contain a
+/// decimal point. Negative numbers are parenthesized, as they would otherwise be parsed as
+/// subtractions inside lists.
+fn nix_num(n: f64) -> Result<String, SerializationError> {
+    if !n.is_finite() {
+        return Err(SerializationError::Other(format!(
+            "Nix doesn't support the number {}",
+            n
+        )));
+    }
+
+    let repr = if n.fract() == 0.0 && n >= (i64::MIN as f64) && n < (i64::MAX as f64) {
+        format!("{}", n as i64)
+    } else {
+        let repr = num_to_string(n);
+        match repr.split_once('e') {
+            Some((mantissa, exp)) if !mantissa.contains('.') => format!("{}.0e{}", mantissa, exp),
+            _ => repr,
+        }
+    };
+
+    if repr.starts_with('-') {
+        Ok(format!("({})", repr))
+    } else {
+        Ok(repr)
+    }
+}
+
+/// Return an attribute name, quoted if it is not a valid Nix identifier.
+fn attr_name(s: &str) -> String {
+    let mut chars = s.chars();
+    let is_ident = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '\'' || c == '-')
+        && !KEYWORDS.contains(&s);
+
+    if is_ident {
+        String::from(s)
+    } else {
+        quote(s)
+    }
+}
+
+/// Write a string as a double-quoted Nix string. Besides the usual escape sequences, `${` is
+/// escaped to prevent interpolation.
+fn quote(s: &str) -> String {
+    let mut result = String::from("\"");
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => result.push_str("\\$"),
+            c => result.push(c),
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::eval_full_str;
+
+    fn to_nix(s: &str) -> String {
+        let rt = eval_full_str(s);
+        to_string(&rt).unwrap()
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(to_nix("null"), "null\n");
+        assert_eq!(
+            to_nix("[true, 1, -2, 0.5, `foo]"),
+            "[\n  true\n  1\n  (-2)\n  0.5\n  \"foo\"\n]\n"
+        );
+        assert_eq!(
+            to_nix("[100000000000000000000000, {}, []]"),
+            "[\n  1.0e23\n  { }\n  [ ]\n]\n"
+        );
+        assert_eq!(
+            to_nix("{b = {c = null}, a = [1]}"),
+            "{\n  a = [\n    1\n  ];\n  b = {\n    c = null;\n  };\n}\n"
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            to_nix(r#""a\"b\\c\n${d} $e""#),
+            "\"a\\\"b\\\\c\\n\\${d} $e\"\n"
+        );
+        assert_eq!(
+            to_nix(r#"{"if" = 1, "a b" = 2, "x-y'" = 3, "1a" = 4}"#),
+            "{\n  \"1a\" = 4;\n  \"a b\" = 2;\n  \"if\" = 1;\n  x-y' = 3;\n}\n"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            to_nix("{a | doc \"The port.\\nMust be positive.\" = 1}"),
+            "{\n  # The port.\n  # Must be positive.\n  a = 1;\n}\n"
+        );
+    }
+}
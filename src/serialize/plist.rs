@@ -0,0 +1,318 @@
+//! Serialization to Apple property lists, in the XML or in the binary (`bplist00`) format.
+//!
+//! Records are written as dictionaries, lists as arrays, and strings and enum tags as strings.
+//! Numbers are written as integers if they are integral and fit in a 64 bits signed integer, and
+//! as reals otherwise. Property lists can't represent `null`.
+use super::xml::escape;
+use super::{sorted_fields, strip_meta};
+use crate::error::SerializationError;
+use crate::term::{RichTerm, Term};
+
+/// Return the integer representing a number, if it is integral and fits in an `i64`.
+fn as_integer(n: f64) -> Option<i64> {
+    if n.fract() == 0.0 && n >= (i64::MIN as f64) && n < (i64::MAX as f64) {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+/// Serialize a fully evaluated term to an XML property list.
+pub fn to_string(rt: &RichTerm) -> Result<String, SerializationError> {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n",
+    );
+    xml_value(&mut out, rt, 0)?;
+    out.push_str("</plist>\n");
+    Ok(out)
+}
+
+fn xml_value(out: &mut String, rt: &RichTerm, indent: usize) -> Result<(), SerializationError> {
+    let rt = strip_meta(rt);
+    out.push_str(&"\t".repeat(indent));
+
+    match rt.as_ref() {
+        Term::Bool(true) => out.push_str("<true/>"),
+        Term::Bool(false) => out.push_str("<false/>"),
+        Term::Num(n) => match as_integer(*n) {
+            Some(i) => out.push_str(&format!("<integer>{}</integer>", i)),
+            None if n.is_finite() => out.push_str(&format!("<real>{:?}</real>", n)),
+            None => {
+                return Err(SerializationError::Other(format!(
+                    "property lists don't support the number {}",
+                    n
+                )))
+            }
+        },
+        Term::Str(s) => out.push_str(&format!("<string>{}</string>", escape(s, false))),
+        Term::Enum(id) => out.push_str(&format!(
+            "<string>{}</string>",
+            escape(&id.to_string(), false)
+        )),
+        Term::Record(map) if map.is_empty() => out.push_str("<dict/>"),
+        Term::Record(map) => {
+            out.push_str("<dict>\n");
+            for (id, t) in sorted_fields(map) {
+                out.push_str(&"\t".repeat(indent + 1));
+                out.push_str(&format!("<key>{}</key>\n", escape(&id.to_string(), false)));
+                xml_value(out, t, indent + 1)?;
+            }
+            out.push_str(&"\t".repeat(indent));
+            out.push_str("</dict>");
+        }
+        Term::List(ts) if ts.is_empty() => out.push_str("<array/>"),
+        Term::List(ts) => {
+            out.push_str("<array>\n");
+            for t in ts.iter() {
+                xml_value(out, t, indent + 1)?;
+            }
+            out.push_str(&"\t".repeat(indent));
+            out.push_str("</array>");
+        }
+        Term::Null => {
+            return Err(SerializationError::UnsupportedNull(
+                super::ExportFormat::Plist,
+                rt.clone(),
+            ))
+        }
+        _ => return Err(SerializationError::NonSerializable(rt.clone(), None)),
+    }
+
+    out.push('\n');
+    Ok(())
+}
+
+/// An object of a binary property list. Arrays and dictionaries refer to other objects by their
+/// index in the object table.
+enum Object {
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    Str(String),
+    Array(Vec<usize>),
+    Dict(Vec<usize>, Vec<usize>),
+}
+
+/// Serialize a fully evaluated term to a binary property list.
+pub fn to_bytes(rt: &RichTerm) -> Result<Vec<u8>, SerializationError> {
+    let mut objects = Vec::new();
+    let top = collect(rt, &mut objects)?;
+    let ref_size = int_size(objects.len() as u64 - 1);
+
+    let mut out = Vec::from(&b"bplist00"[..]);
+    let mut offsets = Vec::with_capacity(objects.len());
+
+    for object in objects.iter() {
+        offsets.push(out.len() as u64);
+        match object {
+            Object::Bool(false) => out.push(0x08),
+            Object::Bool(true) => out.push(0x09),
+            Object::Int(i) => write_int(&mut out, *i),
+            Object::Real(r) => {
+                out.push(0x23);
+                out.extend_from_slice(&r.to_be_bytes());
+            }
+            Object::Str(s) if s.is_ascii() => {
+                write_marker(&mut out, 0x50, s.len());
+                out.extend_from_slice(s.as_bytes());
+            }
+            Object::Str(s) => {
+                let units: Vec<u16> = s.encode_utf16().collect();
+                write_marker(&mut out, 0x60, units.len());
+                for unit in units {
+                    out.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+            Object::Array(refs) => {
+                write_marker(&mut out, 0xA0, refs.len());
+                for r in refs {
+                    write_sized(&mut out, *r as u64, ref_size);
+                }
+            }
+            Object::Dict(keys, values) => {
+                write_marker(&mut out, 0xD0, keys.len());
+                for r in keys.iter().chain(values.iter()) {
+                    write_sized(&mut out, *r as u64, ref_size);
+                }
+            }
+        }
+    }
+
+    let offset_table = out.len() as u64;
+    let offset_size = int_size(offset_table);
+    for offset in offsets {
+        write_sized(&mut out, offset, offset_size);
+    }
+
+    // The trailer: 5 unused bytes, the sort version, the sizes of offsets and references, the
+    // number of objects, the index of the top-level object and the position of the offset table.
+    out.extend_from_slice(&[0; 6]);
+    out.push(offset_size as u8);
+    out.push(ref_size as u8);
+    out.extend_from_slice(&(objects.len() as u64).to_be_bytes());
+    out.extend_from_slice(&(top as u64).to_be_bytes());
+    out.extend_from_slice(&offset_table.to_be_bytes());
+
+    Ok(out)
+}
+
+/// Add a term and its subterms to the object table, and return the index of the term.
+fn collect(rt: &RichTerm, objects: &mut Vec<Object>) -> Result<usize, SerializationError> {
+    let rt = strip_meta(rt);
+
+    let object = match rt.as_ref() {
+        Term::Bool(b) => Object::Bool(*b),
+        Term::Num(n) => match as_integer(*n) {
+            Some(i) => Object::Int(i),
+            None if n.is_finite() => Object::Real(*n),
+            None => {
+                return Err(SerializationError::Other(format!(
+                    "property lists don't support the number {}",
+                    n
+                )))
+            }
+        },
+        Term::Str(s) => Object::Str(s.clone()),
+        Term::Enum(id) => Object::Str(id.to_string()),
+        Term::Record(map) => {
+            let mut keys = Vec::with_capacity(map.len());
+            let mut values = Vec::with_capacity(map.len());
+            for (id, t) in sorted_fields(map) {
+                objects.push(Object::Str(id.to_string()));
+                keys.push(objects.len() - 1);
+                values.push(collect(t, objects)?);
+            }
+            Object::Dict(keys, values)
+        }
+        Term::List(ts) => Object::Array(
+            ts.iter()
+                .map(|t| collect(t, objects))
+                .collect::<Result<_, _>>()?,
+        ),
+        Term::Null => {
+            return Err(SerializationError::UnsupportedNull(
+                super::ExportFormat::BinaryPlist,
+                rt.clone(),
+            ))
+        }
+        _ => return Err(SerializationError::NonSerializable(rt.clone(), None)),
+    };
+
+    objects.push(object);
+    Ok(objects.len() - 1)
+}
+
+/// Return the number of bytes (1, 2, 4 or 8) needed to represent an unsigned integer.
+fn int_size(n: u64) -> usize {
+    if n <= u8::MAX as u64 {
+        1
+    } else if n <= u16::MAX as u64 {
+        2
+    } else if n <= u32::MAX as u64 {
+        4
+    } else {
+        8
+    }
+}
+
+/// Write the `size` lowest bytes of an unsigned integer, in big-endian order.
+fn write_sized(out: &mut Vec<u8>, n: u64, size: usize) {
+    out.extend_from_slice(&n.to_be_bytes()[8 - size..]);
+}
+
+/// Write an integer object. Negative integers are always written on 8 bytes.
+fn write_int(out: &mut Vec<u8>, i: i64) {
+    let size = if i < 0 { 8 } else { int_size(i as u64) };
+    out.push(0x10 | size.trailing_zeros() as u8);
+    write_sized(out, i as u64, size);
+}
+
+/// Write the marker of an object with a length, which is stored in the marker itself if it is
+/// smaller than 15, and as a following integer object otherwise.
+fn write_marker(out: &mut Vec<u8>, kind: u8, len: usize) {
+    if len < 15 {
+        out.push(kind | len as u8);
+    } else {
+        out.push(kind | 0x0F);
+        write_int(out, len as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::eval_full_str;
+
+    #[test]
+    fn xml() {
+        assert_eq!(
+            to_string(&eval_full_str(r#"{b = [1, 0.5, true], a = "<x>", c = {}}"#)).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+             \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n\
+             <dict>\n\
+             \t<key>a</key>\n\
+             \t<string>&lt;x&gt;</string>\n\
+             \t<key>b</key>\n\
+             \t<array>\n\
+             \t\t<integer>1</integer>\n\
+             \t\t<real>0.5</real>\n\
+             \t\t<true/>\n\
+             \t</array>\n\
+             \t<key>c</key>\n\
+             \t<dict/>\n\
+             </dict>\n\
+             </plist>\n"
+        );
+        assert!(to_string(&eval_full_str("{a = null}")).is_err());
+    }
+
+    #[test]
+    fn binary() {
+        let bytes = to_bytes(&eval_full_str(
+            r#"{a = [-1, 1.5], "é" = "abcdefghijklmnop"}"#,
+        ))
+        .unwrap();
+
+        let mut expected = Vec::from(&b"bplist00"[..]);
+        let mut offsets = Vec::new();
+        let mut object = |expected: &mut Vec<u8>, bytes: &[u8]| {
+            offsets.push(expected.len() as u8);
+            expected.extend_from_slice(bytes);
+        };
+        // 0: "a"
+        object(&mut expected, &[0x51, b'a']);
+        // 1: -1
+        object(
+            &mut expected,
+            &[0x13, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        );
+        // 2: 1.5
+        let mut real = vec![0x23];
+        real.extend_from_slice(&1.5f64.to_be_bytes());
+        object(&mut expected, &real);
+        // 3: [-1, 1.5]
+        object(&mut expected, &[0xA2, 1, 2]);
+        // 4: "é"
+        object(&mut expected, &[0x61, 0x00, 0xE9]);
+        // 5: "abcdefghijklmnop", whose length is written as a separate integer
+        let mut long = vec![0x5F, 0x10, 16];
+        long.extend_from_slice(b"abcdefghijklmnop");
+        object(&mut expected, &long);
+        // 6: the top-level dictionary
+        object(&mut expected, &[0xD2, 0, 4, 3, 5]);
+
+        let offset_table = expected.len() as u64;
+        expected.extend_from_slice(&offsets);
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 1, 1]);
+        expected.extend_from_slice(&7u64.to_be_bytes());
+        expected.extend_from_slice(&6u64.to_be_bytes());
+        expected.extend_from_slice(&offset_table.to_be_bytes());
+
+        assert_eq!(bytes, expected);
+    }
+}
@@ -0,0 +1,226 @@
+//! Serialization to XML, using a simple canonical mapping.
+//!
+//! The document has a single `<root>` element, which holds the top-level value:
+//!
+//! - the fields of a record are written as child elements named after the fields, or as
+//!   attributes for the fields whose value is not a list or a record if
+//!   [`RecordMapping::Attributes`](enum.RecordMapping.html) is selected
+//! - the elements of a list are written as `<item>` child elements
+//! - `null` is written as an empty element, and other values as text
+//!
+//! This mapping is lossy: numbers, booleans and strings are all written as text, and `null`
+//! can't be distinguished from the empty string. Fields are written in alphabetical order, as for
+//! the other formats.
+use super::{num_to_string, sorted_fields, strip_meta};
+use crate::error::SerializationError;
+use crate::term::{RichTerm, Term};
+use std::fmt;
+use std::str::FromStr;
+
+/// The number of spaces used to indent nested elements.
+const INDENT: usize = 2;
+
+/// The mapping of the fields of records.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum RecordMapping {
+    /// Every field is written as a child element.
+    #[default]
+    Elements,
+    /// Fields whose value is not a list or a record are written as attributes, and the other
+    /// ones as child elements.
+    Attributes,
+}
+
+/// Options of the XML serializer.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct XmlOptions {
+    pub records: RecordMapping,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseOptionError(String);
+
+impl fmt::Display for ParseOptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported XML record mapping {}", self.0)
+    }
+}
+
+impl FromStr for RecordMapping {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "elements" => Ok(RecordMapping::Elements),
+            "attributes" => Ok(RecordMapping::Attributes),
+            _ => Err(ParseOptionError(String::from(s))),
+        }
+    }
+}
+
+/// Serialize a fully evaluated term to an XML document.
+pub fn to_string(rt: &RichTerm, options: &XmlOptions) -> Result<String, SerializationError> {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    element(&mut out, "root", rt, 0, options)?;
+    out.push('\n');
+    Ok(out)
+}
+
+/// Return the text representing a scalar value, or `None` for `null`, lists and records.
+fn text(rt: &RichTerm) -> Result<Option<String>, SerializationError> {
+    match rt.as_ref() {
+        Term::Bool(b) => Ok(Some(b.to_string())),
+        Term::Num(n) => Ok(Some(num_to_string(*n))),
+        Term::Str(s) => Ok(Some(s.clone())),
+        Term::Enum(id) => Ok(Some(id.to_string())),
+        Term::Null | Term::List(_) | Term::Record(_) => Ok(None),
+        _ => Err(SerializationError::NonSerializable(rt.clone(), None)),
+    }
+}
+
+fn newline(out: &mut String, indent: usize) {
+    out.push('\n');
+    out.push_str(&" ".repeat(indent));
+}
+
+/// Write an element named `name` holding the value `rt`. The first line must have already been
+/// indented.
+fn element(
+    out: &mut String,
+    name: &str,
+    rt: &RichTerm,
+    indent: usize,
+    options: &XmlOptions,
+) -> Result<(), SerializationError> {
+    let rt = strip_meta(rt);
+    out.push('<');
+    out.push_str(name);
+
+    let mut children = Vec::new();
+    match rt.as_ref() {
+        Term::Record(map) => {
+            for (id, t) in sorted_fields(map) {
+                let id = id.to_string();
+                if !is_valid_name(&id) {
+                    return Err(SerializationError::Other(format!(
+                        "XML export: {} is not a valid element or attribute name",
+                        id
+                    )));
+                }
+
+                let t = strip_meta(t);
+                match text(t)? {
+                    Some(s) if options.records == RecordMapping::Attributes => {
+                        out.push(' ');
+                        out.push_str(&id);
+                        out.push_str("=\"");
+                        out.push_str(&escape(&s, true));
+                        out.push('"');
+                    }
+                    _ => children.push((id, t)),
+                }
+            }
+        }
+        Term::List(ts) => {
+            children = ts.iter().map(|t| (String::from("item"), t)).collect();
+        }
+        _ => (),
+    }
+
+    match text(rt)? {
+        Some(s) => {
+            out.push('>');
+            out.push_str(&escape(&s, false));
+        }
+        None if children.is_empty() => {
+            out.push_str("/>");
+            return Ok(());
+        }
+        None => {
+            out.push('>');
+            for (name, t) in children {
+                newline(out, indent + INDENT);
+                element(out, &name, t, indent + INDENT, options)?;
+            }
+            newline(out, indent);
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(name);
+    out.push('>');
+    Ok(())
+}
+
+/// Determine if a string is a valid XML name. This is more restrictive than the XML
+/// specification, which also allows a wide range of non-ASCII characters.
+fn is_valid_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Escape the text of an element, or the value of an attribute if `attribute` is `true`.
+pub(super) fn escape(s: &str, attribute: bool) -> String {
+    let mut result = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' if attribute => result.push_str("&quot;"),
+            '\n' if attribute => result.push_str("&#10;"),
+            '\r' => result.push_str("&#13;"),
+            '\t' if attribute => result.push_str("&#9;"),
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::eval_full_str;
+
+    fn to_xml(s: &str, records: RecordMapping) -> String {
+        let rt = eval_full_str(s);
+        to_string(&rt, &XmlOptions { records }).unwrap()
+    }
+
+    const HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+
+    #[test]
+    fn elements() {
+        assert_eq!(
+            to_xml("1", Default::default()),
+            format!("{}<root>1</root>\n", HEADER)
+        );
+        assert_eq!(
+            to_xml(
+                r#"{b = [1, null, "<&>"], a = {c = true}, d = {}}"#,
+                Default::default()
+            ),
+            format!(
+                "{}<root>\n  <a>\n    <c>true</c>\n  </a>\n  <b>\n    <item>1</item>\n    <item/>\n    <item>&lt;&amp;&gt;</item>\n  </b>\n  <d/>\n</root>\n",
+                HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(
+            to_xml(
+                r#"{name = "a \"b\"", server = {port = 80, hosts = ["x"]}, empty = null}"#,
+                RecordMapping::Attributes
+            ),
+            format!(
+                "{}<root name=\"a &quot;b&quot;\">\n  <empty/>\n  <server port=\"80\">\n    <hosts>\n      <item>x</item>\n    </hosts>\n  </server>\n</root>\n",
+                HEADER
+            )
+        );
+    }
+}
@@ -0,0 +1,732 @@
+//! Serialization to YAML.
+//!
+//! Contrary to the other formats, YAML is not written through `serde`, in order to control the
+//! layout of the output:
+//!
+//! - [`YamlStyle`](enum.YamlStyle.html) selects between the indentation-based block style and
+//!   the JSON-like flow style.
+//! - [`MultilineStyle`](enum.MultilineStyle.html) selects how strings spanning several lines are
+//!   written in block style: as literal (`|`) or folded (`>`) block scalars, or as double-quoted
+//!   strings.
+//! - Identical lists and records are only written once and then referred to using anchors and
+//!   aliases (`&id001` and `*id001`) if [`YamlOptions::aliases`](struct.YamlOptions.html) is set.
+//!   Many tools don't support aliases, which are thus never emitted by default.
+//!
+//! - If [`YamlOptions::multi_document`](struct.YamlOptions.html) is set, the elements of a list
+//!   are written as a stream of separate documents, as expected by `kubectl apply` for example.
+//!
+//! Fields are written in alphabetical order, as for the other formats.
+use super::{num_to_string, sorted_fields, strip_meta};
+use crate::error::SerializationError;
+use crate::term::{RichTerm, Term};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// The width after which long lines of folded block scalars are wrapped.
+const FOLD_WIDTH: usize = 80;
+
+/// The layout of lists and records.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum YamlStyle {
+    /// Indentation-based layout, with one element or field per line.
+    #[default]
+    Block,
+    /// JSON-like layout, delimited by brackets and braces, on a single line.
+    Flow,
+}
+
+/// The representation of strings spanning several lines, in block style.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum MultilineStyle {
+    /// Literal block scalars (`|`), which preserve the line breaks of the string.
+    #[default]
+    Literal,
+    /// Folded block scalars (`>`), where long lines are wrapped.
+    Folded,
+    /// Double-quoted strings, where line breaks are escaped.
+    Quoted,
+}
+
+/// Options of the YAML serializer.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct YamlOptions {
+    pub style: YamlStyle,
+    pub multiline: MultilineStyle,
+    /// Write identical lists and records only once, and then refer to them using aliases.
+    pub aliases: bool,
+    /// Write each element of a list as a separate document. Aliases can't refer to anchors of
+    /// other documents.
+    pub multi_document: bool,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseOptionError(String);
+
+impl fmt::Display for ParseOptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported YAML option {}", self.0)
+    }
+}
+
+impl FromStr for YamlStyle {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "block" => Ok(YamlStyle::Block),
+            "flow" => Ok(YamlStyle::Flow),
+            _ => Err(ParseOptionError(String::from(s))),
+        }
+    }
+}
+
+impl FromStr for MultilineStyle {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "literal" => Ok(MultilineStyle::Literal),
+            "folded" => Ok(MultilineStyle::Folded),
+            "quoted" => Ok(MultilineStyle::Quoted),
+            _ => Err(ParseOptionError(String::from(s))),
+        }
+    }
+}
+
+/// Serialize a fully evaluated term to a YAML document, or to a stream of documents if
+/// `options.multi_document` is set.
+pub fn to_string(rt: &RichTerm, options: &YamlOptions) -> Result<String, SerializationError> {
+    if !options.multi_document {
+        return document(rt, options);
+    }
+
+    match strip_meta(rt).as_ref() {
+        Term::List(ts) => ts.iter().map(|t| document(t, options)).collect(),
+        _ => Err(SerializationError::NotAList(
+            String::from("multi-document YAML"),
+            rt.clone(),
+        )),
+    }
+}
+
+/// Serialize a fully evaluated term to a single YAML document.
+fn document(rt: &RichTerm, options: &YamlOptions) -> Result<String, SerializationError> {
+    let mut emitter = Emitter {
+        options,
+        out: String::from("---"),
+        anchors: HashMap::new(),
+        next_anchor: 1,
+    };
+
+    if options.aliases {
+        emitter.collect_shared(rt);
+    }
+
+    let t = strip_meta(rt).as_ref();
+    if options.style == YamlStyle::Block && is_collection(t) {
+        emitter.out.push('\n');
+        emitter.block(t, 0)?;
+    } else {
+        emitter.out.push(' ');
+        emitter.inline(t, 2)?;
+    }
+
+    emitter.out.push('\n');
+    Ok(emitter.out)
+}
+
+/// Determine if a term is a non-empty list or record.
+fn is_collection(t: &Term) -> bool {
+    match t {
+        Term::Record(map) => !map.is_empty(),
+        Term::List(ts) => !ts.is_empty(),
+        _ => false,
+    }
+}
+
+/// The state of an anchor: either assigned to a list or a record which is written several times,
+/// or already written, in which case the next occurrences are aliases.
+enum Anchor {
+    Shared,
+    Written(String),
+}
+
+/// What must be written in front of a list or a record, as returned by [`Emitter::anchor`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+enum AnchorOutput {
+    /// Nothing.
+    None,
+    /// An anchor (`&id001`), which must be followed by the value.
+    Anchor(String),
+    /// An alias (`*id001`), which replaces the value.
+    Alias(String),
+}
+
+struct Emitter<'a> {
+    options: &'a YamlOptions,
+    out: String,
+    /// The anchors of the lists and records occurring several times, indexed by their JSON
+    /// representation.
+    anchors: HashMap<String, Anchor>,
+    next_anchor: usize,
+}
+
+impl<'a> Emitter<'a> {
+    /// Find the non-empty lists and records occurring several times in a term, and register an
+    /// anchor for them.
+    fn collect_shared(&mut self, rt: &RichTerm) {
+        fn count(t: &Term, counts: &mut HashMap<String, usize>) {
+            let children: Vec<&RichTerm> = match t {
+                Term::Record(map) if !map.is_empty() => map.values().collect(),
+                Term::List(ts) if !ts.is_empty() => ts.iter().collect(),
+                _ => return,
+            };
+
+            if let Ok(key) = serde_json::to_string(t) {
+                *counts.entry(key).or_insert(0) += 1;
+            }
+
+            for child in children {
+                count(strip_meta(child).as_ref(), counts);
+            }
+        }
+
+        let mut counts = HashMap::new();
+        count(strip_meta(rt).as_ref(), &mut counts);
+
+        self.anchors = counts
+            .into_iter()
+            .filter(|(_, n)| *n > 1)
+            .map(|(key, _)| (key, Anchor::Shared))
+            .collect();
+    }
+
+    /// Return the anchor or the alias of a list or a record, if any.
+    fn anchor(&mut self, t: &Term) -> Result<AnchorOutput, SerializationError> {
+        if self.anchors.is_empty() {
+            return Ok(AnchorOutput::None);
+        }
+
+        let key =
+            serde_json::to_string(t).map_err(|err| SerializationError::Other(err.to_string()))?;
+        match self.anchors.get_mut(&key) {
+            Some(Anchor::Written(name)) => Ok(AnchorOutput::Alias(format!("*{}", name))),
+            Some(anchor) => {
+                let name = format!("id{:03}", self.next_anchor);
+                self.next_anchor += 1;
+                let result = AnchorOutput::Anchor(format!("&{}", name));
+                *anchor = Anchor::Written(name);
+                Ok(result)
+            }
+            None => Ok(AnchorOutput::None),
+        }
+    }
+
+    /// Write the anchor or the alias of a list or a record in flow style, if any. Return `true`
+    /// if an alias was written, in which case the value itself must not be written.
+    fn flow_anchor(&mut self, t: &Term) -> Result<bool, SerializationError> {
+        match self.anchor(t)? {
+            AnchorOutput::Alias(alias) => {
+                self.out.push_str(&alias);
+                Ok(true)
+            }
+            AnchorOutput::Anchor(anchor) => {
+                self.out.push_str(&anchor);
+                self.out.push(' ');
+                Ok(false)
+            }
+            AnchorOutput::None => Ok(false),
+        }
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+    }
+
+    /// Write a non-empty list or record in block style. The first line must have already been
+    /// indented, and the following ones are indented by `indent`.
+    fn block(&mut self, t: &Term, indent: usize) -> Result<(), SerializationError> {
+        match t {
+            Term::Record(map) => {
+                for (i, (id, rt)) in sorted_fields(map).into_iter().enumerate() {
+                    if i > 0 {
+                        self.newline(indent);
+                    }
+                    self.string(id.to_string().as_str(), indent, true);
+                    self.out.push(':');
+                    self.block_value(strip_meta(rt).as_ref(), indent + 2, true)?;
+                }
+            }
+            Term::List(ts) => {
+                for (i, rt) in ts.iter().enumerate() {
+                    if i > 0 {
+                        self.newline(indent);
+                    }
+                    self.out.push('-');
+                    self.block_value(strip_meta(rt).as_ref(), indent + 2, false)?;
+                }
+            }
+            t => self.inline(t, indent)?,
+        }
+
+        Ok(())
+    }
+
+    /// Write the value of a field (if `is_field` is `true`) or the element of a list in block
+    /// style, right after the `:` or the `-`.
+    fn block_value(
+        &mut self,
+        t: &Term,
+        indent: usize,
+        is_field: bool,
+    ) -> Result<(), SerializationError> {
+        if !is_collection(t) {
+            self.out.push(' ');
+            return self.inline(t, indent);
+        }
+
+        // The first field of a record or the first element of a list which is itself an element
+        // of a list can be written on the same line, unless an anchor was just written.
+        match self.anchor(t)? {
+            AnchorOutput::Alias(alias) => {
+                self.out.push(' ');
+                self.out.push_str(&alias);
+                return Ok(());
+            }
+            AnchorOutput::Anchor(anchor) => {
+                self.out.push(' ');
+                self.out.push_str(&anchor);
+                self.newline(indent);
+            }
+            AnchorOutput::None if is_field => self.newline(indent),
+            AnchorOutput::None => self.out.push(' '),
+        }
+        self.block(t, indent)
+    }
+
+    /// Write a scalar, or a list or a record in flow style.
+    fn inline(&mut self, t: &Term, indent: usize) -> Result<(), SerializationError> {
+        match t {
+            Term::Null => self.out.push('~'),
+            Term::Bool(b) => self.out.push_str(if *b { "true" } else { "false" }),
+            Term::Num(n) => self.out.push_str(&yaml_num(*n)),
+            Term::Str(s) => self.string(s, indent, false),
+            Term::Enum(id) => self.string(id.to_string().as_str(), indent, false),
+            Term::Record(map) if map.is_empty() => self.out.push_str("{}"),
+            Term::List(ts) if ts.is_empty() => self.out.push_str("[]"),
+            Term::Record(map) => {
+                if self.flow_anchor(t)? {
+                    return Ok(());
+                }
+
+                self.out.push('{');
+                for (i, (id, rt)) in sorted_fields(map).into_iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.string(id.to_string().as_str(), indent, true);
+                    self.out.push_str(": ");
+                    self.inline(strip_meta(rt).as_ref(), indent)?;
+                }
+                self.out.push('}');
+            }
+            Term::List(ts) => {
+                if self.flow_anchor(t)? {
+                    return Ok(());
+                }
+
+                self.out.push('[');
+                for (i, rt) in ts.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.inline(strip_meta(rt).as_ref(), indent)?;
+                }
+                self.out.push(']');
+            }
+            t => {
+                return Err(SerializationError::Other(format!(
+                    "cannot serialize a value of type {} to YAML",
+                    t.type_of().unwrap_or_else(|| String::from("<unevaluated>"))
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write a string. Keys and strings in flow style are always written on one line.
+    fn string(&mut self, s: &str, indent: usize, is_key: bool) {
+        let block = !is_key
+            && self.options.style == YamlStyle::Block
+            && self.options.multiline != MultilineStyle::Quoted
+            && s.contains('\n')
+            && fits_block_scalar(s);
+
+        if is_plain(s) {
+            self.out.push_str(s);
+        } else if block && self.options.multiline == MultilineStyle::Folded && fits_folded(s) {
+            self.block_scalar('>', &fold(s), s, indent);
+        } else if block {
+            self.block_scalar('|', s, s, indent);
+        } else {
+            self.out.push_str(&quote(s));
+        }
+    }
+
+    /// Write a block scalar with the given indicator (`|` or `>`). `content` is the text to
+    /// write, and `original` the string it represents, which determines the chomping indicator.
+    fn block_scalar(&mut self, indicator: char, content: &str, original: &str, indent: usize) {
+        let trailing = original.len() - original.trim_end_matches('\n').len();
+
+        self.out.push(indicator);
+        match trailing {
+            0 => self.out.push('-'),
+            1 => (),
+            _ => self.out.push('+'),
+        }
+
+        for line in content.trim_end_matches('\n').split('\n') {
+            if line.is_empty() {
+                self.out.push('\n');
+            } else {
+                self.newline(indent);
+                self.out.push_str(line);
+            }
+        }
+
+        for _ in 1..trailing {
+            self.out.push('\n');
+        }
+    }
+}
+
+/// Return the representation of a number. Integers are written without a decimal point, and the
+/// exponent of the scientific notation is always signed and preceded by a decimal point, as
+/// required by YAML 1.1.
+fn yaml_num(n: f64) -> String {
+    if n.is_nan() {
+        return String::from(".nan");
+    } else if n.is_infinite() {
+        return String::from(if n > 0.0 { ".inf" } else { "-.inf" });
+    }
+
+    let repr = num_to_string(n);
+    match repr.split_once('e') {
+        Some((mantissa, exp)) => {
+            let mantissa = if mantissa.contains('.') {
+                String::from(mantissa)
+            } else {
+                format!("{}.0", mantissa)
+            };
+            let exp = if exp.starts_with('-') {
+                String::from(exp)
+            } else {
+                format!("+{}", exp)
+            };
+            format!("{}e{}", mantissa, exp)
+        }
+        None => repr,
+    }
+}
+
+/// Determine if a string can be written as a plain scalar, without being mistaken for another
+/// value (such as a number, a boolean or `null`) or clashing with the YAML syntax. This is
+/// conservative: any string starting with a digit is quoted, for example.
+fn is_plain(s: &str) -> bool {
+    const RESERVED: &[&str] = &[
+        "true", "false", "yes", "no", "y", "n", "on", "off", "null", ".inf", ".nan",
+    ];
+
+    let mut chars = s.chars();
+    let first_ok = chars
+        .next()
+        .map(|c| c.is_alphabetic() || c == '_' || c == '/')
+        .unwrap_or(false);
+
+    first_ok
+        && !s.ends_with(' ')
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || " _-./+@()=^$".contains(c))
+        && !RESERVED.contains(&s.to_lowercase().as_str())
+}
+
+/// Determine if a string can be written as a block scalar. Block scalars can't represent control
+/// characters, nor a first line starting with a space (which would be taken as indentation).
+fn fits_block_scalar(s: &str) -> bool {
+    let first_line = s.split('\n').find(|line| !line.is_empty()).unwrap_or("");
+
+    s.chars().all(|c| c == '\n' || c == '\t' || !c.is_control())
+        && !first_line.starts_with(' ')
+        && !first_line.starts_with('\t')
+        && s.contains(|c: char| c != '\n')
+        && !s.contains('\u{feff}')
+}
+
+/// Determine if a string can be written as a folded block scalar. Lines starting with a space or
+/// a tab are "more-indented" lines, whose line breaks are not folded: we fall back to literal
+/// block scalars in this case.
+fn fits_folded(s: &str) -> bool {
+    s.split('\n')
+        .all(|line| !line.starts_with(' ') && !line.starts_with('\t'))
+}
+
+/// Compute the content of a folded block scalar representing `s`. In a folded scalar, a single
+/// line break is read as a space, and `n + 1` consecutive line breaks as `n` newlines. Each run of
+/// newlines thus gets one more line break, and long lines are wrapped at single spaces. Leading
+/// line breaks don't follow a line, and are written as is.
+fn fold(s: &str) -> String {
+    let body = s.trim_end_matches('\n');
+    let content = body.trim_start_matches('\n');
+    let mut result = "\n".repeat(body.len() - content.len());
+    let mut lines = content.split('\n').peekable();
+
+    while let Some(line) = lines.next() {
+        result.push_str(&wrap(line));
+
+        if lines.peek().is_some() {
+            result.push('\n');
+            // A run of n newlines yields n - 1 empty lines, followed by a non-empty line.
+            while let Some(&"") = lines.peek() {
+                result.push('\n');
+                lines.next();
+            }
+            result.push('\n');
+        }
+    }
+
+    result
+}
+
+/// Wrap a line at single spaces, so that its parts don't exceed `FOLD_WIDTH` characters when
+/// possible.
+fn wrap(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut result = String::new();
+    let mut width = 0;
+
+    for (i, c) in chars.iter().enumerate() {
+        let single_space = *c == ' '
+            && i > 0
+            && chars[i - 1] != ' '
+            && chars.get(i + 1).is_some_and(|next| *next != ' ');
+
+        if single_space && width >= FOLD_WIDTH {
+            result.push('\n');
+            width = 0;
+        } else {
+            result.push(*c);
+            width += 1;
+        }
+    }
+
+    result
+}
+
+/// Write a string as a double-quoted scalar.
+fn quote(s: &str) -> String {
+    let mut result = String::from("\"");
+
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            '\0' => result.push_str("\\0"),
+            c if c.is_control() || c == '\u{feff}' => {
+                result.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => result.push(c),
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::eval_full_str;
+
+    fn to_yaml(s: &str, options: YamlOptions) -> String {
+        let rt = eval_full_str(s);
+        let result = to_string(&rt, &options).unwrap();
+
+        // The output must be valid YAML representing the same value as the JSON serializer.
+        let from_yaml: serde_json::Value = serde_yaml::from_str(&result).unwrap();
+        assert_eq!(from_yaml, serde_json::to_value(&rt).unwrap());
+        result
+    }
+
+    fn with_style(style: YamlStyle) -> YamlOptions {
+        YamlOptions {
+            style,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn block_style() {
+        assert_eq!(to_yaml("1", Default::default()), "--- 1\n");
+        assert_eq!(
+            to_yaml(
+                "{b = [1, [2, 3], {c = null}], a = {}, d = []}",
+                Default::default()
+            ),
+            "---\na: {}\nb:\n  - 1\n  - - 2\n    - 3\n  - c: ~\nd: []\n"
+        );
+    }
+
+    #[test]
+    fn flow_style() {
+        assert_eq!(
+            to_yaml(
+                "{b = [1, [2, 3], {c = null}], a = {}}",
+                with_style(YamlStyle::Flow)
+            ),
+            "--- {a: {}, b: [1, [2, 3], {c: ~}]}\n"
+        );
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(
+            to_yaml(
+                r#"["true", "1.5", "", "a: b", "plain text", 1.5, 100000000000000000000000, -0.5, `Foo]"#,
+                with_style(YamlStyle::Flow)
+            ),
+            r#"--- ["true", "1.5", "", "a: b", plain text, 1.5, 1.0e+23, -0.5, Foo]"#.to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn multiline_strings() {
+        let source = r#"{a = "line 1\nline 2\n", b = "no newline\nat the end", c = "more\n\n\n"}"#;
+
+        assert_eq!(
+            to_yaml(source, Default::default()),
+            "---\na: |\n  line 1\n  line 2\nb: |-\n  no newline\n  at the end\nc: |+\n  more\n\n\n"
+        );
+        assert_eq!(
+            to_yaml(
+                source,
+                YamlOptions {
+                    multiline: MultilineStyle::Folded,
+                    ..Default::default()
+                }
+            ),
+            "---\na: >\n  line 1\n\n  line 2\nb: >-\n  no newline\n\n  at the end\nc: >+\n  more\n\n\n"
+        );
+        assert_eq!(
+            to_yaml(
+                source,
+                YamlOptions {
+                    multiline: MultilineStyle::Quoted,
+                    ..Default::default()
+                }
+            ),
+            "---\na: \"line 1\\nline 2\\n\"\nb: \"no newline\\nat the end\"\nc: \"more\\n\\n\\n\"\n"
+        );
+
+        // Long lines are wrapped by the folded style, and a leading space forbids block scalars.
+        let long = "word ".repeat(30);
+        to_yaml(
+            &format!(r#"{{a = "{}\n", b = " a\nb"}}"#, long.trim_end()),
+            YamlOptions {
+                multiline: MultilineStyle::Folded,
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn round_trip_strings() {
+        let strings = [
+            r#""\nlead""#,
+            r#""\n\nlead\n\ntrail\n\n""#,
+            r#""a\nb\n\nc\n\n\nd""#,
+            r#""a b\n""#,
+            r#""x\n\n""#,
+        ];
+
+        for multiline in &[MultilineStyle::Literal, MultilineStyle::Folded] {
+            let options = YamlOptions {
+                multiline: *multiline,
+                ..Default::default()
+            };
+
+            // `to_yaml` checks that the output is read back as the original value.
+            for s in &strings {
+                to_yaml(&format!("{{f = {}}}", s), options.clone());
+            }
+        }
+
+        assert_eq!(
+            to_yaml(
+                r#"{f = "\nlead"}"#,
+                YamlOptions {
+                    multiline: MultilineStyle::Folded,
+                    ..Default::default()
+                }
+            ),
+            "---\nf: >-\n\n  lead\n"
+        );
+    }
+
+    #[test]
+    fn multi_document() {
+        let options = YamlOptions {
+            multi_document: true,
+            aliases: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            to_string(
+                &eval_full_str("let x = {a = [1]} in [{kind = \"Service\", spec = x}, {metadata = x}, x, 1]"),
+                &options
+            )
+            .unwrap(),
+            "---\nkind: Service\nspec:\n  a:\n    - 1\n---\nmetadata:\n  a:\n    - 1\n---\na:\n  - 1\n--- 1\n"
+        );
+        assert_eq!(to_string(&eval_full_str("[]"), &options).unwrap(), "");
+        assert!(to_string(&eval_full_str("{a = 1}"), &options).is_err());
+    }
+
+    #[test]
+    fn aliases() {
+        let source = "let x = {a = [1, 2]} in {b = x, c = x, d = [1, 2]}";
+
+        assert_eq!(
+            to_yaml(source, Default::default()),
+            "---\nb:\n  a:\n    - 1\n    - 2\nc:\n  a:\n    - 1\n    - 2\nd:\n  - 1\n  - 2\n"
+        );
+        assert_eq!(
+            to_yaml(
+                source,
+                YamlOptions {
+                    aliases: true,
+                    ..Default::default()
+                }
+            ),
+            "---\nb: &id001\n  a: &id002\n    - 1\n    - 2\nc: *id001\nd: *id002\n"
+        );
+        assert_eq!(
+            to_yaml(
+                source,
+                YamlOptions {
+                    style: YamlStyle::Flow,
+                    aliases: true,
+                    ..Default::default()
+                }
+            ),
+            "--- {b: &id001 {a: &id002 [1, 2]}, c: *id001, d: *id002}\n"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Snapshot testing: comparison of the evaluated result of programs with golden files.
+//!
+//! The snapshot of a program is the canonical JSON serialization of its fully evaluated result:
+//! indented by two spaces, with the fields of records sorted alphabetically and numbers written
+//! as by [`serialize_num`](../serialize/fn.serialize_num.html), so that it only changes when the
+//! generated configuration does. Snapshots are checked in next to the programs, and a change of
+//! the result is reported as a [structural diff](../diff/index.html) rather than a textual one.
+use crate::diff::{diff, Change};
+use crate::error::{Error, IOError};
+use crate::program::Program;
+use crate::serialize::{self, ExportFormat, Secrets};
+use crate::term::RichTerm;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The extension added to the name of a program to get the name of its snapshot.
+pub const EXTENSION: &str = "snap.json";
+
+/// Return the path of the snapshot of a program: the path of the program with the
+/// [`EXTENSION`](constant.EXTENSION.html) appended, in the given directory if any, or next to
+/// the program otherwise.
+pub fn snapshot_path(file: &Path, dir: Option<&Path>) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(EXTENSION);
+
+    match dir {
+        Some(dir) => dir.join(name),
+        None => file.with_file_name(name),
+    }
+}
+
+/// Return the snapshot of a fully evaluated term. Sensitive values are redacted, such that they
+/// are never written to snapshots.
+pub fn render(rt: &RichTerm) -> Result<String, Error> {
+    let rt = serialize::redact(rt.clone(), Secrets::Redact)?;
+    serialize::validate(ExportFormat::Json, &rt)?;
+    let mut json = serialize::to_string(ExportFormat::Json, &rt)?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// The outcome of the comparison of a program with its snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The result is the snapshot.
+    Matched,
+    /// The result differs from the snapshot.
+    Mismatched(Vec<Change>),
+    /// There is no snapshot.
+    Missing,
+    /// There was no snapshot, and it has been written.
+    Created,
+    /// The result differed from the snapshot, which has been overwritten.
+    Updated(Vec<Change>),
+}
+
+impl Outcome {
+    /// Return `true` if the snapshot is up to date after the comparison.
+    pub fn is_success(&self) -> bool {
+        !matches!(self, Outcome::Mismatched(_) | Outcome::Missing)
+    }
+}
+
+/// Fully evaluate a program and compare its result with the snapshot at the given path. If
+/// `update` is `true`, a missing or different snapshot is (over)written with the result.
+pub fn check(program: &mut Program, snapshot: &Path, update: bool) -> Result<Outcome, Error> {
+    let rt = program.eval_full().map(RichTerm::from)?;
+    let actual = render(&rt)?;
+
+    let expected = match fs::read_to_string(snapshot) {
+        Ok(expected) => Some(expected),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(IOError::from(err).into()),
+    };
+
+    let outcome = match expected {
+        Some(expected) if expected == actual => return Ok(Outcome::Matched),
+        Some(expected) => {
+            let expected_value = serde_json::from_str(&expected).map_err(|err| {
+                IOError(format!(
+                    "invalid snapshot {}: {}",
+                    snapshot.to_string_lossy(),
+                    err
+                ))
+            })?;
+            // The actual result is valid JSON.
+            let actual_value = serde_json::from_str(&actual).unwrap();
+            let changes = diff(&expected_value, &actual_value);
+
+            if !update {
+                return Ok(Outcome::Mismatched(changes));
+            }
+            Outcome::Updated(changes)
+        }
+        None if update => Outcome::Created,
+        None => return Ok(Outcome::Missing),
+    };
+
+    if let Some(dir) = snapshot.parent() {
+        fs::create_dir_all(dir).map_err(IOError::from)?;
+    }
+    fs::write(snapshot, actual).map_err(IOError::from)?;
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    #[test]
+    fn check_and_update() {
+        let dir = std::env::temp_dir().join(format!("nickel-snapshot-{}", std::process::id()));
+        let snapshot = snapshot_path(Path::new("config.ncl"), Some(&dir));
+        assert_eq!(snapshot, dir.join("config.ncl.snap.json"));
+        let _ = fs::remove_file(&snapshot);
+
+        let check_source = |source: &str, update| {
+            let mut p = Program::new_from_source(Cursor::new(source), "config.ncl").unwrap();
+            check(&mut p, &snapshot, update).unwrap()
+        };
+
+        assert_eq!(check_source("{b = 1, a = 2}", false), Outcome::Missing);
+        assert_eq!(check_source("{b = 1, a = 2}", true), Outcome::Created);
+        assert_eq!(
+            fs::read_to_string(&snapshot).unwrap(),
+            "{\n  \"a\": 2,\n  \"b\": 1\n}\n"
+        );
+        assert_eq!(check_source("{a = 2, b = 1}", false), Outcome::Matched);
+
+        let changes = vec![Change::Changed {
+            path: String::from("b"),
+            expected: json!(1),
+            actual: json!(3),
+        }];
+        assert_eq!(
+            check_source("{a = 2, b = 3}", false),
+            Outcome::Mismatched(changes.clone())
+        );
+        assert_eq!(
+            check_source("{a = 2, b = 3}", true),
+            Outcome::Updated(changes)
+        );
+        assert_eq!(check_source("{a = 2, b = 3}", false), Outcome::Matched);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
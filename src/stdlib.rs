@@ -15,10 +15,14 @@ pub const LISTS: (&str, &str) = ("<stdlib/lists>", include_str!("../stdlib/lists
 pub const RECORDS: (&str, &str) = ("<stdlib/records>", include_str!("../stdlib/records.ncl"));
 pub const STRINGS: (&str, &str) = ("<stdlib/strings>", include_str!("../stdlib/strings.ncl"));
 pub const NUMS: (&str, &str) = ("<stdlib/nums>", include_str!("../stdlib/nums.ncl"));
+pub const DEBUG: (&str, &str) = ("<stdlib/debug>", include_str!("../stdlib/debug.ncl"));
+pub const RANDOM: (&str, &str) = ("<stdlib/random>", include_str!("../stdlib/random.ncl"));
 
 /// Return the list `(name, source_code)` of all the stdlib modules.
 pub fn modules() -> Vec<(&'static str, &'static str)> {
-    vec![BUILTINS, CONTRACTS, LISTS, RECORDS, STRINGS, NUMS]
+    vec![
+        BUILTINS, CONTRACTS, LISTS, RECORDS, STRINGS, NUMS, DEBUG, RANDOM,
+    ]
 }
 
 /// Accessors to the builtin contracts.
@@ -53,3 +57,38 @@ pub mod contracts {
     generate_accessor!(dyn_tail);
     generate_accessor!(empty_tail);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar::TermParser, lexer::Lexer};
+    use crate::term::{MetaValue, Term};
+    use codespan::Files;
+
+    /// Check that every symbol exported by the user-facing modules of the standard library is
+    /// documented. The builtin contracts are internal and thus excluded.
+    #[test]
+    fn documented() {
+        let mut files = Files::new();
+
+        for (name, source) in modules().into_iter().filter(|m| *m != CONTRACTS) {
+            let file_id = files.add(name, String::from(source));
+            let rt = TermParser::new()
+                .parse_strict(file_id, Lexer::new(source))
+                .unwrap();
+
+            let module = match rt.as_ref() {
+                Term::RecRecord(map) if map.len() == 1 => map.values().next().unwrap(),
+                _ => panic!("{}: expected a record with one field", name),
+            };
+
+            match module.as_ref() {
+                Term::RecRecord(fields) => fields.iter().for_each(|(id, t)| match t.as_ref() {
+                    Term::MetaValue(MetaValue { doc: Some(_), .. }) => (),
+                    _ => panic!("{}: {} is not documented", name, id),
+                }),
+                _ => panic!("{}: expected the module to be a record", name),
+            }
+        }
+    }
+}
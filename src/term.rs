@@ -16,8 +16,10 @@
 //! contracts, default values, documentation, etc. They bring such usually external object down to
 //! the term level, and together with [merge](../merge/index.html), they allow for flexible and
 //! modular definitions of contracts, record and metadata all together.
+use crate::error::EvalError;
 use crate::identifier::Ident;
 use crate::label::Label;
+use crate::native::NativeFn;
 use crate::position::TermPos;
 use crate::types::{AbsType, Types};
 use codespan::FileId;
@@ -142,9 +144,18 @@ pub enum Term {
     /// An unresolved import.
     #[serde(skip)]
     Import(OsString),
+    /// An unresolved import of the raw content of a file as a string, which is not parsed.
+    #[serde(skip)]
+    ImportStr(OsString),
     /// A resolved import (which has already been loaded and parsed).
     #[serde(skip)]
     ResolvedImport(FileId),
+
+    /// A placeholder for a part of the source that could not be parsed. Only produced by the
+    /// error-recovering parser, see
+    /// [`TermParser::parse_tolerant`](../parser/grammar/struct.TermParser.html).
+    #[serde(skip)]
+    ParseError,
 }
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
@@ -159,18 +170,39 @@ impl Default for MergePriority {
     }
 }
 
+/// The syntax used to attach a [contract](struct.Contract.html) to a term.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum AnnotKind {
+    /// A type annotation `t : T`, which is checked statically and enforced at run-time.
+    Type,
+    /// A contract annotation `t | C`, which is only enforced at run-time.
+    Contract,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Contract {
     pub types: Types,
     pub label: Label,
+    /// Whether the contract comes from a type or a contract annotation. A type annotation can
+    /// end up in the contracts of a metavalue after flattening (see
+    /// [`MetaValue::flatten`](struct.MetaValue.html#method.flatten)), but it keeps its kind.
+    pub kind: AnnotKind,
 }
 
+/// The placeholder printed instead of a [sensitive](struct.MetaValue.html#structfield.sensitive)
+/// value.
+pub const REDACTED: &str = "<redacted>";
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct MetaValue {
     pub doc: Option<String>,
     pub types: Option<Contract>,
     pub contracts: Vec<Contract>,
     pub priority: MergePriority,
+    /// Whether the value is a secret, such as a password or a token. Sensitive values are
+    /// replaced by [`REDACTED`](constant.REDACTED.html) when printed, and are only exported on
+    /// demand (see [`serialize::redact`](../serialize/fn.redact.html)).
+    pub sensitive: bool,
     pub value: Option<RichTerm>,
 }
 
@@ -181,6 +213,7 @@ impl From<RichTerm> for MetaValue {
             types: None,
             contracts: Vec::new(),
             priority: Default::default(),
+            sensitive: false,
             value: Some(rt),
         }
     }
@@ -193,10 +226,19 @@ impl MetaValue {
             types: None,
             contracts: Vec::new(),
             priority: Default::default(),
+            sensitive: false,
             value: None,
         }
     }
 
+    /// Return the type and contract annotations of the metavalue, in the order in which they
+    /// appear in the source.
+    pub fn annotations(&self) -> Vec<&Contract> {
+        let mut annots: Vec<&Contract> = self.types.iter().chain(self.contracts.iter()).collect();
+        annots.sort_by_key(|ctr| ctr.label.span.start);
+        annots
+    }
+
     /// Flatten two nested metavalues into one, combining their metadata. If data that can't be
     /// combined (typically, the documentation or the type annotation) are set by both metavalues,
     /// outer's one are kept.
@@ -219,6 +261,7 @@ impl MetaValue {
             types,
             mut contracts,
             priority,
+            sensitive,
             value: _,
         } = outer;
 
@@ -237,6 +280,7 @@ impl MetaValue {
             types: types.or(inner.types),
             contracts,
             priority: std::cmp::min(priority, inner.priority),
+            sensitive: sensitive || inner.sensitive,
             value: inner.value,
         }
     }
@@ -263,59 +307,74 @@ impl<E> StrChunk<E> {
 }
 
 impl Term {
-    #[cfg(test)]
-    /// Recursively apply a function to all `Term`s contained in a `RichTerm`.
-    pub fn apply_to_rich_terms<F>(&mut self, func: F)
+    /// Apply a function to each direct subterm of a term, including the contracts and the type
+    /// annotation of a metavalue.
+    pub fn apply_to_rich_terms<F>(&mut self, mut func: F)
     where
-        F: Fn(&mut RichTerm),
+        F: FnMut(&mut RichTerm),
     {
         use self::Term::*;
         match self {
-            Null => (),
-            Switch(ref mut t, ref mut cases, ref mut def) => {
-                cases.iter_mut().for_each(|c| {
-                    let (_, t) = c;
-                    func(t);
-                });
+            Null | Bool(_) | Num(_) | Str(_) | Lbl(_) | Var(_) | Sym(_) | Enum(_) | Import(_)
+            | ImportStr(_) | ResolvedImport(_) | ParseError => (),
+            Switch(t, cases, def) => {
                 func(t);
-                if let Some(def) = def {
-                    func(def)
+                cases.values_mut().for_each(&mut func);
+                def.iter_mut().for_each(func);
+            }
+            Record(map) | RecRecord(map) => map.values_mut().for_each(func),
+            Fun(_, t) | Op1(_, t) | Promise(_, _, t) | Wrapped(_, t) => func(t),
+            MetaValue(meta) => {
+                for ctr in meta.types.iter_mut().chain(meta.contracts.iter_mut()) {
+                    if let AbsType::Flat(ref mut rt) = ctr.types.0 {
+                        func(rt);
+                    }
                 }
+                meta.value.iter_mut().for_each(func);
             }
-            Record(ref mut static_map) | RecRecord(ref mut static_map) => {
-                static_map.iter_mut().for_each(|e| {
-                    let (_, t) = e;
-                    func(t);
-                });
+            Let(_, t1, t2) | App(t1, t2) | Op2(_, t1, t2) => {
+                func(t1);
+                func(t2);
             }
+            OpN(_, terms) | List(terms) => terms.iter_mut().for_each(func),
+            StrChunks(chunks) => chunks.iter_mut().for_each(|chunk| match chunk {
+                StrChunk::Literal(_) => (),
+                StrChunk::Expr(e, _) => func(e),
+            }),
+        }
+    }
 
-            Bool(_) | Num(_) | Str(_) | Lbl(_) | Var(_) | Sym(_) | Enum(_) | Import(_)
-            | ResolvedImport(_) => {}
-            Fun(_, ref mut t)
-            | Op1(_, ref mut t)
-            | Promise(_, _, ref mut t)
-            | Wrapped(_, ref mut t) => {
+    /// Apply a function to each direct subterm of a term, including the contracts and the
+    /// type annotation of a metavalue.
+    pub fn for_each_subterm<'a, F>(&'a self, mut func: F)
+    where
+        F: FnMut(&'a RichTerm),
+    {
+        use self::Term::*;
+        match self {
+            Null | Bool(_) | Num(_) | Str(_) | Lbl(_) | Var(_) | Sym(_) | Enum(_) | Import(_)
+            | ImportStr(_) | ResolvedImport(_) | ParseError => (),
+            Switch(t, cases, def) => {
                 func(t);
+                cases.values().for_each(&mut func);
+                def.iter().for_each(func);
             }
-            MetaValue(ref mut meta) => {
-                meta.contracts
-                    .iter_mut()
-                    .for_each(|Contract { types, .. }| match types.0 {
-                        AbsType::Flat(ref mut rt) => func(rt),
-                        _ => (),
-                    });
-                meta.value.iter_mut().for_each(func);
+            Record(map) | RecRecord(map) => map.values().for_each(func),
+            Fun(_, t) | Op1(_, t) | Promise(_, _, t) | Wrapped(_, t) => func(t),
+            MetaValue(meta) => {
+                for ctr in meta.types.iter().chain(meta.contracts.iter()) {
+                    if let AbsType::Flat(ref rt) = ctr.types.0 {
+                        func(rt);
+                    }
+                }
+                meta.value.iter().for_each(func);
             }
-            Let(_, ref mut t1, ref mut t2)
-            | App(ref mut t1, ref mut t2)
-            | Op2(_, ref mut t1, ref mut t2) => {
+            Let(_, t1, t2) | App(t1, t2) | Op2(_, t1, t2) => {
                 func(t1);
                 func(t2);
             }
-            OpN(_, ref mut terms) | List(ref mut terms) => terms.iter_mut().for_each(|t| {
-                func(t);
-            }),
-            StrChunks(chunks) => chunks.iter_mut().for_each(|chunk| match chunk {
+            OpN(_, terms) | List(terms) => terms.iter().for_each(func),
+            StrChunks(chunks) => chunks.iter().for_each(|chunk| match chunk {
                 StrChunk::Literal(_) => (),
                 StrChunk::Expr(e, _) => func(e),
             }),
@@ -351,7 +410,9 @@ impl Term {
             | Term::OpN(..)
             | Term::Promise(_, _, _)
             | Term::Import(_)
+            | Term::ImportStr(_)
             | Term::ResolvedImport(_)
+            | Term::ParseError
             | Term::StrChunks(_) => None,
         }
         .map(String::from)
@@ -363,7 +424,7 @@ impl Term {
             Term::Null => String::from("null"),
             Term::Bool(true) => String::from("true"),
             Term::Bool(false) => String::from("false"),
-            Term::Num(n) => format!("{}", n),
+            Term::Num(n) => crate::serialize::num_to_string(*n),
             Term::Str(s) => format!("\"{}\"", s),
             Term::StrChunks(chunks) => {
                 let chunks_str: Vec<String> = chunks
@@ -379,7 +440,7 @@ impl Term {
             }
             Term::Fun(_, _) => String::from("<func>"),
             Term::Lbl(_) => String::from("<label>"),
-            Term::Enum(Ident(s)) => format!("`{}", s),
+            Term::Enum(Ident { label: s, .. }) => format!("`{}", s),
             Term::Record(_) | Term::RecRecord(_) => String::from("{ ... }"),
             Term::List(_) => String::from("[ ... ]"),
             Term::Sym(_) => String::from("<sym>"),
@@ -393,13 +454,18 @@ impl Term {
                 if !meta.contracts.is_empty() {
                     content.push_str("contract,");
                 }
+                if meta.sensitive {
+                    content.push_str("sensitive,");
+                }
 
                 let value_label = if meta.priority == MergePriority::Default {
                     "default"
                 } else {
                     "value"
                 };
-                let value = if let Some(t) = &meta.value {
+                let value = if meta.sensitive {
+                    String::from(REDACTED)
+                } else if let Some(t) = &meta.value {
                     t.as_ref().shallow_repr()
                 } else {
                     String::from("none")
@@ -407,7 +473,7 @@ impl Term {
 
                 format!("<{}{}={}>", content, value_label, value)
             }
-            Term::Var(Ident(id)) => id.clone(),
+            Term::Var(Ident { label: id, .. }) => id.clone(),
             Term::Let(_, _, _)
             | Term::App(_, _)
             | Term::Switch(..)
@@ -416,7 +482,53 @@ impl Term {
             | Term::OpN(..)
             | Term::Promise(_, _, _)
             | Term::Import(_)
+            | Term::ImportStr(_)
             | Term::ResolvedImport(_) => String::from("<unevaluated>"),
+            Term::ParseError => String::from("<parse error>"),
+        }
+    }
+
+    /// Return a string representation of a term, where the content of lists and records is
+    /// rendered recursively up to `depth` levels of nesting. Deeper values are rendered using
+    /// [`shallow_repr`](#method.shallow_repr).
+    ///
+    /// Variables are printed as is: the term must have been substituted beforehand to get a
+    /// meaningful result.
+    pub fn deep_repr(&self, depth: usize) -> String {
+        if depth == 0 {
+            return self.shallow_repr();
+        }
+
+        match self {
+            Term::Record(map) | Term::RecRecord(map) => {
+                let mut fields: Vec<_> = map.iter().collect();
+                fields.sort_by_key(|(id, _)| *id);
+
+                let content: Vec<String> = fields
+                    .into_iter()
+                    .map(|(Ident { label: id, .. }, t)| {
+                        format!("{} = {}", id, t.as_ref().deep_repr(depth - 1))
+                    })
+                    .collect();
+
+                if content.is_empty() {
+                    String::from("{}")
+                } else {
+                    format!("{{ {} }}", content.join(", "))
+                }
+            }
+            Term::List(ts) => {
+                let content: Vec<String> =
+                    ts.iter().map(|t| t.as_ref().deep_repr(depth - 1)).collect();
+
+                if content.is_empty() {
+                    String::from("[]")
+                } else {
+                    format!("[ {} ]", content.join(", "))
+                }
+            }
+            Term::MetaValue(MetaValue { value: Some(t), .. }) => t.as_ref().deep_repr(depth),
+            t => t.shallow_repr(),
         }
     }
 
@@ -444,7 +556,9 @@ impl Term {
             | Term::Wrapped(_, _)
             | Term::MetaValue(_)
             | Term::Import(_)
+            | Term::ImportStr(_)
             | Term::ResolvedImport(_)
+            | Term::ParseError
             | Term::StrChunks(_)
             | Term::RecRecord(_) => false,
         }
@@ -482,7 +596,9 @@ impl Term {
             | Term::Wrapped(_, _)
             | Term::MetaValue(_)
             | Term::Import(_)
+            | Term::ImportStr(_)
             | Term::ResolvedImport(_)
+            | Term::ParseError
             | Term::StrChunks(_)
             | Term::RecRecord(_) => false,
         }
@@ -602,6 +718,18 @@ pub enum UnaryOp {
     ListLength(),
     /// Generate a list of a given length by mapping a `Num -> Num` function onto `[1,..,n]`.
     ListGen(),
+    /// Return the sum of a list of numbers.
+    ///
+    /// The elements of the list must have been forced beforehand, e.g. using `DeepSeq`.
+    ListSum(),
+    /// Return the product of a list of numbers.
+    ListProduct(),
+    /// Return the minimum of a non-empty list of numbers.
+    ListMin(),
+    /// Return the maximum of a non-empty list of numbers.
+    ListMax(),
+    /// Return the arithmetic mean of a non-empty list of numbers.
+    ListMean(),
 
     /// Generated by the evaluation of a string with interpolated expressions. `ChunksConcat`
     /// applied to the current chunk to evaluate. As additional state, it uses a string
@@ -628,12 +756,27 @@ pub enum UnaryOp {
     StrLowercase(),
     /// Return the length of a string.
     StrLength(),
+    /// Remove the common leading whitespace of all the non-blank lines of a string.
+    StrDedent(),
     /// Transform a data to a string.
     ToStr(),
     /// Transform a string to a number.
     NumFromStr(),
     /// Transform a string to an enum.
     EnumFromStr(),
+
+    /// Print a message on the standard error, then proceed with the next argument on the stack.
+    Trace(),
+    /// Force the evaluation of its argument, then return the current time in seconds since the
+    /// Unix epoch.
+    Clock(),
+
+    /// Call a native function on a list of arguments.
+    ///
+    /// The operator first records the position of the call, then fully evaluates the arguments,
+    /// and proceeds with the same operator with the flag set, which calls the Rust function. See
+    /// the [native module](../native/index.html).
+    Native(NativeFn, bool /* arguments have been forced */),
 }
 
 /// Primitive binary operators
@@ -681,6 +824,13 @@ pub enum BinaryOp {
     GoField(),
     /// Set the tag text of a blame label.
     Tag(),
+    /// Propagate the tag and the position of the checked value of a label to the labels of the
+    /// contracts of a record contract's fields, recursively, when they don't have their own.
+    ///
+    /// Used when a record is applied as a contract with a tagged label, as done for imports
+    /// annotated with contracts, so that the blame of a field still mentions the import. The
+    /// second argument is not forced, such that the metavalues of the fields are preserved.
+    TagContracts(),
     /// Extend a record with a dynamic field.
     ///
     /// Dynamic means that the field name may be an expression instead of a statically known
@@ -688,6 +838,14 @@ pub enum BinaryOp {
     /// field with this name to the given record with the expression on top of the stack as
     /// content.
     DynExtend(),
+    /// Define a dynamic field of a record literal, as in `{"#{name}" = value}`.
+    ///
+    /// Same as `DynExtend`, except that if the field is already defined, the two definitions are
+    /// merged, as it is done for static fields defined several times.
+    DynDefine(),
+    /// Override the fields of a record with the ones of another record, as done by the spread
+    /// syntax `{..base, field = value}`. Fields defined in the second record take precedence.
+    RecordOverride(),
     /// Remove a field from a record. The field name is given as an arbitrary Nickel expression.
     DynRemove(),
     /// Access the field of record. The field name is given as an arbitrary Nickel expression.
@@ -716,12 +874,25 @@ pub enum BinaryOp {
     /// Match a regex on a string, and returns the captured groups together, the index of the
     /// match, etc.
     StrMatch(),
+    /// Indent each non-blank line of a string by a given number of spaces.
+    StrIndent(),
+    /// Access a nested field of a record. The path is given as a list of field names.
+    GetPath(),
+    /// Test if a record has a nested field. The path is given as a list of field names.
+    HasPath(),
+    /// Render a value as a string, recursively up to a given depth.
+    DeepRepr(),
+    /// Transform a number to a string with a fixed number of digits after the decimal point.
+    ToStrFixed(),
+    /// Format a number or a string according to a format specifier, as used in string
+    /// interpolation.
+    StrFormat(),
 }
 
 impl BinaryOp {
     pub fn is_strict(&self) -> bool {
         match self {
-            BinaryOp::Merge() => false,
+            BinaryOp::Merge() | BinaryOp::TagContracts() => false,
             _ => true,
         }
     }
@@ -737,12 +908,38 @@ pub enum NAryOp {
     StrReplaceRegex(),
     /// Return a substring of an original string.
     StrSubstr(),
+    /// Replace a placeholder by a multi-line string in a template. The lines of the inserted
+    /// string following the first one are indented as the line where the placeholder occurs.
+    StrSplice(),
+    /// Update a nested field of a record by applying a function to its current value. The path
+    /// is given as a list of field names.
+    UpdatePath(),
+    /// Pad a string on the left with a character up to a given display width.
+    StrPadLeft(),
+    /// Pad a string on the right with a character up to a given display width.
+    StrPadRight(),
+    /// Center a string by padding it on both sides with a character up to a given display width.
+    StrCenter(),
+    /// Truncate a string to a given display width, ending it with an ellipsis if it was shortened.
+    StrTruncate(),
+    /// Raise a blame error, whose label reports the differences between an expected value and the
+    /// actual one. Both values must be fully evaluated.
+    BlameDiff(),
 }
 
 impl NAryOp {
     pub fn arity(&self) -> usize {
         match self {
-            NAryOp::StrReplace() | NAryOp::StrReplaceRegex() | NAryOp::StrSubstr() => 3,
+            NAryOp::StrReplace()
+            | NAryOp::StrReplaceRegex()
+            | NAryOp::StrSubstr()
+            | NAryOp::StrSplice()
+            | NAryOp::UpdatePath()
+            | NAryOp::StrPadLeft()
+            | NAryOp::StrPadRight()
+            | NAryOp::StrCenter()
+            | NAryOp::StrTruncate()
+            | NAryOp::BlameDiff() => 3,
         }
     }
 
@@ -757,6 +954,13 @@ impl fmt::Display for NAryOp {
             NAryOp::StrReplace() => write!(f, "strReplace"),
             NAryOp::StrReplaceRegex() => write!(f, "strReplaceRegex"),
             NAryOp::StrSubstr() => write!(f, "substring"),
+            NAryOp::StrSplice() => write!(f, "strSplice"),
+            NAryOp::UpdatePath() => write!(f, "updatePath"),
+            NAryOp::StrPadLeft() => write!(f, "strPadLeft"),
+            NAryOp::StrPadRight() => write!(f, "strPadRight"),
+            NAryOp::StrCenter() => write!(f, "strCenter"),
+            NAryOp::StrTruncate() => write!(f, "strTruncate"),
+            NAryOp::BlameDiff() => write!(f, "blameDiff"),
         }
     }
 }
@@ -793,15 +997,108 @@ impl RichTerm {
         self
     }
 
-    /// Apply a transformation on a whole term by mapping a function `f` on each node in a
-    /// bottom-up manner. `f` may return a generic error `E` and use the state `S` which is
-    /// passed around.
-    pub fn traverse<F, S, E>(self, f: &mut F, state: &mut S) -> Result<RichTerm, E>
+    /// Give the subterms which don't have a position the inherited position of their closest
+    /// ancestor which has one. This is used for the terms generated by desugaring, which are
+    /// otherwise left without a position.
+    pub fn inherit_pos(&mut self) {
+        let pos = self.pos.into_inherited();
+        self.term.apply_to_rich_terms(|rt: &mut RichTerm| {
+            if !rt.pos.is_def() {
+                rt.pos = pos;
+            }
+            rt.inherit_pos();
+        });
+    }
+
+    /// Check that every node of the term has a position. Terms coming from the parser satisfy
+    /// this property, which is checked in debug builds.
+    pub fn all_pos_defined(&self) -> bool {
+        let mut result = true;
+        self.traverse_ref(
+            &mut |rt: &RichTerm| result = result && rt.pos.is_def(),
+            TraverseOrder::TopDown,
+        );
+        result
+    }
+
+    /// Check that a term can be evaluated. The terms produced by the parser always can, but a term
+    /// built by hand, for example by an application generating Nickel code, may break one of the
+    /// invariants the evaluator relies on:
+    ///
+    /// - the `if` operator must be applied to two arguments,
+    /// - the operators used internally by the evaluator for `switch` and string interpolation
+    ///   can't appear in a term,
+    /// - an n-ary operator must have as many arguments as its arity,
+    /// - resolved imports and parse errors can't appear in a term.
+    ///
+    /// The constructors of the [`make`](make/index.html) module only build terms which satisfy
+    /// these invariants.
+    pub fn check(&self) -> Result<(), EvalError> {
+        let ill_formed = |msg: String| Err(EvalError::IllFormedTerm(msg, self.pos));
+
+        match self.as_ref() {
+            Term::App(f, t2) => {
+                if let Term::App(ite, t1) = f.as_ref() {
+                    if let Term::Op1(UnaryOp::Ite(), cond) = ite.as_ref() {
+                        cond.check()?;
+                        t1.check()?;
+                        return t2.check();
+                    }
+                }
+            }
+            Term::Op1(UnaryOp::Ite(), _) => {
+                return ill_formed(String::from("`if` must be applied to two arguments"))
+            }
+            Term::Op1(op @ UnaryOp::Switch(_), _) | Term::Op1(op @ UnaryOp::ChunksConcat(), _) => {
+                return ill_formed(format!("the internal operator {:?} can't be used", op))
+            }
+            Term::OpN(op, args) if args.len() != op.arity() => {
+                return ill_formed(format!(
+                    "`{}` expects {} arguments, but was given {}",
+                    op,
+                    op.arity(),
+                    args.len()
+                ))
+            }
+            Term::ResolvedImport(_) => {
+                return ill_formed(String::from("resolved imports can't be used"))
+            }
+            Term::ParseError => return ill_formed(String::from("the term is a parse error")),
+            _ => (),
+        }
+
+        let mut result = Ok(());
+        self.term.for_each_subterm(|t| {
+            if result.is_ok() {
+                result = t.check();
+            }
+        });
+        result
+    }
+
+    /// Apply a transformation on a whole term by mapping a function `f` on each node, in the
+    /// given order. `f` may return a generic error `E` and use the state `S` which is passed
+    /// around.
+    ///
+    /// In a top-down traversal, `f` is applied to a node first, and the traversal then proceeds
+    /// with the children of the result. In a bottom-up traversal, the children are transformed
+    /// first and `f` is applied to the rebuilt node.
+    pub fn traverse<F, S, E>(
+        self,
+        f: &mut F,
+        state: &mut S,
+        order: TraverseOrder,
+    ) -> Result<RichTerm, E>
     where
         F: FnMut(RichTerm, &mut S) -> Result<RichTerm, E>,
     {
-        let RichTerm { term, pos } = self;
-        match *term {
+        let rt = match order {
+            TraverseOrder::TopDown => f(self, state)?,
+            TraverseOrder::BottomUp => self,
+        };
+
+        let RichTerm { term, pos } = rt;
+        let term = match *term {
             v @ Term::Null
             | v @ Term::Bool(_)
             | v @ Term::Num(_)
@@ -811,44 +1108,19 @@ impl RichTerm {
             | v @ Term::Var(_)
             | v @ Term::Enum(_)
             | v @ Term::Import(_)
-            | v @ Term::ResolvedImport(_) => f(
-                RichTerm {
-                    term: Box::new(v),
-                    pos,
-                },
-                state,
-            ),
-            Term::Fun(id, t) => {
-                let t = t.traverse(f, state)?;
-                f(
-                    RichTerm {
-                        term: Box::new(Term::Fun(id, t)),
-                        pos,
-                    },
-                    state,
-                )
-            }
+            | v @ Term::ImportStr(_)
+            | v @ Term::ResolvedImport(_)
+            | v @ Term::ParseError => v,
+            Term::Fun(id, t) => Term::Fun(id, t.traverse(f, state, order)?),
             Term::Let(id, t1, t2) => {
-                let t1 = t1.traverse(f, state)?;
-                let t2 = t2.traverse(f, state)?;
-                f(
-                    RichTerm {
-                        term: Box::new(Term::Let(id, t1, t2)),
-                        pos,
-                    },
-                    state,
-                )
+                let t1 = t1.traverse(f, state, order)?;
+                let t2 = t2.traverse(f, state, order)?;
+                Term::Let(id, t1, t2)
             }
             Term::App(t1, t2) => {
-                let t1 = t1.traverse(f, state)?;
-                let t2 = t2.traverse(f, state)?;
-                f(
-                    RichTerm {
-                        term: Box::new(Term::App(t1, t2)),
-                        pos,
-                    },
-                    state,
-                )
+                let t1 = t1.traverse(f, state, order)?;
+                let t2 = t2.traverse(f, state, order)?;
+                Term::App(t1, t2)
             }
             Term::Switch(t, cases, default) => {
                 // The annotation on `map_res` use Result's corresponding trait to convert from
@@ -856,89 +1128,43 @@ impl RichTerm {
                 let cases_res: Result<HashMap<Ident, RichTerm>, E> = cases
                     .into_iter()
                     // For the conversion to work, note that we need a Result<(Ident,RichTerm), E>
-                    .map(|(id, t)| t.traverse(f, state).map(|t_ok| (id.clone(), t_ok)))
+                    .map(|(id, t)| t.traverse(f, state, order).map(|t_ok| (id.clone(), t_ok)))
                     .collect();
 
                 let default = default
-                    .map(|t| t.traverse(f, state))
+                    .map(|t| t.traverse(f, state, order))
                     // Transpose from Option<Result> to Result<Option>. There is a `transpose`
                     // method in Rust, but it has currently not made it to the stable version yet
                     .map_or(Ok(None), |res| res.map(Some))?;
 
-                let t = t.traverse(f, state)?;
+                let t = t.traverse(f, state, order)?;
 
-                f(
-                    RichTerm::new(Term::Switch(t, cases_res?, default), pos),
-                    state,
-                )
-            }
-            Term::Op1(op, t) => {
-                let t = t.traverse(f, state)?;
-                f(
-                    RichTerm {
-                        term: Box::new(Term::Op1(op, t)),
-                        pos,
-                    },
-                    state,
-                )
+                Term::Switch(t, cases_res?, default)
             }
+            Term::Op1(op, t) => Term::Op1(op, t.traverse(f, state, order)?),
             Term::Op2(op, t1, t2) => {
-                let t1 = t1.traverse(f, state)?;
-                let t2 = t2.traverse(f, state)?;
-                f(
-                    RichTerm {
-                        term: Box::new(Term::Op2(op, t1, t2)),
-                        pos,
-                    },
-                    state,
-                )
+                let t1 = t1.traverse(f, state, order)?;
+                let t2 = t2.traverse(f, state, order)?;
+                Term::Op2(op, t1, t2)
             }
             Term::OpN(op, ts) => {
-                let ts_res: Result<Vec<RichTerm>, E> =
-                    ts.into_iter().map(|t| t.traverse(f, state)).collect();
-                f(
-                    RichTerm {
-                        term: Box::new(Term::OpN(op, ts_res?)),
-                        pos,
-                    },
-                    state,
-                )
-            }
-            Term::Promise(ty, l, t) => {
-                let t = t.traverse(f, state)?;
-                f(
-                    RichTerm {
-                        term: Box::new(Term::Promise(ty, l, t)),
-                        pos,
-                    },
-                    state,
-                )
-            }
-            Term::Wrapped(i, t) => {
-                let t = t.traverse(f, state)?;
-                f(
-                    RichTerm {
-                        term: Box::new(Term::Wrapped(i, t)),
-                        pos,
-                    },
-                    state,
-                )
+                let ts_res: Result<Vec<RichTerm>, E> = ts
+                    .into_iter()
+                    .map(|t| t.traverse(f, state, order))
+                    .collect();
+                Term::OpN(op, ts_res?)
             }
+            Term::Promise(ty, l, t) => Term::Promise(ty, l, t.traverse(f, state, order)?),
+            Term::Wrapped(i, t) => Term::Wrapped(i, t.traverse(f, state, order)?),
             Term::Record(map) => {
                 // The annotation on `map_res` uses Result's corresponding trait to convert from
                 // Iterator<Result> to a Result<Iterator>
                 let map_res: Result<HashMap<Ident, RichTerm>, E> = map
                     .into_iter()
                     // For the conversion to work, note that we need a Result<(Ident,RichTerm), E>
-                    .map(|(id, t)| t.traverse(f, state).map(|t_ok| (id.clone(), t_ok)))
+                    .map(|(id, t)| t.traverse(f, state, order).map(|t_ok| (id.clone(), t_ok)))
                     .collect();
-                f(
-                    RichTerm {
-                        term: Box::new(Term::Record(map_res?)),
-                        pos,
-                    },
-                    state,
-                )
+                Term::Record(map_res?)
             }
             Term::RecRecord(map) => {
                 // The annotation on `map_res` uses Result's corresponding trait to convert from
@@ -946,27 +1172,16 @@ impl RichTerm {
                 let map_res: Result<HashMap<Ident, RichTerm>, E> = map
                     .into_iter()
                     // For the conversion to work, note that we need a Result<(Ident,RichTerm), E>
-                    .map(|(id, t)| t.traverse(f, state).map(|t_ok| (id.clone(), t_ok)))
+                    .map(|(id, t)| t.traverse(f, state, order).map(|t_ok| (id.clone(), t_ok)))
                     .collect();
-                f(
-                    RichTerm {
-                        term: Box::new(Term::RecRecord(map_res?)),
-                        pos,
-                    },
-                    state,
-                )
+                Term::RecRecord(map_res?)
             }
             Term::List(ts) => {
-                let ts_res: Result<Vec<RichTerm>, E> =
-                    ts.into_iter().map(|t| t.traverse(f, state)).collect();
-
-                f(
-                    RichTerm {
-                        term: Box::new(Term::List(ts_res?)),
-                        pos,
-                    },
-                    state,
-                )
+                let ts_res: Result<Vec<RichTerm>, E> = ts
+                    .into_iter()
+                    .map(|t| t.traverse(f, state, order))
+                    .collect();
+                Term::List(ts_res?)
             }
             Term::StrChunks(chunks) => {
                 let chunks_res: Result<Vec<StrChunk<RichTerm>>, E> = chunks
@@ -974,18 +1189,11 @@ impl RichTerm {
                     .map(|chunk| match chunk {
                         chunk @ StrChunk::Literal(_) => Ok(chunk),
                         StrChunk::Expr(t, indent) => {
-                            Ok(StrChunk::Expr(t.traverse(f, state)?, indent))
+                            Ok(StrChunk::Expr(t.traverse(f, state, order)?, indent))
                         }
                     })
                     .collect();
-
-                f(
-                    RichTerm {
-                        term: Box::new(Term::StrChunks(chunks_res?)),
-                        pos,
-                    },
-                    state,
-                )
+                Term::StrChunks(chunks_res?)
             }
             Term::MetaValue(meta) => {
                 let contracts: Result<Vec<Contract>, _> = meta
@@ -993,7 +1201,9 @@ impl RichTerm {
                     .into_iter()
                     .map(|ctr| {
                         let types = match ctr.types {
-                            Types(AbsType::Flat(t)) => Types(AbsType::Flat(t.traverse(f, state)?)),
+                            Types(AbsType::Flat(t)) => {
+                                Types(AbsType::Flat(t.traverse(f, state, order)?))
+                            }
                             ty => ty,
                         };
                         Ok(Contract { types, ..ctr })
@@ -1005,7 +1215,9 @@ impl RichTerm {
                     .types
                     .map(|ctr| {
                         let types = match ctr.types {
-                            Types(AbsType::Flat(t)) => Types(AbsType::Flat(t.traverse(f, state)?)),
+                            Types(AbsType::Flat(t)) => {
+                                Types(AbsType::Flat(t.traverse(f, state, order)?))
+                            }
                             ty => ty,
                         };
                         Ok(Contract { types, ..ctr })
@@ -1016,27 +1228,100 @@ impl RichTerm {
 
                 let value = meta
                     .value
-                    .map(|t| t.traverse(f, state))
+                    .map(|t| t.traverse(f, state, order))
                     .map_or(Ok(None), |res| res.map(Some))?;
 
-                let meta = MetaValue {
+                Term::MetaValue(MetaValue {
                     doc: meta.doc,
                     types,
                     contracts,
                     priority: meta.priority,
+                    sensitive: meta.sensitive,
                     value,
-                };
-
-                f(
-                    RichTerm {
-                        term: Box::new(Term::MetaValue(meta)),
-                        pos,
-                    },
-                    state,
-                )
+                })
             }
+        };
+
+        let rt = RichTerm::new(term, pos);
+        match order {
+            TraverseOrder::TopDown => Ok(rt),
+            TraverseOrder::BottomUp => f(rt, state),
         }
     }
+
+    /// Visit a whole term without modifying it, by calling `f` on each node in the given order.
+    /// Contrary to [`traverse`](#method.traverse), `f` has only access to the nodes by reference.
+    pub fn traverse_ref<F>(&self, f: &mut F, order: TraverseOrder)
+    where
+        F: FnMut(&RichTerm),
+    {
+        if order == TraverseOrder::TopDown {
+            f(self);
+        }
+
+        self.term
+            .for_each_subterm(|rt: &RichTerm| rt.traverse_ref(f, order));
+
+        if order == TraverseOrder::BottomUp {
+            f(self);
+        }
+    }
+
+    /// Walk a whole term with a [visitor](trait.Visitor.html).
+    pub fn accept<V>(&self, visitor: &mut V, order: TraverseOrder)
+    where
+        V: Visitor + ?Sized,
+    {
+        self.traverse_ref(&mut |rt: &RichTerm| visitor.visit(rt), order)
+    }
+
+    /// Rewrite a whole term with a [folder](trait.Folder.html).
+    pub fn fold_with<R>(self, folder: &mut R, order: TraverseOrder) -> Result<RichTerm, R::Error>
+    where
+        R: Folder,
+    {
+        self.traverse(
+            &mut |rt: RichTerm, folder: &mut R| folder.fold(rt),
+            folder,
+            order,
+        )
+    }
+}
+
+/// The order in which the nodes of a term are visited by [`RichTerm::traverse`] and
+/// [`RichTerm::traverse_ref`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TraverseOrder {
+    /// Visit a node before its children.
+    TopDown,
+    /// Visit the children of a node before the node itself.
+    BottomUp,
+}
+
+/// A read-only walk over the nodes of a term, used together with [`RichTerm::accept`].
+///
+/// The visitor is given each node as a whole `RichTerm`, and thus has access to its position and
+/// to the metadata of `MetaValue` nodes. Closures taking a `&RichTerm` are visitors.
+pub trait Visitor {
+    fn visit(&mut self, rt: &RichTerm);
+}
+
+impl<F> Visitor for F
+where
+    F: FnMut(&RichTerm),
+{
+    fn visit(&mut self, rt: &RichTerm) {
+        self(rt)
+    }
+}
+
+/// A rewriting of the nodes of a term, used together with [`RichTerm::fold_with`].
+///
+/// Each node is replaced by the result of `fold`, which may fail and abort the whole rewriting.
+pub trait Folder {
+    type Error;
+
+    fn fold(&mut self, rt: RichTerm) -> Result<RichTerm, Self::Error>;
 }
 
 impl From<RichTerm> for Term {
@@ -1062,6 +1347,14 @@ impl From<Term> for RichTerm {
 
 #[macro_use]
 /// Helpers to build `RichTerm` objects.
+///
+/// Applications generating Nickel code should build terms with these helpers rather than with
+/// the constructors of [`Term`](../enum.Term.html), which can represent terms the evaluator can't
+/// handle (see [`RichTerm::check`](../struct.RichTerm.html#method.check)). The built terms don't
+/// have a position: the position of the root can be set with
+/// [`with_pos`](../struct.RichTerm.html#method.with_pos), and be given to the subterms with
+/// [`inherit_pos`](../struct.RichTerm.html#method.inherit_pos), such that errors point to the
+/// origin of the generated code.
 pub mod make {
     use super::*;
 
@@ -1076,14 +1369,12 @@ pub mod make {
         };
     }
 
-    /// Multi-ary application for types implementing `Into<RichTerm>`.
+    /// N-ary primitive operation for types implementing `Into<RichTerm>`. Panics if the number of
+    /// arguments is not the arity of the operator.
     #[macro_export]
     macro_rules! mk_opn {
         ( $op:expr, $( $args:expr ),+) => {
-            {
-                let args = vec![$( RichTerm::from($args) ),+];
-                $crate::term::RichTerm::from($crate::term::Term::OpN($op, args))
-            }
+            $crate::term::make::opn($op, vec![$( $crate::term::RichTerm::from($args) ),+])
         };
     }
 
@@ -1116,12 +1407,11 @@ pub mod make {
         };
     }
 
-    /// Multi field record for types implementing `Into<Ident>` (for the identifiers), and
-    /// `Into<RichTerm>` for the fields. Identifiers and corresponding content are specified as a
-    /// tuple: `mk_record!(("field1", t1), ("field2", t2))` corresponds to the record `{ field1 =
-    /// t1; field2 = t2 }`.
+    /// Switch for types implementing `Into<Ident>` (for the cases), and `Into<RichTerm>` for the
+    /// tested expression and the bodies. Cases are specified as tuples, followed by an optional
+    /// default case: `mk_switch!(exp, ("foo", t1), ("bar", t2); t3)` corresponds to `switch {
+    /// foo => t1, bar => t2, _ => t3 } exp`.
     #[macro_export]
-    #[cfg(test)]
     macro_rules! mk_switch {
         ( $exp:expr, $( ($id:expr, $body:expr) ),* ; $default:expr ) => {
             {
@@ -1133,14 +1423,28 @@ pub mod make {
             }
         };
         ( $exp:expr, $( ($id:expr, $body:expr) ),*) => {
+            {
                 let mut map = std::collections::HashMap::new();
                 $(
                     map.insert($id.into(), $body.into());
                 )*
                 $crate::term::RichTerm::from($crate::term::Term::Switch($crate::term::RichTerm::from($exp), map, None))
+            }
         };
     }
 
+    pub fn null() -> RichTerm {
+        Term::Null.into()
+    }
+
+    pub fn bool(b: bool) -> RichTerm {
+        Term::Bool(b).into()
+    }
+
+    pub fn num(n: f64) -> RichTerm {
+        Term::Num(n).into()
+    }
+
     pub fn var<I>(v: I) -> RichTerm
     where
         I: Into<Ident>,
@@ -1148,6 +1452,63 @@ pub mod make {
         Term::Var(v.into()).into()
     }
 
+    pub fn enum_tag<I>(tag: I) -> RichTerm
+    where
+        I: Into<Ident>,
+    {
+        Term::Enum(tag.into()).into()
+    }
+
+    pub fn list<I, T>(elts: I) -> RichTerm
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<RichTerm>,
+    {
+        Term::List(elts.into_iter().map(T::into).collect()).into()
+    }
+
+    /// A record whose fields can't refer to each other. If a field is given several times, the
+    /// last definition is kept.
+    pub fn record<I, Id, T>(fields: I) -> RichTerm
+    where
+        I: IntoIterator<Item = (Id, T)>,
+        Id: Into<Ident>,
+        T: Into<RichTerm>,
+    {
+        Term::Record(
+            fields
+                .into_iter()
+                .map(|(id, t)| (id.into(), t.into()))
+                .collect(),
+        )
+        .into()
+    }
+
+    pub fn fun<I, T>(id: I, body: T) -> RichTerm
+    where
+        I: Into<Ident>,
+        T: Into<RichTerm>,
+    {
+        Term::Fun(id.into(), body.into()).into()
+    }
+
+    pub fn app<T1, T2>(f: T1, arg: T2) -> RichTerm
+    where
+        T1: Into<RichTerm>,
+        T2: Into<RichTerm>,
+    {
+        Term::App(f.into(), arg.into()).into()
+    }
+
+    /// The access `record.field`.
+    pub fn static_access<T, I>(record: T, field: I) -> RichTerm
+    where
+        T: Into<RichTerm>,
+        I: Into<Ident>,
+    {
+        op1(UnaryOp::StaticAccess(field.into()), record)
+    }
+
     pub fn let_in<I, T1, T2>(id: I, t1: T1, t2: T2) -> RichTerm
     where
         T1: Into<RichTerm>,
@@ -1157,7 +1518,6 @@ pub mod make {
         Term::Let(id.into(), t1.into(), t2.into()).into()
     }
 
-    #[cfg(test)]
     pub fn if_then_else<T1, T2, T3>(cond: T1, t1: T2, t2: T3) -> RichTerm
     where
         T1: Into<RichTerm>,
@@ -1182,6 +1542,22 @@ pub mod make {
         Term::Op2(op, t1.into(), t2.into()).into()
     }
 
+    /// An n-ary primitive operation. Panics if the number of arguments is not the arity of the
+    /// operator.
+    pub fn opn<T>(op: NAryOp, args: Vec<T>) -> RichTerm
+    where
+        T: Into<RichTerm>,
+    {
+        assert_eq!(
+            args.len(),
+            op.arity(),
+            "`{}` expects {} arguments",
+            op,
+            op.arity()
+        );
+        Term::OpN(op, args.into_iter().map(T::into).collect()).into()
+    }
+
     pub fn assume<T>(types: Types, l: Label, t: T) -> RichTerm
     where
         T: Into<RichTerm>,
@@ -1209,4 +1585,189 @@ pub mod make {
     {
         Term::Import(path.into()).into()
     }
+
+    pub fn import_str<S>(path: S) -> RichTerm
+    where
+        S: Into<OsString>,
+    {
+        Term::ImportStr(path.into()).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::make as mk_term;
+    use super::*;
+
+    /// Collect the numbers of a term in the order they are visited.
+    fn visited_nums(t: &RichTerm, order: TraverseOrder) -> Vec<f64> {
+        let mut nums = Vec::new();
+        t.traverse_ref(
+            &mut |rt: &RichTerm| {
+                if let Term::Num(n) = rt.as_ref() {
+                    nums.push(*n)
+                }
+            },
+            order,
+        );
+        nums
+    }
+
+    #[test]
+    fn check() {
+        let record = mk_term::record(vec![
+            ("a", mk_term::list(vec![mk_term::num(1.0), mk_term::null()])),
+            (
+                "b",
+                mk_term::if_then_else(mk_term::bool(true), mk_term::enum_tag("foo"), Term::Null),
+            ),
+            (
+                "c",
+                mk_term::app(mk_term::fun("x", mk_term::var("x")), mk_term::num(2.0)),
+            ),
+            ("d", mk_term::static_access(mk_term::var("r"), "a")),
+            (
+                "e",
+                mk_opn!(
+                    NAryOp::StrReplace(),
+                    mk_term::string("a"),
+                    mk_term::string("a"),
+                    mk_term::string("b")
+                ),
+            ),
+            (
+                "f",
+                mk_switch!(mk_term::enum_tag("foo"), ("foo", Term::Null)),
+            ),
+        ]);
+        assert_eq!(record.check(), Ok(()));
+
+        let ill_formed = |t: Term| {
+            matches!(
+                mk_term::record(vec![("a", mk_term::list(vec![t]))]).check(),
+                Err(EvalError::IllFormedTerm(..))
+            )
+        };
+        assert!(ill_formed(Term::Op1(UnaryOp::Ite(), mk_term::bool(true))));
+        assert!(ill_formed(Term::App(
+            mk_term::op1(UnaryOp::Ite(), Term::Bool(true)),
+            mk_term::null()
+        )));
+        assert!(ill_formed(Term::Op1(
+            UnaryOp::Switch(false),
+            mk_term::null()
+        )));
+        assert!(ill_formed(Term::OpN(
+            NAryOp::StrSubstr(),
+            vec![mk_term::string("a")]
+        )));
+        assert!(ill_formed(Term::ParseError));
+    }
+
+    #[test]
+    #[should_panic]
+    fn opn_arity() {
+        mk_term::opn(NAryOp::StrReplace(), vec![Term::Null]);
+    }
+
+    #[test]
+    fn traverse_order() {
+        let t = mk_term::op2(
+            BinaryOp::Plus(),
+            Term::Num(1.0),
+            Term::List(vec![Term::Num(2.0).into(), Term::Num(3.0).into()]),
+        );
+
+        assert_eq!(
+            visited_nums(&t, TraverseOrder::TopDown),
+            vec![1.0, 2.0, 3.0]
+        );
+        assert_eq!(
+            visited_nums(&t, TraverseOrder::BottomUp),
+            vec![1.0, 2.0, 3.0]
+        );
+
+        let mut kinds = Vec::new();
+        t.traverse_ref(
+            &mut |rt: &RichTerm| kinds.push(rt.as_ref().type_of()),
+            TraverseOrder::BottomUp,
+        );
+        assert_eq!(kinds.last(), Some(&None));
+        assert_eq!(kinds[kinds.len() - 2], Some(String::from("List")));
+    }
+
+    #[test]
+    fn top_down_rewrites_children_of_result() {
+        // Rewriting `x` to `[y]` and `y` to `1` only reaches the new `y` in a top-down traversal.
+        let rewrite = |rt: RichTerm, _: &mut ()| -> Result<RichTerm, ()> {
+            match rt.as_ref() {
+                Term::Var(id) if id.label == "x" => Ok(Term::List(vec![mk_term::var("y")]).into()),
+                Term::Var(id) if id.label == "y" => Ok(Term::Num(1.0).into()),
+                _ => Ok(rt),
+            }
+        };
+
+        let top_down = mk_term::var("x")
+            .traverse(&mut rewrite.clone(), &mut (), TraverseOrder::TopDown)
+            .unwrap();
+        assert_eq!(top_down, Term::List(vec![Term::Num(1.0).into()]).into());
+
+        let bottom_up = mk_term::var("x")
+            .traverse(&mut rewrite.clone(), &mut (), TraverseOrder::BottomUp)
+            .unwrap();
+        assert_eq!(bottom_up, Term::List(vec![mk_term::var("y")]).into());
+    }
+
+    #[test]
+    fn visitor_and_folder() {
+        struct CountVars(usize);
+
+        impl Visitor for CountVars {
+            fn visit(&mut self, rt: &RichTerm) {
+                if let Term::Var(_) = rt.as_ref() {
+                    self.0 += 1;
+                }
+            }
+        }
+
+        struct Rename;
+
+        impl Folder for Rename {
+            type Error = String;
+
+            fn fold(&mut self, rt: RichTerm) -> Result<RichTerm, String> {
+                match *rt.term {
+                    Term::Var(Ident { label: id, .. }) if id == "forbidden" => Err(id),
+                    Term::Var(Ident { label: id, .. }) => Ok(RichTerm::new(
+                        Term::Var(Ident::from(format!("renamed_{}", id))),
+                        rt.pos,
+                    )),
+                    t => Ok(RichTerm::new(t, rt.pos)),
+                }
+            }
+        }
+
+        let t = mk_term::let_in(
+            "a",
+            mk_term::var("b"),
+            mk_app!(mk_term::var("a"), Term::Null),
+        );
+
+        let mut count = CountVars(0);
+        t.accept(&mut count, TraverseOrder::TopDown);
+        assert_eq!(count.0, 2);
+
+        assert_eq!(
+            t.fold_with(&mut Rename, TraverseOrder::BottomUp),
+            Ok(mk_term::let_in(
+                "a",
+                mk_term::var("renamed_b"),
+                mk_app!(mk_term::var("renamed_a"), Term::Null)
+            ))
+        );
+        assert_eq!(
+            mk_term::var("forbidden").fold_with(&mut Rename, TraverseOrder::TopDown),
+            Err(String::from("forbidden"))
+        );
+    }
 }
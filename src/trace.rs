@@ -0,0 +1,287 @@
+//! Timing of the phases of the interpreter.
+//!
+//! With the `trace` feature, the interpreter records a span for each phase of the processing of
+//! a program: `parse`, `import`, `typecheck`, `transform` and `eval`, and `stdlib` for the
+//! preparation of the standard library. Spans have fields, such as the `file` being processed,
+//! and are nested: the parsing of an imported file happens inside the `import` span of the
+//! import. When a phase is skipped because its result is already in the cache, an event, which
+//! is a span of zero duration, is recorded instead, with the field `cache = "hit"`.
+//!
+//! Spans are passed to the [`Subscriber`](trait.Subscriber.html) of the current thread when they
+//! end, which can forward them to any logging or tracing infrastructure. The
+//! [`Timeline`](struct.Timeline.html) subscriber collects them and renders the timeline of an
+//! execution, as printed by `nickel --trace`:
+//!
+//! ```ignore
+//! let timeline = Rc::new(Timeline::new());
+//! trace::set_subscriber(Some(timeline.clone()));
+//! program.eval_full()?;
+//! eprint!("{}", timeline);
+//! ```
+//!
+//! Without the `trace` feature, the [`trace_span`](../macro.trace_span.html) and
+//! [`trace_event`](../macro.trace_event.html) macros which instrument the interpreter expand to
+//! nothing, and no span is ever recorded.
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// A span, passed to the subscriber when it ends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// An identifier of the span, unique in its thread.
+    pub id: usize,
+    /// The identifier of the enclosing span, if any.
+    pub parent: Option<usize>,
+    pub name: &'static str,
+    pub fields: Vec<(&'static str, String)>,
+    pub start: Instant,
+    /// The duration of the span, which is zero for events.
+    pub elapsed: Duration,
+}
+
+/// A receiver of the spans recorded by the interpreter.
+pub trait Subscriber {
+    /// Receive a span which has ended. A span ends after the spans it encloses.
+    fn record(&self, record: Record);
+}
+
+thread_local! {
+    static SUBSCRIBER: RefCell<Option<Rc<dyn Subscriber>>> = RefCell::new(None);
+    /// The identifiers of the spans which have not ended yet, the innermost last.
+    static STACK: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    static NEXT_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Set the subscriber of the current thread, or remove it with `None`, and return the previous
+/// one. No span is recorded without a subscriber.
+pub fn set_subscriber(subscriber: Option<Rc<dyn Subscriber>>) -> Option<Rc<dyn Subscriber>> {
+    SUBSCRIBER.with(|current| current.replace(subscriber))
+}
+
+fn subscriber() -> Option<Rc<dyn Subscriber>> {
+    SUBSCRIBER.with(|current| current.borrow().clone())
+}
+
+/// A span being recorded, which ends when dropped. Created by
+/// [`trace_span`](../macro.trace_span.html).
+pub struct Span(Option<(Rc<dyn Subscriber>, Record)>);
+
+impl Span {
+    /// Start a span. The fields are only computed if there is a subscriber.
+    pub fn enter<F>(name: &'static str, fields: F) -> Self
+    where
+        F: FnOnce() -> Vec<(&'static str, String)>,
+    {
+        let subscriber = match subscriber() {
+            Some(subscriber) => subscriber,
+            None => return Span(None),
+        };
+
+        let id = NEXT_ID.with(|next| next.replace(next.get() + 1));
+        let parent = STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            let parent = stack.last().copied();
+            stack.push(id);
+            parent
+        });
+        let record = Record {
+            id,
+            parent,
+            name,
+            fields: fields(),
+            start: Instant::now(),
+            elapsed: Duration::default(),
+        };
+
+        Span(Some((subscriber, record)))
+    }
+
+    /// Record an event: a span which ends immediately. Created by
+    /// [`trace_event`](../macro.trace_event.html).
+    pub fn event<F>(name: &'static str, fields: F)
+    where
+        F: FnOnce() -> Vec<(&'static str, String)>,
+    {
+        Span::enter(name, fields).end(Some(Duration::default()));
+    }
+
+    /// End the span, with the given duration or with the time elapsed since its start.
+    fn end(&mut self, elapsed: Option<Duration>) {
+        if let Some((subscriber, mut record)) = self.0.take() {
+            record.elapsed = elapsed.unwrap_or_else(|| record.start.elapsed());
+            STACK.with(|stack| {
+                let mut stack = stack.borrow_mut();
+                if let Some(i) = stack.iter().rposition(|id| *id == record.id) {
+                    stack.remove(i);
+                }
+            });
+            subscriber.record(record);
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        self.end(None);
+    }
+}
+
+/// Start a span of the given name and fields, which ends when the returned value is dropped:
+///
+/// ```ignore
+/// let _span = trace_span!("parse", file = name.to_string_lossy());
+/// ```
+///
+/// The values of the fields must implement `ToString`.
+#[cfg(feature = "trace")]
+#[macro_export]
+macro_rules! trace_span {
+    ($name:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        $crate::trace::Span::enter($name, || vec![$((stringify!($key), $value.to_string())),*])
+    };
+}
+
+/// Without the `trace` feature, spans are not recorded.
+#[cfg(not(feature = "trace"))]
+#[macro_export]
+macro_rules! trace_span {
+    ($($tokens:tt)*) => {
+        ()
+    };
+}
+
+/// Record an event of the given name and fields, as [`trace_span`](macro.trace_span.html).
+#[cfg(feature = "trace")]
+#[macro_export]
+macro_rules! trace_event {
+    ($name:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        $crate::trace::Span::event($name, || vec![$((stringify!($key), $value.to_string())),*])
+    };
+}
+
+/// Without the `trace` feature, events are not recorded.
+#[cfg(not(feature = "trace"))]
+#[macro_export]
+macro_rules! trace_event {
+    ($($tokens:tt)*) => {
+        ()
+    };
+}
+
+/// A subscriber which collects spans, and displays them as a timeline: one span per line, in
+/// the order they started, indented by their depth, with their duration in milliseconds.
+#[derive(Debug, Default)]
+pub struct Timeline(RefCell<Vec<Record>>);
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline::default()
+    }
+
+    /// The spans collected so far, in the order they ended.
+    pub fn records(&self) -> Vec<Record> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Subscriber for Timeline {
+    fn record(&self, record: Record) {
+        self.0.borrow_mut().push(record);
+    }
+}
+
+impl fmt::Display for Timeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut records = self.records();
+        // Spans may start at the same instant as their parent, hence the identifier as a
+        // secondary key.
+        records.sort_by_key(|r| (r.start, r.id));
+
+        for record in &records {
+            let mut depth = 0;
+            let mut parent = record.parent;
+            while let Some(id) = parent {
+                depth += 1;
+                parent = records.iter().find(|r| r.id == id).and_then(|r| r.parent);
+            }
+
+            write!(
+                f,
+                "{:>10.3}ms {}{}",
+                record.elapsed.as_secs_f64() * 1000.0,
+                "  ".repeat(depth),
+                record.name
+            )?;
+            for (key, value) in &record.fields {
+                write!(f, " {}={}", key, value)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans() {
+        let timeline = Rc::new(Timeline::new());
+        set_subscriber(Some(timeline.clone()));
+
+        {
+            let _outer = Span::enter("outer", || vec![("file", String::from("main.ncl"))]);
+            let _inner = Span::enter("inner", Vec::new);
+            Span::event("event", || vec![("cache", String::from("hit"))]);
+        }
+        set_subscriber(None);
+        Span::enter("ignored", Vec::new);
+
+        let records = timeline.records();
+        let names: Vec<_> = records.iter().map(|r| (r.name, r.parent)).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("event", Some(records[1].id)),
+                ("inner", Some(records[2].id)),
+                ("outer", None)
+            ]
+        );
+        assert_eq!(records[0].elapsed, Duration::default());
+
+        let lines: Vec<String> = timeline
+            .to_string()
+            .lines()
+            .map(|line| String::from(line.split("ms ").nth(1).unwrap()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec!["outer file=main.ncl", "  inner", "    event cache=hit"]
+        );
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn pipeline() {
+        use crate::program::Program;
+        use std::io::Cursor;
+
+        let timeline = Rc::new(Timeline::new());
+        set_subscriber(Some(timeline.clone()));
+        let mut p = Program::new_from_source(Cursor::new("1 + 1"), "main.ncl").unwrap();
+        p.eval().unwrap();
+        set_subscriber(None);
+
+        let records = timeline.records();
+        for name in &["parse", "typecheck", "transform", "eval", "stdlib"] {
+            assert!(records.iter().any(|r| r.name == *name), "no {} span", name);
+        }
+        assert!(records
+            .iter()
+            .any(|r| r.name == "parse" && r.fields == vec![("file", String::from("main.ncl"))]));
+    }
+}
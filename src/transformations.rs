@@ -4,7 +4,7 @@ use crate::cache::ImportResolver;
 use crate::error::ImportError;
 use crate::eval::{Closure, Environment, IdentKind, Thunk};
 use crate::identifier::Ident;
-use crate::term::{Contract, RichTerm, Term};
+use crate::term::{Contract, RichTerm, Term, TraverseOrder};
 use crate::types::{AbsType, Types};
 use codespan::FileId;
 use simple_counter::*;
@@ -209,6 +209,10 @@ pub mod import_resolution {
 
                 Ok((RichTerm::new(Term::ResolvedImport(file_id), pos), ret))
             }
+            Term::ImportStr(path) => {
+                let s = resolver.resolve_str(&path, parent.clone(), &pos)?;
+                Ok((RichTerm::new(Term::Str(s), pos), None))
+            }
             t => Ok((RichTerm::new(t, pos), None)),
         }
     }
@@ -224,6 +228,7 @@ pub mod import_resolution {
 /// value is unwrapped.
 pub mod apply_contracts {
     use super::{RichTerm, Term};
+    use crate::label::Label;
     use crate::mk_app;
 
     /// If the top-level node of the AST is a meta-value, apply the meta-value's contracts to the
@@ -233,15 +238,21 @@ pub mod apply_contracts {
 
         match *term {
             Term::MetaValue(mut meta) if meta.value.is_some() => {
+                let inherited = pos.into_inherited();
+                let sensitive = meta.sensitive;
                 let inner = meta.types.iter().chain(meta.contracts.iter()).fold(
                     meta.value.take().unwrap(),
                     |acc, ctr| {
-                        mk_app!(
-                            ctr.types.clone().contract(),
-                            Term::Lbl(ctr.label.clone()),
-                            acc
-                        )
-                        .with_pos(pos)
+                        // The contract and the label are generated: they inherit the position of
+                        // the annotation, such that a failure points back to the source.
+                        let mut contract = ctr.types.clone().contract().with_pos(inherited);
+                        contract.inherit_pos();
+                        let label = Label {
+                            sensitive,
+                            ..ctr.label.clone()
+                        };
+                        let label = RichTerm::new(Term::Lbl(label), inherited);
+                        mk_app!(mk_app!(contract, label).with_pos(inherited), acc).with_pos(pos)
                     },
                 );
 
@@ -253,6 +264,343 @@ pub mod apply_contracts {
     }
 }
 
+/// Destructuring patterns are desugared directly at parsing time into simple let-bindings and
+/// field accesses, such that the typechecker and the evaluator never see them. For example,
+///
+/// ```text
+/// let {host, port = p, ..rest} = server in body
+/// ```
+///
+/// is rewritten to
+///
+/// ```text
+/// let %0 = server in
+/// let host = %0.host in
+/// let p = %0.port in
+/// let rest = %0 -$ "host" -$ "port" in
+/// body
+/// ```
+pub mod desugar_destructuring {
+    use super::{fresh_var, Ident, RichTerm, Term};
+    use crate::term::make as mk_term;
+    use crate::term::{BinaryOp, UnaryOp};
+
+    /// A destructuring pattern, as it can appear on the left-hand side of a let-binding or as a
+    /// function parameter.
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum Destruct {
+        /// A plain identifier, which binds the whole value.
+        Ident(Ident),
+        /// A record pattern `{field1, field2 = pat, ..rest}`. Each field is matched against a
+        /// sub-pattern, and the remaining fields are optionally bound to the last identifier.
+        Record(Vec<(Ident, Destruct)>, Option<Ident>),
+        /// A list pattern `[pat1, pat2]`, matching the elements of a list by position.
+        List(Vec<Destruct>),
+    }
+
+    /// Desugar `let pat = bound in body` into a sequence of simple let-bindings.
+    pub fn desugar_let(pat: Destruct, bound: RichTerm, body: RichTerm) -> RichTerm {
+        let pos = bound.pos;
+
+        match pat {
+            Destruct::Ident(id) => mk_term::let_in(id, bound, body),
+            pat => {
+                let var = fresh_var();
+                let body = bind(pat, mk_term::var(var.clone()).with_pos(pos), body);
+                mk_term::let_in(var, bound, body)
+            }
+        }
+    }
+
+    /// Desugar `fun pat => body` into a function of a fresh variable whose body destructures
+    /// its argument.
+    pub fn desugar_fun(pat: Destruct, body: RichTerm) -> RichTerm {
+        match pat {
+            Destruct::Ident(id) => Term::Fun(id, body).into(),
+            pat => {
+                let var = fresh_var();
+                let body = bind(pat, mk_term::var(var.clone()), body);
+                Term::Fun(var, body).into()
+            }
+        }
+    }
+
+    /// Bind the variables of `pat` to the corresponding parts of `value` inside `body`. `value`
+    /// is expected to be a variable, as it is duplicated for each sub-pattern.
+    fn bind(pat: Destruct, value: RichTerm, body: RichTerm) -> RichTerm {
+        let pos = value.pos;
+
+        match pat {
+            Destruct::Ident(id) => mk_term::let_in(id, value, body),
+            Destruct::Record(fields, rest) => {
+                let body = match rest {
+                    Some(rest) => {
+                        let remaining = fields.iter().fold(value.clone(), |acc, (id, _)| {
+                            mk_term::op2(BinaryOp::DynRemove(), Term::Str(id.to_string()), acc)
+                                .with_pos(pos)
+                        });
+                        mk_term::let_in(rest, remaining, body)
+                    }
+                    None => body,
+                };
+
+                fields.into_iter().rev().fold(body, |body, (id, pat)| {
+                    let field =
+                        mk_term::op1(UnaryOp::StaticAccess(id), value.clone()).with_pos(pos);
+                    desugar_let(pat, field, body)
+                })
+            }
+            Destruct::List(elems) => {
+                elems
+                    .into_iter()
+                    .enumerate()
+                    .rev()
+                    .fold(body, |body, (index, pat)| {
+                        let elem = mk_term::op2(
+                            BinaryOp::ListElemAt(),
+                            value.clone(),
+                            Term::Num(index as f64),
+                        )
+                        .with_pos(pos);
+                        desugar_let(pat, elem, body)
+                    })
+            }
+        }
+    }
+}
+
+/// Desugaring of `match` expressions into conditionals, primitive type tests and field accesses.
+///
+/// A `match` whose patterns are only enum tags, optionally followed by a catch-all case, is
+/// desugared to a `switch`, such that the typechecker is able to check its exhaustiveness.
+/// Otherwise, the cases are tried in order, and a value which is not matched by any case raises a
+/// blame error pointing to the `match` expression.
+pub mod desugar_match {
+    use super::{fresh_var, Ident, RichTerm, Term};
+    use crate::label::Label;
+    use crate::mk_app;
+    use crate::term::make as mk_term;
+    use crate::term::{BinaryOp, UnaryOp};
+    use std::collections::HashMap;
+
+    /// A pattern of a `match` case.
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum Pattern {
+        /// The wildcard `_`, which matches anything.
+        Any,
+        /// An identifier, which matches anything and binds the matched value.
+        Ident(Ident),
+        /// A constant (number, boolean, string or null), compared using `==`.
+        Lit(RichTerm),
+        /// An enum tag.
+        Enum(Ident),
+        /// A record pattern `{field1, field2 = pat, ..}`.
+        Record(Vec<(Ident, Pattern)>, RecordTail),
+        /// A list pattern `[pat1, pat2]`, which matches lists of exactly this length.
+        List(Vec<Pattern>),
+    }
+
+    /// The end of a record pattern, which determines if other fields than the ones listed are
+    /// allowed.
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum RecordTail {
+        /// `{a, b}`: the record must have exactly the listed fields.
+        Closed,
+        /// `{a, b, ..}`: other fields are allowed and ignored.
+        Open,
+        /// `{a, b, ..rest}`: other fields are allowed and bound as a record to `rest`.
+        Capture(Ident),
+    }
+
+    /// A case `pattern if guard => body` of a `match` expression.
+    #[derive(Debug, PartialEq, Clone)]
+    pub struct MatchCase {
+        pub pattern: Pattern,
+        pub guard: Option<RichTerm>,
+        pub body: RichTerm,
+    }
+
+    impl Pattern {
+        /// Return `true` if the pattern matches any value.
+        pub fn is_irrefutable(&self) -> bool {
+            matches!(self, Pattern::Any | Pattern::Ident(_))
+        }
+    }
+
+    /// Desugar `match { cases } exp`. `label` is used to report a value which is not matched by
+    /// any case.
+    pub fn desugar(exp: RichTerm, cases: Vec<MatchCase>, mut label: Label) -> RichTerm {
+        let var = fresh_var();
+        let value = mk_term::var(var.clone());
+
+        let body = match as_switch(&cases, &value) {
+            Some(switch) => switch,
+            None => {
+                label.tag = String::from("non-exhaustive match: no pattern matched the value");
+                // The blame is raised by the generated code: point at the matched expression.
+                label.arg_pos = exp.pos;
+                let fail = mk_term::op1(UnaryOp::Blame(), Term::Lbl(label));
+
+                cases.into_iter().rev().fold(fail, |rest, case| {
+                    let MatchCase {
+                        pattern,
+                        guard,
+                        body,
+                    } = case;
+                    let guard = guard.map(|guard| bind(&pattern, &value, guard));
+                    let cond = and(test(&pattern, &value), guard);
+                    let body = bind(&pattern, &value, body);
+
+                    match cond {
+                        Some(cond) => mk_term::if_then_else(cond, body, rest),
+                        None => body,
+                    }
+                })
+            }
+        };
+
+        mk_term::let_in(var, exp, body)
+    }
+
+    /// If all the cases are unguarded enum tags, possibly followed by a final catch-all case,
+    /// translate the match to a switch.
+    fn as_switch(cases: &[MatchCase], value: &RichTerm) -> Option<RichTerm> {
+        let (last, init) = cases.split_last()?;
+        let (init, default) = if last.pattern.is_irrefutable() && last.guard.is_none() {
+            (init, Some(bind(&last.pattern, value, last.body.clone())))
+        } else {
+            (cases, None)
+        };
+
+        let mut switch_cases = HashMap::with_capacity(init.len());
+        for case in init {
+            match (&case.pattern, &case.guard) {
+                (Pattern::Enum(id), None) => {
+                    switch_cases
+                        .entry(id.clone())
+                        .or_insert_with(|| case.body.clone());
+                }
+                _ => return None,
+            }
+        }
+
+        if switch_cases.is_empty() {
+            return default;
+        }
+
+        Some(Term::Switch(value.clone(), switch_cases, default).into())
+    }
+
+    /// The lazy conjunction of two optional conditions, where `None` stands for `true`.
+    fn and(c1: Option<RichTerm>, c2: Option<RichTerm>) -> Option<RichTerm> {
+        match (c1, c2) {
+            (Some(c1), Some(c2)) => Some(mk_app!(mk_term::op1(UnaryOp::BoolAnd(), c1), c2)),
+            (c1, None) => c1,
+            (None, c2) => c2,
+        }
+    }
+
+    /// Generate the condition under which `value` is matched by `pat`, or `None` if `pat` always
+    /// matches.
+    fn test(pat: &Pattern, value: &RichTerm) -> Option<RichTerm> {
+        match pat {
+            Pattern::Any | Pattern::Ident(_) => None,
+            Pattern::Lit(lit) => Some(mk_term::op2(BinaryOp::Eq(), value.clone(), lit.clone())),
+            Pattern::Enum(id) => Some(mk_term::op2(
+                BinaryOp::Eq(),
+                value.clone(),
+                Term::Enum(id.clone()),
+            )),
+            Pattern::Record(fields, tail) => {
+                let is_record = mk_term::op1(UnaryOp::IsRecord(), value.clone());
+                let size = match tail {
+                    RecordTail::Closed => Some(mk_term::op2(
+                        BinaryOp::Eq(),
+                        mk_term::op1(
+                            UnaryOp::ListLength(),
+                            mk_term::op1(UnaryOp::FieldsOf(), value.clone()),
+                        ),
+                        Term::Num(fields.len() as f64),
+                    )),
+                    _ => None,
+                };
+
+                let cond = fields
+                    .iter()
+                    .fold(and(Some(is_record), size), |acc, (id, pat)| {
+                        let has_field = mk_term::op2(
+                            BinaryOp::HasField(),
+                            Term::Str(id.to_string()),
+                            value.clone(),
+                        );
+                        let sub = test(pat, &access(value, id));
+                        and(and(acc, Some(has_field)), sub)
+                    });
+
+                cond
+            }
+            Pattern::List(elems) => {
+                let is_list = mk_term::op1(UnaryOp::IsList(), value.clone());
+                let length = mk_term::op2(
+                    BinaryOp::Eq(),
+                    mk_term::op1(UnaryOp::ListLength(), value.clone()),
+                    Term::Num(elems.len() as f64),
+                );
+
+                elems
+                    .iter()
+                    .enumerate()
+                    .fold(and(Some(is_list), Some(length)), |acc, (index, pat)| {
+                        and(acc, test(pat, &elem_at(value, index)))
+                    })
+            }
+        }
+    }
+
+    /// Bind the variables of `pat` to the corresponding parts of `value` inside `body`.
+    fn bind(pat: &Pattern, value: &RichTerm, body: RichTerm) -> RichTerm {
+        match pat {
+            Pattern::Any | Pattern::Lit(_) | Pattern::Enum(_) => body,
+            Pattern::Ident(id) => mk_term::let_in(id.clone(), value.clone(), body),
+            Pattern::Record(fields, tail) => {
+                let body = match tail {
+                    RecordTail::Capture(rest) => {
+                        let remaining = fields.iter().fold(value.clone(), |acc, (id, _)| {
+                            mk_term::op2(BinaryOp::DynRemove(), Term::Str(id.to_string()), acc)
+                        });
+                        mk_term::let_in(rest.clone(), remaining, body)
+                    }
+                    _ => body,
+                };
+
+                fields
+                    .iter()
+                    .rev()
+                    .fold(body, |body, (id, pat)| bind(pat, &access(value, id), body))
+            }
+            Pattern::List(elems) => elems
+                .iter()
+                .enumerate()
+                .rev()
+                .fold(body, |body, (index, pat)| {
+                    bind(pat, &elem_at(value, index), body)
+                }),
+        }
+    }
+
+    fn access(value: &RichTerm, id: &Ident) -> RichTerm {
+        mk_term::op1(UnaryOp::StaticAccess(id.clone()), value.clone())
+    }
+
+    fn elem_at(value: &RichTerm, index: usize) -> RichTerm {
+        mk_term::op2(
+            BinaryOp::ListElemAt(),
+            value.clone(),
+            Term::Num(index as f64),
+        )
+    }
+}
+
 /// The state passed around during the program transformation. It holds a reference to the import
 /// resolver, to a stack of pending imported term to be transformed and the path of the import
 /// currently being processed, if any.
@@ -305,8 +653,12 @@ where
         parent,
     };
 
+    // Terms coming from the parser have a position everywhere. The transformations must not
+    // introduce nodes without one, which would make the errors raised at runtime point nowhere.
+    let all_pos_defined = cfg!(debug_assertions) && rt.all_pos_defined();
+
     // Apply one step of each transformation. If an import is resolved, then stack it.
-    rt.traverse(
+    let mut result = rt.traverse(
         &mut |rt: RichTerm, state: &mut TransformState<R>| -> Result<RichTerm, ImportError> {
             // We need to do contract generation before wrapping stuff in variables
             let rt = apply_contracts::transform_one(rt);
@@ -321,12 +673,22 @@ where
             Ok(rt)
         },
         &mut state,
-    )
+        TraverseOrder::BottomUp,
+    )?;
+
+    // The nodes generated without a position are mapped back to the closest node of the source.
+    result.inherit_pos();
+    debug_assert!(
+        !all_pos_defined || result.all_pos_defined(),
+        "transformation lost a position: {:?}",
+        result
+    );
+    Ok(result)
 }
 
 /// Generate a new fresh variable which do not clash with user-defined variables.
 pub fn fresh_var() -> Ident {
-    Ident(format!("%{}", FreshVarCounter::next()))
+    Ident::from(format!("%{}", FreshVarCounter::next()))
 }
 
 /// Structures which can be packed together with their environment as a closure.
@@ -372,7 +734,7 @@ impl Closurizable for Contract {
     fn closurize(self, env: &mut Environment, with_env: Environment) -> Contract {
         Contract {
             types: self.types.closurize(env, with_env),
-            label: self.label,
+            ..self
         }
     }
 }
@@ -45,7 +45,7 @@ use crate::error::TypecheckError;
 use crate::eval;
 use crate::identifier::Ident;
 use crate::label::ty_path;
-use crate::position::TermPos;
+use crate::position::{RawSpan, TermPos};
 use crate::term::{BinaryOp, Contract, MetaValue, NAryOp, RichTerm, StrChunk, Term, UnaryOp};
 use crate::types::{AbsType, Types};
 use crate::{mk_tyw_arrow, mk_tyw_enum, mk_tyw_enum_row, mk_tyw_record, mk_tyw_row};
@@ -424,6 +424,8 @@ pub struct State<'a> {
     ///
     /// Used for error reporting.
     names: &'a mut HashMap<usize, Ident>,
+    /// If set, the type of each occurrence of a variable is recorded together with its position.
+    var_types: Option<&'a mut Vec<(RawSpan, TypeWrapper)>>,
 }
 
 /// Typecheck a term.
@@ -440,6 +442,7 @@ pub fn type_check(
         table: &mut UnifTable::new(),
         constr: &mut RowConstr::new(),
         names: &mut HashMap::new(),
+        var_types: None,
     };
     let ty = TypeWrapper::Ptr(new_var(state.table));
     let global = Envs::mk_global(global_eval_env);
@@ -468,6 +471,7 @@ pub fn type_check_in_env(
         table: &mut UnifTable::new(),
         constr: &mut RowConstr::new(),
         names: &mut HashMap::new(),
+        var_types: None,
     };
     let ty = TypeWrapper::Ptr(new_var(state.table));
     type_check_(&mut state, Envs::from_global(global), false, t, ty.clone())?;
@@ -475,6 +479,42 @@ pub fn type_check_in_env(
     Ok(to_type(&state.table, ty))
 }
 
+/// Typecheck a term using the given global typing environment, and record the type of each
+/// occurrence of a variable, indexed by its position. Used by the language server to show the
+/// types of identifiers.
+///
+/// Typechecking stops at the first error, but the types recorded up to this point are still
+/// returned together with the error.
+pub fn type_check_vars(
+    t: &RichTerm,
+    global: &Environment,
+    resolver: &dyn ImportResolver,
+) -> (Result<Types, TypecheckError>, Vec<(RawSpan, Types)>) {
+    let mut var_types = Vec::new();
+    let mut state = State {
+        resolver,
+        table: &mut UnifTable::new(),
+        constr: &mut RowConstr::new(),
+        names: &mut HashMap::new(),
+        var_types: Some(&mut var_types),
+    };
+    let ty = TypeWrapper::Ptr(new_var(state.table));
+    let result = type_check_(&mut state, Envs::from_global(global), false, t, ty.clone());
+    let recorded = state
+        .var_types
+        .take()
+        .map(std::mem::take)
+        .unwrap_or_default();
+
+    let mut names = reporting::NameReg::new();
+    let var_types = recorded
+        .into_iter()
+        .map(|(span, tyw)| (span, reporting::to_type(&state, &mut names, tyw)))
+        .collect();
+
+    (result.map(|()| to_type(state.table, ty)), var_types)
+}
+
 /// Typecheck a term against a specific type.
 ///
 /// # Arguments
@@ -596,6 +636,10 @@ fn type_check_(
                 .get(&x)
                 .ok_or_else(|| TypecheckError::UnboundIdentifier(x.clone(), *pos))?;
 
+            if let (Some(var_types), TermPos::Original(span)) = (state.var_types.as_mut(), pos) {
+                var_types.push((*span, ty.clone()));
+            }
+
             let instantiated = instantiate_foralls(state, x_ty, ForallInst::Ptr);
             unify(state, strict, ty, instantiated)
                 .map_err(|err| err.into_typecheck_err(state, rt.pos))
@@ -732,7 +776,11 @@ fn type_check_(
              unify(state, strict, ty, mk_typewrapper::dynamic())
                 .map_err(|err| err.into_typecheck_err(state, rt.pos))
         },
-        Term::Import(_) => unify(state, strict, ty, mk_typewrapper::dynamic())
+        // A part of the source that could not be parsed is inferred to be of type `Dyn`, so that
+        // the rest of a partial term produced by the error-recovering parser can be checked.
+        Term::Import(_) | Term::ParseError => unify(state, strict, ty, mk_typewrapper::dynamic())
+            .map_err(|err| err.into_typecheck_err(state, rt.pos)),
+        Term::ImportStr(_) => unify(state, strict, ty, mk_typewrapper::str())
             .map_err(|err| err.into_typecheck_err(state, rt.pos)),
         Term::ResolvedImport(file_id) => {
             let t = state
@@ -1407,7 +1455,7 @@ mod reporting {
             name = format!("{}{}", name, suffix);
         }
 
-        let ident = Ident(name);
+        let ident = Ident::from(name);
         name_reg.reg.insert(id, ident.clone());
         ident
     }
@@ -1591,6 +1639,12 @@ pub fn get_uop_type(
                 mk_tyw_arrow!(f_type, mk_typewrapper::list(a)),
             )
         }
+        // List Num -> Num
+        UnaryOp::ListSum()
+        | UnaryOp::ListProduct()
+        | UnaryOp::ListMin()
+        | UnaryOp::ListMax()
+        | UnaryOp::ListMean() => (mk_typewrapper::list(AbsType::Num()), mk_typewrapper::num()),
         // forall a b. { _ : a} -> (Str -> a -> b) -> { _ : b }
         UnaryOp::RecordMap() => {
             // Assuming f has type Str -> a -> b,
@@ -1661,6 +1715,23 @@ pub fn get_uop_type(
         UnaryOp::StrLowercase() => (mk_typewrapper::str(), mk_typewrapper::str()),
         // Str -> Num
         UnaryOp::StrLength() => (mk_typewrapper::str(), mk_typewrapper::num()),
+        // Str -> Str
+        UnaryOp::StrDedent() => (mk_typewrapper::str(), mk_typewrapper::str()),
+        // forall a. Str -> a -> a
+        UnaryOp::Trace() => {
+            let ty = TypeWrapper::Ptr(new_var(state.table));
+            (mk_typewrapper::str(), mk_tyw_arrow!(ty.clone(), ty))
+        }
+        // forall a. a -> Num
+        UnaryOp::Clock() => {
+            let ty = TypeWrapper::Ptr(new_var(state.table));
+            (ty, mk_typewrapper::num())
+        }
+        // List Dyn -> Dyn
+        UnaryOp::Native(..) => (
+            mk_typewrapper::list(mk_typewrapper::dynamic()),
+            mk_typewrapper::dynamic(),
+        ),
         // Dyn -> Str
         UnaryOp::ToStr() => (mk_typewrapper::dynamic(), mk_typewrapper::num()),
         // Str -> Num
@@ -1698,6 +1769,8 @@ pub fn get_bop_type(
         // Sym -> Dyn -> Dyn -> Dyn
         // This should not happen, as `ApplyContract()` is only produced during evaluation.
         BinaryOp::Assume() => panic!("cannot typecheck assume"),
+        // Same for `TagContracts()`, which is only produced when applying a record contract.
+        BinaryOp::TagContracts() => panic!("cannot typecheck tagContracts"),
         BinaryOp::Unwrap() => (
             mk_typewrapper::sym(),
             mk_typewrapper::dynamic(),
@@ -1741,7 +1814,7 @@ pub fn get_bop_type(
             )
         }
         // forall a. Str -> { _ : a } -> a -> { _ : a }
-        BinaryOp::DynExtend() => {
+        BinaryOp::DynExtend() | BinaryOp::DynDefine() => {
             let res = TypeWrapper::Ptr(new_var(state.table));
             (
                 mk_typewrapper::str(),
@@ -1781,7 +1854,7 @@ pub fn get_bop_type(
             )
         }
         // Dyn -> Dyn -> Dyn
-        BinaryOp::Merge() => (
+        BinaryOp::Merge() | BinaryOp::RecordOverride() => (
             mk_typewrapper::dynamic(),
             mk_typewrapper::dynamic(),
             mk_typewrapper::dynamic(),
@@ -1847,11 +1920,47 @@ pub fn get_bop_type(
             mk_typewrapper::str(),
             mk_typewrapper::list(AbsType::Str()),
         ),
+        // Str -> Num -> Str
+        BinaryOp::StrIndent() => (
+            mk_typewrapper::str(),
+            mk_typewrapper::num(),
+            mk_typewrapper::str(),
+        ),
+        // List Str -> Dyn -> Dyn
+        BinaryOp::GetPath() => (
+            mk_typewrapper::list(AbsType::Str()),
+            mk_typewrapper::dynamic(),
+            mk_typewrapper::dynamic(),
+        ),
+        // List Str -> Dyn -> Bool
+        BinaryOp::HasPath() => (
+            mk_typewrapper::list(AbsType::Str()),
+            mk_typewrapper::dynamic(),
+            mk_typewrapper::bool(),
+        ),
+        // Num -> Dyn -> Str
+        BinaryOp::DeepRepr() => (
+            mk_typewrapper::num(),
+            mk_typewrapper::dynamic(),
+            mk_typewrapper::str(),
+        ),
+        // Num -> Num -> Str
+        BinaryOp::ToStrFixed() => (
+            mk_typewrapper::num(),
+            mk_typewrapper::num(),
+            mk_typewrapper::str(),
+        ),
+        // Str -> Dyn -> Str
+        BinaryOp::StrFormat() => (
+            mk_typewrapper::str(),
+            mk_typewrapper::dynamic(),
+            mk_typewrapper::str(),
+        ),
     })
 }
 
 pub fn get_nop_type(
-    _state: &mut State,
+    state: &mut State,
     op: &NAryOp,
 ) -> Result<(Vec<TypeWrapper>, TypeWrapper), TypecheckError> {
     Ok(match op {
@@ -1864,6 +1973,36 @@ pub fn get_nop_type(
             ],
             mk_typewrapper::str(),
         ),
+        // Str -> Str -> Str -> Str
+        NAryOp::StrSplice() => (
+            vec![
+                mk_typewrapper::str(),
+                mk_typewrapper::str(),
+                mk_typewrapper::str(),
+            ],
+            mk_typewrapper::str(),
+        ),
+        // List Str -> (Dyn -> Dyn) -> Dyn -> Dyn
+        NAryOp::UpdatePath() => (
+            vec![
+                mk_typewrapper::list(AbsType::Str()),
+                mk_tyw_arrow!(mk_typewrapper::dynamic(), mk_typewrapper::dynamic()),
+                mk_typewrapper::dynamic(),
+            ],
+            mk_typewrapper::dynamic(),
+        ),
+        // Str -> Num -> Str -> Str
+        NAryOp::StrPadLeft()
+        | NAryOp::StrPadRight()
+        | NAryOp::StrCenter()
+        | NAryOp::StrTruncate() => (
+            vec![
+                mk_typewrapper::str(),
+                mk_typewrapper::num(),
+                mk_typewrapper::str(),
+            ],
+            mk_typewrapper::str(),
+        ),
         // Str -> Num -> Num -> Str
         NAryOp::StrSubstr() => (
             vec![
@@ -1873,6 +2012,15 @@ pub fn get_nop_type(
             ],
             mk_typewrapper::str(),
         ),
+        // forall a. Dyn -> Dyn -> Dyn -> a
+        NAryOp::BlameDiff() => (
+            vec![
+                mk_typewrapper::dynamic(),
+                mk_typewrapper::dynamic(),
+                mk_typewrapper::dynamic(),
+            ],
+            TypeWrapper::Ptr(new_var(state.table)),
+        ),
     })
 }
 
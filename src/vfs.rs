@@ -0,0 +1,221 @@
+//! Filesystem abstraction, used by the cache to load files and to resolve imports.
+//!
+//! By default, files are read from the filesystem of the operating system. An embedder can
+//! instead provide the sources through a [`VirtualFileSystem`](struct.VirtualFileSystem.html),
+//! which maps paths to contents, so that Nickel runs without any filesystem access, for example
+//! inside a WASI sandbox:
+//!
+//! ```ignore
+//! let mut fs = VirtualFileSystem::new();
+//! fs.add_file("/config/main.ncl", r#"import "lib.ncl" & {port = 80}"#);
+//! fs.add_file("/config/lib.ncl", r#"{host = "example.org"}"#);
+//!
+//! let mut program = Program::builder()
+//!     .source_file("/config/main.ncl")
+//!     .file_system(Rc::new(fs))
+//!     .build()?;
+//! ```
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// The operations needed by the cache to read files.
+pub trait FileSystem: Debug {
+    /// Read the whole content of a file.
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    /// Return the *modified at* timestamp of a file, used to invalidate cached files.
+    fn modified(&self, path: &Path) -> io::Result<SystemTime>;
+
+    /// Return the normalized absolute path of a file, which uniquely identifies it in the cache.
+    fn normalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Return `true` if the file exists.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Return the paths of the files of a directory and of its subdirectories, in lexicographic
+    /// order. Hidden files and directories, whose name starts with a dot, are skipped.
+    fn list_files(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+}
+
+/// Return `true` if a component of a path, relative to a listed directory, is hidden.
+fn is_hidden(path: &Path) -> bool {
+    path.components()
+        .any(|c| matches!(c, Component::Normal(name) if name.to_string_lossy().starts_with('.')))
+}
+
+/// The filesystem of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        fs::metadata(path)?.modified()
+    }
+
+    fn normalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn list_files(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if is_hidden(Path::new(path.file_name().unwrap_or_default())) {
+                continue;
+            } else if path.is_dir() {
+                files.extend(self.list_files(&path)?);
+            } else {
+                files.push(path);
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// An in-memory filesystem, mapping paths to contents.
+///
+/// Relative paths are relative to the root `/`. Paths are normalized lexically: `.` components
+/// are removed and `..` components remove the preceding component. Files never change, and all
+/// have the same timestamp.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualFileSystem {
+    files: HashMap<PathBuf, String>,
+}
+
+impl VirtualFileSystem {
+    pub fn new() -> Self {
+        VirtualFileSystem {
+            files: HashMap::new(),
+        }
+    }
+
+    /// Add a file, or replace its content if it already exists.
+    pub fn add_file(&mut self, path: impl AsRef<Path>, content: impl Into<String>) {
+        self.files
+            .insert(normalize_lexically(path.as_ref()), content.into());
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{}: no such file in the virtual filesystem", path.display()),
+        )
+    }
+}
+
+impl FileSystem for VirtualFileSystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.files
+            .get(&normalize_lexically(path))
+            .cloned()
+            .ok_or_else(|| Self::not_found(path))
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        if self.exists(path) {
+            Ok(SystemTime::UNIX_EPOCH)
+        } else {
+            Err(Self::not_found(path))
+        }
+    }
+
+    fn normalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let normalized = normalize_lexically(path);
+
+        if self.files.contains_key(&normalized) {
+            Ok(normalized)
+        } else {
+            Err(Self::not_found(path))
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize_lexically(path))
+    }
+
+    fn list_files(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = normalize_lexically(dir);
+        let mut files: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|path| matches!(path.strip_prefix(&dir), Ok(rel) if !is_hidden(rel)))
+            .cloned()
+            .collect();
+
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// Make a path absolute relatively to the root and remove its `.` and `..` components, without
+/// accessing any filesystem.
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut result = PathBuf::from("/");
+
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => result.push(c),
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => (),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtual_paths() {
+        let mut fs = VirtualFileSystem::new();
+        fs.add_file("config/main.ncl", "1");
+        fs.add_file("/config/./lib/../lib.ncl", "2");
+
+        assert_eq!(
+            fs.read_to_string(Path::new("/config/main.ncl")).unwrap(),
+            "1"
+        );
+        assert_eq!(
+            fs.read_to_string(Path::new("config/lib/../lib.ncl"))
+                .unwrap(),
+            "2"
+        );
+        assert_eq!(
+            fs.normalize(Path::new("./config/lib.ncl")).unwrap(),
+            PathBuf::from("/config/lib.ncl")
+        );
+        assert!(fs.exists(Path::new("/config/../config/main.ncl")));
+        assert!(!fs.exists(Path::new("/main.ncl")));
+        assert!(fs.read_to_string(Path::new("/main.ncl")).is_err());
+        assert!(fs.modified(Path::new("/main.ncl")).is_err());
+
+        fs.add_file("/config/.git/config", "3");
+        assert_eq!(
+            fs.list_files(Path::new("config")).unwrap(),
+            vec![
+                PathBuf::from("/config/lib.ncl"),
+                PathBuf::from("/config/main.ncl")
+            ]
+        );
+    }
+}
@@ -0,0 +1,468 @@
+//! Warnings: non-fatal diagnostics about suspicious code.
+//!
+//! Warnings are found at different stages: deprecated syntax is found when lexing a source, while
+//! shadowed bindings, unused imports and implicitly dynamic variables in statically typed code
+//! are found by a pass over the parsed term (see [`check`](fn.check.html)). Each kind of warning
+//! has a name and an [error code](../explain/index.html) in the `N07xx` range.
+//!
+//! The [level](enum.Level.html) of each kind of warning is given by a [`Config`](struct.Config.html):
+//! a warning can be allowed, which silences it, reported, which is the default, or denied, which
+//! turns it into an error (see [`Error::DeniedWarnings`](../error/enum.Error.html)).
+use crate::error::diagnostic::{Diagnostic, Label, Severity};
+use crate::identifier::Ident;
+use crate::parser::lexer::{Lexer, NormalToken, Token};
+use crate::position::RawSpan;
+use crate::term::{Contract, MetaValue, RichTerm, Term};
+use crate::typecheck::{apparent_type, ApparentType};
+use codespan::{ByteIndex, FileId};
+use std::collections::HashMap;
+
+/// A kind of warning.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Kind {
+    Deprecated,
+    Shadowing,
+    ImplicitDyn,
+    UnusedImport,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [
+        Kind::Deprecated,
+        Kind::Shadowing,
+        Kind::ImplicitDyn,
+        Kind::UnusedImport,
+    ];
+
+    /// The name of the kind, as given to `--allow` and `--deny`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Deprecated => "deprecated",
+            Kind::Shadowing => "shadowing",
+            Kind::ImplicitDyn => "implicit-dyn",
+            Kind::UnusedImport => "unused-import",
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Kind::Deprecated => "N0701",
+            Kind::Shadowing => "N0702",
+            Kind::ImplicitDyn => "N0703",
+            Kind::UnusedImport => "N0704",
+        }
+    }
+
+    /// Find a kind from its name or its code.
+    pub fn from_name(name: &str) -> Option<Kind> {
+        Kind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.name() == name || kind.code().eq_ignore_ascii_case(name))
+    }
+}
+
+/// A warning.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// Use of a deprecated syntax, with the syntax which replaces it and how to migrate, if this
+    /// is not just a matter of replacing one with the other.
+    Deprecated {
+        syntax: &'static str,
+        replacement: &'static str,
+        span: RawSpan,
+        note: Option<&'static str>,
+    },
+    /// A binding shadows a local binding of the same name, whose span is given.
+    Shadowing {
+        name: Ident,
+        span: RawSpan,
+        shadowed: RawSpan,
+    },
+    /// A variable of statically typed code is bound by an unannotated binding of dynamically typed
+    /// code, and thus has type `Dyn`.
+    ImplicitDyn {
+        name: Ident,
+        span: RawSpan,
+        binding: RawSpan,
+    },
+    /// An import is bound to a variable which is never used.
+    UnusedImport { name: Ident, span: RawSpan },
+}
+
+impl Warning {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Warning::Deprecated { .. } => Kind::Deprecated,
+            Warning::Shadowing { .. } => Kind::Shadowing,
+            Warning::ImplicitDyn { .. } => Kind::ImplicitDyn,
+            Warning::UnusedImport { .. } => Kind::UnusedImport,
+        }
+    }
+
+    /// The span of the code causing the warning.
+    pub fn span(&self) -> RawSpan {
+        match self {
+            Warning::Deprecated { span, .. }
+            | Warning::Shadowing { span, .. }
+            | Warning::ImplicitDyn { span, .. }
+            | Warning::UnusedImport { span, .. } => *span,
+        }
+    }
+
+    /// Convert the warning to a diagnostic. A denied warning is reported as an error.
+    pub fn diagnostic(&self, level: Level) -> Diagnostic {
+        let (severity, note) = match level {
+            Level::Deny => (
+                Severity::Error,
+                format!("the `{}` warnings are denied", self.kind().name()),
+            ),
+            _ => (
+                Severity::Warning,
+                format!(
+                    "use `--allow {}` to silence this warning",
+                    self.kind().name()
+                ),
+            ),
+        };
+
+        let diagnostic = match self {
+            Warning::Deprecated {
+                syntax,
+                replacement,
+                span,
+                note,
+            } => Diagnostic::new(severity, format!("`{}` is deprecated", syntax))
+                .with_labels(vec![Label::new(
+                    true,
+                    *span,
+                    format!("use `{}` instead", replacement),
+                )])
+                .with_notes(note.iter().map(|note| String::from(*note)).collect()),
+            Warning::Shadowing {
+                name,
+                span,
+                shadowed,
+            } => Diagnostic::new(severity, format!("`{}` shadows a previous binding", name))
+                .with_labels(vec![
+                    Label::new(true, *span, "this binding"),
+                    Label::new(false, *shadowed, "shadows this one"),
+                ]),
+            Warning::ImplicitDyn {
+                name,
+                span,
+                binding,
+            } => Diagnostic::new(
+                severity,
+                format!("`{}` has the type `Dyn` in statically typed code", name),
+            )
+            .with_labels(vec![
+                Label::new(true, *span, "used here"),
+                Label::new(false, *binding, "bound here without a type annotation"),
+            ])
+            .with_notes(vec![String::from(
+                "annotate the binding with a type to check its uses statically",
+            )]),
+            Warning::UnusedImport { name, span } => {
+                Diagnostic::new(severity, format!("unused import `{}`", name))
+                    .with_labels(vec![Label::new(true, *span, "never used")])
+            }
+        };
+
+        diagnostic
+            .with_code(self.kind().code())
+            .with_notes(vec![note])
+    }
+}
+
+/// The level of a kind of warning.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Level {
+    /// The warning is silenced.
+    Allow,
+    /// The warning is reported.
+    Warn,
+    /// The warning is reported as an error.
+    Deny,
+}
+
+/// The levels of the kinds of warnings. A level set for a kind takes precedence over a level set
+/// for all warnings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    all: Option<Level>,
+    levels: HashMap<Kind, Level>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    /// Set the level of a kind of warning, given by its name or its code, or of all warnings if
+    /// `name` is `warnings`.
+    pub fn set(&mut self, name: &str, level: Level) -> Result<(), String> {
+        if name == "warnings" {
+            self.all = Some(level);
+        } else {
+            let kind = Kind::from_name(name).ok_or_else(|| format!("unknown warning: {}", name))?;
+            self.levels.insert(kind, level);
+        }
+
+        Ok(())
+    }
+
+    pub fn level(&self, kind: Kind) -> Level {
+        self.levels
+            .get(&kind)
+            .copied()
+            .or(self.all)
+            .unwrap_or(Level::Warn)
+    }
+}
+
+/// Return the uses of deprecated syntax in a source.
+pub fn deprecations(file_id: FileId, source: &str) -> Vec<Warning> {
+    Lexer::new(source)
+        .filter_map(|token| match token {
+            Ok((start, Token::Normal(NormalToken::Switch), end)) => Some(Warning::Deprecated {
+                syntax: "switch",
+                replacement: "match",
+                note: Some("the cases of `match` are enum tags, such as `` `foo => ``, while `foo =>` matches any value"),
+                span: RawSpan {
+                    src_id: file_id,
+                    start: ByteIndex(start as u32),
+                    end: ByteIndex(end as u32),
+                },
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Return the warnings of a parsed term: shadowed bindings, unused imports and implicitly dynamic
+/// variables, sorted by position.
+pub fn check(rt: &RichTerm) -> Vec<Warning> {
+    let mut checker = Checker::default();
+    checker.check(rt, false);
+
+    let mut warnings = checker.warnings;
+    warnings.sort_by_key(|warning| {
+        let span = warning.span();
+        (span.src_id, span.start)
+    });
+    warnings
+}
+
+/// A local binding in scope.
+struct Binding<'a> {
+    id: &'a Ident,
+    /// Whether the binding is a local variable, as opposed to the field of a recursive record.
+    local: bool,
+    /// Whether the binding is given the type `Dyn` by the typechecker, because it is an
+    /// unannotated binding of dynamically typed code.
+    implicit_dyn: bool,
+    import: bool,
+    used: bool,
+}
+
+#[derive(Default)]
+struct Checker<'a> {
+    scope: Vec<Binding<'a>>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Checker<'a> {
+    /// Check a term. `strict` is `true` in statically typed code.
+    fn check(&mut self, rt: &'a RichTerm, strict: bool) {
+        match rt.term.as_ref() {
+            Term::Let(id, bound, body) => {
+                self.check(bound, strict);
+                let implicit_dyn = !strict && self.is_approximated(bound);
+                let import = matches!(
+                    bound.term.as_ref(),
+                    Term::Import(_) | Term::ImportStr(_) | Term::ResolvedImport(_)
+                );
+                self.bind(id, true, implicit_dyn, import);
+                self.check(body, strict);
+                self.unbind(1);
+            }
+            Term::Fun(id, body) => {
+                self.bind(id, true, false, false);
+                self.check(body, strict);
+                self.unbind(1);
+            }
+            Term::RecRecord(fields) => {
+                for (id, field) in fields {
+                    let implicit_dyn = !strict && self.is_approximated(field);
+                    self.bind(id, false, implicit_dyn, false);
+                }
+                for field in fields.values() {
+                    self.check(field, strict);
+                }
+                self.unbind(fields.len());
+            }
+            Term::Var(id) => {
+                let span = rt.pos.into_opt();
+                let binding = self
+                    .scope
+                    .iter_mut()
+                    .rev()
+                    .find(|binding| binding.id.label == id.label);
+
+                if let Some(binding) = binding {
+                    binding.used = true;
+                    if let (true, Some(span), Some(bound)) = (
+                        strict && binding.implicit_dyn,
+                        span,
+                        binding.id.pos.into_opt(),
+                    ) {
+                        // Only the first use of the binding is reported.
+                        binding.implicit_dyn = false;
+                        self.warnings.push(Warning::ImplicitDyn {
+                            name: id.clone(),
+                            span,
+                            binding: bound,
+                        });
+                    }
+                }
+            }
+            Term::Promise(_, _, t)
+            | Term::MetaValue(MetaValue {
+                types: Some(Contract { .. }),
+                value: Some(t),
+                ..
+            }) => self.check(t, true),
+            Term::MetaValue(MetaValue {
+                contracts,
+                value: Some(t),
+                ..
+            }) if !contracts.is_empty() => self.check(t, false),
+            term => term.for_each_subterm(|sub| self.check(sub, strict)),
+        }
+    }
+
+    /// Return `true` if the typechecker approximates the type of an unannotated binding, as
+    /// opposed to a binding whose type is annotated or obvious.
+    fn is_approximated(&self, bound: &RichTerm) -> bool {
+        match bound.term.as_ref() {
+            Term::Var(id) => self
+                .scope
+                .iter()
+                .rev()
+                .find(|binding| binding.id.label == id.label)
+                .is_some_and(|binding| binding.implicit_dyn),
+            term => matches!(apparent_type(term, None), ApparentType::Approximated(_)),
+        }
+    }
+
+    fn bind(&mut self, id: &'a Ident, local: bool, implicit_dyn: bool, import: bool) {
+        // Generated variables, such as the ones of desugared patterns, can't be shadowed.
+        let from_source = !id.label.starts_with('%');
+
+        if let (true, true, Some(span)) = (from_source, local, id.pos.into_opt()) {
+            let shadowed = self
+                .scope
+                .iter()
+                .rev()
+                .find(|binding| binding.local && binding.id.label == id.label)
+                .and_then(|binding| binding.id.pos.into_opt());
+
+            if let Some(shadowed) = shadowed {
+                self.warnings.push(Warning::Shadowing {
+                    name: id.clone(),
+                    span,
+                    shadowed,
+                });
+            }
+        }
+
+        self.scope.push(Binding {
+            id,
+            local,
+            implicit_dyn: implicit_dyn && from_source,
+            import,
+            used: false,
+        });
+    }
+
+    fn unbind(&mut self, count: usize) {
+        for _ in 0..count {
+            let binding = self.scope.pop().unwrap();
+            if let (true, false, Some(span)) =
+                (binding.import, binding.used, binding.id.pos.into_opt())
+            {
+                self.warnings.push(Warning::UnusedImport {
+                    name: binding.id.clone(),
+                    span,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{grammar, lexer};
+    use codespan::Files;
+
+    fn warnings(source: &str) -> Vec<(Kind, String)> {
+        let file_id = Files::new().add("<test>", String::from(source));
+        let rt = grammar::TermParser::new()
+            .parse_strict(file_id, lexer::Lexer::new(source))
+            .unwrap();
+
+        deprecations(file_id, source)
+            .into_iter()
+            .chain(check(&rt))
+            .map(|warning| {
+                let span = warning.span();
+                let text = &source[span.start.to_usize()..span.end.to_usize()];
+                (warning.kind(), String::from(text))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kinds() {
+        assert_eq!(
+            warnings("switch { a => 1, _ => 2 } `a"),
+            vec![(Kind::Deprecated, String::from("switch"))]
+        );
+        assert_eq!(
+            warnings("let x = 1 in let f = fun x => x in f x"),
+            vec![(Kind::Shadowing, String::from("x"))]
+        );
+        assert_eq!(
+            warnings("let lib = import \"lib.ncl\" in let other = import \"other.ncl\" in lib"),
+            vec![(Kind::UnusedImport, String::from("other"))]
+        );
+        assert_eq!(
+            warnings("let x = 1 + 1 in let y : Num = 2 in (x == y : Bool)"),
+            vec![(Kind::ImplicitDyn, String::from("x"))]
+        );
+    }
+
+    #[test]
+    fn no_warnings() {
+        // Annotated and obvious bindings, and bindings of statically typed code, have a type.
+        assert!(warnings("let x = 1 in let y | Num = 1 + 1 in (x + y : Num)").is_empty());
+        assert!(warnings("(let x = 1 + 1 in x + 1) : Num").is_empty());
+        // Record fields don't shadow variables.
+        assert!(warnings("let a = 1 in {a = 2, b = a}").is_empty());
+        assert!(warnings("match { `a => 1, _ => 2 } `a").is_empty());
+    }
+
+    #[test]
+    fn config() {
+        let mut config = Config::new();
+        assert_eq!(config.level(Kind::Shadowing), Level::Warn);
+
+        config.set("warnings", Level::Deny).unwrap();
+        config.set("N0702", Level::Allow).unwrap();
+        assert_eq!(config.level(Kind::Shadowing), Level::Allow);
+        assert_eq!(config.level(Kind::UnusedImport), Level::Deny);
+        assert!(config.set("unknown", Level::Allow).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! Support for calling the [C interface](../capi/index.html) from a WebAssembly host, enabled by
+//! the `wasm` feature.
+//!
+//! The functions of the C interface take NUL-terminated strings, which must live in the linear
+//! memory of the module. A host, such as a JavaScript runtime, can't allocate memory there by
+//! itself: it calls [`nickel_alloc`](fn.nickel_alloc.html) to get a buffer, writes the string and
+//! its terminating NUL byte in it, passes it to the C interface, and releases it with
+//! [`nickel_dealloc`](fn.nickel_dealloc.html):
+//!
+//! ```js
+//! const bytes = new TextEncoder().encode(source + "\0");
+//! const ptr = nickel.nickel_alloc(bytes.length);
+//! new Uint8Array(nickel.memory.buffer, ptr, bytes.length).set(bytes);
+//! const status = nickel.nickel_eval_string(ctx, ptr);
+//! nickel.nickel_dealloc(ptr, bytes.length);
+//! ```
+//!
+//! The module is built with `cargo rustc --lib --release --target wasm32-wasi
+//! --no-default-features --features wasm --crate-type cdylib`.
+use std::alloc::{self, Layout};
+use std::ptr;
+
+/// The layout of a buffer of `len` bytes, or `None` if `len` is too large.
+fn layout(len: usize) -> Option<Layout> {
+    Layout::array::<u8>(len).ok()
+}
+
+/// Allocate a buffer of `len` bytes in the memory of the module. Return a null pointer if `len`
+/// is zero or if the allocation failed.
+#[no_mangle]
+pub extern "C" fn nickel_alloc(len: usize) -> *mut u8 {
+    match layout(len) {
+        Some(layout) if len > 0 => unsafe { alloc::alloc(layout) },
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Free a buffer allocated by [`nickel_alloc`](fn.nickel_alloc.html). `len` must be the length
+/// given to `nickel_alloc`. Freeing a null pointer does nothing.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been returned by `nickel_alloc(len)`, and must not be used after
+/// this call.
+#[no_mangle]
+pub unsafe extern "C" fn nickel_dealloc(ptr: *mut u8, len: usize) {
+    if let Some(layout) = layout(len) {
+        if !ptr.is_null() && len > 0 {
+            alloc::dealloc(ptr, layout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capi::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn eval_from_buffer() {
+        let source = b"{port = 80 + 8000}\0";
+        let buf = nickel_alloc(source.len());
+        assert!(!buf.is_null());
+        assert!(nickel_alloc(0).is_null());
+
+        unsafe {
+            ptr::copy_nonoverlapping(source.as_ptr(), buf, source.len());
+            let ctx = nickel_context_new();
+            assert_eq!(nickel_eval_string(ctx, buf as *const _), 0);
+            let result = CStr::from_ptr(nickel_result_json(ctx)).to_str().unwrap();
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(result).unwrap(),
+                serde_json::json!({"port": 8080})
+            );
+            nickel_context_free(ctx);
+            nickel_dealloc(buf, source.len());
+            nickel_dealloc(ptr::null_mut(), 0);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use assert_matches::assert_matches;
-use nickel::error::{Error, EvalError};
+use nickel::error::{Error, EvalError, ParseError};
 
 mod common;
 use common::eval;
@@ -12,6 +12,34 @@ fn div_by_zero() {
     );
 }
 
+#[test]
+fn non_exhaustive_match() {
+    assert_matches!(
+        eval("match {1 => true, {a} => a} 2"),
+        Err(Error::EvalError(EvalError::BlameError(..)))
+    );
+    assert_matches!(
+        eval("match {x if x > 0 => x} (-1)"),
+        Err(Error::EvalError(EvalError::BlameError(..)))
+    );
+}
+
+#[test]
+fn format_spec() {
+    assert_matches!(
+        eval("\"#{1.5:d}\""),
+        Err(Error::EvalError(EvalError::Other(..)))
+    );
+    assert_matches!(
+        eval("\"#{\"a\":x}\""),
+        Err(Error::EvalError(EvalError::Other(..)))
+    );
+    assert_matches!(
+        eval("strings.format \"bad\" 1"),
+        Err(Error::EvalError(EvalError::Other(..)))
+    );
+}
+
 #[test]
 fn comparisons() {
     assert_matches!(
@@ -63,3 +91,35 @@ fn string_chunks() {
         Err(Error::EvalError(EvalError::TypeError(..)))
     );
 }
+
+#[test]
+fn string_padding() {
+    assert_matches!(
+        eval("%strPadLeft% \"a\" 3 \"ab\""),
+        Err(Error::EvalError(EvalError::Other(..)))
+    );
+    assert_matches!(
+        eval("%strPadRight% \"a\" (-1) \" \""),
+        Err(Error::EvalError(EvalError::Other(..)))
+    );
+    assert_matches!(
+        eval("%strTruncate% \"abcdef\" 2 \"...\""),
+        Err(Error::EvalError(EvalError::Other(..)))
+    );
+    assert_matches!(
+        eval("%strCenter% 1 3 \" \""),
+        Err(Error::EvalError(EvalError::TypeError(..)))
+    );
+}
+
+#[test]
+fn missing_body() {
+    assert_matches!(
+        eval("let x = 1; let y = 2;"),
+        Err(Error::ParseError(ParseError::UnexpectedEOF(..)))
+    );
+    assert_matches!(
+        eval("let x = 1"),
+        Err(Error::ParseError(ParseError::UnexpectedEOF(..)))
+    );
+}
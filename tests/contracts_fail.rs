@@ -171,6 +171,39 @@ fn lists_contracts() {
     res.unwrap_err().to_diagnostic(&mut files, None);
 }
 
+#[test]
+fn equal() {
+    use nickel::diff::Change;
+    use serde_json::json;
+
+    let res = eval(
+        "let expected = {port = 80, hosts = [\"a\", \"b\"]} in
+        {port = 40 + 40, hosts = [\"a\", \"c\"], debug = true} | #(contracts.equal expected)",
+    );
+    match &res {
+        Err(Error::EvalError(EvalError::BlameError(ref l, _))) => assert_eq!(
+            l.diff,
+            vec![
+                Change::Added {
+                    path: String::from("debug"),
+                    value: json!(true),
+                },
+                Change::Changed {
+                    path: String::from("hosts[1]"),
+                    expected: json!("b"),
+                    actual: json!("c"),
+                },
+            ]
+        ),
+        err => panic!("expected blame error, got {:?}", err),
+    }
+    let mut files = Files::new();
+    let diagnostics = res.unwrap_err().diagnostics(&mut files, None);
+    assert_eq!(diagnostics[0].diff().len(), 2);
+
+    assert_raise_blame!("1 | #(contracts.equal \"1\")");
+}
+
 // #[test]
 // fn enum_complex() {
 //     eval(
@@ -52,6 +52,11 @@ fn contracts() {
     check_file("contracts.ncl");
 }
 
+#[test]
+fn destructuring() {
+    check_file("destructuring.ncl");
+}
+
 #[test]
 fn eq() {
     check_file("eq.ncl")
@@ -67,6 +72,11 @@ fn lists() {
     check_file("lists.ncl");
 }
 
+#[test]
+fn match_expr() {
+    check_file("match.ncl");
+}
+
 #[test]
 fn metavalues() {
     check_file("metavalues.ncl");
@@ -82,6 +92,11 @@ fn record_defs() {
     check_file("record-defs.ncl");
 }
 
+#[test]
+fn random() {
+    check_file("random.ncl");
+}
+
 #[test]
 fn strings() {
     check_file("strings.ncl");
@@ -107,3 +122,8 @@ fn serialize() {
 fn annot_parsing() {
     check_file("annotations.ncl");
 }
+
+#[test]
+fn statements() {
+    check_file("statements.ncl");
+}
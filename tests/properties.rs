@@ -0,0 +1,141 @@
+//! Property tests on randomly generated well-typed terms, see `src/generate.rs`. Run with
+//! `cargo test --features property-tests --test properties`. The number of generated terms can be
+//! set with the `NICKEL_PROPERTY_CASES` environment variable, and the first seed with
+//! `NICKEL_PROPERTY_SEED`, to reproduce a failure.
+#![cfg(feature = "property-tests")]
+
+use codespan::Files;
+use nickel::error::{Error, EvalError};
+use nickel::generate::Generator;
+use nickel::parser::{grammar, lexer};
+use nickel::position::TermPos;
+use nickel::pretty::{pretty, pretty_types, DEFAULT_WIDTH};
+use nickel::program::Program;
+use nickel::term::{RichTerm, Term, TraverseOrder};
+use nickel::types::Types;
+use std::io::Cursor;
+use std::thread;
+
+const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+fn env_or(var: &str, default: u64) -> u64 {
+    std::env::var(var)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Run a property on the terms generated from a range of seeds.
+fn for_all<F>(property: F)
+where
+    F: Fn(u64, &Types, &RichTerm) + Send + 'static,
+{
+    let first = env_or("NICKEL_PROPERTY_SEED", 0);
+    let cases = env_or("NICKEL_PROPERTY_CASES", 200);
+
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            for seed in first..first + cases {
+                let mut gen = Generator::new(seed);
+                let ty = gen.types(2);
+                let t = gen.term(&ty, 4);
+                property(seed, &ty, &t);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+fn erase_positions(rt: RichTerm) -> RichTerm {
+    rt.traverse::<_, _, ()>(
+        &mut |mut rt: RichTerm, _| {
+            rt.pos = TermPos::None;
+            Ok(rt)
+        },
+        &mut (),
+        TraverseOrder::TopDown,
+    )
+    .unwrap()
+}
+
+fn parse(source: &str) -> RichTerm {
+    let file_id = Files::new().add("<generated>", String::from(source));
+    grammar::TermParser::new()
+        .parse_strict(file_id, lexer::Lexer::new(source))
+        .unwrap_or_else(|err| panic!("could not parse {}: {:?}", source, err))
+}
+
+/// The source of the term annotated with its type.
+fn annotated(ty: &Types, t: &RichTerm) -> String {
+    format!(
+        "({}) : {}",
+        pretty(t, DEFAULT_WIDTH),
+        pretty_types(ty, DEFAULT_WIDTH)
+    )
+}
+
+fn eval(source: &str) -> Result<Term, Error> {
+    let mut p = Program::new_from_source(Cursor::new(source.to_owned()), "<generated>").unwrap();
+    p.typecheck()?;
+    p.eval_full()
+}
+
+#[test]
+fn print_parse() {
+    for_all(|seed, _, t| {
+        for &width in &[DEFAULT_WIDTH, 20] {
+            let source = pretty(t, width);
+            let parsed = parse(&source);
+            assert_eq!(
+                erase_positions(parsed.clone()),
+                erase_positions(t.clone()),
+                "seed {}: parsing the printed term gives a different term\n{}",
+                seed,
+                source
+            );
+            assert_eq!(
+                pretty(&parsed, width),
+                source,
+                "seed {}: printing the parsed term gives a different source",
+                seed
+            );
+        }
+    });
+}
+
+#[test]
+fn well_typed() {
+    for_all(|seed, ty, t| {
+        let source = annotated(ty, t);
+        match eval(&source) {
+            Ok(_) => (),
+            Err(Error::EvalError(EvalError::TypeError(..)))
+            | Err(Error::EvalError(EvalError::NotAFunc(..)))
+            | Err(Error::EvalError(EvalError::FieldMissing(..)))
+            | Err(Error::TypecheckError(_)) => panic!(
+                "seed {}: well-typed term failed: {:?}\n{}",
+                seed,
+                eval(&source),
+                source
+            ),
+            // Other errors, such as an arithmetic overflow, are not type errors.
+            Err(_) => (),
+        }
+    });
+}
+
+#[test]
+fn deterministic_eval() {
+    for_all(|seed, ty, t| {
+        let source = annotated(ty, t);
+        assert_eq!(
+            eval(&source).ok(),
+            eval(&source).ok(),
+            "seed {}: evaluations give different results\n{}",
+            seed,
+            source
+        );
+    });
+}
@@ -11,10 +11,22 @@ fn records_access() {
         Err(Error::EvalError(EvalError::FieldMissing(..)))
     );
     assert_matches!(
-        eval("({ \"#{(if false then \"foo\" else \"bar\")}\" = false, bar = true, }).foo"),
+        eval("({ \"#{(if false then \"foo\" else \"bar\")}\" = false, bar = true, }).bar"),
+        Err(Error::EvalError(EvalError::MergeIncompatibleArgs(..)))
+    );
+    assert_matches!(
+        eval("({bar = true}$[\"bar\" = false]).bar"),
         Err(Error::EvalError(EvalError::Other(msg, ..))) if msg.starts_with("$[ .. ]"));
 }
 
+#[test]
+fn spread_non_record() {
+    assert_matches!(
+        eval("{..1, a = 2}"),
+        Err(Error::EvalError(EvalError::TypeError(..)))
+    );
+}
+
 #[test]
 fn non_mergeable() {
     assert_matches!(
@@ -42,3 +54,15 @@ fn non_mergeable_piecewise() {
         Err(Error::EvalError(EvalError::MergeIncompatibleArgs(..)))
     );
 }
+
+#[test]
+fn missing_path() {
+    assert_matches!(
+        eval("records.getPath [\"a\", \"c\"] {a = {b = 1}}"),
+        Err(Error::EvalError(EvalError::FieldMissing(..)))
+    );
+    assert_matches!(
+        eval("(records.updatePath [\"a\", \"c\"] (fun x => x) {a = {b = 1}}).a"),
+        Err(Error::EvalError(EvalError::FieldMissing(..)))
+    );
+}
@@ -74,3 +74,32 @@ fn head_tail() {
         Err(Error::EvalError(EvalError::BlameError(..)))
     );
 }
+
+#[test]
+fn aggregation() {
+    assert_matches!(
+        eval("%listMin% []"),
+        Err(Error::EvalError(EvalError::Other(..)))
+    );
+    assert_matches!(
+        eval("%listMean% []"),
+        Err(Error::EvalError(EvalError::Other(..)))
+    );
+    assert_matches!(
+        eval("%listSum% {}"),
+        Err(Error::EvalError(EvalError::TypeError(..)))
+    );
+    assert_matches!(
+        eval("%listSum% [1, \"a\"]"),
+        Err(Error::EvalError(EvalError::TypeError(..)))
+    );
+    assert_matches!(
+        eval("%listSum% [1, \"a\" ++ \"b\"]"),
+        Err(Error::EvalError(EvalError::TypeError(..)))
+    );
+
+    assert_matches!(
+        eval("lists.sum [1, \"a\"]"),
+        Err(Error::EvalError(EvalError::BlameError(..)))
+    );
+}
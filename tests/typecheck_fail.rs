@@ -16,7 +16,7 @@ fn type_check_expr(s: impl std::string::ToString) -> Result<Types, TypecheckErro
     let id = Files::new().add("<test>", s.clone());
     type_check(
         &grammar::TermParser::new()
-            .parse(id, lexer::Lexer::new(&s))
+            .parse_strict(id, lexer::Lexer::new(&s))
             .unwrap(),
     )
 }
@@ -80,6 +80,7 @@ fn enum_simple() {
     assert_typecheck_fails!("`foo : <bar>");
     assert_typecheck_fails!("switch { foo => 3} `bar : Num");
     assert_typecheck_fails!("switch { foo => 3, bar => true} `bar : Num");
+    assert_typecheck_fails!("match { `foo => 3} `bar : Num");
 }
 
 #[test]
@@ -191,6 +192,14 @@ fn recursive_records() {
     assert_typecheck_fails!("{a = 1, b : Bool = a} : {a : Num, b : Bool}");
 }
 
+#[test]
+fn let_rec() {
+    assert_typecheck_fails!("(let rec f = fun x => if x then f x else 1 in f 0) : Num");
+    assert_typecheck_fails!(
+        "(let rec f = fun x => g x and g : Num -> Str = fun x => f x in f 0) : Num"
+    );
+}
+
 #[test]
 fn let_inference() {
     assert_typecheck_fails!("(let x = 1 + 2 in let f = fun x => x ++ \"a\" in f x) : Num");